@@ -0,0 +1,49 @@
+//! The stable public interface of libtenx, for tools built on top of it.
+//!
+//! Everything exported here follows semver, and only changes incompatibly with a new major
+//! version. The rest of the crate is private, apart from the modules the tenx command line tools
+//! use through the `internal` feature, which may change in any release.
+//!
+//! A typical integration loads a [`Config`], creates a [`Tenx`] and a [`Session`], and drives the
+//! session with [`Tenx::code`] and [`Tenx::continue_steps`], listening for [`Event`]s along the
+//! way with an [`EventConsumer`]. Integrations that talk to a model directly can get one with
+//! [`Config::active_model`], and read its response as a stream with [`ModelProvider::stream`].
+//!
+//! The types of the public fields of exported types are exported too. Configuration types whose
+//! names clash with other exports are renamed: the `context` and model sections of a [`Config`]
+//! are [`ContextConfig`] and [`ModelConfig`].
+
+pub use state::{files::HashIndex, Change, Patch, PatchInfo, State};
+
+pub use crate::{
+    checkpoint::Checkpoint,
+    checks::{Baseline, CheckMode, FailingTests},
+    config::{
+        load_config, Backend, Budget, CheckConfig, Checks, Config, Context as ContextConfig,
+        Dialect, LicenseHeader, ModeConfig, ModeSpec, Model as ModelConfig, Models, PostProcess,
+        Pricing, Project, PromptOrder, RateLimit, ReasoningEffort, RetryPrompt, Safety, Sampling,
+        Scrub, SessionStoreKind, SystemPromptMode, TestFocus, TextContext,
+    },
+    context::{Context, ContextManager, ContextProvider},
+    error::{Result, TenxError},
+    event_consumers::{
+        consume, Broadcast, CallbackConsumer, ChannelConsumer, EventConsumer, EventFilter,
+    },
+    events::{Event, EventReceiver, EventSender, LogLevel, SessionStats, StepId},
+    model::{
+        Chat, ClaudeUsage, DummyUsage, EchoUsage, GoogleUsage, Model, ModelProvider, OpenAiUsage,
+        StreamEvent, Usage,
+    },
+    risk::{Risk, RiskKind},
+    session::{
+        Action, Attempt, ModelResponse, Operation, Session, Step, StepOutcome, StepRequest,
+        StepResponse, Summary, SystemPrompt,
+    },
+    session_store::SessionStore,
+    strategy::{
+        ActionState, Code, CodeStep, Completion, Fix, InputRequired, Strategy, StrategyStep,
+        TestFirst, TestFirstStep, TestPhase,
+    },
+    tenx::{RiskConfirm, StepConfirm, StepDecision, Tenx},
+    throttle::Throttle,
+};
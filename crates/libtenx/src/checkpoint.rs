@@ -0,0 +1,381 @@
+//! Git checkpoints of the files a session changes, taken before it first changes them. A
+//! checkpoint is a commit of the repository's `HEAD` tree with the affected files as they were on
+//! disk, made with a temporary index so the user's index, stash and branches are never touched.
+//! When a later step changes files the checkpoint doesn't cover yet, they're added to it. It's
+//! kept alive by a ref under `refs/tenx/checkpoints/` named for the session, so sessions for
+//! projects that share a repository keep separate checkpoints. Restoring it puts the covered
+//! files back exactly as they were, and leaves everything else alone.
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use fs_err as fs;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::error::{Result, TenxError};
+
+/// The namespace for checkpoint refs, which are named for their session.
+pub const CHECKPOINT_REFS: &str = "refs/tenx/checkpoints";
+
+/// A checkpoint of the files a session has changed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Checkpoint {
+    /// The checkpoint commit.
+    pub commit: String,
+    /// The ref that keeps the commit alive.
+    pub reference: String,
+    /// The files the checkpoint covers, relative to the project root. Files that didn't exist
+    /// when they were checkpointed are covered, but absent from the commit.
+    pub paths: Vec<PathBuf>,
+}
+
+/// Replaces characters that aren't safe in a ref or file name.
+fn safe_name(session: &str) -> String {
+    session
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Returns the ref that holds a session's checkpoint. Characters that aren't safe in a ref name
+/// are replaced.
+pub fn checkpoint_ref(session: &str) -> String {
+    format!("{}/{}", CHECKPOINT_REFS, safe_name(session))
+}
+
+/// Runs git in `root` with the given arguments, and an alternate index file if given. Returns
+/// stdout as is. Pathspecs are taken literally, so file names are never read as patterns.
+fn git_raw(root: &Path, index: Option<&Path>, args: &[&str]) -> Result<String> {
+    let mut cmd = Command::new("git");
+    cmd.args(args)
+        .current_dir(root)
+        .env("GIT_LITERAL_PATHSPECS", "1");
+    if let Some(index) = index {
+        cmd.env("GIT_INDEX_FILE", index);
+    }
+    let output = cmd.output().map_err(|e| TenxError::Exec {
+        cmd: format!("git {}", args.join(" ")),
+        error: e.to_string(),
+    })?;
+    if !output.status.success() {
+        return Err(TenxError::Exec {
+            cmd: format!("git {}", args.join(" ")),
+            error: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Runs git like `git_raw`, returning trimmed stdout.
+fn git(root: &Path, index: Option<&Path>, args: &[&str]) -> Result<String> {
+    Ok(git_raw(root, index, args)?.trim().to_string())
+}
+
+/// Runs git with `paths` appended after `--`, returning the NUL-separated records in its output.
+/// The command must be given `-z`.
+fn git_paths(
+    root: &Path,
+    index: Option<&Path>,
+    args: &[&str],
+    paths: &[PathBuf],
+) -> Result<Vec<String>> {
+    let mut args: Vec<&str> = args.to_vec();
+    args.push("--");
+    args.extend(paths.iter().filter_map(|p| p.to_str()));
+    Ok(git_raw(root, index, &args)?
+        .split('\0')
+        .filter(|r| !r.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+/// A temporary index file inside the repository's git directory, removed when dropped. Each
+/// session and process gets its own, so concurrent sessions don't clobber each other.
+struct TempIndex(PathBuf);
+
+impl TempIndex {
+    fn new(root: &Path, reference: &str) -> Result<Self> {
+        let git_dir = PathBuf::from(git(root, None, &["rev-parse", "--absolute-git-dir"])?);
+        let name = reference.rsplit('/').next().unwrap_or_default();
+        let path = git_dir.join(format!(
+            "tenx-checkpoint-{}-{}.index",
+            name,
+            std::process::id()
+        ));
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+        Ok(Self(path))
+    }
+}
+
+impl Drop for TempIndex {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Is the project root inside a git work tree?
+pub fn is_repo(root: &Path) -> bool {
+    git(root, None, &["rev-parse", "--is-inside-work-tree"]).is_ok_and(|o| o == "true")
+}
+
+/// Makes paths relative to the project root, and drops any outside it.
+fn relative(root: &Path, paths: &[PathBuf]) -> Vec<PathBuf> {
+    paths
+        .iter()
+        .filter_map(|p| match p.strip_prefix(root) {
+            Ok(rel) => Some(rel.to_path_buf()),
+            Err(_) if p.is_relative() => Some(p.clone()),
+            Err(_) => None,
+        })
+        .collect()
+}
+
+/// Commits `base` with `paths` as they are on disk, points `reference` at the commit, and returns
+/// the commit id. Paths missing from disk are left out of the commit.
+fn commit(root: &Path, reference: &str, base: Option<&str>, paths: &[PathBuf]) -> Result<String> {
+    let index = TempIndex::new(root, reference)?;
+    if let Some(base) = base {
+        git(root, Some(&index.0), &["read-tree", base])?;
+    }
+    git_paths(
+        root,
+        Some(&index.0),
+        &["update-index", "-z", "--add", "--remove"],
+        paths,
+    )?;
+    let tree = git(root, Some(&index.0), &["write-tree"])?;
+    // Checkpoints are internal, so they don't need the user's identity
+    let mut args = vec![
+        "-c",
+        "user.name=tenx",
+        "-c",
+        "user.email=tenx@localhost",
+        "commit-tree",
+        tree.as_str(),
+        "-m",
+        "tenx checkpoint",
+    ];
+    if let Some(base) = base {
+        args.extend(["-p", base]);
+    }
+    let commit = git(root, None, &args)?;
+    git(root, None, &["update-ref", reference, &commit])?;
+    debug!("Created checkpoint {}", commit);
+    Ok(commit)
+}
+
+/// Checkpoints `paths` as they are now, and points the session's checkpoint ref at the commit.
+pub fn create(root: &Path, session: &str, paths: &[PathBuf]) -> Result<Checkpoint> {
+    let head = git(root, None, &["rev-parse", "--verify", "--quiet", "HEAD"]).ok();
+    let reference = checkpoint_ref(session);
+    let paths = relative(root, paths);
+    Ok(Checkpoint {
+        commit: commit(root, &reference, head.as_deref(), &paths)?,
+        reference,
+        paths,
+    })
+}
+
+/// Adds any of `paths` the checkpoint doesn't cover yet, as they are now. Returns true if the
+/// checkpoint changed.
+pub fn extend(root: &Path, checkpoint: &mut Checkpoint, paths: &[PathBuf]) -> Result<bool> {
+    let new: Vec<PathBuf> = relative(root, paths)
+        .into_iter()
+        .filter(|p| !checkpoint.paths.contains(p))
+        .collect();
+    if new.is_empty() {
+        return Ok(false);
+    }
+    checkpoint.commit = commit(root, &checkpoint.reference, Some(&checkpoint.commit), &new)?;
+    checkpoint.paths.extend(new);
+    Ok(true)
+}
+
+/// Lists the files that restoring a checkpoint would change, with what happens to each: files
+/// modified or deleted since the checkpoint are reset, and files created since are removed.
+pub fn changes(root: &Path, checkpoint: &Checkpoint) -> Result<Vec<String>> {
+    let index = TempIndex::new(root, &checkpoint.reference)?;
+    git(root, Some(&index.0), &["read-tree", &checkpoint.commit])?;
+    git_paths(
+        root,
+        Some(&index.0),
+        &["update-index", "-z", "--add", "--remove"],
+        &checkpoint.paths,
+    )?;
+    let status = git_paths(
+        root,
+        Some(&index.0),
+        &[
+            "diff-index",
+            "--cached",
+            "--name-status",
+            "--no-renames",
+            "--relative",
+            "-z",
+            &checkpoint.commit,
+        ],
+        &checkpoint.paths,
+    )?;
+    // Records alternate between a status and a path
+    Ok(status
+        .chunks(2)
+        .filter_map(|r| match r {
+            [status, path] => Some(match status.as_str() {
+                "A" => format!("{} (new, will be removed)", path),
+                "D" => format!("{} (deleted, will be restored)", path),
+                _ => format!("{} (modified, will be reset)", path),
+            }),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Restores the files a checkpoint covers: files are reset to their checkpointed content, and
+/// files that didn't exist when they were checkpointed are removed. Other files, and the user's
+/// index, aren't touched. Returns the number of files removed.
+pub fn restore(root: &Path, checkpoint: &Checkpoint) -> Result<usize> {
+    let index = TempIndex::new(root, &checkpoint.reference)?;
+    git(root, Some(&index.0), &["read-tree", &checkpoint.commit])?;
+    let checkpointed: BTreeSet<PathBuf> =
+        git_paths(root, Some(&index.0), &["ls-files", "-z"], &checkpoint.paths)?
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+    let mut removed = 0;
+    for path in checkpoint
+        .paths
+        .iter()
+        .filter(|p| !checkpointed.contains(*p))
+    {
+        let full = root.join(path);
+        if full.is_file() {
+            fs::remove_file(full)?;
+            removed += 1;
+        }
+    }
+    let present: Vec<PathBuf> = checkpointed.into_iter().collect();
+    if !present.is_empty() {
+        git_paths(
+            root,
+            Some(&index.0),
+            &["checkout-index", "-f", "-z"],
+            &present,
+        )?;
+    }
+    Ok(removed)
+}
+
+/// Deletes the checkpoint's ref, letting git collect the checkpoint commit.
+pub fn drop_ref(root: &Path, checkpoint: &Checkpoint) -> Result<()> {
+    git(root, None, &["update-ref", "-d", &checkpoint.reference])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        assert!(!is_repo(root));
+        git(root, None, &["init", "-q"])?;
+        assert!(is_repo(root));
+        let commit = |msg: &str| {
+            git(
+                root,
+                None,
+                &[
+                    "-c",
+                    "user.name=tenx",
+                    "-c",
+                    "user.email=tenx@example.com",
+                    "commit",
+                    "-q",
+                    "-am",
+                    msg,
+                ],
+            )
+        };
+        fs::write(root.join("a.txt"), "committed")?;
+        git(root, None, &["add", "a.txt"])?;
+        commit("init")?;
+        fs::write(root.join("a.txt"), "modified")?;
+        fs::write(root.join("b c.txt"), "staged")?;
+        git(root, None, &["add", "b c.txt"])?;
+        fs::write(root.join("notes.txt"), "the user's own")?;
+
+        let paths = |names: &[&str]| names.iter().map(PathBuf::from).collect::<Vec<_>>();
+        let mut cp = create(root, "_project", &paths(&["a.txt", "new.txt"]))?;
+        assert_eq!(
+            git(root, None, &["rev-parse", "refs/tenx/checkpoints/_project"])?,
+            cp.commit
+        );
+        // Other sessions in the same repository keep their own checkpoints
+        let other = create(root, "_project_sub", &paths(&["a.txt"]))?;
+        assert_eq!(
+            git(root, None, &["rev-parse", &checkpoint_ref("_project_sub")])?,
+            other.commit
+        );
+        // Later changes extend the checkpoint, and only with files it doesn't cover yet
+        let first = cp.commit.clone();
+        assert!(extend(root, &mut cp, &paths(&["b c.txt", "a.txt"]))?);
+        assert!(!extend(root, &mut cp, &paths(&["a.txt"]))?);
+        assert_ne!(cp.commit, first);
+        assert_eq!(cp.paths, paths(&["a.txt", "new.txt", "b c.txt"]));
+        // The user's index is untouched
+        assert_eq!(
+            git(root, None, &["diff", "--cached", "--name-only"])?,
+            "b c.txt"
+        );
+
+        fs::write(root.join("a.txt"), "broken")?;
+        fs::remove_file(root.join("b c.txt"))?;
+        fs::write(root.join("new.txt"), "new")?;
+        fs::write(root.join("notes.txt"), "edited by the user")?;
+        assert_eq!(
+            changes(root, &cp)?,
+            vec![
+                "a.txt (modified, will be reset)",
+                "b c.txt (deleted, will be restored)",
+                "new.txt (new, will be removed)",
+            ]
+        );
+        assert_eq!(restore(root, &cp)?, 1);
+        assert_eq!(fs::read_to_string(root.join("a.txt"))?, "modified");
+        assert_eq!(fs::read_to_string(root.join("b c.txt"))?, "staged");
+        assert!(!root.join("new.txt").exists());
+        // Files outside the checkpoint are left alone
+        assert_eq!(
+            fs::read_to_string(root.join("notes.txt"))?,
+            "edited by the user"
+        );
+
+        assert!(changes(root, &cp)?.is_empty());
+
+        drop_ref(root, &cp)?;
+        assert!(git(
+            root,
+            None,
+            &[
+                "rev-parse",
+                "--verify",
+                "--quiet",
+                &checkpoint_ref("_project")
+            ]
+        )
+        .is_err());
+        assert_eq!(checkpoint_ref("a/b c"), "refs/tenx/checkpoints/a_b_c");
+        Ok(())
+    }
+}
@@ -1,13 +1,33 @@
 //! Check module for running code conformance checks.
-use std::path::PathBuf;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use fs_err as fs;
+
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::trace;
 
 use crate::{
-    config::Config,
+    config::{check_language, Config, TestFocus},
+    diagnostics,
     error::{Result, TenxError},
-    events::{EventBlock, EventSender},
-    exec::exec,
+    events::{send_event, Event, EventSender, LogLevel},
+    exec::find_program,
+    python::PythonEnv,
+    sarif::{self, Diagnostic},
 };
 
+/// Commands the shell runs itself, which won't be found on the `PATH`.
+const SHELL_BUILTINS: &[&str] = &[
+    ".", ":", "cd", "echo", "eval", "exec", "exit", "export", "printf", "set", "source", "test",
+    "true", "false",
+];
+
 pub enum Runnable {
     Ok,
     Error(String),
@@ -19,35 +39,186 @@ impl Runnable {
     }
 }
 
-/// A validator that runs a shell command and checks its output. Relies on `sh` being available.
+/// What a check does to the project when it runs.
+#[derive(
+    Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckMode {
+    /// Rewrites files in place, like a formatter. Transforms run before validators, so validators
+    /// see the transformed files.
+    Transform,
+    /// Inspects files without changing them, like a compiler, linter or test suite.
+    #[default]
+    Validate,
+}
+
+impl std::fmt::Display for CheckMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckMode::Transform => write!(f, "transform"),
+            CheckMode::Validate => write!(f, "validate"),
+        }
+    }
+}
+
+/// A check that runs a shell command and checks its output. Commands are run with the platform
+/// shell, which is `sh` on Unix and `cmd` on Windows. Formatters and validators are both checks,
+/// distinguished by their mode.
 ///
-/// Check commands are always run in the project root directory.
+/// Check commands are run in the project root directory, or in a configured subdirectory of it.
 pub struct Check {
-    /// Name of the validator for display and error reporting
+    /// Name of the check for display and error reporting
     pub name: String,
-    /// Shell command to execute, run with sh -c
+    /// Shell command to execute, run with `sh -c` or `cmd /C`
     pub command: String,
     /// List of glob patterns to match against files for determining relevance
     pub globs: Vec<String>,
-    /// Whether this validator defaults to off in the configuration
+    /// Whether this check defaults to off in the configuration
     pub default_off: bool,
     /// Whether to treat any stderr output as a failure, regardless of exit code
     pub fail_on_stderr: bool,
+    /// Whether the check rewrites files or only inspects them
+    pub mode: CheckMode,
+    /// How to re-run only the tests that failed on the last run
+    pub focus: Option<TestFocus>,
+    /// The directory to run the command in, relative to the project root
+    pub cwd: Option<PathBuf>,
+    /// A command that mechanically fixes what it can of the check's failures
+    pub fix: Option<String>,
+}
+
+/// Quotes a test name for the platform shell.
+fn quote(name: &str) -> String {
+    if cfg!(windows) {
+        format!("\"{}\"", name)
+    } else {
+        format!("'{}'", name.replace('\'', "'\\''"))
+    }
+}
+
+/// Truncates text longer than `max` bytes by cutting out the middle, keeping the head and tail
+/// and noting how much was elided. Failing test suites often put the summary at the end and the
+/// first error at the start, so both are worth keeping. A `max` of zero means no limit.
+pub fn truncate_middle(text: &str, max: usize) -> String {
+    if max == 0 || text.len() <= max {
+        return text.to_string();
+    }
+    let mut head = max / 2;
+    while !text.is_char_boundary(head) {
+        head -= 1;
+    }
+    let mut tail = text.len() - (max - max / 2);
+    while !text.is_char_boundary(tail) {
+        tail += 1;
+    }
+    format!(
+        "{}\n[... {} bytes elided ...]\n{}",
+        &text[..head],
+        tail - head,
+        &text[tail..]
+    )
 }
 
 impl Check {
+    /// Is this a Python check? Builtin checks say which language they're for, and custom checks
+    /// count if they match Python files.
+    fn is_python(&self) -> bool {
+        match check_language(&self.name) {
+            Some(language) => language == "python",
+            None => self.globs.iter().any(|g| g.ends_with(".py")),
+        }
+    }
+
+    /// The project's Python environment, for Python checks only.
+    fn python_env(&self, config: &Config) -> Option<PythonEnv> {
+        self.is_python().then(|| config.python_env()).flatten()
+    }
+
+    /// Returns a command with its program taken from the project's Python environment, if this
+    /// is a Python check and the environment provides it.
+    fn resolve_command(&self, config: &Config, command: &str) -> String {
+        match self.python_env(config) {
+            Some(env) => env.command(command),
+            None => command.to_string(),
+        }
+    }
+
     pub fn check(&self, config: &Config) -> Result<()> {
-        let (status, stdout, stderr) = exec(config.project_root(), &self.command)?;
+        self.check_command(config, &self.command)
+    }
 
-        if !status.success() || (self.fail_on_stderr && !stderr.is_empty()) {
-            let msg = format!("Check command failed: {}", self.command);
-            Err(TenxError::Check {
-                name: self.name.clone(),
-                user: msg,
-                model: format!("stdout:\n{}\n\nstderr:\n{}", stdout, stderr),
-            })
+    /// Runs the check's fix command, returning false if it has none. The command's exit status is
+    /// ignored, since fixers commonly fail when some problems remain that they can't fix.
+    pub fn run_fix(&self, config: &Config) -> Result<bool> {
+        let Some(fix) = &self.fix else {
+            return Ok(false);
+        };
+        let output = config
+            .executor()
+            .exec(&self.dir(config), &self.resolve_command(config, fix))?;
+        trace!(
+            "Fix for check {} exited with {:?}\nstdout:\n{}\nstderr:\n{}",
+            self.name,
+            output.code,
+            output.stdout,
+            output.stderr
+        );
+        Ok(true)
+    }
+
+    /// The directory the check's command runs in: its configured working directory, relative to
+    /// the project root, or the config's scope.
+    pub fn dir(&self, config: &Config) -> PathBuf {
+        match &self.cwd {
+            Some(cwd) => config.project_root().join(cwd),
+            None => config.scope_root(),
+        }
+    }
+
+    fn check_command(&self, config: &Config, command: &str) -> Result<()> {
+        let output = self.exec_command(config, command)?;
+        self.result(config, command, output)
+    }
+
+    /// Runs a command, returning its full output if it failed.
+    fn exec_command(&self, config: &Config, command: &str) -> Result<Option<String>> {
+        let output = config.executor().exec_timeout(
+            &self.dir(config),
+            &self.resolve_command(config, command),
+            self.timeout(config),
+        )?;
+        let (stdout, stderr) = (output.stdout.as_str(), output.stderr.as_str());
+        trace!(
+            "Check {} exited with {:?}\nstdout:\n{}\nstderr:\n{}",
+            self.name,
+            output.code,
+            stdout,
+            stderr
+        );
+
+        if !output.success() || (self.fail_on_stderr && !stderr.is_empty()) {
+            Ok(Some(format!("stdout:\n{}\n\nstderr:\n{}", stdout, stderr)))
         } else {
-            Ok(())
+            Ok(None)
+        }
+    }
+
+    /// Turns the failure output of a command into a check error, truncating the output for the
+    /// model.
+    fn result(&self, config: &Config, command: &str, failure: Option<String>) -> Result<()> {
+        match failure {
+            Some(output) => Err(self.failure(config, command, &output)),
+            None => Ok(()),
+        }
+    }
+
+    /// The check error for a command's full failure output, truncated for the model.
+    fn failure(&self, config: &Config, command: &str, output: &str) -> TenxError {
+        TenxError::Check {
+            name: self.name.clone(),
+            user: format!("Check command failed: {}", command),
+            model: truncate_middle(output, config.checks.max_output),
         }
     }
 
@@ -75,8 +246,202 @@ impl Check {
         Ok(false)
     }
 
-    pub fn runnable(&self) -> Result<Runnable> {
-        Ok(Runnable::Ok)
+    /// Returns the paths that match the check's glob patterns.
+    pub fn relevant_paths(&self, paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+        let mut relevant = vec![];
+        for path in paths {
+            if self.match_globs(path.to_str().unwrap_or_default(), &self.globs)? {
+                relevant.push(path.clone());
+            }
+        }
+        Ok(relevant)
+    }
+
+    /// Extracts the names of failing tests from the output of a failed run. Returns an empty list
+    /// if the check has no focus configuration.
+    pub fn failing_tests(&self, output: &str) -> Result<Vec<String>> {
+        let Some(focus) = &self.focus else {
+            return Ok(vec![]);
+        };
+        let re = Regex::new(&focus.pattern).map_err(|e| {
+            TenxError::Config(format!("Invalid test pattern for {}: {}", self.name, e))
+        })?;
+        let mut tests = vec![];
+        for line in output.lines() {
+            if let Some(name) = re.captures(line).and_then(|c| c.get(1)) {
+                let name = name.as_str().to_string();
+                if !tests.contains(&name) {
+                    tests.push(name);
+                }
+            }
+        }
+        Ok(tests)
+    }
+
+    /// Runs the check, emitting events for its start and outcome.
+    pub fn run(
+        &self,
+        config: &Config,
+        paths: &[PathBuf],
+        sender: &Option<EventSender>,
+    ) -> Result<()> {
+        let failure = self.run_output(config, paths, sender)?;
+        self.result(config, &self.command, failure)
+    }
+
+    /// Runs the check like `run`, returning the full, untruncated output if it failed. With
+    /// `checks.cache` set, a check that passed before on the same files isn't run again.
+    pub fn run_output(
+        &self,
+        config: &Config,
+        paths: &[PathBuf],
+        sender: &Option<EventSender>,
+    ) -> Result<Option<String>> {
+        let marker = self.cache_marker(config)?;
+        if let Some(marker) = marker.as_ref().filter(|m| m.exists()) {
+            trace!(
+                "Check {} passed before, found {}",
+                self.name,
+                marker.display()
+            );
+            send_event(
+                sender,
+                Event::Log(
+                    LogLevel::Info,
+                    format!(
+                        "check {} passed before on the same files, skipped",
+                        self.name
+                    ),
+                ),
+            )?;
+            return Ok(None);
+        }
+        let failure = self.run_command(config, paths, &self.command, sender)?;
+        if let (None, Some(marker)) = (&failure, marker) {
+            if let Some(dir) = marker.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            fs::write(marker, "")?;
+        }
+        Ok(failure)
+    }
+
+    /// How long the check may run: its own timeout if it has one, or the default for all checks.
+    pub fn timeout(&self, config: &Config) -> Option<Duration> {
+        let secs = config
+            .checks
+            .timeouts
+            .get(&self.name)
+            .copied()
+            .unwrap_or(config.checks.timeout);
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+
+    /// The file that records a pass of this check on the project's current files, or None if
+    /// check results aren't cached. The name hashes the command, where it runs, and the path and
+    /// content of every project file the check's globs match.
+    fn cache_marker(&self, config: &Config) -> Result<Option<PathBuf>> {
+        if !config.checks.cache || config.cache_dir.as_os_str().is_empty() {
+            return Ok(None);
+        }
+        let mut key = format!(
+            "{}\0{}\0{}\0",
+            self.name,
+            self.resolve_command(config, &self.command),
+            self.dir(config).display()
+        );
+        let mut files = self.relevant_paths(&config.state()?.list()?)?;
+        files.sort();
+        for path in files {
+            let content = fs::read(config.abspath(&path)?)?;
+            key.push_str(&format!(
+                "{}\0{:016x}\0",
+                path.display(),
+                state::files::hash_content(&content)
+            ));
+        }
+        Ok(Some(config.cache_dir.join("checks").join(format!(
+            "{:016x}",
+            state::files::hash_content(key.as_bytes())
+        ))))
+    }
+
+    /// Runs only the named tests, using the check's focus command. Runs the full check if the
+    /// check has no focus configuration.
+    pub fn run_focused(
+        &self,
+        config: &Config,
+        paths: &[PathBuf],
+        tests: &[String],
+        sender: &Option<EventSender>,
+    ) -> Result<()> {
+        let Some(command) = self.focus_command(tests) else {
+            return self.run(config, paths, sender);
+        };
+        let failure = self.run_command(config, paths, &command, sender)?;
+        self.result(config, &command, failure)
+    }
+
+    /// The command that runs only the named tests, or None if the check has no focus
+    /// configuration.
+    fn focus_command(&self, tests: &[String]) -> Option<String> {
+        let focus = self.focus.as_ref()?;
+        let names: Vec<String> = tests.iter().map(|t| quote(t)).collect();
+        Some(focus.command.replace("{tests}", &names.join(" ")))
+    }
+
+    fn run_command(
+        &self,
+        config: &Config,
+        paths: &[PathBuf],
+        command: &str,
+        sender: &Option<EventSender>,
+    ) -> Result<Option<String>> {
+        send_event(
+            sender,
+            Event::CheckStart {
+                name: self.name.clone(),
+                files: self.relevant_paths(paths)?,
+            },
+        )?;
+        let start = Instant::now();
+        let result = self.exec_command(config, command);
+        let (name, duration) = (self.name.clone(), start.elapsed());
+        let event = match &result {
+            Ok(None) => Event::CheckOk { name, duration },
+            Ok(Some(output)) => Event::CheckFailed {
+                name,
+                duration,
+                source: diagnostics::render(config, self, output),
+            },
+            Err(_) => Event::CheckFailed {
+                name,
+                duration,
+                source: None,
+            },
+        };
+        send_event(sender, event)?;
+        result
+    }
+
+    /// Can this check be run? We check that the program named by the first word of the command
+    /// is a shell builtin, or can be found in the project's Python environment or on the `PATH`,
+    /// taking the platform's executable suffix into account.
+    pub fn runnable(&self, config: &Config) -> Result<Runnable> {
+        let Some(program) = self.command.split_whitespace().next() else {
+            return Ok(Runnable::Error("Empty check command".into()));
+        };
+        if SHELL_BUILTINS.contains(&program) {
+            return Ok(Runnable::Ok);
+        }
+        let in_env = self
+            .python_env(config)
+            .is_some_and(|env| env.program(program).is_some());
+        if in_env || find_program(program).is_some() {
+            Ok(Runnable::Ok)
+        } else {
+            Ok(Runnable::Error(format!("Program not found: {}", program)))
+        }
     }
 
     pub fn default_off(&self) -> bool {
@@ -84,21 +449,153 @@ impl Check {
     }
 }
 
+/// Returns the enabled checks, first telling the user about checks relevant to the paths that
+/// are skipped even though the configuration enables them.
+fn enabled_checks(
+    conf: &Config,
+    paths: &Vec<PathBuf>,
+    sender: &Option<EventSender>,
+) -> Result<Vec<Check>> {
+    for (check, reason) in conf.skipped_checks() {
+        if check.is_relevant(paths)? {
+            send_event(
+                sender,
+                Event::CheckSkipped {
+                    name: check.name,
+                    reason,
+                },
+            )?;
+        }
+    }
+    Ok(conf.enabled_checks())
+}
+
 /// Run checks on a given set of paths with a mode filter.
 pub fn check_paths(
     conf: &Config,
     paths: &Vec<PathBuf>,
     sender: &Option<EventSender>,
 ) -> Result<()> {
-    for c in conf.enabled_checks() {
+    for c in enabled_checks(conf, paths, sender)? {
         if c.is_relevant(paths)? {
-            let _check_block = EventBlock::check(sender, &c.name)?;
-            c.check(conf)?;
+            c.run(conf, paths, sender)?;
         }
     }
     Ok(())
 }
 
+/// The full failure output of checks that failed before any changes were made, keyed by check
+/// name.
+pub type Baseline = BTreeMap<String, String>;
+
+/// Run checks on a given set of paths, recording failures rather than stopping at the first. Checks
+/// named in `skip` are not run.
+pub fn baseline_paths(
+    conf: &Config,
+    paths: &Vec<PathBuf>,
+    skip: &BTreeSet<String>,
+    sender: &Option<EventSender>,
+) -> Result<Baseline> {
+    let mut baseline = Baseline::new();
+    for c in enabled_checks(conf, paths, sender)? {
+        if !skip.contains(&c.name) && c.is_relevant(paths)? {
+            // The full output is kept, so test names aren't lost to truncation
+            if let Some(output) = c.run_output(conf, paths, sender)? {
+                baseline.insert(c.name.clone(), output);
+            }
+        }
+    }
+    Ok(baseline)
+}
+
+/// The names of the tests that failed on the last run of each check, keyed by check name.
+pub type FailingTests = BTreeMap<String, Vec<String>>;
+
+/// Run checks on a given set of paths, ignoring failures that are identical to the failure
+/// recorded for the same check in the baseline. These are pre-existing failures unrelated to the
+/// changes being checked. Checks named in `skip` are not run. Returns the names of checks whose
+/// failures were ignored.
+///
+/// Checks with a focus configuration record the tests that fail in `failing`. On the next run,
+/// only those tests are run, and the full check runs as a final confirmation once they pass.
+pub fn check_paths_triaged(
+    conf: &Config,
+    paths: &Vec<PathBuf>,
+    baseline: &Baseline,
+    skip: &BTreeSet<String>,
+    failing: &mut FailingTests,
+    sender: &Option<EventSender>,
+) -> Result<Vec<String>> {
+    let mut ignored = vec![];
+    for c in enabled_checks(conf, paths, sender)? {
+        if skip.contains(&c.name) || !c.is_relevant(paths)? {
+            continue;
+        }
+        // Tests that failed before any changes were made aren't ours to fix
+        let known = c.failing_tests(baseline.get(&c.name).map_or("", |s| s.as_str()))?;
+        let record = |failing: &mut FailingTests, output: &str| -> Result<()> {
+            let tests: Vec<String> = c
+                .failing_tests(output)?
+                .into_iter()
+                .filter(|t| !known.contains(t))
+                .collect();
+            if tests.is_empty() {
+                failing.remove(&c.name);
+            } else {
+                failing.insert(c.name.clone(), tests);
+            }
+            Ok(())
+        };
+        if let Some(tests) = failing.get(&c.name).cloned() {
+            let command = c.focus_command(&tests);
+            let failure = match &command {
+                Some(command) => c.run_command(conf, paths, command, sender)?,
+                None => c.run_output(conf, paths, sender)?,
+            };
+            if let Some(output) = &failure {
+                // Keep the previous names if we can't find any in the output
+                if !c.failing_tests(output)?.is_empty() {
+                    record(failing, output)?;
+                }
+            }
+            c.result(conf, command.as_deref().unwrap_or(&c.command), failure)?;
+        }
+        // Test names are parsed from the full output, and only the error for the model is
+        // truncated
+        match c.run_output(conf, paths, sender)? {
+            None => {
+                failing.remove(&c.name);
+            }
+            Some(output) if baseline.get(&c.name) == Some(&output) => {
+                failing.remove(&c.name);
+                ignored.push(c.name.clone());
+            }
+            Some(output) => {
+                record(failing, &output)?;
+                return Err(c.failure(conf, &c.command, &output));
+            }
+        }
+    }
+    Ok(ignored)
+}
+
+/// Run the validators on all project files, collecting the diagnostics from each failure rather
+/// than stopping at the first. Transforms are not run, so files are left unchanged.
+pub fn preflight(conf: &Config, sender: &Option<EventSender>) -> Result<Vec<Diagnostic>> {
+    let paths = conf.state()?.list()?;
+    let mut diagnostics = vec![];
+    for c in enabled_checks(conf, &paths, sender)? {
+        if c.mode != CheckMode::Validate || !c.is_relevant(&paths)? {
+            continue;
+        }
+        // Diagnostics are parsed from the full output, since truncation is only for the model
+        if let Some(output) = c.run_output(conf, &paths, sender)? {
+            diagnostics.extend(sarif::parse(&c, &output));
+        }
+    }
+    Ok(diagnostics)
+}
+
 /// Run checks on all configured state files.
 pub fn check_all(conf: &Config, sender: &Option<EventSender>) -> Result<()> {
     let state = conf.state()?;
@@ -123,6 +620,10 @@ mod tests {
             globs: vec!["src/*.rs".to_string(), "tests/**/*.rs".to_string()],
             default_off: false,
             fail_on_stderr: true,
+            mode: CheckMode::Validate,
+            focus: None,
+            cwd: None,
+            fix: None,
         };
 
         let patterns = check.globs.clone();
@@ -135,10 +636,14 @@ mod tests {
     fn test_shell_success() {
         let shell = Check {
             name: "test".to_string(),
-            command: "true".to_string(),
+            command: "exit 0".to_string(),
             globs: vec!["*.rs".to_string()],
             default_off: false,
             fail_on_stderr: true,
+            mode: CheckMode::Validate,
+            focus: None,
+            cwd: None,
+            fix: None,
         };
 
         let config = test_config();
@@ -150,10 +655,14 @@ mod tests {
     fn test_shell_failure() {
         let shell = Check {
             name: "test".to_string(),
-            command: "echo 'error message' >&2 && echo 'output message' && false".to_string(),
+            command: "echo error message 1>&2 && echo output message && exit 1".to_string(),
             globs: vec!["*.rs".to_string()],
             default_off: false,
             fail_on_stderr: true,
+            mode: CheckMode::Validate,
+            focus: None,
+            cwd: None,
+            fix: None,
         };
 
         let config = test_config();
@@ -170,4 +679,476 @@ mod tests {
             _ => panic!("Expected Check error"),
         }
     }
+
+    #[test]
+    fn test_fake_executor() {
+        use crate::exec::{ExecOutput, FakeExecutor};
+
+        let check = |command: &str, fail_on_stderr| Check {
+            name: "fake".to_string(),
+            command: command.to_string(),
+            globs: vec!["*.rs".to_string()],
+            default_off: false,
+            fail_on_stderr,
+            mode: CheckMode::Validate,
+            focus: None,
+            cwd: None,
+            fix: None,
+        };
+        let config = test_config().with_dummy_executor(
+            FakeExecutor::default()
+                .with_output("cargo build", ExecOutput::new(0, "", "warning: unused"))
+                .with_output("cargo test", ExecOutput::new(101, "test a ... FAILED", ""))
+                .with_error("slow", "timed out after 60s"),
+        );
+
+        assert!(check("cargo build", false).check(&config).is_ok());
+        assert!(check("cargo build", true).check(&config).is_err());
+        match check("cargo test --all", false).check(&config) {
+            Err(TenxError::Check { model, .. }) => {
+                assert!(model.contains("stdout:\ntest a ... FAILED"))
+            }
+            r => panic!("Expected Check error, got {:?}", r),
+        }
+        assert!(matches!(
+            check("slow", false).check(&config),
+            Err(TenxError::Exec { error, .. }) if error == "timed out after 60s"
+        ));
+        assert!(matches!(
+            check("ruff check", false).check(&config),
+            Err(TenxError::Exec { .. })
+        ));
+    }
+
+    #[test]
+    fn test_check_paths_triaged() -> Result<()> {
+        let mut config = test_config();
+        config.checks.builtin = vec![
+            crate::config::CheckConfig {
+                name: "old".into(),
+                command: "echo broken && exit 1".into(),
+                globs: vec!["*.rs".into()],
+                default_off: false,
+                fail_on_stderr: false,
+                mode: CheckMode::Validate,
+                focus: None,
+                cwd: None,
+                fix: None,
+            },
+            crate::config::CheckConfig {
+                name: "new".into(),
+                command: "exit 0".into(),
+                globs: vec!["*.rs".into()],
+                default_off: false,
+                fail_on_stderr: false,
+                mode: CheckMode::Validate,
+                focus: None,
+                cwd: None,
+                fix: None,
+            },
+        ];
+        let paths = vec![PathBuf::from("lib.rs")];
+        let baseline = baseline_paths(&config, &paths, &BTreeSet::new(), &None)?;
+        assert_eq!(baseline.keys().collect::<Vec<_>>(), vec!["old"]);
+
+        // The same failure is ignored
+        let ignored = check_paths_triaged(
+            &config,
+            &paths,
+            &baseline,
+            &BTreeSet::new(),
+            &mut FailingTests::new(),
+            &None,
+        )?;
+        assert_eq!(ignored, vec!["old"]);
+
+        // A failure that differs from the baseline is reported
+        config.checks.builtin[0].command = "echo broken differently && exit 1".into();
+        assert!(check_paths_triaged(
+            &config,
+            &paths,
+            &baseline,
+            &BTreeSet::new(),
+            &mut FailingTests::new(),
+            &None
+        )
+        .is_err());
+
+        // Skipped checks aren't run at all
+        let skip = BTreeSet::from(["old".to_string()]);
+        assert!(check_paths_triaged(
+            &config,
+            &paths,
+            &baseline,
+            &skip,
+            &mut FailingTests::new(),
+            &None
+        )?
+        .is_empty());
+        assert!(baseline_paths(&config, &paths, &skip, &None)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_focused_tests() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut config = Config::default().with_root(dir.path());
+        config.checks.builtin = vec![crate::config::CheckConfig {
+            name: "test".into(),
+            command: "echo '---- t::a stdout ----' && echo '---- t::b stdout ----' && exit 1"
+                .into(),
+            globs: vec!["*.rs".into()],
+            default_off: false,
+            fail_on_stderr: false,
+            mode: CheckMode::Validate,
+            focus: Some(TestFocus {
+                pattern: r"^---- (\S+) stdout ----$".into(),
+                command: "printf '%s\\n' {tests} > focused.txt".into(),
+            }),
+            cwd: None,
+            fix: None,
+        }];
+        let paths = vec![PathBuf::from("lib.rs")];
+        let (baseline, skip) = (Baseline::new(), BTreeSet::new());
+        let mut failing = FailingTests::new();
+
+        // A failed run records the failing tests
+        assert!(
+            check_paths_triaged(&config, &paths, &baseline, &skip, &mut failing, &None).is_err()
+        );
+        assert_eq!(failing["test"], vec!["t::a", "t::b"]);
+
+        // The next run focuses on them, and confirms with the full check once they pass
+        assert!(
+            check_paths_triaged(&config, &paths, &baseline, &skip, &mut failing, &None).is_err()
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("focused.txt"))?,
+            "t::a\nt::b\n"
+        );
+
+        // Once the full check passes, nothing is remembered
+        config.checks.builtin[0].command = "exit 0".into();
+        check_paths_triaged(&config, &paths, &baseline, &skip, &mut failing, &None)?;
+        assert!(failing.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_triage_full_output() -> Result<()> {
+        use crate::exec::{ExecOutput, FakeExecutor};
+        let dir = tempfile::tempdir()?;
+        let output: String = (1..=50)
+            .map(|n| format!("---- t::test{} stdout ----\n", n))
+            .collect();
+        let mut config = Config::default().with_root(dir.path()).with_dummy_executor(
+            FakeExecutor::default().with_output("test", ExecOutput::new(1, &output, "")),
+        );
+        config.checks.max_output = 100;
+        config.checks.builtin = vec![crate::config::CheckConfig {
+            name: "test".into(),
+            command: "test".into(),
+            globs: vec!["*.rs".into()],
+            default_off: false,
+            fail_on_stderr: false,
+            mode: CheckMode::Validate,
+            focus: Some(TestFocus {
+                pattern: r"^---- (\S+) stdout ----$".into(),
+                command: "test {tests}".into(),
+            }),
+            cwd: None,
+            fix: None,
+        }];
+        let paths = vec![PathBuf::from("lib.rs")];
+        let skip = BTreeSet::new();
+
+        // Tests are found in the full output, though the model only sees part of it
+        let mut failing = FailingTests::new();
+        match check_paths_triaged(
+            &config,
+            &paths,
+            &Baseline::new(),
+            &skip,
+            &mut failing,
+            &None,
+        ) {
+            Err(TenxError::Check { model, .. }) => assert!(model.contains("bytes elided")),
+            r => panic!("Expected Check error, got {:?}", r),
+        }
+        assert_eq!(failing["test"].len(), 50);
+
+        // The baseline keeps the full output, so none of its tests count as new failures
+        let baseline = baseline_paths(&config, &paths, &skip, &None)?;
+        assert!(baseline["test"].contains("t::test25 "));
+        let mut failing = FailingTests::new();
+        let ignored = check_paths_triaged(&config, &paths, &baseline, &skip, &mut failing, &None)?;
+        assert_eq!(ignored, vec!["test"]);
+        assert!(failing.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cwd() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir_all(dir.path().join("web"))?;
+        std::fs::create_dir_all(dir.path().join("api"))?;
+        let mut config = Config::default().with_root(dir.path());
+        let check = |name: &str, cwd: Option<&str>| crate::config::CheckConfig {
+            name: name.into(),
+            command: format!("echo {} > out.txt", name),
+            globs: vec!["*.rs".into()],
+            default_off: false,
+            fail_on_stderr: false,
+            mode: CheckMode::Validate,
+            focus: None,
+            cwd: cwd.map(PathBuf::from),
+            fix: None,
+        };
+        config.checks.builtin = vec![check("web", Some("web")), check("api", None)];
+        config.checks.cwd.insert("api".into(), PathBuf::from("api"));
+
+        check_paths(&config, &vec![PathBuf::from("lib.rs")], &None)?;
+        let read = |p: &str| std::fs::read_to_string(dir.path().join(p)).unwrap();
+        assert_eq!(read("web/out.txt").trim(), "web");
+        assert_eq!(read("api/out.txt").trim(), "api");
+        assert!(!dir.path().join("out.txt").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_events() {
+        let check = Check {
+            name: "test".to_string(),
+            command: "exit 1".to_string(),
+            globs: vec!["*.rs".to_string()],
+            default_off: false,
+            fail_on_stderr: false,
+            mode: CheckMode::Validate,
+            focus: None,
+            cwd: None,
+            fix: None,
+        };
+        let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+        let paths = vec![PathBuf::from("lib.rs"), PathBuf::from("README.md")];
+        assert!(check.run(&test_config(), &paths, &Some(tx)).is_err());
+
+        match rx.try_recv().unwrap() {
+            Event::CheckStart { name, files } => {
+                assert_eq!(name, "test");
+                assert_eq!(files, vec![PathBuf::from("lib.rs")]);
+            }
+            e => panic!("unexpected event: {:?}", e),
+        }
+        assert!(
+            matches!(rx.try_recv().unwrap(), Event::CheckFailed { name, .. } if name == "test")
+        );
+    }
+
+    #[test]
+    fn test_skipped_events() -> Result<()> {
+        let mut config = test_config();
+        config.project.languages = vec!["python".into()];
+        config.checks.builtin = vec![crate::config::CheckConfig {
+            name: "ruff-check".into(),
+            command: "tenx-no-such-program check".into(),
+            globs: vec!["*.py".into()],
+            default_off: false,
+            fail_on_stderr: false,
+            mode: CheckMode::Validate,
+            focus: None,
+            cwd: None,
+            fix: None,
+        }];
+        let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+        check_paths(&config, &vec![PathBuf::from("lib.rs")], &Some(tx.clone()))?;
+        assert!(rx.try_recv().is_err());
+
+        check_paths(&config, &vec![PathBuf::from("main.py")], &Some(tx))?;
+        assert!(matches!(
+            rx.try_recv().unwrap(),
+            Event::CheckSkipped { name, .. } if name == "ruff-check"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncate_middle() {
+        assert_eq!(truncate_middle("short", 10), "short");
+        assert_eq!(truncate_middle("no limit", 0), "no limit");
+        assert_eq!(
+            truncate_middle("aaaaXXXXXXXXbbbb", 8),
+            "aaaa\n[... 8 bytes elided ...]\nbbbb"
+        );
+        // Cuts never split a multi-byte character
+        let t = truncate_middle("ééééé", 5);
+        assert!(t.starts_with("é\n") && t.ends_with("\né"));
+
+        use crate::exec::{ExecOutput, FakeExecutor};
+        let mut config = test_config().with_dummy_executor(
+            FakeExecutor::default().with_output("test", ExecOutput::new(1, &"x".repeat(1000), "")),
+        );
+        config.checks.max_output = 100;
+        let check = Check {
+            name: "test".to_string(),
+            command: "test".to_string(),
+            globs: vec!["*.rs".to_string()],
+            default_off: false,
+            fail_on_stderr: false,
+            mode: CheckMode::Validate,
+            focus: None,
+            cwd: None,
+            fix: None,
+        };
+        match check.check(&config) {
+            Err(TenxError::Check { model, .. }) => {
+                assert!(model.starts_with("stdout:\n"));
+                assert!(model.contains("bytes elided"));
+                assert!(model.ends_with("stderr:\n"));
+            }
+            r => panic!("Expected Check error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_preflight_full_output() -> Result<()> {
+        use crate::exec::{ExecOutput, FakeExecutor};
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("lib.rs"), "")?;
+        let output: String = (1..=50)
+            .map(|n| format!("lib.rs:{}: error: problem {}\n", n, n))
+            .collect();
+        let mut config = Config::default().with_root(dir.path()).with_dummy_executor(
+            FakeExecutor::default().with_output("lint", ExecOutput::new(1, &output, "")),
+        );
+        config.checks.max_output = 100;
+        config.checks.builtin = vec![crate::config::CheckConfig {
+            name: "lint".into(),
+            command: "lint".into(),
+            globs: vec!["*.rs".into()],
+            default_off: false,
+            fail_on_stderr: false,
+            mode: CheckMode::Validate,
+            focus: None,
+            cwd: None,
+            fix: None,
+        }];
+
+        // Truncating the output for the model doesn't lose diagnostics
+        let diagnostics = preflight(&config, &None)?;
+        assert_eq!(diagnostics.len(), 50);
+        assert_eq!(diagnostics[24].message, "problem 25");
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_cache() -> Result<()> {
+        use crate::exec::{ExecOutput, FakeExecutor};
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("lib.rs"), "fn main() {}")?;
+        let with_lint = |code: i32| {
+            let mut config = Config::default().with_root(dir.path()).with_dummy_executor(
+                FakeExecutor::default().with_output("lint", ExecOutput::new(code, "", "")),
+            );
+            config.cache_dir = dir.path().join("cache");
+            config.checks.cache = true;
+            config.checks.timeout = 60;
+            config.checks.timeouts.insert("slow".into(), 0);
+            config
+        };
+        let check = Check {
+            name: "lint".to_string(),
+            command: "lint".to_string(),
+            globs: vec!["*.rs".to_string()],
+            default_off: false,
+            fail_on_stderr: false,
+            mode: CheckMode::Validate,
+            focus: None,
+            cwd: None,
+            fix: None,
+        };
+        let paths = vec![PathBuf::from("lib.rs")];
+
+        // A pass is remembered, so the failing command isn't run on the same files
+        check.run(&with_lint(0), &paths, &None)?;
+        check.run(&with_lint(1), &paths, &None)?;
+        std::fs::write(dir.path().join("lib.rs"), "fn main() { x }")?;
+        assert!(check.run(&with_lint(1), &paths, &None).is_err());
+
+        // Failures aren't cached
+        assert!(check.run(&with_lint(1), &paths, &None).is_err());
+        let mut uncached = with_lint(1);
+        uncached.checks.cache = false;
+        std::fs::write(dir.path().join("lib.rs"), "fn main() {}")?;
+        assert!(check.run(&uncached, &paths, &None).is_err());
+
+        let config = with_lint(0);
+        assert_eq!(check.timeout(&config), Some(Duration::from_secs(60)));
+        let slow = Check {
+            name: "slow".into(),
+            ..check
+        };
+        assert_eq!(slow.timeout(&config), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_python_env() {
+        let root = tempfile::tempdir().unwrap();
+        let bin = root
+            .path()
+            .join(".venv")
+            .join(if cfg!(windows) { "Scripts" } else { "bin" });
+        std::fs::create_dir_all(&bin).unwrap();
+        std::fs::write(root.path().join(".venv/pyvenv.cfg"), "").unwrap();
+        for program in ["pytest", "cargo"] {
+            std::fs::write(bin.join(crate::exec::exe_name(program)), "").unwrap();
+        }
+        let config = Config::default().with_root(root.path());
+        let check = |name: &str, globs: &[&str]| Check {
+            name: name.to_string(),
+            command: String::new(),
+            globs: globs.iter().map(|g| g.to_string()).collect(),
+            default_off: false,
+            fail_on_stderr: false,
+            mode: CheckMode::Validate,
+            focus: None,
+            cwd: None,
+            fix: None,
+        };
+
+        // Only Python checks run programs from the environment
+        let pytest = check("pytest", &["*.py"]).resolve_command(&config, "pytest -q");
+        assert!(pytest.contains(".venv"));
+        let custom = check("types", &["*.py"]).resolve_command(&config, "pytest -q");
+        assert!(custom.contains(".venv"));
+        let cargo = check("cargo-test", &["*.rs"]).resolve_command(&config, "cargo test");
+        assert_eq!(cargo, "cargo test");
+
+        // The environment is detected once
+        std::fs::remove_dir_all(root.path().join(".venv")).unwrap();
+        assert!(config.python_env().is_some());
+    }
+
+    #[test]
+    fn test_runnable() {
+        let mut check = Check {
+            name: "test".to_string(),
+            command: "tenx-no-such-program --flag".to_string(),
+            globs: vec!["*.rs".to_string()],
+            default_off: false,
+            fail_on_stderr: false,
+            mode: CheckMode::Validate,
+            focus: None,
+            cwd: None,
+            fix: None,
+        };
+        assert!(!check.runnable(&Config::default()).unwrap().is_ok());
+
+        let exe = std::env::current_exe().unwrap();
+        check.command = format!("{} --help", exe.display());
+        assert!(check.runnable(&Config::default()).unwrap().is_ok());
+
+        check.command = "cd sub && tenx-no-such-program".to_string();
+        assert!(check.runnable(&Config::default()).unwrap().is_ok());
+    }
 }
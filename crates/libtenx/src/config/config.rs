@@ -1,25 +1,30 @@
 use std::{
     collections::HashMap,
-    env, fs,
+    env, fmt, fs,
     path::{absolute, Path, PathBuf},
+    sync::Mutex,
 };
 
 use globset::Glob;
 use optional_struct::*;
 use path_clean::clean;
 use pathdiff::diff_paths;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use ron;
 
 use crate::{
     checks,
-    config::default_config,
+    config::{check_language, default_config, detect_languages, edit},
+    credentials::{self, FakeKeychain, Keychain, OsKeychain},
     dialect,
     error::{self, TenxError},
-    model,
+    exec::{Executor, FakeExecutor, ShellExecutor},
+    model, python,
 };
 use state;
+use tracing::debug;
 
 pub const HOME_CONFIG_FILE: &str = "tenx.ron";
 pub const PROJECT_CONFIG_FILE: &str = ".tenx.ron";
@@ -91,7 +96,232 @@ pub fn load_config(current_dir: &Path) -> error::Result<Config> {
     parse_config(&home_config, &project_config, current_dir)
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+/// Returns true if a line of pretty-printed RON is a struct field with no value.
+fn is_unset_field(line: &str) -> bool {
+    line.trim()
+        .strip_suffix(": None,")
+        .is_some_and(|f| f.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+}
+
+/// Serializes a config file, leaving out unset values so the file only records what was
+/// explicitly configured.
+fn config_file_to_ron(cnf: &ConfigFile) -> error::Result<String> {
+    let pretty =
+        ron::ser::PrettyConfig::default().extensions(ron::extensions::Extensions::IMPLICIT_SOME);
+    let ron = ron::ser::to_string_pretty(cnf, pretty)
+        .map_err(|e| TenxError::Internal(format!("Failed to serialize to RON: {}", e)))?;
+    let mut out = ron
+        .lines()
+        .filter(|l| !is_unset_field(l))
+        .collect::<Vec<_>>()
+        .join("\n");
+    out.push('\n');
+    Ok(out)
+}
+
+/// Updates a config file in place, creating it if needed. Only the values set in the file are
+/// written back, so settings inherited from other config files stay where they are. Values that
+/// changed are edited where they are, so comments and formatting survive. If that isn't
+/// possible, the file is rewritten, unless it has comments that would be lost.
+fn update_config_file<F>(path: &Path, f: F) -> error::Result<()>
+where
+    F: FnOnce(&mut ConfigFile) -> error::Result<()>,
+{
+    let text =
+        if path.exists() {
+            Some(fs::read_to_string(path).map_err(|e| {
+                TenxError::Config(format!("Failed to read {}: {}", path.display(), e))
+            })?)
+        } else {
+            None
+        };
+    let old = match &text {
+        Some(text) => parse_config_file(text)
+            .map_err(|e| TenxError::Config(format!("Failed to parse {}: {}", path.display(), e)))?,
+        None => ConfigFile::default(),
+    };
+    let mut cnf = old.clone();
+    f(&mut cnf)?;
+    let ron = config_file_to_ron(&cnf)?;
+    let out = match &text {
+        Some(text) => {
+            let edited = edit::edit(text, &config_file_to_ron(&old)?, &ron)
+                .filter(|t| parse_config_file(t).is_ok_and(|c| c == cnf));
+            match edited {
+                Some(edited) => edited,
+                None if edit::has_comments(text) => {
+                    return Err(TenxError::Config(format!(
+                        "Can't update {} without losing its comments, edit it by hand instead",
+                        path.display()
+                    )))
+                }
+                None => ron,
+            }
+        }
+        None => ron,
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| {
+            TenxError::Config(format!("Failed to create {}: {}", parent.display(), e))
+        })?;
+    }
+    fs::write(path, out)
+        .map_err(|e| TenxError::Config(format!("Failed to write {}: {}", path.display(), e)))
+}
+
+/// Updates the project config file in place, creating it if needed. Only the values set in the
+/// file are written back, so settings inherited from the home config stay where they are.
+pub fn update_project_config<F>(project_root: &Path, f: F) -> error::Result<()>
+where
+    F: FnOnce(&mut ConfigFile) -> error::Result<()>,
+{
+    update_config_file(&project_root.join(PROJECT_CONFIG_FILE), f)
+}
+
+/// The path to the user's home config file.
+pub fn home_config_file() -> PathBuf {
+    home_config_dir().join(HOME_CONFIG_FILE)
+}
+
+/// The top-level setting that can't be addressed by key. Its keys are enums, which have no
+/// representation as plain keys.
+const UNKEYED_SETTING: &str = "modes";
+
+/// Serializes a config value to JSON, which can be navigated and edited by key.
+fn to_json<T: Serialize>(value: &T) -> error::Result<serde_json::Value> {
+    serde_json::to_value(value)
+        .map_err(|e| TenxError::Internal(format!("Failed to serialize config: {}", e)))
+}
+
+/// Finds the value at a dotted key path, like `models.default`, in a JSON value.
+fn lookup<'a>(value: &'a serde_json::Value, key: &str) -> Option<&'a serde_json::Value> {
+    key.split('.').try_fold(value, |v, part| match v {
+        serde_json::Value::Object(m) => m.get(part),
+        _ => None,
+    })
+}
+
+/// Checks that a key names a setting in the config, and returns the setting's value.
+fn config_value(config: &Config, key: &str) -> error::Result<serde_json::Value> {
+    let unknown = || TenxError::Config(format!("Unknown config key: {}", key));
+    if key.split('.').next() == Some(UNKEYED_SETTING) {
+        return Err(TenxError::Config(format!(
+            "{} can't be addressed by key, edit the config file instead",
+            UNKEYED_SETTING
+        )));
+    }
+    let mut config = config.clone();
+    config.modes.clear();
+    lookup(&to_json(&config)?, key).cloned().ok_or_else(unknown)
+}
+
+/// Returns the effective value of a config setting, addressed by a dotted key path like
+/// `models.default` or `step_limit`. Strings are returned as-is, and other values as RON.
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+pub fn get_config_value(config: &Config, key: &str) -> error::Result<String> {
+    match config_value(config, key)? {
+        serde_json::Value::String(s) => Ok(s),
+        v => ron::ser::to_string_pretty(&v, ron::ser::PrettyConfig::default())
+            .map_err(|e| TenxError::Internal(format!("Failed to serialize to RON: {}", e))),
+    }
+}
+
+/// Sets a config setting, addressed by a dotted key path like `models.default`, in the project
+/// config file, or the home config file if `home` is true. The value is parsed as RON, so
+/// `true`, `3` and `["a", "b"]` work as expected. Values that aren't valid RON, like
+/// `claude-3-5`, are taken as strings.
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+pub fn set_config_value(config: &Config, key: &str, value: &str, home: bool) -> error::Result<()> {
+    config_value(config, key)?;
+    let value = match ron::from_str::<serde_json::Value>(value) {
+        Ok(v) if !v.is_null() => v,
+        _ => serde_json::Value::String(value.to_string()),
+    };
+    let path = if home {
+        home_config_file()
+    } else {
+        config.project_root().join(PROJECT_CONFIG_FILE)
+    };
+    update_config_file(&path, |cnf| {
+        let modes = cnf.modes.take();
+        let mut doc = to_json(&*cnf)?;
+        let mut target = &mut doc;
+        for part in key.split('.') {
+            if !target.is_object() {
+                *target = serde_json::Value::Object(Default::default());
+            }
+            target = target
+                .as_object_mut()
+                .expect("target is an object")
+                .entry(part)
+                .or_insert(serde_json::Value::Null);
+        }
+        *target = value;
+        *cnf = serde_json::from_value(doc)
+            .map_err(|e| TenxError::Config(format!("Invalid value for {}: {}", key, e)))?;
+        cnf.modes = modes;
+        Ok(())
+    })
+}
+
+/// Enables or disables a check in the project config file. If `globs` is non-empty, the check
+/// is also scoped to those globs.
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+pub fn set_check_enabled(
+    config: &Config,
+    name: &str,
+    enabled: bool,
+    globs: &[String],
+) -> error::Result<()> {
+    if config.get_check(name).is_none() {
+        return Err(TenxError::Config(format!("Unknown check: {}", name)));
+    }
+    update_project_config(&config.project_root(), |cnf| {
+        let checks = cnf.checks.get_or_insert_with(Default::default);
+        // The project lists replace inherited ones, so start from the current effective lists
+        let mut enable = checks
+            .enable
+            .take()
+            .unwrap_or_else(|| config.checks.enable.clone());
+        let mut disable = checks
+            .disable
+            .take()
+            .unwrap_or_else(|| config.checks.disable.clone());
+        enable.retain(|n| n != name);
+        disable.retain(|n| n != name);
+        if enabled {
+            enable.push(name.to_string());
+        } else {
+            disable.push(name.to_string());
+        }
+        checks.enable = Some(enable);
+        checks.disable = Some(disable);
+        if !globs.is_empty() {
+            checks
+                .globs
+                .get_or_insert_with(|| config.checks.globs.clone())
+                .insert(name.to_string(), globs.to_vec());
+        }
+        Ok(())
+    })
+}
+
+/// Sets the default model in the project config file.
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+pub fn set_default_model(config: &Config, name: &str) -> error::Result<()> {
+    if config
+        .get_model_conf(config.resolve_model_name(name))
+        .is_none()
+    {
+        return Err(TenxError::Config(format!("Unknown model: {}", name)));
+    }
+    update_project_config(&config.project_root(), |cnf| {
+        cnf.models.get_or_insert_with(Default::default).default = Some(name.to_string());
+        Ok(())
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 /// Match specification for a Mode over-ride.
 pub enum ModeSpec {
@@ -101,7 +331,7 @@ pub enum ModeSpec {
 
 /// Mode over-ride configuration.
 #[optional_struct]
-#[derive(Default, Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, Deserialize, Serialize, PartialEq, Eq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct ModeConfig {
     /// The default context configuration.
@@ -111,7 +341,7 @@ pub struct ModeConfig {
 }
 
 /// A named block of text to include as context in model interactions.
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, JsonSchema)]
 pub struct TextContext {
     pub name: String,
     pub content: String,
@@ -119,18 +349,58 @@ pub struct TextContext {
 
 /// Configuration for what context to include in model interactions.
 #[optional_struct]
-#[derive(Default, Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, Deserialize, Serialize, PartialEq, Eq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 /// Defines which context is included in model interactions.
 pub struct Context {
     pub ruskel: Vec<String>,
     pub path: Vec<String>,
     pub project_map: bool,
+    /// Include a summary of the project's manifests, with dependency versions. Off by default.
+    pub project_facts: bool,
+    /// Include the notes in the project's `.tenx/knowledge/` directory, at low priority.
+    pub knowledge: bool,
     pub text: Vec<TextContext>,
     pub cmd: Vec<String>,
+    /// Captured context older than this many hours is stale. 0 means context never expires.
+    pub expire_hours: u64,
+    /// Refresh stale context automatically. If false, we only warn.
+    pub refresh_stale: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+/// Where requests for a Claude or Gemini model are sent.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    /// The provider's own API, authenticated with an API key.
+    #[default]
+    Direct,
+    /// AWS Bedrock, authenticated with SigV4 using the credentials in AWS_ACCESS_KEY_ID,
+    /// AWS_SECRET_ACCESS_KEY and AWS_SESSION_TOKEN. Claude models only.
+    Bedrock {
+        /// The AWS region, e.g. "us-east-1".
+        region: String,
+    },
+    /// GCP Vertex AI, authenticated with application default credentials.
+    Vertex {
+        /// The GCP project ID.
+        project: String,
+        /// The Vertex AI region, e.g. "us-east5", or "global".
+        region: String,
+    },
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Direct => write!(f, "direct"),
+            Backend::Bedrock { region } => write!(f, "bedrock ({})", region),
+            Backend::Vertex { project, region } => write!(f, "vertex ({}, {})", project, region),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 /// Configuration for a specific model provider (Claude, OpenAI, or Google).
 pub enum Model {
@@ -143,6 +413,9 @@ pub enum Model {
         key: String,
         /// The environment variable to load the API key from.
         key_env: String,
+        /// Where requests are sent. Defaults to the Anthropic API.
+        #[serde(default)]
+        backend: Backend,
     },
     OpenAi {
         /// The name of the model.
@@ -173,6 +446,24 @@ pub enum Model {
         key_env: String,
         /// Whether the model can stream responses.
         can_stream: bool,
+        /// Where requests are sent. Defaults to the Google Generative Language API.
+        #[serde(default)]
+        backend: Backend,
+    },
+    /// A model that makes no API calls. It records each rendered request and returns a canned
+    /// response, for offline development and integration tests.
+    Echo {
+        /// The name of the model.
+        name: String,
+        /// The raw response, parsed by the dialect like a real model's response.
+        #[serde(default)]
+        response: String,
+        /// A file to read the raw response from on each request. Over-rides `response` if set.
+        #[serde(default)]
+        response_file: PathBuf,
+        /// A directory to record each rendered request in, if set.
+        #[serde(default)]
+        record_dir: PathBuf,
     },
 }
 
@@ -202,6 +493,7 @@ impl Model {
                 }
                 self
             }
+            Model::Echo { .. } => self,
         }
     }
 
@@ -211,6 +503,7 @@ impl Model {
             Model::Claude { name, .. } => name,
             Model::OpenAi { name, .. } => name,
             Model::Google { name, .. } => name,
+            Model::Echo { name, .. } => name,
         }
     }
 
@@ -220,6 +513,7 @@ impl Model {
             Model::Claude { .. } => "claude",
             Model::OpenAi { .. } => "openai",
             Model::Google { .. } => "google",
+            Model::Echo { .. } => "echo",
         }
     }
 
@@ -229,6 +523,7 @@ impl Model {
             Model::Claude { api_model, .. } => api_model,
             Model::OpenAi { api_model, .. } => api_model,
             Model::Google { api_model, .. } => api_model,
+            Model::Echo { .. } => "echo",
         }
     }
 
@@ -247,6 +542,7 @@ impl Model {
                 api_model,
                 key,
                 key_env,
+                backend,
                 ..
             } => {
                 let key = if verbose {
@@ -258,6 +554,7 @@ impl Model {
                     format!("api_model = {}", api_model),
                     format!("key = {}", key),
                     format!("key_env = {}", key_env),
+                    format!("backend = {}", backend),
                 ]
                 .join("\n")
             }
@@ -290,6 +587,7 @@ impl Model {
                 key,
                 key_env,
                 can_stream,
+                backend,
                 ..
             } => {
                 let key = if verbose {
@@ -302,27 +600,55 @@ impl Model {
                     format!("key = {}", key),
                     format!("key_env = {}", key_env),
                     format!("stream = {}", can_stream),
+                    format!("backend = {}", backend),
                 ]
                 .join("\n")
             }
+            Model::Echo {
+                response,
+                response_file,
+                record_dir,
+                ..
+            } => [
+                format!("response = {} chars", response.len()),
+                format!("response_file = {}", response_file.display()),
+                format!("record_dir = {}", record_dir.display()),
+            ]
+            .join("\n"),
         }
     }
 
-    /// Converts ModelConfig to a Claude, OpenAi, or Google model.
-    pub fn to_model(&self, no_stream: bool) -> error::Result<model::Model> {
+    /// Converts ModelConfig to a Claude, OpenAi, Google or Echo model, looking up keys that
+    /// aren't configured in the keychain.
+    pub(crate) fn to_model(
+        &self,
+        no_stream: bool,
+        max_continuations: usize,
+        rate_limit: Option<(String, RateLimit)>,
+        keychain: &dyn Keychain,
+    ) -> error::Result<model::Model> {
         match self {
-            Model::Claude { api_model, key, .. } => {
+            Model::Claude {
+                api_model,
+                key,
+                backend,
+                ..
+            } => {
                 if api_model.is_empty() {
                     return Err(TenxError::Model("Empty API model name".into()));
                 }
-                if key.is_empty() {
-                    return Err(TenxError::Model("Empty Anthropic API key".into()));
+                let key = credentials::resolve(keychain, "claude", key);
+                if key.is_empty() && *backend == Backend::Direct {
+                    return Err(credentials::missing_key("claude"));
                 }
                 Ok(model::Model::Claude(model::Claude {
                     name: self.name().to_string(),
                     api_model: api_model.clone(),
-                    anthropic_key: key.clone(),
+                    anthropic_key: key,
                     streaming: !no_stream,
+                    max_continuations,
+                    backend: backend.clone(),
+                    rate_limit,
                 }))
             }
             Model::OpenAi {
@@ -336,7 +662,11 @@ impl Model {
             } => Ok(model::Model::OpenAi(model::OpenAi {
                 name: self.name().to_string(),
                 api_model: api_model.clone(),
-                openai_key: key.clone(),
+                openai_key: credentials::resolve(
+                    keychain,
+                    &credentials::openai_provider(api_base),
+                    key,
+                ),
                 api_base: api_base.clone(),
                 streaming: *can_stream && !no_stream,
                 no_system_prompt: *no_system_prompt,
@@ -346,26 +676,44 @@ impl Model {
                     Some(ReasoningEffort::High) => Some(model::ReasoningEffort::High),
                     None => None,
                 },
+                max_continuations,
+                rate_limit,
             })),
             Model::Google {
                 api_model,
                 key,
                 can_stream,
+                backend,
                 ..
             } => {
                 if api_model.is_empty() {
                     return Err(TenxError::Model("Empty API model name".into()));
                 }
-                if key.is_empty() {
-                    return Err(TenxError::Model("Empty Google API key".into()));
+                let key = credentials::resolve(keychain, "google", key);
+                if key.is_empty() && *backend == Backend::Direct {
+                    return Err(credentials::missing_key("google"));
                 }
                 Ok(model::Model::Google(model::Google {
                     name: self.name().to_string(),
                     api_model: api_model.clone(),
-                    api_key: key.clone(),
+                    api_key: key,
                     streaming: *can_stream && !no_stream,
+                    backend: backend.clone(),
+                    max_continuations,
+                    rate_limit,
                 }))
             }
+            Model::Echo {
+                name,
+                response,
+                response_file,
+                record_dir,
+            } => Ok(model::Model::Echo(model::Echo {
+                name: name.clone(),
+                response: response.clone(),
+                response_file: response_file.clone(),
+                record_dir: record_dir.clone(),
+            })),
         }
     }
 }
@@ -373,16 +721,60 @@ impl Model {
 /// Settings related to the dialect we are using to communicate to models. For the moment, we have
 /// only one dialect, so this section is pretty simple.
 #[optional_struct]
-#[derive(Default, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
 /// Settings related to the dialect used for model communication.
 pub struct Dialect {
     /// Allow the model to request to edit files in the project map
     pub edit: bool,
+
+    /// The order in which context items and editable files are rendered in prompts.
+    #[serde(default)]
+    pub order: PromptOrder,
+
+    /// Editable files estimated to be larger than this many tokens are sent as outlines, with
+    /// only the regions relevant to the current step shown in full. The model can ask to expand
+    /// elided regions. 0, the default, means files are always sent in full.
+    #[serde(default)]
+    pub outline_tokens: usize,
+
+    /// Where the dialect's instructions are sent.
+    #[serde(default)]
+    pub system_prompt: SystemPromptMode,
+}
+
+/// Where the dialect's instructions are sent. Some models, particularly local ones, ignore or
+/// reject system prompts, so the instructions are folded into the first user message instead.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SystemPromptMode {
+    /// Fold the instructions into the first user message for models configured with
+    /// `no_system_prompt`, and use a system prompt otherwise
+    #[default]
+    Auto,
+    /// Always use a system prompt
+    System,
+    /// Always fold the instructions into the first user message
+    User,
+}
+
+/// The order in which context items and editable files are rendered in prompts. A stable order
+/// keeps prompts identical between requests, which helps prompt caching and makes prompts easier
+/// to compare.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptOrder {
+    /// The order in which items were added
+    #[default]
+    Insertion,
+    /// Sorted by path or context source
+    Path,
+    /// Sorted by size, smallest first, with ties sorted by path
+    Size,
 }
 
 /// Project configuration.
 #[optional_struct]
-#[derive(Default, Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
 /// Project configuration including root directory and file inclusion rules.
 pub struct Project {
     /// Project root configuration.
@@ -393,10 +785,27 @@ pub struct Project {
     /// over-ridden, Tenx respects .gitignore, .ignore and .git/info/exclude files.
     #[serde(default)]
     pub include: Vec<String>,
+
+    /// Glob patterns for generated files, like "**/*.pb.rs". Generated files stay in the project
+    /// for checks, but are left out of glob-matched context and editables, and the model is told
+    /// not to edit them directly.
+    #[serde(default)]
+    pub generated: Vec<String>,
+
+    /// Languages used in the project, like "rust" or "python". Builtin checks for other languages
+    /// are disabled unless explicitly enabled. Detected from manifests and file extensions when
+    /// empty.
+    #[serde(default)]
+    pub languages: Vec<String>,
+
+    /// Take a git checkpoint of each file before a session first changes it, so `tenx abandon`
+    /// can restore them. Ignored outside git repositories.
+    #[serde(default)]
+    pub checkpoint: bool,
 }
 
 #[optional_struct]
-#[derive(Default, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
 /// Configuration for checks.
 pub struct Checks {
     #[serde(default)]
@@ -409,12 +818,112 @@ pub struct Checks {
     pub enable: Vec<String>,
     #[serde(default)]
     pub no_pre: bool,
+    /// Run the checks once before each action makes changes, so that failures that predate the
+    /// action are reported to the user but not the model. Off by default, since it runs the whole
+    /// check suite for every action.
+    #[serde(default)]
+    pub triage: bool,
+    /// Mark checks that fail identically before and after a change as known failing, and skip
+    /// them for the rest of the session. Implies `triage`.
+    #[serde(default)]
+    pub mark_known_failing: bool,
     #[serde(default)]
     pub only: Option<String>,
+    /// Glob overrides keyed by check name. These replace a check's own globs, scoping where it
+    /// runs.
+    #[serde(default)]
+    pub globs: HashMap<String, Vec<String>>,
+    /// Working directory overrides keyed by check name, relative to the project root. These let
+    /// builtin checks run in a subdirectory, like the root of a nested workspace.
+    #[serde(default)]
+    pub cwd: HashMap<String, PathBuf>,
+    /// The maximum size in bytes of check output sent to the model. Longer output is cut from
+    /// the middle, keeping the head and tail. Zero means no limit.
+    #[serde(default)]
+    pub max_output: usize,
+    /// How check failures are reported to the model when it's asked to fix them.
+    #[serde(default)]
+    pub retry_prompt: RetryPrompt,
+    /// Run a failing check's fix command, like `cargo clippy --fix`, before asking the model to
+    /// fix the failure. Changes the fix makes are recorded as a step of their own.
+    #[serde(default)]
+    pub autofix: bool,
+    /// How long a check may run, in seconds, before it's stopped and reported as an error. Zero
+    /// means no limit.
+    #[serde(default)]
+    pub timeout: u64,
+    /// Timeout overrides in seconds, keyed by check name.
+    #[serde(default)]
+    pub timeouts: HashMap<String, u64>,
+    /// Skip a check if it passed before with the same command and the same content in every
+    /// project file its globs match. Passes are recorded in the cache directory. Off by default,
+    /// since a check can depend on files its globs don't match, like a manifest.
+    #[serde(default)]
+    pub cache: bool,
+}
+
+/// How check failures are framed for the model. Models differ in what feedback they fix
+/// failures best from.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryPrompt {
+    /// The check output only
+    Errors,
+    /// The check output, and a diff of the changes that led to the failure
+    #[default]
+    ErrorsDiff,
+    /// The check output, and the source of the failing tests, for Rust checks that report them
+    ErrorsTests,
+    /// A custom template, in which `{errors}`, `{diff}` and `{tests}` are replaced with the check
+    /// output, the diff and the source of the failing tests. Unavailable values are empty.
+    Template(String),
+}
+
+#[optional_struct]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+/// Processors that fix trivial issues in files written by the model, applied as each patch is
+/// applied and before checks run.
+pub struct PostProcess {
+    /// Make sure non-empty files end with exactly one newline.
+    #[serde(default)]
+    pub final_newline: bool,
+
+    /// Strip trailing whitespace from every line.
+    #[serde(default)]
+    pub trim_trailing_whitespace: bool,
+
+    /// License headers to insert at the top of new files that don't already have one.
+    #[serde(default)]
+    pub license_headers: Vec<LicenseHeader>,
 }
 
 #[optional_struct]
-#[derive(Default, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+/// Redaction of secrets, like API keys and private keys, from context and editable files before
+/// they're sent to the model.
+pub struct Scrub {
+    /// Send content to the model without redacting secrets.
+    #[serde(default)]
+    pub disable: bool,
+
+    /// Regular expressions matching values that should never be redacted, like example keys in
+    /// test fixtures.
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+/// A license header for new files matching a set of glob patterns.
+pub struct LicenseHeader {
+    /// Glob patterns matched against file paths relative to the project root.
+    pub globs: Vec<String>,
+
+    /// The header text, including any comment markers.
+    pub text: String,
+}
+
+#[optional_struct]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
 /// Configuration for available models.
 pub struct Models {
     /// Custom model configurations. Entries with the same name as a builtin will override the
@@ -433,9 +942,173 @@ pub struct Models {
     /// Disable streaming for all models
     #[serde(default)]
     pub no_stream: bool,
+
+    /// The maximum number of continuation requests to issue when a model response is truncated
+    /// because it hit the output token limit. Set to 0 to disable continuations.
+    #[serde(default)]
+    pub max_continuations: usize,
+
+    /// Rate limits, keyed by provider kind (e.g. "claude", "openai" or "google"). Limits apply
+    /// to all requests made to a provider by this process.
+    #[serde(default)]
+    pub rate_limits: HashMap<String, RateLimit>,
+
+    /// Model pricing, keyed by API model name (e.g. "claude-3-7-sonnet-latest"). Used to estimate
+    /// spend against the budget.
+    #[serde(default)]
+    pub pricing: HashMap<String, Pricing>,
+
+    /// Context window sizes in tokens, keyed by API model name. If the prompt would exceed the
+    /// window, context items are cut to fit.
+    #[serde(default)]
+    pub context_windows: HashMap<String, usize>,
+
+    /// Default sampling parameters for all models.
+    #[serde(default)]
+    pub sampling: Sampling,
+
+    /// Sampling parameters keyed by model name. These override the defaults.
+    #[serde(default)]
+    pub model_sampling: HashMap<String, Sampling>,
+
+    /// Sampling parameters keyed by strategy name (e.g. "code" or "fix"). These override both the
+    /// defaults and the per-model parameters.
+    #[serde(default)]
+    pub strategy_sampling: HashMap<String, Sampling>,
+}
+
+#[derive(Default, Debug, Clone, Copy, Serialize, Deserialize, PartialEq, JsonSchema)]
+/// The price of a model, in USD per million tokens.
+pub struct Pricing {
+    /// The price of input tokens.
+    #[serde(default)]
+    pub input: f64,
+
+    /// The price of output tokens.
+    #[serde(default)]
+    pub output: f64,
+}
+
+impl Pricing {
+    /// Returns the cost in USD of a request with the given token counts.
+    pub fn cost(&self, input_tokens: u64, output_tokens: u64) -> f64 {
+        (input_tokens as f64 * self.input + output_tokens as f64 * self.output) / 1_000_000.0
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+/// Sampling parameters for model requests. Unset values are left to the provider's defaults.
+pub struct Sampling {
+    /// The sampling temperature.
+    #[serde(default)]
+    pub temperature: Option<f32>,
+
+    /// Nucleus sampling probability mass.
+    #[serde(default)]
+    pub top_p: Option<f32>,
+
+    /// The maximum number of output tokens per request.
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+
+    /// Sequences that stop generation.
+    #[serde(default)]
+    pub stop: Vec<String>,
+}
+
+impl Sampling {
+    /// Returns these parameters with any values set in `other` taking precedence.
+    pub fn merge(&self, other: &Sampling) -> Sampling {
+        Sampling {
+            temperature: other.temperature.or(self.temperature),
+            top_p: other.top_p.or(self.top_p),
+            max_tokens: other.max_tokens.or(self.max_tokens),
+            stop: if other.stop.is_empty() {
+                self.stop.clone()
+            } else {
+                other.stop.clone()
+            },
+        }
+    }
+
+    /// Returns true if no parameters are set.
+    pub fn is_empty(&self) -> bool {
+        *self == Sampling::default()
+    }
+}
+
+impl fmt::Display for Sampling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = vec![];
+        if let Some(t) = self.temperature {
+            parts.push(format!("temperature={}", t));
+        }
+        if let Some(p) = self.top_p {
+            parts.push(format!("top_p={}", p));
+        }
+        if let Some(m) = self.max_tokens {
+            parts.push(format!("max_tokens={}", m));
+        }
+        if !self.stop.is_empty() {
+            parts.push(format!("stop={:?}", self.stop));
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+#[optional_struct]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+/// Spending limits, in USD. A value of 0 means no limit.
+pub struct Budget {
+    /// The maximum spend for a single session.
+    #[serde(default)]
+    pub session_usd: f64,
+
+    /// The maximum spend across all sessions in a day (UTC).
+    #[serde(default)]
+    pub daily_usd: f64,
+
+    /// Prompt the model even if this would exceed the budget. Usually set with --over-budget on
+    /// the command line.
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[optional_struct]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+/// Safety analysis of patches before they're applied. Patches that delete many lines, change CI
+/// workflows or dependency manifests, or touch `unsafe` code need to be confirmed.
+pub struct Safety {
+    /// Analyze patches for risky operations.
+    #[serde(default)]
+    pub check: bool,
+
+    /// Apply risky patches without asking. Usually set with --allow-risky on the command line.
+    #[serde(default)]
+    pub allow_risky: bool,
+
+    /// Deleting more than this many lines from a file is risky. Zero means no limit.
+    #[serde(default)]
+    pub max_deleted_lines: usize,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+/// Rate limits for a model provider. A value of 0 means no limit.
+pub struct RateLimit {
+    /// The maximum number of requests started in any one minute window.
+    #[serde(default)]
+    pub requests_per_minute: u64,
+
+    /// The maximum number of tokens, input and output, used in any one minute window.
+    #[serde(default)]
+    pub tokens_per_minute: u64,
+
+    /// The maximum number of requests in flight at once.
+    #[serde(default)]
+    pub max_concurrent: usize,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 /// When a check should run - before changes, after changes, or both.
 pub enum ReasoningEffort {
@@ -444,10 +1117,21 @@ pub enum ReasoningEffort {
     High,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+/// The storage backend used for sessions.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionStoreKind {
+    /// One JSON file per session
+    #[default]
+    Files,
+    /// A single SQLite database
+    Sqlite,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
 /// Configuration for a specific check.
 pub struct CheckConfig {
-    /// Name of the validator for display and error reporting
+    /// Name of the check for display and error reporting
     pub name: String,
 
     /// Shell command to execute, run with sh -c
@@ -456,13 +1140,44 @@ pub struct CheckConfig {
     /// List of glob patterns to match against files for determining relevance
     pub globs: Vec<String>,
 
-    /// Whether this validator defaults to off in the configuration
+    /// Whether this check defaults to off in the configuration
     #[serde(default)]
     pub default_off: bool,
 
     /// Whether to treat any stderr output as a failure, regardless of exit code
     #[serde(default)]
     pub fail_on_stderr: bool,
+
+    /// Whether the check rewrites files, like a formatter, or only validates them
+    #[serde(default)]
+    pub mode: checks::CheckMode,
+
+    /// How to re-run only the tests that failed, while iterating on a fix
+    #[serde(default)]
+    pub focus: Option<TestFocus>,
+
+    /// The directory to run the command in, relative to the project root. Defaults to the
+    /// project root.
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
+
+    /// A command that mechanically fixes what it can of the check's failures, like
+    /// `cargo clippy --fix`. Run before involving the model when `checks.autofix` is set.
+    #[serde(default)]
+    pub fix: Option<String>,
+}
+
+/// Lets a check re-run only the tests that failed on its last run. Once the focused tests pass,
+/// the full check runs again to confirm the fix.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+pub struct TestFocus {
+    /// A regex matched against each line of the check's output. The first capture group is the
+    /// name of a failing test.
+    pub pattern: String,
+
+    /// The command that runs only the named tests. `{tests}` is replaced with the quoted,
+    /// space-separated test names.
+    pub command: String,
 }
 
 impl CheckConfig {
@@ -474,12 +1189,16 @@ impl CheckConfig {
             globs: self.globs.clone(),
             default_off: self.default_off,
             fail_on_stderr: self.fail_on_stderr,
+            mode: self.mode,
+            focus: self.focus.clone(),
+            cwd: self.cwd.clone(),
+            fix: self.fix.clone(),
         }
     }
 }
 
 #[optional_struct(ConfigFile)]
-#[derive(Debug, Default, Clone, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Default, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
 /// Primary configuration struct containing all settings.
 pub struct Config {
     /// Model configuration
@@ -492,13 +1211,32 @@ pub struct Config {
     #[optional_wrap]
     pub project: Project,
 
+    /// A subtree of the project to work in, like "crates/libtenx", relative to the project root.
+    /// Only files under it are included, checks run in it, and languages and workspace members
+    /// are discovered from it. Paths are still relative to the project root. Empty means the
+    /// whole project.
+    #[serde(default)]
+    pub scope: PathBuf,
+
     /// The directory to store session state. Defaults to ~/.config/tenx/state
     pub session_store_dir: PathBuf,
 
+    /// The storage backend for sessions, either "files" or "sqlite". Defaults to "files".
+    pub session_store: SessionStoreKind,
+
+    /// The directory for cached data, like the skeletons of published crates. Defaults to
+    /// ~/.config/tenx/cache
+    pub cache_dir: PathBuf,
+
     /// The number of steps we can take autonomously without user input. This doesn't limit the
     /// total number of steps in a session.
     pub step_limit: usize,
 
+    /// Stop stepping when this many consecutive steps fail with the same error after making
+    /// near-identical changes, since the model is stuck. Values below 2 disable the check.
+    #[serde(default)]
+    pub stuck_limit: usize,
+
     /// Operations that can be executed by the model.
     #[optional_rename(OptionalDialect)]
     #[optional_wrap]
@@ -514,9 +1252,48 @@ pub struct Config {
     #[optional_wrap]
     pub checks: Checks,
 
+    /// Processing applied to files written by the model.
+    #[optional_rename(OptionalPostProcess)]
+    #[optional_wrap]
+    pub post_process: PostProcess,
+
+    /// Secret redaction for content sent to models.
+    #[optional_rename(OptionalScrub)]
+    #[optional_wrap]
+    pub scrub: Scrub,
+
+    /// Spending limits.
+    #[optional_rename(OptionalBudget)]
+    #[optional_wrap]
+    pub budget: Budget,
+
+    /// Safety analysis of patches.
+    #[optional_rename(OptionalSafety)]
+    #[optional_wrap]
+    pub safety: Safety,
+
+    /// The width to wrap rendered output to when stdout is not a terminal. Zero uses the
+    /// renderer's default.
+    pub term_width: usize,
+
+    /// Never touch the network. Models that call an API are replaced by an echo model that
+    /// returns an empty response, and URL and Ruskel contexts get placeholder content instead of
+    /// being fetched. This lets applications that embed libtenx run their tests hermetically.
+    #[serde(default)]
+    pub offline: bool,
+
     /// Mode configuration
     pub modes: HashMap<ModeSpec, ModeConfig>,
 
+    /// Model aliases, mapping short names like "fast" or "smart" to configured model names.
+    /// Aliases can be used anywhere a model name is accepted.
+    pub aliases: HashMap<String, String>,
+
+    /// Named groups of context specifications, which can be added to a session together. Each
+    /// entry is a path or glob pattern, or a prefixed specification like `ruskel:hyper`,
+    /// `url:https://example.com` or `cmd:cargo tree`.
+    pub context_groups: HashMap<String, Vec<String>>,
+
     // Internal fields, not to be set in config
     //
     /// Set a dummy model for end-to-end testing. Over-rides the configured model.
@@ -527,10 +1304,69 @@ pub struct Config {
     #[serde(skip)]
     pub(crate) dummy_dialect: Option<dialect::DummyDialect>,
 
+    /// Set a fake executor for running checks in tests. Over-rides the platform shell.
+    #[serde(skip)]
+    pub(crate) dummy_executor: Option<FakeExecutor>,
+
+    /// Set a fake keychain for tests. Over-rides the platform keychain.
+    #[serde(skip)]
+    pub(crate) dummy_keychain: Option<FakeKeychain>,
+
     /// The current working directory when testing. We need this, because we can't change the CWD
     /// reliably in tests for reasons of concurrency.
     #[serde(skip)]
     pub(crate) cwd: Option<PathBuf>,
+
+    /// Facts detected from the project, cached on first use.
+    #[serde(skip)]
+    pub(crate) detected: Detected,
+}
+
+/// A value detected from the project, along with the root it was detected in.
+type Slot<T> = Mutex<Option<(PathBuf, T)>>;
+
+/// Facts detected from the project on first use and cached, since detection touches the
+/// filesystem or runs commands. Each is detected again if the root it came from changes. The
+/// cache isn't configuration, so it doesn't affect equality.
+#[derive(Debug, Default)]
+pub(crate) struct Detected {
+    languages: Slot<Vec<String>>,
+    python_env: Slot<Option<python::PythonEnv>>,
+    uninstalled_checks: Slot<Vec<(String, String)>>,
+}
+
+impl Detected {
+    /// Returns the cached value for `root`, detecting it if there's none.
+    fn get<T: Clone>(slot: &Slot<T>, root: &Path, detect: impl FnOnce() -> T) -> T {
+        let mut slot = slot.lock().unwrap_or_else(|e| e.into_inner());
+        match &*slot {
+            Some((r, value)) if r == root => value.clone(),
+            _ => {
+                let value = detect();
+                *slot = Some((root.to_path_buf(), value.clone()));
+                value
+            }
+        }
+    }
+}
+
+impl Clone for Detected {
+    fn clone(&self) -> Self {
+        fn copy<T: Clone>(slot: &Slot<T>) -> Slot<T> {
+            Mutex::new(slot.lock().unwrap_or_else(|e| e.into_inner()).clone())
+        }
+        Detected {
+            languages: copy(&self.languages),
+            python_env: copy(&self.python_env),
+            uninstalled_checks: copy(&self.uninstalled_checks),
+        }
+    }
+}
+
+impl PartialEq for Detected {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
 }
 
 impl Config {
@@ -564,6 +1400,12 @@ impl Config {
         }
     }
 
+    /// Runs without touching the network. See `offline`.
+    pub fn with_offline(mut self) -> Self {
+        self.offline = true;
+        self
+    }
+
     /// Sets the current working directory, so we don't consult the environment
     pub fn with_cwd(mut self, path: PathBuf) -> Self {
         self.cwd = Some(path);
@@ -578,6 +1420,11 @@ impl Config {
         }
     }
 
+    /// Returns the directory of the configured scope, or the project root if there's no scope.
+    pub fn scope_root(&self) -> PathBuf {
+        self.project_root().join(&self.scope)
+    }
+
     /// Calculates the relative path from the root to the given absolute path.
     pub fn relpath(&self, path: &Path) -> PathBuf {
         diff_paths(path, self.project_root()).unwrap_or_else(|| path.to_path_buf())
@@ -634,6 +1481,16 @@ impl Config {
         }))
     }
 
+    /// Returns true if the path, relative to the project root, matches one of the project's
+    /// generated file patterns.
+    pub fn is_generated(&self, path: &Path) -> bool {
+        self.project.generated.iter().any(|pattern| {
+            Glob::new(pattern)
+                .map(|g| g.compile_matcher().is_match(path))
+                .unwrap_or(false)
+        })
+    }
+
     /// Traverse the included files and return a list of files that match the given glob pattern.
     pub fn match_files_with_glob(&self, pattern: &str) -> error::Result<Vec<PathBuf>> {
         let project_root = &self.project_root();
@@ -684,16 +1541,29 @@ impl Config {
     /// a memory overlay for files prefixed with "::".
     pub fn state(&self) -> error::Result<state::State> {
         let s = state::State::default()
-            .with_directory(&self.project.root, self.project.include.clone())?;
+            .with_directory(&self.project.root, self.project.include.clone())?
+            .with_scope(&self.scope);
         Ok(s)
     }
 
+    /// Checks that a path taken from a model, a tool's output or an API client can't reach
+    /// outside the project root.
+    pub fn confine(&self, path: &Path) -> error::Result<()> {
+        Ok(self.state()?.confine(path)?)
+    }
+
     pub fn project_files(&self) -> error::Result<Vec<PathBuf>> {
         let root = state::abspath::AbsPath::new(self.project.root.clone())?;
-        let ret = state::files::list_files(root, self.project.include.clone())?;
+        let ret = state::files::list_files_under(root, &self.scope, self.project.include.clone())?;
         Ok(ret)
     }
 
+    /// Returns a JSON Schema describing config files, in which every setting is optional.
+    pub fn json_schema() -> error::Result<String> {
+        serde_json::to_string_pretty(&schemars::schema_for!(ConfigFile))
+            .map_err(|e| TenxError::Internal(format!("Failed to serialize schema: {}", e)))
+    }
+
     /// Serialize the Config into a RON string.
     pub fn to_ron(&self) -> error::Result<String> {
         let pretty_config = ron::ser::PrettyConfig::default();
@@ -701,6 +1571,32 @@ impl Config {
             .map_err(|e| TenxError::Internal(format!("Failed to serialize to RON: {}", e)))
     }
 
+    pub fn with_dummy_executor(mut self, executor: FakeExecutor) -> Self {
+        self.dummy_executor = Some(executor);
+        self
+    }
+
+    /// Returns the executor that checks run their commands with.
+    pub(crate) fn executor(&self) -> Box<dyn Executor> {
+        match &self.dummy_executor {
+            Some(executor) => Box::new(executor.clone()),
+            None => Box::new(ShellExecutor),
+        }
+    }
+
+    pub fn with_dummy_keychain(mut self, keychain: FakeKeychain) -> Self {
+        self.dummy_keychain = Some(keychain);
+        self
+    }
+
+    /// Returns the keychain that models look up stored keys in.
+    pub(crate) fn keychain(&self) -> Box<dyn Keychain> {
+        match &self.dummy_keychain {
+            Some(keychain) => Box::new(keychain.clone()),
+            None => Box::new(OsKeychain),
+        }
+    }
+
     pub fn with_dummy_model(mut self, model: model::DummyModel) -> Self {
         self.dummy_model = Some(model);
         self
@@ -735,13 +1631,61 @@ impl Config {
         self
     }
 
+    /// Resolves a model name that may be an alias to the name of a configured model. Names that
+    /// are not aliases are returned unchanged.
+    pub fn resolve_model_name(&self, name: &str) -> String {
+        self.aliases
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| name.to_string())
+    }
+
+    /// Returns the name of the active model, with any alias resolved.
+    pub fn model_name(&self) -> String {
+        self.resolve_model_name(&self.models.default)
+    }
+
+    /// Returns the alias used to select the active model, if any.
+    pub fn model_alias(&self) -> Option<String> {
+        self.aliases
+            .contains_key(&self.models.default)
+            .then(|| self.models.default.clone())
+    }
+
+    /// Returns a copy of this config with the given model or alias as the active model.
+    pub fn with_model(&self, model: &str) -> Config {
+        let mut config = self.clone();
+        config.models.default = model.to_string();
+        config
+    }
+
+    /// Returns all aliases that resolve to the given model name, sorted.
+    pub fn aliases_for(&self, name: &str) -> Vec<String> {
+        let mut ret: Vec<String> = self
+            .aliases
+            .iter()
+            .filter(|(_, v)| *v == name)
+            .map(|(k, _)| k.clone())
+            .collect();
+        ret.sort();
+        ret
+    }
+
+    /// Returns the context specifications in the named context group.
+    pub fn context_group(&self, name: &str) -> error::Result<&[String]> {
+        self.context_groups
+            .get(name)
+            .map(|v| v.as_slice())
+            .ok_or_else(|| TenxError::Config(format!("Unknown context group: {}", name)))
+    }
+
     /// Returns the configured model.
     pub fn active_model(&self) -> error::Result<model::Model> {
         if let Some(dummy_model) = &self.dummy_model {
             return Ok(model::Model::Dummy(dummy_model.clone()));
         }
 
-        let name = self.models.default.clone();
+        let name = self.model_name();
 
         let model_config = self
             .model_confs()
@@ -749,47 +1693,126 @@ impl Config {
             .find(|m| m.name() == name)
             .ok_or_else(|| TenxError::Internal(format!("Model {} not found", name)))?;
 
-        match model_config {
-            Model::Claude {
-                name,
-                api_model,
-                key,
-                ..
-            } => Ok(model::Model::Claude(model::Claude {
-                name: name.clone(),
-                api_model: api_model.clone(),
-                anthropic_key: key.clone(),
-                streaming: !self.models.no_stream,
-            })),
-            Model::OpenAi {
-                api_model,
-                key,
-                api_base,
-                can_stream,
-                no_system_prompt,
-                ..
-            } => Ok(model::Model::OpenAi(model::OpenAi {
-                name: name.clone(),
-                api_model: api_model.clone(),
-                openai_key: key.clone(),
-                api_base: api_base.clone(),
-                streaming: can_stream && !self.models.no_stream,
-                no_system_prompt,
-                reasoning_effort: None,
-            })),
-            Model::Google {
-                name,
-                api_model,
-                key,
-                can_stream,
-                ..
-            } => Ok(model::Model::Google(model::Google {
-                name: name.clone(),
-                api_model: api_model.clone(),
-                api_key: key.clone(),
-                streaming: can_stream && !self.models.no_stream,
-            })),
+        self.to_model(&model_config)
+    }
+
+    /// Converts a model configuration to a model, with this config's streaming, continuation
+    /// and rate limit settings and keychain. Offline, models that call an API become an echo
+    /// model.
+    pub fn to_model(&self, model_config: &Model) -> error::Result<model::Model> {
+        if self.offline && !matches!(model_config, Model::Echo { .. }) {
+            return Ok(model::Model::Echo(model::Echo {
+                name: model_config.name().to_string(),
+                ..Default::default()
+            }));
+        }
+        model_config.to_model(
+            self.models.no_stream,
+            self.models.max_continuations,
+            self.rate_limit_for(model_config),
+            &*self.keychain(),
+        )
+    }
+
+    /// Returns the provider kind and rate limits for the active model, if any are configured.
+    pub fn rate_limit(&self) -> Option<(String, RateLimit)> {
+        if self.dummy_model.is_some() {
+            return None;
         }
+        let name = self.model_name();
+        self.rate_limit_for(&self.model_confs().into_iter().find(|m| m.name() == name)?)
+    }
+
+    /// Returns the provider kind and rate limits for a model, if any are configured.
+    fn rate_limit_for(&self, model_config: &Model) -> Option<(String, RateLimit)> {
+        if self.offline {
+            return None;
+        }
+        let kind = model_config.kind();
+        self.models
+            .rate_limits
+            .get(kind)
+            .map(|limits| (kind.to_string(), limits.clone()))
+    }
+
+    /// Returns the API model name of the active model.
+    fn active_api_model(&self) -> Option<String> {
+        if self.dummy_model.is_some() {
+            Some("dummy".to_string())
+        } else {
+            Some(
+                self.get_model_conf(self.model_name())?
+                    .api_model()
+                    .to_string(),
+            )
+        }
+    }
+
+    /// Is the default model one we can use? False if it names a model that isn't configured, for
+    /// instance because its provider's API key isn't set.
+    pub fn has_default_model(&self) -> bool {
+        self.dummy_model.is_some() || self.get_model_conf(self.model_name()).is_some()
+    }
+
+    /// Returns the pricing for a configured model, if it's known.
+    pub fn pricing_for(&self, name: &str) -> Option<Pricing> {
+        let model = self.get_model_conf(name)?;
+        self.models.pricing.get(model.api_model()).copied()
+    }
+
+    /// Returns the context window of a configured model in tokens, if it's known.
+    pub fn context_window_for(&self, name: &str) -> Option<usize> {
+        let model = self.get_model_conf(name)?;
+        self.models.context_windows.get(model.api_model()).copied()
+    }
+
+    /// Returns the pricing for the active model, if it's known.
+    pub fn pricing(&self) -> Option<Pricing> {
+        self.models.pricing.get(&self.active_api_model()?).copied()
+    }
+
+    /// Returns the context window of the active model in tokens, if it's known.
+    pub fn context_window(&self) -> Option<usize> {
+        self.models
+            .context_windows
+            .get(&self.active_api_model()?)
+            .copied()
+    }
+
+    /// Should the dialect's instructions be folded into the first user message for the active
+    /// model, rather than sent as a system prompt?
+    pub fn fold_system_prompt(&self) -> bool {
+        match self.dialect.system_prompt {
+            SystemPromptMode::System => false,
+            SystemPromptMode::User => true,
+            SystemPromptMode::Auto => self.get_model_conf(self.model_name()).is_some_and(|m| {
+                matches!(
+                    m,
+                    Model::OpenAi {
+                        no_system_prompt: true,
+                        ..
+                    }
+                )
+            }),
+        }
+    }
+
+    /// Returns the sampling parameters for a model and strategy. Per-strategy parameters take
+    /// precedence over per-model parameters, which take precedence over the defaults.
+    pub fn sampling_for(&self, model: &str, strategy: &str) -> Sampling {
+        let mut ret = self.models.sampling.clone();
+        if let Some(s) = self.models.model_sampling.get(model) {
+            ret = ret.merge(s);
+        }
+        if let Some(s) = self.models.strategy_sampling.get(strategy) {
+            ret = ret.merge(s);
+        }
+        ret
+    }
+
+    /// Returns the sampling parameters for the active model and a strategy.
+    pub fn sampling(&self, strategy: &str) -> Sampling {
+        self.sampling_for(&self.model_name(), strategy)
     }
 
     /// Returns the configured dialect.
@@ -829,6 +1852,15 @@ impl Config {
             }
         }
 
+        for check in checks.iter_mut() {
+            if let Some(globs) = self.checks.globs.get(&check.name) {
+                check.globs = globs.clone();
+            }
+            if let Some(cwd) = self.checks.cwd.get(&check.name) {
+                check.cwd = Some(cwd.clone());
+            }
+        }
+
         checks
     }
 
@@ -839,9 +1871,95 @@ impl Config {
             .find(|c| c.name == name.as_ref())
     }
 
-    /// Returns true if a check is enabled based on its name and default state in the config
+    /// Returns the project's languages, as configured or detected from the project root. Detected
+    /// languages are cached.
+    pub fn languages(&self) -> Vec<String> {
+        if self.project.languages.is_empty() {
+            let root = self.scope_root();
+            Detected::get(&self.detected.languages, &root, || {
+                let languages = detect_languages(&root);
+                debug!("Detected languages in {}: {:?}", root.display(), languages);
+                languages
+            })
+        } else {
+            self.project.languages.clone()
+        }
+    }
+
+    /// Discovers the project's Python environment, if it has one. The result is cached.
+    pub fn python_env(&self) -> Option<python::PythonEnv> {
+        let root = self.scope_root();
+        Detected::get(&self.detected.python_env, &root, || {
+            python::detect(&root, self.executor().as_ref())
+        })
+    }
+
+    /// Returns the builtin language checks whose program isn't installed, with the reason for
+    /// each. Checks replaced by custom checks aren't probed. The result is cached, since probing
+    /// searches the `PATH`.
+    fn uninstalled_checks(&self) -> Vec<(String, String)> {
+        let root = self.scope_root();
+        Detected::get(&self.detected.uninstalled_checks, &root, || {
+            self.checks
+                .builtin
+                .iter()
+                .filter(|b| check_language(&b.name).is_some())
+                .filter(|b| !self.checks.custom.iter().any(|c| c.name == b.name))
+                .filter_map(|b| match self.get_check(&b.name)?.runnable(self) {
+                    Ok(checks::Runnable::Error(reason)) => Some((b.name.clone(), reason)),
+                    _ => None,
+                })
+                .collect()
+        })
+    }
+
+    /// Returns why a check is skipped even though its configuration enables it, if it is. Builtin
+    /// language checks are skipped if their program isn't installed, unless they are explicitly
+    /// enabled.
+    fn check_skipped(&self, name: &str) -> Option<String> {
+        // A fake executor runs any command, so there's nothing to look for
+        if self.checks.enable.iter().any(|c| c == name) || self.dummy_executor.is_some() {
+            return None;
+        }
+        self.uninstalled_checks()
+            .into_iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, reason)| reason)
+    }
+
+    /// Returns the checks that the configuration enables but that are skipped, with the reason
+    /// for each.
+    pub fn skipped_checks(&self) -> Vec<(checks::Check, String)> {
+        self.all_checks()
+            .into_iter()
+            .filter(|check| self.check_configured(&check.name))
+            .filter_map(|check| {
+                let reason = self.check_skipped(&check.name)?;
+                Some((check, reason))
+            })
+            .collect()
+    }
+
+    /// Returns true if a check is enabled based on its name and default state in the config.
+    /// Language-specific builtin checks are only enabled by default if the project uses their
+    /// language, or if the project's languages are unknown, and are skipped if their program
+    /// isn't installed.
     pub fn is_check_enabled<S: AsRef<str>>(&self, name: S) -> bool {
         let name = name.as_ref();
+        self.check_configured(name) && self.check_skipped(name).is_none()
+    }
+
+    /// Returns true if the configuration enables a check, before looking for its program.
+    fn check_configured(&self, name: &str) -> bool {
+        if let Some(language) = check_language(name) {
+            let languages = self.languages();
+            if !languages.is_empty()
+                && !languages.iter().any(|l| l == language)
+                && !self.checks.enable.iter().any(|c| c == name)
+            {
+                return false;
+            }
+        }
         if let Some(check) = self.get_check(name) {
             if check.default_off() {
                 // Return only if explicitly enabled
@@ -855,8 +1973,14 @@ impl Config {
         }
     }
 
-    /// Return all enabled checks.
+    /// Return all enabled checks, with transforms ahead of validators.
     pub fn enabled_checks(&self) -> Vec<checks::Check> {
+        let mut checks = self.enabled_checks_unordered();
+        checks.sort_by_key(|c| c.mode);
+        checks
+    }
+
+    fn enabled_checks_unordered(&self) -> Vec<checks::Check> {
         if let Some(only_check) = &self.checks.only {
             self.all_checks()
                 .into_iter()
@@ -893,6 +2017,16 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_json_schema() -> error::Result<()> {
+        let schema: serde_json::Value = serde_json::from_str(&Config::json_schema()?).unwrap();
+        assert_eq!(schema["title"], "ConfigFile");
+        assert!(schema["properties"]["step_limit"].is_object());
+        // Internal fields aren't part of the file format
+        assert!(schema["properties"]["dummy_model"].is_null());
+        Ok(())
+    }
+
     #[test]
     fn test_config_roundtrip() -> error::Result<()> {
         let project = testutils::test_project();
@@ -908,6 +2042,385 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_sampling() -> error::Result<()> {
+        let project = testutils::test_project();
+        let config = parse_config(
+            "",
+            r#"(models: (
+                sampling: (temperature: Some(0.7), max_tokens: Some(4000)),
+                model_sampling: {"sonnet": (temperature: Some(0.5), stop: ["END"])},
+                strategy_sampling: {"fix": (temperature: Some(0.1))},
+            ))"#,
+            &project.config.cwd()?,
+        )?;
+        let code = config.sampling_for("sonnet", "code");
+        assert_eq!(code.temperature, Some(0.5));
+        assert_eq!(code.max_tokens, Some(4000));
+        assert_eq!(code.stop, vec!["END".to_string()]);
+
+        let fix = config.sampling_for("sonnet", "fix");
+        assert_eq!(fix.temperature, Some(0.1));
+        assert_eq!(fix.stop, vec!["END".to_string()]);
+
+        let other = config.sampling_for("gpt4o", "code");
+        assert_eq!(other.temperature, Some(0.7));
+        assert!(other.stop.is_empty());
+        assert!(Config::default().sampling("code").is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_default_model() -> error::Result<()> {
+        let project = testutils::test_project();
+        let mut config = project.config.clone();
+        config.models.default = "missing".to_string();
+        config.models.builtin = vec![Model::Claude {
+            name: "haiku".to_string(),
+            api_model: "claude-3-5-haiku-latest".to_string(),
+            key: "".to_string(),
+            key_env: "".to_string(),
+            backend: Default::default(),
+        }];
+        config.models.pricing = HashMap::from([(
+            "claude-3-5-haiku-latest".to_string(),
+            Pricing {
+                input: 0.8,
+                output: 4.0,
+            },
+        )]);
+        assert!(!config.has_default_model());
+        assert_eq!(config.pricing_for("haiku").map(|p| p.output), Some(4.0));
+        assert_eq!(config.context_window_for("haiku"), None);
+
+        assert!(set_default_model(&config, "nonexistent").is_err());
+        set_default_model(&config, "haiku")?;
+        let root = config.project_root();
+        let text = fs::read_to_string(root.join(PROJECT_CONFIG_FILE))?;
+        let parsed = parse_config("", &text, &project.config.cwd()?)?;
+        assert_eq!(parsed.models.default, "haiku");
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_check_enabled() -> error::Result<()> {
+        let project = testutils::test_project();
+        let root = project.config.project_root();
+        fs::write(
+            root.join(PROJECT_CONFIG_FILE),
+            "// Too slow\n(models: (default: \"haiku\"), checks: (disable: [\"cargo-clippy\"]))",
+        )?;
+        let config = parse_config(
+            "",
+            &fs::read_to_string(root.join(PROJECT_CONFIG_FILE))?,
+            &project.config.cwd()?,
+        )?;
+        assert_eq!(config.project_root(), root);
+
+        set_check_enabled(&config, "cargo-clippy", true, &[])?;
+        set_check_enabled(&config, "pytest", true, &["src/**/*.py".to_string()])?;
+        assert!(set_check_enabled(&config, "nonexistent", true, &[]).is_err());
+
+        let text = fs::read_to_string(root.join(PROJECT_CONFIG_FILE))?;
+        assert!(!text.contains("None"));
+        assert!(text.starts_with("// Too slow\n"));
+        let parsed = parse_config("", &text, &project.config.cwd()?)?;
+        assert_eq!(parsed.models.default, "haiku");
+        assert!(parsed.is_check_enabled("cargo-clippy"));
+        assert!(parsed.is_check_enabled("pytest"));
+        assert_eq!(
+            parsed.get_check("pytest").unwrap().globs,
+            vec!["src/**/*.py".to_string()]
+        );
+
+        set_check_enabled(&parsed, "pytest", false, &[])?;
+        let text = fs::read_to_string(root.join(PROJECT_CONFIG_FILE))?;
+        let parsed = parse_config("", &text, &project.config.cwd()?)?;
+        assert!(!parsed.is_check_enabled("pytest"));
+        assert!(parsed.is_check_enabled("cargo-clippy"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_values() -> error::Result<()> {
+        let project = testutils::test_project();
+        let root = project.config.project_root();
+        fs::write(
+            root.join(PROJECT_CONFIG_FILE),
+            r#"(models: (default: "haiku"))"#,
+        )?;
+        let load = || -> error::Result<Config> {
+            parse_config(
+                "",
+                &fs::read_to_string(root.join(PROJECT_CONFIG_FILE))?,
+                &project.config.cwd()?,
+            )
+        };
+        let config = load()?;
+        assert_eq!(get_config_value(&config, "models.default")?, "haiku");
+        assert!(get_config_value(&config, "nonexistent").is_err());
+
+        set_config_value(&config, "models.default", "claude-3-5", false)?;
+        set_config_value(&config, "step_limit", "3", false)?;
+        set_config_value(&config, "context.ruskel", "[\"serde\"]", false)?;
+        assert!(set_config_value(&config, "nonexistent", "1", false).is_err());
+        assert!(set_config_value(&config, "step_limit", "many", false).is_err());
+        assert!(set_config_value(&config, "modes", "{}", false).is_err());
+
+        let text = fs::read_to_string(root.join(PROJECT_CONFIG_FILE))?;
+        assert!(!text.contains("None"));
+        let config = load()?;
+        assert_eq!(config.models.default, "claude-3-5");
+        assert_eq!(config.step_limit, 3);
+        assert_eq!(config.context.ruskel, vec!["serde".to_string()]);
+        assert_eq!(get_config_value(&config, "step_limit")?, "3");
+
+        // Comments and formatting survive changes
+        let conf =
+            "// Project settings\n(\n    // Fast and cheap\n    models: (default: \"haiku\"),\n)\n";
+        fs::write(root.join(PROJECT_CONFIG_FILE), conf)?;
+        let config = load()?;
+        set_config_value(&config, "models.default", "sonnet", false)?;
+        set_config_value(&config, "step_limit", "4", false)?;
+        assert_eq!(
+            fs::read_to_string(root.join(PROJECT_CONFIG_FILE))?,
+            "// Project settings\n(\n    // Fast and cheap\n    models: (default: \"sonnet\"),\n    step_limit: 4,\n)\n"
+        );
+        let config = load()?;
+        assert_eq!(config.models.default, "sonnet");
+        assert_eq!(config.step_limit, 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_detect_languages() -> error::Result<()> {
+        let project = test_project();
+        let root = project.config.project_root();
+        let fresh = || {
+            let mut config = default_config(&root).with_dummy_executor(FakeExecutor::default());
+            config.project = project.config.project.clone();
+            config
+        };
+        let config = fresh();
+        assert!(detect_languages(&root).is_empty());
+        assert!(config.is_check_enabled("ruff-check"));
+
+        project.create_file_tree(&["scripts/gen.py"]);
+        assert_eq!(detect_languages(&root), vec!["python"]);
+        // Detection is cached for the life of the config
+        assert!(config.languages().is_empty());
+        let config = fresh();
+        assert_eq!(config.languages(), vec!["python"]);
+        assert!(!config.is_check_enabled("cargo-check"));
+
+        project.write("Cargo.toml", "");
+        assert_eq!(detect_languages(&root), vec!["rust"]);
+        let mut config = fresh();
+        assert!(config.is_check_enabled("cargo-check"));
+        assert!(!config.is_check_enabled("ruff-check"));
+        assert!(!config.is_check_enabled("cargo-clippy"));
+
+        // Configured languages win, and checks can still be enabled explicitly
+        config.project.languages = vec!["python".into()];
+        config.checks.enable.push("cargo-fmt".into());
+        assert!(config.is_check_enabled("ruff-check"));
+        assert!(!config.is_check_enabled("cargo-check"));
+        assert!(config.is_check_enabled("cargo-fmt"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_uninstalled_checks() -> error::Result<()> {
+        let project = testutils::test_project();
+        let mut config = parse_config(
+            "",
+            r#"(checks: (custom: [(name: "missing", command: "tenx-no-such-program .", globs: ["*.rs"])]))"#,
+            &project.config.cwd()?,
+        )?;
+        config.project.languages = vec!["python".into()];
+        for check in config.checks.builtin.iter_mut() {
+            if check.name == "ruff-check" {
+                check.command = "tenx-no-such-program check".into();
+            }
+        }
+
+        // Builtin language checks are skipped, and reported as such
+        assert!(!config.is_check_enabled("ruff-check"));
+        assert!(!config
+            .enabled_checks()
+            .iter()
+            .any(|c| c.name == "ruff-check"));
+        let skipped = config.skipped_checks();
+        let (_, reason) = skipped
+            .iter()
+            .find(|(c, _)| c.name == "ruff-check")
+            .unwrap();
+        assert!(reason.contains("tenx-no-such-program"));
+        assert!(!skipped.iter().any(|(c, _)| c.name == "missing"));
+
+        // Custom checks run anyway, so a missing program shows up as a failure
+        assert!(config.is_check_enabled("missing"));
+
+        // Explicitly enabled checks run anyway, so the user sees the failure
+        let mut enabled = config.clone();
+        enabled.checks.enable.push("ruff-check".into());
+        assert!(enabled.is_check_enabled("ruff-check"));
+        assert!(!enabled
+            .skipped_checks()
+            .iter()
+            .any(|(c, _)| c.name == "ruff-check"));
+
+        // Disabled checks aren't skipped, they're just off
+        let mut disabled = config.clone();
+        disabled.checks.disable.push("ruff-check".into());
+        assert!(!disabled
+            .skipped_checks()
+            .iter()
+            .any(|(c, _)| c.name == "ruff-check"));
+
+        let faked = config.with_dummy_executor(FakeExecutor::default());
+        assert!(faked.is_check_enabled("ruff-check"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_modes() -> error::Result<()> {
+        let project = testutils::test_project();
+        let config = parse_config(
+            "",
+            r#"(checks: (custom: [(name: "prettier", command: "prettier -w .", globs: ["*.ts"], mode: transform)]))"#,
+            &project.config.cwd()?,
+        )?;
+        assert_eq!(
+            config.get_check("prettier").unwrap().mode,
+            checks::CheckMode::Transform
+        );
+        assert_eq!(
+            config.get_check("cargo-check").unwrap().mode,
+            checks::CheckMode::Validate
+        );
+
+        // Transforms run ahead of validators
+        let names: Vec<_> = config
+            .enabled_checks()
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        let pos = |n: &str| names.iter().position(|c| c == n).unwrap();
+        assert!(pos("cargo-fmt") < pos("cargo-check"));
+        assert!(pos("prettier") < pos("cargo-test"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_model_aliases() -> error::Result<()> {
+        let project = testutils::test_project();
+        let mut config = parse_config(
+            "",
+            r#"(models: (default: "fast"), aliases: {"fast": "haiku", "quick": "haiku", "smart": "sonnet"})"#,
+            &project.config.cwd()?,
+        )?;
+        assert_eq!(config.model_name(), "haiku");
+        assert_eq!(config.model_alias(), Some("fast".to_string()));
+        assert_eq!(config.aliases_for("haiku"), vec!["fast", "quick"]);
+        assert!(config.aliases_for("o1").is_empty());
+
+        config.models.default = "sonnet".to_string();
+        assert_eq!(config.model_name(), "sonnet");
+        assert_eq!(config.model_alias(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_offline_model() -> error::Result<()> {
+        use crate::model::ModelProvider;
+        let config = default_config(".").with_offline();
+        let active = config.active_model()?;
+        assert!(matches!(&active, model::Model::Echo(_)));
+        assert_eq!(active.name(), config.model_name());
+        assert!(config.rate_limit().is_none());
+        // Models converted directly, as for pings and races, are offline too
+        for conf in config.model_confs() {
+            assert!(matches!(config.to_model(&conf)?, model::Model::Echo(_)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_model_key() -> error::Result<()> {
+        let claude = |key: &str| Model::Claude {
+            name: "sonnet".into(),
+            api_model: "claude-sonnet".into(),
+            key: key.into(),
+            key_env: String::new(),
+            backend: Backend::Direct,
+        };
+        let anthropic_key = |m: model::Model| match m {
+            model::Model::Claude(m) => m.anthropic_key,
+            m => panic!("unexpected model: {:?}", m),
+        };
+        let config = default_config(".");
+        let stored = default_config(".")
+            .with_dummy_keychain(FakeKeychain::default().with_key("claude", "from-keychain"));
+        assert_eq!(
+            anthropic_key(stored.to_model(&claude("from-config"))?),
+            "from-config"
+        );
+        // Without a key in the config, the stored key is used, or we say how to store one
+        assert_eq!(
+            anthropic_key(stored.to_model(&claude(""))?),
+            "from-keychain"
+        );
+        let config = config.with_dummy_keychain(FakeKeychain::default());
+        assert!(matches!(
+            config.to_model(&claude("")),
+            Err(TenxError::MissingKey { .. })
+        ));
+
+        // Requests to the active model use the stored key too
+        let mut stored = stored;
+        stored.models.custom = vec![claude("")];
+        stored.models.default = "sonnet".into();
+        assert_eq!(anthropic_key(stored.active_model()?), "from-keychain");
+        Ok(())
+    }
+
+    #[test]
+    fn test_scope() -> error::Result<()> {
+        let mut project = testutils::test_project();
+        project.create_file_tree(&["crates/a/src/lib.rs", "crates/b/src/lib.rs", "README.md"]);
+        project.config.scope = "crates/a".into();
+        assert_eq!(
+            project.config.project_files()?,
+            vec![PathBuf::from("crates/a/src/lib.rs")]
+        );
+        assert_eq!(
+            project.config.state()?.list()?,
+            vec![PathBuf::from("crates/a/src/lib.rs")]
+        );
+        assert_eq!(
+            project.config.scope_root(),
+            project.config.project_root().join("crates/a")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_context_groups() -> error::Result<()> {
+        let project = testutils::test_project();
+        let config = parse_config(
+            "",
+            r#"(context_groups: {"http": ["src/http/**", "ruskel:hyper"]})"#,
+            &project.config.cwd()?,
+        )?;
+        assert_eq!(
+            config.context_group("http")?,
+            &["src/http/**".to_string(), "ruskel:hyper".to_string()]
+        );
+        assert!(config.context_group("db").is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_parse_config_value() -> error::Result<()> {
         // Test loading a config with a custom step_limit
@@ -995,6 +2508,7 @@ mod tests {
                     "subdir/*.txt".to_string(),
                     "!**/ignore.rs".to_string(),
                 ],
+                ..Default::default()
             },
             ..Default::default()
         };
@@ -1021,6 +2535,7 @@ mod tests {
                     "!**/ignore.rs".to_string(),
                     "!subdir/*.txt".to_string(),
                 ],
+                ..Default::default()
             },
             ..Default::default()
         };
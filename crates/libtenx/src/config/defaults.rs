@@ -1,9 +1,17 @@
+use std::collections::HashMap;
 use std::env;
 use std::path::{Path, PathBuf};
 
 use super::config::*;
+use crate::checks::CheckMode;
 
 const DEFAULT_STEP_LIMIT: usize = 16;
+const DEFAULT_STUCK_LIMIT: usize = 2;
+const DEFAULT_MAX_CONTINUATIONS: usize = 3;
+const DEFAULT_TERM_WIDTH: usize = 100;
+const DEFAULT_CHECK_MAX_OUTPUT: usize = 32 * 1024;
+const DEFAULT_CHECK_TIMEOUT: u64 = 10 * 60;
+const DEFAULT_MAX_DELETED_LINES: usize = 200;
 
 const ANTHROPIC_API_KEY: &str = "ANTHROPIC_API_KEY";
 const ANTHROPIC_CLAUDE_SONNET: &str = "claude-3-7-sonnet-latest";
@@ -61,6 +69,85 @@ fn find_project_root(current_dir: &Path) -> PathBuf {
     current_dir.to_path_buf()
 }
 
+/// Builtin checks that only apply to projects in a given language.
+const LANGUAGE_CHECKS: &[(&str, &[&str])] = &[
+    (
+        "rust",
+        &["cargo-check", "cargo-test", "cargo-clippy", "cargo-fmt"],
+    ),
+    ("python", &["ruff-check", "ruff-format", "pytest", "mypy"]),
+];
+
+/// Manifest files that identify a project's languages.
+const LANGUAGE_MANIFESTS: &[(&str, &str)] = &[
+    ("Cargo.toml", "rust"),
+    ("pyproject.toml", "python"),
+    ("setup.py", "python"),
+    ("requirements.txt", "python"),
+    ("package.json", "javascript"),
+    ("go.mod", "go"),
+];
+
+/// File extensions that identify a project's languages when there are no manifests.
+const LANGUAGE_EXTENSIONS: &[(&str, &str)] = &[
+    ("rs", "rust"),
+    ("py", "python"),
+    ("js", "javascript"),
+    ("ts", "javascript"),
+    ("go", "go"),
+];
+
+/// Returns the language a builtin check applies to, if it's language-specific.
+pub fn check_language(name: &str) -> Option<&'static str> {
+    LANGUAGE_CHECKS
+        .iter()
+        .find(|(_, checks)| checks.contains(&name))
+        .map(|(language, _)| *language)
+}
+
+/// Detects the languages used in a project from the manifests in its root. If there are none,
+/// falls back to the extensions of files in the root and its immediate subdirectories. Returns
+/// a sorted list, which is empty if nothing was recognised.
+pub fn detect_languages(root: &Path) -> Vec<String> {
+    let mut languages: Vec<String> = LANGUAGE_MANIFESTS
+        .iter()
+        .filter(|(manifest, _)| root.join(manifest).is_file())
+        .map(|(_, language)| language.to_string())
+        .collect();
+    if languages.is_empty() {
+        let mut dirs = vec![root.to_path_buf()];
+        if let Ok(entries) = std::fs::read_dir(root) {
+            dirs.extend(
+                entries
+                    .flatten()
+                    .map(|e| e.path())
+                    .filter(|p| p.is_dir())
+                    .filter(|p| {
+                        !p.file_name()
+                            .is_some_and(|n| n.to_string_lossy().starts_with('.'))
+                    }),
+            );
+        }
+        for dir in dirs {
+            let Ok(entries) = std::fs::read_dir(dir) else {
+                continue;
+            };
+            for path in entries.flatten().map(|e| e.path()) {
+                let ext = path.extension().map(|e| e.to_string_lossy().to_string());
+                if let Some((_, language)) = LANGUAGE_EXTENSIONS
+                    .iter()
+                    .find(|(e, _)| Some(e.to_string()) == ext)
+                {
+                    languages.push(language.to_string());
+                }
+            }
+        }
+    }
+    languages.sort();
+    languages.dedup();
+    languages
+}
+
 /// Returns the default set of model configurations based on available API keys
 fn default_models() -> Vec<Model> {
     let mut models = Vec::new();
@@ -72,18 +159,21 @@ fn default_models() -> Vec<Model> {
                 api_model: ANTHROPIC_CLAUDE_SONNET.to_string(),
                 key: "".to_string(),
                 key_env: ANTHROPIC_API_KEY.to_string(),
+                backend: Backend::Direct,
             },
             Model::Claude {
                 name: "sonnet35".to_string(),
                 api_model: ANTHROPIC_CLAUDE_SONNET35.to_string(),
                 key: "".to_string(),
                 key_env: ANTHROPIC_API_KEY.to_string(),
+                backend: Backend::Direct,
             },
             Model::Claude {
                 name: "haiku".to_string(),
                 api_model: ANTHROPIC_CLAUDE_HAIKU.to_string(),
                 key: "".to_string(),
                 key_env: ANTHROPIC_API_KEY.to_string(),
+                backend: Backend::Direct,
             },
         ]);
     }
@@ -298,6 +388,7 @@ fn default_models() -> Vec<Model> {
                 key: "".to_string(),
                 key_env: GOOGLEAI_API_KEY.to_string(),
                 can_stream: false,
+                backend: Backend::Direct,
             },
             Model::Google {
                 name: "gemini-flash".to_string(),
@@ -305,6 +396,7 @@ fn default_models() -> Vec<Model> {
                 key: "".to_string(),
                 key_env: GOOGLEAI_API_KEY.to_string(),
                 can_stream: false,
+                backend: Backend::Direct,
             },
             Model::Google {
                 name: "gemini-flash-lite".to_string(),
@@ -312,6 +404,7 @@ fn default_models() -> Vec<Model> {
                 key: "".to_string(),
                 key_env: GOOGLEAI_API_KEY.to_string(),
                 can_stream: false,
+                backend: Backend::Direct,
             },
             Model::Google {
                 name: "gemini-flash-thinking-exp".to_string(),
@@ -319,6 +412,7 @@ fn default_models() -> Vec<Model> {
                 key: "".to_string(),
                 key_env: GOOGLEAI_API_KEY.to_string(),
                 can_stream: false,
+                backend: Backend::Direct,
             },
         ]);
     }
@@ -326,6 +420,57 @@ fn default_models() -> Vec<Model> {
     models
 }
 
+/// Returns published prices for the builtin models, in USD per million tokens. Prices change, so
+/// these are only a guide for budgeting, and can be overridden in config.
+fn default_pricing() -> HashMap<String, Pricing> {
+    [
+        (ANTHROPIC_CLAUDE_SONNET, 3.0, 15.0),
+        (ANTHROPIC_CLAUDE_SONNET35, 3.0, 15.0),
+        (ANTHROPIC_CLAUDE_HAIKU, 0.8, 4.0),
+        (OPENAI_GPT_O1, 15.0, 60.0),
+        (OPENAI_GPT_O1_MINI, 1.1, 4.4),
+        (OPENAI_GPT_O3_MINI, 1.1, 4.4),
+        (OPENAI_GPT4O, 2.5, 10.0),
+        (OPENAI_GPT4O_MINI, 0.15, 0.6),
+        ("deepseek-chat", 0.27, 1.1),
+        ("deepseek-reasoner", 0.55, 2.19),
+        (GROQ_LLAMA33_70B, 0.59, 0.79),
+        (GROQ_LLAMA31_8B_INSTANT, 0.05, 0.08),
+        (GROQ_DEEPSEEK_R1, 0.75, 0.99),
+        (XAI_DEFAULT_GROK, 5.0, 15.0),
+        (GOOGLEAI_GEMINI_FLASH, 0.1, 0.4),
+        (GOOGLEAI_GEMINI_FLASH_LITE, 0.075, 0.3),
+    ]
+    .into_iter()
+    .map(|(model, input, output)| (model.to_string(), Pricing { input, output }))
+    .collect()
+}
+
+/// Context window sizes in tokens for the models we know about.
+fn default_context_windows() -> HashMap<String, usize> {
+    [
+        (ANTHROPIC_CLAUDE_SONNET, 200_000),
+        (ANTHROPIC_CLAUDE_SONNET35, 200_000),
+        (ANTHROPIC_CLAUDE_HAIKU, 200_000),
+        (OPENAI_GPT_O1, 200_000),
+        (OPENAI_GPT_O1_MINI, 128_000),
+        (OPENAI_GPT_O3_MINI, 200_000),
+        (OPENAI_GPT4O, 128_000),
+        (OPENAI_GPT4O_MINI, 128_000),
+        ("deepseek-chat", 64_000),
+        ("deepseek-reasoner", 64_000),
+        (GROQ_LLAMA33_70B, 128_000),
+        (GROQ_LLAMA31_8B_INSTANT, 128_000),
+        (GROQ_DEEPSEEK_R1, 128_000),
+        (XAI_DEFAULT_GROK, 131_072),
+        (GOOGLEAI_GEMINI_FLASH, 1_048_576),
+        (GOOGLEAI_GEMINI_FLASH_LITE, 1_048_576),
+    ]
+    .into_iter()
+    .map(|(model, tokens)| (model.to_string(), tokens))
+    .collect()
+}
+
 /// Returns the default set of check configurations
 fn default_checks() -> Checks {
     Checks {
@@ -336,6 +481,12 @@ fn default_checks() -> Checks {
                 globs: vec!["*.rs".to_string()],
                 default_off: false,
                 fail_on_stderr: false,
+                mode: CheckMode::Validate,
+                focus: None,
+                cwd: None,
+                fix: Some(
+                    "cargo fix --allow-dirty --allow-staged --tests --examples -q".to_string(),
+                ),
             },
             CheckConfig {
                 name: "cargo-test".to_string(),
@@ -343,6 +494,13 @@ fn default_checks() -> Checks {
                 globs: vec!["*.rs".to_string()],
                 default_off: false,
                 fail_on_stderr: false,
+                mode: CheckMode::Validate,
+                focus: Some(TestFocus {
+                    pattern: r"^---- (\S+) stdout ----$".to_string(),
+                    command: "cargo test -q -- --exact {tests}".to_string(),
+                }),
+                cwd: None,
+                fix: None,
             },
             CheckConfig {
                 name: "cargo-clippy".to_string(),
@@ -350,6 +508,13 @@ fn default_checks() -> Checks {
                 globs: vec!["*.rs".to_string()],
                 default_off: true,
                 fail_on_stderr: true,
+                mode: CheckMode::Validate,
+                focus: None,
+                cwd: None,
+                fix: Some(
+                    "cargo clippy --fix --allow-dirty --allow-staged --no-deps --all --tests -q"
+                        .to_string(),
+                ),
             },
             CheckConfig {
                 name: "cargo-fmt".to_string(),
@@ -357,6 +522,10 @@ fn default_checks() -> Checks {
                 globs: vec!["*.rs".to_string()],
                 default_off: false,
                 fail_on_stderr: true,
+                mode: CheckMode::Transform,
+                focus: None,
+                cwd: None,
+                fix: None,
             },
             CheckConfig {
                 name: "ruff-check".to_string(),
@@ -364,6 +533,10 @@ fn default_checks() -> Checks {
                 globs: vec!["*.py".to_string()],
                 default_off: false,
                 fail_on_stderr: false,
+                mode: CheckMode::Validate,
+                focus: None,
+                cwd: None,
+                fix: Some("ruff check -q --fix".to_string()),
             },
             CheckConfig {
                 name: "ruff-format".to_string(),
@@ -371,8 +544,39 @@ fn default_checks() -> Checks {
                 globs: vec!["*.py".to_string()],
                 default_off: false,
                 fail_on_stderr: false,
+                mode: CheckMode::Transform,
+                focus: None,
+                cwd: None,
+                fix: None,
+            },
+            CheckConfig {
+                name: "pytest".to_string(),
+                command: "pytest -q".to_string(),
+                globs: vec!["*.py".to_string()],
+                default_off: true,
+                fail_on_stderr: false,
+                mode: CheckMode::Validate,
+                focus: Some(TestFocus {
+                    pattern: r"^FAILED (\S+)".to_string(),
+                    command: "pytest -q {tests}".to_string(),
+                }),
+                cwd: None,
+                fix: None,
+            },
+            CheckConfig {
+                name: "mypy".to_string(),
+                command: "mypy .".to_string(),
+                globs: vec!["*.py".to_string()],
+                default_off: true,
+                fail_on_stderr: false,
+                mode: CheckMode::Validate,
+                focus: None,
+                cwd: None,
+                fix: None,
             },
         ],
+        max_output: DEFAULT_CHECK_MAX_OUTPUT,
+        timeout: DEFAULT_CHECK_TIMEOUT,
         ..Default::default()
     }
 }
@@ -384,23 +588,43 @@ pub fn default_config<P: AsRef<Path>>(current_dir: P) -> Config {
         models: Models {
             default: "sonnet".to_string(),
             builtin: default_models(),
+            max_continuations: DEFAULT_MAX_CONTINUATIONS,
+            pricing: default_pricing(),
+            context_windows: default_context_windows(),
             ..Default::default()
         },
         context: Context {
             project_map: true,
+            project_facts: false,
+            knowledge: true,
+            refresh_stale: true,
+            ..Default::default()
+        },
+        dialect: Dialect {
+            edit: true,
             ..Default::default()
         },
-        dialect: Dialect { edit: true },
         project: {
             let root = find_project_root(current_dir.as_ref());
             Project {
                 include: vec![],
+                generated: vec![],
+                languages: vec![],
                 root,
+                checkpoint: true,
             }
         },
         session_store_dir: home_config_dir().join("state"),
+        cache_dir: home_config_dir().join("cache"),
         step_limit: DEFAULT_STEP_LIMIT,
+        stuck_limit: DEFAULT_STUCK_LIMIT,
+        term_width: DEFAULT_TERM_WIDTH,
         checks: default_checks(),
+        safety: Safety {
+            check: true,
+            max_deleted_lines: DEFAULT_MAX_DELETED_LINES,
+            ..Default::default()
+        },
         ..Default::default()
     }
 }
@@ -0,0 +1,440 @@
+//! In-place edits to RON config files. Commands that change a setting edit just the values that
+//! changed, so comments and formatting elsewhere in the file survive.
+use std::ops::Range;
+
+/// A value in a RON document, with its position in the source.
+struct Value {
+    span: Range<usize>,
+    /// For structs and maps, the entries in source order. Empty for other values.
+    entries: Vec<Entry>,
+    /// True for structs and maps, whose entries are addressed by key
+    keyed: bool,
+}
+
+/// A struct field or map entry.
+struct Entry {
+    /// The key with whitespace removed, so field names and map keys compare by their text
+    key: String,
+    /// Where the key starts
+    start: usize,
+    value: Value,
+    /// Where the entry ends, after its trailing comma if it has one
+    end: usize,
+}
+
+impl Value {
+    fn get(&self, key: &str) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.key == key)
+    }
+
+    /// Finds the value at a path of keys.
+    fn find(&self, path: &[String]) -> Option<&Value> {
+        path.iter()
+            .try_fold(self, |v, key| v.get(key).map(|e| &e.value))
+    }
+}
+
+/// A minimal RON parser that records where values are, rather than what they are.
+struct Parser<'a> {
+    src: &'a [u8],
+    pos: usize,
+    comments: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a str) -> Self {
+        Self {
+            src: src.as_bytes(),
+            pos: 0,
+            comments: false,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.src.get(self.pos).copied()
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        self.src[self.pos..].starts_with(s.as_bytes())
+    }
+
+    /// Skips whitespace and comments. Block comments nest in RON.
+    fn skip(&mut self) -> Option<()> {
+        loop {
+            while self.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+                self.pos += 1;
+            }
+            if self.starts_with("//") {
+                self.comments = true;
+                while self.peek().is_some_and(|c| c != b'\n') {
+                    self.pos += 1;
+                }
+            } else if self.starts_with("/*") {
+                self.comments = true;
+                let mut depth = 0;
+                loop {
+                    if self.starts_with("/*") {
+                        depth += 1;
+                        self.pos += 2;
+                    } else if self.starts_with("*/") {
+                        depth -= 1;
+                        self.pos += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    } else {
+                        self.peek()?;
+                        self.pos += 1;
+                    }
+                }
+            } else {
+                return Some(());
+            }
+        }
+    }
+
+    /// Parses a whole document, skipping any leading `#![enable(...)]` attributes.
+    fn document(&mut self) -> Option<Value> {
+        self.skip()?;
+        while self.starts_with("#!") {
+            while self.peek()? != b']' {
+                self.pos += 1;
+            }
+            self.pos += 1;
+            self.skip()?;
+        }
+        let value = self.value()?;
+        self.skip()?;
+        (self.pos == self.src.len()).then_some(value)
+    }
+
+    fn value(&mut self) -> Option<Value> {
+        self.skip()?;
+        let start = self.pos;
+        match self.peek()? {
+            b'(' | b'[' | b'{' => self.container(start),
+            b'"' => self.string(start),
+            b'r' if matches!(self.src.get(self.pos + 1), Some(b'"' | b'#')) => {
+                self.raw_string(start)
+            }
+            b'\'' => {
+                self.pos += 1;
+                while self.peek()? != b'\'' {
+                    self.pos += if self.peek()? == b'\\' { 2 } else { 1 };
+                }
+                self.pos += 1;
+                Some(self.atom(start))
+            }
+            c if c.is_ascii_alphabetic() || c == b'_' => {
+                while self
+                    .peek()
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || c == b'_')
+                {
+                    self.pos += 1;
+                }
+                let end = self.pos;
+                self.skip()?;
+                if self.peek() == Some(b'(') {
+                    // A named struct or an enum variant with data
+                    self.container(start)
+                } else {
+                    self.pos = end;
+                    Some(self.atom(start))
+                }
+            }
+            _ => {
+                while self
+                    .peek()
+                    .is_some_and(|c| !c.is_ascii_whitespace() && !b",:)]}/".contains(&c))
+                {
+                    self.pos += 1;
+                }
+                (self.pos > start).then(|| self.atom(start))
+            }
+        }
+    }
+
+    fn atom(&self, start: usize) -> Value {
+        Value {
+            span: start..self.pos,
+            entries: vec![],
+            keyed: false,
+        }
+    }
+
+    fn string(&mut self, start: usize) -> Option<Value> {
+        self.pos += 1;
+        while self.peek()? != b'"' {
+            self.pos += if self.peek()? == b'\\' { 2 } else { 1 };
+        }
+        self.pos += 1;
+        Some(self.atom(start))
+    }
+
+    fn raw_string(&mut self, start: usize) -> Option<Value> {
+        self.pos += 1;
+        let hashes = self.src[self.pos..]
+            .iter()
+            .take_while(|c| **c == b'#')
+            .count();
+        self.pos += hashes + 1;
+        let close = format!("\"{}", "#".repeat(hashes));
+        while !self.starts_with(&close) {
+            self.peek()?;
+            self.pos += 1;
+        }
+        self.pos += close.len();
+        Some(self.atom(start))
+    }
+
+    /// Parses a struct, tuple, list or map, starting at its opening bracket.
+    fn container(&mut self, start: usize) -> Option<Value> {
+        let open = self.peek()?;
+        let close = match open {
+            b'(' => b')',
+            b'[' => b']',
+            _ => b'}',
+        };
+        self.pos += 1;
+        let mut entries = vec![];
+        let mut positional = false;
+        loop {
+            self.skip()?;
+            if self.peek()? == close {
+                self.pos += 1;
+                break;
+            }
+            let item = self.value()?;
+            self.skip()?;
+            if self.peek()? == b':' {
+                self.pos += 1;
+                let key = String::from_utf8_lossy(&self.src[item.span.clone()])
+                    .split_whitespace()
+                    .collect();
+                let value = self.value()?;
+                entries.push(Entry {
+                    key,
+                    start: item.span.start,
+                    value,
+                    end: 0,
+                });
+            } else {
+                positional = true;
+            }
+            self.skip()?;
+            match self.peek()? {
+                b',' => self.pos += 1,
+                c if c == close => {}
+                _ => return None,
+            }
+            if let Some(entry) = entries.last_mut().filter(|e| e.end == 0) {
+                entry.end = self.pos;
+            }
+        }
+        let keyed = open != b'[' && !positional;
+        Some(Value {
+            span: start..self.pos,
+            entries: if keyed { entries } else { vec![] },
+            keyed,
+        })
+    }
+}
+
+fn parse(src: &str) -> Option<Value> {
+    Parser::new(src).document()
+}
+
+/// Returns true if a RON document has comments, or might have if it doesn't parse.
+pub(super) fn has_comments(src: &str) -> bool {
+    let mut parser = Parser::new(src);
+    match parser.document() {
+        Some(_) => parser.comments,
+        None => src.contains("//") || src.contains("/*"),
+    }
+}
+
+/// A change between two serializations of a document.
+enum Change {
+    /// The value at a path changed
+    Replace(Vec<String>),
+    /// A key was added at a path
+    Insert(Vec<String>),
+}
+
+/// Lists the changes from one document to another. Returns None if an entry was removed, which
+/// we don't edit in place.
+fn changes(
+    old_src: &str,
+    old: &Value,
+    new_src: &str,
+    new: &Value,
+    path: &mut Vec<String>,
+    out: &mut Vec<Change>,
+) -> Option<()> {
+    if old.keyed && new.keyed {
+        if old.entries.iter().any(|e| new.get(&e.key).is_none()) {
+            return None;
+        }
+        for entry in &new.entries {
+            path.push(entry.key.clone());
+            match old.get(&entry.key) {
+                Some(o) => changes(old_src, &o.value, new_src, &entry.value, path, out)?,
+                None => out.push(Change::Insert(path.clone())),
+            }
+            path.pop();
+        }
+    } else if old_src[old.span.clone()] != new_src[new.span.clone()] {
+        out.push(Change::Replace(path.clone()));
+    }
+    Some(())
+}
+
+/// Returns the indentation of the line containing `pos`.
+fn indent_at(src: &str, pos: usize) -> &str {
+    let line = &src[src[..pos].rfind('\n').map_or(0, |i| i + 1)..];
+    &line[..line.len() - line.trim_start_matches(' ').len()]
+}
+
+/// Returns the end of the line containing `pos`, if only whitespace and comments follow `pos` on
+/// it. Otherwise returns `pos`.
+fn line_end(src: &str, pos: usize) -> usize {
+    let end = src[pos..].find('\n').map_or(src.len(), |i| pos + i);
+    let rest = src[pos..end].trim();
+    let comment = rest.starts_with("//")
+        || (rest.starts_with("/*") && rest.ends_with("*/") && rest.matches("*/").count() == 1);
+    if rest.is_empty() || comment {
+        end
+    } else {
+        pos
+    }
+}
+
+/// Moves text taken from a line indented by `from` to a line indented by `to`, re-indenting
+/// its continuation lines to match.
+fn reindent(text: &str, from: &str, to: &str) -> String {
+    text.split('\n')
+        .enumerate()
+        .map(|(i, l)| match i {
+            0 => l.to_string(),
+            _ => format!("{}{}", to, l.strip_prefix(from).unwrap_or(l)),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Edits `src` so it holds the values of `new`, where `old` and `new` are serializations of the
+/// document before and after a change. Only the values that differ are touched. Returns None if
+/// the change can't be made in place.
+pub(super) fn edit(src: &str, old: &str, new: &str) -> Option<String> {
+    let tree = parse(src)?;
+    let new_tree = parse(new)?;
+    let mut out = vec![];
+    changes(old, &parse(old)?, new, &new_tree, &mut vec![], &mut out)?;
+
+    // Each edit replaces a range of the source, and they're applied from the end back
+    let mut edits: Vec<(Range<usize>, String)> = vec![];
+    let mut inserted: Vec<&[String]> = vec![];
+    for change in &out {
+        match change {
+            Change::Replace(path) => {
+                let target = tree.find(path)?;
+                let value = new_tree.find(path)?;
+                let text = reindent(
+                    &new[value.span.clone()],
+                    indent_at(new, value.span.start),
+                    indent_at(src, target.span.start),
+                );
+                edits.push((target.span.clone(), text));
+            }
+            Change::Insert(path) => {
+                // Insert under the deepest container in the source along the path
+                let depth = (0..path.len())
+                    .rev()
+                    .find(|i| tree.find(&path[..*i]).is_some())?;
+                let container = tree.find(&path[..depth])?;
+                if !container.keyed {
+                    return None;
+                }
+                if inserted.contains(&&path[..=depth]) {
+                    // Already inserted along with a sibling below the same parent
+                    continue;
+                }
+                inserted.push(&path[..=depth]);
+                let entry = new_tree.find(&path[..depth])?.get(&path[depth])?;
+                let last = container.entries.last();
+                if let Some(e) = last.filter(|e| !src[e.value.span.end..e.end].contains(',')) {
+                    let comma = (e.value.span.end..e.value.span.end, ",".to_string());
+                    if !edits.contains(&comma) {
+                        edits.push(comma);
+                    }
+                }
+                let single_line = !src[container.span.clone()].contains('\n');
+                let to = match last {
+                    Some(e) if !single_line => indent_at(src, e.start).to_string(),
+                    _ => format!("{}    ", indent_at(src, container.span.start)),
+                };
+                let text = reindent(
+                    &new[entry.start..entry.value.span.end],
+                    indent_at(new, entry.start),
+                    &to,
+                );
+                let (pos, text) = match last {
+                    Some(e) if single_line && !text.contains('\n') => (e.end, format!(" {}", text)),
+                    None if single_line && !text.contains('\n') => (container.span.start + 1, text),
+                    _ if single_line => (
+                        container.span.end - 1,
+                        format!(
+                            "\n{}{},\n{}",
+                            to,
+                            text,
+                            indent_at(src, container.span.start)
+                        ),
+                    ),
+                    Some(e) => (line_end(src, e.end), format!("\n{}{},", to, text)),
+                    None => (container.span.start + 1, format!("\n{}{},", to, text)),
+                };
+                edits.push((pos..pos, text));
+            }
+        }
+    }
+    // Insertions at the same position are applied last first, so they keep their order
+    let mut edits: Vec<_> = edits.into_iter().enumerate().collect();
+    edits.sort_by_key(|(i, (r, _))| std::cmp::Reverse((r.start, *i)));
+    let mut src = src.to_string();
+    for (_, (range, text)) in edits {
+        src.replace_range(range, &text);
+    }
+    Some(src)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit() {
+        let src = "// Project settings\n(\n    // The model we like\n    models: (default: \"haiku\"),\n    step_limit: 2, /* for now */\n)\n";
+        let old = "(\n    models: (\n        default: \"haiku\",\n    ),\n    step_limit: 2,\n)\n";
+        let new = "(\n    models: (\n        default: \"sonnet\",\n    ),\n    step_limit: 2,\n    checks: (\n        enable: [\n            \"a\",\n        ],\n    ),\n)\n";
+        assert!(has_comments(src));
+        assert!(!has_comments(old));
+        assert_eq!(
+            edit(src, old, new).unwrap(),
+            "// Project settings\n(\n    // The model we like\n    models: (default: \"sonnet\"),\n    step_limit: 2, /* for now */\n    checks: (\n        enable: [\n            \"a\",\n        ],\n    ),\n)\n"
+        );
+
+        // Entries are added inline to single-line structs
+        assert_eq!(
+            edit(
+                "(models: (default: \"haiku\")) // keep\n",
+                "(\n    models: (\n        default: \"haiku\",\n    ),\n)",
+                "(\n    models: (\n        default: \"haiku\",\n    ),\n    step_limit: 3,\n)",
+            )
+            .unwrap(),
+            "(models: (default: \"haiku\"), step_limit: 3) // keep\n"
+        );
+
+        // Removing an entry isn't done in place
+        assert!(edit("(a: 1, b: 2)", "(a: 1, b: 2)", "(a: 1)").is_none());
+    }
+}
@@ -3,6 +3,9 @@
 #[allow(clippy::module_inception)]
 mod config;
 mod defaults;
+mod edit;
+mod reload;
 
 pub use config::*;
 pub use defaults::*;
+pub use reload::*;
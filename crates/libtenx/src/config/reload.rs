@@ -0,0 +1,165 @@
+//! Live reloading of configuration for long-running commands.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use super::{home_config_dir, load_config, Config, HOME_CONFIG_FILE, PROJECT_CONFIG_FILE};
+use crate::{
+    error::Result,
+    events::{send_event, Event, EventSender},
+};
+
+/// Watches the home and project config files, reloading the configuration when either changes.
+/// Long-running commands call `poll` between operations, so changes to check toggles, the model
+/// and include rules take effect without a restart.
+///
+/// The reloaded configuration is exactly what the files specify, so callers that apply
+/// command-line overrides need to re-apply them to the result.
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+pub struct ConfigWatcher {
+    current_dir: PathBuf,
+    files: Vec<PathBuf>,
+    stamps: Vec<Option<SystemTime>>,
+    config: Config,
+}
+
+/// Returns the modification time of a file, or None if it doesn't exist.
+fn stamp(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+impl ConfigWatcher {
+    /// Creates a watcher for the config files that apply in `current_dir`, starting from an
+    /// already loaded configuration.
+    pub fn new(current_dir: &Path, config: Config) -> Self {
+        let files = vec![
+            home_config_dir().join(HOME_CONFIG_FILE),
+            config.project_root().join(PROJECT_CONFIG_FILE),
+        ];
+        Self::with_files(current_dir, config, files)
+    }
+
+    fn with_files(current_dir: &Path, config: Config, files: Vec<PathBuf>) -> Self {
+        let stamps = files.iter().map(|f| stamp(f)).collect();
+        Self {
+            current_dir: current_dir.to_path_buf(),
+            files,
+            stamps,
+            config,
+        }
+    }
+
+    /// The current configuration.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Reloads the configuration if a config file changed since the last poll. Returns the new
+    /// configuration if anything in it changed, and emits `Event::ConfigReloaded` describing the
+    /// changes. If the new config fails to load, the current configuration is kept and the error
+    /// is returned, so a half-written file doesn't bring down the command.
+    pub fn poll(&mut self, sender: &Option<EventSender>) -> Result<Option<&Config>> {
+        let stamps: Vec<_> = self.files.iter().map(|f| stamp(f)).collect();
+        if stamps == self.stamps {
+            return Ok(None);
+        }
+        self.stamps = stamps;
+        let config = load_config(&self.current_dir)?;
+        let changes = self.config.changes(&config);
+        if changes.is_empty() {
+            return Ok(None);
+        }
+        send_event(sender, Event::ConfigReloaded(changes))?;
+        self.config = config;
+        Ok(Some(&self.config))
+    }
+}
+
+impl Config {
+    /// Describes the differences from another configuration that matter to a running command:
+    /// enabled checks, the default model and the project's include rules. Returns an empty list if
+    /// there are none.
+    pub fn changes(&self, other: &Config) -> Vec<String> {
+        let mut changes = vec![];
+        if self.model_name() != other.model_name() {
+            changes.push(format!(
+                "model: {} -> {}",
+                self.model_name(),
+                other.model_name()
+            ));
+        }
+        let names = |c: &Config| {
+            c.enabled_checks()
+                .into_iter()
+                .map(|c| c.name)
+                .collect::<Vec<_>>()
+        };
+        let (before, after) = (names(self), names(other));
+        for name in after.iter().filter(|n| !before.contains(n)) {
+            changes.push(format!("check enabled: {}", name));
+        }
+        for name in before.iter().filter(|n| !after.contains(n)) {
+            changes.push(format!("check disabled: {}", name));
+        }
+        if self.project.include != other.project.include {
+            changes.push(format!("include: {}", other.project.include.join(", ")));
+        }
+        if changes.is_empty() && self != other {
+            changes.push("configuration updated".to_string());
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes() {
+        let config = Config::default();
+        assert!(config.changes(&config.clone()).is_empty());
+
+        let mut other = config.clone();
+        other.models.default = "opus".into();
+        other.project.include = vec!["*.rs".into()];
+        other.checks.max_output = 10;
+        assert_eq!(
+            config.changes(&other),
+            vec!["model:  -> opus".to_string(), "include: *.rs".to_string()]
+        );
+
+        let mut other = config.clone();
+        other.step_limit = 3;
+        assert_eq!(config.changes(&other), vec!["configuration updated"]);
+    }
+
+    #[test]
+    fn test_poll() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join(PROJECT_CONFIG_FILE);
+        let config = Config::default().with_root(dir.path());
+        let mut watcher = ConfigWatcher::with_files(dir.path(), config, vec![file.clone()]);
+        assert!(watcher.poll(&None)?.is_none());
+
+        fs::write(&file, "(models: (default: \"opus\"))")?;
+        let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+        let reloaded = watcher.poll(&Some(tx.clone()))?;
+        assert_eq!(
+            reloaded.map(|c| c.models.default.clone()),
+            Some("opus".into())
+        );
+        match rx.try_recv().unwrap() {
+            Event::ConfigReloaded(changes) => assert!(changes[0].ends_with("-> opus")),
+            e => panic!("unexpected event: {:?}", e),
+        }
+
+        // Nothing changed since the last poll
+        assert!(watcher.poll(&Some(tx))?.is_none());
+        assert!(rx.try_recv().is_err());
+        Ok(())
+    }
+}
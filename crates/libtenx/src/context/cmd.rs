@@ -1,14 +1,16 @@
 use super::ContextItem;
 use super::ContextProvider;
+use super::Revision;
 use crate::config::Config;
 use crate::error::Result;
 use crate::exec::exec;
 use crate::session::Session;
 use async_trait::async_trait;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 /// A context provider that captures command output
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
 pub struct Cmd {
     pub(crate) command: String,
     pub(crate) content: String,
@@ -63,6 +65,10 @@ impl ContextProvider for Cmd {
     async fn needs_refresh(&self, _config: &Config) -> bool {
         self.content.is_empty()
     }
+
+    fn revision(&self, config: &Config) -> Option<Revision> {
+        Revision::git(&config.project_root())
+    }
 }
 
 #[cfg(test)]
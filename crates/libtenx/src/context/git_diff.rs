@@ -0,0 +1,157 @@
+use std::{path::PathBuf, process::Command};
+
+use super::ContextItem;
+use super::ContextProvider;
+use super::Revision;
+use crate::config::Config;
+use crate::error::{Result, TenxError};
+use crate::session::Session;
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// A context provider for the project's uncommitted changes, as a git diff. This lets the model
+/// see work in progress, like changes a human started that the model is asked to continue or
+/// review. Untracked files aren't part of the diff, so new files only show up once they're added
+/// to the index.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
+pub struct GitDiff {
+    /// The ref to diff against. The working tree is diffed against HEAD if not set.
+    pub(crate) reference: Option<String>,
+    pub(crate) content: String,
+}
+
+impl GitDiff {
+    pub(crate) fn new(reference: Option<String>) -> Self {
+        Self {
+            reference,
+            content: String::new(),
+        }
+    }
+
+    fn reference(&self) -> &str {
+        self.reference.as_deref().unwrap_or("HEAD")
+    }
+
+    /// Returns the current diff against the reference.
+    fn diff(&self, config: &Config) -> Result<String> {
+        let cmd = format!("git diff --no-color {}", self.reference());
+        let output = Command::new("git")
+            .args(["diff", "--no-color", self.reference(), "--"])
+            .current_dir(config.project_root())
+            .output()
+            .map_err(|e| TenxError::Exec {
+                cmd: cmd.clone(),
+                error: e.to_string(),
+            })?;
+        if !output.status.success() {
+            return Err(TenxError::Resolve(format!(
+                "{} failed: {}",
+                cmd,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_string())
+    }
+}
+
+#[async_trait]
+impl ContextProvider for GitDiff {
+    fn context_items(&self, _config: &Config, _session: &Session) -> Result<Vec<ContextItem>> {
+        let body = if self.content.is_empty() {
+            format!("No changes against {}", self.reference())
+        } else {
+            self.content.clone()
+        };
+        Ok(vec![ContextItem {
+            ty: "git_diff".to_string(),
+            source: self.reference().to_string(),
+            body,
+        }])
+    }
+
+    fn human(&self) -> String {
+        format!("git diff: {}", self.reference())
+    }
+
+    fn id(&self) -> String {
+        format!("git-diff:{}", self.reference())
+    }
+
+    async fn refresh(&mut self, config: &Config) -> Result<()> {
+        self.content = self.diff(config)?;
+        Ok(())
+    }
+
+    /// The working tree changes as the session's patches are applied, and as the user edits, so
+    /// the diff is compared with the current one.
+    async fn needs_refresh(&self, config: &Config) -> bool {
+        !matches!(self.diff(config), Ok(diff) if diff == self.content)
+    }
+
+    /// The diff covers the whole working tree, so any change alters it.
+    fn overlaps(&self, files: &[PathBuf]) -> bool {
+        !files.is_empty()
+    }
+
+    fn revision(&self, config: &Config) -> Option<Revision> {
+        Revision::git(&config.project_root())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::{Context, ContextProvider},
+        testutils::test_project,
+    };
+
+    fn git(p: &crate::testutils::TestProject, args: &[&str]) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=tenx", "-c", "user.email=tenx@example.com"])
+            .args(args)
+            .current_dir(p.config.project_root())
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {:?}", args);
+    }
+
+    #[tokio::test]
+    async fn test_git_diff_context() -> Result<()> {
+        let p = test_project();
+        p.write("lib.rs", "old\n");
+        git(&p, &["init", "-q"]);
+        git(&p, &["add", "lib.rs"]);
+        git(&p, &["commit", "-q", "-m", "init"]);
+
+        let mut context = Context::new_git_diff(None);
+        context.refresh(&p.config).await?;
+        let items = context.context_items(&p.config, &p.session)?;
+        assert_eq!(items[0].source, "HEAD");
+        assert_eq!(items[0].body, "No changes against HEAD");
+
+        assert!(!context.needs_refresh(&p.config).await);
+
+        // The captured diff goes stale as the working tree changes
+        p.write("lib.rs", "new\n");
+        assert!(context.needs_refresh(&p.config).await);
+        context.refresh(&p.config).await?;
+        assert!(!context.needs_refresh(&p.config).await);
+        let items = context.context_items(&p.config, &p.session)?;
+        assert!(items[0].body.contains("-old\n+new"));
+        p.write("lib.rs", "newer\n");
+        assert!(context.needs_refresh(&p.config).await);
+        assert_eq!(context.human(), "git diff: HEAD");
+
+        let mut bad = Context::new_git_diff(Some("no-such-ref".into()));
+        assert!(matches!(
+            bad.refresh(&p.config).await,
+            Err(TenxError::Resolve(_))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+//! The project's knowledge base: markdown notes on architecture, conventions, terminology and the
+//! like, kept in `.tenx/knowledge/`. Notes are read from disk for every prompt, so edits take
+//! effect immediately. A note can start with a front matter block that controls when it's
+//! included:
+//!
+//! ```text
+//! ---
+//! paths: src/web/**, templates/**
+//! languages: python
+//! include: true
+//! ---
+//! ```
+//!
+//! With `paths`, the note is only included once the session touches a matching file. With
+//! `languages`, it's only included if the project uses one of the languages. `include: false`
+//! turns the note off. Other keys are ignored.
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use fs_err as fs;
+use globset::Glob;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::ContextItem;
+use super::ContextProvider;
+use crate::config::Config;
+use crate::error::Result;
+use crate::session::Session;
+
+/// The directory knowledge notes are read from, relative to the project root.
+pub const KNOWLEDGE_DIR: &str = ".tenx/knowledge";
+
+/// The inclusion rules from a note's front matter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rules {
+    include: bool,
+    /// Globs, one of which a file touched by the session must match
+    paths: Vec<String>,
+    /// Languages, one of which the project must use
+    languages: Vec<String>,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            include: true,
+            paths: vec![],
+            languages: vec![],
+        }
+    }
+}
+
+impl Rules {
+    /// Should a note with these rules be included, given the files the session has touched?
+    fn applies(&self, config: &Config, touched: &[PathBuf]) -> bool {
+        if !self.include {
+            return false;
+        }
+        if !self.languages.is_empty() {
+            let languages = config.languages();
+            if !self.languages.iter().any(|l| languages.contains(l)) {
+                return false;
+            }
+        }
+        self.paths.is_empty()
+            || touched.iter().any(|path| {
+                self.paths.iter().any(|g| {
+                    Glob::new(g)
+                        .map(|g| g.compile_matcher().is_match(path))
+                        .unwrap_or(false)
+                })
+            })
+    }
+}
+
+/// Parses a comma-separated front matter list, which may also be written like `[a, "b"]`.
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .trim()
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .split(',')
+        .map(|v| v.trim().trim_matches(['"', '\'']).to_string())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+/// Splits a note into the rules from its front matter, and its body. A note without front matter
+/// is always included.
+fn parse_note(text: &str) -> (Rules, &str) {
+    let mut rules = Rules::default();
+    let Some(rest) = text
+        .strip_prefix("---\n")
+        .or_else(|| text.strip_prefix("---\r\n"))
+    else {
+        return (rules, text);
+    };
+    let Some(end) = rest.find("\n---") else {
+        return (rules, text);
+    };
+    let body = rest[end + 4..].split_once('\n').map_or("", |(_, b)| b);
+    for line in rest[..end].lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        match key.trim() {
+            "include" => rules.include = value.trim() != "false",
+            "paths" => rules.paths = parse_list(value),
+            "languages" => rules.languages = parse_list(value),
+            _ => {}
+        }
+    }
+    (rules, body.trim_start_matches(['\r', '\n']))
+}
+
+/// The files touched by any action in the session, relative to the project root.
+fn touched_files(session: &Session) -> Result<Vec<PathBuf>> {
+    let mut touched = vec![];
+    for action in &session.actions {
+        touched.extend(action.state.changed()?);
+    }
+    Ok(touched)
+}
+
+/// Lists the markdown notes in a knowledge directory, sorted by name.
+fn notes(dir: &Path) -> Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut notes: Vec<PathBuf> = fs::read_dir(dir)?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && p.extension().is_some_and(|e| e == "md"))
+        .collect();
+    notes.sort();
+    Ok(notes)
+}
+
+/// A context provider for the notes in the project's knowledge base that apply to the session.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
+pub struct Knowledge;
+
+impl Knowledge {
+    pub(crate) fn new() -> Self {
+        Self
+    }
+}
+
+#[async_trait]
+impl ContextProvider for Knowledge {
+    fn context_items(&self, config: &Config, session: &Session) -> Result<Vec<ContextItem>> {
+        let touched = touched_files(session)?;
+        let mut items = vec![];
+        for path in notes(&config.project_root().join(KNOWLEDGE_DIR))? {
+            let text = fs::read_to_string(&path)?;
+            let (rules, body) = parse_note(&text);
+            if !rules.applies(config, &touched) || body.trim().is_empty() {
+                continue;
+            }
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            items.push(ContextItem {
+                ty: "knowledge".to_string(),
+                source: format!("{}/{}", KNOWLEDGE_DIR, name),
+                body: body.trim_end().to_string(),
+            });
+        }
+        Ok(items)
+    }
+
+    fn human(&self) -> String {
+        "knowledge".to_string()
+    }
+
+    fn id(&self) -> String {
+        "knowledge".to_string()
+    }
+
+    async fn refresh(&mut self, _config: &Config) -> Result<()> {
+        Ok(())
+    }
+
+    fn captures(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{session::Action, strategy, testutils::test_project};
+
+    #[test]
+    fn test_knowledge() -> Result<()> {
+        let mut p = test_project();
+        p.config.project.languages = vec!["rust".into()];
+        p.create_file_tree(&[".tenx/knowledge/arch.md", "web/index.html"]);
+        p.write(".tenx/knowledge/arch.md", "# Architecture\n\nLayers.\n");
+        p.write(
+            ".tenx/knowledge/web.md",
+            "---\npaths: [\"web/**\"]\n---\n\nTemplates live in web/.\n",
+        );
+        p.write(
+            ".tenx/knowledge/py.md",
+            "---\nlanguages: python\n---\nUse ruff.\n",
+        );
+        p.write(".tenx/knowledge/off.md", "---\ninclude: false\n---\nOld.\n");
+        p.write(".tenx/knowledge/notes.txt", "Not markdown.\n");
+
+        let sources = |p: &crate::testutils::TestProject| -> Result<Vec<String>> {
+            Ok(Knowledge::new()
+                .context_items(&p.config, &p.session)?
+                .into_iter()
+                .map(|i| i.source)
+                .collect())
+        };
+        assert_eq!(sources(&p)?, vec![".tenx/knowledge/arch.md"]);
+        assert_eq!(
+            Knowledge::new().context_items(&p.config, &p.session)?[0].body,
+            "# Architecture\n\nLayers."
+        );
+
+        // Path rules apply once the session touches a matching file
+        p.write("web/index.html", "<html></html>\n");
+        p.session.add_action(Action::new(
+            &p.config,
+            strategy::Strategy::Code(strategy::Code::new()),
+        )?)?;
+        p.session
+            .last_action_mut()?
+            .state
+            .touch(p.config.project_root(), vec!["web/index.html".into()])?;
+        assert_eq!(
+            sources(&p)?,
+            vec![".tenx/knowledge/arch.md", ".tenx/knowledge/web.md"]
+        );
+        Ok(())
+    }
+}
@@ -1,34 +1,133 @@
+use indexmap::IndexMap;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 use std::iter::IntoIterator;
 
-use crate::error::Result;
+use crate::{config::Config, error::Result, usage};
 use unirend::{Detail, Render};
 
-use super::{Context, ContextProvider};
+use super::{Context, ContextProvider, Provenance};
+
+/// How important a context is when the prompt has to be cut to fit the model's context window.
+/// Lower priorities are trimmed first, and pinned contexts are always sent in full.
+#[derive(
+    Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+    Pinned,
+}
 
 /// A manager for a collection of context items.
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
 pub struct ContextManager {
-    /// A map of context items with their IDs as keys.
-    contexts: HashMap<String, Context>,
+    /// A map of context items with their IDs as keys, in the order they were added.
+    contexts: IndexMap<String, Context>,
+    /// Priorities for contexts that aren't at normal priority, keyed by context ID.
+    #[serde(default)]
+    priorities: IndexMap<String, Priority>,
+    /// Where and when each context's content was captured, keyed by context ID.
+    #[serde(default)]
+    provenance: IndexMap<String, Provenance>,
 }
 
 impl ContextManager {
     /// Creates a new empty ContextManager.
     pub fn new() -> Self {
         Self {
-            contexts: HashMap::new(),
+            contexts: IndexMap::new(),
+            priorities: IndexMap::new(),
+            provenance: IndexMap::new(),
         }
     }
 
     /// Adds a context item to the manager.
-    /// If a duplicate context already exists, it will be replaced.
+    /// If a duplicate context already exists, it will be replaced in place.
     pub fn add(&mut self, context: Context) {
         let id = context.id();
+        self.provenance.shift_remove(&id);
         self.contexts.insert(id, context);
     }
 
+    /// Adds a context item to the manager with the given priority, replacing any duplicate and
+    /// its priority.
+    pub fn add_with_priority(&mut self, context: Context, priority: Priority) {
+        self.set_priority(&context.id(), priority);
+        self.add(context);
+    }
+
+    /// Sets the priority of the context with the given ID.
+    pub fn set_priority(&mut self, id: &str, priority: Priority) {
+        if priority == Priority::Normal {
+            self.priorities.shift_remove(id);
+        } else {
+            self.priorities.insert(id.to_string(), priority);
+        }
+    }
+
+    /// Returns the priority of a context.
+    pub fn priority(&self, context: &Context) -> Priority {
+        self.priorities
+            .get(&context.id())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Returns the provenance of a context, if its content has been captured.
+    pub fn provenance(&self, context: &Context) -> Option<&Provenance> {
+        self.provenance.get(&context.id())
+    }
+
+    /// Returns the context with the given ID.
+    pub fn get(&self, id: &str) -> Option<&Context> {
+        self.contexts.get(id)
+    }
+
+    /// Returns the IDs of all contexts, in order.
+    pub fn ids(&self) -> Vec<String> {
+        self.contexts.keys().cloned().collect()
+    }
+
+    /// Refreshes the context with the given ID, recording where and when its content was
+    /// captured.
+    pub async fn refresh(&mut self, id: &str, config: &Config) -> Result<()> {
+        let Some(context) = self.contexts.get_mut(id) else {
+            return Ok(());
+        };
+        context.refresh(config).await?;
+        if context.captures() {
+            let provenance = Provenance {
+                captured: usage::now(),
+                revision: context.revision(config),
+            };
+            self.provenance.insert(id.to_string(), provenance);
+        }
+        Ok(())
+    }
+
+    /// Returns the contexts whose captured content is stale, keyed by context ID, with the
+    /// reason. Content is stale if its source has moved on since it was captured, or if it's
+    /// older than the configured expiry.
+    pub fn stale(&self, config: &Config) -> IndexMap<String, String> {
+        let now = usage::now();
+        let max_age = config.context.expire_hours * 60 * 60;
+        self.contexts
+            .iter()
+            .filter_map(|(id, context)| {
+                let reason = self.provenance.get(id)?.staleness(
+                    context.revision(config).as_ref(),
+                    max_age,
+                    now,
+                )?;
+                Some((id.clone(), reason))
+            })
+            .collect()
+    }
+
     /// Returns a list of all contexts.
     pub fn list(&self) -> Vec<&Context> {
         self.contexts.values().collect()
@@ -37,6 +136,8 @@ impl ContextManager {
     /// Clears all contexts.
     pub fn clear(&mut self) {
         self.contexts.clear();
+        self.priorities.clear();
+        self.provenance.clear();
     }
 
     /// Returns the number of contexts in the manager.
@@ -55,9 +156,19 @@ impl ContextManager {
     }
 
     pub fn render<R: Render>(&self, renderer: &mut R, _detail: Detail) -> Result<()> {
+        let now = usage::now();
         let mut bullets = vec![];
         for context in self.list() {
-            bullets.push(context.human());
+            let mut bullet = match self.priority(context) {
+                Priority::Normal => context.human(),
+                Priority::Low => format!("{} [low]", context.human()),
+                Priority::High => format!("{} [high]", context.human()),
+                Priority::Pinned => format!("{} [pinned]", context.human()),
+            };
+            if let Some(provenance) = self.provenance(context) {
+                bullet.push_str(&format!(" ({})", provenance.describe(now)));
+            }
+            bullets.push(bullet);
         }
         renderer.bullets(bullets);
         Ok(())
@@ -66,7 +177,7 @@ impl ContextManager {
 
 impl<'a> IntoIterator for &'a ContextManager {
     type Item = &'a Context;
-    type IntoIter = std::collections::hash_map::Values<'a, String, Context>;
+    type IntoIter = indexmap::map::Values<'a, String, Context>;
 
     fn into_iter(self) -> Self::IntoIter {
         self.contexts.values()
@@ -76,7 +187,10 @@ impl<'a> IntoIterator for &'a ContextManager {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::context::{Context, ContextProvider};
+    use crate::{
+        context::{Context, ContextProvider},
+        testutils::test_project,
+    };
 
     #[test]
     fn test_context_manager() {
@@ -107,8 +221,44 @@ mod tests {
         // Count contexts
         assert_eq!(manager.list().len(), 2);
 
+        // Priorities survive replacement, and normal priority isn't stored
+        manager.add_with_priority(Context::new_text("test2", "content2"), Priority::Pinned);
+        manager.add(Context::new_text("test2", "updated"));
+        let id = manager.list()[1].id();
+        assert_eq!(manager.priority(manager.list()[1]), Priority::Pinned);
+        manager.set_priority(&id, Priority::Normal);
+        assert!(manager.priorities.is_empty());
+
         // Clear all contexts
         manager.clear();
         assert!(manager.is_empty());
     }
+
+    #[tokio::test]
+    async fn test_provenance() -> Result<()> {
+        let mut p = test_project();
+        let mut manager = ContextManager::new();
+        manager.add(Context::new_cmd("echo hi"));
+        manager.add(Context::new_text("notes", "content"));
+        for id in manager.ids() {
+            manager.refresh(&id, &p.config).await?;
+        }
+
+        // Only captured content has provenance
+        let cmd = manager.list()[0].clone();
+        assert!(manager.provenance(&cmd).is_some());
+        assert!(manager.provenance(manager.list()[1]).is_none());
+        assert!(manager.stale(&p.config).is_empty());
+
+        // Old content expires if an expiry is configured
+        manager.provenance.get_mut(&cmd.id()).unwrap().captured = 0;
+        assert!(manager.stale(&p.config).is_empty());
+        p.config.context.expire_hours = 1;
+        assert!(manager.stale(&p.config).contains_key(&cmd.id()));
+
+        // Replacing a context discards its provenance
+        manager.add(Context::new_cmd("echo hi"));
+        assert!(manager.provenance(&cmd).is_none());
+        Ok(())
+    }
 }
@@ -7,28 +7,43 @@ which are included in prompts.
 use enum_dispatch::enum_dispatch;
 
 mod cmd;
+mod git_diff;
+mod knowledge;
 mod manager;
 mod path;
+mod project_facts;
 mod project_map;
+mod provenance;
 mod ruskel;
+mod search;
+mod snippet;
+mod symbol;
 mod text;
 mod url;
 
 pub use cmd::*;
+pub use git_diff::*;
+pub use knowledge::*;
 pub use manager::*;
 pub use path::*;
+pub use project_facts::*;
 pub use project_map::*;
+pub use provenance::*;
 pub use ruskel::*;
+pub use search::*;
+pub use snippet::*;
+pub use symbol::*;
 pub use text::*;
 pub use url::*;
 
 use async_trait::async_trait;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::{config::Config, error::Result, session::Session};
 
 /// An individual context item.
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 /// Represents a single piece of context information to include in a prompt. Each ContextProvider
 /// can provide multiple ContextItems.
 pub struct ContextItem {
@@ -74,11 +89,30 @@ pub trait ContextProvider {
     async fn needs_refresh(&self, _config: &Config) -> bool {
         false
     }
+
+    /// Returns true if refreshing captures content that is stored in the session, and so can go
+    /// stale. Contexts rendered afresh for every prompt, and fixed text, return false.
+    fn captures(&self) -> bool {
+        true
+    }
+
+    /// Returns the current revision of the source this context's content is captured from, like
+    /// the project's git HEAD. Contexts that are rendered afresh for every prompt don't have one.
+    fn revision(&self, _config: &Config) -> Option<Revision> {
+        None
+    }
+
+    /// Returns true if this context's content is derived from any of `files`, given relative to
+    /// the project root. Contexts that overlap the files a patch writes are refreshed as soon as
+    /// it's applied, so later steps don't see the old content.
+    fn overlaps(&self, _files: &[std::path::PathBuf]) -> bool {
+        false
+    }
 }
 
 /// A context provider that produces reference material for model interactions.
 #[enum_dispatch]
-#[derive(Debug, Serialize, Deserialize, Clone, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, JsonSchema)]
 pub enum Context {
     /// API documentation generated using Ruskel
     Ruskel(Ruskel),
@@ -86,12 +120,24 @@ pub enum Context {
     Path(Path),
     /// A list of all files in the project
     ProjectMap(ProjectMap),
+    /// A summary of the project's manifests and dependency versions
+    ProjectFacts(ProjectFacts),
     /// Content fetched from a remote URL
     Url(Url),
     /// Raw text content provided directly
     Text(Text),
     /// Output from executing a command
     Cmd(Cmd),
+    /// Matches from a project search
+    Search(Search),
+    /// Named items extracted from the project's Rust files
+    Symbol(Symbol),
+    /// A range of lines from a project file
+    Snippet(Snippet),
+    /// Uncommitted changes in the project's git repository
+    GitDiff(GitDiff),
+    /// Notes from the project's knowledge base in `.tenx/knowledge/`
+    Knowledge(Knowledge),
 }
 
 impl Context {
@@ -115,6 +161,16 @@ impl Context {
         Context::ProjectMap(ProjectMap::new())
     }
 
+    /// Creates a new Context for the project facts.
+    pub fn new_project_facts() -> Self {
+        Context::ProjectFacts(ProjectFacts::new())
+    }
+
+    /// Creates a new Context for the project's knowledge base.
+    pub fn new_knowledge() -> Self {
+        Context::Knowledge(Knowledge::new())
+    }
+
     /// Creates a new Context for a URL.
     pub fn new_url(url: &str) -> Self {
         Context::Url(Url::new(url.to_string()))
@@ -124,4 +180,88 @@ impl Context {
     pub fn new_cmd(command: &str) -> Self {
         Context::Cmd(Cmd::new(command.to_string()))
     }
+
+    /// Creates a new Context for a project search, with the given number of surrounding context
+    /// lines for each match.
+    pub fn new_search(pattern: &str, context_lines: usize) -> Self {
+        Context::Search(Search::new(pattern.to_string(), context_lines))
+    }
+
+    /// Creates a new Context for a symbol, like `Session::apply_patch`.
+    pub fn new_symbol(symbol: &str) -> Self {
+        Context::Symbol(Symbol::new(symbol.to_string()))
+    }
+
+    /// Creates a new Context for a line range, like `src/session.rs:100-180`.
+    pub fn new_lines(config: &Config, spec: &str) -> Result<Self> {
+        Ok(Context::Snippet(Snippet::new(config, spec)?))
+    }
+
+    /// Creates a new Context for the git diff of the working tree against a ref, or against HEAD
+    /// if no ref is given.
+    pub fn new_git_diff(reference: Option<String>) -> Self {
+        Context::GitDiff(GitDiff::new(reference))
+    }
+
+    /// Creates a new Context from a specification string, as used in context groups. The
+    /// specification is a path or glob pattern, a URL, or one of "ruskel:", "url:", "cmd:",
+    /// "symbol:", "lines:", "git-diff:" or "path:" followed by the argument for that context type.
+    /// An empty "git-diff:" argument diffs against HEAD.
+    pub fn from_spec(config: &Config, spec: &str) -> Result<Self> {
+        if spec.starts_with("http://") || spec.starts_with("https://") {
+            return Ok(Context::new_url(spec));
+        }
+        match spec.split_once(':') {
+            Some(("ruskel", v)) => Ok(Context::new_ruskel(v)),
+            Some(("url", v)) => Ok(Context::new_url(v)),
+            Some(("cmd", v)) => Ok(Context::new_cmd(v)),
+            Some(("path", v)) => Context::new_path(config, v),
+            Some(("symbol", v)) => Ok(Context::new_symbol(v)),
+            Some(("lines", v)) => Context::new_lines(config, v),
+            Some(("git-diff", v)) => Ok(Context::new_git_diff(
+                Some(v.to_string()).filter(|v| !v.is_empty()),
+            )),
+            _ => Context::new_path(config, spec),
+        }
+    }
+
+    /// Creates the contexts for a named context group in the config.
+    pub fn from_group(config: &Config, name: &str) -> Result<Vec<Self>> {
+        config
+            .context_group(name)?
+            .iter()
+            .map(|spec| Context::from_spec(config, spec))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutils::test_project;
+
+    #[test]
+    fn test_from_spec() -> Result<()> {
+        let mut p = test_project();
+        p.config.context_groups.insert(
+            "http".into(),
+            vec![
+                "src/http/**".into(),
+                "ruskel:hyper".into(),
+                "cmd:cargo tree".into(),
+                "https://example.com".into(),
+                "git-diff:".into(),
+                "git-diff:main".into(),
+            ],
+        );
+        let contexts = Context::from_group(&p.config, "http")?;
+        assert!(matches!(&contexts[0], Context::Path(_)));
+        assert_eq!(contexts[1], Context::new_ruskel("hyper"));
+        assert_eq!(contexts[2], Context::new_cmd("cargo tree"));
+        assert_eq!(contexts[3], Context::new_url("https://example.com"));
+        assert_eq!(contexts[4], Context::new_git_diff(None));
+        assert_eq!(contexts[5], Context::new_git_diff(Some("main".into())));
+        assert!(Context::from_group(&p.config, "missing").is_err());
+        Ok(())
+    }
 }
@@ -5,16 +5,18 @@ use crate::error::Result;
 use crate::session::Session;
 use async_trait::async_trait;
 use fs_err as fs;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use state::files::slash_path;
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
 pub enum PathType {
     SinglePath(String),
     Pattern(String),
 }
 
 /// A context provider that handles file paths, either single files or glob patterns.
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
 pub struct Path {
     pub(crate) path_type: PathType,
 }
@@ -36,7 +38,11 @@ impl ContextProvider for Path {
     fn context_items(&self, config: &Config, _session: &Session) -> Result<Vec<ContextItem>> {
         let matched_files = match &self.path_type {
             PathType::SinglePath(path) => vec![std::path::PathBuf::from(path)],
-            PathType::Pattern(pattern) => config.match_files_with_glob(pattern)?,
+            PathType::Pattern(pattern) => config
+                .match_files_with_glob(pattern)?
+                .into_iter()
+                .filter(|f| !config.is_generated(f))
+                .collect(),
         };
         let mut contexts = Vec::new();
         for file in matched_files {
@@ -44,7 +50,7 @@ impl ContextProvider for Path {
             let body = fs::read_to_string(&abs_path)?;
             contexts.push(ContextItem {
                 ty: "file".to_string(),
-                source: file.to_string_lossy().into_owned(),
+                source: slash_path(&file),
                 body,
             });
         }
@@ -68,6 +74,10 @@ impl ContextProvider for Path {
     async fn refresh(&mut self, _config: &Config) -> Result<()> {
         Ok(())
     }
+
+    fn captures(&self) -> bool {
+        false
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,426 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use async_trait::async_trait;
+use fs_err as fs;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
+
+use super::ContextItem;
+use super::ContextProvider;
+use crate::config::Config;
+use crate::error::{Result, TenxError};
+use crate::session::Session;
+
+/// A context provider that summarises the project's manifests: language versions, dependencies
+/// with their versions, and the workspace layout. This is regenerated whenever the manifests
+/// change, so the model works against the dependency versions the project actually uses.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default, JsonSchema)]
+pub struct ProjectFacts {
+    pub(crate) facts: String,
+}
+
+impl ProjectFacts {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Reads and parses a TOML file relative to the project root, returning None if it doesn't
+/// exist.
+fn read_toml(root: &Path, path: &str) -> Result<Option<Table>> {
+    let path = root.join(path);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let table = fs::read_to_string(&path)?
+        .parse::<Table>()
+        .map_err(|e| TenxError::Resolve(format!("Failed to parse {}: {}", path.display(), e)))?;
+    Ok(Some(table))
+}
+
+/// Returns a string field from a nested table, like `["package", "edition"]`.
+fn get_str<'a>(table: &'a Table, keys: &[&str]) -> Option<&'a str> {
+    let (last, parents) = keys.split_last()?;
+    let mut t = table;
+    for k in parents {
+        t = t.get(*k)?.as_table()?;
+    }
+    t.get(*last)?.as_str()
+}
+
+/// Returns a package field, falling back to the workspace value if the field is inherited.
+fn package_field(manifest: &Table, workspace: Option<&Table>, key: &str) -> Option<String> {
+    match manifest.get("package")?.as_table()?.get(key)? {
+        Value::String(s) => Some(s.clone()),
+        Value::Table(_) => {
+            workspace.and_then(|w| get_str(w, &["workspace", "package", key]).map(String::from))
+        }
+        _ => None,
+    }
+}
+
+/// Describes a Cargo dependency specification, resolving workspace dependencies and adding the
+/// locked version if we have one.
+fn cargo_dep(
+    name: &str,
+    spec: &Value,
+    workspace: Option<&Table>,
+    locked: &BTreeMap<String, BTreeSet<String>>,
+) -> String {
+    let (package, version) = match spec {
+        Value::String(v) => (name, v.clone()),
+        Value::Table(t) => {
+            if t.get("workspace").and_then(Value::as_bool) == Some(true) {
+                if let Some(spec) =
+                    workspace.and_then(|w| w.get("workspace")?.get("dependencies")?.get(name))
+                {
+                    return cargo_dep(name, spec, None, locked);
+                }
+            }
+            let package = t.get("package").and_then(Value::as_str).unwrap_or(name);
+            let version = if let Some(v) = t.get("version").and_then(Value::as_str) {
+                v.to_string()
+            } else if let Some(p) = t.get("path").and_then(Value::as_str) {
+                format!("path {}", p)
+            } else if let Some(g) = t.get("git").and_then(Value::as_str) {
+                format!("git {}", g)
+            } else {
+                "*".to_string()
+            };
+            (package, version)
+        }
+        _ => (name, "*".to_string()),
+    };
+    let mut ret = if package == name {
+        format!("{} {}", name, version)
+    } else {
+        format!("{} ({}) {}", name, package, version)
+    };
+    if let Some(versions) = locked.get(package) {
+        let versions: Vec<_> = versions.iter().map(|v| v.as_str()).collect();
+        ret.push_str(&format!(" (locked {})", versions.join(", ")));
+    }
+    ret
+}
+
+/// Appends a section listing the dependencies in a manifest table, if there are any.
+fn cargo_deps(
+    out: &mut Vec<String>,
+    manifest: &Table,
+    key: &str,
+    workspace: Option<&Table>,
+    locked: &BTreeMap<String, BTreeSet<String>>,
+) {
+    let Some(deps) = manifest.get(key).and_then(Value::as_table) else {
+        return;
+    };
+    if deps.is_empty() {
+        return;
+    }
+    out.push(format!("{}:", key));
+    for (name, spec) in deps {
+        out.push(format!("    {}", cargo_dep(name, spec, workspace, locked)));
+    }
+}
+
+/// Describes a single Cargo package at `dir`, relative to the project root.
+fn cargo_package(
+    out: &mut Vec<String>,
+    dir: &str,
+    manifest: &Table,
+    workspace: Option<&Table>,
+    locked: &BTreeMap<String, BTreeSet<String>>,
+) {
+    let Some(name) = get_str(manifest, &["package", "name"]) else {
+        return;
+    };
+    out.push(String::new());
+    if dir.is_empty() {
+        out.push(format!("## crate {}", name));
+    } else {
+        out.push(format!("## crate {} ({})", name, dir));
+    }
+    for key in ["version", "edition", "rust-version"] {
+        if let Some(v) = package_field(manifest, workspace, key) {
+            out.push(format!("{}: {}", key, v));
+        }
+    }
+    for key in ["dependencies", "dev-dependencies", "build-dependencies"] {
+        cargo_deps(out, manifest, key, workspace, locked);
+    }
+}
+
+/// Returns the versions of each package in the project's Cargo.lock, keyed by package name.
+pub(crate) fn locked_versions(root: &Path) -> Result<BTreeMap<String, BTreeSet<String>>> {
+    let mut locked: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    if let Some(lock) = read_toml(root, "Cargo.lock")? {
+        for pkg in lock
+            .get("package")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_table)
+        {
+            if let (Some(name), Some(version)) =
+                (get_str(pkg, &["name"]), get_str(pkg, &["version"]))
+            {
+                locked
+                    .entry(name.to_string())
+                    .or_default()
+                    .insert(version.to_string());
+            }
+        }
+    }
+    Ok(locked)
+}
+
+/// Collects facts from the Cargo manifests in the project. Only workspace members inside `scope`,
+/// relative to the root, are included.
+fn cargo_facts(root: &Path, scope: &Path, out: &mut Vec<String>) -> Result<()> {
+    let Some(manifest) = read_toml(root, "Cargo.toml")? else {
+        return Ok(());
+    };
+    let locked = locked_versions(root)?;
+
+    out.push("# Rust (Cargo)".to_string());
+    if let Some(toolchain) = read_toml(root, "rust-toolchain.toml")? {
+        if let Some(channel) = get_str(&toolchain, &["toolchain", "channel"]) {
+            out.push(format!("toolchain: {}", channel));
+        }
+    } else if root.join("rust-toolchain").is_file() {
+        let channel = fs::read_to_string(root.join("rust-toolchain"))?;
+        out.push(format!("toolchain: {}", channel.trim()));
+    }
+
+    let workspace = manifest.contains_key("workspace").then_some(&manifest);
+    cargo_package(out, "", &manifest, workspace, &locked);
+    if let Some(ws) = manifest.get("workspace").and_then(Value::as_table) {
+        let mut members = BTreeSet::new();
+        for pattern in ws
+            .get("members")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+        {
+            let pattern = root.join(pattern).join("Cargo.toml");
+            let paths = glob::glob(&pattern.to_string_lossy())
+                .map_err(|e| TenxError::Resolve(format!("Invalid workspace member: {}", e)))?;
+            for path in paths.flatten() {
+                let dir = path.parent().and_then(|p| p.strip_prefix(root).ok());
+                if let Some(dir) = dir.filter(|d| d.starts_with(scope)) {
+                    members.insert(state::files::slash_path(dir));
+                }
+            }
+        }
+        if !members.is_empty() {
+            out.push(format!(
+                "workspace members: {}",
+                members.iter().cloned().collect::<Vec<_>>().join(", ")
+            ));
+        }
+        cargo_deps(out, ws, "dependencies", None, &locked);
+        for dir in members {
+            if let Some(member) = read_toml(root, &format!("{}/Cargo.toml", dir))? {
+                cargo_package(out, &dir, &member, workspace, &locked);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Collects facts from a Python pyproject.toml, if there is one.
+fn python_facts(root: &Path, out: &mut Vec<String>) -> Result<()> {
+    let Some(manifest) = read_toml(root, "pyproject.toml")? else {
+        return Ok(());
+    };
+    if !out.is_empty() {
+        out.push(String::new());
+    }
+    out.push("# Python (pyproject.toml)".to_string());
+    for (label, keys) in [
+        ("name", &["project", "name"][..]),
+        ("version", &["project", "version"]),
+        ("requires-python", &["project", "requires-python"]),
+        ("python", &["tool", "poetry", "dependencies", "python"]),
+    ] {
+        if let Some(v) = get_str(&manifest, keys) {
+            out.push(format!("{}: {}", label, v));
+        }
+    }
+
+    let strings = |v: Option<&Value>| -> Vec<String> {
+        v.and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .map(|s| format!("    {}", s))
+            .collect()
+    };
+    let project = manifest.get("project");
+    let deps = strings(project.and_then(|p| p.get("dependencies")));
+    if !deps.is_empty() {
+        out.push("dependencies:".to_string());
+        out.extend(deps);
+    }
+    if let Some(extras) = project
+        .and_then(|p| p.get("optional-dependencies"))
+        .and_then(Value::as_table)
+    {
+        for (extra, deps) in extras {
+            out.push(format!("optional-dependencies.{}:", extra));
+            out.extend(strings(Some(deps)));
+        }
+    }
+    if let Some(deps) = manifest
+        .get("tool")
+        .and_then(|t| t.get("poetry"))
+        .and_then(|p| p.get("dependencies"))
+        .and_then(Value::as_table)
+    {
+        out.push("poetry dependencies:".to_string());
+        for (name, spec) in deps.iter().filter(|(n, _)| *n != "python") {
+            let version = match spec {
+                Value::String(v) => v.as_str(),
+                Value::Table(t) => t.get("version").and_then(Value::as_str).unwrap_or("*"),
+                _ => "*",
+            };
+            out.push(format!("    {} {}", name, version));
+        }
+    }
+    Ok(())
+}
+
+/// Generates a summary of the facts in the project's manifests. Returns an empty string if the
+/// project has no manifests we understand.
+pub(crate) fn project_facts(config: &Config) -> Result<String> {
+    let root = config.project_root();
+    let mut out = vec![];
+    cargo_facts(&root, &config.scope, &mut out)?;
+    python_facts(&root, &mut out)?;
+    Ok(out.join("\n"))
+}
+
+#[async_trait]
+impl ContextProvider for ProjectFacts {
+    fn context_items(&self, _config: &Config, _session: &Session) -> Result<Vec<ContextItem>> {
+        if self.facts.is_empty() {
+            return Ok(vec![]);
+        }
+        Ok(vec![ContextItem {
+            ty: "project_facts".to_string(),
+            source: "project_facts".to_string(),
+            body: self.facts.clone(),
+        }])
+    }
+
+    fn human(&self) -> String {
+        "project_facts".to_string()
+    }
+
+    fn id(&self) -> String {
+        "project_facts".to_string()
+    }
+
+    async fn refresh(&mut self, config: &Config) -> Result<()> {
+        self.facts = project_facts(config)?;
+        Ok(())
+    }
+
+    async fn needs_refresh(&self, config: &Config) -> bool {
+        project_facts(config).is_ok_and(|f| f != self.facts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{context::Context, testutils::test_project};
+    use indoc::indoc;
+
+    #[tokio::test]
+    async fn test_project_facts() -> Result<()> {
+        let p = test_project();
+        let mut ctx = Context::new_project_facts();
+        ctx.refresh(&p.config).await?;
+        assert!(ctx.context_items(&p.config, &p.session)?.is_empty());
+
+        p.write(
+            "Cargo.toml",
+            indoc! {r#"
+                [workspace]
+                members = ["crates/*"]
+
+                [workspace.package]
+                edition = "2021"
+
+                [workspace.dependencies]
+                serde = { version = "1.0", features = ["derive"] }
+            "#},
+        );
+        fs::create_dir_all(p.tempdir.path().join("crates/core"))?;
+        p.write(
+            "crates/core/Cargo.toml",
+            indoc! {r#"
+                [package]
+                name = "core"
+                edition.workspace = true
+                rust-version = "1.80"
+
+                [dependencies]
+                serde = { workspace = true }
+                tokio = "1.39"
+                util = { path = "../util" }
+            "#},
+        );
+        p.write(
+            "Cargo.lock",
+            indoc! {r#"
+                [[package]]
+                name = "tokio"
+                version = "1.41.0"
+            "#},
+        );
+        p.write(
+            "pyproject.toml",
+            indoc! {r#"
+                [project]
+                name = "bindings"
+                requires-python = ">=3.10"
+                dependencies = ["numpy>=1.26"]
+            "#},
+        );
+        assert!(ctx.needs_refresh(&p.config).await);
+        ctx.refresh(&p.config).await?;
+        assert!(!ctx.needs_refresh(&p.config).await);
+
+        let items = ctx.context_items(&p.config, &p.session)?;
+        assert_eq!(
+            items[0].body,
+            indoc! {r#"
+                # Rust (Cargo)
+                workspace members: crates/core
+                dependencies:
+                    serde 1.0
+
+                ## crate core (crates/core)
+                edition: 2021
+                rust-version: 1.80
+                dependencies:
+                    serde 1.0
+                    tokio 1.39 (locked 1.41.0)
+                    util path ../util
+
+                # Python (pyproject.toml)
+                name: bindings
+                requires-python: >=3.10
+                dependencies:
+                    numpy>=1.26"#}
+        );
+        Ok(())
+    }
+}
@@ -4,10 +4,12 @@ use crate::config::Config;
 use crate::error::Result;
 use crate::session::Session;
 use async_trait::async_trait;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use state::files::slash_path;
 
 /// A context provider that represents the project's file structure.
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
 pub struct ProjectMap;
 
 impl ProjectMap {
@@ -22,7 +24,7 @@ impl ContextProvider for ProjectMap {
         let files = config.project_files()?;
         let body = files
             .iter()
-            .map(|p| p.to_string_lossy().to_string())
+            .map(|p| slash_path(p))
             .collect::<Vec<_>>()
             .join("\n");
 
@@ -44,6 +46,10 @@ impl ContextProvider for ProjectMap {
     async fn refresh(&mut self, _config: &Config) -> Result<()> {
         Ok(())
     }
+
+    fn captures(&self) -> bool {
+        false
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,117 @@
+use std::{fmt, path::Path, process::Command};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// The revision of the source a context's content was captured from.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Revision {
+    /// The commit checked out in the project's git repository
+    Git(String),
+    /// The ETag a server returned for a URL
+    ETag(String),
+    /// The version of a crate
+    Crate(String),
+}
+
+impl Revision {
+    /// The project's git HEAD commit, if the project is in a git repository with commits.
+    pub fn git(root: &Path) -> Option<Self> {
+        let output = Command::new("git")
+            .args(["rev-parse", "HEAD"])
+            .current_dir(root)
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        let hash = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        (!hash.is_empty()).then_some(Revision::Git(hash))
+    }
+}
+
+impl fmt::Display for Revision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Revision::Git(hash) => write!(f, "git {}", &hash[..hash.len().min(10)]),
+            Revision::ETag(etag) => write!(f, "etag {}", etag),
+            Revision::Crate(version) => write!(f, "version {}", version),
+        }
+    }
+}
+
+/// Where and when a context's content was captured.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
+pub struct Provenance {
+    /// When the content was captured, in seconds since the Unix epoch
+    pub captured: u64,
+    /// The revision of the source at capture time, if the source has one
+    pub revision: Option<Revision>,
+}
+
+impl Provenance {
+    /// Returns why content with this provenance is stale, if it is. Content is stale if the
+    /// source has moved to a different revision, or if it's older than `max_age` seconds. A
+    /// `max_age` of 0 means content never expires.
+    pub fn staleness(&self, current: Option<&Revision>, max_age: u64, now: u64) -> Option<String> {
+        if let (Some(was), Some(current)) = (&self.revision, current) {
+            if was != current {
+                return Some(match (was, current) {
+                    (Revision::Git(_), Revision::Git(_)) => {
+                        format!("HEAD moved from {} to {}", was, current)
+                    }
+                    _ => format!("source changed from {} to {}", was, current),
+                });
+            }
+        }
+        let age = now.saturating_sub(self.captured);
+        (max_age > 0 && age > max_age).then(|| format!("captured {} ago", human_age(age)))
+    }
+
+    /// A short description, like "captured 2h ago at git 1a2b3c4d5e".
+    pub fn describe(&self, now: u64) -> String {
+        let age = human_age(now.saturating_sub(self.captured));
+        match &self.revision {
+            Some(revision) => format!("captured {} ago at {}", age, revision),
+            None => format!("captured {} ago", age),
+        }
+    }
+}
+
+/// Formats a duration in seconds in its largest whole unit, like "45s", "3h" or "2d".
+fn human_age(secs: u64) -> String {
+    match secs {
+        s if s < 60 => format!("{}s", s),
+        s if s < 60 * 60 => format!("{}m", s / 60),
+        s if s < 24 * 60 * 60 => format!("{}h", s / (60 * 60)),
+        s => format!("{}d", s / (24 * 60 * 60)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_staleness() {
+        let p = Provenance {
+            captured: 1000,
+            revision: Some(Revision::Git("aaaaaaaaaaaa".into())),
+        };
+        let same = Revision::Git("aaaaaaaaaaaa".into());
+        let moved = Revision::Git("bbbbbbbbbbbb".into());
+        assert_eq!(p.staleness(Some(&same), 0, 100_000), None);
+        assert_eq!(
+            p.staleness(Some(&moved), 0, 1000),
+            Some("HEAD moved from git aaaaaaaaaa to git bbbbbbbbbb".into())
+        );
+        // An unknown current revision only expires by age
+        assert_eq!(p.staleness(None, 3600, 2000), None);
+        assert_eq!(
+            p.staleness(None, 3600, 1000 + 2 * 3600),
+            Some("captured 2h ago".into())
+        );
+        assert_eq!(p.describe(1090), "captured 1m ago at git aaaaaaaaaa");
+    }
+}
@@ -1,19 +1,51 @@
+use std::path::PathBuf;
+
 use super::ContextItem;
 use super::ContextProvider;
+use super::{locked_versions, Revision};
 use crate::config::Config;
 use crate::error::{Result, TenxError};
 use crate::session::Session;
 use async_trait::async_trait;
+use fs_err as fs;
 use libruskel::Ruskel as LibRuskel;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use tracing::warn;
 
 /// A context provider that generates Rust API documentation using Ruskel.
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
 pub struct Ruskel {
     pub(crate) name: String,
     pub(crate) content: String,
 }
 
+/// Splits a specification like `serde@1.0.200` into a published crate name and version. Returns
+/// None for anything else, like a plain crate name or a path.
+fn published(name: &str) -> Option<(&str, &str)> {
+    let (krate, version) = name.split_once('@')?;
+    let valid_crate = !krate.is_empty()
+        && krate
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    let valid_version = !version.is_empty()
+        && version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+'));
+    (valid_crate && valid_version).then_some((krate, version))
+}
+
+/// Returns true if a version names exactly one release, like `1.0.200`, rather than a requirement
+/// like `1`. The content of a release never changes, so we can always use a cached copy.
+fn is_exact(version: &str) -> bool {
+    let core = version.split(['-', '+']).next().unwrap_or_default();
+    let parts: Vec<&str> = core.split('.').collect();
+    parts.len() == 3
+        && parts
+            .iter()
+            .all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()))
+}
+
 impl Ruskel {
     pub(crate) fn new(name: String) -> Self {
         Self {
@@ -21,15 +53,42 @@ impl Ruskel {
             content: String::new(),
         }
     }
+
+    /// The path of the cached skeleton for a published crate version, if this is one and a cache
+    /// directory is configured.
+    fn cache_path(&self, config: &Config) -> Option<PathBuf> {
+        if config.cache_dir.as_os_str().is_empty() {
+            return None;
+        }
+        let (krate, version) = published(&self.name)?;
+        Some(
+            config
+                .cache_dir
+                .join("ruskel")
+                .join(krate)
+                .join(format!("{}.rs", version)),
+        )
+    }
+
+    fn render(&self) -> Result<String> {
+        LibRuskel::new(&self.name)
+            .render(false, false, true)
+            .map_err(|e| TenxError::Resolve(e.to_string()))
+    }
 }
 
 #[async_trait]
 impl ContextProvider for Ruskel {
-    fn context_items(&self, _config: &Config, _session: &Session) -> Result<Vec<ContextItem>> {
+    fn context_items(&self, config: &Config, _session: &Session) -> Result<Vec<ContextItem>> {
+        let body = if config.offline && self.content.is_empty() {
+            format!("// Offline: the skeleton of {} was not rendered", self.name)
+        } else {
+            self.content.clone()
+        };
         Ok(vec![ContextItem {
             ty: "ruskel".to_string(),
             source: self.name.clone(),
-            body: self.content.clone(),
+            body,
         }])
     }
 
@@ -41,15 +100,112 @@ impl ContextProvider for Ruskel {
         self.name.clone()
     }
 
-    async fn refresh(&mut self, _config: &Config) -> Result<()> {
-        let ruskel = LibRuskel::new(&self.name);
-        self.content = ruskel
-            .render(false, false, true)
-            .map_err(|e| TenxError::Resolve(e.to_string()))?;
+    /// Renders the skeleton. Published crate versions are cached on disk, keyed by crate and
+    /// version. An exact version is always served from the cache if present, and a version
+    /// requirement falls back to the cache if rendering fails, so we can work offline. In offline
+    /// mode, nothing is rendered, so the skeleton is rendered on the first refresh back online.
+    async fn refresh(&mut self, config: &Config) -> Result<()> {
+        if config.offline {
+            return Ok(());
+        }
+        let Some(cache) = self.cache_path(config) else {
+            self.content = self.render()?;
+            return Ok(());
+        };
+        let exact = published(&self.name).is_some_and(|(_, v)| is_exact(v));
+        if exact && cache.exists() {
+            self.content = fs::read_to_string(&cache)?;
+            return Ok(());
+        }
+        match self.render() {
+            Ok(content) => {
+                if let Some(parent) = cache.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&cache, &content)?;
+                self.content = content;
+            }
+            Err(e) if cache.exists() => {
+                warn!("Using cached skeleton for {}: {}", self.name, e);
+                self.content = fs::read_to_string(&cache)?;
+            }
+            Err(e) => return Err(e),
+        }
         Ok(())
     }
 
     async fn needs_refresh(&self, _config: &Config) -> bool {
         self.content.is_empty()
     }
+
+    /// The crate version: the one named in the specification, or else the project's locked
+    /// version, if it locks exactly one.
+    fn revision(&self, config: &Config) -> Option<Revision> {
+        if let Some((_, version)) = published(&self.name) {
+            return Some(Revision::Crate(version.to_string()));
+        }
+        let locked = locked_versions(&config.project_root()).ok()?;
+        let versions = locked.get(&self.name)?;
+        if versions.len() != 1 {
+            return None;
+        }
+        versions.first().cloned().map(Revision::Crate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutils::test_project;
+
+    #[tokio::test]
+    async fn test_published_cache() -> Result<()> {
+        assert_eq!(published("serde@1.0.200"), Some(("serde", "1.0.200")));
+        assert_eq!(published("serde"), None);
+        assert_eq!(published("./path@1"), None);
+        assert!(is_exact("1.0.200"));
+        assert!(is_exact("1.0.0-beta.1"));
+        assert!(!is_exact("1.0"));
+
+        let mut p = test_project();
+        p.config.cache_dir = p.tempdir.path().join("cache");
+        let cached = p.config.cache_dir.join("ruskel/serde/1.0.200.rs");
+        fs::create_dir_all(cached.parent().unwrap())?;
+        fs::write(&cached, "pub trait Serialize {}")?;
+
+        // A cached exact version never touches the network
+        let mut ruskel = Ruskel::new("serde@1.0.200".into());
+        ruskel.refresh(&p.config).await?;
+        assert_eq!(ruskel.content, "pub trait Serialize {}");
+
+        // Offline, even a cached version isn't read
+        p.config.offline = true;
+        let mut offline = Ruskel::new("serde@1.0.200".into());
+        offline.refresh(&p.config).await?;
+        assert_eq!(
+            offline.context_items(&p.config, &p.session)?[0].body,
+            "// Offline: the skeleton of serde@1.0.200 was not rendered"
+        );
+        p.config.offline = false;
+        assert!(offline.needs_refresh(&p.config).await);
+
+        // Plain names aren't cached, since they may be workspace crates or paths
+        assert!(Ruskel::new("serde".into()).cache_path(&p.config).is_none());
+
+        // Versions come from the specification, or from the project's lockfile
+        assert_eq!(
+            ruskel.revision(&p.config),
+            Some(Revision::Crate("1.0.200".into()))
+        );
+        assert_eq!(Ruskel::new("serde".into()).revision(&p.config), None);
+        fs::write(
+            p.tempdir.path().join("Cargo.lock"),
+            "[[package]]\nname = \"serde\"\nversion = \"1.0.210\"\n",
+        )?;
+        assert_eq!(
+            Ruskel::new("serde".into()).revision(&p.config),
+            Some(Revision::Crate("1.0.210".into()))
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,113 @@
+use super::ContextItem;
+use super::ContextProvider;
+use super::Revision;
+use crate::config::Config;
+use crate::error::Result;
+use crate::scrub::Scrubber;
+use crate::search;
+use crate::session::Session;
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// A context provider that captures the results of a project search, showing each match with
+/// surrounding lines.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
+pub struct Search {
+    pub(crate) pattern: String,
+    pub(crate) context_lines: usize,
+    pub(crate) content: String,
+    /// The number of matching lines, not counting context lines
+    #[serde(default)]
+    pub(crate) matches: usize,
+}
+
+impl Search {
+    pub(crate) fn new(pattern: String, context_lines: usize) -> Self {
+        Self {
+            pattern,
+            context_lines,
+            content: String::new(),
+            matches: 0,
+        }
+    }
+}
+
+#[async_trait]
+impl ContextProvider for Search {
+    fn context_items(&self, _config: &Config, _session: &Session) -> Result<Vec<ContextItem>> {
+        Ok(vec![ContextItem {
+            ty: "search".to_string(),
+            source: self.pattern.clone(),
+            body: self.content.clone(),
+        }])
+    }
+
+    fn human(&self) -> String {
+        format!("search: {} ({} matches)", self.pattern, self.matches)
+    }
+
+    fn id(&self) -> String {
+        format!("search:{}", self.pattern)
+    }
+
+    async fn refresh(&mut self, config: &Config) -> Result<()> {
+        let scrubber = Scrubber::from_config(&config.scrub)?;
+        let matches = search::search(config, &self.pattern, self.context_lines, scrubber.as_ref())?;
+        self.content = search::render(&matches);
+        self.matches = matches.iter().map(|m| m.match_count()).sum();
+        Ok(())
+    }
+
+    async fn needs_refresh(&self, _config: &Config) -> bool {
+        self.content.is_empty()
+    }
+
+    /// Any change can add or remove matches.
+    fn overlaps(&self, files: &[PathBuf]) -> bool {
+        !files.is_empty()
+    }
+
+    fn revision(&self, config: &Config) -> Option<Revision> {
+        Revision::git(&config.project_root())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::{Context, ContextProvider},
+        testutils::test_project,
+    };
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn test_search_context() {
+        let rt = Runtime::new().unwrap();
+        let test_project = test_project();
+        test_project.write("a.rs", "fn foo() {}\nfn bar() {\n    foo();\n}\n");
+        let config = test_project.config;
+        let session = Session::new(&config).unwrap();
+        let mut context = Context::new_search(r"foo\(", 0);
+
+        assert!(rt.block_on(async { context.needs_refresh(&config).await }));
+        rt.block_on(async { context.refresh(&config).await.unwrap() });
+
+        let items = context.context_items(&config, &session).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].ty, "search");
+        assert_eq!(items[0].source, r"foo\(");
+        assert_eq!(items[0].body, "a.rs:1:fn foo() {}\n--\na.rs:3:    foo();");
+        assert_eq!(context.human(), r"search: foo\( (2 matches)");
+        assert!(!rt.block_on(async { context.needs_refresh(&config).await }));
+
+        // Context lines aren't counted as matches
+        let mut context = Context::new_search(r"fn bar", 1);
+        rt.block_on(async { context.refresh(&config).await.unwrap() });
+        let items = context.context_items(&config, &session).unwrap();
+        assert_eq!(items[0].body.lines().count(), 3);
+        assert_eq!(context.human(), "search: fn bar (1 matches)");
+    }
+}
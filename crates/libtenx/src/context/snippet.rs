@@ -0,0 +1,189 @@
+use super::ContextItem;
+use super::ContextProvider;
+use crate::config::Config;
+use crate::error::{Result, TenxError};
+use crate::session::Session;
+use async_trait::async_trait;
+use fs_err as fs;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use state::files::slash_path;
+use std::path::PathBuf;
+
+/// A context provider for a range of lines in a project file, like `src/session.rs:100-180`.
+/// The captured lines are kept as an anchor, so when the file is edited and the lines move, the
+/// range follows them on refresh.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
+pub struct Snippet {
+    /// The file path, relative to the project root.
+    pub(crate) path: String,
+    /// The first line of the range, 1-based.
+    pub(crate) start: usize,
+    /// The last line of the range, inclusive.
+    pub(crate) end: usize,
+    /// The lines of the range when it was last refreshed.
+    pub(crate) lines: Vec<String>,
+}
+
+impl Snippet {
+    /// Creates a snippet from a specification like `src/session.rs:100-180`.
+    pub(crate) fn new(config: &Config, spec: &str) -> Result<Self> {
+        let invalid = || {
+            TenxError::Config(format!(
+                "Invalid line range {}, expected path:start-end",
+                spec
+            ))
+        };
+        let (path, range) = spec.rsplit_once(':').ok_or_else(invalid)?;
+        let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+        let start: usize = start.trim().parse().map_err(|_| invalid())?;
+        let end: usize = end.trim().parse().map_err(|_| invalid())?;
+        if start == 0 || end < start {
+            return Err(invalid());
+        }
+        Ok(Self {
+            path: slash_path(&config.normalize_path(path)?),
+            start,
+            end,
+            lines: vec![],
+        })
+    }
+
+    /// Reads the lines of the file.
+    fn read(&self, config: &Config) -> Result<Vec<String>> {
+        let text = fs::read_to_string(config.abspath(std::path::Path::new(&self.path))?)?;
+        Ok(text.lines().map(|l| l.to_string()).collect())
+    }
+
+    /// Returns the lines of the file in the current range, clamped to the file's length.
+    fn slice<'a>(&self, file: &'a [String]) -> &'a [String] {
+        let end = self.end.min(file.len());
+        let start = (self.start - 1).min(end);
+        &file[start..end]
+    }
+
+    /// Finds where the anchor lines are now, preferring the match nearest the old position.
+    /// First looks for the whole block, then for a span that starts and ends with the anchor's
+    /// first and last lines. Returns the new 1-based start and end.
+    fn locate(&self, file: &[String]) -> Option<(usize, usize)> {
+        let anchor = &self.lines;
+        let (first, last) = (anchor.first()?.trim(), anchor.last()?.trim());
+        let nearest = |candidates: Vec<(usize, usize)>| {
+            candidates
+                .into_iter()
+                .min_by_key(|(s, _)| s.abs_diff(self.start - 1))
+                .map(|(s, e)| (s + 1, e + 1))
+        };
+        let exact = (0..file.len().saturating_sub(anchor.len() - 1))
+            .filter(|&i| file[i..i + anchor.len()] == anchor[..])
+            .map(|i| (i, i + anchor.len() - 1))
+            .collect();
+        nearest(exact).or_else(|| {
+            let spans = (0..file.len())
+                .filter(|&i| file[i].trim() == first)
+                .filter_map(|i| {
+                    let from = i + usize::from(anchor.len() > 1);
+                    (from..file.len())
+                        .find(|&j| file[j].trim() == last)
+                        .map(|j| (i, j))
+                })
+                .collect();
+            nearest(spans)
+        })
+    }
+}
+
+#[async_trait]
+impl ContextProvider for Snippet {
+    fn context_items(&self, _config: &Config, _session: &Session) -> Result<Vec<ContextItem>> {
+        let width = self.end.to_string().len();
+        let mut body = format!("{} (lines {}-{})\n", self.path, self.start, self.end);
+        for (i, line) in self.lines.iter().enumerate() {
+            body.push_str(&format!("{:>width$} | {}\n", self.start + i, line));
+        }
+        Ok(vec![ContextItem {
+            ty: "snippet".to_string(),
+            source: format!("{}:{}-{}", self.path, self.start, self.end),
+            body,
+        }])
+    }
+
+    fn human(&self) -> String {
+        format!("lines: {}:{}-{}", self.path, self.start, self.end)
+    }
+
+    fn id(&self) -> String {
+        format!("lines:{}:{}-{}", self.path, self.start, self.end)
+    }
+
+    async fn refresh(&mut self, config: &Config) -> Result<()> {
+        let file = self.read(config)?;
+        if !self.lines.is_empty() && self.slice(&file) != self.lines {
+            if let Some((start, end)) = self.locate(&file) {
+                self.start = start;
+                self.end = end;
+            }
+        }
+        let lines = self.slice(&file).to_vec();
+        if lines.is_empty() {
+            return Err(TenxError::Resolve(format!(
+                "No lines {}-{} in {}",
+                self.start, self.end, self.path
+            )));
+        }
+        self.end = self.start + lines.len() - 1;
+        self.lines = lines;
+        Ok(())
+    }
+
+    async fn needs_refresh(&self, config: &Config) -> bool {
+        match self.read(config) {
+            Ok(file) => self.lines.is_empty() || self.slice(&file) != self.lines,
+            Err(_) => true,
+        }
+    }
+
+    fn overlaps(&self, files: &[PathBuf]) -> bool {
+        files.iter().any(|f| slash_path(f) == self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{context::Context, testutils::test_project};
+
+    #[tokio::test]
+    async fn test_snippet_context() -> Result<()> {
+        let p = test_project();
+        p.write("lib.rs", "a\nb\nfn go() {\n    run();\n}\nc\n");
+        let mut context = Context::new_lines(&p.config, "lib.rs:3-5")?;
+        assert!(context.needs_refresh(&p.config).await);
+        context.refresh(&p.config).await?;
+        assert!(!context.needs_refresh(&p.config).await);
+
+        let items = context.context_items(&p.config, &p.session)?;
+        assert_eq!(items[0].source, "lib.rs:3-5");
+        assert_eq!(
+            items[0].body,
+            "lib.rs (lines 3-5)\n3 | fn go() {\n4 |     run();\n5 | }\n"
+        );
+
+        // Lines inserted above move the range
+        p.write("lib.rs", "x\ny\na\nb\nfn go() {\n    run();\n}\nc\n");
+        assert!(context.needs_refresh(&p.config).await);
+        context.refresh(&p.config).await?;
+        assert_eq!(context.id(), "lines:lib.rs:5-7");
+
+        // An edited body is still found by its first and last lines
+        p.write("lib.rs", "fn go() {\n    run();\n    stop();\n}\n");
+        context.refresh(&p.config).await?;
+        let items = context.context_items(&p.config, &p.session)?;
+        assert_eq!(items[0].source, "lib.rs:1-4");
+        assert!(items[0].body.contains("3 |     stop();\n"));
+
+        assert!(Context::new_lines(&p.config, "lib.rs:5-2").is_err());
+        assert!(Context::new_lines(&p.config, "lib.rs").is_err());
+        Ok(())
+    }
+}
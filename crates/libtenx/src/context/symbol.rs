@@ -0,0 +1,120 @@
+use super::ContextItem;
+use super::ContextProvider;
+use crate::config::Config;
+use crate::error::{Result, TenxError};
+use crate::session::Session;
+use crate::symbols::{self, SymbolMatch};
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use state::files::slash_path;
+use std::path::PathBuf;
+
+/// A context provider that extracts named items, like a function or an impl block, from the
+/// project's Rust files. Items are tracked by symbol, so they are re-extracted when the files
+/// containing them change.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
+pub struct Symbol {
+    pub(crate) symbol: String,
+    pub(crate) matches: Vec<SymbolMatch>,
+}
+
+impl Symbol {
+    pub(crate) fn new(symbol: String) -> Self {
+        Self {
+            symbol,
+            matches: vec![],
+        }
+    }
+}
+
+#[async_trait]
+impl ContextProvider for Symbol {
+    fn context_items(&self, _config: &Config, _session: &Session) -> Result<Vec<ContextItem>> {
+        Ok(self
+            .matches
+            .iter()
+            .map(|m| ContextItem {
+                ty: "symbol".to_string(),
+                source: format!("{}:{} ({})", slash_path(&m.path), m.line, self.symbol),
+                body: m.text.clone(),
+            })
+            .collect())
+    }
+
+    fn human(&self) -> String {
+        format!("symbol: {} ({} matches)", self.symbol, self.matches.len())
+    }
+
+    fn id(&self) -> String {
+        format!("symbol:{}", self.symbol)
+    }
+
+    async fn refresh(&mut self, config: &Config) -> Result<()> {
+        let matches = symbols::find(config, &self.symbol)?;
+        if matches.is_empty() {
+            return Err(TenxError::Resolve(format!(
+                "Symbol not found: {}",
+                self.symbol
+            )));
+        }
+        self.matches = matches;
+        Ok(())
+    }
+
+    async fn needs_refresh(&self, config: &Config) -> bool {
+        if self.matches.is_empty() {
+            return true;
+        }
+        // Re-extract from the files we found matches in, and refresh if anything has changed
+        let mut paths: Vec<_> = self.matches.iter().map(|m| &m.path).collect();
+        paths.dedup();
+        let mut current = vec![];
+        for path in paths {
+            match symbols::find_in_file(config, path, &self.symbol) {
+                Ok(m) => current.extend(m),
+                Err(_) => return true,
+            }
+        }
+        current != self.matches
+    }
+
+    fn overlaps(&self, files: &[PathBuf]) -> bool {
+        self.matches.iter().any(|m| files.contains(&m.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{context::Context, testutils::test_project};
+
+    #[tokio::test]
+    async fn test_symbol_context() -> Result<()> {
+        let p = test_project();
+        p.write("lib.rs", "struct A;\n\nimpl A {\n    fn go(&self) {}\n}\n");
+        let mut context = Context::new_symbol("A::go");
+        assert!(context.needs_refresh(&p.config).await);
+        context.refresh(&p.config).await?;
+        assert!(!context.needs_refresh(&p.config).await);
+
+        let items = context.context_items(&p.config, &p.session)?;
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].source, "lib.rs:4 (A::go)");
+        assert_eq!(items[0].body, "fn go(&self) {}");
+
+        // Changing the file means the symbol is re-extracted
+        p.write(
+            "lib.rs",
+            "struct A;\n\nimpl A {\n    fn go(&self) { todo!() }\n}\n",
+        );
+        assert!(context.needs_refresh(&p.config).await);
+        context.refresh(&p.config).await?;
+        let items = context.context_items(&p.config, &p.session)?;
+        assert_eq!(items[0].body, "fn go(&self) { todo!() }");
+
+        let mut missing = Context::new_symbol("A::stop");
+        assert!(missing.refresh(&p.config).await.is_err());
+        Ok(())
+    }
+}
@@ -4,10 +4,11 @@ use crate::config::Config;
 use crate::error::Result;
 use crate::session::Session;
 use async_trait::async_trait;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 /// A context provider for raw text content.
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
 pub struct Text {
     pub(crate) name: String,
     pub(crate) content: String,
@@ -42,4 +43,8 @@ impl ContextProvider for Text {
     async fn refresh(&mut self, _config: &Config) -> Result<()> {
         Ok(())
     }
+
+    fn captures(&self) -> bool {
+        false
+    }
 }
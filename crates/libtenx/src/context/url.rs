@@ -1,17 +1,23 @@
 use super::ContextItem;
 use super::ContextProvider;
+use super::Revision;
 use crate::config::Config;
 use crate::error::{Result, TenxError};
 use crate::session::Session;
 use async_trait::async_trait;
+use reqwest::{header, StatusCode};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 /// A context provider that fetches content from a remote URL.
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
 pub struct Url {
     pub(crate) name: String,
     pub(crate) url: String,
     pub(crate) content: String,
+    /// The ETag the server returned with the content, if any
+    #[serde(default)]
+    pub(crate) etag: Option<String>,
 }
 
 impl Url {
@@ -26,17 +32,23 @@ impl Url {
             name,
             url,
             content: String::new(),
+            etag: None,
         }
     }
 }
 
 #[async_trait]
 impl ContextProvider for Url {
-    fn context_items(&self, _config: &Config, _session: &Session) -> Result<Vec<ContextItem>> {
+    fn context_items(&self, config: &Config, _session: &Session) -> Result<Vec<ContextItem>> {
+        let body = if config.offline && self.content.is_empty() {
+            format!("Offline: {} was not fetched", self.url)
+        } else {
+            self.content.clone()
+        };
         Ok(vec![ContextItem {
             ty: "url".to_string(),
             source: self.url.clone(),
-            body: self.content.clone(),
+            body,
         }])
     }
 
@@ -48,13 +60,31 @@ impl ContextProvider for Url {
         self.url.clone()
     }
 
-    async fn refresh(&mut self, _config: &Config) -> Result<()> {
+    /// Fetches the content. If we have content with an ETag, the request is conditional, and
+    /// the content is kept if the server says it hasn't changed. Offline, nothing is fetched, so
+    /// the URL is fetched on the first refresh after going back online.
+    async fn refresh(&mut self, config: &Config) -> Result<()> {
+        if config.offline {
+            return Ok(());
+        }
         let client = reqwest::Client::new();
-        self.content = client
-            .get(&self.url)
+        let mut request = client.get(&self.url);
+        if let Some(etag) = self.etag.as_ref().filter(|_| !self.content.is_empty()) {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        let response = request
             .send()
             .await
-            .map_err(|e| TenxError::Resolve(e.to_string()))?
+            .map_err(|e| TenxError::Resolve(e.to_string()))?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(());
+        }
+        self.etag = response
+            .headers()
+            .get(header::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+        self.content = response
             .text()
             .await
             .map_err(|e| TenxError::Resolve(e.to_string()))?;
@@ -64,4 +94,32 @@ impl ContextProvider for Url {
     async fn needs_refresh(&self, _config: &Config) -> bool {
         self.content.is_empty()
     }
+
+    fn revision(&self, _config: &Config) -> Option<Revision> {
+        self.etag.clone().map(Revision::ETag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutils::offline_project;
+
+    #[tokio::test]
+    async fn test_offline() -> Result<()> {
+        let mut p = offline_project();
+        let mut url = Url::new("https://example.com/docs".into());
+        assert!(url.needs_refresh(&p.config).await);
+        url.refresh(&p.config).await?;
+        assert_eq!(
+            url.context_items(&p.config, &p.session)?[0].body,
+            "Offline: https://example.com/docs was not fetched"
+        );
+        assert_eq!(url.revision(&p.config), None);
+
+        // The placeholder isn't kept, so the URL is fetched once we're back online
+        p.config.offline = false;
+        assert!(url.needs_refresh(&p.config).await);
+        Ok(())
+    }
 }
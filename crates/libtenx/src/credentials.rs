@@ -0,0 +1,289 @@
+//! API key resolution for model providers.
+//!
+//! A provider's key comes from the first of these that is set: the `key` in the model's config,
+//! the environment variable named by the model's `key_env`, then the keychain. Keys are stored
+//! in the keychain with `tenx auth set <provider>`. OpenAI-compatible APIs other than OpenAI's
+//! own, like Groq or DeepSeek, never use the stored OpenAI key: their keys are stored under the
+//! API's host, for instance `tenx auth set api.groq.com`. Only two keychains are supported: the macOS
+//! keychain, through the `security` tool, and the libsecret keyring on Linux, through
+//! `secret-tool`. Keys are passed to both on stdin, so they never appear in a command line. Other
+//! platforms have no keychain, and keys have to be set in the environment or config.
+use std::{
+    collections::HashMap,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use crate::{
+    error::{Result, TenxError},
+    exec::find_program,
+};
+
+/// The service name keys are stored under in the keychain.
+const SERVICE: &str = "tenx";
+
+/// The providers we store keys for.
+pub const PROVIDERS: &[&str] = &["claude", "openai", "google"];
+
+/// The host of OpenAI's own API. Only models using it fall back to the stored OpenAI key.
+const OPENAI_HOST: &str = "api.openai.com";
+
+/// Returns the host of an API base URL, like `api.groq.com` for `https://api.groq.com/openai/v1`.
+fn host(api_base: &str) -> &str {
+    let rest = api_base
+        .split_once("://")
+        .map_or(api_base, |(_, rest)| rest);
+    let authority = rest.split('/').next().unwrap_or_default();
+    let authority = authority.rsplit('@').next().unwrap_or_default();
+    authority.split(':').next().unwrap_or_default()
+}
+
+/// Returns the provider an OpenAI-compatible model's key is stored under: "openai" for OpenAI's
+/// own API, and the API's host for anything else, so a stored OpenAI key is never sent to a
+/// third party.
+pub fn openai_provider(api_base: &str) -> String {
+    let host = host(api_base).to_ascii_lowercase();
+    if host == OPENAI_HOST {
+        "openai".to_string()
+    } else {
+        host
+    }
+}
+
+/// Is this a host that keys can be stored under?
+fn is_host(provider: &str) -> bool {
+    provider.contains('.')
+        && provider
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+}
+
+/// The name of the keychain on this platform, or None if there isn't one.
+pub fn keychain_name() -> Option<&'static str> {
+    if cfg!(target_os = "macos") {
+        Some("macOS keychain")
+    } else if cfg!(target_os = "linux") {
+        Some("libsecret keyring")
+    } else {
+        None
+    }
+}
+
+/// Returns an error for a provider with no configured key, explaining how to set one.
+pub fn missing_key(provider: &str) -> TenxError {
+    TenxError::MissingKey {
+        provider: provider.to_string(),
+    }
+}
+
+fn check_provider(provider: &str) -> Result<()> {
+    if PROVIDERS.contains(&provider) || is_host(provider) {
+        Ok(())
+    } else {
+        Err(TenxError::Config(format!(
+            "Unknown provider: {} (expected one of {}, or the host of an OpenAI-compatible API)",
+            provider,
+            PROVIDERS.join(", ")
+        )))
+    }
+}
+
+/// Runs a keychain tool, returning its stdout if it succeeded.
+fn run(program: &str, args: &[&str], input: Option<&str>) -> Result<Option<String>> {
+    if find_program(program).is_none() {
+        return Ok(None);
+    }
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| TenxError::Exec {
+            cmd: program.to_string(),
+            error: e.to_string(),
+        })?;
+    if let Some(input) = input {
+        child
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(input.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    Ok(Some(
+        String::from_utf8_lossy(&output.stdout).trim().to_string(),
+    ))
+}
+
+/// Reads a provider's key from the keychain. Returns None if there is no stored key, or no
+/// keychain on this platform.
+pub fn keychain_get(provider: &str) -> Result<Option<String>> {
+    check_provider(provider)?;
+    let key = if cfg!(target_os = "macos") {
+        run(
+            "security",
+            &["find-generic-password", "-s", SERVICE, "-a", provider, "-w"],
+            None,
+        )?
+    } else if cfg!(target_os = "linux") {
+        run(
+            "secret-tool",
+            &["lookup", "service", SERVICE, "provider", provider],
+            None,
+        )?
+    } else {
+        None
+    };
+    Ok(key.filter(|k| !k.is_empty()))
+}
+
+/// Stores a provider's key in the keychain, replacing any existing key.
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+pub fn keychain_set(provider: &str, key: &str) -> Result<()> {
+    check_provider(provider)?;
+    if key.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        return Err(TenxError::Config(
+            "API keys can't contain whitespace, quotes or backslashes".into(),
+        ));
+    }
+    let stored = if cfg!(target_os = "macos") {
+        // In interactive mode security reads commands from stdin, keeping the key out of argv
+        let command = format!(
+            "add-generic-password -U -s {} -a {} -w \"{}\"\n",
+            SERVICE, provider, key
+        );
+        run("security", &["-i"], Some(&command))?
+            .and_then(|_| keychain_get(provider).ok().flatten())
+            .filter(|k| k == key)
+    } else if cfg!(target_os = "linux") {
+        let label = format!("tenx {} API key", provider);
+        run(
+            "secret-tool",
+            &[
+                "store", "--label", &label, "service", SERVICE, "provider", provider,
+            ],
+            Some(key),
+        )?
+    } else {
+        None
+    };
+    stored.map(|_| ()).ok_or_else(|| {
+        TenxError::Config(format!(
+            "Could not store the key for {} in the {}. Set it in the environment or config \
+             instead.",
+            provider,
+            keychain_name().unwrap_or("keychain, since this platform has none")
+        ))
+    })
+}
+
+/// Looks up stored keys. Models never read the keychain directly, so tests can substitute a
+/// `FakeKeychain`.
+pub trait Keychain {
+    /// Returns a provider's stored key, or None if there isn't one.
+    fn get(&self, provider: &str) -> Result<Option<String>>;
+}
+
+/// The platform keychain.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OsKeychain;
+
+impl Keychain for OsKeychain {
+    fn get(&self, provider: &str) -> Result<Option<String>> {
+        keychain_get(provider)
+    }
+}
+
+/// A keychain for tests that holds keys in memory.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FakeKeychain {
+    keys: HashMap<String, String>,
+}
+
+impl FakeKeychain {
+    /// Stores a key for a provider.
+    pub fn with_key(mut self, provider: &str, key: &str) -> Self {
+        self.keys.insert(provider.to_string(), key.to_string());
+        self
+    }
+}
+
+impl Keychain for FakeKeychain {
+    fn get(&self, provider: &str) -> Result<Option<String>> {
+        check_provider(provider)?;
+        Ok(self.keys.get(provider).cloned())
+    }
+}
+
+/// Resolves a provider's key, given the key from the config after environment variables have
+/// been applied. Falls back to the keychain if the key is empty, and returns an empty key if
+/// none is found, so commands that don't talk to the model still work.
+pub fn resolve(keychain: &dyn Keychain, provider: &str, key: &str) -> String {
+    if !key.is_empty() {
+        return key.to_string();
+    }
+    keychain.get(provider).ok().flatten().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let keychain = FakeKeychain::default().with_key("claude", "stored");
+        assert_eq!(resolve(&keychain, "claude", "from-config"), "from-config");
+        assert_eq!(resolve(&keychain, "claude", ""), "stored");
+        assert_eq!(resolve(&keychain, "openai", ""), "");
+        assert!(keychain.get("nope").is_err());
+        assert!(keychain_get("nope").is_err());
+        // Keys that can't be passed to the keychain tools safely are refused before storing
+        assert!(keychain_set("claude", "a\" -w \"b").is_err());
+        assert_eq!(
+            missing_key("openai").to_string(),
+            "No API key for openai. Run `tenx auth set openai`, or set the key in the \
+             environment or config."
+        );
+    }
+
+    #[test]
+    fn test_openai_provider() {
+        assert_eq!(openai_provider("https://api.openai.com/v1"), "openai");
+        assert_eq!(openai_provider("https://API.openai.com:443/v1"), "openai");
+        assert_eq!(
+            openai_provider("https://api.groq.com/openai/v1"),
+            "api.groq.com"
+        );
+        assert_eq!(
+            openai_provider("https://api.deepseek.com"),
+            "api.deepseek.com"
+        );
+        // Lookalike hosts don't get the OpenAI key
+        assert_eq!(
+            openai_provider("https://api.openai.com.evil.test/v1"),
+            "api.openai.com.evil.test"
+        );
+
+        let keychain = FakeKeychain::default()
+            .with_key("openai", "openai-key")
+            .with_key("api.groq.com", "groq-key");
+        assert_eq!(
+            resolve(
+                &keychain,
+                &openai_provider("https://api.groq.com/openai/v1"),
+                ""
+            ),
+            "groq-key"
+        );
+        assert_eq!(
+            resolve(&keychain, &openai_provider("https://api.deepseek.com"), ""),
+            ""
+        );
+        assert!(check_provider("api.groq.com").is_ok());
+        assert!(check_provider("no such host").is_err());
+    }
+}
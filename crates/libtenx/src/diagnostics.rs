@@ -0,0 +1,191 @@
+//! Rendering of check diagnostics with the source they point at. The same renderer is used for
+//! the terminal output and for the message that asks the model to fix a failed check, so both see
+//! the same snippets.
+use std::path::PathBuf;
+
+use fs_err as fs;
+
+use crate::{
+    checks::Check,
+    config::Config,
+    sarif::{self, Diagnostic},
+};
+
+/// Lines shown above and below the line a diagnostic points at.
+const CONTEXT_LINES: usize = 2;
+
+/// The most snippets rendered for a single check failure.
+const MAX_SNIPPETS: usize = 10;
+
+/// The source around a diagnostic's location.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snippet {
+    /// The path as reported by the tool.
+    pub path: String,
+    /// The 1-based line the diagnostic points at.
+    pub line: usize,
+    /// The 1-based column the diagnostic points at, if the tool reported one.
+    pub column: Option<usize>,
+    pub message: String,
+    /// The 1-based number of the first line in `lines`.
+    pub start: usize,
+    pub lines: Vec<String>,
+}
+
+impl Snippet {
+    /// Loads the source around a diagnostic's location, looking for the file relative to each of
+    /// `dirs` in turn. Paths come from a tool's output, so files outside the project are never
+    /// read. Returns None if the diagnostic has no location, or the file can't be read.
+    pub fn load(config: &Config, dirs: &[PathBuf], d: &Diagnostic) -> Option<Self> {
+        let (path, line) = (d.path.as_ref()?, d.line?);
+        let root = config.project_root();
+        let text = dirs.iter().find_map(|dir| {
+            let full = dir.join(path);
+            config.confine(full.strip_prefix(&root).ok()?).ok()?;
+            fs::read_to_string(&full).ok()
+        })?;
+        let all: Vec<&str> = text.lines().collect();
+        if line == 0 || line > all.len() {
+            return None;
+        }
+        let start = line.saturating_sub(CONTEXT_LINES).max(1);
+        let end = (line + CONTEXT_LINES).min(all.len());
+        Some(Snippet {
+            path: path.clone(),
+            line,
+            column: d.column,
+            message: d.message.clone(),
+            start,
+            lines: all[start - 1..end].iter().map(|l| l.to_string()).collect(),
+        })
+    }
+
+    /// The text of the line the diagnostic points at.
+    fn target(&self) -> &str {
+        &self.lines[self.line - self.start]
+    }
+
+    /// Renders the snippet in the style of rustc, with a caret under the reported column.
+    pub fn render(&self) -> String {
+        let width = (self.start + self.lines.len() - 1).to_string().len();
+        let gutter = " ".repeat(width);
+        let location = match self.column {
+            Some(column) => format!("{}:{}:{}", self.path, self.line, column),
+            None => format!("{}:{}", self.path, self.line),
+        };
+        let mut out = vec![
+            format!("{}: {}", location, self.message),
+            format!("{} |", gutter),
+        ];
+        for (i, text) in self.lines.iter().enumerate() {
+            let number = self.start + i;
+            out.push(
+                format!("{:>width$} | {}", number, text, width = width)
+                    .trim_end()
+                    .into(),
+            );
+            if number == self.line {
+                if let Some(column) = self.column {
+                    // Keep tabs, so the caret lines up however the line is displayed
+                    let pad: String = text
+                        .chars()
+                        .take(column.saturating_sub(1))
+                        .map(|c| if c == '\t' { '\t' } else { ' ' })
+                        .collect();
+                    out.push(format!("{} | {}^", gutter, pad));
+                }
+            }
+        }
+        out.join("\n")
+    }
+}
+
+/// Loads snippets for the diagnostics in a failed check's output. Diagnostics whose source line
+/// the tool already quoted, as rustc does, are skipped, as are repeats of the same location.
+pub fn snippets(config: &Config, check: &Check, output: &str) -> Vec<Snippet> {
+    let dirs = [check.dir(config), config.project_root()];
+    let mut ret: Vec<Snippet> = vec![];
+    for d in sarif::parse(check, output) {
+        if ret.len() == MAX_SNIPPETS {
+            break;
+        }
+        let Some(snippet) = Snippet::load(config, &dirs, &d) else {
+            continue;
+        };
+        let quoted = !snippet.target().trim().is_empty()
+            && output.contains(&format!("| {}", snippet.target()));
+        let repeat = ret
+            .iter()
+            .any(|s| s.path == snippet.path && s.line == snippet.line);
+        if !quoted && !repeat {
+            ret.push(snippet);
+        }
+    }
+    ret
+}
+
+/// Renders the snippets for a failed check's output, or returns None if there are none.
+pub fn render(config: &Config, check: &Check, output: &str) -> Option<String> {
+    let snippets = snippets(config, check, output);
+    (!snippets.is_empty()).then(|| {
+        snippets
+            .iter()
+            .map(Snippet::render)
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    })
+}
+
+/// Renders the snippets for a failed check by name, if the check exists and has any.
+pub fn render_for(config: &Config, check: &str, output: &str) -> Option<String> {
+    render(config, &config.get_check(check)?, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{checks::CheckMode, testutils::test_project};
+
+    fn check(name: &str, command: &str) -> Check {
+        Check {
+            name: name.into(),
+            command: command.into(),
+            globs: vec![],
+            default_off: false,
+            fail_on_stderr: false,
+            mode: CheckMode::Validate,
+            focus: None,
+            cwd: None,
+            fix: None,
+        }
+    }
+
+    #[test]
+    fn test_snippets() {
+        let p = test_project();
+        p.write("app.py", "import os\n\ndef f():\n\treturn \"a\"\n\nf()\n");
+        let mypy = check("mypy", "mypy .");
+        let output = "stdout:\napp.py:4:2: error: Incompatible return value\n\
+            app.py:4: note: Same line\nmissing.py:1: error: Gone\n";
+        let rendered = render(&p.config, &mypy, output).unwrap();
+        assert_eq!(
+            rendered,
+            "app.py:4:2: Incompatible return value\n  |\n2 |\n3 | def f():\n4 | \treturn \"a\"\n  | \t^\n5 |\n6 | f()"
+        );
+
+        // Source the tool already quoted isn't repeated
+        let quoted = format!("{}\n4 | \treturn \"a\"\n", output);
+        assert!(render(&p.config, &mypy, &quoted).is_none());
+
+        // Files outside the project are never shown
+        let outside = tempfile::tempdir().unwrap();
+        let secret = outside.path().join("secret.py");
+        fs::write(&secret, "password = 1\n").unwrap();
+        let escapes = format!(
+            "{}:1: error: Absolute\n../{}/secret.py:1: error: Relative\n",
+            secret.display(),
+            outside.path().file_name().unwrap().to_string_lossy()
+        );
+        assert!(render(&p.config, &mypy, &escapes).is_none());
+    }
+}
@@ -0,0 +1,117 @@
+//! Regression testing of dialect parsers against a corpus of saved model responses. A corpus is a
+//! directory of raw responses, one per file, searched recursively. Each response is expected to
+//! parse, unless its file name contains `.fail.`, like `unclosed-write.fail.txt`, in which case
+//! it's expected to be rejected. Responses that tripped up a parser can be added as fixtures
+//! without writing any Rust. The corpus for the tags dialect lives in `src/dialect/corpus`.
+use std::path::{Path, PathBuf};
+
+use fs_err as fs;
+
+use super::DialectProvider;
+use crate::error::Result;
+
+/// The marker in a fixture's file name that says it's expected to fail to parse.
+const FAIL_MARKER: &str = ".fail.";
+
+/// The result of parsing one fixture in a corpus.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+pub struct FixtureResult {
+    /// The fixture's path, relative to the corpus directory.
+    pub path: PathBuf,
+    /// True if the fixture is expected to fail to parse.
+    pub expect_failure: bool,
+    /// The parse error, if parsing failed.
+    pub error: Option<String>,
+    /// The 1-based line the parse failed at, if known.
+    pub line: Option<usize>,
+    /// The number of changes parsed.
+    pub changes: usize,
+    /// The number of operations parsed.
+    pub operations: usize,
+}
+
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+impl FixtureResult {
+    /// True if the fixture parsed, or failed to, as expected.
+    pub fn passed(&self) -> bool {
+        self.error.is_some() == self.expect_failure
+    }
+}
+
+/// Lists the fixtures in a corpus directory, recursively, sorted by path. Hidden files are
+/// skipped.
+fn fixtures(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut ret = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path
+            .file_name()
+            .is_some_and(|n| n.to_string_lossy().starts_with('.'))
+        {
+            continue;
+        }
+        if path.is_dir() {
+            ret.extend(fixtures(&path)?);
+        } else {
+            ret.push(path);
+        }
+    }
+    ret.sort();
+    Ok(ret)
+}
+
+/// Parses every fixture in a corpus directory with a dialect, and reports whether each passed.
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+pub fn parse_corpus<D: DialectProvider>(dialect: &D, dir: &Path) -> Result<Vec<FixtureResult>> {
+    let mut results = vec![];
+    for path in fixtures(dir)? {
+        let text = fs::read_to_string(&path)?;
+        let relative = path.strip_prefix(dir).unwrap_or(&path).to_path_buf();
+        let expect_failure = relative
+            .file_name()
+            .is_some_and(|n| n.to_string_lossy().contains(FAIL_MARKER));
+        let result = match dialect.parse_located(&text) {
+            Ok(resp) => FixtureResult {
+                path: relative,
+                expect_failure,
+                error: None,
+                line: None,
+                changes: resp.patch.map_or(0, |p| p.changes.len()),
+                operations: resp.operations.len(),
+            },
+            Err(failure) => FixtureResult {
+                path: relative,
+                expect_failure,
+                error: Some(failure.error.to_string()),
+                line: failure.line(&text),
+                changes: 0,
+                operations: 0,
+            },
+        };
+        results.push(result);
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Tags;
+
+    #[test]
+    fn test_bundled_corpus() -> Result<()> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/dialect/corpus");
+        let results = parse_corpus(&Tags::default(), &dir)?;
+        assert!(results.len() >= 5);
+        for result in &results {
+            assert!(result.passed(), "{:?}", result);
+        }
+        let write = results
+            .iter()
+            .find(|r| r.path == Path::new("write-with-comment.txt"))
+            .unwrap();
+        assert_eq!(write.changes, 1);
+        Ok(())
+    }
+}
@@ -4,20 +4,151 @@ use enum_dispatch::enum_dispatch;
 #[cfg(test)]
 mod tags_test;
 
+mod corpus;
 mod dummy_dialect;
+mod outline;
 mod tags;
 mod xmlish;
 
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
 use crate::{
-    config::Config,
+    config::{Config, PromptOrder},
+    context::{ContextItem, Priority},
     error::{Result, TenxError},
-    model::Chat,
+    model::{estimate_tokens, Chat},
     session::{ModelResponse, Session},
 };
 
+pub use corpus::*;
 pub use dummy_dialect::*;
 pub use tags::*;
 
+pub(crate) use outline::outlined;
+
+/// A failure to parse a model response, with the location of the failure if known.
+#[derive(Debug, Clone)]
+pub struct ParseFailure {
+    /// The byte offset in the response at which parsing failed.
+    pub offset: Option<usize>,
+    pub error: TenxError,
+}
+
+impl ParseFailure {
+    /// The 1-based line number of the failure within the response text, if known.
+    pub fn line(&self, txt: &str) -> Option<usize> {
+        self.offset
+            .map(|offset| txt[..offset.min(txt.len())].matches('\n').count() + 1)
+    }
+}
+
+/// Returns the byte offset of the start of the line with the given 0-based index.
+pub(crate) fn line_offset(txt: &str, line_idx: usize) -> usize {
+    txt.split_inclusive('\n').take(line_idx).map(str::len).sum()
+}
+
+/// Tokens reserved for the model's response when fitting a prompt into the context window.
+pub(crate) const RESPONSE_RESERVE_TOKENS: usize = 4096;
+
+/// Context items with less room than this aren't worth truncating, and are dropped instead.
+const MIN_TRUNCATED_TOKENS: usize = 256;
+
+/// The marker appended to truncated context items.
+const TRUNCATION_MARKER: &str = "\n[truncated to fit the context window]";
+
+/// A context item that was truncated or dropped to fit the model's context window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextCut {
+    /// The source of the context item.
+    pub source: String,
+    /// The estimated size of the item in tokens.
+    pub tokens: usize,
+    /// The estimated number of tokens kept, or 0 if the item was dropped.
+    pub kept: usize,
+}
+
+/// Fits context items into a budget of estimated tokens. Items are given in the order they were
+/// added, each with the priority of the context it came from. Pinned items are always kept in
+/// full. The rest are kept highest priority first, and the most recently added first within a
+/// priority. An item that doesn't fit is truncated to the remaining room, or dropped if there's
+/// too little room left to be useful. Returns the kept items in their original order, and the
+/// cuts that were made.
+pub fn fit_context(
+    items: Vec<(Priority, ContextItem)>,
+    budget: usize,
+) -> (Vec<ContextItem>, Vec<ContextCut>) {
+    let mut remaining = budget;
+    let mut kept = vec![];
+    let mut cuts = vec![];
+    let mut items: Vec<_> = items.into_iter().enumerate().collect();
+    items.sort_by(|(ai, (ap, _)), (bi, (bp, _))| (bp, bi).cmp(&(ap, ai)));
+    for (idx, (priority, mut item)) in items {
+        let tokens = estimate_tokens(&item.body);
+        if priority == Priority::Pinned {
+            remaining = remaining.saturating_sub(tokens);
+            kept.push((idx, item));
+        } else if tokens <= remaining {
+            remaining -= tokens;
+            kept.push((idx, item));
+        } else if remaining >= MIN_TRUNCATED_TOKENS {
+            let keep = remaining - estimate_tokens(TRUNCATION_MARKER);
+            item.body = item.body.chars().take(keep * 4).collect::<String>() + TRUNCATION_MARKER;
+            cuts.push(ContextCut {
+                source: item.source.clone(),
+                tokens,
+                kept: keep,
+            });
+            remaining = 0;
+            kept.push((idx, item));
+        } else {
+            cuts.push(ContextCut {
+                source: item.source.clone(),
+                tokens,
+                kept: 0,
+            });
+        }
+    }
+    kept.sort_by_key(|(idx, _)| *idx);
+    (kept.into_iter().map(|(_, item)| item).collect(), cuts)
+}
+
+/// Sorts context items into the configured prompt order. The sort is stable, so items that
+/// compare equal keep their insertion order.
+pub fn order_context_items(order: PromptOrder, items: &mut [ContextItem]) {
+    match order {
+        PromptOrder::Insertion => {}
+        PromptOrder::Path => items.sort_by(|a, b| (&a.source, &a.ty).cmp(&(&b.source, &b.ty))),
+        PromptOrder::Size => items.sort_by(|a, b| {
+            (a.body.len(), &a.source, &a.ty).cmp(&(b.body.len(), &b.source, &b.ty))
+        }),
+    }
+}
+
+/// Sorts editable files, given as (path, contents) pairs, into the configured prompt order.
+pub fn order_editables(order: PromptOrder, files: &mut [(PathBuf, String)]) {
+    match order {
+        PromptOrder::Insertion => {}
+        PromptOrder::Path => files.sort_by(|a, b| a.0.cmp(&b.0)),
+        PromptOrder::Size => files.sort_by(|a, b| (a.1.len(), &a.0).cmp(&(b.1.len(), &b.0))),
+    }
+}
+
+/// What a dialect accepts from the model under a given configuration, so tooling and tests can
+/// check the active contract.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// The name of the dialect.
+    pub dialect: String,
+    /// The kinds of change to files the dialect parses, like "write" or "replace_fuzzy".
+    pub changes: Vec<String>,
+    /// The operations the dialect parses, like "done" or "ask_user".
+    pub operations: Vec<String>,
+    /// Whether large editable files are sent as outlines.
+    pub outlines: bool,
+}
+
 /// A dialect encapsulates a particular style of interaction with a model. It defines the system
 /// prompt, how to render a user's prompt, and how to parse a model's response.
 /// A trait defining the behavior of a dialect, including rendering and parsing capabilities.
@@ -32,17 +163,53 @@ pub trait DialectProvider {
     /// Parse a model's response into concrete operations
     fn parse(&self, txt: &str) -> Result<ModelResponse>;
 
+    /// Parse a model's response, locating the point of failure in the response if possible.
+    /// Dialects that can't locate errors return failures without an offset.
+    fn parse_located(&self, txt: &str) -> std::result::Result<ModelResponse, ParseFailure> {
+        self.parse(txt).map_err(|error| ParseFailure {
+            offset: None,
+            error,
+        })
+    }
+
+    /// Builds the chat for the latest step of an action, returning the context items that were
+    /// cut to fit the model's context window.
     fn build_chat(
         &self,
         _config: &Config,
         _session: &Session,
         _action_offset: usize,
         _chat: &mut Box<dyn Chat>,
-    ) -> Result<()> {
+    ) -> Result<Vec<ContextCut>> {
         Err(TenxError::Internal(
             "build_chat not implemented".to_string(),
         ))
     }
+
+    /// Describes the changes and operations this dialect accepts with the given configuration.
+    fn capabilities(&self, _config: &Config) -> Capabilities {
+        Capabilities {
+            dialect: self.name().to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Renders a file exactly as `build_chat` would send it for the latest step of an action:
+    /// framed as an editable file or a context item, with outlining, truncation and scrubbing
+    /// applied. A file can appear more than once, say as both an editable and a snippet. Returns
+    /// an empty list if the file isn't in the prompt. Without an action, only the session context
+    /// is considered. To see a file as the next step will send it, use `Tenx::render_file`.
+    fn render_file(
+        &self,
+        _config: &Config,
+        _session: &Session,
+        _action_offset: Option<usize>,
+        _path: &Path,
+    ) -> Result<Vec<String>> {
+        Err(TenxError::Internal(
+            "render_file not implemented".to_string(),
+        ))
+    }
 }
 
 #[enum_dispatch]
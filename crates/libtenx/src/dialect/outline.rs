@@ -0,0 +1,317 @@
+//! Outlines of large editable files. Only the regions likely to matter for a step are shown in
+//! full - lines named in check failures, definitions of identifiers mentioned in the prompt, and
+//! regions the model asked to expand. Everything else is elided, apart from declaration lines that
+//! show the file's structure.
+use std::{collections::BTreeSet, path::Path};
+
+use diffy::{DiffOptions, Line};
+use regex::Regex;
+use state::files::slash_path;
+
+use crate::{config::Config, error::TenxError, model::estimate_tokens, session::Session};
+
+/// Lines of surrounding context kept around each relevant line.
+const CONTEXT_LINES: usize = 3;
+
+/// Runs of hidden lines shorter than this are shown, since a marker would save nothing.
+const MIN_ELIDED: usize = 3;
+
+/// The longest block expanded around a definition mentioned in the prompt.
+const MAX_BLOCK_LINES: usize = 200;
+
+/// Line prefixes that start a declaration in common languages. Declarations are always shown, so
+/// the model can see what's in the elided regions.
+const DECLARATIONS: &[&str] = &[
+    "fn ",
+    "pub ",
+    "pub(",
+    "async ",
+    "impl ",
+    "impl<",
+    "trait ",
+    "struct ",
+    "enum ",
+    "mod ",
+    "def ",
+    "class ",
+    "function ",
+    "export ",
+    "interface ",
+    "func ",
+    "type ",
+];
+
+/// An inclusive range of 1-based line numbers.
+pub type LineRange = (usize, usize);
+
+fn indent(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+fn is_declaration(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    DECLARATIONS.iter().any(|d| trimmed.starts_with(d))
+}
+
+/// Top-level lines and declarations are always shown.
+fn is_structural(line: &str) -> bool {
+    !line.trim().is_empty() && (indent(line) == 0 || is_declaration(line))
+}
+
+/// The marker that replaces a run of elided lines.
+pub fn elision_marker(start: usize, end: usize) -> String {
+    format!("... [lines {}-{} elided]", start, end)
+}
+
+/// Renders `contents` showing only the lines in `keep` and the structural lines, replacing each
+/// run of other lines with a marker naming the elided line range.
+pub fn outline(contents: &str, keep: &[LineRange]) -> String {
+    let lines: Vec<&str> = contents.lines().collect();
+    let shown: Vec<bool> = lines
+        .iter()
+        .enumerate()
+        .map(|(i, l)| is_structural(l) || keep.iter().any(|(s, e)| (*s..=*e).contains(&(i + 1))))
+        .collect();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let start = i;
+        while i < lines.len() && !shown[i] {
+            i += 1;
+        }
+        if i - start >= MIN_ELIDED {
+            out.push_str(&elision_marker(start + 1, i));
+            out.push('\n');
+        } else {
+            for line in &lines[start..i] {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+        if i < lines.len() {
+            out.push_str(lines[i]);
+            out.push('\n');
+            i += 1;
+        }
+    }
+    out
+}
+
+/// Returns the outline of an editable file as it's sent to the model for a step, or None if the
+/// file is sent in full, because it's under the configured size or every line is shown anyway.
+pub(crate) fn outlined(
+    config: &Config,
+    session: &Session,
+    action_offset: usize,
+    step_offset: usize,
+    path: &Path,
+    contents: &str,
+) -> Option<String> {
+    let limit = config.dialect.outline_tokens;
+    if limit == 0 || estimate_tokens(contents) <= limit {
+        return None;
+    }
+    let keep = relevant_ranges(session, action_offset, step_offset, path, contents);
+    Some(outline(contents, &keep)).filter(|o| o != contents)
+}
+
+/// Maps each 1-based line of `old` to its line in `new`, or None if the line was removed. Index 0
+/// is unused.
+fn line_map(old: &str, new: &str) -> Vec<Option<usize>> {
+    let lines = old.lines().count();
+    let mut map = vec![None; lines + 1];
+    let patch = DiffOptions::new().create_patch(old, new);
+    let (mut old_line, mut new_line) = (1, 1);
+    for hunk in patch.hunks() {
+        // Lines between hunks are unchanged, and only shift
+        while old_line < hunk.old_range().start() {
+            map[old_line] = Some(new_line);
+            old_line += 1;
+            new_line += 1;
+        }
+        for line in hunk.lines() {
+            match line {
+                Line::Context(_) => {
+                    map[old_line] = Some(new_line);
+                    old_line += 1;
+                    new_line += 1;
+                }
+                Line::Delete(_) => old_line += 1,
+                Line::Insert(_) => new_line += 1,
+            }
+        }
+    }
+    while old_line <= lines {
+        map[old_line] = Some(new_line);
+        old_line += 1;
+        new_line += 1;
+    }
+    map
+}
+
+/// Moves a range of lines in `old` to where its surviving lines are in `new`. Returns None if
+/// none of them survived.
+fn remap(map: &[Option<usize>], (start, end): LineRange) -> Option<LineRange> {
+    let mut lines = (start..=end.min(map.len().saturating_sub(1))).filter_map(|i| map[i]);
+    let first = lines.next()?;
+    Some((first, lines.next_back().unwrap_or(first)))
+}
+
+/// Returns the range of the block starting at the 0-based line `idx`: every following line that is
+/// blank or indented further, plus a closing bracket at the same indentation.
+fn block(lines: &[&str], idx: usize) -> LineRange {
+    let depth = indent(lines[idx]);
+    let mut end = idx;
+    for (i, line) in lines.iter().enumerate().skip(idx + 1) {
+        if i - idx >= MAX_BLOCK_LINES {
+            break;
+        }
+        if line.trim().is_empty() || indent(line) > depth {
+            end = i;
+            continue;
+        }
+        if line.trim_start().starts_with(['}', ')', ']']) {
+            end = i;
+        }
+        break;
+    }
+    (idx + 1, end + 1)
+}
+
+/// Returns identifiers mentioned in a prompt: words in backticks, and words that look like code
+/// because they contain an underscore or an interior capital.
+fn prompt_identifiers(prompt: &str) -> BTreeSet<String> {
+    let word = Regex::new(r"[A-Za-z_][A-Za-z0-9_]*").unwrap();
+    let quoted = Regex::new(r"`([^`]+)`").unwrap();
+    let mut idents = BTreeSet::new();
+    for cap in quoted.captures_iter(prompt) {
+        idents.extend(word.find_iter(&cap[1]).map(|m| m.as_str().to_string()));
+    }
+    for m in word.find_iter(prompt) {
+        let w = m.as_str();
+        if w.contains('_') || w.chars().skip(1).any(|c| c.is_ascii_uppercase()) {
+            idents.insert(w.to_string());
+        }
+    }
+    idents.retain(|w| w.len() >= 3);
+    idents
+}
+
+/// Returns the 1-based line numbers of `path` named in check output, like `src/lib.rs:12:5`.
+fn diagnostic_lines(path: &Path, output: &str) -> Vec<usize> {
+    let re = Regex::new(&format!(r"{}:(\d+)", regex::escape(&slash_path(path)))).unwrap();
+    re.captures_iter(output)
+        .filter_map(|c| c[1].parse().ok())
+        .collect()
+}
+
+/// Returns the line ranges of an editable file to show in full when building the given step:
+/// lines named in the previous step's check failure, lines mentioning identifiers from the
+/// action's prompts (with whole blocks for definitions), and regions the model asked to expand
+/// in earlier steps. Expanded regions refer to the file as it was shown in the step that asked
+/// for them, so they're moved to match any edits made since.
+pub fn relevant_ranges(
+    session: &Session,
+    action_offset: usize,
+    step_offset: usize,
+    path: &Path,
+    contents: &str,
+) -> Vec<LineRange> {
+    let lines: Vec<&str> = contents.lines().collect();
+    let around = |n: usize| (n.saturating_sub(CONTEXT_LINES).max(1), n + CONTEXT_LINES);
+    let steps = &session.actions[action_offset].steps[..=step_offset];
+    let mut ranges = vec![];
+
+    if let Some(TenxError::Check { model, .. }) = step_offset
+        .checked_sub(1)
+        .and_then(|i| steps[i].outcome.err.as_ref())
+    {
+        ranges.extend(diagnostic_lines(path, model).into_iter().map(around));
+    }
+
+    let mut idents = BTreeSet::new();
+    for step in steps {
+        idents.extend(prompt_identifiers(&step.request.raw_prompt));
+    }
+    for ident in idents {
+        let re = Regex::new(&format!(r"\b{}\b", regex::escape(&ident))).unwrap();
+        for (i, line) in lines.iter().enumerate() {
+            if re.is_match(line) {
+                ranges.push(if is_declaration(line) {
+                    block(&lines, i)
+                } else {
+                    around(i + 1)
+                });
+            }
+        }
+    }
+
+    let state = &session.actions[action_offset].state;
+    for step in &steps[..step_offset] {
+        let Some(resp) = &step.response.model_response else {
+            continue;
+        };
+        let expansions = resp
+            .expansions()
+            .into_iter()
+            .filter(|(p, _, _)| *p == path)
+            .map(|(_, start, end)| (start, end));
+        match state.content_before(path, step.outcome.rollback_id) {
+            None => ranges.extend(expansions),
+            Some(Some(shown)) => {
+                let map = line_map(&shown, contents);
+                ranges.extend(expansions.filter_map(|r| remap(&map, r)));
+            }
+            // The file didn't exist when the step was shown
+            Some(None) => {}
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outline() {
+        let src = "use std::fs;\n\nfn one() {\n    let a = 1;\n    let b = 2;\n    let c = 3;\n    a + b + c\n}\n\nfn two() {\n    2\n}\n";
+        assert_eq!(
+            outline(src, &[]),
+            "use std::fs;\n\nfn one() {\n... [lines 4-7 elided]\n}\n\nfn two() {\n    2\n}\n"
+        );
+        assert_eq!(outline(src, &[(1, 12)]), src);
+
+        let lines: Vec<&str> = src.lines().collect();
+        assert_eq!(block(&lines, 2), (3, 8));
+        assert_eq!(block(&lines, 9), (10, 12));
+    }
+
+    #[test]
+    fn test_remap() {
+        let old = "a\nb\nc\nd\ne\n";
+        let new = "x\ny\na\nb\nd\ne\n";
+        let map = line_map(old, new);
+        assert_eq!(remap(&map, (1, 2)), Some((3, 4)));
+        // A removed line drops out of the range
+        assert_eq!(remap(&map, (2, 4)), Some((4, 5)));
+        assert_eq!(remap(&map, (3, 3)), None);
+        assert_eq!(remap(&map, (5, 9)), Some((6, 6)));
+    }
+
+    #[test]
+    fn test_prompt_identifiers() {
+        let idents = prompt_identifiers("Fix `parse` in apply_patch so the ConfigWatcher works");
+        assert_eq!(
+            idents.into_iter().collect::<Vec<_>>(),
+            vec!["ConfigWatcher", "apply_patch", "parse"]
+        );
+        assert_eq!(
+            diagnostic_lines(
+                Path::new("src/lib.rs"),
+                "error\n --> src/lib.rs:12:5\nsrc/lib.rs:40: warning"
+            ),
+            vec![12, 40]
+        );
+    }
+}
@@ -1,16 +1,32 @@
 //! Defines an interaction style where files are sent to the model in XML-like tags, and model
 //! responses are parsed from similar tags.
 
-use super::{xmlish, DialectProvider};
+use std::{
+    cell::Cell,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use super::{
+    fit_context, line_offset, order_context_items, order_editables, outline, xmlish, Capabilities,
+    ContextCut, DialectProvider, ParseFailure, RESPONSE_RESERVE_TOKENS,
+};
 use crate::{
     config::Config,
-    context::ContextProvider,
+    context::{ContextItem, ContextProvider},
     error::{Result, TenxError},
-    model::Chat,
-    session::{ModelResponse, Session},
+    events::EventSender,
+    model::{estimate_tokens, Chat, TextChat},
+    scrub::ScrubbedChat,
+    session::{ModelResponse, Operation, Session, Summary},
 };
+use async_trait::async_trait;
 use fs_err as fs;
-use state::{Change, Patch, ReplaceFuzzy, WriteFile};
+use state::{
+    encoding::Encoding,
+    files::{from_slash, slash_path},
+    Change, MoveFile, Patch, ReplaceFuzzy, WriteFile,
+};
 
 const SYSTEM: &str = include_str!("./tags-system.txt");
 const REPLACE: &str = include_str!("./tags-replace.txt");
@@ -20,6 +36,37 @@ const EDIT: &str = include_str!("./tags-edit.txt");
 const CONTEXT_LEADIN: &str = "Here is some immutable context that you may not edit.";
 const EDITABLE_LEADIN: &str = "Here are the editable files.";
 const ACK: &str = "Got it.";
+const SYSTEM_START: &str = "=== SYSTEM INSTRUCTIONS - follow these throughout the conversation ===";
+const SYSTEM_END: &str = "=== END SYSTEM INSTRUCTIONS ===";
+const GENERATED_NOTICE: &str = "\nFiles matching these patterns are generated. Never edit them \
+                                directly - change the sources they are generated from instead:\n";
+
+/// What a tag in a model response gives the model the ability to do.
+enum Provides {
+    /// A kind of change to files, named as in `Capabilities::changes`.
+    Change(&'static str),
+    /// An operation, named as in `Capabilities::operations`.
+    Operation(&'static str),
+    /// Neither, like a comment.
+    Nothing,
+}
+
+/// The tags the parser accepts. Tags that aren't listed are skipped, and the dialect's
+/// capabilities are read from here, so the two can't drift apart.
+const TAGS: &[(&str, Provides)] = &[
+    ("write_file", Provides::Change("write")),
+    ("replace", Provides::Change("replace_fuzzy")),
+    ("edit", Provides::Change("view")),
+    ("move", Provides::Change("move")),
+    ("comment", Provides::Nothing),
+    ("summary", Provides::Nothing),
+    ("done", Provides::Operation("done")),
+    ("request_context", Provides::Operation("request_context")),
+    ("ask_user", Provides::Operation("ask_user")),
+    ("abort", Provides::Operation("abort")),
+    ("expand", Provides::Operation("expand")),
+    ("search", Provides::Operation("search")),
+];
 
 /// Tenx's primary code generation dialect, which uses XML-ish tags as the basic communication format with models.
 #[derive(Debug, Default, PartialEq, Eq, Clone)]
@@ -30,6 +77,136 @@ impl Tags {
         Self {}
     }
 
+    /// Returns the context items to send, ordered and cut to fit the model's context window if
+    /// it's known, along with the cuts that were made. Editable files, the conversation and
+    /// pinned contexts are always sent in full.
+    fn context_plan(
+        &self,
+        config: &Config,
+        session: &Session,
+        action_offset: usize,
+    ) -> Result<(Vec<ContextItem>, Vec<ContextCut>)> {
+        let mut items = vec![];
+        for cspec in &session.contexts {
+            let priority = session.contexts.priority(cspec);
+            items.extend(
+                cspec
+                    .context_items(config, session)?
+                    .into_iter()
+                    .map(|item| (priority, item)),
+            );
+        }
+        let (mut items, cuts) = match config.context_window() {
+            Some(window) => {
+                let mut chat: Box<dyn Chat> = Box::new(TextChat::default());
+                self.build_chat_with(config, session, action_offset, &mut chat, vec![])?;
+                let fixed = estimate_tokens(&chat.render()?) + RESPONSE_RESERVE_TOKENS;
+                if fixed > window {
+                    return Err(TenxError::Model(format!(
+                        "The prompt needs ~{} tokens without context, which exceeds the model's \
+                         context window of {} tokens",
+                        fixed, window
+                    )));
+                }
+                fit_context(items, window - fixed)
+            }
+            None => (items.into_iter().map(|(_, item)| item).collect(), vec![]),
+        };
+        order_context_items(config.dialect.order, &mut items);
+        Ok((items, cuts))
+    }
+
+    /// Builds a chat with the given context items, which should already be ordered.
+    fn build_chat_with(
+        &self,
+        config: &Config,
+        session: &Session,
+        action_offset: usize,
+        chat: &mut Box<dyn Chat>,
+        items: Vec<ContextItem>,
+    ) -> Result<()> {
+        let mut system = session.system_prompt_for(self);
+        if !config.project.generated.is_empty() {
+            system.push_str(GENERATED_NOTICE);
+            for pattern in &config.project.generated {
+                system.push_str(&format!("- {}\n", pattern));
+            }
+        }
+        // For models without system prompts, the instructions lead the first user message
+        let mut folded = None;
+        if config.fold_system_prompt() {
+            folded = Some(system);
+        } else {
+            chat.add_system_prompt(&system)?;
+        }
+        let mut add_user_message = |chat: &mut Box<dyn Chat>, text: &str| match folded.take() {
+            Some(system) => chat.add_user_message(&fold_system(&system, text)),
+            None => chat.add_user_message(text),
+        };
+
+        let order = config.dialect.order;
+        if !items.is_empty() {
+            add_user_message(chat, CONTEXT_LEADIN)?;
+            for ctx in items {
+                chat.add_context(&ctx.source, &render_context(&ctx))?;
+            }
+            chat.add_agent_message(ACK)?;
+        }
+
+        let steps = &session.actions[action_offset].steps;
+        for (i, step) in steps.iter().enumerate() {
+            let mut editables = session.editables_for_step_state(action_offset, i)?;
+            // Editables the model asked to expand in the previous step are shown again, with the
+            // expanded regions in full
+            if let Some(prev) = i.checked_sub(1) {
+                if let Some(resp) = &steps[prev].response.model_response {
+                    for (path, _, _) in resp.expansions() {
+                        let shown = (0..i)
+                            .map(|s| session.editables_for_step_state(action_offset, s))
+                            .collect::<Result<Vec<_>>>()?
+                            .concat();
+                        if shown.iter().any(|p| p == path) && !editables.iter().any(|p| p == path) {
+                            editables.push(path.to_path_buf());
+                        }
+                    }
+                }
+            }
+            if !editables.is_empty() {
+                add_user_message(chat, EDITABLE_LEADIN)?;
+                // Files moved away by a later step are no longer there to show
+                let mut files = editables
+                    .into_iter()
+                    .filter(|path| config.abspath(path).is_ok_and(|p| p.exists()))
+                    .map(|path| {
+                        let contents = fs::read_to_string(config.abspath(&path)?)?;
+                        // Models work with LF line endings, and patches convert back to the
+                        // file's own
+                        Ok((path, Encoding::detect(&contents).decode(&contents)))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                order_editables(order, &mut files);
+                for (path, contents) in files {
+                    let txt = render_editable(config, session, action_offset, i, &path, &contents);
+                    chat.add_editable(&slash_path(&path), &txt)?;
+                }
+                chat.add_agent_message(ACK)?;
+            }
+
+            // Add the step request
+            add_user_message(chat, &self.render_step_request(session, action_offset, i)?)?;
+
+            // Add the step response if available
+            if step.response.model_response.is_some() {
+                chat.add_agent_message(&self.render_step_response(session, action_offset, i)?)?;
+            } else if i != session.actions[action_offset].steps.len() - 1 {
+                // We have no model response, but we're not the last step
+                chat.add_agent_message("omitted due to error")?;
+            }
+        }
+
+        Ok(())
+    }
+
     fn render_step_request(
         &self,
         session: &Session,
@@ -38,7 +215,10 @@ impl Tags {
     ) -> Result<String> {
         let step = &session.actions[action_offset].steps[step_offset];
         let mut rendered = String::new();
-        rendered.push_str(&format!("\n<prompt>\n{}\n</prompt>\n\n", &step.raw_prompt));
+        rendered.push_str(&format!(
+            "\n<prompt>\n{}\n</prompt>\n\n",
+            &step.request.raw_prompt
+        ));
         Ok(rendered)
     }
 
@@ -49,31 +229,76 @@ impl Tags {
         step_offset: usize,
     ) -> Result<String> {
         let step = &session.actions[action_offset].steps[step_offset];
-        if let Some(resp) = &step.model_response {
+        if let Some(resp) = &step.response.model_response {
             let mut rendered = String::new();
             if let Some(comment) = &resp.comment {
                 rendered.push_str(&format!("<comment>\n{}\n</comment>\n\n", comment));
             }
+            if let Some(summary) = &resp.summary {
+                let scope = summary
+                    .scope
+                    .as_ref()
+                    .map(|s| format!(" scope=\"{}\"", s))
+                    .unwrap_or_default();
+                let body = summary
+                    .body
+                    .as_ref()
+                    .map(|b| format!("\n\n{}", b))
+                    .unwrap_or_default();
+                rendered.push_str(&format!(
+                    "<summary type=\"{}\"{}>\n{}{}\n</summary>\n\n",
+                    summary.kind, scope, summary.description, body
+                ));
+            }
+            for op in &resp.operations {
+                rendered.push_str(&match op {
+                    Operation::Done => "<done>\n</done>\n\n".to_string(),
+                    Operation::RequestContext(spec) => {
+                        format!("<request_context>\n{}\n</request_context>\n\n", spec)
+                    }
+                    Operation::AskUser(question) => {
+                        format!("<ask_user>\n{}\n</ask_user>\n\n", question)
+                    }
+                    Operation::Abort(reason) => format!("<abort>\n{}\n</abort>\n\n", reason),
+                    Operation::Expand { path, start, end } => format!(
+                        "<expand path=\"{}\">\n{}-{}\n</expand>\n\n",
+                        slash_path(path),
+                        start,
+                        end
+                    ),
+                    Operation::Search { pattern, literal } => {
+                        let attr = if *literal { " literal=\"true\"" } else { "" };
+                        format!("<search{}>\n{}\n</search>\n\n", attr, pattern)
+                    }
+                });
+            }
             if let Some(patch) = &resp.patch {
                 for change in &patch.changes {
                     match change {
                         Change::Write(write_file) => {
                             rendered.push_str(&format!(
                                 "<write_file path=\"{}\">\n{}\n</write_file>\n\n",
-                                write_file.path.display(),
+                                slash_path(&write_file.path),
                                 write_file.content
                             ));
                         }
                         Change::ReplaceFuzzy(replace) => {
                             rendered.push_str(&format!(
                             "<replace path=\"{}\">\n<old>\n{}\n</old>\n<new>\n{}\n</new>\n</replace>\n\n",
-                            replace.path.display(),
+                            slash_path(&replace.path),
                             replace.old,
                             replace.new
                         ));
                         }
                         Change::View(v) => {
-                            rendered.push_str(&format!("<edit>\n{}\n</edit>\n", v.display()));
+                            rendered.push_str(&format!("<edit>\n{}\n</edit>\n", slash_path(v)));
+                        }
+                        Change::Move(m) => {
+                            rendered.push_str(&format!(
+                                "<move from=\"{}\" to=\"{}\">\n</move>\n\n",
+                                slash_path(&m.from),
+                                slash_path(&m.to)
+                            ));
                         }
                         v => {
                             panic!("unsupported change type: {:?}", v);
@@ -88,6 +313,125 @@ impl Tags {
     }
 }
 
+/// Prefixes a user message with the system instructions, for models without system prompts.
+fn fold_system(system: &str, text: &str) -> String {
+    format!(
+        "{}\n{}\n{}\n\n{}",
+        SYSTEM_START,
+        system.trim_end(),
+        SYSTEM_END,
+        text
+    )
+}
+
+/// Renders a context item as it's sent to the model.
+fn render_context(ctx: &ContextItem) -> String {
+    format!(
+        "<context name=\"{}\" type=\"{:?}\">\n{}\n</context>\n",
+        ctx.source, ctx.ty, ctx.body
+    )
+}
+
+/// Renders an editable file as it's sent to the model for a step, outlined if it's over the
+/// configured size.
+fn render_editable(
+    config: &Config,
+    session: &Session,
+    action_offset: usize,
+    step_offset: usize,
+    path: &Path,
+    contents: &str,
+) -> String {
+    if let Some(outline) =
+        outline::outlined(config, session, action_offset, step_offset, path, contents)
+    {
+        format!(
+            "<editable path=\"{}\" outline=\"true\">\n{}</editable>\n\n",
+            slash_path(path),
+            outline
+        )
+    } else {
+        format!(
+            "<editable path=\"{}\">\n{}</editable>\n\n",
+            slash_path(path),
+            contents
+        )
+    }
+}
+
+/// Parses a 1-based, inclusive line range like "120-180".
+fn parse_line_range(range: &str) -> Result<(usize, usize)> {
+    range
+        .split_once('-')
+        .and_then(|(s, e)| Some((s.trim().parse().ok()?, e.trim().parse().ok()?)))
+        .filter(|(s, e): &(usize, usize)| *s > 0 && s <= e)
+        .ok_or_else(|| TenxError::ResponseParse {
+            user: "Failed to parse model response".into(),
+            model: format!(
+                "Invalid line range in expand tag: '{}'. Use START-END, like 120-180.",
+                range
+            ),
+        })
+}
+
+/// The sections of a chat that show one file.
+#[derive(Default)]
+struct FileSections {
+    /// The file as an editable in the latest step, if it's there
+    editable: Option<String>,
+    /// Context items sourced from the file
+    contexts: Vec<String>,
+}
+
+/// A chat that keeps only the sections showing one file, for `render_file`.
+struct FileChat {
+    source: String,
+    found: Arc<Mutex<FileSections>>,
+}
+
+#[async_trait]
+impl Chat for FileChat {
+    fn add_system_prompt(&mut self, _prompt: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn add_user_message(&mut self, _text: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn add_agent_message(&mut self, _text: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn add_context(&mut self, name: &str, data: &str) -> Result<()> {
+        // Snippets and symbols are sourced like "path:line"
+        let matches = name
+            .strip_prefix(self.source.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(':'));
+        if matches {
+            let mut found = self.found.lock().expect("file chat lock poisoned");
+            found.contexts.push(data.to_string());
+        }
+        Ok(())
+    }
+
+    fn add_editable(&mut self, path: &str, data: &str) -> Result<()> {
+        // An editable can be shown in several steps, and the model works from the last showing
+        if path == self.source {
+            self.found.lock().expect("file chat lock poisoned").editable = Some(data.to_string());
+        }
+        Ok(())
+    }
+
+    async fn send(&mut self, _sender: Option<EventSender>) -> Result<ModelResponse> {
+        Err(TenxError::Internal("A file chat can't be sent".into()))
+    }
+
+    fn render(&self) -> Result<String> {
+        Err(TenxError::Internal("A file chat can't be rendered".into()))
+    }
+}
+
 impl DialectProvider for Tags {
     fn name(&self) -> &'static str {
         "tags"
@@ -100,58 +444,72 @@ impl DialectProvider for Tags {
         out
     }
 
+    fn capabilities(&self, config: &Config) -> Capabilities {
+        Capabilities {
+            dialect: self.name().to_string(),
+            changes: TAGS
+                .iter()
+                .filter_map(|(_, p)| match p {
+                    Provides::Change(name) => Some(name.to_string()),
+                    _ => None,
+                })
+                .collect(),
+            operations: TAGS
+                .iter()
+                .filter_map(|(_, p)| match p {
+                    Provides::Operation(name) => Some(name.to_string()),
+                    _ => None,
+                })
+                .collect(),
+            outlines: config.dialect.outline_tokens > 0,
+        }
+    }
+
     fn build_chat(
         &self,
         config: &Config,
         session: &Session,
         action_offset: usize,
         chat: &mut Box<dyn Chat>,
-    ) -> Result<()> {
-        chat.add_system_prompt(&self.system())?;
-
-        if !session.contexts.is_empty() {
-            chat.add_user_message(CONTEXT_LEADIN)?;
-            for cspec in &session.contexts {
-                for ctx in cspec.context_items(config, session)? {
-                    let txt = format!(
-                        "<context name=\"{}\" type=\"{:?}\">\n{}\n</context>\n",
-                        ctx.source, ctx.ty, ctx.body
-                    );
-                    chat.add_context(&ctx.source, &txt)?;
-                }
-            }
-            chat.add_agent_message(ACK)?;
-        }
+    ) -> Result<Vec<ContextCut>> {
+        let (items, cuts) = self.context_plan(config, session, action_offset)?;
+        self.build_chat_with(config, session, action_offset, chat, items)?;
+        Ok(cuts)
+    }
 
-        for (i, step) in session.actions[action_offset].steps.iter().enumerate() {
-            let editables = session.editables_for_step_state(action_offset, i)?;
-            if !editables.is_empty() {
-                chat.add_user_message(EDITABLE_LEADIN)?;
-                for path in editables {
-                    let contents = fs::read_to_string(config.abspath(&path)?)?;
-                    let txt = &format!(
-                        "<editable path=\"{}\">\n{}</editable>\n\n",
-                        path.display(),
-                        contents
-                    );
-                    chat.add_editable(&path.display().to_string(), txt)?;
-                }
-                chat.add_agent_message(ACK)?;
+    fn render_file(
+        &self,
+        config: &Config,
+        session: &Session,
+        action_offset: Option<usize>,
+        path: &Path,
+    ) -> Result<Vec<String>> {
+        let found = Arc::new(Mutex::new(FileSections::default()));
+        let chat: Box<dyn Chat> = Box::new(FileChat {
+            source: slash_path(path),
+            found: found.clone(),
+        });
+        // Rendered through the same scrubbing as a real request, so secrets show as they're sent
+        let (mut chat, _) = ScrubbedChat::wrap(chat, &config.scrub)?;
+        match action_offset {
+            Some(action_offset) => {
+                self.build_chat(config, session, action_offset, &mut chat)?;
             }
-
-            // Add the step request
-            chat.add_user_message(&self.render_step_request(session, action_offset, i)?)?;
-
-            // Add the step response if available
-            if step.model_response.is_some() {
-                chat.add_agent_message(&self.render_step_response(session, action_offset, i)?)?;
-            } else if i != session.actions[action_offset].steps.len() - 1 {
-                // We have no model response, but we're not the last step
-                chat.add_agent_message("omitted due to error")?;
+            None => {
+                for cspec in &session.contexts {
+                    for ctx in cspec.context_items(config, session)? {
+                        chat.add_context(&ctx.source, &render_context(&ctx))?;
+                    }
+                }
             }
         }
-
-        Ok(())
+        let found = found.lock().expect("file chat lock poisoned");
+        Ok(found
+            .editable
+            .iter()
+            .chain(&found.contexts)
+            .cloned()
+            .collect())
     }
 
     /// Parses a response string containing XML-like tags and returns a `Patch` struct.
@@ -178,12 +536,41 @@ impl DialectProvider for Tags {
     /// Whitespace is trimmed from the content of all tags. Any text outside of recognized tags is
     /// ignored.
     fn parse(&self, response: &str) -> Result<ModelResponse> {
+        self.parse_located(response).map_err(|e| e.error)
+    }
+
+    /// On failure, the error is located at the start of the line holding the opening tag of the
+    /// block that failed to parse.
+    fn parse_located(&self, response: &str) -> std::result::Result<ModelResponse, ParseFailure> {
+        let tag_line = Cell::new(0);
+        self.parse_tags(response, &tag_line)
+            .map_err(|error| ParseFailure {
+                offset: Some(line_offset(response, tag_line.get())),
+                error,
+            })
+    }
+}
+
+impl Tags {
+    /// Parse tags from a model response. The 0-based index of the line holding the most recent
+    /// opening tag is recorded in `tag_line`, so that callers can locate errors.
+    fn parse_tags(&self, response: &str, tag_line: &Cell<usize>) -> Result<ModelResponse> {
         let mut patch = Patch::default();
-        let mut lines = response.lines().map(String::from).peekable();
+        let consumed = Cell::new(0);
+        let mut lines = response
+            .lines()
+            .inspect(|_| consumed.set(consumed.get() + 1))
+            .map(String::from)
+            .peekable();
         let mut comment = None;
+        let mut summary = None;
+        let mut operations = vec![];
 
         while let Some(line) = lines.peek() {
-            if let Some(tag) = xmlish::parse_open(line) {
+            if let Some(tag) = xmlish::parse_open(line)
+                .filter(|tag| TAGS.iter().any(|(name, _)| *name == tag.name))
+            {
+                tag_line.set(consumed.get() - 1);
                 match tag.name.as_str() {
                     "write_file" => {
                         let path = tag
@@ -199,7 +586,7 @@ impl DialectProvider for Tags {
                             .clone();
                         let (_, content) = xmlish::parse_block("write_file", &mut lines)?;
                         patch.changes.push(Change::Write(WriteFile {
-                            path: path.into(),
+                            path: from_slash(&path),
                             content: content.join("\n"),
                         }));
                     }
@@ -220,24 +607,133 @@ impl DialectProvider for Tags {
                         let (_, old) = xmlish::parse_block("old", &mut replace_lines)?;
                         let (_, new) = xmlish::parse_block("new", &mut replace_lines)?;
                         patch.changes.push(Change::ReplaceFuzzy(ReplaceFuzzy {
-                            path: path.into(),
+                            path: from_slash(&path),
                             old: old.join("\n"),
                             new: new.join("\n"),
                         }));
                     }
+                    "move" => {
+                        let attr = |name: &str| {
+                            tag.attributes.get(name).cloned().ok_or_else(|| {
+                                TenxError::ResponseParse {
+                                    user: "Failed to parse model response".into(),
+                                    model: format!(
+                                        "Missing {} attribute in move tag. Line: '{}'",
+                                        name, line
+                                    ),
+                                }
+                            })
+                        };
+                        let (from, to) = (attr("from")?, attr("to")?);
+                        xmlish::parse_block("move", &mut lines)?;
+                        patch.changes.push(Change::Move(MoveFile {
+                            from: from_slash(&from),
+                            to: from_slash(&to),
+                        }));
+                    }
                     "comment" => {
                         let (_, content) = xmlish::parse_block("comment", &mut lines)?;
                         comment = Some(content.join("\n"));
                     }
+                    "summary" => {
+                        let kind = tag
+                            .attributes
+                            .get("type")
+                            .ok_or_else(|| TenxError::ResponseParse {
+                                user: "Failed to parse model response".into(),
+                                model: format!(
+                                    "Missing type attribute in summary tag. Line: '{}'",
+                                    line
+                                ),
+                            })?
+                            .clone();
+                        let (_, content) = xmlish::parse_block("summary", &mut lines)?;
+                        let mut content = content.into_iter().skip_while(|l| l.trim().is_empty());
+                        let description = content.next().unwrap_or_default().trim().to_string();
+                        if description.is_empty() {
+                            return Err(TenxError::ResponseParse {
+                                user: "Failed to parse model response".into(),
+                                model: "Missing description in summary tag".into(),
+                            });
+                        }
+                        let body = content.collect::<Vec<_>>().join("\n").trim().to_string();
+                        summary = Some(Summary {
+                            kind,
+                            scope: tag.attributes.get("scope").cloned(),
+                            description,
+                            body: (!body.is_empty()).then_some(body),
+                        });
+                    }
                     "edit" => {
                         let (_, content) = xmlish::parse_block("edit", &mut lines)?;
                         for line in content {
                             let path = line.trim().to_string();
                             if !path.is_empty() {
-                                patch.changes.push(Change::View(path.clone().into()));
+                                patch.changes.push(Change::View(from_slash(&path)));
                             }
                         }
                     }
+                    "done" => {
+                        xmlish::parse_block("done", &mut lines)?;
+                        operations.push(Operation::Done);
+                    }
+                    "request_context" => {
+                        let (_, content) = xmlish::parse_block("request_context", &mut lines)?;
+                        for line in content {
+                            let spec = line.trim();
+                            if !spec.is_empty() {
+                                operations.push(Operation::RequestContext(spec.to_string()));
+                            }
+                        }
+                    }
+                    "expand" => {
+                        let path = tag
+                            .attributes
+                            .get("path")
+                            .ok_or_else(|| TenxError::ResponseParse {
+                                user: "Failed to parse model response".into(),
+                                model: format!(
+                                    "Missing path attribute in expand tag. Line: '{}'",
+                                    line
+                                ),
+                            })?
+                            .clone();
+                        let (_, content) = xmlish::parse_block("expand", &mut lines)?;
+                        for range in content.iter().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+                            let (start, end) = parse_line_range(range)?;
+                            operations.push(Operation::Expand {
+                                path: from_slash(&path),
+                                start,
+                                end,
+                            });
+                        }
+                    }
+                    "search" => {
+                        let literal = tag.attributes.get("literal").is_some_and(|v| v == "true");
+                        let (_, content) = xmlish::parse_block("search", &mut lines)?;
+                        for pattern in content.iter().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+                            operations.push(Operation::Search {
+                                pattern: pattern.to_string(),
+                                literal,
+                            });
+                        }
+                    }
+                    "ask_user" | "abort" => {
+                        let name = tag.name.clone();
+                        let (_, content) = xmlish::parse_block(&name, &mut lines)?;
+                        let text = content.join("\n").trim().to_string();
+                        if text.is_empty() {
+                            return Err(TenxError::ResponseParse {
+                                user: "Failed to parse model response".into(),
+                                model: format!("Missing text in {} tag", name),
+                            });
+                        }
+                        operations.push(if name == "abort" {
+                            Operation::Abort(text)
+                        } else {
+                            Operation::AskUser(text)
+                        });
+                    }
                     _ => {
                         lines.next();
                     }
@@ -248,9 +744,10 @@ impl DialectProvider for Tags {
         }
         Ok(ModelResponse {
             patch: Some(patch),
-            operations: vec![],
+            operations,
             usage: None,
             comment,
+            summary,
             raw_response: Some(response.to_string()),
         })
     }
@@ -307,6 +804,7 @@ mod tests {
             operations: vec![],
             usage: None,
             comment: Some("This is a comment.".to_string()),
+            summary: None,
             raw_response: Some(input.to_string()),
         };
 
@@ -314,6 +812,71 @@ mod tests {
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn test_summary() {
+        let d = Tags::default();
+
+        let input = indoc! {r#"
+            <summary type="fix" scope="parser">
+            Handle empty input
+
+            The tokenizer assumed at least one character.
+            </summary>
+        "#};
+        let resp = d.parse(input).unwrap();
+        let summary = resp.summary.clone().unwrap();
+        assert_eq!(
+            summary,
+            Summary {
+                kind: "fix".into(),
+                scope: Some("parser".into()),
+                description: "Handle empty input".into(),
+                body: Some("The tokenizer assumed at least one character.".into()),
+            }
+        );
+        assert_eq!(summary.subject(), "fix(parser): Handle empty input");
+
+        assert_eq!(
+            summary.commit_message(),
+            "fix(parser): Handle empty input\n\nThe tokenizer assumed at least one character."
+        );
+
+        let resp = d
+            .parse("<summary type=\"feat\">\nAdd a thing\n</summary>")
+            .unwrap();
+        assert_eq!(resp.summary.unwrap().subject(), "feat: Add a thing");
+
+        assert!(d.parse("<summary>\nNo type\n</summary>").is_err());
+        assert!(d.parse("<summary type=\"fix\">\n</summary>").is_err());
+    }
+
+    #[test]
+    fn test_parse_located() {
+        let d = Tags::default();
+
+        let input = indoc! {r#"
+            <comment>
+            A comment
+            </comment>
+            <replace path="a.txt">
+            <old>
+            foo
+            </old>
+            </replace>
+        "#};
+        let err = d.parse_located(input).unwrap_err();
+        assert_eq!(err.offset, Some(input.find("<replace").unwrap()));
+        assert_eq!(err.line(input), Some(4));
+        assert!(matches!(err.error, TenxError::ResponseParse { .. }));
+
+        let input = "<write_file path=\"a.txt\">\nunclosed\n";
+        let err = d.parse_located(input).unwrap_err();
+        assert_eq!(err.offset, Some(0));
+        assert_eq!(err.line(input), Some(1));
+
+        assert!(d.parse_located("<comment>\nok\n</comment>").is_ok());
+    }
+
     #[test]
     fn test_parse_edit() {
         let d = Tags::default();
@@ -340,6 +903,108 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_operations() {
+        let d = Tags::default();
+
+        let input = indoc! {r#"
+            <request_context>
+            ruskel:serde
+                src/lib.rs
+            </request_context>
+            <ask_user>
+            Should the cache be persistent?
+            </ask_user>
+            <abort>
+            The requested API doesn't exist.
+            </abort>
+            <done>
+            </done>
+            <expand path="src/big.rs">
+            10-20
+            </expand>
+            <search>
+            fn parse_\w+
+            </search>
+            <search literal="true">
+            parse_patch(
+            </search>
+        "#};
+        let resp = d.parse(input).unwrap();
+        assert_eq!(
+            resp.operations,
+            vec![
+                Operation::RequestContext("ruskel:serde".into()),
+                Operation::RequestContext("src/lib.rs".into()),
+                Operation::AskUser("Should the cache be persistent?".into()),
+                Operation::Abort("The requested API doesn't exist.".into()),
+                Operation::Done,
+                Operation::Expand {
+                    path: "src/big.rs".into(),
+                    start: 10,
+                    end: 20,
+                },
+                Operation::Search {
+                    pattern: "fn parse_\\w+".into(),
+                    literal: false,
+                },
+                Operation::Search {
+                    pattern: "parse_patch(".into(),
+                    literal: true,
+                },
+            ]
+        );
+        assert_eq!(resp.question(), Some("Should the cache be persistent?"));
+        assert_eq!(resp.context_requests(), vec!["ruskel:serde", "src/lib.rs"]);
+
+        assert!(d
+            .parse(
+                "<ask_user>
+</ask_user>"
+            )
+            .is_err());
+        assert!(d
+            .parse(
+                "<expand path=\"src/big.rs\">
+20-10
+</expand>"
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_move() {
+        let d = Tags::default();
+        let resp = d
+            .parse(indoc! {r#"
+                <move from="src/util.rs" to="src/helpers.rs">
+                </move>
+            "#})
+            .unwrap();
+        assert_eq!(
+            resp.patch.unwrap(),
+            Patch::default().with_move("src/util.rs", "src/helpers.rs")
+        );
+        assert!(d.parse("<move from=\"src/util.rs\">\n</move>").is_err());
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let mut config = Config::default();
+        let caps = Tags::new().capabilities(&config);
+        assert_eq!(caps.dialect, "tags");
+        assert!(caps.changes.contains(&"write".to_string()));
+        assert!(caps.operations.contains(&"expand".to_string()));
+        assert_eq!(caps.changes, vec!["write", "replace_fuzzy", "view", "move"]);
+        assert!(!caps.outlines);
+        config.dialect.outline_tokens = 100;
+        assert!(Tags::new().capabilities(&config).outlines);
+        assert!(crate::dialect::DummyDialect::default()
+            .capabilities(&config)
+            .changes
+            .is_empty());
+    }
+
     #[test]
     fn test_render_edit() -> Result<()> {
         let mut p = testutils::test_project();
@@ -348,6 +1013,7 @@ mod tests {
 
         let response = ModelResponse {
             comment: Some("A comment".into()),
+            summary: None,
             patch: Some(Patch {
                 changes: vec![
                     Change::View(PathBuf::from("src/main.rs")),
@@ -369,7 +1035,7 @@ mod tests {
             strategy::StrategyStep::Code(strategy::CodeStep::default()),
         ))?;
         if let Some(step) = p.session.last_step_mut() {
-            step.model_response = Some(response);
+            step.response.model_response = Some(response);
         }
 
         let result = d.render_step_response(&p.session, 0, 0).unwrap();
@@ -1,3 +1,7 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use state;
 use thiserror::Error;
@@ -6,7 +10,39 @@ use crate::throttle::Throttle;
 
 pub type Result<T> = std::result::Result<T, TenxError>;
 
-#[derive(Error, Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+/// Fragments of error messages that indicate a transient failure, for provider errors that come
+/// without an HTTP status.
+const TRANSIENT_MARKERS: &[&str] = &[
+    "timed out",
+    "timeout",
+    "connection reset",
+    "connection closed",
+    "error sending request",
+    "overloaded",
+    "temporarily unavailable",
+];
+
+/// Fragments of error messages that indicate bad credentials, for provider errors that come
+/// without an HTTP status.
+const AUTH_MARKERS: &[&str] = &[
+    "unauthorized",
+    "authentication",
+    "invalid api key",
+    "invalid x-api-key",
+];
+
+/// Explicit mentions of an HTTP status, like "status: 503", "status code 429" or "HTTP/1.1 502".
+static STATUS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b(?:status(?: code)?:?|http(?:/[\d.]+)?)\s+([45]\d\d)\b").unwrap()
+});
+
+/// Finds an HTTP error status mentioned in an error message. Only explicit status forms count,
+/// so stray numbers like line and column positions aren't mistaken for a status.
+fn status_in(message: &str) -> Option<u16> {
+    STATUS.captures(message).and_then(|c| c[1].parse().ok())
+}
+
+#[derive(Error, Debug, Serialize, Deserialize, Clone, Eq, PartialEq, JsonSchema)]
 pub enum TenxError {
     #[error("config error: {0}")]
     Config(String),
@@ -20,6 +56,13 @@ pub enum TenxError {
     #[error("Model error: {0}")]
     Model(String),
 
+    /// No API key is configured for a provider.
+    #[error(
+        "No API key for {provider}. Run `tenx auth set {provider}`, or set the key in the \
+         environment or config."
+    )]
+    MissingKey { provider: String },
+
     #[error("{msg}: {path}")]
     NotFound { msg: String, path: String },
 
@@ -67,12 +110,89 @@ pub enum TenxError {
     #[error("Throttled: {0}")]
     Throttle(Throttle),
 
+    /// A request would exceed the configured spending budget.
+    #[error("Budget exceeded: {0}")]
+    Budget(String),
+
+    /// A patch makes risky changes that weren't confirmed or allowed.
+    #[error("Risky patch not applied: {0}")]
+    Risky(String),
+
+    /// Files a patch would write were changed by something else after the prompt was rendered.
+    #[error("Conflicting edits: {0}")]
+    Conflict(String),
+
+    /// The model keeps making the same failing changes, so retrying won't help.
+    #[error("Model is stuck: {0}")]
+    Stuck(String),
+
+    /// A request to a model provider failed. Transient failures are retried with backoff, and
+    /// permanent ones fail straight away.
+    #[error("{message}")]
+    Provider {
+        /// The HTTP status the provider returned, if known
+        status: Option<u16>,
+        /// True if the request might succeed if it's retried
+        transient: bool,
+        /// What went wrong, with advice on fixing it for permanent failures
+        message: String,
+    },
+
     /// We've exceeded the max retries trying to send a request.
     #[error("Max retries exceeded: {0}")]
     MaxRetries(u64),
 }
 
 impl TenxError {
+    /// Classifies a failed request to a model provider, by its HTTP status if known, and
+    /// otherwise by its message. Rate limits become throttle errors. Timeouts and server errors
+    /// are transient, and everything else, like bad credentials or malformed requests, is
+    /// permanent.
+    pub fn provider(status: Option<u16>, message: impl Into<String>) -> Self {
+        let message = message.into();
+        let lower = message.to_lowercase();
+        let status = status.or_else(|| status_in(&message));
+        let transient = match status {
+            Some(429 | 529) => return TenxError::Throttle(Throttle::Backoff),
+            Some(s) => s == 408 || s >= 500,
+            None => TRANSIENT_MARKERS.iter().any(|m| lower.contains(m)),
+        };
+        let auth = matches!(status, Some(401 | 403))
+            || (status.is_none() && AUTH_MARKERS.iter().any(|m| lower.contains(m)));
+        let message = if auth {
+            format!(
+                "{}. The provider rejected our credentials, check the API key with `tenx auth`",
+                message
+            )
+        } else if matches!(status, Some(400 | 404 | 422)) {
+            format!(
+                "{}. The provider rejected the request, check the model's api_model and \
+                 settings in the config",
+                message
+            )
+        } else {
+            message
+        };
+        TenxError::Provider {
+            status,
+            transient,
+            message,
+        }
+    }
+
+    /// Returns true if the error is a transient provider failure, which might not recur if the
+    /// request is retried.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            TenxError::Throttle(_)
+                | TenxError::Provider {
+                    transient: true,
+                    ..
+                }
+        )
+    }
+
     /// Returns the model response if the error is retryable, otherwise None.
     pub fn should_retry(&self) -> Option<String> {
         match self {
@@ -95,8 +215,29 @@ impl From<misanthropy::Error> for TenxError {
         match error {
             misanthropy::Error::RateLimitExceeded(_msg) => TenxError::Throttle(Throttle::Backoff),
             misanthropy::Error::ApiOverloaded(_msg) => TenxError::Throttle(Throttle::Backoff),
-            _ => TenxError::Model(error.to_string()),
+            misanthropy::Error::Unauthorized(_) => {
+                TenxError::provider(Some(401), error.to_string())
+            }
+            misanthropy::Error::BadRequest(_) => TenxError::provider(Some(400), error.to_string()),
+            misanthropy::Error::HttpError(ref e) => {
+                TenxError::provider(e.status().map(|s| s.as_u16()), error.to_string())
+            }
+            _ => TenxError::provider(None, error.to_string()),
+        }
+    }
+}
+
+impl From<reqwest::Error> for TenxError {
+    fn from(error: reqwest::Error) -> Self {
+        // Failures to connect or get a response in time are transient
+        if error.is_timeout() || error.is_connect() {
+            return TenxError::Provider {
+                status: None,
+                transient: true,
+                message: error.to_string(),
+            };
         }
+        TenxError::provider(error.status().map(|s| s.as_u16()), error.to_string())
     }
 }
 
@@ -111,3 +252,53 @@ impl From<state::Error> for TenxError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provider() {
+        let transient = |status, msg| TenxError::provider(status, msg).is_transient();
+        assert!(transient(Some(500), "internal error"));
+        assert!(transient(Some(503), "unavailable"));
+        assert!(transient(Some(408), "request timeout"));
+        assert!(transient(None, "operation timed out"));
+        assert!(transient(None, "server returned HTTP 502 Bad Gateway"));
+        assert!(!transient(Some(401), "unauthorized"));
+        assert!(!transient(Some(400), "invalid schema"));
+        assert!(!transient(None, "something odd happened"));
+
+        assert_eq!(
+            TenxError::provider(Some(429), "slow down"),
+            TenxError::Throttle(Throttle::Backoff)
+        );
+        let err = TenxError::provider(None, "status: 401 Unauthorized");
+        assert!(matches!(
+            err,
+            TenxError::Provider {
+                status: Some(401),
+                ..
+            }
+        ));
+        assert!(err.to_string().contains("tenx auth"));
+        assert!(TenxError::provider(Some(400), "bad field")
+            .to_string()
+            .contains("api_model"));
+    }
+
+    #[test]
+    fn test_status_in() {
+        assert_eq!(status_in("status: 503 Service Unavailable"), Some(503));
+        assert_eq!(status_in("HTTP status code 429"), Some(429));
+        assert_eq!(status_in("HTTP 502 Bad Gateway"), Some(502));
+        assert_eq!(status_in("HTTP/1.1 404 Not Found"), Some(404));
+        assert_eq!(status_in("expected value at line 1 column 512"), None);
+        assert_eq!(status_in("450"), None);
+        assert_eq!(status_in("request 404 of 500 failed"), None);
+        assert_eq!(status_in("status: 200"), None);
+
+        // A position in a parse error isn't a server error
+        assert!(!TenxError::provider(None, "invalid json at line 1 column 512").is_transient());
+    }
+}
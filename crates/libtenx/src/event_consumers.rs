@@ -1,30 +1,291 @@
-//! Helpers for consuming and displaying events
+//! Helpers for consuming and displaying events. Embedders can drive their own interface by
+//! implementing [`EventConsumer`], or by using the [`CallbackConsumer`] and [`ChannelConsumer`]
+//! adapters, and running the consumer over a `Tenx` event channel with [`consume`]. Several
+//! consumers can share one channel through a [`Broadcast`].
 use colored::*;
 use indicatif::{ProgressBar, ProgressStyle};
-use std::time::Duration;
+use std::{
+    io::Write,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use textwrap;
 use tokio::sync::mpsc;
 use tracing::Subscriber;
 use tracing_subscriber::fmt::format::FmtSpan;
-use tracing_subscriber::{fmt, EnvFilter};
+use tracing_subscriber::{fmt, layer::SubscriberExt, EnvFilter, Layer};
 
-use crate::events::{Event, EventReceiver, EventSender, LogLevel};
+use crate::{
+    error::Result,
+    events::{Event, EventReceiver, EventSender, LogLevel},
+};
 
 const SPINNER_STRINGS: &[&str] = &["▹▹▹▹▹", "▸▹▹▹▹", "▹▸▹▹▹", "▹▹▸▹▹", "▹▹▹▸▹", "▹▹▹▹▸"];
 
-/// Discards all events without processing them
-pub async fn discard_events(mut receiver: EventReceiver, mut kill_signal: mpsc::Receiver<()>) {
+/// A consumer of the events emitted by `Tenx` operations, for instance to drive a user
+/// interface.
+pub trait EventConsumer: Send {
+    /// Handles a single event. Events are delivered in the order they were sent.
+    fn handle(&mut self, event: Event);
+
+    /// Called once when the event stream ends, either because all senders were dropped or
+    /// because the consumer was killed.
+    fn finish(&mut self) {}
+}
+
+impl<C: EventConsumer + ?Sized> EventConsumer for Box<C> {
+    fn handle(&mut self, event: Event) {
+        (**self).handle(event)
+    }
+
+    fn finish(&mut self) {
+        (**self).finish()
+    }
+}
+
+/// Feeds events from a receiver to a consumer until the channel closes or a kill signal is
+/// received, then finishes the consumer. Dropping the kill signal's sender does not stop the
+/// consumer. This is usually spawned as a task alongside `Tenx`
+/// operations, which are given the channel's sender.
+pub async fn consume<C: EventConsumer>(
+    mut consumer: C,
+    mut receiver: EventReceiver,
+    mut kill_signal: mpsc::Receiver<()>,
+) {
     loop {
         tokio::select! {
-            _ = receiver.recv() => {}
-            _ = kill_signal.recv() => break,
+            Some(event) = receiver.recv() => consumer.handle(event),
+            Some(()) = kill_signal.recv() => break,
             else => break,
         }
     }
+    consumer.finish();
+}
+
+/// An event consumer that calls a function for each event.
+pub struct CallbackConsumer<F> {
+    callback: F,
+}
+
+impl<F: FnMut(Event) + Send> CallbackConsumer<F> {
+    pub fn new(callback: F) -> Self {
+        Self { callback }
+    }
+}
+
+impl<F: FnMut(Event) + Send> EventConsumer for CallbackConsumer<F> {
+    fn handle(&mut self, event: Event) {
+        (self.callback)(event)
+    }
+}
+
+/// An event consumer that forwards events to an unbounded channel, so that they can be
+/// processed elsewhere, for instance on a UI thread that isn't running in the async runtime.
+pub struct ChannelConsumer {
+    sender: mpsc::UnboundedSender<Event>,
+}
+
+impl ChannelConsumer {
+    /// Creates a consumer, along with the receiver that its events are forwarded to.
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<Event>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (Self { sender }, receiver)
+    }
 }
 
-/// Creates a subscriber that sends all tracing events to an mpsc channel for processing.
-pub fn create_tracing_subscriber(verbosity: u8, sender: EventSender) -> impl Subscriber {
+impl EventConsumer for ChannelConsumer {
+    fn handle(&mut self, event: Event) {
+        // The receiving end may have gone away, in which case events are dropped.
+        let _ = self.sender.send(event);
+    }
+}
+
+/// Discards all events without processing them
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+pub async fn discard_events(receiver: EventReceiver, kill_signal: mpsc::Receiver<()>) {
+    consume(CallbackConsumer::new(|_| {}), receiver, kill_signal).await
+}
+
+/// A log file that receives full trace-level logs and every event, independent of the verbosity
+/// of the terminal output. Clones share the same file.
+#[derive(Clone)]
+pub struct LogFile {
+    file: Arc<Mutex<std::fs::File>>,
+}
+
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+impl LogFile {
+    /// Creates the log file, truncating it if it exists.
+    pub fn create(path: &Path) -> Result<Self> {
+        Ok(Self {
+            file: Arc::new(Mutex::new(std::fs::File::create(path)?)),
+        })
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.file.lock() {
+            Ok(mut f) => f.write(buf),
+            Err(_) => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.file.lock() {
+            Ok(mut f) => f.flush(),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+/// An event consumer that writes every event to a log file, then passes it on to another
+/// consumer. Log events are not written, since the log file receives those from tracing.
+pub struct LogFileConsumer<C> {
+    file: LogFile,
+    inner: C,
+}
+
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+impl<C: EventConsumer> LogFileConsumer<C> {
+    pub fn new(file: LogFile, inner: C) -> Self {
+        Self { file, inner }
+    }
+}
+
+impl<C: EventConsumer> EventConsumer for LogFileConsumer<C> {
+    fn handle(&mut self, event: Event) {
+        if !matches!(event, Event::Log(..)) {
+            let _ = writeln!(self.file, "event {}: {}", event.name(), event.display());
+        }
+        self.inner.handle(event)
+    }
+
+    fn finish(&mut self) {
+        let _ = self.file.flush();
+        self.inner.finish()
+    }
+}
+
+/// Selects events by name, as returned by [`Event::name`]. An empty filter matches everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventFilter {
+    names: Vec<String>,
+}
+
+impl EventFilter {
+    /// Creates a filter matching only the named events.
+    pub fn names<S: AsRef<str>>(names: &[S]) -> Self {
+        Self {
+            names: names.iter().map(|n| n.as_ref().to_string()).collect(),
+        }
+    }
+
+    /// Does the filter let this event through?
+    pub fn matches(&self, event: &Event) -> bool {
+        self.names.is_empty() || self.names.contains(&event.name())
+    }
+}
+
+/// A consumer running on its own thread, fed through a channel.
+struct Outlet {
+    filter: EventFilter,
+    sender: std::sync::mpsc::Sender<Event>,
+    thread: std::thread::JoinHandle<()>,
+}
+
+/// An event consumer that fans events out to any number of other consumers, for instance a
+/// progress display, a JSON log and a socket at once. Each consumer runs on its own thread, so a
+/// slow one doesn't hold up the others, and sees only the events its filter matches, in order.
+#[derive(Default)]
+pub struct Broadcast {
+    outlets: Vec<Outlet>,
+}
+
+impl Broadcast {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a consumer that receives every event.
+    pub fn with<C: EventConsumer + 'static>(self, consumer: C) -> Self {
+        self.with_filtered(consumer, EventFilter::default())
+    }
+
+    /// Adds a consumer that receives only the events matching `filter`.
+    pub fn with_filtered<C: EventConsumer + 'static>(
+        mut self,
+        mut consumer: C,
+        filter: EventFilter,
+    ) -> Self {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let thread = std::thread::spawn(move || {
+            for event in receiver {
+                consumer.handle(event);
+            }
+            consumer.finish();
+        });
+        self.outlets.push(Outlet {
+            filter,
+            sender,
+            thread,
+        });
+        self
+    }
+}
+
+impl EventConsumer for Broadcast {
+    fn handle(&mut self, event: Event) {
+        for sub in &self.outlets {
+            if sub.filter.matches(&event) {
+                // A consumer that panicked has gone away, and just misses out
+                let _ = sub.sender.send(event.clone());
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        for sub in std::mem::take(&mut self.outlets) {
+            drop(sub.sender);
+            let _ = sub.thread.join();
+        }
+    }
+}
+
+/// An event consumer that writes each event as a line of JSON, for other programs to follow.
+/// Write errors are ignored, so a reader going away doesn't interrupt the session.
+pub struct JsonConsumer<W> {
+    writer: W,
+}
+
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+impl<W: Write + Send> JsonConsumer<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write + Send> EventConsumer for JsonConsumer<W> {
+    fn handle(&mut self, event: Event) {
+        if let Ok(line) = serde_json::to_string(&event) {
+            let _ = writeln!(self.writer, "{}", line);
+        }
+    }
+
+    fn finish(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+/// Creates a subscriber that sends all tracing events to an mpsc channel for processing. If a
+/// log file is given, trace-level logs are also written to it, regardless of verbosity.
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+pub fn create_tracing_subscriber(
+    verbosity: u8,
+    sender: EventSender,
+    log_file: Option<LogFile>,
+) -> impl Subscriber {
     let log_level = match verbosity {
         0 => "warn",
         1 => "info",
@@ -67,148 +328,324 @@ pub fn create_tracing_subscriber(verbosity: u8, sender: EventSender) -> impl Sub
         sender: sender.clone(),
     };
 
-    fmt::Subscriber::builder()
-        .with_env_filter(filter)
-        .with_writer(make_writer)
-        .with_span_events(FmtSpan::NONE)
-        .without_time()
-        .finish()
+    let file_layer = log_file.map(|file| {
+        fmt::layer()
+            .with_writer(move || file.clone())
+            .with_ansi(false)
+            .with_filter(EnvFilter::new(
+                "info,libtenx=trace,tenx=trace,ttrial=trace,state=trace",
+            ))
+    });
+
+    tracing_subscriber::registry()
+        .with(
+            fmt::layer()
+                .with_writer(make_writer)
+                .with_span_events(FmtSpan::NONE)
+                .without_time()
+                .with_filter(filter),
+        )
+        .with(file_layer)
 }
 
-/// Output events in a text log format
-pub async fn output_logs(mut receiver: EventReceiver, mut kill_signal: mpsc::Receiver<()>) {
-    loop {
-        tokio::select! {
-            Some(event) = receiver.recv() => {
-                match event {
-                    Event::Log(level, message) => {
-                        let severity = match level {
-                            LogLevel::Error => "error".red(),
-                            LogLevel::Warn => "warn".yellow(),
-                            LogLevel::Info => "info".green(),
-                            LogLevel::Debug => "debug".cyan(),
-                            LogLevel::Trace => "trace".magenta(),
-                        };
-                        println!("{}: {}", severity, message);
-                    }
-                    Event::IterationLimit => {
-                        println!("{}: step limit reached", "warn".yellow());
-                    }
-                    _ => {
-                        let name = event.name().to_string();
-                        let display = event.display();
-                        if display.is_empty() {
-                            println!("{}", name.blue());
-                        } else {
-                            println!("{}: {}", name.blue(), display);
-                        }
-                    }
+/// An event consumer that prints events in a text log format.
+#[derive(Debug, Default)]
+pub struct LogConsumer;
+
+impl EventConsumer for LogConsumer {
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::Log(level, message) => {
+                let severity = match level {
+                    LogLevel::Error => "error".red(),
+                    LogLevel::Warn => "warn".yellow(),
+                    LogLevel::Info => "info".green(),
+                    LogLevel::Debug => "debug".cyan(),
+                    LogLevel::Trace => "trace".magenta(),
+                };
+                println!("{}: {}", severity, message);
+            }
+            Event::IterationLimit => {
+                println!("{}: step limit reached", "warn".yellow());
+            }
+            Event::TimeBudget(ref summary) => {
+                println!("{}: time budget exhausted\n{}", "warn".yellow(), summary);
+            }
+            _ => {
+                let name = event.name().to_string();
+                let display = event.display();
+                if display.is_empty() {
+                    println!("{}", name.blue());
+                } else {
+                    println!("{}: {}", name.blue(), display);
                 }
             }
-            _ = kill_signal.recv() => break,
-            else => break,
         }
     }
 }
 
-/// Fancy event output, with progress bars
-pub async fn output_progress(
-    mut receiver: EventReceiver,
-    mut kill_signal: mpsc::Receiver<()>,
+/// Output events in a text log format
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+pub async fn output_logs(receiver: EventReceiver, kill_signal: mpsc::Receiver<()>) {
+    consume(LogConsumer, receiver, kill_signal).await
+}
+
+/// An event consumer for fancy event output, with progress spinners.
+pub struct ProgressConsumer {
     verbosity: u8,
-) {
-    let spinner_indent = SPINNER_STRINGS[0].chars().count();
-    let validator_spinner_style = ProgressStyle::with_template("    {spinner:.green.bold} {msg}")
-        .unwrap()
-        .tick_strings(SPINNER_STRINGS);
+    spinner_indent: usize,
+    spinner_style: ProgressStyle,
+    current_spinner: Option<ProgressBar>,
+}
 
-    let mut current_spinner: Option<ProgressBar> = None;
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+impl ProgressConsumer {
+    pub fn new(verbosity: u8) -> Self {
+        Self {
+            verbosity,
+            spinner_indent: SPINNER_STRINGS[0].chars().count(),
+            spinner_style: ProgressStyle::with_template("    {spinner:.green.bold} {msg}")
+                .unwrap()
+                .tick_strings(SPINNER_STRINGS),
+            current_spinner: None,
+        }
+    }
 
-    fn finish_spinner(spinner: &mut Option<ProgressBar>) {
-        if let Some(s) = spinner.take() {
+    fn finish_spinner(&mut self) {
+        if let Some(s) = self.current_spinner.take() {
             s.finish();
         }
     }
 
-    fn start_new_spinner(
-        current_spinner: &mut Option<ProgressBar>,
-        style: &ProgressStyle,
-        message: &str,
-    ) {
-        if let Some(spinner) = current_spinner.as_ref() {
-            spinner.finish();
-        }
-        let new_spinner = ProgressBar::new_spinner().with_style(style.clone());
+    fn start_new_spinner(&mut self, message: &str) {
+        self.finish_spinner();
+        let new_spinner = ProgressBar::new_spinner().with_style(self.spinner_style.clone());
         new_spinner.enable_steady_tick(Duration::from_millis(100));
         new_spinner.set_message(message.to_string());
-        *current_spinner = Some(new_spinner);
+        self.current_spinner = Some(new_spinner);
     }
 
-    loop {
-        tokio::select! {
-            Some(event) = receiver.recv() => {
-                if let Some(header) = event.header_message() {
-                    finish_spinner(&mut current_spinner);
-                    println!("{}", header.blue());
-                } else if let Some(progress_event) = event.progress_event() {
-                    start_new_spinner(
-                        &mut current_spinner,
-                        &validator_spinner_style,
-                        &progress_event,
+    /// Prints an indented note, finishing any active spinner first.
+    fn note(&mut self, message: ColoredString) {
+        self.finish_spinner();
+        println!("{:>width$}{}", "", message, width = self.spinner_indent);
+    }
+}
+
+impl EventConsumer for ProgressConsumer {
+    fn handle(&mut self, event: Event) {
+        if let Some(header) = event.header_message() {
+            self.finish_spinner();
+            println!("{}", header.blue());
+        } else if let Some(progress_event) = event.progress_event() {
+            self.start_new_spinner(&progress_event);
+        }
+
+        match event {
+            Event::Throttled(ms) => {
+                self.note(format!("throttled: waiting {}ms", ms).yellow());
+            }
+            Event::ContextCut { .. } => {
+                self.note(format!("context cut: {}", event.display()).yellow());
+            }
+            Event::Redacted { .. } => {
+                self.note(format!("redacted: {}", event.display()).yellow());
+            }
+            Event::Queued(ref reason) => {
+                self.note(format!("queued: {}", reason).yellow());
+            }
+            Event::PromptLint(ref warning) => {
+                self.note(format!("lint: {}", warning).yellow());
+            }
+            Event::ConfigReloaded(..) => {
+                self.note(format!("config reloaded: {}", event.display()).blue());
+            }
+            Event::Interact => {
+                self.finish_spinner();
+                println!("{}", "getting user input...".blue());
+            }
+            Event::NextStep {
+                ref user,
+                ref model,
+                ..
+            } => {
+                self.note(format!("next step: {}", user).yellow());
+                if self.verbosity > 0 {
+                    let indent = self.spinner_indent;
+                    let wrapped =
+                        textwrap::indent(&textwrap::fill(model, 80 - indent), &" ".repeat(indent));
+                    println!("{:>width$}Model message:", "", width = indent);
+                    println!("{}", wrapped.yellow());
+                }
+            }
+            Event::IterationLimit => {
+                self.note("step limit reached".yellow());
+            }
+            Event::TimeBudget(ref summary) => {
+                self.note("time budget exhausted".yellow());
+                let indent = " ".repeat(self.spinner_indent + 2);
+                println!("{}", textwrap::indent(summary, &indent));
+            }
+            Event::AskUser(ref question) => {
+                self.note(format!("model asks: {}", question).yellow());
+            }
+            Event::Aborted(ref reason) => {
+                self.note(format!("model aborted: {}", reason).red());
+            }
+            Event::Fatal(ref message) => {
+                self.note(format!("fatal: {}", message).red());
+            }
+            Event::Snippet(ref chunk) => {
+                self.finish_spinner();
+                print!("{}", chunk);
+            }
+            Event::ModelResponse(ref text) => {
+                self.finish_spinner();
+                print!("{}", text);
+            }
+            Event::Finish => {
+                self.finish_spinner();
+            }
+            Event::SessionStats(ref stats) => {
+                self.finish_spinner();
+                let lines = stats.lines();
+                let width = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+                println!("{}", format!("╭{}╮", "─".repeat(width + 2)).blue());
+                for line in lines {
+                    println!(
+                        "{} {:<width$} {}",
+                        "│".blue(),
+                        line,
+                        "│".blue(),
+                        width = width
                     );
                 }
-
-                match event {
-                    Event::Throttled(ms) => {
-                        finish_spinner(&mut current_spinner);
-                        println!("{:>width$}{}", "", format!("throttled: waiting {}ms", ms).yellow(), width=spinner_indent);
-                    }
-                    Event::Interact => {
-                        finish_spinner(&mut current_spinner);
-                        println!("{}", "getting user input...".blue());
-                    }
-                    Event::NextStep{ref user, ref model} => {
-                        finish_spinner(&mut current_spinner);
-                        println!("{:>width$}{}", "", format!("next step: {}", user).yellow(), width=spinner_indent);
-                        if verbosity > 0 {
-                            let wrapped = textwrap::indent(
-                                &textwrap::fill(model, 80 - spinner_indent),
-                                &" ".repeat(spinner_indent)
-                            );
-                            println!("{:>width$}Model message:", "", width=spinner_indent);
-                            println!("{}", wrapped.yellow());
-                        }
-                    }
-                    Event::IterationLimit => {
-                        finish_spinner(&mut current_spinner);
-                        println!("{:>width$}{}", "", "step limit reached".yellow(), width=spinner_indent);
-                    }
-                    Event::Fatal(ref message) => {
-                        finish_spinner(&mut current_spinner);
-                        println!("{:>width$}{}", "", format!("fatal: {}", message).red(), width=spinner_indent);
-                    }
-                    Event::Snippet(ref chunk) => {
-                        finish_spinner(&mut current_spinner);
-                        print!("{}", chunk);
-                    }
-                    Event::ModelResponse(ref text) => {
-                        finish_spinner(&mut current_spinner);
-                        print!("{}", text);
-                    }
-                    Event::Finish => {
-                        finish_spinner(&mut current_spinner);
-                    }
-                    Event::PromptEnd(_) => {
-                        finish_spinner(&mut current_spinner);
-                        println!("\n");
-                    }
-                    _ => {}
+                println!("{}", format!("╰{}╯", "─".repeat(width + 2)).blue());
+            }
+            Event::CheckSkipped {
+                ref name,
+                ref reason,
+            } => {
+                self.note(format!("check skipped: {} ({})", name, reason).yellow());
+            }
+            Event::CheckFailed {
+                ref name,
+                ref source,
+                ..
+            } => {
+                self.note(format!("check failed: {}", name).red());
+                if let Some(source) = source {
+                    let indent = " ".repeat(self.spinner_indent + 2);
+                    println!("{}", textwrap::indent(source, &indent).dimmed());
                 }
             }
-            _ = kill_signal.recv() => break,
-            else => break,
+            Event::PromptEnd { .. } => {
+                self.finish_spinner();
+                println!("\n");
+            }
+            Event::PatchApplyEnd { .. } => {
+                self.finish_spinner();
+            }
+            _ => {}
         }
     }
 
-    finish_spinner(&mut current_spinner);
+    fn finish(&mut self) {
+        self.finish_spinner();
+    }
+}
+
+/// Fancy event output, with progress bars
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+pub async fn output_progress(
+    receiver: EventReceiver,
+    kill_signal: mpsc::Receiver<()>,
+    verbosity: u8,
+) {
+    consume(ProgressConsumer::new(verbosity), receiver, kill_signal).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_consume() {
+        let (sender, receiver) = mpsc::channel(10);
+        let (_, kill_rx) = mpsc::channel(1);
+        let (consumer, mut forwarded) = ChannelConsumer::new();
+        sender.send(Event::Start).await.unwrap();
+        sender.send(Event::Snippet("hi".into())).await.unwrap();
+        drop(sender);
+        consume(consumer, receiver, kill_rx).await;
+
+        let mut names = vec![];
+        while let Ok(event) = forwarded.try_recv() {
+            names.push(event.name());
+        }
+        assert_eq!(names, vec!["start", "snippet"]);
+
+        let (sender, receiver) = mpsc::channel(10);
+        let (_, kill_rx) = mpsc::channel(1);
+        let mut count = 0;
+        sender.send(Event::Finish).await.unwrap();
+        drop(sender);
+        consume(CallbackConsumer::new(|_| count += 1), receiver, kill_rx).await;
+        assert_eq!(count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_log_file_consumer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tenx.log");
+        let file = LogFile::create(&path).unwrap();
+        let (sender, receiver) = mpsc::channel(10);
+        let (_, kill_rx) = mpsc::channel(1);
+        let mut count = 0;
+        sender.send(Event::Queued("busy".into())).await.unwrap();
+        sender
+            .send(Event::Log(LogLevel::Info, "hidden".into()))
+            .await
+            .unwrap();
+        drop(sender);
+        let consumer = LogFileConsumer::new(file, CallbackConsumer::new(|_| count += 1));
+        consume(consumer, receiver, kill_rx).await;
+        assert_eq!(count, 2);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "event queued: busy\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_broadcast() {
+        let (sender, receiver) = mpsc::channel(10);
+        let (_, kill_rx) = mpsc::channel(1);
+        let (all, mut all_rx) = ChannelConsumer::new();
+        let (some, mut some_rx) = ChannelConsumer::new();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.json");
+        let broadcast = Broadcast::new()
+            .with(all)
+            .with_filtered(some, EventFilter::names(&["finish"]))
+            .with(JsonConsumer::new(std::fs::File::create(&path).unwrap()));
+        sender.send(Event::Start).await.unwrap();
+        sender.send(Event::Finish).await.unwrap();
+        drop(sender);
+        consume(broadcast, receiver, kill_rx).await;
+
+        let names = |rx: &mut mpsc::UnboundedReceiver<Event>| {
+            let mut names = vec![];
+            while let Ok(event) = rx.try_recv() {
+                names.push(event.name());
+            }
+            names
+        };
+        assert_eq!(names(&mut all_rx), vec!["start", "finish"]);
+        assert_eq!(names(&mut some_rx), vec!["finish"]);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "\"Start\"\n\"Finish\"\n"
+        );
+    }
 }
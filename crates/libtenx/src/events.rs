@@ -1,10 +1,19 @@
 //! Events emitted by Tenx during operation, for display to users.
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
 use heck::ToSnakeCase;
 use serde::{Deserialize, Serialize};
 use serde_variant::to_variant_name;
 use tokio::sync::mpsc;
 
-use crate::error::{Result, TenxError};
+use crate::{
+    config::Config,
+    error::{Result, TenxError},
+    session_store::path_to_filename,
+};
 
 pub type EventSender = mpsc::Sender<Event>;
 pub type EventReceiver = mpsc::Receiver<Event>;
@@ -29,6 +38,75 @@ pub fn send_event(sender: &Option<EventSender>, event: Event) -> Result<()> {
     Ok(())
 }
 
+/// Identifies a step in a session, so that consumers can associate events with steps.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StepId {
+    /// The session identifier, which is the name the session is stored under
+    pub session: String,
+    /// The offset of the action in the session
+    pub action: usize,
+    /// The offset of the step in the action
+    pub step: usize,
+}
+
+impl StepId {
+    /// Creates a StepId for a step in the project's current session.
+    pub fn new(config: &Config, action: usize, step: usize) -> Self {
+        Self {
+            session: path_to_filename(&config.project_root()),
+            action,
+            step,
+        }
+    }
+}
+
+/// A summary of a run of steps, sent when the run ends so consumers can report on it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionStats {
+    /// The number of steps run
+    pub steps: usize,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// The estimated cost in USD, or 0 if the model's pricing is unknown
+    pub cost: f64,
+    pub duration: Duration,
+    /// The files the run's patches changed, sorted
+    pub files: Vec<PathBuf>,
+    /// Checks that failed during the run, in the order they first failed
+    pub failed_checks: Vec<String>,
+    /// The check that was still failing when the run ended, if any
+    pub failing_check: Option<String>,
+}
+
+impl SessionStats {
+    /// Returns a short, human-readable description of the stats, one item per line.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("steps: {}, {:.1}s", self.steps, self.duration.as_secs_f64()),
+            format!(
+                "tokens: {} in, {} out, ${:.2}",
+                self.input_tokens, self.output_tokens, self.cost
+            ),
+        ];
+        if !self.files.is_empty() {
+            lines.push(format!(
+                "files: {}",
+                self.files
+                    .iter()
+                    .map(|f| f.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        lines.push(match (&self.failing_check, self.failed_checks.is_empty()) {
+            (Some(name), _) => format!("checks: {} failing", name),
+            (None, true) => "checks: no failures".to_string(),
+            (None, false) => format!("checks: fixed {}", self.failed_checks.join(", ")),
+        });
+        lines
+    }
+}
+
 // The events are listed below roughly in the order they are expected to occur
 
 /// Events emitted during execution to track progress and provide feedback.
@@ -53,29 +131,95 @@ pub enum Event {
     ContextRefreshStart(String),
     /// A context refresh operation ended
     ContextRefreshEnd(String),
+    /// A context item was truncated or dropped to fit the model's context window
+    ContextCut {
+        /// The source of the context item
+        source: String,
+        /// The estimated size of the item in tokens
+        tokens: usize,
+        /// The estimated number of tokens kept, or 0 if the item was dropped
+        kept: usize,
+    },
+
+    /// Secrets were redacted from content before sending it to the model
+    Redacted {
+        /// The context item or editable file the secrets were found in
+        source: String,
+        /// The kinds of secret redacted, with their 1-based line numbers
+        secrets: Vec<(String, usize)>,
+    },
+
+    /// A likely problem with a prompt, found before it was sent to the model
+    PromptLint(String),
+
+    /// The configuration was reloaded because a config file changed, with a description of each
+    /// change
+    ConfigReloaded(Vec<String>),
 
     /// A check has started
-    CheckStart(String),
+    CheckStart {
+        /// The name of the check
+        name: String,
+        /// The files that made the check relevant
+        files: Vec<PathBuf>,
+    },
+    /// A check was skipped even though the configuration enables it, such as a builtin check
+    /// whose program isn't installed
+    CheckSkipped { name: String, reason: String },
     /// A check has passed
-    CheckOk(String),
+    CheckOk { name: String, duration: Duration },
+    /// A check has failed
+    CheckFailed {
+        name: String,
+        duration: Duration,
+        /// The source around the locations the check's diagnostics point at, rendered for
+        /// display, if the tool didn't quote it itself.
+        source: Option<String>,
+    },
 
     /// A model request has started
-    PromptStart(String),
+    PromptStart {
+        /// The name of the model
+        model: String,
+        /// The step being prompted for
+        step: StepId,
+    },
     /// A model request has completed
-    PromptEnd(String),
+    PromptEnd {
+        model: String,
+        step: StepId,
+        duration: Duration,
+    },
     /// We've been throttled for a given number of milliseconds
     Throttled(u64),
+    /// A model request is queued by the rate limiter, with a description of why
+    Queued(String),
 
     /// A snippet of output text received from a model
     Snippet(String),
     /// A a complete, non-streamed response was received from a model
     ModelResponse(String),
     /// Patch application has started
-    ApplyPatch,
+    PatchApplyStart {
+        step: StepId,
+        /// The files affected by the patch
+        files: Vec<PathBuf>,
+    },
+    /// A file was written or removed while applying a patch
+    FileWritten(PathBuf),
+    /// Patch application has ended
+    PatchApplyEnd {
+        /// The files written or removed, in the order they were written
+        files: Vec<PathBuf>,
+        duration: Duration,
+    },
 
     /// The command has started
     Start,
 
+    /// A run of steps has ended, successfully or not
+    SessionStats(SessionStats),
+
     /// The command has finished successfully
     Finish,
 
@@ -85,6 +229,13 @@ pub enum Event {
 
     /// We've hit a limit on the number of iterations
     IterationLimit,
+    /// The run's time budget ran out, with a summary of what it attempted
+    TimeBudget(String),
+
+    /// The model asked the user a question, which the next prompt should answer
+    AskUser(String),
+    /// The model stopped work on the action, with a reason
+    Aborted(String),
 
     /// A log message with a specified log level
     Log(LogLevel, String),
@@ -95,6 +246,8 @@ pub enum Event {
         user: String,
         /// The full message sent to the model
         model: String,
+        /// The step that led to the next step
+        step: StepId,
     },
     /// A fatal error has occurred
     Fatal(String),
@@ -110,7 +263,8 @@ impl Event {
     pub fn progress_event(&self) -> Option<String> {
         match self {
             Event::ContextRefreshStart(s) => Some(s.clone()),
-            Event::CheckStart(s) => Some(s.clone()),
+            Event::CheckStart { name, .. } => Some(name.clone()),
+            Event::FileWritten(path) => Some(path.display().to_string()),
             _ => None,
         }
     }
@@ -118,42 +272,114 @@ impl Event {
     /// If this event is a section header, return a string description
     pub fn header_message(&self) -> Option<String> {
         match self {
-            Event::ApplyPatch => Some("applying patch".to_string()),
+            Event::PatchApplyStart { .. } => Some("applying patch".to_string()),
             Event::ContextStart => Some("preparing context".to_string()),
             Event::PreCheckStart => Some("pre checks".to_string()),
             Event::PostCheckStart => Some("post checks".to_string()),
-            Event::PromptStart(n) => Some(format!("prompting {}", n)),
+            Event::PromptStart { model, .. } => Some(format!("prompting {}", model)),
             _ => None,
         }
     }
 
-    /// Returns the enclosed string if any, otherwise an empty string
+    /// Returns a one-line description of the event's payload, or an empty string if there is
+    /// none. This is for simple consumers that just print events - richer consumers should use the
+    /// structured payloads directly.
     pub fn display(&self) -> String {
         match self {
-            Event::Snippet(s) | Event::CheckStart(s) => s.clone(),
-            Event::Log(_, s) => s.clone(),
+            Event::Snippet(s)
+            | Event::Log(_, s)
+            | Event::Queued(s)
+            | Event::PromptLint(s)
+            | Event::AskUser(s)
+            | Event::Aborted(s)
+            | Event::TimeBudget(s)
+            | Event::Fatal(s)
+            | Event::ContextRefreshStart(s)
+            | Event::ContextRefreshEnd(s) => s.clone(),
+            Event::CheckStart { name, .. } => name.clone(),
+            Event::CheckSkipped { name, reason } => format!("{}: {}", name, reason),
+            Event::CheckOk { name, duration } | Event::CheckFailed { name, duration, .. } => {
+                format!("{} ({:.1}s)", name, duration.as_secs_f64())
+            }
+            Event::PromptStart { model, step } => {
+                format!("{} (step {}:{})", model, step.action, step.step)
+            }
+            Event::PromptEnd {
+                model,
+                step,
+                duration,
+            } => format!(
+                "{} (step {}:{}, {:.1}s)",
+                model,
+                step.action,
+                step.step,
+                duration.as_secs_f64()
+            ),
+            Event::PatchApplyStart { files, .. } => files
+                .iter()
+                .map(|f| f.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            Event::FileWritten(path) => path.display().to_string(),
+            Event::PatchApplyEnd { files, duration } => {
+                format!("{} files ({:.1}s)", files.len(), duration.as_secs_f64())
+            }
+            Event::Throttled(ms) => format!("{}ms", ms),
+            Event::ConfigReloaded(changes) => changes.join(", "),
+            Event::ContextCut {
+                source,
+                tokens,
+                kept,
+            } => {
+                if *kept == 0 {
+                    format!("{}: dropped ~{} tokens", source, tokens)
+                } else {
+                    format!("{}: kept ~{} of ~{} tokens", source, kept, tokens)
+                }
+            }
+            Event::Redacted { source, secrets } => format!(
+                "{}: {}",
+                source,
+                secrets
+                    .iter()
+                    .map(|(kind, line)| format!("{} on line {}", kind, line))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Event::NextStep { user, .. } => user.clone(),
+            Event::SessionStats(stats) => stats.lines().join("; "),
             _ => String::new(),
         }
     }
 
+    /// Returns the event with its duration set, for events that record one.
+    fn with_duration(mut self, elapsed: Duration) -> Self {
+        if let Event::PromptEnd { duration, .. } = &mut self {
+            *duration = elapsed;
+        }
+        self
+    }
+
     /// Returns an optional String if there's a commencement message related to the event
     pub fn step_start_message(&self) -> Option<String> {
         match self {
             Event::PreCheckStart => Some("Pre checks...".to_string()),
             Event::PostCheckStart => Some("Post checks...".to_string()),
-            Event::CheckStart(name) => Some(format!("Check {}...", name)),
-            Event::PromptStart(model) => Some(format!("Prompting {}...", model)),
-            Event::ApplyPatch => Some("Applying patch...".to_string()),
+            Event::CheckStart { name, .. } => Some(format!("Check {}...", name)),
+            Event::PromptStart { model, .. } => Some(format!("Prompting {}...", model)),
+            Event::PatchApplyStart { .. } => Some("Applying patch...".to_string()),
             Event::IterationLimit => Some("Step limit reached".to_string()),
             _ => None,
         }
     }
 }
 
-/// Helper struct to manage event sequencing
+/// Helper struct to manage event sequencing. End events that record a duration are sent with the
+/// time elapsed since the block started.
 pub struct EventBlock {
     sender: Option<EventSender>,
     end_event: Event,
+    started: Instant,
 }
 
 impl EventBlock {
@@ -163,6 +389,7 @@ impl EventBlock {
         Ok(Self {
             sender: sender.clone(),
             end_event,
+            started: Instant::now(),
         })
     }
 
@@ -190,32 +417,31 @@ impl EventBlock {
         Self::new(sender, Event::PreCheckStart, Event::PreCheckEnd)
     }
 
-    /// Creates a new EventBlock for validator operations
-    pub fn check(sender: &Option<EventSender>, name: &str) -> Result<Self> {
-        Self::new(
-            sender,
-            Event::CheckStart(name.to_string()),
-            Event::CheckOk(name.to_string()),
-        )
-    }
-
     /// Creates a new EventBlock for post-patch validation operations
     pub fn post_check(sender: &Option<EventSender>) -> Result<Self> {
         Self::new(sender, Event::PostCheckStart, Event::PostCheckEnd)
     }
 
     /// Creates a new EventBlock for model request operations
-    pub fn prompt(sender: &Option<EventSender>, model: &str) -> Result<Self> {
+    pub fn prompt(sender: &Option<EventSender>, model: &str, step: StepId) -> Result<Self> {
         Self::new(
             sender,
-            Event::PromptStart(model.to_string()),
-            Event::PromptEnd(model.to_string()),
+            Event::PromptStart {
+                model: model.to_string(),
+                step: step.clone(),
+            },
+            Event::PromptEnd {
+                model: model.to_string(),
+                step,
+                duration: Duration::ZERO,
+            },
         )
     }
 }
 
 impl Drop for EventBlock {
     fn drop(&mut self) {
-        let _ = send_event(&self.sender, self.end_event.clone());
+        let end_event = self.end_event.clone().with_duration(self.started.elapsed());
+        let _ = send_event(&self.sender, end_event);
     }
 }
@@ -1,17 +1,57 @@
 //! Execute shell commands and return status, stdout and stderr.
+//!
+//! Commands are run through the platform shell: `sh -c` on Unix, and `cmd /C` on Windows.
 use std::{
-    path::Path,
-    process::{Command, ExitStatus},
+    env,
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    thread,
+    time::{Duration, Instant},
 };
 
 use crate::error::{Result, TenxError};
 
+/// The platform shell, and the flag used to pass it a command string.
+#[cfg(windows)]
+const SHELL: (&str, &str) = ("cmd", "/C");
+#[cfg(not(windows))]
+const SHELL: (&str, &str) = ("sh", "-c");
+
+/// Build a command that runs a command string through the platform shell.
+pub fn shell_command(cmd: &str) -> Command {
+    let mut command = Command::new(SHELL.0);
+    command.arg(SHELL.1).arg(cmd);
+    command
+}
+
+/// Returns the executable file name for a program on this platform, adding the platform's
+/// executable suffix (e.g. `.exe` on Windows) if the name doesn't already have an extension.
+pub fn exe_name(name: &str) -> String {
+    if Path::new(name).extension().is_some() {
+        name.to_string()
+    } else {
+        format!("{}{}", name, env::consts::EXE_SUFFIX)
+    }
+}
+
+/// Find a program on the `PATH`, returning the full path to the executable if it exists. Names
+/// that contain a path separator are checked directly rather than searched for.
+pub fn find_program(name: &str) -> Option<PathBuf> {
+    let exe = exe_name(name);
+    if Path::new(&exe).components().count() > 1 {
+        let p = PathBuf::from(exe);
+        return p.is_file().then_some(p);
+    }
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(&exe))
+        .find(|p| p.is_file())
+}
+
 /// Execute a shell command and return status, stdout and stderr, with ANSI escapes removed.
 /// The command is run in the specified root directory.
 pub fn exec<P: AsRef<Path>>(root: P, cmd: &str) -> Result<(ExitStatus, String, String)> {
-    let output = Command::new("sh")
-        .arg("-c")
-        .arg(cmd)
+    let output = shell_command(cmd)
         .current_dir(root)
         .output()
         .map_err(|e| TenxError::Exec {
@@ -28,6 +68,243 @@ pub fn exec<P: AsRef<Path>>(root: P, cmd: &str) -> Result<(ExitStatus, String, S
     Ok((output.status, stdout, stderr))
 }
 
+/// A spawned shell together with every process it starts, so that a command like
+/// `cargo clippy && cargo test` can be killed as a whole rather than just its shell. On Unix the
+/// shell leads its own process group; on Windows it's assigned to a job object.
+struct ProcessGroup {
+    child: Child,
+    #[cfg(windows)]
+    job: windows_sys::Win32::Foundation::HANDLE,
+}
+
+impl ProcessGroup {
+    #[cfg(unix)]
+    fn spawn(command: &mut Command) -> std::io::Result<Self> {
+        use std::os::unix::process::CommandExt;
+        let child = command.process_group(0).spawn()?;
+        Ok(Self { child })
+    }
+
+    #[cfg(windows)]
+    fn spawn(command: &mut Command) -> std::io::Result<Self> {
+        use std::os::windows::io::AsRawHandle;
+        use windows_sys::Win32::{
+            Foundation::CloseHandle,
+            System::JobObjects::{AssignProcessToJobObject, CreateJobObjectW},
+        };
+        let job = unsafe { CreateJobObjectW(std::ptr::null(), std::ptr::null()) };
+        if job.is_null() {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                unsafe { CloseHandle(job) };
+                return Err(e);
+            }
+        };
+        if unsafe { AssignProcessToJobObject(job, child.as_raw_handle() as _) } == 0 {
+            let e = std::io::Error::last_os_error();
+            let _ = child.kill();
+            let _ = child.wait();
+            unsafe { CloseHandle(job) };
+            return Err(e);
+        }
+        Ok(Self { child, job })
+    }
+
+    /// Kills the shell and everything it started, and reaps the shell.
+    fn kill(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            libc::kill(-(self.child.id() as libc::pid_t), libc::SIGKILL);
+        }
+        #[cfg(windows)]
+        unsafe {
+            windows_sys::Win32::System::JobObjects::TerminateJobObject(self.job, 1);
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(windows)]
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        unsafe { windows_sys::Win32::Foundation::CloseHandle(self.job) };
+    }
+}
+
+/// Execute a shell command like `exec`, killing it and every process it started if it runs for
+/// longer than `timeout`. A command that times out is an execution error.
+pub fn exec_timeout<P: AsRef<Path>>(
+    root: P,
+    cmd: &str,
+    timeout: Duration,
+) -> Result<(ExitStatus, String, String)> {
+    let err = |e: std::io::Error| TenxError::Exec {
+        cmd: cmd.to_string(),
+        error: e.to_string(),
+    };
+    let mut group = ProcessGroup::spawn(
+        shell_command(cmd)
+            .current_dir(root)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+    )
+    .map_err(err)?;
+    // Pipes are drained as the command runs, so it can't block on a full pipe
+    let drain = |pipe: Option<Box<dyn Read + Send>>| {
+        thread::spawn(move || {
+            let mut buf = vec![];
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut buf);
+            }
+            buf
+        })
+    };
+    let stdout = drain(group.child.stdout.take().map(|p| Box::new(p) as _));
+    let stderr = drain(group.child.stderr.take().map(|p| Box::new(p) as _));
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = group.child.try_wait().map_err(err)? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            group.kill();
+            // With the whole group gone the pipes are closed, so the drains finish
+            let _ = stdout.join();
+            let _ = stderr.join();
+            return Err(TenxError::Exec {
+                cmd: cmd.to_string(),
+                error: format!("timed out after {}s", timeout.as_secs()),
+            });
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    let text = |bytes: Vec<u8>| {
+        String::from_utf8_lossy(&strip_ansi_escapes::strip(bytes))
+            .trim()
+            .to_string()
+    };
+    Ok((
+        status,
+        text(stdout.join().unwrap_or_default()),
+        text(stderr.join().unwrap_or_default()),
+    ))
+}
+
+/// The captured result of running a command.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExecOutput {
+    /// The exit code, or None if the process was terminated by a signal
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl ExecOutput {
+    /// Creates an output with the given exit code.
+    pub fn new(code: i32, stdout: &str, stderr: &str) -> Self {
+        Self {
+            code: Some(code),
+            stdout: stdout.to_string(),
+            stderr: stderr.to_string(),
+        }
+    }
+
+    /// Did the command exit successfully?
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+/// Runs shell commands on behalf of checks. Checks never spawn processes directly, so tests can
+/// substitute a `FakeExecutor`.
+pub trait Executor {
+    /// Runs a command in the given directory, with output processed as for `exec`.
+    fn exec(&self, root: &Path, cmd: &str) -> Result<ExecOutput>;
+
+    /// Runs a command like `exec`, failing with an execution error if it runs for longer than
+    /// `timeout`. Executors that can't time commands out just run them.
+    fn exec_timeout(
+        &self,
+        root: &Path,
+        cmd: &str,
+        timeout: Option<Duration>,
+    ) -> Result<ExecOutput> {
+        let _ = timeout;
+        self.exec(root, cmd)
+    }
+}
+
+/// An executor that runs commands through the platform shell.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShellExecutor;
+
+impl Executor for ShellExecutor {
+    fn exec(&self, root: &Path, cmd: &str) -> Result<ExecOutput> {
+        self.exec_timeout(root, cmd, None)
+    }
+
+    fn exec_timeout(
+        &self,
+        root: &Path,
+        cmd: &str,
+        timeout: Option<Duration>,
+    ) -> Result<ExecOutput> {
+        let (status, stdout, stderr) = match timeout {
+            Some(timeout) => exec_timeout(root, cmd, timeout)?,
+            None => exec(root, cmd)?,
+        };
+        Ok(ExecOutput {
+            code: status.code(),
+            stdout,
+            stderr,
+        })
+    }
+}
+
+/// An executor for tests that returns canned results instead of running commands. Each command
+/// gets the result of the first registered prefix it starts with, and commands with no matching
+/// prefix fail as if the program couldn't be started.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FakeExecutor {
+    results: Vec<(String, std::result::Result<ExecOutput, String>)>,
+}
+
+impl FakeExecutor {
+    /// Returns the given output for commands starting with a prefix.
+    pub fn with_output(mut self, prefix: &str, output: ExecOutput) -> Self {
+        self.results.push((prefix.to_string(), Ok(output)));
+        self
+    }
+
+    /// Fails commands starting with a prefix with an execution error, for instance to simulate a
+    /// timeout.
+    pub fn with_error(mut self, prefix: &str, error: &str) -> Self {
+        self.results
+            .push((prefix.to_string(), Err(error.to_string())));
+        self
+    }
+}
+
+impl Executor for FakeExecutor {
+    fn exec(&self, _root: &Path, cmd: &str) -> Result<ExecOutput> {
+        let result = self
+            .results
+            .iter()
+            .find(|(prefix, _)| cmd.starts_with(prefix.as_str()))
+            .map(|(_, result)| result.clone())
+            .unwrap_or_else(|| Err("program not found".to_string()));
+        result.map_err(|error| TenxError::Exec {
+            cmd: cmd.to_string(),
+            error,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -38,13 +315,13 @@ mod tests {
         let cwd = current_dir().unwrap();
 
         // Test successful command with stdout
-        let (status, stdout, stderr) = exec(&cwd, "echo 'hello'").unwrap();
+        let (status, stdout, stderr) = exec(&cwd, "echo hello").unwrap();
         assert!(status.success());
         assert_eq!(stdout, "hello");
         assert_eq!(stderr, "");
 
         // Test command with stderr
-        let (status, stdout, stderr) = exec(&cwd, "echo 'error' >&2").unwrap();
+        let (status, stdout, stderr) = exec(&cwd, "echo error 1>&2").unwrap();
         assert!(status.success());
         assert_eq!(stdout, "");
         assert_eq!(stderr, "error");
@@ -55,4 +332,56 @@ mod tests {
         assert_eq!(stdout, "");
         assert_eq!(stderr, "");
     }
+
+    #[test]
+    fn test_exec_timeout() {
+        let cwd = current_dir().unwrap();
+        let (status, stdout, _) =
+            exec_timeout(&cwd, "echo hello", Duration::from_secs(10)).unwrap();
+        assert!(status.success());
+        assert_eq!(stdout, "hello");
+
+        let start = Instant::now();
+        let cmd = if cfg!(windows) {
+            "ping -n 30 127.0.0.1"
+        } else {
+            "sleep 30"
+        };
+        assert!(matches!(
+            exec_timeout(&cwd, cmd, Duration::from_millis(200)),
+            Err(TenxError::Exec { error, .. }) if error.starts_with("timed out")
+        ));
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exec_timeout_kills_group() {
+        // The shell forks sleep, which holds the output pipes open unless it's killed too
+        let start = Instant::now();
+        assert!(exec_timeout(
+            current_dir().unwrap(),
+            "sleep 30 && echo done",
+            Duration::from_millis(200)
+        )
+        .is_err());
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_exe_name() {
+        assert_eq!(
+            exe_name("cargo"),
+            format!("cargo{}", env::consts::EXE_SUFFIX)
+        );
+        assert_eq!(exe_name("script.sh"), "script.sh");
+        assert_eq!(exe_name("tool.exe"), "tool.exe");
+    }
+
+    #[test]
+    fn test_find_program() {
+        assert!(find_program(SHELL.0).is_some());
+        assert!(find_program("tenx-no-such-program").is_none());
+        assert!(find_program("no/such/program").is_none());
+    }
 }
@@ -1,18 +1,62 @@
-pub mod checks;
-pub mod config;
-pub mod context;
-pub mod dialect;
-pub mod error;
-pub mod event_consumers;
-pub mod events;
-pub mod model;
-pub mod session;
-pub mod session_store;
-pub mod strategy;
-mod tenx;
+//! A library for building AI-assisted coding tools.
+//!
+//! Integrators should use the [`api`] module, which is the crate's stable public surface. The
+//! other modules are private, except that the `internal` feature exposes the ones the tenx
+//! command line tools use. Those may change in any release.
+
+/// Declares modules that are public with the `internal` feature, and private to the crate
+/// otherwise. Items that only the command line tools use are marked
+/// `#[cfg_attr(not(feature = "internal"), allow(dead_code))]` where they're defined.
+macro_rules! internal {
+    ($($name:ident),* $(,)?) => {
+        $(
+            #[cfg(feature = "internal")]
+            #[doc(hidden)]
+            pub mod $name;
+            #[cfg(not(feature = "internal"))]
+            mod $name;
+        )*
+    };
+}
+
+pub mod api;
+#[cfg(any(test, feature = "test-util"))]
+pub mod snapshot;
+#[cfg(any(test, feature = "test-util"))]
+#[doc(hidden)]
 pub mod testutils;
 
+internal!(
+    checks,
+    config,
+    context,
+    credentials,
+    dialect,
+    error,
+    event_consumers,
+    events,
+    model,
+    porcelain,
+    recipe,
+    sarif,
+    search,
+    session,
+    session_store,
+    strategy,
+    usage,
+);
+
+mod checkpoint;
+mod diagnostics;
 mod exec;
+mod lint;
+mod postprocess;
+mod python;
+mod risk;
+mod scrub;
+mod stuck;
+mod symbols;
+mod tenx;
 mod throttle;
 
-pub use tenx::*;
+pub use tenx::{RiskConfirm, StepConfirm, StepDecision, Tenx};
@@ -1,13 +1,15 @@
 //! This module implements the Claude model provider for the tenx system.
 use std::{collections::HashMap, convert::From};
 
-use misanthropy::{Anthropic, Content, ContentBlockDelta, Role, StreamEvent};
+use misanthropy::{Anthropic, Content, ContentBlockDelta, Role, StopReason, StreamEvent};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json;
 use tracing::{trace, warn};
 
 use crate::{
-    config::Config,
+    config::{Backend, Config, RateLimit, Sampling},
+    credentials,
     dialect::{Dialect, DialectProvider},
     error::{Result, TenxError},
     events::*,
@@ -34,6 +36,12 @@ pub struct ClaudeChat {
     pub anthropic_key: String,
     /// Whether to stream responses
     pub streaming: bool,
+    /// The maximum number of continuation requests to make for a truncated response
+    pub max_continuations: usize,
+    /// Where requests are sent
+    pub backend: Backend,
+    /// The provider kind and its rate limits, if any are configured
+    pub rate_limit: Option<(String, RateLimit)>,
     /// The messages request being built
     request: misanthropy::MessagesRequest,
 }
@@ -68,25 +76,61 @@ impl ClaudeChat {
         Ok(streamed_response.response)
     }
 
-    fn extract_changes(
+    /// Returns the text content of a response.
+    fn response_text(resp: &misanthropy::MessagesResponse) -> String {
+        resp.content
+            .iter()
+            .filter_map(|c| match c {
+                Content::Text(text) => Some(text.text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn extract_changes(&self, dialect: &Dialect, text: &str) -> Result<ModelResponse> {
+        if text.is_empty() {
+            // We are seeing this happen fairly frequently with the Anthropic API.
+            return Err(TenxError::Throttle(Throttle::Backoff));
+        }
+        dialect.parse(text)
+    }
+
+    /// Send the current request to the model, either streaming or not.
+    async fn send_request(
         &self,
-        dialect: &Dialect,
-        req: &misanthropy::MessagesRequest,
-    ) -> Result<ModelResponse> {
-        if let Some(message) = &req.messages.last() {
-            if message.role == Role::Assistant {
-                if message.content.is_empty() {
-                    // We are seeing this happen fairly frequently with the Anthropic API.
-                    return Err(TenxError::Throttle(Throttle::Backoff));
-                }
-                for content in &message.content {
-                    if let Content::Text(text) = content {
-                        return dialect.parse(&text.text);
-                    }
-                }
+        sender: &Option<EventSender>,
+    ) -> Result<misanthropy::MessagesResponse> {
+        trace!(
+            "Sending request ({} bytes): {}",
+            serde_json::to_vec(&self.request)?.len(),
+            serde_json::to_string_pretty(&self.request)?
+        );
+        let resp = if self.backend != Backend::Direct {
+            let resp: misanthropy::MessagesResponse = serde_json::from_value(
+                super::cloud::send_claude(
+                    &self.backend,
+                    &self.api_model,
+                    serde_json::to_value(&self.request)?,
+                )
+                .await?,
+            )?;
+            if let Some(text) = resp.format_content().into() {
+                send_event(sender, Event::ModelResponse(text))?;
             }
-        }
-        Err(TenxError::Internal("No patch to parse.".into()))
+            resp
+        } else if self.streaming {
+            self.stream_response(self.anthropic_key.clone(), &self.request, sender.clone())
+                .await?
+        } else {
+            let anthropic = Anthropic::new(&self.anthropic_key);
+            let resp = anthropic.messages(&self.request).await?;
+            if let Some(text) = resp.format_content().into() {
+                send_event(sender, Event::ModelResponse(text))?;
+            }
+            resp
+        };
+        trace!("Got response: {}", serde_json::to_string_pretty(&resp)?);
+        Ok(resp)
     }
 
     fn append_last_message(&mut self, data: &str) -> Result<()> {
@@ -147,49 +191,57 @@ impl Chat for ClaudeChat {
         self.append_last_message(data)
     }
 
+    /// Sets temperature, max tokens and stop sequences. The messages request doesn't expose
+    /// `top_p`, so it's ignored.
+    fn set_sampling(&mut self, sampling: &Sampling) -> Result<()> {
+        self.request.temperature = sampling.temperature;
+        self.request.max_tokens = sampling.max_tokens.unwrap_or(MAX_TOKENS);
+        self.request.stop_sequences = sampling.stop.clone();
+        Ok(())
+    }
+
     async fn send(&mut self, sender: Option<EventSender>) -> Result<ModelResponse> {
-        if self.anthropic_key.is_empty() {
-            return Err(TenxError::Model(
-                "No Anthropic key configured for Claude model.".into(),
-            ));
+        if self.anthropic_key.is_empty() && self.backend == Backend::Direct {
+            return Err(credentials::missing_key("claude"));
         }
 
         self.request.model = self.api_model.clone();
-        self.request.max_tokens = MAX_TOKENS;
-        self.request.stream = self.streaming;
+        // Cloud backends don't stream
+        self.request.stream = self.streaming && self.backend == Backend::Direct;
 
-        trace!(
-            "Sending request: {}",
-            serde_json::to_string_pretty(&self.request)?
-        );
+        let mut text = String::new();
+        let mut usage = ClaudeUsage::default();
+        let mut continuations = 0;
+        loop {
+            let permit = super::limiter::acquire_for(&self.rate_limit, &sender).await?;
+            let resp = self.send_request(&sender).await?;
+            let mut used = ClaudeUsage::default();
+            used.add(&resp.usage);
+            let (tokens_in, tokens_out) = used.totals();
+            permit.record(tokens_in + tokens_out);
+            self.request.merge_response(&resp);
+            usage.add(&resp.usage);
+            text = super::stitch(&text, &Self::response_text(&resp));
 
-        let resp = if self.streaming {
-            self.stream_response(self.anthropic_key.clone(), &self.request, sender.clone())
-                .await?
-        } else {
-            let anthropic = Anthropic::new(&self.anthropic_key);
-            let resp = anthropic.messages(&self.request).await?;
-            if let Some(text) = resp.format_content().into() {
-                send_event(&sender, Event::ModelResponse(text))?;
+            if !matches!(resp.stop_reason, Some(StopReason::MaxTokens))
+                || continuations >= self.max_continuations
+            {
+                break;
             }
-            resp
-        };
-
-        trace!("Got response: {}", serde_json::to_string_pretty(&resp)?);
-
-        self.request.merge_response(&resp);
+            continuations += 1;
+            trace!(
+                "Response truncated at max tokens, requesting continuation {}",
+                continuations
+            );
+            self.add_user_message(super::CONTINUATION_PROMPT)?;
+        }
 
         // Get dialect from config
         let config = Config::default();
         let dialect = config.dialect()?;
 
-        let mut modresp = self.extract_changes(&dialect, &self.request)?;
-        modresp.usage = Some(super::Usage::Claude(ClaudeUsage {
-            input_tokens: resp.usage.input_tokens,
-            output_tokens: resp.usage.output_tokens,
-            cache_creation_input_tokens: resp.usage.cache_creation_input_tokens,
-            cache_read_input_tokens: resp.usage.cache_read_input_tokens,
-        }));
+        let mut modresp = self.extract_changes(&dialect, &text)?;
+        modresp.usage = Some(super::Usage::Claude(usage));
         Ok(modresp)
     }
 
@@ -215,10 +267,16 @@ pub struct Claude {
     pub anthropic_key: String,
     /// Whether to stream responses
     pub streaming: bool,
+    /// The maximum number of continuation requests to make for a truncated response
+    pub max_continuations: usize,
+    /// Where requests are sent
+    pub backend: Backend,
+    /// The provider kind and its rate limits, if any are configured
+    pub rate_limit: Option<(String, RateLimit)>,
 }
 
 /// Mirrors the Usage struct from misanthropy to track token usage statistics.
-#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, JsonSchema)]
 pub struct ClaudeUsage {
     pub input_tokens: Option<u32>,
     pub output_tokens: Option<u32>,
@@ -227,6 +285,24 @@ pub struct ClaudeUsage {
 }
 
 impl ClaudeUsage {
+    /// Accumulate usage from a response, used when a response spans multiple requests.
+    fn add(&mut self, usage: &misanthropy::Usage) {
+        fn sum(a: Option<u32>, b: Option<u32>) -> Option<u32> {
+            match (a, b) {
+                (None, None) => None,
+                (a, b) => Some(a.unwrap_or(0) + b.unwrap_or(0)),
+            }
+        }
+        self.input_tokens = sum(self.input_tokens, usage.input_tokens);
+        self.output_tokens = sum(self.output_tokens, usage.output_tokens);
+        self.cache_creation_input_tokens = sum(
+            self.cache_creation_input_tokens,
+            usage.cache_creation_input_tokens,
+        );
+        self.cache_read_input_tokens =
+            sum(self.cache_read_input_tokens, usage.cache_read_input_tokens);
+    }
+
     pub fn values(&self) -> HashMap<String, u64> {
         let mut map = HashMap::new();
         if let Some(input_tokens) = self.input_tokens {
@@ -276,6 +352,9 @@ impl ModelProvider for Claude {
             api_model: self.api_model.clone(),
             anthropic_key: self.anthropic_key.clone(),
             streaming: self.streaming,
+            max_continuations: self.max_continuations,
+            backend: self.backend.clone(),
+            rate_limit: self.rate_limit.clone(),
             request: misanthropy::MessagesRequest {
                 model: self.api_model.clone(),
                 max_tokens: MAX_TOKENS,
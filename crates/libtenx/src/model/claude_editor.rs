@@ -5,6 +5,7 @@ use tracing::{trace, warn};
 
 use super::claude::ClaudeUsage;
 use crate::{
+    credentials,
     error::{Result, TenxError},
     events::*,
     model::ModelProvider,
@@ -136,6 +137,7 @@ impl ClaudeEditorChat {
             patch: if !patch.is_empty() { Some(patch) } else { None },
             operations: vec![],
             comment,
+            summary: None,
             usage: None,
             raw_response: Some(last_message.format_content()),
         })
@@ -183,9 +185,7 @@ impl Chat for ClaudeEditorChat {
 
     async fn send(&mut self, sender: Option<EventSender>) -> Result<ModelResponse> {
         if self.anthropic_key.is_empty() {
-            return Err(TenxError::Model(
-                "No Anthropic key configured for Claude model.".into(),
-            ));
+            return Err(credentials::missing_key("claude"));
         }
 
         self.request.model = self.api_model.clone();
@@ -193,7 +193,8 @@ impl Chat for ClaudeEditorChat {
         self.request.stream = self.streaming;
 
         trace!(
-            "Sending request: {}",
+            "Sending request ({} bytes): {}",
+            serde_json::to_vec(&self.request)?.len(),
             serde_json::to_string_pretty(&self.request)?
         );
 
@@ -0,0 +1,378 @@
+//! Requests to models hosted on cloud platforms: AWS Bedrock, authenticated with SigV4, and GCP
+//! Vertex AI, authenticated with application default credentials. The request bodies are built
+//! by the Claude and Google chats, so this module only handles endpoints and authentication.
+//!
+//! Responses from cloud backends are not streamed.
+use std::{
+    env,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use ring::{digest, hmac};
+use serde_json::Value;
+use tracing::warn;
+
+use crate::{
+    config::Backend,
+    error::{Result, TenxError},
+    exec::find_program,
+};
+
+/// The `anthropic_version` Bedrock expects in Claude requests.
+const BEDROCK_ANTHROPIC_VERSION: &str = "bedrock-2023-05-31";
+
+/// The `anthropic_version` Vertex expects in Claude requests.
+const VERTEX_ANTHROPIC_VERSION: &str = "vertex-2023-10-16";
+
+/// AWS credentials, read from the standard environment variables.
+#[derive(Debug, Clone)]
+pub(crate) struct AwsCredentials {
+    pub access_key: String,
+    pub secret_key: String,
+    pub session_token: Option<String>,
+}
+
+impl AwsCredentials {
+    fn from_env() -> Result<Self> {
+        let var = |name: &str| env::var(name).ok().filter(|v| !v.is_empty());
+        match (var("AWS_ACCESS_KEY_ID"), var("AWS_SECRET_ACCESS_KEY")) {
+            (Some(access_key), Some(secret_key)) => Ok(Self {
+                access_key,
+                secret_key,
+                session_token: var("AWS_SESSION_TOKEN"),
+            }),
+            _ => Err(TenxError::Config(
+                "Bedrock requires AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY to be set".into(),
+            )),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex(digest::digest(&digest::SHA256, data).as_ref())
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), data.as_bytes())
+        .as_ref()
+        .to_vec()
+}
+
+/// Percent-encodes everything but RFC 3986 unreserved characters.
+fn uri_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Formats a Unix timestamp as an AWS date-time, like `20150830T123600Z`.
+fn amz_date(secs: u64) -> String {
+    // Days to civil date, from Howard Hinnant's date algorithms
+    let days = (secs / 86400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    let rem = secs % 86400;
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// A request to sign with SigV4. The path must already be URI-encoded, and the headers must
+/// include `host` and `x-amz-date`.
+pub(crate) struct SigV4Request<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub query: &'a str,
+    pub headers: Vec<(String, String)>,
+    pub body: &'a [u8],
+}
+
+/// Returns the SigV4 `Authorization` header value for a request.
+pub(crate) fn sigv4_authorization(
+    req: &SigV4Request,
+    creds: &AwsCredentials,
+    region: &str,
+    service: &str,
+) -> Result<String> {
+    let amz_date = req
+        .headers
+        .iter()
+        .find(|(k, _)| k == "x-amz-date")
+        .map(|(_, v)| v.as_str())
+        .ok_or_else(|| TenxError::Internal("SigV4 request has no x-amz-date".into()))?;
+    let date = &amz_date[..8];
+
+    let mut headers: Vec<(String, String)> = req
+        .headers
+        .iter()
+        .map(|(k, v)| (k.to_lowercase(), v.trim().to_string()))
+        .collect();
+    headers.sort();
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(k, v)| format!("{}:{}\n", k, v))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(k, _)| k.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+    // Outside S3, each path segment is encoded a second time in the canonical request
+    let canonical_path = req
+        .path
+        .split('/')
+        .map(uri_encode)
+        .collect::<Vec<_>>()
+        .join("/");
+    let canonical_request = [
+        req.method,
+        &canonical_path,
+        req.query,
+        &canonical_headers,
+        &signed_headers,
+        &sha256_hex(req.body),
+    ]
+    .join("\n");
+
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = [
+        "AWS4-HMAC-SHA256",
+        amz_date,
+        &scope,
+        &sha256_hex(canonical_request.as_bytes()),
+    ]
+    .join("\n");
+    let mut key = hmac_sha256(format!("AWS4{}", creds.secret_key).as_bytes(), date);
+    for part in [region, service, "aws4_request"] {
+        key = hmac_sha256(&key, part);
+    }
+    Ok(format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        creds.access_key,
+        scope,
+        signed_headers,
+        hex(&hmac_sha256(&key, &string_to_sign))
+    ))
+}
+
+/// Returns an access token from the application default credentials. `GOOGLE_OAUTH_ACCESS_TOKEN`
+/// is used if set, otherwise the token is fetched with `gcloud`.
+fn vertex_token() -> Result<String> {
+    if let Some(token) = env::var("GOOGLE_OAUTH_ACCESS_TOKEN")
+        .ok()
+        .filter(|t| !t.is_empty())
+    {
+        return Ok(token);
+    }
+    let no_token = |detail: String| {
+        TenxError::Config(format!(
+            "Could not get a Vertex AI access token: {}. Run `gcloud auth application-default \
+             login`, or set GOOGLE_OAUTH_ACCESS_TOKEN.",
+            detail
+        ))
+    };
+    if find_program("gcloud").is_none() {
+        return Err(no_token("gcloud not found".into()));
+    }
+    let output = Command::new("gcloud")
+        .args(["auth", "application-default", "print-access-token"])
+        .output()?;
+    if !output.status.success() {
+        return Err(no_token(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Returns the Vertex AI endpoint for a model from the given publisher.
+fn vertex_url(project: &str, region: &str, publisher: &str, model: &str, method: &str) -> String {
+    let host = if region == "global" {
+        "aiplatform.googleapis.com".to_string()
+    } else {
+        format!("{}-aiplatform.googleapis.com", region)
+    };
+    format!(
+        "https://{}/v1/projects/{}/locations/{}/publishers/{}/models/{}:{}",
+        host, project, region, publisher, model, method
+    )
+}
+
+/// Posts a JSON body, returning the parsed JSON response.
+async fn post_json(url: &str, headers: Vec<(String, String)>, body: Vec<u8>) -> Result<Value> {
+    let mut req = reqwest::Client::new()
+        .post(url)
+        .header("content-type", "application/json");
+    for (k, v) in headers {
+        req = req.header(k, v);
+    }
+    let resp = req.body(body).send().await.map_err(TenxError::from)?;
+    let status = resp.status();
+    let text = resp.text().await.map_err(TenxError::from)?;
+    if !status.is_success() {
+        warn!("Cloud API error: {} {}", status, text);
+        return Err(TenxError::provider(
+            Some(status.as_u16()),
+            format!("{}: {}", status, text),
+        ));
+    }
+    Ok(serde_json::from_str(&text)?)
+}
+
+/// Sends a Claude messages request to Bedrock or Vertex AI. The request is the JSON for the
+/// Anthropic API, which we adjust for the platform.
+pub(crate) async fn send_claude(
+    backend: &Backend,
+    model: &str,
+    mut request: Value,
+) -> Result<Value> {
+    let obj = request
+        .as_object_mut()
+        .ok_or_else(|| TenxError::Internal("Claude request is not an object".into()))?;
+    obj.remove("model");
+    obj.remove("stream");
+    match backend {
+        Backend::Direct => Err(TenxError::Internal(
+            "Direct requests don't go through a cloud backend".into(),
+        )),
+        Backend::Bedrock { region } => {
+            obj.insert("anthropic_version".into(), BEDROCK_ANTHROPIC_VERSION.into());
+            let creds = AwsCredentials::from_env()?;
+            let body = serde_json::to_vec(&request)?;
+            let host = format!("bedrock-runtime.{}.amazonaws.com", region);
+            let path = format!("/model/{}/invoke", uri_encode(model));
+            let secs = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|e| TenxError::Internal(e.to_string()))?
+                .as_secs();
+            let mut headers = vec![
+                ("host".to_string(), host.clone()),
+                ("x-amz-date".to_string(), amz_date(secs)),
+            ];
+            if let Some(token) = &creds.session_token {
+                headers.push(("x-amz-security-token".to_string(), token.clone()));
+            }
+            let auth = sigv4_authorization(
+                &SigV4Request {
+                    method: "POST",
+                    path: &path,
+                    query: "",
+                    headers: headers.clone(),
+                    body: &body,
+                },
+                &creds,
+                region,
+                "bedrock",
+            )?;
+            headers.retain(|(k, _)| k != "host");
+            headers.push(("authorization".to_string(), auth));
+            post_json(&format!("https://{}{}", host, path), headers, body).await
+        }
+        Backend::Vertex { project, region } => {
+            obj.insert("anthropic_version".into(), VERTEX_ANTHROPIC_VERSION.into());
+            let url = vertex_url(project, region, "anthropic", model, "rawPredict");
+            let auth = format!("Bearer {}", vertex_token()?);
+            let body = serde_json::to_vec(&request)?;
+            post_json(&url, vec![("authorization".to_string(), auth)], body).await
+        }
+    }
+}
+
+/// Sends a Gemini generateContent request to Vertex AI.
+pub(crate) async fn send_gemini(
+    backend: &Backend,
+    model: &str,
+    mut request: Value,
+) -> Result<Value> {
+    let Backend::Vertex { project, region } = backend else {
+        return Err(TenxError::Config(
+            "Gemini models are only available directly or through Vertex AI".into(),
+        ));
+    };
+    if let Some(obj) = request.as_object_mut() {
+        obj.remove("model");
+    }
+    let url = vertex_url(project, region, "google", model, "generateContent");
+    let auth = format!("Bearer {}", vertex_token()?);
+    post_json(
+        &url,
+        vec![("authorization".to_string(), auth)],
+        serde_json::to_vec(&request)?,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sigv4() -> Result<()> {
+        // The example from the AWS Signature Version 4 documentation
+        let creds = AwsCredentials {
+            access_key: "AKIDEXAMPLE".into(),
+            secret_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".into(),
+            session_token: None,
+        };
+        let auth = sigv4_authorization(
+            &SigV4Request {
+                method: "GET",
+                path: "/",
+                query: "Action=ListUsers&Version=2010-05-08",
+                headers: vec![
+                    (
+                        "Content-Type".into(),
+                        "application/x-www-form-urlencoded; charset=utf-8".into(),
+                    ),
+                    ("Host".into(), "iam.amazonaws.com".into()),
+                    ("x-amz-date".into(), "20150830T123600Z".into()),
+                ],
+                body: b"",
+            },
+            &creds,
+            "us-east-1",
+            "iam",
+        )?;
+        assert_eq!(
+            auth,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date, \
+             Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7"
+        );
+
+        assert_eq!(amz_date(1_440_938_160), "20150830T123600Z");
+        assert_eq!(amz_date(951_782_400), "20000229T000000Z");
+        assert_eq!(
+            uri_encode("anthropic.claude-3-5-sonnet-20241022-v2:0"),
+            "anthropic.claude-3-5-sonnet-20241022-v2%3A0"
+        );
+        assert_eq!(
+            vertex_url("p", "us-east5", "anthropic", "claude", "rawPredict"),
+            "https://us-east5-aiplatform.googleapis.com/v1/projects/p/locations/us-east5/\
+             publishers/anthropic/models/claude:rawPredict"
+        );
+        Ok(())
+    }
+}
@@ -1,14 +1,16 @@
 use async_trait::async_trait;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use super::{Chat, ModelProvider};
 use crate::{error::Result, events::EventSender, session::ModelResponse};
 
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
 /// A dummy chat implementation for testing purposes.
 pub struct DummyChat {
     model_response: Result<ModelResponse>,
+    delay: Option<Duration>,
 }
 
 #[async_trait]
@@ -34,6 +36,9 @@ impl Chat for DummyChat {
     }
 
     async fn send(&mut self, _sender: Option<EventSender>) -> Result<ModelResponse> {
+        if let Some(delay) = self.delay {
+            tokio::time::sleep(delay).await;
+        }
         let mut resp = self.model_response.clone()?;
         resp.usage = Some(super::Usage::Dummy(DummyUsage { dummy_counter: 1 }));
         Ok(resp)
@@ -45,7 +50,7 @@ impl Chat for DummyChat {
 }
 
 /// A dummy usage struct for testing purposes.
-#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, JsonSchema)]
 pub struct DummyUsage {
     pub dummy_counter: u32,
 }
@@ -66,6 +71,7 @@ impl DummyUsage {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DummyModel {
     model_response: Result<ModelResponse>,
+    delay: Option<Duration>,
 }
 
 impl DummyModel {
@@ -73,14 +79,22 @@ impl DummyModel {
     pub fn from_model_response(mr: ModelResponse) -> Self {
         Self {
             model_response: Ok(mr),
+            delay: None,
         }
     }
+
+    /// Delays each response, to simulate a slow model.
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
 }
 
 impl Default for DummyModel {
     fn default() -> Self {
         Self {
             model_response: Ok(ModelResponse::default()),
+            delay: None,
         }
     }
 }
@@ -98,6 +112,7 @@ impl ModelProvider for DummyModel {
     fn chat(&self) -> Option<Box<dyn Chat>> {
         Some(Box::new(DummyChat {
             model_response: self.model_response.clone(),
+            delay: self.delay,
         }))
     }
 }
@@ -0,0 +1,184 @@
+//! A model that never leaves the machine. It records each rendered request and answers with a
+//! canned or file-supplied response, which lets us develop dialects, parsers and the event
+//! pipeline offline, without API keys. Downstream users can configure it in integration tests.
+use std::{collections::HashMap, path::PathBuf};
+
+use async_trait::async_trait;
+use fs_err as fs;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{estimate_tokens, Chat, ModelProvider, TextChat};
+use crate::{
+    config::Config,
+    dialect::DialectProvider,
+    error::Result,
+    events::{send_event, Event, EventSender},
+    session::ModelResponse,
+};
+
+/// Estimated token usage for an echo model, since no tokenizer is involved.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, JsonSchema)]
+pub struct EchoUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+impl EchoUsage {
+    pub fn values(&self) -> HashMap<String, u64> {
+        let mut map = HashMap::new();
+        map.insert("input_tokens".to_string(), self.input_tokens);
+        map.insert("output_tokens".to_string(), self.output_tokens);
+        map
+    }
+
+    pub fn totals(&self) -> (u64, u64) {
+        (self.input_tokens, self.output_tokens)
+    }
+}
+
+/// A chat that renders the request as plain text, and replies with a fixed response.
+pub struct EchoChat {
+    text: TextChat,
+    response: String,
+    response_file: PathBuf,
+    record_dir: PathBuf,
+}
+
+impl EchoChat {
+    /// Writes the rendered request to the next free numbered file in the record directory.
+    fn record(&self, request: &str) -> Result<()> {
+        fs::create_dir_all(&self.record_dir)?;
+        let n = fs::read_dir(&self.record_dir)?.count();
+        fs::write(
+            self.record_dir.join(format!("request-{:04}.txt", n)),
+            request,
+        )?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Chat for EchoChat {
+    fn add_system_prompt(&mut self, prompt: &str) -> Result<()> {
+        self.text.add_system_prompt(prompt)
+    }
+
+    fn add_user_message(&mut self, text: &str) -> Result<()> {
+        self.text.add_user_message(text)
+    }
+
+    fn add_agent_message(&mut self, text: &str) -> Result<()> {
+        self.text.add_agent_message(text)
+    }
+
+    fn add_context(&mut self, name: &str, data: &str) -> Result<()> {
+        self.text.add_context(name, data)
+    }
+
+    fn add_editable(&mut self, path: &str, data: &str) -> Result<()> {
+        self.text.add_editable(path, data)
+    }
+
+    async fn send(&mut self, sender: Option<EventSender>) -> Result<ModelResponse> {
+        let request = self.text.render()?;
+        if !self.record_dir.as_os_str().is_empty() {
+            self.record(&request)?;
+        }
+        // The response file is read on every request, so it can be changed between steps.
+        let text = if self.response_file.as_os_str().is_empty() {
+            self.response.clone()
+        } else {
+            fs::read_to_string(&self.response_file)?
+        };
+        send_event(&sender, Event::ModelResponse(text.clone()))?;
+
+        let dialect = Config::default().dialect()?;
+        let mut resp = dialect.parse(&text)?;
+        resp.usage = Some(super::Usage::Echo(EchoUsage {
+            input_tokens: estimate_tokens(&request) as u64,
+            output_tokens: estimate_tokens(&text) as u64,
+        }));
+        Ok(resp)
+    }
+
+    fn render(&self) -> Result<String> {
+        self.text.render()
+    }
+}
+
+/// A model that records requests and returns a canned response.
+#[derive(Default, Debug, Clone)]
+pub struct Echo {
+    /// The user facing name of the model
+    pub name: String,
+    /// The raw response, parsed by the dialect just like a real model's response
+    pub response: String,
+    /// A file to read the raw response from on each request. Over-rides `response` if set.
+    pub response_file: PathBuf,
+    /// A directory to record each rendered request in, if set
+    pub record_dir: PathBuf,
+}
+
+#[async_trait]
+impl ModelProvider for Echo {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn api_model(&self) -> String {
+        "echo".to_string()
+    }
+
+    fn chat(&self) -> Option<Box<dyn Chat>> {
+        Some(Box::new(EchoChat {
+            text: TextChat::default(),
+            response: self.response.clone(),
+            response_file: self.response_file.clone(),
+            record_dir: self.record_dir.clone(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_echo() -> Result<()> {
+        let dir = tempdir()?;
+        let echo = Echo {
+            name: "echo".into(),
+            response: "<comment>\ndone\n</comment>".into(),
+            record_dir: dir.path().join("requests"),
+            ..Default::default()
+        };
+
+        let mut chat = echo.chat().unwrap();
+        chat.add_system_prompt("be terse")?;
+        chat.add_user_message("do it")?;
+        let resp = chat.send(None).await?;
+        assert_eq!(resp.comment.as_deref(), Some("done"));
+        assert_eq!(
+            fs::read_to_string(dir.path().join("requests/request-0000.txt"))?,
+            "## system\n\nbe terse\n\n## user\n\ndo it\n"
+        );
+
+        // A response file takes precedence, and each request gets its own record
+        fs::write(
+            dir.path().join("resp.txt"),
+            "<comment>\nfrom file\n</comment>",
+        )?;
+        let echo = Echo {
+            response_file: dir.path().join("resp.txt"),
+            ..echo
+        };
+        let mut chat = echo.chat().unwrap();
+        chat.add_user_message("again")?;
+        let resp = chat.send(None).await?;
+        assert_eq!(resp.comment.as_deref(), Some("from file"));
+        assert!(dir.path().join("requests/request-0001.txt").exists());
+        Ok(())
+    }
+}
@@ -2,13 +2,15 @@
 use std::collections::HashMap;
 
 use google_genai::datatypes::{Content, GenerateContentReq, GenerateContentResponse, Part};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use tracing::{trace, warn};
 
 use super::Chat;
 
 use crate::{
-    config::Config,
+    config::{Backend, Config, RateLimit},
+    credentials,
     dialect::{Dialect, DialectProvider},
     error::{Result, TenxError},
     events::*,
@@ -34,10 +36,10 @@ fn map_error(e: google_genai::error::GenAiError) -> TenxError {
                 }
                 TenxError::Throttle(Throttle::Backoff)
             } else {
-                TenxError::Model(message)
+                TenxError::provider(Some(status), message)
             }
         }
-        google_genai::error::GenAiError::Internal(msg) => TenxError::Model(msg),
+        google_genai::error::GenAiError::Internal(msg) => TenxError::provider(None, msg),
     }
 }
 
@@ -54,10 +56,15 @@ pub struct Google {
     pub api_model: String,
     pub api_key: String,
     pub streaming: bool,
+    pub backend: Backend,
+    /// The maximum number of continuation requests to make for a truncated response
+    pub max_continuations: usize,
+    /// The provider kind and its rate limits, if any are configured
+    pub rate_limit: Option<(String, RateLimit)>,
 }
 
 /// Usage statistics for the Google PaLM API.
-#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, JsonSchema)]
 pub struct GoogleUsage {
     pub input_tokens: Option<u32>,
     pub output_tokens: Option<u32>,
@@ -95,6 +102,12 @@ pub struct GoogleChat {
     pub api_key: String,
     /// Whether to stream responses
     pub streaming: bool,
+    /// Where requests are sent
+    pub backend: Backend,
+    /// The maximum number of continuation requests to make for a truncated response
+    pub max_continuations: usize,
+    /// The provider kind and its rate limits, if any are configured
+    pub rate_limit: Option<(String, RateLimit)>,
     /// The contents request being built
     request: GenerateContentReq,
 }
@@ -145,30 +158,79 @@ impl GoogleChat {
         Ok(responses)
     }
 
-    fn extract_changes(
-        &self,
-        dialect: &Dialect,
-        responses: &[GenerateContentResponse],
-    ) -> Result<ModelResponse> {
-        let mut full_text = String::new();
-        let mut total_prompt_tokens = 0;
-        let mut total_candidate_tokens = 0;
-        let mut total_tokens = 0;
-
+    /// Returns the text of a response, which may have been streamed in several parts.
+    fn response_text(responses: &[GenerateContentResponse]) -> String {
+        let mut text = String::new();
         for response in responses {
             if let Some(candidates) = &response.candidates {
                 if let Some(candidate) = candidates.first() {
                     if let Some(content) = &candidate.content {
                         if let Some(parts) = &content.parts {
                             for part in parts {
-                                if let Some(text) = &part.text {
-                                    full_text.push_str(text);
+                                if let Some(text_part) = &part.text {
+                                    text.push_str(text_part);
                                 }
                             }
                         }
                     }
                 }
             }
+        }
+        text
+    }
+
+    /// Returns true if a response stopped because it hit the output token limit. The finish
+    /// reason is compared in the form the API reports it.
+    fn truncated(responses: &[GenerateContentResponse]) -> bool {
+        responses
+            .iter()
+            .filter_map(|r| r.candidates.as_ref()?.first())
+            .filter_map(|c| serde_json::to_value(&c.finish_reason).ok())
+            .any(|reason| reason == "MAX_TOKENS")
+    }
+
+    async fn send_request(
+        &self,
+        sender: &Option<EventSender>,
+    ) -> Result<Vec<GenerateContentResponse>> {
+        trace!("Sending request: {:#?}", self.request);
+        let responses = if self.backend != Backend::Direct {
+            let resp: GenerateContentResponse = serde_json::from_value(
+                super::cloud::send_gemini(
+                    &self.backend,
+                    &self.api_model,
+                    serde_json::to_value(&self.request)?,
+                )
+                .await?,
+            )?;
+            self.emit_event(sender, &resp)?;
+            vec![resp]
+        } else if self.streaming {
+            self.stream_response(self.api_key.clone(), &self.request, sender.clone())
+                .await?
+        } else {
+            let resp = google_genai::generate_content(&self.api_key, self.request.clone())
+                .await
+                .map_err(map_error)?;
+
+            self.emit_event(sender, &resp)?;
+            vec![resp]
+        };
+        trace!("Got responses: {:#?}", responses);
+        Ok(responses)
+    }
+
+    fn extract_changes(
+        &self,
+        dialect: &Dialect,
+        text: &str,
+        responses: &[GenerateContentResponse],
+    ) -> Result<ModelResponse> {
+        let mut total_prompt_tokens = 0;
+        let mut total_candidate_tokens = 0;
+        let mut total_tokens = 0;
+
+        for response in responses {
             if let Some(metadata) = &response.usage_metadata {
                 total_prompt_tokens += metadata.prompt_token_count.unwrap_or(0);
                 total_candidate_tokens += metadata.candidates_token_count.unwrap_or(0);
@@ -176,11 +238,11 @@ impl GoogleChat {
             }
         }
 
-        if full_text.is_empty() {
+        if text.is_empty() {
             return Err(TenxError::Throttle(Throttle::Backoff));
         }
 
-        let mut modresp = dialect.parse(&full_text)?;
+        let mut modresp = dialect.parse(text)?;
         modresp.usage = Some(super::Usage::Google(GoogleUsage {
             input_tokens: Some(total_prompt_tokens as u32),
             output_tokens: Some(total_candidate_tokens as u32),
@@ -236,35 +298,50 @@ impl Chat for GoogleChat {
     }
 
     async fn send(&mut self, sender: Option<EventSender>) -> Result<ModelResponse> {
-        if self.api_key.is_empty() {
-            return Err(TenxError::Model(
-                "No API key configured for Google model.".into(),
-            ));
+        if self.api_key.is_empty() && self.backend == Backend::Direct {
+            return Err(credentials::missing_key("google"));
         }
 
         self.request = self.request.clone().model(&self.api_model);
 
-        trace!("Sending request: {:#?}", self.request);
-
-        let responses = if self.streaming {
-            self.stream_response(self.api_key.clone(), &self.request, sender.clone())
-                .await?
-        } else {
-            let resp = google_genai::generate_content(&self.api_key, self.request.clone())
-                .await
-                .map_err(map_error)?;
-
-            self.emit_event(&sender, &resp)?;
-            vec![resp]
-        };
-
-        trace!("Got responses: {:#?}", responses);
+        let mut text = String::new();
+        let mut responses = Vec::new();
+        let mut continuations = 0;
+        loop {
+            let permit = super::limiter::acquire_for(&self.rate_limit, &sender).await?;
+            let resps = self.send_request(&sender).await?;
+            permit.record(
+                resps
+                    .iter()
+                    .filter_map(|r| r.usage_metadata.as_ref())
+                    .map(|m| {
+                        m.prompt_token_count.unwrap_or(0) as u64
+                            + m.candidates_token_count.unwrap_or(0) as u64
+                    })
+                    .sum(),
+            );
+            let chunk = Self::response_text(&resps);
+            text = super::stitch(&text, &chunk);
+            let truncated = Self::truncated(&resps);
+            responses.extend(resps);
+
+            if !truncated || continuations >= self.max_continuations {
+                break;
+            }
+            continuations += 1;
+            trace!(
+                "Response truncated at max tokens, requesting continuation {}",
+                continuations
+            );
+            self.add_agent_message(&chunk)?;
+            self.add_user_message(super::CONTINUATION_PROMPT)?;
+        }
 
         // Get dialect from config
         let config = Config::default();
         let dialect = config.dialect()?;
 
-        let modresp = self.extract_changes(&dialect, &responses)?;
+        let modresp = self.extract_changes(&dialect, &text, &responses)?;
         Ok(modresp)
     }
 
@@ -288,6 +365,9 @@ impl ModelProvider for Google {
             api_model: self.api_model.clone(),
             api_key: self.api_key.clone(),
             streaming: self.streaming,
+            backend: self.backend.clone(),
+            max_continuations: self.max_continuations,
+            rate_limit: self.rate_limit.clone(),
             request: GenerateContentReq::default(),
         }))
     }
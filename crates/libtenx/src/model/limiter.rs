@@ -0,0 +1,289 @@
+//! A process-wide rate limiter for model providers. Limits are configured per provider kind, and
+//! shared by every request made in the process, so that watch mode, races and batch operations
+//! don't trip provider rate limits. Requests over the limit are queued until they can proceed.
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::sleep,
+};
+
+use crate::{
+    config::RateLimit,
+    error::{Result, TenxError},
+    events::{send_event, Event, EventSender},
+};
+
+/// The window over which per-minute limits are measured.
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Limiters for each provider, shared across the process.
+static LIMITERS: LazyLock<Mutex<HashMap<String, Arc<Limiter>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// A request made within the rate limiting window.
+#[derive(Debug)]
+struct Request {
+    id: u64,
+    at: Instant,
+    tokens: u64,
+}
+
+/// The rate limiter for a single provider.
+#[derive(Debug)]
+struct Limiter {
+    limits: RateLimit,
+    connections: Option<Arc<Semaphore>>,
+    window: Mutex<Window>,
+}
+
+/// The requests made within the last rate limiting window.
+#[derive(Debug, Default)]
+struct Window {
+    requests: VecDeque<Request>,
+    next_id: u64,
+}
+
+impl Window {
+    /// Drop requests that have fallen out of the window.
+    fn prune(&mut self, now: Instant) {
+        while self
+            .requests
+            .front()
+            .is_some_and(|r| now.duration_since(r.at) >= WINDOW)
+        {
+            self.requests.pop_front();
+        }
+    }
+
+    /// How long we must wait at `now` before a new request is within the limits.
+    fn wait_time(&mut self, limits: &RateLimit, now: Instant) -> Duration {
+        self.prune(now);
+        let mut until = now;
+        let rpm = limits.requests_per_minute as usize;
+        if rpm > 0 && self.requests.len() >= rpm {
+            // Enough requests must expire to leave room for one more
+            let r = &self.requests[self.requests.len() - rpm];
+            until = until.max(r.at + WINDOW);
+        }
+        if limits.tokens_per_minute > 0 {
+            let mut total: u64 = self.requests.iter().map(|r| r.tokens).sum();
+            for r in &self.requests {
+                if total < limits.tokens_per_minute {
+                    break;
+                }
+                total -= r.tokens;
+                until = until.max(r.at + WINDOW);
+            }
+        }
+        until - now
+    }
+
+    /// Record a new request at `now`, returning its id.
+    fn push(&mut self, now: Instant) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.requests.push_back(Request {
+            id,
+            at: now,
+            tokens: 0,
+        });
+        id
+    }
+
+    /// Record a new request at `now` if the limits allow it, returning its id, or how long to wait
+    /// before trying again. Checking and recording in one step means concurrent callers can't
+    /// both take the last slot.
+    fn try_push(&mut self, limits: &RateLimit, now: Instant) -> std::result::Result<u64, Duration> {
+        let wait = self.wait_time(limits, now);
+        if wait.is_zero() {
+            Ok(self.push(now))
+        } else {
+            Err(wait)
+        }
+    }
+
+    /// Record the tokens used by a request.
+    fn record(&mut self, id: u64, tokens: u64) {
+        if let Some(r) = self.requests.iter_mut().find(|r| r.id == id) {
+            r.tokens += tokens;
+        }
+    }
+}
+
+impl Limiter {
+    fn new(limits: RateLimit) -> Self {
+        let connections =
+            (limits.max_concurrent > 0).then(|| Arc::new(Semaphore::new(limits.max_concurrent)));
+        Limiter {
+            limits,
+            connections,
+            window: Mutex::new(Window::default()),
+        }
+    }
+
+    fn window(&self) -> std::sync::MutexGuard<'_, Window> {
+        self.window.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Permission to make a request, held for the duration of the request. Dropping the permit
+/// releases the connection slot, if concurrency is limited.
+#[derive(Debug, Default)]
+pub(crate) struct Permit {
+    request: Option<(Arc<Limiter>, u64)>,
+    _connection: Option<OwnedSemaphorePermit>,
+}
+
+impl Permit {
+    /// Record the tokens used by the request, counting them against the tokens per minute limit.
+    pub(crate) fn record(&self, tokens: u64) {
+        if let Some((limiter, id)) = &self.request {
+            limiter.window().record(*id, tokens);
+        }
+    }
+}
+
+/// Returns the shared limiter for a provider, replacing it if the limits have changed.
+fn limiter(provider: &str, limits: &RateLimit) -> Arc<Limiter> {
+    let mut limiters = LIMITERS.lock().unwrap_or_else(|e| e.into_inner());
+    match limiters.get(provider) {
+        Some(l) if l.limits == *limits => l.clone(),
+        _ => {
+            let l = Arc::new(Limiter::new(limits.clone()));
+            limiters.insert(provider.to_string(), l.clone());
+            l
+        }
+    }
+}
+
+/// Wait until the limits for a provider allow a new request, emitting a `Queued` event if we have
+/// to wait.
+pub(crate) async fn acquire(
+    provider: &str,
+    limits: &RateLimit,
+    sender: &Option<EventSender>,
+) -> Result<Permit> {
+    let limiter = limiter(provider, limits);
+
+    let connection = match &limiter.connections {
+        Some(sem) => Some(match sem.clone().try_acquire_owned() {
+            Ok(p) => p,
+            Err(_) => {
+                send_event(
+                    sender,
+                    Event::Queued(format!(
+                        "{}: waiting for one of {} connections",
+                        provider, limits.max_concurrent
+                    )),
+                )?;
+                sem.clone()
+                    .acquire_owned()
+                    .await
+                    .map_err(|e| TenxError::Internal(e.to_string()))?
+            }
+        }),
+        None => None,
+    };
+
+    let id = loop {
+        let wait = match limiter.window().try_push(limits, Instant::now()) {
+            Ok(id) => break id,
+            Err(wait) => wait,
+        };
+        send_event(
+            sender,
+            Event::Queued(format!(
+                "{}: rate limit reached, waiting {}ms",
+                provider,
+                wait.as_millis()
+            )),
+        )?;
+        sleep(wait).await;
+    };
+
+    Ok(Permit {
+        request: Some((limiter, id)),
+        _connection: connection,
+    })
+}
+
+/// Wait until the limits for a provider allow a new request, if it has limits. Every request to
+/// a provider takes a permit, including continuations of a truncated response.
+pub(crate) async fn acquire_for(
+    rate_limit: &Option<(String, RateLimit)>,
+    sender: &Option<EventSender>,
+) -> Result<Permit> {
+    match rate_limit {
+        Some((provider, limits)) => acquire(provider, limits, sender).await,
+        None => Ok(Permit::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait_time() {
+        let limits = RateLimit {
+            requests_per_minute: 2,
+            tokens_per_minute: 100,
+            max_concurrent: 0,
+        };
+        let start = Instant::now();
+        let mut w = Window::default();
+        assert_eq!(w.wait_time(&limits, start), Duration::ZERO);
+
+        // Request limit
+        assert_eq!(w.try_push(&limits, start), Ok(0));
+        let id = w.push(start + Duration::from_secs(10));
+        assert_eq!(
+            w.wait_time(&limits, start + Duration::from_secs(20)),
+            Duration::from_secs(40)
+        );
+        assert_eq!(
+            w.try_push(&limits, start + Duration::from_secs(20)),
+            Err(Duration::from_secs(40))
+        );
+        assert_eq!(w.requests.len(), 2);
+        // Once the first request expires, there's room again
+        assert_eq!(
+            w.wait_time(&limits, start + Duration::from_secs(60)),
+            Duration::ZERO
+        );
+
+        // Token limit: the request using the tokens has to expire
+        w.record(id, 150);
+        assert_eq!(
+            w.wait_time(&limits, start + Duration::from_secs(60)),
+            Duration::from_secs(10)
+        );
+        assert_eq!(
+            w.wait_time(&limits, start + Duration::from_secs(70)),
+            Duration::ZERO
+        );
+        assert!(w.requests.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_acquire_concurrency() {
+        let limits = RateLimit {
+            max_concurrent: 1,
+            ..Default::default()
+        };
+        let p1 = acquire("test_acquire_concurrency", &limits, &None)
+            .await
+            .unwrap();
+        let l = limiter("test_acquire_concurrency", &limits);
+        assert_eq!(l.connections.as_ref().unwrap().available_permits(), 0);
+        p1.record(10);
+        assert_eq!(l.window().requests[0].tokens, 10);
+        drop(p1);
+        assert_eq!(l.connections.as_ref().unwrap().available_permits(), 1);
+    }
+}
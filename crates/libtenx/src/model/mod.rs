@@ -5,24 +5,69 @@
 
 mod claude;
 mod claude_editor;
+mod cloud;
 mod dummy_model;
+mod echo;
 mod google;
+pub(crate) mod limiter;
 mod openai;
+mod ping;
+mod stream;
+mod text;
 
 use async_trait::async_trait;
 use enum_dispatch::enum_dispatch;
+use futures_util::stream::BoxStream;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 pub use claude::{Claude, ClaudeChat, ClaudeUsage};
 pub use claude_editor::ClaudeEditor;
 pub use dummy_model::{DummyModel, DummyUsage};
+pub use echo::{Echo, EchoChat, EchoUsage};
 pub use google::{Google, GoogleChat, GoogleUsage};
 pub use openai::{OpenAi, OpenAiChat, OpenAiUsage, ReasoningEffort};
+pub use ping::{ping, Ping, PingStatus};
+pub use stream::StreamEvent;
+pub use text::{estimate_tokens, TextChat};
 
-use crate::{error::Result, events::EventSender, session::ModelResponse};
+use crate::{config::Sampling, error::Result, events::EventSender, session::ModelResponse};
 
 use std::collections::HashMap;
 
+/// The user message sent to ask a model to continue a response that was truncated because it hit
+/// the output token limit.
+pub(crate) const CONTINUATION_PROMPT: &str = "Your previous response was cut off because it \
+reached the output token limit. Continue exactly where you left off, without repeating any text \
+you have already written and without any preamble.";
+
+/// The minimum overlap we consider when de-duplicating text a model repeats at the start of a
+/// continuation, when the repeated text starts at the beginning of a line. Code often repeats short
+/// runs of lines, like closing braces, so shorter overlaps are treated as coincidence.
+const MIN_STITCH_OVERLAP: usize = 64;
+
+/// The minimum overlap we consider when the repeated text starts part way through a line.
+const MIN_UNANCHORED_STITCH_OVERLAP: usize = 256;
+
+/// Stitch the text of a continuation onto a truncated response. Models sometimes repeat the tail
+/// of the truncated response at the start of a continuation, so we drop the longest suffix of
+/// `partial` that is also a prefix of `continuation`, if it is long enough to be meaningful.
+pub(crate) fn stitch(partial: &str, continuation: &str) -> String {
+    let max = partial.len().min(continuation.len());
+    let overlap = (MIN_STITCH_OVERLAP..=max)
+        .rev()
+        .filter(|n| {
+            partial.is_char_boundary(partial.len() - n) && continuation.is_char_boundary(*n)
+        })
+        .filter(|n| {
+            let start = partial.len() - n;
+            *n >= MIN_UNANCHORED_STITCH_OVERLAP || start == 0 || partial[..start].ends_with('\n')
+        })
+        .find(|n| partial[partial.len() - n..] == continuation[..*n])
+        .unwrap_or(0);
+    format!("{}{}", partial, &continuation[overlap..])
+}
+
 /// A trait used to prepare a chat interaction to be sent to the model for
 /// completion.
 ///
@@ -48,6 +93,11 @@ pub trait Chat: Send {
     /// May start a new user message, and synthesize an agent response.
     fn add_editable(&mut self, path: &str, data: &str) -> Result<()>;
 
+    /// Sets sampling parameters for the request. Providers ignore parameters they don't support.
+    fn set_sampling(&mut self, _sampling: &Sampling) -> Result<()> {
+        Ok(())
+    }
+
     /// Render and send a session to the model.
     async fn send(&mut self, sender: Option<EventSender>) -> Result<ModelResponse>;
 
@@ -57,12 +107,13 @@ pub trait Chat: Send {
 }
 
 /// Represents usage statistics for different model types.
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
 pub enum Usage {
     Claude(ClaudeUsage),
     OpenAi(OpenAiUsage),
     Dummy(DummyUsage),
     Google(google::GoogleUsage),
+    Echo(EchoUsage),
 }
 
 impl Usage {
@@ -73,6 +124,7 @@ impl Usage {
             Usage::OpenAi(usage) => usage.values(),
             Usage::Dummy(usage) => usage.values(),
             Usage::Google(usage) => usage.values(),
+            Usage::Echo(usage) => usage.values(),
         }
     }
 
@@ -83,6 +135,7 @@ impl Usage {
             Usage::OpenAi(usage) => usage.totals(),
             Usage::Dummy(usage) => usage.totals(),
             Usage::Google(usage) => usage.totals(),
+            Usage::Echo(usage) => usage.totals(),
         }
     }
 }
@@ -102,6 +155,13 @@ pub trait ModelProvider {
     fn chat(&self) -> Option<Box<dyn Chat>> {
         None
     }
+
+    /// Sends a chat prepared from `chat()`, returning the response as a stream of events instead
+    /// of through an event channel. The stream ends with the parsed response, or the error the
+    /// request failed with. Must be called from within a Tokio runtime.
+    fn stream(&self, chat: Box<dyn Chat>) -> BoxStream<'static, StreamEvent> {
+        stream::stream_chat(chat)
+    }
 }
 
 /// Available model implementations that can be used for AI interactions.
@@ -113,4 +173,44 @@ pub enum Model {
     OpenAi(OpenAi),
     Google(google::Google),
     Dummy(DummyModel),
+    Echo(Echo),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stitch() {
+        assert_eq!(stitch("foo", "bar"), "foobar");
+        assert_eq!(stitch("", "bar"), "bar");
+        assert_eq!(stitch("foo", ""), "foo");
+        // Short overlaps are treated as coincidence
+        assert_eq!(stitch("foo bar", "bar baz"), "foo barbar baz");
+        let repeated = "<write_file path=\"a.rs\">\nfn main() {\n    println!(\"hello\");\n    println!(\"world\");";
+        assert_eq!(
+            stitch(
+                &format!("intro\n{}", repeated),
+                &format!("{}\n}}\n</write_file>", repeated)
+            ),
+            format!("intro\n{}\n}}\n</write_file>", repeated)
+        );
+        // Runs of identical lines at the join are kept, since code really does repeat them
+        let braces = "        }\n".repeat(6);
+        assert_eq!(
+            stitch(&format!("x\n{}", braces), &format!("{}y\n", braces)),
+            format!("x\n{}{}y\n", braces, braces)
+        );
+        // A long overlap that doesn't start at a line is only dropped if it's very long
+        let tail = format!(" wörld, ünïcode {}", "ü".repeat(30));
+        assert_eq!(
+            stitch(&format!("héllo{}", tail), &format!("{}!", tail)),
+            format!("héllo{}{}!", tail, tail)
+        );
+        let tail = format!(" wörld, ünïcode {}", "ü".repeat(150));
+        assert_eq!(
+            stitch(&format!("héllo{}", tail), &format!("{}!", tail)),
+            format!("héllo{}!", tail)
+        );
+    }
 }
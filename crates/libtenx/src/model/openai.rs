@@ -5,17 +5,19 @@ use async_openai::{
         ChatCompletionRequestDeveloperMessageArgs, ChatCompletionRequestSystemMessageArgs,
         ChatCompletionRequestUserMessageArgs, ChatCompletionResponseMessage,
         CreateChatCompletionRequest, CreateChatCompletionRequestArgs, CreateChatCompletionResponse,
-        FinishReason,
+        FinishReason, Stop,
     },
     Client,
 };
 use async_trait::async_trait;
 use futures_util::StreamExt;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use tracing::trace;
 
 use crate::{
-    config::Config,
+    config::{Config, RateLimit, Sampling},
+    credentials,
     dialect::{Dialect, DialectProvider},
     error::{Result, TenxError},
     events::{send_event, Event, EventSender},
@@ -44,10 +46,14 @@ pub struct OpenAi {
     pub no_system_prompt: bool,
     /// For OpenAI o1 and o3 models only.
     pub reasoning_effort: Option<ReasoningEffort>,
+    /// The maximum number of continuation requests to make for a truncated response
+    pub max_continuations: usize,
+    /// The provider kind and its rate limits, if any are configured
+    pub rate_limit: Option<(String, RateLimit)>,
 }
 
 /// OpenAI-specific usage information.
-#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, JsonSchema)]
 pub struct OpenAiUsage {
     pub prompt_tokens: Option<u32>,
     pub completion_tokens: Option<u32>,
@@ -56,14 +62,14 @@ pub struct OpenAiUsage {
 
 impl From<async_openai::error::OpenAIError> for TenxError {
     fn from(e: async_openai::error::OpenAIError) -> Self {
-        if let async_openai::error::OpenAIError::Reqwest(ref e) = e {
-            if let Some(status) = e.status() {
-                if status == 429 || status == 529 {
-                    return TenxError::Throttle(crate::throttle::Throttle::Backoff);
-                }
+        match e {
+            async_openai::error::OpenAIError::Reqwest(e) => e.into(),
+            async_openai::error::OpenAIError::ApiError(_)
+            | async_openai::error::OpenAIError::StreamError(_) => {
+                TenxError::provider(None, e.to_string())
             }
+            _ => TenxError::Model(e.to_string()),
         }
-        TenxError::Model(e.to_string())
     }
 }
 
@@ -105,6 +111,10 @@ pub struct OpenAiChat {
     pub no_system_prompt: bool,
     /// Reasoning effort level for o1/o3 models
     pub reasoning_effort: Option<ReasoningEffort>,
+    /// The maximum number of continuation requests to make for a truncated response
+    pub max_continuations: usize,
+    /// The provider kind and its rate limits, if any are configured
+    pub rate_limit: Option<(String, RateLimit)>,
     /// The request being built
     request: CreateChatCompletionRequest,
     /// Last response from the model
@@ -126,6 +136,7 @@ impl OpenAiChat {
 
         let mut stream = client.chat().create_stream(req).await?;
         let mut full_response = String::new();
+        let mut finish_reason = None;
 
         while let Some(result) = stream.next().await {
             match result {
@@ -135,6 +146,9 @@ impl OpenAiChat {
                             full_response.push_str(content);
                             send_event(&sender, Event::Snippet(content.to_string()))?;
                         }
+                        if choice.finish_reason.is_some() {
+                            finish_reason = choice.finish_reason;
+                        }
                     }
                 }
                 Err(err) => {
@@ -161,13 +175,41 @@ impl OpenAiChat {
                     function_call: None,
                     audio: None,
                 },
-                finish_reason: Some(FinishReason::Stop),
+                finish_reason: finish_reason.or(Some(FinishReason::Stop)),
                 logprobs: None,
             }],
             usage: None,
         })
     }
 
+    /// Send the current request to the model, either streaming or not.
+    async fn send_request(
+        &self,
+        sender: &Option<EventSender>,
+    ) -> Result<CreateChatCompletionResponse> {
+        trace!(
+            "Sending request ({} bytes): {:?}",
+            serde_json::to_vec(&self.request)?.len(),
+            self.request
+        );
+        let resp = if self.streaming {
+            self.stream_response(sender.clone()).await?
+        } else {
+            let openai_config = OpenAIConfig::new()
+                .with_api_key(self.openai_key.clone())
+                .with_api_base(&self.api_base);
+            let client = Client::with_config(openai_config);
+
+            let resp = client.chat().create(self.request.clone()).await?;
+            if let Some(content) = resp.choices[0].message.content.as_ref() {
+                send_event(sender, Event::ModelResponse(content.to_string()))?;
+            }
+            resp
+        };
+        trace!("Got response: {:?}", resp);
+        Ok(resp)
+    }
+
     fn extract_changes(&self, dialect: &Dialect) -> Result<ModelResponse> {
         if let Some(response) = &self.response {
             if let Some(content) = &response.content {
@@ -235,9 +277,20 @@ impl Chat for OpenAiChat {
         ))
     }
 
+    fn set_sampling(&mut self, sampling: &Sampling) -> Result<()> {
+        self.request.temperature = sampling.temperature;
+        self.request.top_p = sampling.top_p;
+        self.request.max_completion_tokens = sampling.max_tokens;
+        self.request.stop =
+            (!sampling.stop.is_empty()).then(|| Stop::StringArray(sampling.stop.clone()));
+        Ok(())
+    }
+
     async fn send(&mut self, sender: Option<EventSender>) -> Result<ModelResponse> {
         if self.openai_key.is_empty() {
-            return Err(TenxError::Model("No OpenAI key configured.".into()));
+            return Err(credentials::missing_key(&credentials::openai_provider(
+                &self.api_base,
+            )));
         }
         if self.api_model.is_empty() {
             return Err(TenxError::Model("Empty API model name".into()));
@@ -252,28 +305,48 @@ impl Chat for OpenAiChat {
             });
         }
 
-        trace!("Sending request: {:?}", self.request);
-
-        let resp = if self.streaming {
-            self.stream_response(sender.clone()).await?
-        } else {
-            let openai_config = OpenAIConfig::new()
-                .with_api_key(self.openai_key.clone())
-                .with_api_base(&self.api_base);
-            let client = Client::with_config(openai_config);
-
-            let resp = client.chat().create(self.request.clone()).await?;
-            if let Some(content) = resp.choices[0].message.content.as_ref() {
-                send_event(&sender, Event::ModelResponse(content.to_string()))?;
+        let mut text = String::new();
+        let mut usage: Option<OpenAiUsage> = None;
+        let mut continuations = 0;
+        loop {
+            let permit = super::limiter::acquire_for(&self.rate_limit, &sender).await?;
+            let resp = self.send_request(&sender).await?;
+            if let Some(u) = &resp.usage {
+                permit.record(u.prompt_tokens as u64 + u.completion_tokens as u64);
+            }
+            let choice = resp.choices.first();
+            let chunk = choice
+                .and_then(|c| c.message.content.clone())
+                .unwrap_or_default();
+            text = super::stitch(&text, &chunk);
+            if let Some(u) = resp.usage {
+                let acc = usage.get_or_insert_with(OpenAiUsage::default);
+                acc.prompt_tokens = Some(acc.prompt_tokens.unwrap_or(0) + u.prompt_tokens);
+                acc.completion_tokens =
+                    Some(acc.completion_tokens.unwrap_or(0) + u.completion_tokens);
+                acc.total_tokens = Some(acc.total_tokens.unwrap_or(0) + u.total_tokens);
             }
-            resp
-        };
 
-        trace!("Got response: {:?}", resp);
+            // Store response for future reference, with the text of all continuations stitched
+            // together
+            if let Some(choice) = choice {
+                let mut message = choice.message.clone();
+                message.content = Some(text.clone());
+                self.response = Some(message);
+            }
 
-        // Store response for future reference
-        if let Some(choice) = resp.choices.first() {
-            self.response = Some(choice.message.clone());
+            if choice.and_then(|c| c.finish_reason) != Some(FinishReason::Length)
+                || continuations >= self.max_continuations
+            {
+                break;
+            }
+            continuations += 1;
+            trace!(
+                "Response truncated at max tokens, requesting continuation {}",
+                continuations
+            );
+            self.add_agent_message(&chunk)?;
+            self.add_user_message(super::CONTINUATION_PROMPT)?;
         }
 
         // Get dialect from config
@@ -281,13 +354,8 @@ impl Chat for OpenAiChat {
         let dialect = config.dialect()?;
 
         let mut modresp = self.extract_changes(&dialect)?;
-
-        if let Some(usage) = resp.usage {
-            modresp.usage = Some(super::Usage::OpenAi(OpenAiUsage {
-                prompt_tokens: Some(usage.prompt_tokens),
-                completion_tokens: Some(usage.completion_tokens),
-                total_tokens: Some(usage.total_tokens),
-            }));
+        if let Some(usage) = usage {
+            modresp.usage = Some(super::Usage::OpenAi(usage));
         }
 
         Ok(modresp)
@@ -327,6 +395,8 @@ impl ModelProvider for OpenAi {
                 streaming: self.streaming,
                 no_system_prompt: self.no_system_prompt,
                 reasoning_effort: self.reasoning_effort.clone(),
+                max_continuations: self.max_continuations,
+                rate_limit: self.rate_limit.clone(),
                 request,
                 response: None,
             })),
@@ -0,0 +1,171 @@
+//! Probing models with a minimal request, to find bad keys and unreachable endpoints before they
+//! interrupt a long session.
+use std::time::{Duration, Instant};
+
+use super::{Chat, Model, ModelProvider};
+use crate::{
+    config::Sampling,
+    error::{Result, TenxError},
+};
+
+/// The prompt sent to probe a model. The reply is discarded.
+const PING_PROMPT: &str = "Reply with the single word: pong";
+
+/// The outcome of probing a model.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PingStatus {
+    /// The model answered.
+    Ok,
+    /// The provider rejected our credentials, or no key is configured.
+    Auth(String),
+    /// The provider is reachable and accepted our credentials, but is rate limiting us.
+    Throttled(String),
+    /// The request never reached the provider.
+    Unreachable(String),
+    /// Any other failure.
+    Error(String),
+}
+
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+impl PingStatus {
+    /// Classifies the error a probe failed with.
+    pub fn from_error(err: &TenxError) -> Self {
+        let msg = err.to_string();
+        let lower = msg.to_lowercase();
+        match err {
+            TenxError::MissingKey { .. } => PingStatus::Auth(msg),
+            TenxError::Throttle(_) => PingStatus::Throttled(msg),
+            TenxError::Provider {
+                status: Some(401 | 403),
+                ..
+            } => PingStatus::Auth(msg),
+            // The model answered, just not in our dialect
+            TenxError::ResponseParse { .. } => PingStatus::Ok,
+            _ if [
+                "401",
+                "403",
+                "unauthorized",
+                "authentication",
+                "api key",
+                "api_key",
+            ]
+            .iter()
+            .any(|s| lower.contains(s)) =>
+            {
+                PingStatus::Auth(msg)
+            }
+            _ if [
+                "error sending request",
+                "connect",
+                "dns",
+                "timed out",
+                "timeout",
+            ]
+            .iter()
+            .any(|s| lower.contains(s)) =>
+            {
+                PingStatus::Unreachable(msg)
+            }
+            _ => PingStatus::Error(msg),
+        }
+    }
+
+    /// Did the provider answer?
+    pub fn is_ok(&self) -> bool {
+        matches!(self, PingStatus::Ok)
+    }
+}
+
+impl std::fmt::Display for PingStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PingStatus::Ok => write!(f, "ok"),
+            PingStatus::Auth(msg) => write!(f, "auth failed: {}", msg),
+            PingStatus::Throttled(msg) => write!(f, "throttled: {}", msg),
+            PingStatus::Unreachable(msg) => write!(f, "unreachable: {}", msg),
+            PingStatus::Error(msg) => write!(f, "error: {}", msg),
+        }
+    }
+}
+
+/// The result of probing a model.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+pub struct Ping {
+    /// The name of the model.
+    pub model: String,
+    pub status: PingStatus,
+    /// The round-trip time of the request.
+    pub latency: Duration,
+}
+
+async fn send_ping(model: &Model) -> Result<()> {
+    let mut chat: Box<dyn Chat> = model
+        .chat()
+        .ok_or_else(|| TenxError::Model(format!("{} does not support chat", model.name())))?;
+    chat.set_sampling(&Sampling {
+        max_tokens: Some(16),
+        ..Default::default()
+    })?;
+    chat.add_user_message(PING_PROMPT)?;
+    chat.send(None).await?;
+    Ok(())
+}
+
+/// Sends a minimal request to a model, and reports whether it answered and how long it took.
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+pub async fn ping(model: &Model) -> Ping {
+    let start = Instant::now();
+    let status = match send_ping(model).await {
+        Ok(()) => PingStatus::Ok,
+        Err(e) => PingStatus::from_error(&e),
+    };
+    Ping {
+        model: model.name(),
+        status,
+        latency: start.elapsed(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{model::Echo, throttle::Throttle};
+
+    #[tokio::test]
+    async fn test_ping() {
+        let model = Model::Echo(Echo {
+            name: "echo".into(),
+            response: "pong".into(),
+            ..Default::default()
+        });
+        let result = ping(&model).await;
+        assert_eq!(result.model, "echo");
+        assert_eq!(result.status, PingStatus::Ok);
+    }
+
+    #[test]
+    fn test_from_error() {
+        let status = |e: TenxError| PingStatus::from_error(&e);
+        assert!(matches!(
+            status(crate::credentials::missing_key("claude")),
+            PingStatus::Auth(_)
+        ));
+        assert!(matches!(
+            status(TenxError::Internal("HTTP 401: invalid x-api-key".into())),
+            PingStatus::Auth(_)
+        ));
+        assert!(matches!(
+            status(TenxError::Throttle(Throttle::Backoff)),
+            PingStatus::Throttled(_)
+        ));
+        assert!(matches!(
+            status(TenxError::Model("error sending request for url".into())),
+            PingStatus::Unreachable(_)
+        ));
+        assert!(matches!(
+            status(TenxError::Model("overloaded".into())),
+            PingStatus::Error(_)
+        ));
+    }
+}
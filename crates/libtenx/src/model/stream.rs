@@ -0,0 +1,170 @@
+//! Model responses as an async stream, for embedders that would rather consume a `Stream` than
+//! set up an event channel and consumer of their own.
+use futures_util::stream::{self, BoxStream, StreamExt};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use super::Chat;
+use crate::{error::TenxError, events::Event, session::ModelResponse};
+
+/// The buffer for events from the model. Events are forwarded as soon as they arrive, so this
+/// only has to absorb bursts.
+const EVENT_BUFFER: usize = 1024;
+
+/// An event in a streamed model response.
+#[derive(Debug, Clone)]
+pub enum StreamEvent {
+    /// Response text, as the model produces it. Models that don't stream send the whole text at
+    /// once.
+    Text(String),
+    /// The request is waiting on rate limits, with a description of why
+    Waiting(String),
+    /// The parsed response. This is always the last event of a successful request.
+    Response(ModelResponse),
+    /// The request failed. This is always the last event of a failed request.
+    Error(TenxError),
+}
+
+impl StreamEvent {
+    /// Converts an event from the model into a stream event, if it's one streams carry.
+    fn from_event(event: Event) -> Option<Self> {
+        match event {
+            Event::Snippet(text) | Event::ModelResponse(text) => Some(StreamEvent::Text(text)),
+            Event::Throttled(ms) => Some(StreamEvent::Waiting(format!("throttled for {}ms", ms))),
+            Event::Queued(reason) => Some(StreamEvent::Waiting(reason)),
+            _ => None,
+        }
+    }
+}
+
+/// Aborts a task when dropped, so a stream nobody reads any more doesn't leave its request
+/// running.
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Sends a chat on a background task, and returns its events as a stream. Must be called from
+/// within a Tokio runtime. Dropping the stream cancels the request.
+pub(crate) fn stream_chat(mut chat: Box<dyn Chat>) -> BoxStream<'static, StreamEvent> {
+    let (out, rx) = mpsc::unbounded_channel();
+    let task = tokio::spawn(async move {
+        let (tx, mut events) = mpsc::channel(EVENT_BUFFER);
+        let send = chat.send(Some(tx));
+        tokio::pin!(send);
+        let result = loop {
+            tokio::select! {
+                biased;
+                _ = out.closed() => return,
+                Some(event) = events.recv() => {
+                    if let Some(event) = StreamEvent::from_event(event) {
+                        let _ = out.send(event);
+                    }
+                }
+                result = &mut send => break result,
+            }
+        };
+        while let Ok(event) = events.try_recv() {
+            if let Some(event) = StreamEvent::from_event(event) {
+                let _ = out.send(event);
+            }
+        }
+        let _ = out.send(match result {
+            Ok(response) => StreamEvent::Response(response),
+            Err(e) => StreamEvent::Error(e),
+        });
+    });
+    let task = AbortOnDrop(task);
+    stream::unfold((rx, task), |(mut rx, task)| async move {
+        rx.recv().await.map(|e| (e, (rx, task)))
+    })
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        events::EventSender,
+        model::{Echo, ModelProvider},
+    };
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_stream() {
+        let echo = Echo {
+            name: "echo".into(),
+            response: "<comment>\ndone\n</comment>".into(),
+            ..Default::default()
+        };
+        let mut chat = echo.chat().unwrap();
+        chat.add_user_message("do it").unwrap();
+        let events: Vec<StreamEvent> = echo.stream(chat).collect().await;
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[0], StreamEvent::Text(t) if t.contains("done")));
+        assert!(
+            matches!(&events[1], StreamEvent::Response(r) if r.comment.as_deref() == Some("done"))
+        );
+
+        // Failures end the stream
+        let echo = Echo {
+            response_file: "/nonexistent/response.txt".into(),
+            ..echo
+        };
+        let mut chat = echo.chat().unwrap();
+        chat.add_user_message("do it").unwrap();
+        let events: Vec<StreamEvent> = echo.stream(chat).collect().await;
+        assert!(matches!(events.as_slice(), [StreamEvent::Error(_)]));
+    }
+
+    /// A chat whose request never finishes, holding a reference for as long as it's running.
+    struct Pending(#[allow(dead_code)] Arc<()>);
+
+    #[async_trait::async_trait]
+    impl Chat for Pending {
+        fn add_system_prompt(&mut self, _prompt: &str) -> crate::error::Result<()> {
+            Ok(())
+        }
+        fn add_user_message(&mut self, _text: &str) -> crate::error::Result<()> {
+            Ok(())
+        }
+        fn add_agent_message(&mut self, _text: &str) -> crate::error::Result<()> {
+            Ok(())
+        }
+        fn add_context(&mut self, _name: &str, _data: &str) -> crate::error::Result<()> {
+            Ok(())
+        }
+        fn add_editable(&mut self, _path: &str, _data: &str) -> crate::error::Result<()> {
+            Ok(())
+        }
+        async fn send(
+            &mut self,
+            _sender: Option<EventSender>,
+        ) -> crate::error::Result<ModelResponse> {
+            std::future::pending().await
+        }
+        fn render(&self) -> crate::error::Result<String> {
+            Ok(String::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_drop() {
+        let running = Arc::new(());
+        let stream = stream_chat(Box::new(Pending(running.clone())));
+        tokio::task::yield_now().await;
+        assert_eq!(Arc::strong_count(&running), 2);
+
+        // Dropping the stream cancels the request
+        drop(stream);
+        for _ in 0..100 {
+            if Arc::strong_count(&running) == 1 {
+                return;
+            }
+            tokio::task::yield_now().await;
+        }
+        panic!("the request kept running after the stream was dropped");
+    }
+}
@@ -0,0 +1,114 @@
+use async_trait::async_trait;
+
+use super::Chat;
+use crate::{
+    error::{Result, TenxError},
+    events::EventSender,
+    session::ModelResponse,
+};
+
+/// A chat that can't be sent, but renders the conversation as plain text. This lets us inspect
+/// and compare the prompts sent to models independently of any model's wire format.
+#[derive(Debug, Default, Clone)]
+pub struct TextChat {
+    sections: Vec<(String, String)>,
+    token_counts: bool,
+}
+
+/// Returns a rough estimate of the number of tokens in a text. Tokenizers differ between models,
+/// so this is only useful as a guide to relative sizes.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+impl TextChat {
+    /// Creates a chat that annotates each section with its estimated token count, and ends with
+    /// an estimated total.
+    pub fn with_token_counts() -> Self {
+        Self {
+            token_counts: true,
+            ..Default::default()
+        }
+    }
+
+    fn add(&mut self, header: String, text: &str) -> Result<()> {
+        self.sections.push((header, text.to_string()));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Chat for TextChat {
+    fn add_system_prompt(&mut self, prompt: &str) -> Result<()> {
+        self.add("system".into(), prompt)
+    }
+
+    fn add_user_message(&mut self, text: &str) -> Result<()> {
+        self.add("user".into(), text)
+    }
+
+    fn add_agent_message(&mut self, text: &str) -> Result<()> {
+        self.add("agent".into(), text)
+    }
+
+    fn add_context(&mut self, name: &str, data: &str) -> Result<()> {
+        self.add(format!("context: {}", name), data)
+    }
+
+    fn add_editable(&mut self, path: &str, data: &str) -> Result<()> {
+        self.add(format!("editable: {}", path), data)
+    }
+
+    async fn send(&mut self, _sender: Option<EventSender>) -> Result<ModelResponse> {
+        Err(TenxError::Internal("A text chat can't be sent".into()))
+    }
+
+    fn render(&self) -> Result<String> {
+        let mut sections = self
+            .sections
+            .iter()
+            .map(|(header, text)| {
+                if self.token_counts {
+                    format!(
+                        "## {} (~{} tokens)\n\n{}\n",
+                        header,
+                        estimate_tokens(text),
+                        text.trim_end()
+                    )
+                } else {
+                    format!("## {}\n\n{}\n", header, text.trim_end())
+                }
+            })
+            .collect::<Vec<_>>();
+        if self.token_counts {
+            let total: usize = self.sections.iter().map(|(_, t)| estimate_tokens(t)).sum();
+            sections.push(format!("## total\n\n~{} tokens\n", total));
+        }
+        Ok(sections.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_chat() {
+        let mut chat = TextChat::default();
+        chat.add_system_prompt("be terse").unwrap();
+        chat.add_editable("src/lib.rs", "fn a() {}\n").unwrap();
+        chat.add_user_message("do it").unwrap();
+        assert_eq!(
+            chat.render().unwrap(),
+            "## system\n\nbe terse\n\n## editable: src/lib.rs\n\nfn a() {}\n\n## user\n\ndo it\n"
+        );
+
+        let mut chat = TextChat::with_token_counts();
+        chat.add_system_prompt("be terse").unwrap();
+        chat.add_user_message("do it").unwrap();
+        assert_eq!(
+            chat.render().unwrap(),
+            "## system (~2 tokens)\n\nbe terse\n\n## user (~2 tokens)\n\ndo it\n\n## total\n\n~4 tokens\n"
+        );
+    }
+}
@@ -0,0 +1,171 @@
+/*!
+A stable, line-oriented description of a session, for editor integrations and other tools. Unlike
+the pretty output, this format is versioned, and only changes in backwards compatible ways within
+a version.
+
+Each line is a record: a record type followed by tab-separated fields. Tabs, line breaks and
+backslashes within fields are escaped as `\t`, `\n`, `\r` and `\\`. Consumers should ignore record
+types they don't recognise. The records are, in order:
+
+```text
+version     <format version>
+context     <context id>
+action      <action> <strategy> <completion> <input required>
+file        <action> <path>
+step        <action> <step> <status> <model>
+error       <action> <step> <message>
+last_error  <message>
+```
+
+Action and step indices are 0-based. Completion is one of `complete`, `incomplete` or
+`complete_continue`. Input required is one of `yes`, `no` or `optional`. Step status is one of
+`pending`, `error` or `done`. A `file` record lists a file touched by an action. `last_error`
+appears only if the last step of the session has an error.
+*/
+use crate::{
+    config::Config,
+    context::ContextProvider,
+    error::Result,
+    session::{Session, Step},
+    strategy::{ActionStrategy, Completion, InputRequired},
+};
+
+/// The version of the porcelain format. This is bumped when a change breaks compatibility.
+pub const VERSION: u32 = 1;
+
+/// Escapes a field value so it can't break the line format.
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// Formats a record from its type and fields.
+fn record(kind: &str, fields: &[String]) -> String {
+    let mut parts = vec![kind.to_string()];
+    parts.extend(fields.iter().map(|f| escape(f)));
+    parts.join("\t")
+}
+
+fn completion(c: &Completion) -> &'static str {
+    match c {
+        Completion::Complete => "complete",
+        Completion::Incomplete => "incomplete",
+        Completion::CompleteContinue => "complete_continue",
+    }
+}
+
+fn input_required(i: &InputRequired) -> &'static str {
+    match i {
+        InputRequired::Yes => "yes",
+        InputRequired::No => "no",
+        InputRequired::Optional => "optional",
+    }
+}
+
+fn step_status(step: &Step) -> &'static str {
+    if step.outcome.err.is_some()
+        || step
+            .outcome
+            .patch_info
+            .as_ref()
+            .is_some_and(|p| !p.failures.is_empty())
+    {
+        "error"
+    } else if step.is_incomplete() {
+        "pending"
+    } else {
+        "done"
+    }
+}
+
+/// Renders a session in the porcelain format.
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+pub fn render(config: &Config, session: &Session) -> Result<String> {
+    let mut lines = vec![record("version", &[VERSION.to_string()])];
+    for ctx in session.contexts.list() {
+        lines.push(record("context", &[ctx.id()]));
+    }
+    for (a, action) in session.actions.iter().enumerate() {
+        let state = action.strategy.state(config, session, a);
+        lines.push(record(
+            "action",
+            &[
+                a.to_string(),
+                action.strategy.name().to_string(),
+                completion(&state.completion).to_string(),
+                input_required(&state.input_required).to_string(),
+            ],
+        ));
+        for path in action.state.changed()? {
+            lines.push(record(
+                "file",
+                &[a.to_string(), path.to_string_lossy().to_string()],
+            ));
+        }
+        for (s, step) in action.steps.iter().enumerate() {
+            lines.push(record(
+                "step",
+                &[
+                    a.to_string(),
+                    s.to_string(),
+                    step_status(step).to_string(),
+                    step.request.model.clone(),
+                ],
+            ));
+            if let Some(err) = &step.outcome.err {
+                lines.push(record(
+                    "error",
+                    &[a.to_string(), s.to_string(), err.to_string()],
+                ));
+            }
+        }
+    }
+    if let Some(err) = session.last_step().and_then(|s| s.outcome.err.as_ref()) {
+        lines.push(record("last_error", &[err.to_string()]));
+    }
+    Ok(lines.join("\n") + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::Context,
+        error::TenxError,
+        session::Action,
+        strategy::{Code, Strategy, StrategyStep},
+        testutils::test_project,
+    };
+
+    #[test]
+    fn test_render() -> Result<()> {
+        let mut p = test_project();
+        p.session.add_context(Context::new_text("notes", "hello"));
+        let mut action = Action::new(&p.config, Strategy::Code(Code::new()))?;
+        action.add_step(Step::new(
+            "sonnet".into(),
+            "do it".into(),
+            StrategyStep::Code(Default::default()),
+        ))?;
+        action.steps[0].outcome.err = Some(TenxError::Internal("bad\tthing\nhappened".into()));
+        p.session.add_action(action)?;
+
+        let out = render(&p.config, &p.session)?;
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines[0], "version\t1");
+        assert_eq!(lines[1], "context\tnotes");
+        assert!(lines[2].starts_with("action\t0\tcode\t"));
+        assert_eq!(lines[3], "step\t0\t0\terror\tsonnet");
+        assert_eq!(
+            lines[4],
+            "error\t0\t0\tInternal error: bad\\tthing\\nhappened"
+        );
+        assert_eq!(
+            lines[5],
+            "last_error\tInternal error: bad\\tthing\\nhappened"
+        );
+        Ok(())
+    }
+}
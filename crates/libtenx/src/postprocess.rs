@@ -0,0 +1,114 @@
+//! Post-processing for files written by the model. Processors fix trivial issues, like trailing
+//! whitespace or a missing license header, that would otherwise fail checks and cost a retry.
+use std::path::Path;
+
+use globset::Glob;
+
+use crate::config::PostProcess;
+
+/// Strips trailing whitespace from every line, preserving line endings.
+fn trim_trailing_whitespace(content: &str) -> String {
+    content
+        .split_inclusive('\n')
+        .map(|line| {
+            let (text, ending) = match line.strip_suffix("\r\n") {
+                Some(text) => (text, "\r\n"),
+                None => match line.strip_suffix('\n') {
+                    Some(text) => (text, "\n"),
+                    None => (line, ""),
+                },
+            };
+            format!("{}{}", text.trim_end(), ending)
+        })
+        .collect()
+}
+
+/// Makes sure non-empty content ends with exactly one newline.
+fn final_newline(content: &str) -> String {
+    let trimmed = content.trim_end_matches(['\n', '\r']);
+    if trimmed.is_empty() {
+        return String::new();
+    }
+    format!("{}\n", trimmed)
+}
+
+/// Returns true if the path matches any of the glob patterns.
+fn matches_globs(globs: &[String], path: &Path) -> bool {
+    globs.iter().any(|g| {
+        Glob::new(g)
+            .map(|g| g.compile_matcher().is_match(path))
+            .unwrap_or(false)
+    })
+}
+
+/// Applies the configured processors to a file written by the model. `created` is true if the
+/// file is new. Returns the processed content, or None if nothing changed.
+pub fn process(conf: &PostProcess, path: &Path, content: &str, created: bool) -> Option<String> {
+    let mut ret = content.to_string();
+    if created {
+        if let Some(header) = conf
+            .license_headers
+            .iter()
+            .find(|h| matches_globs(&h.globs, path))
+        {
+            let text = header.text.trim_end();
+            if !text.is_empty() && !ret.trim_start().starts_with(text) {
+                ret = format!("{}\n\n{}", text, ret);
+            }
+        }
+    }
+    if conf.trim_trailing_whitespace {
+        ret = trim_trailing_whitespace(&ret);
+    }
+    if conf.final_newline {
+        ret = final_newline(&ret);
+    }
+    (ret != content).then_some(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LicenseHeader;
+
+    #[test]
+    fn test_process() {
+        let conf = PostProcess {
+            final_newline: true,
+            trim_trailing_whitespace: true,
+            license_headers: vec![LicenseHeader {
+                globs: vec!["**/*.rs".into()],
+                text: "// SPDX-License-Identifier: MIT\n".into(),
+            }],
+        };
+        let rs = Path::new("src/lib.rs");
+
+        assert_eq!(
+            process(&conf, rs, "fn a() {}  \r\n\n\n", false),
+            Some("fn a() {}\n".into())
+        );
+        assert_eq!(process(&conf, rs, "fn a() {}\n", false), None);
+        assert_eq!(process(&conf, rs, "", false), None);
+
+        // Headers are only added to new files that match, and don't already have one
+        assert_eq!(
+            process(&conf, rs, "fn a() {}\n", true),
+            Some("// SPDX-License-Identifier: MIT\n\nfn a() {}\n".into())
+        );
+        assert_eq!(
+            process(
+                &conf,
+                rs,
+                "// SPDX-License-Identifier: MIT\nfn a() {}\n",
+                true
+            ),
+            None
+        );
+        assert_eq!(process(&conf, Path::new("README.md"), "# x\n", true), None);
+
+        assert_eq!(
+            process(&PostProcess::default(), rs, "fn a() {}  ", true),
+            None
+        );
+    }
+}
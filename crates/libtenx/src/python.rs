@@ -0,0 +1,236 @@
+//! Discovery of a project's Python environment, so that Python tools like ruff, pytest and mypy
+//! run with the project's interpreter and installed packages, rather than whatever comes first on
+//! the `PATH`.
+use std::{
+    env, fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::exec::{exe_name, Executor};
+
+/// Virtual environment directories we look for in the project root, in order.
+const VENV_DIRS: &[&str] = &[".venv", "venv"];
+
+/// How a Python environment was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvKind {
+    /// The environment of a uv project
+    Uv,
+    /// The environment of a Poetry project
+    Poetry,
+    /// A plain virtual environment in the project root
+    Venv,
+    /// The virtual environment active when tenx was started
+    Active,
+}
+
+impl fmt::Display for EnvKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            EnvKind::Uv => "uv",
+            EnvKind::Poetry => "poetry",
+            EnvKind::Venv => "venv",
+            EnvKind::Active => "active",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A Python virtual environment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PythonEnv {
+    pub kind: EnvKind,
+    /// The root directory of the environment
+    pub path: PathBuf,
+}
+
+impl PythonEnv {
+    /// The directory the environment's executables are installed in.
+    pub fn bin_dir(&self) -> PathBuf {
+        if cfg!(windows) {
+            self.path.join("Scripts")
+        } else {
+            self.path.join("bin")
+        }
+    }
+
+    /// The environment's Python interpreter.
+    pub fn interpreter(&self) -> PathBuf {
+        self.bin_dir().join(exe_name("python"))
+    }
+
+    /// Returns the path to a program installed in the environment, if it exists.
+    pub fn program(&self, name: &str) -> Option<PathBuf> {
+        let path = self.bin_dir().join(exe_name(name));
+        path.is_file().then_some(path)
+    }
+
+    /// Rewrites a shell command to run its program from the environment, if the environment
+    /// provides it. Other commands are returned unchanged, so tools installed globally still
+    /// work.
+    pub fn command(&self, cmd: &str) -> String {
+        let trimmed = cmd.trim_start();
+        let program = trimmed.split_whitespace().next().unwrap_or_default();
+        if program.is_empty() || program.contains(['/', '\\']) {
+            return cmd.to_string();
+        }
+        match self.program(program) {
+            Some(path) => format!("{}{}", quote_path(&path), &trimmed[program.len()..]),
+            None => cmd.to_string(),
+        }
+    }
+}
+
+/// Quotes a path for the platform shell, if it needs quoting.
+fn quote_path(path: &Path) -> String {
+    let s = path.display().to_string();
+    if !s.contains(char::is_whitespace) {
+        s
+    } else if cfg!(windows) {
+        format!("\"{}\"", s)
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
+}
+
+/// Is a directory a virtual environment?
+fn is_venv(dir: &Path) -> bool {
+    dir.join("pyvenv.cfg").is_file()
+}
+
+/// Returns true if the project's pyproject.toml has a `[tool.<name>]` table.
+fn has_tool(root: &Path, name: &str) -> bool {
+    std::fs::read_to_string(root.join("pyproject.toml"))
+        .ok()
+        .and_then(|s| s.parse::<toml::Table>().ok())
+        .is_some_and(|t| {
+            t.get("tool")
+                .and_then(|tool| tool.get(name))
+                .is_some_and(|v| v.is_table())
+        })
+}
+
+/// The environment of a uv project, which lives in `.venv` unless `UV_PROJECT_ENVIRONMENT` says
+/// otherwise.
+fn uv_env(root: &Path) -> Option<PythonEnv> {
+    if !root.join("uv.lock").is_file() && !has_tool(root, "uv") {
+        return None;
+    }
+    let path = match env::var_os("UV_PROJECT_ENVIRONMENT") {
+        Some(dir) => root.join(dir),
+        None => root.join(".venv"),
+    };
+    is_venv(&path).then_some(PythonEnv {
+        kind: EnvKind::Uv,
+        path,
+    })
+}
+
+/// The environment of a Poetry project. Poetry keeps environments in the project when configured
+/// to, and otherwise in its cache, where we have to ask Poetry for the location.
+fn poetry_env(root: &Path, executor: &dyn Executor) -> Option<PythonEnv> {
+    if !root.join("poetry.lock").is_file() && !has_tool(root, "poetry") {
+        return None;
+    }
+    let local = root.join(".venv");
+    let path = if is_venv(&local) {
+        local
+    } else {
+        let output = executor.exec(root, "poetry env info -p").ok()?;
+        if !output.success() {
+            return None;
+        }
+        PathBuf::from(output.stdout.trim())
+    };
+    is_venv(&path).then_some(PythonEnv {
+        kind: EnvKind::Poetry,
+        path,
+    })
+}
+
+/// Discovers the Python environment for a project rooted at `root`. Project managers are
+/// consulted first, then virtual environments in the project root, and finally the environment
+/// active in tenx's own environment.
+pub fn detect(root: &Path, executor: &dyn Executor) -> Option<PythonEnv> {
+    if let Some(env) = uv_env(root).or_else(|| poetry_env(root, executor)) {
+        return Some(env);
+    }
+    for dir in VENV_DIRS {
+        let path = root.join(dir);
+        if is_venv(&path) {
+            return Some(PythonEnv {
+                kind: EnvKind::Venv,
+                path,
+            });
+        }
+    }
+    let path = PathBuf::from(env::var_os("VIRTUAL_ENV")?);
+    is_venv(&path).then_some(PythonEnv {
+        kind: EnvKind::Active,
+        path,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::{ExecOutput, FakeExecutor};
+    use tempfile::tempdir;
+
+    fn make_venv(dir: &Path, programs: &[&str]) -> PythonEnv {
+        let env = PythonEnv {
+            kind: EnvKind::Venv,
+            path: dir.to_path_buf(),
+        };
+        std::fs::create_dir_all(env.bin_dir()).unwrap();
+        std::fs::write(dir.join("pyvenv.cfg"), "home = /usr/bin\n").unwrap();
+        for p in programs {
+            std::fs::write(env.bin_dir().join(exe_name(p)), "").unwrap();
+        }
+        env
+    }
+
+    #[test]
+    fn test_detect() {
+        let root = tempdir().unwrap();
+        let root = root.path();
+        let none = FakeExecutor::default();
+
+        // A poetry project with its environment in Poetry's cache
+        std::fs::write(root.join("pyproject.toml"), "[tool.poetry]\nname = \"x\"\n").unwrap();
+        let cache = tempdir().unwrap();
+        make_venv(cache.path(), &[]);
+        let poetry = FakeExecutor::default().with_output(
+            "poetry env info",
+            ExecOutput::new(0, &cache.path().display().to_string(), ""),
+        );
+        let env = detect(root, &poetry).unwrap();
+        assert_eq!(env.kind, EnvKind::Poetry);
+        assert_eq!(env.path, cache.path());
+
+        // An in-project environment is preferred, without asking Poetry
+        make_venv(&root.join(".venv"), &["pytest"]);
+        let env = detect(root, &none).unwrap();
+        assert_eq!(env.kind, EnvKind::Poetry);
+        assert_eq!(env.path, root.join(".venv"));
+
+        std::fs::write(root.join("pyproject.toml"), "[project]\nname = \"x\"\n").unwrap();
+        assert_eq!(detect(root, &none).unwrap().kind, EnvKind::Venv);
+        std::fs::write(root.join("uv.lock"), "").unwrap();
+        assert_eq!(detect(root, &none).unwrap().kind, EnvKind::Uv);
+    }
+
+    #[test]
+    fn test_command() {
+        let dir = tempdir().unwrap();
+        let env = make_venv(dir.path(), &["pytest"]);
+        let pytest = env.bin_dir().join(exe_name("pytest"));
+        assert_eq!(
+            env.command("pytest -q {tests}"),
+            format!("{} -q {{tests}}", quote_path(&pytest))
+        );
+        // Programs the environment doesn't have are left alone
+        assert_eq!(env.command("ruff check -q"), "ruff check -q");
+        assert_eq!(env.command("./pytest -q"), "./pytest -q");
+    }
+}
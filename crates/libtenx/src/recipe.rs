@@ -0,0 +1,253 @@
+//! Recipes are scripted multi-step workflows, defined in a TOML or YAML file as an ordered list of
+//! items.
+//! Each item is either a prompt, run as an action of its own with the item's editables and checks,
+//! or a shell command. For example:
+//!
+//! ```toml
+//! name = "add endpoint"
+//!
+//! [[item]]
+//! name = "router"
+//! prompt = "Add a /health route to the router"
+//! edit = ["src/router.rs"]
+//! checks = ["cargo-check"]
+//!
+//! [[item]]
+//! command = "cargo fmt"
+//! on_failure = "continue"
+//! ```
+//!
+//! The same recipe in YAML lists its items under `item`:
+//!
+//! ```yaml
+//! name: add endpoint
+//! item:
+//!   - name: router
+//!     prompt: Add a /health route to the router
+//!     edit: [src/router.rs]
+//!     checks: [cargo-check]
+//!   - command: cargo fmt
+//!     on_failure: continue
+//! ```
+use std::path::Path;
+
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    error::{Result, TenxError},
+};
+
+/// What to do when a recipe item fails.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnFailure {
+    /// Stop running the recipe.
+    #[default]
+    Stop,
+    /// Carry on with the next item.
+    Continue,
+}
+
+/// A single item in a recipe.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecipeItem {
+    /// A name for the item, used when reporting progress
+    pub name: Option<String>,
+    /// A prompt for the model
+    pub prompt: Option<String>,
+    /// A shell command, run in the project root
+    pub command: Option<String>,
+    /// Files or globs to make editable for the prompt
+    #[serde(default)]
+    pub edit: Vec<String>,
+    /// Checks to run after the prompt's changes. If empty, the enabled checks run as usual.
+    #[serde(default)]
+    pub checks: Vec<String>,
+    /// The model or alias to use for the prompt
+    pub model: Option<String>,
+    /// What to do if the item fails, overriding the recipe's setting
+    pub on_failure: Option<OnFailure>,
+}
+
+impl RecipeItem {
+    /// The item's name, or a description based on its position and contents.
+    pub fn label(&self, index: usize) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        match &self.command {
+            Some(command) => format!("{}: {}", index + 1, command),
+            None => format!("{}: prompt", index + 1),
+        }
+    }
+
+    /// Returns the config to run the item's prompt with. If the item names checks, only those
+    /// checks are enabled.
+    pub fn config(&self, base: &Config) -> Result<Config> {
+        let mut config = base.clone();
+        if self.checks.is_empty() {
+            return Ok(config);
+        }
+        let names: Vec<String> = config.all_checks().into_iter().map(|c| c.name).collect();
+        if let Some(unknown) = self.checks.iter().find(|c| !names.contains(c)) {
+            return Err(TenxError::Config(format!(
+                "Unknown check in recipe: {}",
+                unknown
+            )));
+        }
+        config.checks.enable.extend(self.checks.iter().cloned());
+        config.checks.disable = names
+            .into_iter()
+            .filter(|n| !self.checks.contains(n))
+            .collect();
+        config.checks.only = None;
+        Ok(config)
+    }
+}
+
+/// A scripted workflow.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Recipe {
+    pub name: Option<String>,
+    /// What to do when an item fails, unless the item says otherwise
+    #[serde(default)]
+    pub on_failure: OnFailure,
+    #[serde(default, rename = "item")]
+    pub items: Vec<RecipeItem>,
+}
+
+impl Recipe {
+    /// Parses a TOML recipe, checking that every item has exactly one of a prompt or a command.
+    pub fn parse(text: &str) -> Result<Self> {
+        toml::from_str::<Recipe>(text)
+            .map_err(|e| TenxError::Config(format!("Invalid recipe: {}", e)))?
+            .validate()
+    }
+
+    /// Parses a YAML recipe, with the same structure and checks as a TOML one.
+    pub fn parse_yaml(text: &str) -> Result<Self> {
+        serde_yaml::from_str::<Recipe>(text)
+            .map_err(|e| TenxError::Config(format!("Invalid recipe: {}", e)))?
+            .validate()
+    }
+
+    fn validate(self) -> Result<Self> {
+        let recipe = self;
+        if recipe.items.is_empty() {
+            return Err(TenxError::Config("Recipe has no items".into()));
+        }
+        for (i, item) in recipe.items.iter().enumerate() {
+            if item.prompt.is_some() == item.command.is_some() {
+                return Err(TenxError::Config(format!(
+                    "Recipe item {} must have either a prompt or a command",
+                    i + 1
+                )));
+            }
+            if item.command.is_some() && (!item.edit.is_empty() || !item.checks.is_empty()) {
+                return Err(TenxError::Config(format!(
+                    "Recipe item {} is a command, and can't have editables or checks",
+                    i + 1
+                )));
+            }
+        }
+        Ok(recipe)
+    }
+
+    /// Loads a recipe from a file. Files ending in `.yaml` or `.yml` are YAML, and anything else
+    /// is TOML.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml" | "yml") => Self::parse_yaml(&text),
+            _ => Self::parse(&text),
+        }
+    }
+
+    /// What to do when the item at `index` fails.
+    pub fn on_failure(&self, index: usize) -> OnFailure {
+        self.items
+            .get(index)
+            .and_then(|i| i.on_failure)
+            .unwrap_or(self.on_failure)
+    }
+}
+
+/// The result of running a recipe item.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ItemResult {
+    pub label: String,
+    /// Why the item failed, if it did
+    pub error: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutils::test_project;
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let recipe = Recipe::parse(
+            r#"
+            name = "add endpoint"
+            on_failure = "continue"
+
+            [[item]]
+            prompt = "Add a handler"
+            edit = ["src/*.rs"]
+            checks = ["cargo-check"]
+
+            [[item]]
+            command = "cargo fmt"
+            on_failure = "stop"
+            "#,
+        )?;
+        assert_eq!(recipe.items.len(), 2);
+        assert_eq!(recipe.items[0].edit, vec!["src/*.rs"]);
+        assert_eq!(recipe.items[0].label(0), "1: prompt");
+        assert_eq!(recipe.items[1].label(1), "2: cargo fmt");
+        assert_eq!(recipe.on_failure(0), OnFailure::Continue);
+        assert_eq!(recipe.on_failure(1), OnFailure::Stop);
+
+        assert!(Recipe::parse("").is_err());
+        assert!(Recipe::parse("[[item]]\nprompt = \"a\"\ncommand = \"b\"\n").is_err());
+        assert!(Recipe::parse("[[item]]\ncommand = \"b\"\nedit = [\"x\"]\n").is_err());
+        assert!(Recipe::parse("[[item]]\nprompt = \"a\"\nbogus = 1\n").is_err());
+
+        let yaml = Recipe::parse_yaml(
+            "name: add endpoint\non_failure: continue\nitem:\n  - prompt: Add a handler\n    \
+             edit: [src/*.rs]\n    checks: [cargo-check]\n  - command: cargo fmt\n    \
+             on_failure: stop\n",
+        )?;
+        assert_eq!(yaml, recipe);
+        assert!(Recipe::parse_yaml("item:\n  - prompt: a\n    command: b\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_item_config() -> Result<()> {
+        let p = test_project();
+        let mut config = p.config.clone();
+        config.checks.builtin = crate::config::default_config(".").checks.builtin;
+        config.project.languages = vec!["rust".into()];
+        let item = RecipeItem {
+            prompt: Some("x".into()),
+            checks: vec!["cargo-fmt".into()],
+            ..Default::default()
+        };
+        let scoped = item.config(&config)?;
+        assert!(scoped.is_check_enabled("cargo-fmt"));
+        assert!(!scoped.is_check_enabled("cargo-check"));
+
+        let unknown = RecipeItem {
+            checks: vec!["nope".into()],
+            ..Default::default()
+        };
+        assert!(unknown.config(&config).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+//! Safety analysis of patches before they're applied. Some changes are cheap to make and
+//! expensive to get wrong: deleting large amounts of code, editing CI workflows, changing
+//! dependency manifests, or touching `unsafe` code. Patches that make them have to be confirmed
+//! by the user, or explicitly allowed with `--allow-risky`.
+use std::{fmt, path::Path, sync::LazyLock};
+
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use state::{Change, Patch, State};
+
+use crate::config::Config;
+
+/// Globs for CI and workflow configuration.
+const WORKFLOW_GLOBS: &[&str] = &[
+    ".github/workflows/**",
+    ".github/actions/**",
+    ".gitlab-ci.yml",
+    ".circleci/**",
+    ".travis.yml",
+    "azure-pipelines.yml",
+    "Jenkinsfile",
+    ".buildkite/**",
+];
+
+/// File names of dependency manifests and lock files.
+const MANIFESTS: &[&str] = &[
+    "Cargo.toml",
+    "Cargo.lock",
+    "package.json",
+    "package-lock.json",
+    "yarn.lock",
+    "pnpm-lock.yaml",
+    "pyproject.toml",
+    "setup.py",
+    "setup.cfg",
+    "poetry.lock",
+    "uv.lock",
+    "Pipfile",
+    "Pipfile.lock",
+    "go.mod",
+    "go.sum",
+    "Gemfile",
+    "Gemfile.lock",
+];
+
+/// The kind of a risky operation.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RiskKind {
+    /// Deletes more lines from a file than the configured limit
+    Deletion,
+    /// Changes CI or workflow configuration
+    Workflow,
+    /// Changes a dependency manifest or lock file
+    Manifest,
+    /// Adds, removes or changes lines that open Rust `unsafe` blocks or functions
+    Unsafe,
+}
+
+/// A risky operation found in a patch.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+pub struct Risk {
+    pub kind: RiskKind,
+    /// The file the operation affects
+    pub path: String,
+    /// What the operation does, for the user
+    pub detail: String,
+}
+
+impl fmt::Display for Risk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.detail)
+    }
+}
+
+/// Summarizes risks on a single line, for error messages.
+pub fn summary(risks: &[Risk]) -> String {
+    risks
+        .iter()
+        .map(Risk::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn is_workflow(path: &Path) -> bool {
+    WORKFLOW_GLOBS.iter().any(|g| {
+        globset::Glob::new(g)
+            .map(|g| g.compile_matcher().is_match(path))
+            .unwrap_or(false)
+    })
+}
+
+fn is_manifest(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    MANIFESTS.contains(&name.as_ref())
+        || (name.starts_with("requirements") && name.ends_with(".txt"))
+}
+
+/// String literals, whose contents aren't code.
+static STRING: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#""(?:[^"\\]|\\.)*""#).expect("valid string pattern"));
+
+/// The start of an `unsafe` block or function. A block's brace may be on the next line.
+static UNSAFE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"\bunsafe\s*(?:\{|$|(?:extern\s*(?:""\s*)?)?fn\b)"#).expect("valid unsafe pattern")
+});
+
+/// Whether a line of a Rust file opens an `unsafe` block or function. Comments and strings that
+/// mention unsafe code don't count.
+fn is_unsafe(path: &Path, line: &str) -> bool {
+    if path.extension().is_none_or(|e| e != "rs") {
+        return false;
+    }
+    let code = STRING.replace_all(line, "\"\"");
+    let code = code.split("//").next().unwrap_or_default();
+    UNSAFE.is_match(code.trim_end())
+}
+
+/// The text a change replaces, and the text it replaces it with. Changes that don't edit text
+/// return None.
+fn edit(state: &State, change: &Change) -> Option<(String, String)> {
+    match change {
+        Change::Write(w) => Some((state.read(&w.path).unwrap_or_default(), w.content.clone())),
+        Change::Replace(r) => Some((r.old.clone(), r.new.clone())),
+        Change::ReplaceFuzzy(r) => Some((r.old.clone(), r.new.clone())),
+        Change::Insert(i) => Some((String::new(), i.new.clone())),
+        _ => None,
+    }
+}
+
+/// Finds the risky operations in a patch, before it's applied to `state`.
+pub fn assess(config: &Config, state: &State, patch: &Patch) -> Vec<Risk> {
+    let mut risks: Vec<Risk> = vec![];
+    let mut add = |kind: RiskKind, path: &Path, detail: String| {
+        let path = path.to_string_lossy().to_string();
+        if !risks.iter().any(|r| r.kind == kind && r.path == path) {
+            risks.push(Risk { kind, path, detail });
+        }
+    };
+    let mut deleted = std::collections::BTreeMap::new();
+    for change in &patch.changes {
+        if matches!(change, Change::View(_) | Change::ViewRange(..)) {
+            continue;
+        }
+        for path in change.paths() {
+            let path = path.as_path();
+            if is_workflow(path) {
+                add(RiskKind::Workflow, path, "changes CI configuration".into());
+            }
+            if is_manifest(path) {
+                add(RiskKind::Manifest, path, "changes dependencies".into());
+            }
+        }
+        let Some((old, new)) = edit(state, change) else {
+            continue;
+        };
+        for hunk in diffy::create_patch(&old, &new).hunks() {
+            for line in hunk.lines() {
+                let text = match line {
+                    diffy::Line::Delete(text) => {
+                        *deleted.entry(change.path().to_path_buf()).or_insert(0) += 1;
+                        text
+                    }
+                    diffy::Line::Insert(text) => text,
+                    diffy::Line::Context(_) => continue,
+                };
+                if is_unsafe(change.path(), text) {
+                    add(
+                        RiskKind::Unsafe,
+                        change.path().as_path(),
+                        "changes unsafe code".into(),
+                    );
+                }
+            }
+        }
+    }
+    let limit = config.safety.max_deleted_lines;
+    for (path, count) in deleted {
+        if limit > 0 && count > limit {
+            add(
+                RiskKind::Deletion,
+                path.as_path(),
+                format!("deletes {} lines", count),
+            );
+        }
+    }
+    risks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutils::test_project;
+
+    #[test]
+    fn test_assess() {
+        let mut p = test_project();
+        p.config.safety.max_deleted_lines = 3;
+        let long: String = (0..10).map(|i| format!("line {}\n", i)).collect();
+        p.create_file_tree(&["src/lib.rs", "src/long.rs"]);
+        p.write("src/lib.rs", "fn f() {}\n");
+        p.write("src/long.rs", &long);
+        let state = p.config.state().unwrap();
+
+        let patch = Patch::default().with_replace("src/lib.rs", "fn f() {}", "fn g() {}");
+        assert!(assess(&p.config, &state, &patch).is_empty());
+
+        let patch = Patch::default()
+            .with_write(".github/workflows/ci.yml", "on: push\n")
+            .with_write("crates/core/Cargo.toml", "[package]\n")
+            .with_replace("src/lib.rs", "fn f() {}", "unsafe fn f() {}")
+            .with_write("src/long.rs", "line 0\n");
+        let kinds: Vec<(RiskKind, String)> = assess(&p.config, &state, &patch)
+            .into_iter()
+            .map(|r| (r.kind, r.path))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (RiskKind::Workflow, ".github/workflows/ci.yml".into()),
+                (RiskKind::Manifest, "crates/core/Cargo.toml".into()),
+                (RiskKind::Unsafe, "src/lib.rs".into()),
+                (RiskKind::Deletion, "src/long.rs".into()),
+            ]
+        );
+        // Only Rust unsafe blocks and functions count
+        let rs = Path::new("src/lib.rs");
+        assert!(is_unsafe(rs, "    let x = unsafe { *p };"));
+        assert!(is_unsafe(rs, "    unsafe"));
+        assert!(is_unsafe(rs, "pub unsafe fn f() {}"));
+        assert!(is_unsafe(rs, "pub unsafe extern \"C\" fn f() {}"));
+        assert!(!is_unsafe(rs, "let unsafe_count = 1;"));
+        assert!(!is_unsafe(rs, "// This used to be unsafe {"));
+        assert!(!is_unsafe(rs, "let msg = \"unsafe { code }\";"));
+        assert!(!is_unsafe(Path::new("README.md"), "unsafe { *p }"));
+        assert!(!is_unsafe(Path::new("app.py"), "def unsafe(): pass"));
+    }
+}
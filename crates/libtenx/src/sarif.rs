@@ -0,0 +1,313 @@
+//! Structured diagnostics parsed from check output, and conversion to SARIF 2.1.0 for upload to
+//! code scanning tools.
+use std::collections::BTreeMap;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    checks::Check,
+    error::{Result, TenxError},
+};
+
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+const CLIPPY_LINTS: &str = "https://rust-lang.github.io/rust-clippy/master/index.html#";
+
+/// The severity of a diagnostic, named as in SARIF.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Level {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warning => "warning",
+            Level::Note => "note",
+        }
+    }
+}
+
+/// A single problem reported by a check.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The name of the check that reported the problem.
+    pub check: String,
+    /// The tool's identifier for the rule, like `E0308`, `clippy::needless_return` or `F401`.
+    pub rule: Option<String>,
+    pub level: Level,
+    pub message: String,
+    /// The file path as reported by the tool, relative to the project root.
+    pub path: Option<String>,
+    /// The 1-based line number.
+    pub line: Option<usize>,
+    /// The 1-based column number.
+    pub column: Option<usize>,
+}
+
+/// The output formats we know how to parse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// rustc's human-readable output, also used by cargo and clippy.
+    Rustc,
+    /// ruff's full or concise output.
+    Ruff,
+}
+
+impl Format {
+    /// Picks the parser for a check from the program its command runs.
+    fn for_check(check: &Check) -> Option<Self> {
+        match check.command.split_whitespace().next()? {
+            "cargo" | "rustc" | "clippy-driver" => Some(Format::Rustc),
+            "ruff" => Some(Format::Ruff),
+            _ => None,
+        }
+    }
+}
+
+/// Parses rustc-style diagnostics, which start with a header like `error[E0308]: mismatched
+/// types` followed by a ` --> path:line:col` location. Diagnostics without a location, like
+/// cargo's "could not compile" summary, are dropped.
+fn parse_rustc(check: &str, output: &str) -> Vec<Diagnostic> {
+    let header = Regex::new(r"^(error|warning)(?:\[(\w+)\])?: (.+)$").unwrap();
+    let location = Regex::new(r"^\s*--> (.+):(\d+):(\d+)$").unwrap();
+    let lint = Regex::new(&format!(r"{}(\w+)", regex::escape(CLIPPY_LINTS))).unwrap();
+
+    let mut diagnostics = vec![];
+    let mut current: Option<Diagnostic> = None;
+    for line in output.lines() {
+        if let Some(c) = header.captures(line) {
+            diagnostics.extend(current.take().filter(|d| d.path.is_some()));
+            current = Some(Diagnostic {
+                check: check.to_string(),
+                rule: c.get(2).map(|m| m.as_str().to_string()),
+                level: if &c[1] == "error" {
+                    Level::Error
+                } else {
+                    Level::Warning
+                },
+                message: c[3].to_string(),
+                path: None,
+                line: None,
+                column: None,
+            });
+        } else if let Some(d) = current.as_mut() {
+            if let Some(c) = location.captures(line).filter(|_| d.path.is_none()) {
+                d.path = Some(c[1].to_string());
+                d.line = c[2].parse().ok();
+                d.column = c[3].parse().ok();
+            } else if let Some(c) = lint.captures(line).filter(|_| d.rule.is_none()) {
+                d.rule = Some(format!("clippy::{}", &c[1]));
+            }
+        }
+    }
+    diagnostics.extend(current.filter(|d| d.path.is_some()));
+    diagnostics
+}
+
+/// Parses ruff diagnostics, which start with a line like `src/app.py:3:8: F401 [*] message`.
+fn parse_ruff(check: &str, output: &str) -> Vec<Diagnostic> {
+    let header = Regex::new(r"^(.+?):(\d+):(\d+): ([A-Z]+\d+) (?:\[\*\] )?(.+)$").unwrap();
+    output
+        .lines()
+        .filter_map(|line| header.captures(line))
+        .map(|c| Diagnostic {
+            check: check.to_string(),
+            rule: Some(c[4].to_string()),
+            level: Level::Error,
+            message: c[5].to_string(),
+            path: Some(c[1].to_string()),
+            line: c[2].parse().ok(),
+            column: c[3].parse().ok(),
+        })
+        .collect()
+}
+
+/// Parses diagnostics from tools we don't know, from lines that start with a location like
+/// `src/app.py:12: error: message` or `src/app.js:3:7: message`. This is the form used by mypy,
+/// pytest tracebacks and most linters' compact output.
+fn parse_located(check: &str, output: &str) -> Vec<Diagnostic> {
+    let header =
+        Regex::new(r"^([^\s:]+):(\d+)(?::(\d+))?: (?:(error|warning|note): )?(.+)$").unwrap();
+    output
+        .lines()
+        .filter_map(|line| header.captures(line))
+        .map(|c| Diagnostic {
+            check: check.to_string(),
+            rule: None,
+            level: match c.get(4).map(|m| m.as_str()) {
+                Some("warning") => Level::Warning,
+                Some("note") => Level::Note,
+                _ => Level::Error,
+            },
+            message: c[5].to_string(),
+            path: Some(c[1].to_string()),
+            line: c[2].parse().ok(),
+            column: c.get(3).and_then(|m| m.as_str().parse().ok()),
+        })
+        .collect()
+}
+
+/// Parses the output of a failed check into diagnostics. If the tool's format is unknown, or no
+/// diagnostics can be found in the output, the failure is reported as one diagnostic without a
+/// location, so it isn't lost.
+pub fn parse(check: &Check, output: &str) -> Vec<Diagnostic> {
+    let diagnostics = match Format::for_check(check) {
+        Some(Format::Rustc) => parse_rustc(&check.name, output),
+        Some(Format::Ruff) => parse_ruff(&check.name, output),
+        None => parse_located(&check.name, output),
+    };
+    if diagnostics.is_empty() {
+        vec![Diagnostic {
+            check: check.name.clone(),
+            rule: None,
+            level: Level::Error,
+            message: format!("Check command failed: {}", check.command),
+            path: None,
+            line: None,
+            column: None,
+        }]
+    } else {
+        diagnostics
+    }
+}
+
+/// Converts diagnostics to a pretty-printed SARIF 2.1.0 log, with one run per check.
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+pub fn to_sarif(diagnostics: &[Diagnostic]) -> Result<String> {
+    let mut by_check: BTreeMap<&str, Vec<&Diagnostic>> = BTreeMap::new();
+    for d in diagnostics {
+        by_check.entry(&d.check).or_default().push(d);
+    }
+    let runs: Vec<Value> = by_check
+        .into_iter()
+        .map(|(check, diagnostics)| {
+            let results: Vec<Value> = diagnostics.into_iter().map(result).collect();
+            json!({
+                "tool": { "driver": { "name": check } },
+                "results": results,
+            })
+        })
+        .collect();
+    let log = json!({
+        "$schema": SARIF_SCHEMA,
+        "version": "2.1.0",
+        "runs": runs,
+    });
+    serde_json::to_string_pretty(&log).map_err(|e| TenxError::Internal(e.to_string()))
+}
+
+/// Converts a diagnostic to a SARIF result.
+fn result(d: &Diagnostic) -> Value {
+    let mut result = json!({
+        "level": d.level.as_str(),
+        "message": { "text": d.message },
+    });
+    if let Some(rule) = &d.rule {
+        result["ruleId"] = json!(rule);
+    }
+    if let Some(path) = &d.path {
+        let mut region = json!({});
+        if let Some(line) = d.line {
+            region["startLine"] = json!(line);
+        }
+        if let Some(column) = d.column {
+            region["startColumn"] = json!(column);
+        }
+        result["locations"] = json!([{
+            "physicalLocation": {
+                "artifactLocation": {
+                    "uri": path.trim_start_matches("./").replace('\\', "/"),
+                    "uriBaseId": "%SRCROOT%",
+                },
+                "region": region,
+            }
+        }]);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checks::CheckMode;
+
+    fn check(name: &str, command: &str) -> Check {
+        Check {
+            name: name.into(),
+            command: command.into(),
+            globs: vec![],
+            default_off: false,
+            fail_on_stderr: false,
+            mode: CheckMode::Validate,
+            focus: None,
+            cwd: None,
+            fix: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_and_convert() -> Result<()> {
+        let clippy = check("cargo-clippy", "cargo clippy --all-targets");
+        let output = "stdout:\n\n\nstderr:\n    Checking foo v0.1.0\n\
+            error[E0308]: mismatched types\n --> src/lib.rs:3:5\n  |\n3 |     \"x\"\n\n\
+            warning: unneeded `return` statement\n  --> src/main.rs:10:9\n   |\n\
+            = help: for further information visit https://rust-lang.github.io/rust-clippy/master/index.html#needless_return\n\n\
+            error: could not compile `foo` due to 1 previous error\n";
+        let diagnostics = parse(&clippy, output);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].rule.as_deref(), Some("E0308"));
+        assert_eq!(diagnostics[0].path.as_deref(), Some("src/lib.rs"));
+        assert_eq!(
+            (diagnostics[0].line, diagnostics[0].column),
+            (Some(3), Some(5))
+        );
+        assert_eq!(diagnostics[1].level, Level::Warning);
+        assert_eq!(
+            diagnostics[1].rule.as_deref(),
+            Some("clippy::needless_return")
+        );
+
+        let ruff = check("ruff-check", "ruff check -q");
+        let output = "stdout:\napp.py:1:8: F401 [*] `os` imported but unused\n\
+            app.py:4:1: E711 Comparison to `None`\nFound 2 errors.\n\nstderr:\n";
+        let diagnostics = parse(&ruff, output);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].message, "`os` imported but unused");
+        assert_eq!(diagnostics[1].rule.as_deref(), Some("E711"));
+
+        let custom = check("custom", "./lint.sh");
+        let unknown = parse(&custom, "stdout:\nbad things\n");
+        assert_eq!(unknown.len(), 1);
+        assert!(unknown[0].path.is_none());
+
+        let mypy = check("mypy", "mypy .");
+        let located = parse(
+            &mypy,
+            "stdout:\napp.py:4: error: Incompatible return value\napp.py:9:3: note: See here\n",
+        );
+        assert_eq!(located.len(), 2);
+        assert_eq!(located[0].path.as_deref(), Some("app.py"));
+        assert_eq!((located[0].line, located[0].column), (Some(4), None));
+        assert_eq!(located[0].message, "Incompatible return value");
+        assert_eq!(located[1].level, Level::Note);
+        assert_eq!(located[1].column, Some(3));
+
+        let all: Vec<Diagnostic> = diagnostics.into_iter().chain(unknown).collect();
+        let log: Value = serde_json::from_str(&to_sarif(&all)?).unwrap();
+        assert_eq!(log["version"], "2.1.0");
+        assert_eq!(log["runs"].as_array().unwrap().len(), 2);
+        let run = &log["runs"][1];
+        assert_eq!(run["tool"]["driver"]["name"], "ruff-check");
+        let location = &run["results"][0]["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "app.py");
+        assert_eq!(location["region"]["startLine"], 1);
+        assert!(log["runs"][0]["results"][0].get("locations").is_none());
+        Ok(())
+    }
+}
@@ -1,23 +1,38 @@
 //! Session is the context and a sequence of model interaction steps.
-use std::path::PathBuf;
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    checkpoint::{self, Checkpoint},
+    checks::{Baseline, FailingTests},
     config, context,
+    dialect::DialectProvider,
     error::{Result, TenxError},
+    events::SessionStats,
     model::Usage,
+    postprocess,
+    risk::Risk,
+    session_store,
     strategy::{self, ActionStrategy, StrategyStep},
 };
-use state::{self, Patch};
+use state::{self, Change, Patch};
 use unirend::Detail;
 
 /// A parsed model response
-#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq, JsonSchema)]
 pub struct ModelResponse {
     /// Model's comment - the user-visible part of the response
     pub comment: Option<String>,
 
+    /// A structured summary of the change, in the style of a conventional commit
+    #[serde(default)]
+    pub summary: Option<Summary>,
+
     /// The unified patch in the response
     pub patch: Option<Patch>,
 
@@ -31,74 +46,311 @@ pub struct ModelResponse {
     pub raw_response: Option<String>,
 }
 
-/// Operations requested by the model, other than patching.
-#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
-pub enum Operation {}
+/// A structured summary of a change, modelled on conventional commits.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq, JsonSchema)]
+pub struct Summary {
+    /// The type of change, e.g. "feat", "fix" or "refactor"
+    pub kind: String,
+    /// The part of the codebase affected by the change, if any
+    pub scope: Option<String>,
+    /// A one-line description of the change
+    pub description: String,
+    /// A longer explanation of the change, if needed
+    pub body: Option<String>,
+}
 
-/// A single step in the session - single prompt and model response. Steps also store
-/// processed information from the active strategy in `strategy_step`.
-#[derive(Debug, Deserialize, Serialize, Clone)]
-pub struct Step {
+impl Summary {
+    /// The summary line, formatted as `type(scope): description`.
+    pub fn subject(&self) -> String {
+        match &self.scope {
+            Some(scope) => format!("{}({}): {}", self.kind, scope, self.description),
+            None => format!("{}: {}", self.kind, self.description),
+        }
+    }
+
+    /// A complete commit message for the change, with the body separated from the subject line
+    /// by a blank line.
+    pub fn commit_message(&self) -> String {
+        match &self.body {
+            Some(body) => format!("{}\n\n{}", self.subject(), body),
+            None => self.subject(),
+        }
+    }
+}
+
+/// Operations requested by the model, other than patching. These let the model steer the
+/// session, rather than only emitting changes.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, JsonSchema)]
+pub enum Operation {
+    /// The model considers the task finished.
+    Done,
+    /// The model asks for a context item, as a context specification like "ruskel:serde" or
+    /// "src/lib.rs", to be added before it continues.
+    RequestContext(String),
+    /// The model needs an answer from the user before it can continue.
+    AskUser(String),
+    /// The model gives up on the task, with a reason.
+    Abort(String),
+    /// The model asks to see an elided region of an outlined editable file, as a 1-based
+    /// inclusive range of lines.
+    Expand {
+        path: PathBuf,
+        start: usize,
+        end: usize,
+    },
+    /// The model asks for a search over the project's files, and sees the matches before it
+    /// continues. The pattern is a regular expression, or literal text if `literal` is set.
+    Search { pattern: String, literal: bool },
+}
+
+impl ModelResponse {
+    /// Returns the question for the user, if the model asked one.
+    pub fn question(&self) -> Option<&str> {
+        self.operations.iter().find_map(|op| match op {
+            Operation::AskUser(q) => Some(q.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Returns true if the model says the task is finished.
+    pub fn is_done(&self) -> bool {
+        self.operations.contains(&Operation::Done)
+    }
+
+    /// Returns true if the model asked to see context, expanded regions or search results before
+    /// it continues. A model that says it's done is finished, so any such requests are ignored.
+    pub fn wants_more(&self) -> bool {
+        !self.is_done()
+            && (!self.context_requests().is_empty()
+                || !self.expansions().is_empty()
+                || !self.searches().is_empty())
+    }
+
+    /// Returns the reason the model gave for aborting, if it aborted.
+    pub fn abort_reason(&self) -> Option<&str> {
+        self.operations.iter().find_map(|op| match op {
+            Operation::Abort(r) => Some(r.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Returns the regions of outlined files the model asked to expand, as paths with 1-based
+    /// inclusive line ranges.
+    pub fn expansions(&self) -> Vec<(&Path, usize, usize)> {
+        self.operations
+            .iter()
+            .filter_map(|op| match op {
+                Operation::Expand { path, start, end } => Some((path.as_path(), *start, *end)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the searches the model asked for, as patterns with a flag that's set for literal
+    /// text.
+    pub fn searches(&self) -> Vec<(&str, bool)> {
+        self.operations
+            .iter()
+            .filter_map(|op| match op {
+                Operation::Search { pattern, literal } => Some((pattern.as_str(), *literal)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the context specifications the model requested.
+    pub fn context_requests(&self) -> Vec<&str> {
+        self.operations
+            .iter()
+            .filter_map(|op| match op {
+                Operation::RequestContext(spec) => Some(spec.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// A previous attempt at a step, recorded when the step is reset for a retry.
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct Attempt {
+    /// The name of the model used for the attempt
+    pub model: String,
+    /// The raw prompt provided to the model
+    pub raw_prompt: String,
+    /// Time in seconds to receive the complete model response
+    pub response_time: Option<f64>,
+    /// The response from the model, if one was received
+    pub model_response: Option<ModelResponse>,
+    /// The error the attempt ended with, if any
+    pub err: Option<TenxError>,
+}
+
+/// What was sent to the model in a step.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, JsonSchema)]
+pub struct StepRequest {
     /// The name of the model used for this step
     pub model: String,
 
+    /// The alias the model was selected by, if any
+    #[serde(default)]
+    pub model_alias: Option<String>,
+
     /// The raw prompt provided to the model
     pub raw_prompt: String,
+}
+
+/// What came back from the model in a step.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, JsonSchema)]
+pub struct StepResponse {
+    /// The response from the model
+    pub model_response: Option<ModelResponse>,
 
     /// Time in seconds to receive the complete model response
-    pub response_time: Option<f64>,
+    pub time: Option<f64>,
+}
 
+/// The result of processing a step, and how to undo it.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, JsonSchema)]
+pub struct StepOutcome {
     /// An associated error, for instance an error processing a model response. This may be
     /// retryable, in which case a new step will be synthesized to go back to the model.
     pub err: Option<TenxError>,
 
-    /// The response from the model
-    pub model_response: Option<ModelResponse>,
-
     /// Information about the patch applied in this step, including any failures.
     pub patch_info: Option<state::PatchInfo>,
 
     /// The rollback identifier for this step. Rolling back to this identifier will revert all
     /// changes.
     pub rollback_id: u64,
+
+    /// The risky operations found in the step's patch before it was applied.
+    #[serde(default)]
+    pub risks: Vec<Risk>,
+}
+
+/// A single step in the session - single prompt and model response. Steps also store
+/// processed information from the active strategy in `strategy_step`.
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct Step {
+    pub request: StepRequest,
+    #[serde(default)]
+    pub response: StepResponse,
+    #[serde(default)]
+    pub outcome: StepOutcome,
     pub strategy_step: StrategyStep,
+
+    /// Previous attempts at this step, oldest first. An attempt is recorded each time the step
+    /// is reset after receiving a response or an error.
+    #[serde(default)]
+    pub attempts: Vec<Attempt>,
 }
 
 impl Step {
     /// Creates a new Step with the given prompt and rollback ID.
     pub fn new(model: String, raw_prompt: String, strategy_step: StrategyStep) -> Self {
         Step {
-            model,
-            raw_prompt,
-            rollback_id: 0,
-            model_response: None,
-            response_time: None,
-            patch_info: None,
-            err: None,
+            request: StepRequest {
+                model,
+                model_alias: None,
+                raw_prompt,
+            },
+            response: StepResponse::default(),
+            outcome: StepOutcome::default(),
             strategy_step,
+            attempts: vec![],
         }
     }
 
+    /// Creates a new Step using the active model from the config, recording the alias it was
+    /// selected by, if any.
+    pub fn from_config(
+        config: &config::Config,
+        raw_prompt: String,
+        strategy_step: StrategyStep,
+    ) -> Self {
+        let mut step = Step::new(config.model_name(), raw_prompt, strategy_step);
+        step.request.model_alias = config.model_alias();
+        step
+    }
+
     /// Reset the step, clearing all response data and setting the rollback ID. The rollback ID is
-    /// required because presumably the state has been rolled back before this call.
+    /// required because presumably the state has been rolled back before this call. If the step
+    /// was complete, it's recorded in the step's attempts first.
     pub fn reset(&mut self, rollback_id: u64) {
-        self.model_response = None;
-        self.response_time = None;
-        self.patch_info = None;
-        self.err = None;
-        self.rollback_id = rollback_id;
+        let response = std::mem::take(&mut self.response);
+        let outcome = std::mem::replace(
+            &mut self.outcome,
+            StepOutcome {
+                rollback_id,
+                ..Default::default()
+            },
+        );
+        if response.model_response.is_some() || outcome.err.is_some() {
+            self.attempts.push(Attempt {
+                model: self.request.model.clone(),
+                raw_prompt: self.request.raw_prompt.clone(),
+                response_time: response.time,
+                model_response: response.model_response,
+                err: outcome.err,
+            });
+        }
+    }
+
+    /// A one-word status for the step: "pending" if the model hasn't responded yet, "error" if
+    /// the step failed, and "ok" otherwise.
+    pub fn status(&self) -> &'static str {
+        if self.outcome.err.is_some() {
+            "error"
+        } else if self.response.model_response.is_none() {
+            "pending"
+        } else {
+            "ok"
+        }
+    }
+
+    /// The files the step's patch changes, sorted, without files the model only viewed.
+    pub fn files(&self) -> Vec<PathBuf> {
+        let Some(patch) = self
+            .response
+            .model_response
+            .as_ref()
+            .and_then(|r| r.patch.as_ref())
+        else {
+            return vec![];
+        };
+        patch
+            .changes
+            .iter()
+            .filter(|c| !matches!(c, Change::View(_) | Change::ViewRange(..)))
+            .flat_map(|c| c.paths())
+            .cloned()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
     }
 
     /// Is this step incomplete?
     pub fn is_incomplete(&self) -> bool {
-        self.model_response.is_none() && self.err.is_none()
+        self.response.model_response.is_none() && self.outcome.err.is_none()
     }
 
     /// Returns true if a step should continue, based on:
     /// a) there is a patch error, or
-    /// b) there is a step error, and the error's should_retry() is not None.
+    /// b) there is a step error, and the error's should_retry() is not None, or
+    /// c) the model requested context, expanded regions or searches, which it needs to see
+    ///    before continuing, and didn't say it's done.
     pub fn should_continue(&self) -> bool {
         if self
+            .response
+            .model_response
+            .as_ref()
+            .is_some_and(|r| r.wants_more())
+        {
+            return true;
+        }
+
+        if self
+            .outcome
             .patch_info
             .as_ref()
             .is_some_and(|p| !p.failures.is_empty())
@@ -106,7 +358,7 @@ impl Step {
             return true;
         }
 
-        if let Some(err) = &self.err {
+        if let Some(err) = &self.outcome.err {
             if err.should_retry().is_some() {
                 return true;
             }
@@ -117,12 +369,20 @@ impl Step {
 }
 
 /// A user-requested action, which may contain many steps.
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct Action {
     pub strategy: strategy::Strategy,
     pub state: state::State,
     /// The steps in the action
     pub steps: Vec<Step>,
+    /// Check failures from before the action made any changes. Post-change failures identical to
+    /// these are pre-existing, and aren't reported to the model.
+    #[serde(default)]
+    pub baseline: Option<Baseline>,
+    /// Notes about the action that aren't part of the conversation with the model, like the
+    /// summary of a run that ran out of time.
+    #[serde(default)]
+    pub notes: Vec<String>,
 }
 
 impl Action {
@@ -132,31 +392,113 @@ impl Action {
             strategy,
             steps: Vec::new(),
             state: config.state()?,
+            baseline: None,
+            notes: vec![],
         })
     }
 
+    /// Summarizes the steps from `first_step` onward: the number of steps, token usage, the
+    /// files changed and check failures. Cost and duration aren't known to the action, and are
+    /// left for the caller to fill in.
+    pub fn stats(&self, first_step: usize) -> SessionStats {
+        let mut stats = SessionStats::default();
+        let mut files = BTreeSet::new();
+        for step in self.steps.iter().skip(first_step) {
+            stats.steps += 1;
+            if let Some(resp) = &step.response.model_response {
+                if let Some((input, output)) = resp.usage.as_ref().map(|u| u.totals()) {
+                    stats.input_tokens += input;
+                    stats.output_tokens += output;
+                }
+                if let Some(patch) = &resp.patch {
+                    files.extend(
+                        patch
+                            .changes
+                            .iter()
+                            .filter(|c| !matches!(c, Change::View(_) | Change::ViewRange(..)))
+                            .flat_map(|c| c.paths())
+                            .cloned(),
+                    );
+                }
+            }
+            if let Some(TenxError::Check { name, .. }) = &step.outcome.err {
+                if !stats.failed_checks.contains(name) {
+                    stats.failed_checks.push(name.clone());
+                }
+            }
+        }
+        stats.files = files.into_iter().collect();
+        if let Some(TenxError::Check { name, .. }) =
+            self.last_step().and_then(|s| s.outcome.err.as_ref())
+        {
+            stats.failing_check = Some(name.clone());
+        }
+        stats
+    }
+
     /// Returns a reference to the last step in the action
     pub fn last_step(&self) -> Option<&Step> {
         self.steps.last()
     }
 
+    /// Returns the most recent change summary provided by the model in this action.
+    pub fn summary(&self) -> Option<&Summary> {
+        self.steps.iter().rev().find_map(|s| {
+            s.response
+                .model_response
+                .as_ref()
+                .and_then(|r| r.summary.as_ref())
+        })
+    }
+
     /// Adds a new step to the action.
     ///
     /// Returns an error if the last step doesn't have either a model response or an error.
     pub fn add_step(&mut self, mut step: Step) -> Result<()> {
         if let Some(last_step) = self.steps.last() {
-            if last_step.model_response.is_none() && last_step.err.is_none() {
+            if last_step.response.model_response.is_none() && last_step.outcome.err.is_none() {
                 return Err(TenxError::Internal(
                     "Cannot add a new prompt while the previous step has no response".into(),
                 ));
             }
         }
         let rollback_id = self.state.mark()?;
-        step.rollback_id = rollback_id;
+        step.outcome.rollback_id = rollback_id;
         self.steps.push(step);
         Ok(())
     }
 
+    /// Renders a unified diff of the changes made to files since a step started. Files are
+    /// listed in path order, and an empty string means nothing changed.
+    pub fn step_diff(&self, step_offset: usize) -> Result<String> {
+        let step = self
+            .steps
+            .get(step_offset)
+            .ok_or_else(|| TenxError::Internal(format!("No such step: {}", step_offset)))?;
+        let mut out = String::new();
+        for path in self.state.changed()? {
+            let Some(before) = self.state.content_before(&path, step.outcome.rollback_id) else {
+                continue;
+            };
+            let after = self.state.read(&path).ok();
+            if before == after {
+                continue;
+            }
+            let diff = diffy::create_patch(
+                before.as_deref().unwrap_or_default(),
+                after.as_deref().unwrap_or_default(),
+            )
+            .to_string();
+            let path = path.to_string_lossy();
+            out.push_str(&format!("--- a/{}\n+++ b/{}\n", path, path));
+            for line in diff.lines().skip(2) {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+        Ok(out)
+    }
+
     /// Render the action using the provided renderer
     pub fn render<R: unirend::Render>(
         &self,
@@ -191,38 +533,119 @@ impl Action {
                 detail,
             )?;
         }
+
+        if !self.notes.is_empty() {
+            renderer.push("notes");
+            for note in &self.notes {
+                renderer.para(note);
+            }
+            renderer.pop();
+        }
         renderer.pop();
         Ok(())
     }
 }
 
+/// The dialect system prompt a session was created with. Later steps use this copy, so upgrading
+/// tenx mid-session doesn't change the instructions the model is working under.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, JsonSchema)]
+pub struct SystemPrompt {
+    /// The name of the dialect the prompt belongs to
+    pub dialect: String,
+    /// The version of tenx that created the session
+    pub version: String,
+    /// A hash of the prompt text, for comparing prompts at a glance
+    pub hash: String,
+    pub text: String,
+}
+
+impl SystemPrompt {
+    /// Captures the system prompt of the configured dialect.
+    pub fn new(config: &config::Config) -> Result<Self> {
+        let dialect = config.dialect()?;
+        let text = dialect.system();
+        Ok(Self {
+            dialect: dialect.name().to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            hash: format!("{:016x}", state::files::hash_content(text.as_bytes())),
+            text,
+        })
+    }
+}
+
 /// A serializable session, which persists between invocations.
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct Session {
     pub actions: Vec<Action>,
     pub contexts: context::ContextManager,
+    /// Checks marked as known failing, which are skipped for the rest of the session.
+    #[serde(default)]
+    pub known_failing: BTreeSet<String>,
+    /// The tests that failed on the last run of each check, which are run on their own until
+    /// they pass.
+    #[serde(default)]
+    pub failing_tests: FailingTests,
+    /// The estimated total spend on model requests in this session, in USD.
+    #[serde(default)]
+    pub spend: f64,
+    /// Content hashes of the project's files as of the last completed step.
+    #[serde(default)]
+    pub file_hashes: state::files::HashIndex,
+    /// The system prompt pinned when the session was created. Sessions from older versions of
+    /// tenx don't have one, and use the current dialect's prompt.
+    #[serde(default)]
+    pub system_prompt: Option<SystemPrompt>,
+    /// The git checkpoint of the files the session has changed, if any.
+    #[serde(default)]
+    pub checkpoint: Option<Checkpoint>,
 }
 
 impl Session {
+    /// Returns a JSON Schema describing stored sessions.
+    pub fn json_schema() -> Result<String> {
+        serde_json::to_string_pretty(&session_store::session_schema())
+            .map_err(|e| TenxError::Internal(format!("Failed to serialize schema: {}", e)))
+    }
+
     /// Creates a new Session, configuring its state directory.
     ///
     /// If `dir` is provided, it is used as the project root; otherwise the configuration's
     /// project root is used.
-    pub fn new(_config: &config::Config) -> Result<Self> {
+    pub fn new(config: &config::Config) -> Result<Self> {
         Ok(Session {
             actions: vec![],
             contexts: context::ContextManager::new(),
+            known_failing: BTreeSet::new(),
+            failing_tests: FailingTests::new(),
+            spend: 0.0,
+            file_hashes: Default::default(),
+            system_prompt: Some(SystemPrompt::new(config)?),
+            checkpoint: None,
         })
     }
 
+    /// Returns the system prompt to use with a dialect: the pinned prompt if it was captured from
+    /// the same dialect, or the dialect's current prompt otherwise.
+    pub fn system_prompt_for<D: DialectProvider>(&self, dialect: &D) -> String {
+        match &self.system_prompt {
+            Some(pinned) if pinned.dialect == dialect.name() => pinned.text.clone(),
+            _ => dialect.system(),
+        }
+    }
+
     /// Clears all contexts from the session.
     pub fn clear_ctx(&mut self) {
         self.contexts.clear();
     }
 
     /// Clears all actions in the session, but keeps the current editable and context intact.
-    pub fn clear(&mut self) {
+    /// The session's checkpoint is dropped, along with the git ref that kept it.
+    pub fn clear(&mut self, config: &config::Config) -> Result<()> {
         self.actions.clear();
+        if let Some(cp) = self.checkpoint.take() {
+            checkpoint::drop_ref(&config.project_root(), &cp)?;
+        }
+        Ok(())
     }
 
     /// Returns a reference to the last action in the session.
@@ -249,7 +672,7 @@ impl Session {
     /// Does this session have a pending prompt?
     pub fn should_continue(&self) -> bool {
         if let Some(step) = self.last_step() {
-            step.model_response.is_none() && step.err.is_none()
+            step.response.model_response.is_none() && step.outcome.err.is_none()
         } else {
             false
         }
@@ -263,8 +686,18 @@ impl Session {
             .ok_or_else(|| TenxError::Internal("No actions in session".into()))
     }
 
-    /// Adds a new action to the session.
-    pub fn add_action(&mut self, action: Action) -> Result<()> {
+    /// Adds an action to the session. Files created by patches in earlier actions are carried
+    /// over as editables, so that follow-up actions can see and change them.
+    pub fn add_action(&mut self, mut action: Action) -> Result<()> {
+        let mut created = BTreeSet::new();
+        for prev in &self.actions {
+            created.extend(prev.state.created());
+        }
+        if !created.is_empty() {
+            action.state.patch(&Patch {
+                changes: created.into_iter().map(state::Change::View).collect(),
+            })?;
+        }
         self.actions.push(action);
         Ok(())
     }
@@ -309,7 +742,7 @@ impl Session {
             if next_step_idx < action.steps.len() {
                 action
                     .state
-                    .revert(action.steps[next_step_idx].rollback_id)?;
+                    .revert(action.steps[next_step_idx].outcome.rollback_id)?;
             }
 
             // Truncate steps in the current action
@@ -322,6 +755,62 @@ impl Session {
         Ok(())
     }
 
+    /// Removes a failed step, rolling back any changes it made. Only the last step of the session
+    /// can be dropped, so earlier steps can't be pulled out from under the ones that built on them.
+    pub fn drop_step(&mut self, action_idx: usize, step_idx: usize) -> Result<()> {
+        let is_last = action_idx + 1 == self.actions.len()
+            && step_idx + 1 == self.actions[action_idx].steps.len();
+        if !is_last {
+            return Err(TenxError::Internal(format!(
+                "Step {}:{} is not the last step in the session",
+                action_idx, step_idx
+            )));
+        }
+        let action = &mut self.actions[action_idx];
+        if action.steps[step_idx].outcome.err.is_none() {
+            return Err(TenxError::Internal(format!(
+                "Step {}:{} didn't fail, use reset to undo it",
+                action_idx, step_idx
+            )));
+        }
+        action
+            .state
+            .revert_from(action.steps[step_idx].outcome.rollback_id)?;
+        action.steps.pop();
+        Ok(())
+    }
+
+    /// Restores a single file to its content at the start of a step, undoing the changes made to
+    /// it by that step and everything after, while leaving other files alone. The restore is
+    /// recorded in the last action's state. Returns false if the file hasn't changed since the
+    /// start of the step.
+    pub fn revert_file(&mut self, path: &Path, action_idx: usize, step_idx: usize) -> Result<bool> {
+        let rollback_id = self
+            .actions
+            .get(action_idx)
+            .and_then(|a| a.steps.get(step_idx))
+            .map(|s| s.outcome.rollback_id)
+            .ok_or_else(|| {
+                TenxError::Internal(format!("Invalid step {}:{}", action_idx, step_idx))
+            })?;
+        // Later actions have their own state, so the first change to the file may be in any of
+        // them.
+        let content = self.actions[action_idx..]
+            .iter()
+            .enumerate()
+            .find_map(|(i, a)| {
+                let id = if i == 0 { rollback_id } else { 0 };
+                a.state.content_before(path, id)
+            });
+        let Some(content) = content else {
+            return Ok(false);
+        };
+        self.last_action_mut()?
+            .state
+            .restore(path, content.as_deref())?;
+        Ok(true)
+    }
+
     /// Reset the session to a specific action and step and prepare it for retry.
     /// This method first resets the session to the specified step, then clears the step's
     /// response data, and reverts to the step's rollback_id to reset the state.
@@ -357,23 +846,188 @@ impl Session {
 
     /// Apply the last step in the session, applying the patch and operations. The step must
     /// already have a model response.
-    pub fn apply_last_step(&mut self, _config: &config::Config) -> Result<()> {
+    pub fn apply_last_step(&mut self, config: &config::Config) -> Result<()> {
+        self.apply_last_step_with(config, |_| {})
+    }
+
+    /// Apply the last step like `apply_last_step`, calling `written` with each file as the patch
+    /// writes or removes it.
+    pub fn apply_last_step_with<W: FnMut(&Path)>(
+        &mut self,
+        config: &config::Config,
+        written: W,
+    ) -> Result<()> {
         let resp = self
             .last_step()
             .ok_or_else(|| TenxError::Internal("No steps in session".into()))?
+            .response
             .model_response
             .clone()
             .ok_or_else(|| TenxError::Internal("No response in the last step".into()))?;
         if let Some(patch) = &resp.patch {
-            let patch_info = self.actions.last_mut().unwrap().state.patch(patch)?;
+            let refused = self.outlined_writes(config, patch);
+            let patch = Patch {
+                changes: patch
+                    .changes
+                    .iter()
+                    .filter(|c| !refused.contains(c))
+                    .cloned()
+                    .collect(),
+            };
+            let mut patch_info = self.actions.last_mut().unwrap().state.patch_observed(
+                &patch,
+                |path, content, created| {
+                    postprocess::process(&config.post_process, path, content, created)
+                },
+                written,
+            )?;
+            for change in refused {
+                let Change::Write(write) = &change else {
+                    continue;
+                };
+                let msg = format!(
+                    "Not writing {}, which was sent as an outline",
+                    write.path.display()
+                );
+                patch_info.add_failure(
+                    change.clone(),
+                    state::Error::Patch {
+                        user: msg.clone(),
+                        model: format!(
+                            "{}. Writing the whole file would delete its elided lines. Edit it \
+                             with replacements, or expand the elided regions first.",
+                            msg
+                        ),
+                    },
+                )?;
+            }
             let step = self
                 .last_step_mut()
                 .ok_or_else(|| TenxError::Internal("No steps in session".into()))?;
-            step.patch_info = Some(patch_info);
+            step.outcome.patch_info = Some(patch_info);
         }
         Ok(())
     }
 
+    /// Returns the whole-file writes in the last step's patch to files the model was shown as an
+    /// outline. Applying them would delete the elided lines the model never saw.
+    fn outlined_writes(&self, config: &config::Config, patch: &Patch) -> Vec<Change> {
+        let (Some(action), Some(action_offset)) =
+            (self.actions.last(), self.actions.len().checked_sub(1))
+        else {
+            return vec![];
+        };
+        let step_offset = action.steps.len().saturating_sub(1);
+        patch
+            .changes
+            .iter()
+            .filter(|change| match change {
+                Change::Write(write) => action.state.read(&write.path).is_ok_and(|contents| {
+                    crate::dialect::outlined(
+                        config,
+                        self,
+                        action_offset,
+                        step_offset,
+                        &write.path,
+                        &contents,
+                    )
+                    .is_some()
+                }),
+                _ => false,
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// The files the content hash index covers: the editables shown to the model in any step of
+    /// the last action. In-memory files aren't on disk, so they're left out.
+    fn hashed_files(&self) -> Result<Vec<PathBuf>> {
+        let Some(action) = self.actions.last() else {
+            return Ok(vec![]);
+        };
+        let action_offset = self.actions.len() - 1;
+        let mut files = BTreeSet::new();
+        for step_offset in 0..action.steps.len() {
+            files.extend(
+                self.editables_for_step_state(action_offset, step_offset)?
+                    .into_iter()
+                    .filter(|p| !p.to_string_lossy().starts_with(state::MEM_PREFIX)),
+            );
+        }
+        Ok(files.into_iter().collect())
+    }
+
+    /// Updates the content hash index over the last action's editables, returning the files that
+    /// changed since the last update. Files that are no longer editable drop out of the index.
+    pub fn update_file_hashes(&mut self, config: &config::Config) -> Result<Vec<PathBuf>> {
+        let files = self.hashed_files()?;
+        self.file_hashes.retain(&files);
+        Ok(self.file_hashes.update(config.project_root(), &files)?)
+    }
+
+    /// Renders a unified diff of all the changes made to files in the session, in a format that
+    /// `git apply` accepts. Files are listed in path order, and an empty string means nothing
+    /// changed.
+    pub fn diff(&self) -> Result<String> {
+        let Some(last) = self.actions.last() else {
+            return Ok(String::new());
+        };
+        let mut paths = BTreeSet::new();
+        for action in &self.actions {
+            paths.extend(action.state.changed()?);
+        }
+        let mut out = String::new();
+        for path in paths {
+            if path.to_string_lossy().starts_with(state::MEM_PREFIX) {
+                continue;
+            }
+            // Later actions have their own state, so the file's original content is in the first
+            // action that changed it.
+            let Some(before) = self
+                .actions
+                .iter()
+                .find_map(|a| a.state.content_before(&path, 0))
+            else {
+                continue;
+            };
+            let after = last.state.read(&path).ok();
+            if before == after {
+                continue;
+            }
+            let diff = diffy::create_patch(
+                before.as_deref().unwrap_or_default(),
+                after.as_deref().unwrap_or_default(),
+            )
+            .to_string();
+            let path = path.to_string_lossy();
+            let (old, new) = (format!("a/{}", path), format!("b/{}", path));
+            out.push_str(&format!("diff --git {} {}\n", old, new));
+            if before.is_none() {
+                out.push_str("new file mode 100644\n");
+            }
+            if after.is_none() {
+                out.push_str("deleted file mode 100644\n");
+            }
+            out.push_str(&format!(
+                "--- {}\n+++ {}\n",
+                if before.is_some() { &old } else { "/dev/null" },
+                if after.is_some() { &new } else { "/dev/null" },
+            ));
+            for line in diff.lines().skip(2) {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+        Ok(out)
+    }
+
+    /// Returns the editables that changed since the content hash index was last updated, without
+    /// updating it.
+    pub fn changed_files(&self, config: &config::Config) -> Result<Vec<PathBuf>> {
+        let files = self.hashed_files()?;
+        Ok(self.file_hashes.changed(config.project_root(), &files)?)
+    }
+
     /// Get editables for a specific action and step in the session
     pub fn editables_for_step_state(
         &self,
@@ -397,15 +1051,12 @@ impl Session {
             )));
         }
 
-        let curr_rollback_id = Some(action.steps[step_idx].rollback_id);
+        let curr_rollback_id = Some(action.steps[step_idx].outcome.rollback_id);
 
-        // Get the previous rollback id (if this isn't the first step)
+        // Get the previous rollback id (if this isn't the first step). Each action has its own
+        // state, so the first step of an action sees everything touched before it.
         let prev_rollback_id = if step_idx > 0 {
-            Some(action.steps[step_idx - 1].rollback_id)
-        } else if action_idx > 0 {
-            // Get the last step of the previous action
-            let prev_action = &self.actions[action_idx - 1];
-            prev_action.steps.last().map(|s| s.rollback_id)
+            Some(action.steps[step_idx - 1].outcome.rollback_id)
         } else {
             None
         };
@@ -443,6 +1094,36 @@ mod tests {
     use crate::strategy::Strategy;
     use crate::testutils;
 
+    #[test]
+    fn test_json_schema() -> Result<()> {
+        let schema: serde_json::Value = serde_json::from_str(&Session::json_schema()?).unwrap();
+        assert_eq!(schema["title"], "Session");
+        assert!(schema["properties"]["actions"].is_object());
+        assert!(schema["definitions"]["Step"].is_object());
+        Ok(())
+    }
+
+    #[test]
+    fn test_step_format() {
+        let mut step = Step::new(
+            "model".into(),
+            "prompt".into(),
+            StrategyStep::Code(strategy::CodeStep::default()),
+        );
+        step.response.model_response = Some(ModelResponse {
+            comment: Some("done".into()),
+            ..Default::default()
+        });
+        step.response.time = Some(1.5);
+        step.outcome.err = Some(TenxError::Model("failed".into()));
+        step.outcome.rollback_id = 3;
+
+        // Steps round-trip in the current format
+        let current = serde_json::to_value(&step).unwrap();
+        let loaded: Step = serde_json::from_value(current.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), current);
+    }
+
     #[test]
     fn test_add_context_ignores_duplicates() -> Result<()> {
         let mut test_project = crate::testutils::test_project();
@@ -468,6 +1149,189 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_created_files_stay_editable() -> Result<()> {
+        let mut tp = testutils::test_project();
+        tp.write("a.txt", "A0");
+        let new_step = || {
+            let mut step = Step::new(
+                "model".into(),
+                "prompt".into(),
+                strategy::StrategyStep::Code(strategy::CodeStep::default()),
+            );
+            step.response.model_response = Some(ModelResponse::default());
+            step
+        };
+        let mut action = Action::new(&tp.config, Strategy::Code(strategy::Code::new()))?;
+        action.add_step(new_step())?;
+        action
+            .state
+            .patch(&Patch::default().with_write("new.txt", "N"))?;
+        action.add_step(new_step())?;
+        tp.session.add_action(action)?;
+        assert_eq!(
+            tp.session.editables_for_step_state(0, 1)?,
+            vec![PathBuf::from("new.txt")]
+        );
+
+        // A follow-up action starts with the created file as an editable
+        let action = Action::new(&tp.config, Strategy::Code(strategy::Code::new()))?;
+        tp.session.add_action(action)?;
+        tp.session.last_action_mut()?.add_step(new_step())?;
+        assert_eq!(
+            tp.session.editables_for_step_state(1, 0)?,
+            vec![PathBuf::from("new.txt")]
+        );
+        assert_eq!(
+            tp.session.actions[1].state.changed()?,
+            vec![PathBuf::from("new.txt")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_revert_file() -> Result<()> {
+        let mut tp = testutils::test_project();
+        tp.write("a.txt", "A0");
+        tp.write("b.txt", "B0");
+        let mut action = Action::new(&tp.config, Strategy::Code(strategy::Code::new()))?;
+        let patches = [
+            Patch::default()
+                .with_write("a.txt", "A1")
+                .with_write("b.txt", "B1"),
+            Patch::default().with_write("a.txt", "A2"),
+        ];
+        for patch in patches {
+            let mut step = Step::new(
+                "model".into(),
+                "prompt".into(),
+                strategy::StrategyStep::Code(strategy::CodeStep::default()),
+            );
+            step.response.model_response = Some(ModelResponse::default());
+            action.add_step(step)?;
+            action.state.patch(&patch)?;
+        }
+        tp.session.add_action(action)?;
+        let read = |p: &str| fs_err::read_to_string(tp.tempdir.path().join(p)).unwrap();
+
+        assert!(tp.session.revert_file(Path::new("a.txt"), 0, 1)?);
+        assert_eq!(read("a.txt"), "A1");
+        assert!(!tp.session.revert_file(Path::new("b.txt"), 0, 1)?);
+        assert!(tp.session.revert_file(Path::new("a.txt"), 0, 0)?);
+        assert_eq!(read("a.txt"), "A0");
+        assert_eq!(read("b.txt"), "B1");
+        assert!(tp.session.revert_file(Path::new("a.txt"), 0, 5).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_drop_step() -> Result<()> {
+        let mut tp = testutils::test_project();
+        tp.write("a.txt", "A0");
+        let mut action = Action::new(&tp.config, Strategy::Code(strategy::Code::new()))?;
+        for content in ["A1", "A2"] {
+            let mut step = Step::new(
+                "model".into(),
+                "prompt".into(),
+                strategy::StrategyStep::Code(strategy::CodeStep::default()),
+            );
+            let patch = Patch::default().with_write("a.txt", content);
+            step.response.model_response = Some(ModelResponse {
+                patch: Some(patch.clone()),
+                ..Default::default()
+            });
+            action.add_step(step)?;
+            action.state.patch(&patch)?;
+        }
+        tp.session.add_action(action)?;
+        let read = |p: &str| fs_err::read_to_string(tp.tempdir.path().join(p)).unwrap();
+
+        let step = &tp.session.actions[0].steps[1];
+        assert_eq!(step.files(), vec![PathBuf::from("a.txt")]);
+        assert_eq!(step.status(), "ok");
+
+        // Only a failed last step can be dropped
+        assert!(tp.session.drop_step(0, 1).is_err());
+        tp.session.actions[0].steps[0].outcome.err = Some(TenxError::Model("failed".into()));
+        assert!(tp.session.drop_step(0, 0).is_err());
+        tp.session.actions[0].steps[1].outcome.err = Some(TenxError::Model("failed".into()));
+        assert_eq!(tp.session.actions[0].steps[1].status(), "error");
+        tp.session.drop_step(0, 1)?;
+        assert_eq!(tp.session.actions[0].steps.len(), 1);
+        assert_eq!(read("a.txt"), "A1");
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_diff() -> Result<()> {
+        let mut tp = testutils::test_project();
+        tp.write("a.txt", "A0\n");
+        for patch in [
+            Patch::default()
+                .with_write("a.txt", "A1\n")
+                .with_write("new.txt", "N\n"),
+            Patch::default().with_write("a.txt", "A2\n"),
+        ] {
+            let mut action = Action::new(&tp.config, Strategy::Code(strategy::Code::new()))?;
+            action.state.patch(&patch)?;
+            tp.session.add_action(action)?;
+        }
+        assert_eq!(
+            tp.session.diff()?,
+            indoc::indoc! {"
+                diff --git a/a.txt b/a.txt
+                --- a/a.txt
+                +++ b/a.txt
+                @@ -1 +1 @@
+                -A0
+                +A2
+                diff --git a/new.txt b/new.txt
+                new file mode 100644
+                --- /dev/null
+                +++ b/new.txt
+                @@ -0,0 +1 @@
+                +N
+            "}
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_changed_files() -> Result<()> {
+        let mut p = testutils::test_project();
+        p.write("a.txt", "one");
+        p.write("b.txt", "two");
+        p.write("c.txt", "three");
+        let mut action = Action::new(&p.config, Strategy::Code(strategy::Code::new()))?;
+        action
+            .state
+            .touch(p.config.project_root(), vec!["[ab].txt".into()])?;
+        action.add_step(Step::new(
+            "model".into(),
+            "prompt".into(),
+            strategy::StrategyStep::Code(strategy::CodeStep::default()),
+        ))?;
+        p.session.add_action(action)?;
+        assert_eq!(p.session.update_file_hashes(&p.config)?.len(), 2);
+        assert!(p.session.changed_files(&p.config)?.is_empty());
+
+        // Only editables are hashed
+        p.write("c.txt", "changed");
+        assert!(p.session.changed_files(&p.config)?.is_empty());
+
+        p.write("b.txt", "changed");
+        assert_eq!(
+            p.session.changed_files(&p.config)?,
+            vec![PathBuf::from("b.txt")]
+        );
+        assert_eq!(
+            p.session.update_file_hashes(&p.config)?,
+            vec![PathBuf::from("b.txt")]
+        );
+        assert!(p.session.changed_files(&p.config)?.is_empty());
+        Ok(())
+    }
+
     #[test]
     fn test_retry_resets_step() -> Result<()> {
         let tp = testutils::test_project();
@@ -481,8 +1345,9 @@ mod tests {
             "prompt1".into(),
             strategy::StrategyStep::Code(strategy::CodeStep::default()),
         );
-        step1.model_response = Some(ModelResponse {
+        step1.response.model_response = Some(ModelResponse {
             comment: Some("first response".into()),
+            summary: None,
             patch: None,
             operations: vec![],
             usage: None,
@@ -496,8 +1361,9 @@ mod tests {
             "prompt2".into(),
             strategy::StrategyStep::Code(strategy::CodeStep::default()),
         );
-        step2.model_response = Some(ModelResponse {
+        step2.response.model_response = Some(ModelResponse {
             comment: Some("second response".into()),
+            summary: None,
             patch: None,
             operations: vec![],
             usage: None,
@@ -509,6 +1375,12 @@ mod tests {
         let mut session = Session {
             actions: vec![action],
             contexts: context::ContextManager::new(),
+            known_failing: BTreeSet::new(),
+            failing_tests: FailingTests::new(),
+            spend: 0.0,
+            file_hashes: Default::default(),
+            system_prompt: None,
+            checkpoint: None,
         };
 
         // Call retry on the second step (index 1) of the first action.
@@ -518,10 +1390,28 @@ mod tests {
         let action = session.actions.first().unwrap();
         assert_eq!(action.steps.len(), 2);
         let retried_step = action.steps.get(1).unwrap();
-        assert!(retried_step.model_response.is_none());
-        assert!(retried_step.response_time.is_none());
-        assert!(retried_step.patch_info.is_none());
-        assert!(retried_step.err.is_none());
+        assert!(retried_step.response.model_response.is_none());
+        assert!(retried_step.response.time.is_none());
+        assert!(retried_step.outcome.patch_info.is_none());
+        assert!(retried_step.outcome.err.is_none());
+
+        // The previous attempt is kept in the step's history, and retrying again adds another
+        assert_eq!(retried_step.attempts.len(), 1);
+        assert_eq!(retried_step.attempts[0].raw_prompt, "prompt2");
+        assert_eq!(
+            retried_step.attempts[0]
+                .model_response
+                .as_ref()
+                .and_then(|r| r.comment.as_deref()),
+            Some("second response")
+        );
+        session.actions[0].steps[1].outcome.err = Some(TenxError::Model("failed".into()));
+        session.retry(0, 1)?;
+        let retried_step = &session.actions[0].steps[1];
+        assert_eq!(retried_step.attempts.len(), 2);
+        assert!(retried_step.attempts[1].model_response.is_none());
+        assert!(retried_step.attempts[1].err.is_some());
+        assert!(session.actions[0].steps[0].attempts.is_empty());
 
         Ok(())
     }
@@ -0,0 +1,125 @@
+use std::{collections::BTreeMap, io::Write, path::PathBuf};
+
+use fs4::fs_std::FileExt;
+use fs_err as fs;
+
+use super::{load_session, migrate, SessionBackend, LOCK_EXTENSION};
+use crate::{
+    error::{Result, TenxError},
+    session::Session,
+};
+
+/// The name of the file recording daily spend in the store directory.
+pub(crate) const SPEND_FILE: &str = "spend.json";
+
+/// The prefix of temporary files written while saving, which aren't sessions.
+const TEMP_PREFIX: &str = ".tenx-";
+
+/// Stores each session as a JSON file in a directory.
+#[derive(Debug, Clone)]
+pub struct FileBackend {
+    base_dir: PathBuf,
+}
+
+impl FileBackend {
+    pub fn new(base_dir: PathBuf) -> Self {
+        Self { base_dir }
+    }
+
+    /// Writes a file in the store by writing a temporary file next to it and renaming it into
+    /// place, so readers never see a partly written file.
+    fn write(&self, name: &str, content: &str) -> Result<()> {
+        let err =
+            |e: std::io::Error| TenxError::SessionStore(format!("Failed to write {}: {}", name, e));
+        let mut tmp = tempfile::Builder::new()
+            .prefix(TEMP_PREFIX)
+            .suffix(".tmp")
+            .tempfile_in(&self.base_dir)
+            .map_err(err)?;
+        tmp.write_all(content.as_bytes()).map_err(err)?;
+        tmp.as_file().sync_all().map_err(err)?;
+        tmp.persist(self.base_dir.join(name))
+            .map_err(|e| err(e.error))?;
+        Ok(())
+    }
+
+    /// Takes an exclusive lock on the spend ledger, waiting for any other process that holds it.
+    /// The lock is released when the returned file is dropped.
+    fn lock_spend(&self) -> Result<std::fs::File> {
+        let path = self
+            .base_dir
+            .join(format!("{}.{}", SPEND_FILE, LOCK_EXTENSION));
+        let err = |e: std::io::Error| {
+            TenxError::SessionStore(format!("Failed to lock spend ledger: {}", e))
+        };
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .map_err(err)?;
+        file.lock_exclusive().map_err(err)?;
+        Ok(file)
+    }
+
+    /// Reads the daily spend ledger, keyed by day.
+    fn read_spend(&self) -> Result<BTreeMap<u64, f64>> {
+        let path = self.base_dir.join(SPEND_FILE);
+        if !path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| TenxError::SessionStore(format!("Failed to parse spend: {}", e)))
+    }
+}
+
+impl SessionBackend for FileBackend {
+    fn save(&self, name: &str, session: &Session) -> Result<()> {
+        self.write(name, &migrate::encode(session)?)
+    }
+
+    fn load(&self, name: &str) -> Result<Session> {
+        load_session(self.base_dir.join(name))
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        let mut sessions = Vec::new();
+        for entry in fs::read_dir(&self.base_dir)
+            .map_err(|e| TenxError::SessionStore(format!("Failed to read directory: {}", e)))?
+        {
+            let entry = entry
+                .map_err(|e| TenxError::SessionStore(format!("Failed to read entry: {}", e)))?;
+            if entry
+                .file_type()
+                .map_err(|e| TenxError::SessionStore(format!("Failed to get file type: {}", e)))?
+                .is_file()
+            {
+                if let Some(name) = entry.file_name().to_str() {
+                    if !name.ends_with(&format!(".{}", LOCK_EXTENSION))
+                        && !name.starts_with(TEMP_PREFIX)
+                        && name != super::sqlite::DB_FILE
+                        && name != SPEND_FILE
+                        && name != crate::usage::USAGE_FILE
+                    {
+                        sessions.push(name.to_string());
+                    }
+                }
+            }
+        }
+        Ok(sessions)
+    }
+
+    fn add_spend(&self, day: u64, usd: f64) -> Result<()> {
+        // Hold the lock across the read and the write, so concurrent updates aren't lost
+        let _lock = self.lock_spend()?;
+        let mut spend = self.read_spend()?;
+        *spend.entry(day).or_default() += usd;
+        let serialized = serde_json::to_string(&spend)
+            .map_err(|e| TenxError::SessionStore(format!("serialization failed: {}", e)))?;
+        self.write(SPEND_FILE, &serialized)
+    }
+
+    fn spend(&self, day: u64) -> Result<f64> {
+        Ok(self.read_spend()?.get(&day).copied().unwrap_or_default())
+    }
+}
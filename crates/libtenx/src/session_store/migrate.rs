@@ -0,0 +1,224 @@
+//! Versioning of stored sessions. Sessions are stored as JSON, with a top-level `version` field
+//! recording the format they were saved in. When the format changes, `SESSION_VERSION` is bumped
+//! and a migration is added to `MIGRATIONS` that upgrades the JSON from the previous version, so
+//! sessions saved by older releases still load. Sessions saved before the format was versioned
+//! have no version field, and are version 0.
+//!
+//! Each historical format is pinned by a fixture in `src/session_store/fixtures`, named for its
+//! version. Fixtures are never changed once added.
+use schemars::schema::{InstanceType, RootSchema, SchemaObject};
+use serde_json::{Map, Value};
+
+use crate::{
+    error::{Result, TenxError},
+    session::Session,
+};
+
+/// The version of the session format written by this release.
+pub const SESSION_VERSION: u64 = 1;
+
+/// The field recording the format version of a stored session.
+const VERSION_FIELD: &str = "version";
+
+/// A migration upgrades a stored session from one version to the next, in place.
+type Migration = fn(&mut Map<String, Value>) -> Result<()>;
+
+/// Migrations, indexed by the version they upgrade from.
+const MIGRATIONS: &[Migration] = &[split_steps];
+
+/// The fields of a version 0 step that moved into its request, response and outcome, with their
+/// new names.
+const SPLIT_FIELDS: &[(&str, &str, &str)] = &[
+    ("model", "request", "model"),
+    ("model_alias", "request", "model_alias"),
+    ("raw_prompt", "request", "raw_prompt"),
+    ("model_response", "response", "model_response"),
+    ("response_time", "response", "time"),
+    ("err", "outcome", "err"),
+    ("patch_info", "outcome", "patch_info"),
+    ("rollback_id", "outcome", "rollback_id"),
+];
+
+/// Version 0 to 1: steps saved before they were split into a request, response and outcome have
+/// flat fields, which are moved into place.
+fn split_steps(session: &mut Map<String, Value>) -> Result<()> {
+    let steps = session
+        .get_mut("actions")
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten()
+        .filter_map(|action| action.get_mut("steps").and_then(Value::as_array_mut))
+        .flatten()
+        .filter_map(Value::as_object_mut);
+    for step in steps {
+        if step.contains_key("request") {
+            continue;
+        }
+        let mut parts: Map<String, Value> = ["request", "response", "outcome"]
+            .iter()
+            .map(|p| (p.to_string(), Value::Object(Map::new())))
+            .collect();
+        for (old, part, new) in SPLIT_FIELDS {
+            if let Some(value) = step.remove(*old) {
+                parts[*part]
+                    .as_object_mut()
+                    .expect("parts are objects")
+                    .insert(new.to_string(), value);
+            }
+        }
+        // Flat fields were all optional, so fill in the ones the split format requires
+        let request = parts["request"].as_object_mut().expect("parts are objects");
+        for field in ["model", "raw_prompt"] {
+            request
+                .entry(field)
+                .or_insert_with(|| Value::String(String::new()));
+        }
+        parts["outcome"]
+            .as_object_mut()
+            .expect("parts are objects")
+            .entry("rollback_id")
+            .or_insert(Value::from(0));
+        step.extend(parts);
+    }
+    Ok(())
+}
+
+/// Upgrades stored session JSON to the current version.
+fn upgrade(value: Value) -> Result<Value> {
+    let Value::Object(mut session) = value else {
+        return Err(TenxError::SessionStore(
+            "Failed to parse session: not an object".into(),
+        ));
+    };
+    let version = match session.remove(VERSION_FIELD) {
+        None => 0,
+        Some(v) => v.as_u64().ok_or_else(|| {
+            TenxError::SessionStore(format!("Failed to parse session: invalid version {}", v))
+        })?,
+    };
+    if version > SESSION_VERSION {
+        return Err(TenxError::SessionStore(format!(
+            "Session was saved in format version {}, but this version of tenx only reads up to \
+             version {}. Upgrade tenx to load it",
+            version, SESSION_VERSION
+        )));
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(&mut session)?;
+    }
+    Ok(Value::Object(session))
+}
+
+/// Serializes a session in the current format.
+pub(crate) fn encode(session: &Session) -> Result<String> {
+    let mut value = serde_json::to_value(session)
+        .map_err(|e| TenxError::SessionStore(format!("serialization failed: {}", e)))?;
+    if let Value::Object(map) = &mut value {
+        map.insert(VERSION_FIELD.into(), Value::from(SESSION_VERSION));
+    }
+    serde_json::to_string(&value)
+        .map_err(|e| TenxError::SessionStore(format!("serialization failed: {}", e)))
+}
+
+/// Returns a JSON Schema for sessions as `encode` writes them, with the version field.
+pub(crate) fn session_schema() -> RootSchema {
+    let mut root = schemars::schema_for!(Session);
+    let version = SchemaObject {
+        instance_type: Some(InstanceType::Integer.into()),
+        const_value: Some(Value::from(SESSION_VERSION)),
+        ..Default::default()
+    };
+    let object = root.schema.object();
+    object
+        .properties
+        .insert(VERSION_FIELD.into(), version.into());
+    object.required.insert(VERSION_FIELD.into());
+    root
+}
+
+/// Deserializes a session stored in any version of the format, upgrading it as needed.
+pub(crate) fn decode(text: &str) -> Result<Session> {
+    let value = serde_json::from_str(text)
+        .map_err(|e| TenxError::SessionStore(format!("Failed to parse session: {}", e)))?;
+    serde_json::from_value(upgrade(value)?)
+        .map_err(|e| TenxError::SessionStore(format!("Failed to parse session: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Loads the fixture pinning a historical format.
+    fn fixture(name: &str) -> String {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/session_store/fixtures")
+            .join(name);
+        fs_err::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn test_session_schema() {
+        let schema = serde_json::to_value(session_schema()).unwrap();
+        assert_eq!(
+            schema["properties"][VERSION_FIELD]["const"],
+            Value::from(SESSION_VERSION)
+        );
+
+        // Every field of a stored session is described, and every required field is stored
+        let tp = crate::testutils::test_project();
+        let stored: Map<String, Value> =
+            serde_json::from_str(&encode(&tp.session).unwrap()).unwrap();
+        for key in stored.keys() {
+            assert!(schema["properties"][key].is_object(), "{}", key);
+        }
+        for key in schema["required"].as_array().unwrap() {
+            assert!(stored.contains_key(key.as_str().unwrap()), "{}", key);
+        }
+    }
+
+    #[test]
+    fn test_migrations() {
+        // There's one migration for every version before the current one
+        assert_eq!(MIGRATIONS.len() as u64, SESSION_VERSION);
+
+        for name in ["v0.json", "v0-split.json", "v1.json"] {
+            let session = decode(&fixture(name)).unwrap_or_else(|e| panic!("{}: {}", name, e));
+            let step = &session.actions[0].steps[0];
+            assert_eq!(step.request.model, "sonnet", "{}", name);
+            assert_eq!(step.request.raw_prompt, "add a test", "{}", name);
+            assert_eq!(step.response.time, Some(1.5), "{}", name);
+            assert_eq!(
+                step.response
+                    .model_response
+                    .as_ref()
+                    .and_then(|r| r.comment.as_deref()),
+                Some("done"),
+                "{}",
+                name
+            );
+            assert_eq!(step.outcome.rollback_id, 3, "{}", name);
+            assert!(
+                session.actions[0].steps[1].outcome.err.is_some(),
+                "{}",
+                name
+            );
+
+            // Sessions are saved in the current format, and load unchanged
+            let encoded = encode(&session).unwrap();
+            let value: Value = serde_json::from_str(&encoded).unwrap();
+            assert_eq!(value[VERSION_FIELD], SESSION_VERSION);
+            let reloaded = decode(&encoded).unwrap();
+            assert_eq!(
+                serde_json::to_value(&reloaded).unwrap(),
+                serde_json::to_value(&session).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_newer_version() {
+        let text = fixture("v1.json").replacen("\"version\": 1,", "\"version\": 99,", 1);
+        let err = decode(&text).unwrap_err();
+        assert!(err.to_string().contains("Upgrade tenx"));
+    }
+}
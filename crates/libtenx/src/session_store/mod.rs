@@ -0,0 +1,363 @@
+//! Session persistence module, handling storage and retrieval of sessions.
+
+mod files;
+mod migrate;
+mod sqlite;
+
+use crate::{
+    config::{Config, SessionStoreKind},
+    error::{Result, TenxError},
+    session::Session,
+};
+use enum_dispatch::enum_dispatch;
+use fs4::fs_std::FileExt;
+use fs_err as fs;
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub use files::FileBackend;
+pub(crate) use migrate::session_schema;
+pub use migrate::SESSION_VERSION;
+pub use sqlite::SqliteBackend;
+
+/// The extension used for session lock files in the store directory.
+const LOCK_EXTENSION: &str = "lock";
+
+/// Normalizes a path for use as a filename by replacing problematic characters.
+pub fn path_to_filename(path: &Path) -> String {
+    path.to_string_lossy()
+        .replace(['/', '\\'], "_")
+        .replace([':', '<', '>', '"', '|', '?', '*'], "")
+}
+
+/// Returns the current day as a count of days since the Unix epoch, in UTC. Spend is tracked per
+/// day.
+pub fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / 86400)
+        .unwrap_or_default()
+}
+
+/// Loads a session from a file located at a specific path. Sessions saved in older formats are
+/// upgraded.
+pub fn load_session<P: AsRef<Path>>(path: P) -> Result<Session> {
+    let path = path.as_ref();
+    if !path.exists() {
+        return Err(TenxError::NotFound {
+            msg: "No such session".into(),
+            path: path.display().to_string(),
+        });
+    }
+    let serialized = fs::read_to_string(path)
+        .map_err(|e| TenxError::SessionStore(format!("Failed to read session: {}", e)))?;
+    migrate::decode(&serialized)
+}
+
+/// A storage backend for sessions.
+#[enum_dispatch(Backend)]
+pub trait SessionBackend {
+    /// Saves a session with the specified name, replacing any existing session.
+    fn save(&self, name: &str, session: &Session) -> Result<()>;
+
+    /// Loads the named session.
+    fn load(&self, name: &str) -> Result<Session>;
+
+    /// Lists the names of all stored sessions.
+    fn list(&self) -> Result<Vec<String>>;
+
+    /// Adds to the total spend recorded for a day, in USD.
+    fn add_spend(&self, day: u64, usd: f64) -> Result<()>;
+
+    /// Returns the total spend recorded for a day, in USD.
+    fn spend(&self, day: u64) -> Result<f64>;
+}
+
+/// The available session storage backends.
+#[enum_dispatch]
+#[derive(Debug, Clone)]
+pub enum Backend {
+    /// A directory with one JSON file per session
+    Files(FileBackend),
+    /// A single SQLite database
+    Sqlite(SqliteBackend),
+}
+
+/// An exclusive write lock on a stored session. The lock is released when this object is
+/// dropped, or when the holding process exits.
+#[derive(Debug)]
+pub struct SessionLock {
+    _file: std::fs::File,
+}
+
+/// Manages persistent storage and retrieval of Session objects.
+///
+/// Sessions are stored in a directory, using one of the storage backends. Lock files are always
+/// kept in the directory, regardless of the backend. The store provides methods to save, load,
+/// and list available sessions.
+pub struct SessionStore {
+    base_dir: PathBuf,
+    backend: Backend,
+}
+
+impl SessionStore {
+    /// Creates a new file-backed StateStore with the specified base directory.
+    pub fn open(base_dir: PathBuf) -> Result<Self> {
+        Self::open_kind(base_dir, SessionStoreKind::Files)
+    }
+
+    /// Creates a new StateStore with the specified base directory and storage backend.
+    pub fn open_kind(base_dir: PathBuf, kind: SessionStoreKind) -> Result<Self> {
+        fs::create_dir_all(&base_dir)?;
+        let backend = match kind {
+            SessionStoreKind::Files => Backend::Files(FileBackend::new(base_dir.clone())),
+            SessionStoreKind::Sqlite => Backend::Sqlite(SqliteBackend::open(base_dir.clone())?),
+        };
+        Ok(Self { base_dir, backend })
+    }
+
+    /// Opens a file-backed store for reading only. Unlike `open`, this never touches the
+    /// filesystem, so it can be used to inspect sessions without side effects.
+    pub fn open_read_only(base_dir: PathBuf) -> Self {
+        Self::open_read_only_kind(base_dir, SessionStoreKind::Files)
+    }
+
+    /// Opens a store with the specified storage backend for reading only.
+    pub fn open_read_only_kind(base_dir: PathBuf, kind: SessionStoreKind) -> Self {
+        let backend = match kind {
+            SessionStoreKind::Files => Backend::Files(FileBackend::new(base_dir.clone())),
+            SessionStoreKind::Sqlite => {
+                Backend::Sqlite(SqliteBackend::open_read_only(base_dir.clone()))
+            }
+        };
+        Self { base_dir, backend }
+    }
+
+    /// Opens the store configured for this project.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        Self::open_kind(config.session_store_dir.clone(), config.session_store)
+    }
+
+    /// Opens the store configured for this project for reading only.
+    pub fn from_config_read_only(config: &Config) -> Self {
+        Self::open_read_only_kind(config.session_store_dir.clone(), config.session_store)
+    }
+
+    /// Acquires an exclusive write lock on the named session. Fails immediately if another
+    /// process holds the lock. Readers do not take the lock, so loading a session for inspection
+    /// is always possible.
+    pub fn lock(&self, name: &str) -> Result<SessionLock> {
+        let path = self.base_dir.join(format!("{}.{}", name, LOCK_EXTENSION));
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|e| TenxError::SessionStore(format!("Failed to open lock file: {}", e)))?;
+        match file.try_lock_exclusive() {
+            Ok(true) => Ok(SessionLock { _file: file }),
+            Ok(false) => Err(TenxError::SessionStore(
+                "Session is locked by another tenx process".into(),
+            )),
+            Err(e) => Err(TenxError::SessionStore(format!(
+                "Failed to lock session: {}",
+                e
+            ))),
+        }
+    }
+
+    /// Saves a session to the store with the specified name.
+    pub fn save(&self, name: &str, state: &Session) -> Result<()> {
+        self.backend.save(name, state)
+    }
+
+    /// Saves the given State to a the store, using the current directory identifier.
+    pub fn save_current(&self, config: &Config, state: &Session) -> Result<()> {
+        let file_name = path_to_filename(&config.project_root());
+        self.save(&file_name, state)
+    }
+
+    /// Loads a State based on the given name.
+    pub fn load<S: AsRef<str>>(&self, name: S) -> Result<Session> {
+        self.backend.load(name.as_ref())
+    }
+
+    /// Lists all sessions in the store.
+    pub fn list(&self) -> Result<Vec<String>> {
+        self.backend.list()
+    }
+
+    /// Records spend against today's total, across all sessions in the store.
+    pub fn add_spend(&self, usd: f64) -> Result<()> {
+        self.backend.add_spend(today(), usd)
+    }
+
+    /// Returns today's total spend across all sessions in the store.
+    pub fn spend_today(&self) -> Result<f64> {
+        self.backend.spend(today())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Project;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(path_to_filename(Path::new("/foo/bar")), "_foo_bar");
+        assert_eq!(
+            path_to_filename(Path::new("C:\\Windows\\System32")),
+            "C_Windows_System32"
+        );
+        assert_eq!(path_to_filename(Path::new("file:name.txt")), "filename.txt");
+    }
+
+    #[test]
+    fn test_state_store() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            project: Project {
+                root: temp_dir.path().into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let state_store = SessionStore::open(temp_dir.path().into()).unwrap();
+
+        let state = Session::new(&config)?;
+        state_store.save_current(&config, &state).unwrap();
+        state_store.save("test_session", &state).unwrap();
+
+        let name = path_to_filename(&config.project_root());
+        let _ = state_store.load(&name)?;
+
+        let sessions = state_store.list()?;
+        assert_eq!(sessions.len(), 2);
+        assert!(sessions.contains(&name));
+        assert!(sessions.contains(&"test_session".to_string()));
+
+        // Sessions are written through a temporary file that is renamed into place
+        state_store.save("test_session", &state)?;
+        assert_eq!(std::fs::read_dir(temp_dir.path())?.count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            project: Project {
+                root: temp_dir.path().into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let store = SessionStore::open(temp_dir.path().into())?;
+        store.save("test_session", &Session::new(&config)?)?;
+
+        let lock = store.lock("test_session")?;
+        assert!(store.lock("test_session").is_err());
+        assert!(store.lock("other_session").is_ok());
+
+        // Readers are not blocked by the lock, and lock files are not listed as sessions
+        let reader = SessionStore::open_read_only(temp_dir.path().into());
+        reader.load("test_session")?;
+        assert_eq!(reader.list()?, vec!["test_session".to_string()]);
+
+        drop(lock);
+        assert!(store.lock("test_session").is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqlite_store() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            project: Project {
+                root: temp_dir.path().into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        // Reading an empty store doesn't create the database
+        let reader =
+            SessionStore::open_read_only_kind(temp_dir.path().into(), SessionStoreKind::Sqlite);
+        assert!(reader.list()?.is_empty());
+        assert!(matches!(
+            reader.load("b_session"),
+            Err(TenxError::NotFound { .. })
+        ));
+        assert!(!temp_dir.path().join(sqlite::DB_FILE).exists());
+
+        let store = SessionStore::open_kind(temp_dir.path().into(), SessionStoreKind::Sqlite)?;
+        let mut session = Session::new(&config)?;
+        store.save("b_session", &session)?;
+        store.save("a_session", &session)?;
+
+        // Saving again replaces the session
+        session.add_context(crate::context::Context::new_text("note", "hello"));
+        store.save("b_session", &session)?;
+        assert_eq!(store.load("b_session")?.contexts.len(), 1);
+        assert!(matches!(
+            store.load("missing"),
+            Err(TenxError::NotFound { .. })
+        ));
+
+        let _lock = store.lock("a_session")?;
+        assert_eq!(reader.list()?, vec!["a_session", "b_session"]);
+
+        // The database isn't listed as a session by the file backend
+        let files = SessionStore::open_read_only(temp_dir.path().into());
+        assert!(files.list()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_spend() -> Result<()> {
+        for kind in [SessionStoreKind::Files, SessionStoreKind::Sqlite] {
+            let temp_dir = TempDir::new().unwrap();
+            let reader = SessionStore::open_read_only_kind(temp_dir.path().into(), kind);
+            assert_eq!(reader.spend_today()?, 0.0);
+
+            let store = SessionStore::open_kind(temp_dir.path().into(), kind)?;
+            store.add_spend(0.25)?;
+            store.add_spend(0.5)?;
+            assert_eq!(store.spend_today()?, 0.75);
+            assert_eq!(store.backend.spend(today() - 1)?, 0.0);
+
+            // The spend ledger isn't listed as a session
+            assert!(store.list()?.is_empty());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_spend_concurrent() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let dir = temp_dir.path().to_path_buf();
+                std::thread::spawn(move || {
+                    let store = SessionStore::open(dir).unwrap();
+                    for _ in 0..10 {
+                        store.add_spend(0.25).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        // No update is lost to a concurrent read-modify-write
+        let store = SessionStore::open(temp_dir.path().into())?;
+        assert_eq!(store.spend_today()?, 20.0);
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+
+use super::{migrate, SessionBackend};
+use crate::{
+    error::{Result, TenxError},
+    session::Session,
+};
+
+/// The name of the database file in the store directory.
+pub(crate) const DB_FILE: &str = "sessions.db";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS sessions (
+    name TEXT PRIMARY KEY NOT NULL,
+    data TEXT NOT NULL,
+    updated INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS sessions_updated ON sessions (updated);
+CREATE TABLE IF NOT EXISTS spend (
+    day INTEGER PRIMARY KEY NOT NULL,
+    usd REAL NOT NULL
+);
+";
+
+fn db_err(e: rusqlite::Error) -> TenxError {
+    TenxError::SessionStore(format!("Database error: {}", e))
+}
+
+/// Stores sessions in a single SQLite database in the store directory. Writes are atomic, and
+/// listing sessions doesn't need to touch every session on disk.
+#[derive(Debug, Clone)]
+pub struct SqliteBackend {
+    path: PathBuf,
+    read_only: bool,
+}
+
+impl SqliteBackend {
+    /// Opens the database in `base_dir`, creating it if needed.
+    pub fn open(base_dir: PathBuf) -> Result<Self> {
+        let backend = Self {
+            path: base_dir.join(DB_FILE),
+            read_only: false,
+        };
+        backend.connect()?.execute_batch(SCHEMA).map_err(db_err)?;
+        Ok(backend)
+    }
+
+    /// Opens the database in `base_dir` for reading only. This never creates or modifies the
+    /// database.
+    pub fn open_read_only(base_dir: PathBuf) -> Self {
+        Self {
+            path: base_dir.join(DB_FILE),
+            read_only: true,
+        }
+    }
+
+    /// Connects to the database. We connect for each operation, so the backend can be shared
+    /// freely across threads.
+    fn connect(&self) -> Result<Connection> {
+        let conn = if self.read_only {
+            Connection::open_with_flags(
+                &self.path,
+                OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )
+        } else {
+            Connection::open(&self.path)
+        };
+        conn.map_err(db_err)
+    }
+}
+
+impl SessionBackend for SqliteBackend {
+    fn save(&self, name: &str, session: &Session) -> Result<()> {
+        let serialized = migrate::encode(session)?;
+        let updated = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+        let mut conn = self.connect()?;
+        let tx = conn.transaction().map_err(db_err)?;
+        tx.execute(
+            "INSERT OR REPLACE INTO sessions (name, data, updated) VALUES (?1, ?2, ?3)",
+            params![name, serialized, updated],
+        )
+        .map_err(db_err)?;
+        tx.commit().map_err(db_err)
+    }
+
+    fn load(&self, name: &str) -> Result<Session> {
+        let no_session = || TenxError::NotFound {
+            msg: "No such session".into(),
+            path: name.to_string(),
+        };
+        if !self.path.exists() {
+            return Err(no_session());
+        }
+        let data: Option<String> = self
+            .connect()?
+            .query_row(
+                "SELECT data FROM sessions WHERE name = ?1",
+                params![name],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(db_err)?;
+        let data = data.ok_or_else(no_session)?;
+        migrate::decode(&data)
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        if !self.path.exists() {
+            return Ok(vec![]);
+        }
+        let conn = self.connect()?;
+        let mut stmt = conn
+            .prepare("SELECT name FROM sessions ORDER BY name")
+            .map_err(db_err)?;
+        let names = stmt
+            .query_map([], |row| row.get(0))
+            .map_err(db_err)?
+            .collect::<rusqlite::Result<Vec<String>>>()
+            .map_err(db_err)?;
+        Ok(names)
+    }
+
+    fn add_spend(&self, day: u64, usd: f64) -> Result<()> {
+        self.connect()?
+            .execute(
+                "INSERT INTO spend (day, usd) VALUES (?1, ?2)
+                 ON CONFLICT (day) DO UPDATE SET usd = usd + excluded.usd",
+                params![day as i64, usd],
+            )
+            .map_err(db_err)?;
+        Ok(())
+    }
+
+    fn spend(&self, day: u64) -> Result<f64> {
+        if !self.path.exists() {
+            return Ok(0.0);
+        }
+        let usd: Option<f64> = self
+            .connect()?
+            .query_row(
+                "SELECT usd FROM spend WHERE day = ?1",
+                params![day as i64],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(db_err)?;
+        Ok(usd.unwrap_or_default())
+    }
+}
@@ -0,0 +1,161 @@
+//! Snapshot tests of rendered prompts. [`prompt_fixture`] builds a session with fixed files,
+//! contexts, and history, [`render_prompt`] renders the prompt a dialect would send for it as
+//! plain text, and [`assert_snapshot`] compares the result with a snapshot file. Changes to
+//! system prompts or rendering order then show up as snapshot diffs in review.
+//!
+//! Snapshots are stored as `<name>.snap` files, and should be committed. A missing snapshot is
+//! recorded on the first run. On a mismatch the new output is written alongside as
+//! `<name>.snap.new`, and the test fails. Set `TENX_UPDATE_SNAPSHOTS=1` to accept all changes.
+//!
+//! This module is available to other crates with the `test-util` feature, so custom dialects can
+//! snapshot their own output.
+use std::path::Path;
+
+use fs_err as fs;
+use state::Patch;
+
+use crate::{
+    config::Config,
+    context::Context,
+    dialect::DialectProvider,
+    error::Result,
+    model::{Chat, TextChat},
+    session::{Action, ModelResponse, Session, Step},
+    strategy,
+    testutils::{test_project, TestProject},
+};
+
+/// Set to accept changed snapshots instead of failing.
+const UPDATE_VAR: &str = "TENX_UPDATE_SNAPSHOTS";
+
+/// Creates a project with a session whose prompt renders the same on every run. The session has
+/// two editable files, a text context, and an action with one completed step and one pending
+/// step, so snapshots cover context, editables, history and the current prompt.
+pub fn prompt_fixture() -> Result<TestProject> {
+    let mut p = test_project();
+    p.create_file_tree(&["src/lib.rs", "src/util.rs"]);
+    p.write(
+        "src/lib.rs",
+        "pub mod util;\n\npub fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n",
+    );
+    p.write(
+        "src/util.rs",
+        "pub fn double(x: i32) -> i32 {\n    x * 2\n}\n",
+    );
+    p.session.add_context(Context::new_text(
+        "conventions",
+        "Functions are documented with a one-line summary.",
+    ));
+    p.session.add_action(Action::new(
+        &p.config,
+        strategy::Strategy::Code(strategy::Code::new()),
+    )?)?;
+    let root = p.config.project_root();
+    p.session
+        .last_action_mut()?
+        .state
+        .touch(root, vec!["src/*.rs".into()])?;
+
+    let step = |prompt: &str| {
+        Step::new(
+            "fixture".into(),
+            prompt.into(),
+            strategy::StrategyStep::Code(strategy::CodeStep::default()),
+        )
+    };
+    p.session
+        .last_action_mut()?
+        .add_step(step("Add a function that triples a number"))?;
+    if let Some(step) = p.session.last_step_mut() {
+        step.response.model_response = Some(ModelResponse {
+            comment: Some("Added triple to util.rs".into()),
+            patch: Some(Patch::default().with_replace_fuzzy(
+                "src/util.rs",
+                "pub fn double",
+                "pub fn triple(x: i32) -> i32 {\n    x * 3\n}\n\npub fn double",
+            )),
+            ..Default::default()
+        });
+    }
+    p.session
+        .last_action_mut()?
+        .add_step(step("Now document both functions"))?;
+    Ok(p)
+}
+
+/// Renders the prompt a dialect would send for the last action in a session, as plain text. The
+/// project root is replaced with `<root>`, so snapshots don't depend on where the project lives.
+pub fn render_prompt<D: DialectProvider>(
+    dialect: &D,
+    config: &Config,
+    session: &Session,
+) -> Result<String> {
+    let mut chat: Box<dyn Chat> = Box::new(TextChat::default());
+    dialect.build_chat(config, session, session.actions.len() - 1, &mut chat)?;
+    let root = config.project_root().display().to_string();
+    Ok(chat.render()?.replace(&root, "<root>"))
+}
+
+/// Compares `actual` with the snapshot `name` in `dir`, panicking with a diff if they differ.
+pub fn assert_snapshot(dir: &Path, name: &str, actual: &str) {
+    let path = dir.join(format!("{}.snap", name));
+    let new_path = dir.join(format!("{}.snap.new", name));
+    let update = std::env::var_os(UPDATE_VAR).is_some();
+    let expected = if path.exists() {
+        Some(fs::read_to_string(&path).expect("failed to read snapshot"))
+    } else {
+        None
+    };
+    match expected {
+        Some(expected) if expected == actual => {
+            let _ = fs::remove_file(&new_path);
+        }
+        Some(expected) if !update => {
+            fs::write(&new_path, actual).expect("failed to write snapshot");
+            panic!(
+                "snapshot {} doesn't match, the new output is in {}. Set {}=1 to accept it.\n\n{}",
+                path.display(),
+                new_path.display(),
+                UPDATE_VAR,
+                diffy::create_patch(&expected, actual)
+            );
+        }
+        _ => {
+            fs::create_dir_all(dir).expect("failed to create snapshot directory");
+            fs::write(&path, actual).expect("failed to write snapshot");
+            let _ = fs::remove_file(&new_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Tags;
+
+    #[test]
+    fn test_tags_prompt() -> Result<()> {
+        let render = || -> Result<String> {
+            let p = prompt_fixture()?;
+            render_prompt(&Tags::default(), &p.config, &p.session)
+        };
+        let prompt = render()?;
+        // The fixture renders the same in every project directory
+        assert_eq!(prompt, render()?);
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/dialect/snapshots");
+        assert_snapshot(&dir, "tags", &prompt);
+        Ok(())
+    }
+
+    #[test]
+    fn test_assert_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.snap"), "one").unwrap();
+        assert_snapshot(dir.path(), "a", "one");
+        let changed = std::panic::catch_unwind(|| assert_snapshot(dir.path(), "a", "two"));
+        if std::env::var_os(UPDATE_VAR).is_none() {
+            assert!(changed.is_err());
+            assert!(dir.path().join("a.snap.new").exists());
+        }
+    }
+}
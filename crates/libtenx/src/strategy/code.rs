@@ -1,20 +1,26 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use tracing::debug;
 
 use crate::{
-    checks::check_paths,
-    config::Config,
-    error::Result,
-    error::TenxError,
-    events::{send_event, Event, EventSender},
+    checks::{check_paths, check_paths_triaged},
+    config::{Config, RetryPrompt},
+    context::{Context, PathType},
+    diagnostics,
+    error::{Result, TenxError},
+    events::{send_event, Event, EventSender, LogLevel, StepId},
+    scrub::Scrubber,
+    search,
     session::{Action, Step},
+    symbols,
 };
+use state::files::slash_path;
 use unirend::{Detail, Render, Style};
 
 use super::*;
 
 /// Shared step data for Code and Fix strategies.
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+#[derive(Clone, Debug, Serialize, Deserialize, Default, JsonSchema)]
 pub struct CodeStep {
     pub user_input: Option<String>,
 }
@@ -27,7 +33,7 @@ impl CodeStep {
 }
 
 /// The Code strategy allows the model to write and modify code based on a prompt.
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+#[derive(Clone, Debug, Serialize, Deserialize, Default, JsonSchema)]
 pub struct Code {}
 
 impl Code {
@@ -38,7 +44,7 @@ impl Code {
 }
 
 /// The Fix strategy is used to resolve errors in code by providing the model with error details.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Fix {
     error: String,
 }
@@ -52,34 +58,232 @@ impl Fix {
     }
 }
 
-/// Common logic for processing a step in both Code and Fix strategies.
+/// The maximum number of diff lines to include when telling the model a check failed.
+const MAX_RETRY_DIFF_LINES: usize = 200;
+
+/// Returns a compact diff of the changes made by a step, for inclusion in retry prompts, or None
+/// if the step changed nothing.
+fn retry_diff(
+    session: &Session,
+    action_offset: usize,
+    step_offset: usize,
+) -> Result<Option<String>> {
+    let diff = session.actions[action_offset].step_diff(step_offset)?;
+    if diff.is_empty() {
+        return Ok(None);
+    }
+    let lines: Vec<&str> = diff.lines().collect();
+    let mut diff = lines
+        .iter()
+        .take(MAX_RETRY_DIFF_LINES)
+        .copied()
+        .collect::<Vec<_>>()
+        .join("\n");
+    if lines.len() > MAX_RETRY_DIFF_LINES {
+        diff.push_str(&format!(
+            "\n... ({} more lines)",
+            lines.len() - MAX_RETRY_DIFF_LINES
+        ));
+    }
+    Ok(Some(diff))
+}
+
+/// Returns the source of the tests a check reported as failing, or None if there are none or
+/// they can't be found. Test names are matched on their final segment, so this works for Rust
+/// tests named like `module::tests::test_name`.
+fn failing_test_source(config: &Config, session: &Session, check: &str) -> Option<String> {
+    let names = session.failing_tests.get(check)?;
+    let mut source = vec![];
+    for name in names {
+        let item = name.rsplit("::").next().unwrap_or(name);
+        for m in symbols::find(config, item).ok()? {
+            source.push(format!(
+                "<test name=\"{}\" path=\"{}\" line=\"{}\">\n{}\n</test>",
+                name,
+                m.path.display(),
+                m.line,
+                m.text
+            ));
+        }
+    }
+    (!source.is_empty()).then(|| source.join("\n"))
+}
+
+/// Builds the message telling the model a check failed, framed as configured in
+/// `checks.retry_prompt`.
+fn check_retry_message(
+    config: &Config,
+    session: &Session,
+    action_offset: usize,
+    step_offset: usize,
+    check: &str,
+    errors: &str,
+) -> Result<String> {
+    // Show the model the source its errors point at, when the tool doesn't quote it
+    let errors = &match diagnostics::render_for(config, check, errors) {
+        Some(source) => format!(
+            "{}\n\nThis is the source at the reported locations:\n\n{}",
+            errors, source
+        ),
+        None => errors.to_string(),
+    };
+    let diff = || retry_diff(session, action_offset, step_offset);
+    let tests = || failing_test_source(config, session, check);
+    Ok(match &config.checks.retry_prompt {
+        RetryPrompt::Errors => errors.to_string(),
+        // Show the model what it changed, so it can connect a check failure to its edits
+        RetryPrompt::ErrorsDiff => match diff()? {
+            Some(diff) => format!(
+                "{}\n\nThese are the changes you made in your last response:\n\n<diff>\n{}\n</diff>",
+                errors, diff
+            ),
+            None => errors.to_string(),
+        },
+        RetryPrompt::ErrorsTests => match tests() {
+            Some(tests) => format!(
+                "{}\n\nThis is the source of the failing tests:\n\n{}",
+                errors, tests
+            ),
+            None => errors.to_string(),
+        },
+        RetryPrompt::Template(template) => template
+            .replace("{diff}", &diff()?.unwrap_or_default())
+            .replace("{tests}", &tests().unwrap_or_default())
+            .replace("{errors}", errors),
+    })
+}
+
+/// Creates a context requested by the model. Commands and URLs are refused, since they would let
+/// the model run programs or reach outside the project.
+fn requested_context(config: &Config, spec: &str) -> Result<Context> {
+    let kind = spec.split_once(':').map(|(kind, _)| kind);
+    if spec.starts_with("http://") || spec.starts_with("https://") || kind == Some("url") {
+        return Err(TenxError::Resolve("URLs can't be requested".into()));
+    }
+    if kind == Some("cmd") {
+        return Err(TenxError::Resolve("Commands can't be requested".into()));
+    }
+    let context = Context::from_spec(config, spec)?;
+    // Requested paths come from the model, so they have to stay inside the project
+    let path = match &context {
+        Context::Path(p) => match &p.path_type {
+            PathType::SinglePath(path) | PathType::Pattern(path) => Some(path.as_str()),
+        },
+        Context::Snippet(s) => Some(s.path.as_str()),
+        _ => None,
+    };
+    if let Some(path) = path {
+        config.confine(std::path::Path::new(path))?;
+    }
+    Ok(context)
+}
+
+/// Lines of surrounding context shown with each match of a search the model asked for.
+const SEARCH_CONTEXT_LINES: usize = 2;
+
+/// The most lines of results sent to the model for a single search.
+const SEARCH_MAX_LINES: usize = 200;
+
+/// Runs a search the model asked for over the project's files, and renders the results for the
+/// model. Files are scrubbed of secrets before they're searched. Failures are reported to the
+/// model rather than failing the step, so it can correct the pattern.
+fn search_results(config: &Config, pattern: &str, literal: bool) -> String {
+    let re = if literal {
+        regex::escape(pattern)
+    } else {
+        pattern.to_string()
+    };
+    let matches = Scrubber::from_config(&config.scrub)
+        .and_then(|scrubber| search::search(config, &re, SEARCH_CONTEXT_LINES, scrubber.as_ref()));
+    let matches = match matches {
+        Ok(matches) => matches,
+        Err(e) => return format!("Search for {} failed: {}", pattern, e),
+    };
+    if matches.is_empty() {
+        return format!("No matches for search: {}", pattern);
+    }
+    let count: usize = matches.iter().map(|m| m.match_count()).sum();
+    let rendered = search::render(&matches);
+    let lines: Vec<&str> = rendered.lines().collect();
+    let mut out = format!(
+        "Search results for {} ({} matches in {} files):\n{}",
+        pattern,
+        count,
+        matches.len(),
+        lines[..lines.len().min(SEARCH_MAX_LINES)].join("\n")
+    );
+    if lines.len() > SEARCH_MAX_LINES {
+        out.push_str(&format!(
+            "\n... {} more lines not shown. Narrow the search to see them.",
+            lines.len() - SEARCH_MAX_LINES
+        ));
+    }
+    out
+}
+
+/// Common logic for processing a step in all strategies.
 ///
 /// This function:
 /// 1. Checks for errors and patch failures in the current step
-/// 2. Creates a new step with appropriate messages if needed
+/// 2. Creates a new step with appropriate messages if needed, using `next_step` as its strategy
+///    step
 /// 3. Returns the current state of the action
-fn process_step(
+///
+/// If the model asked the user a question, `user_input` is the answer. Without one, the action
+/// waits for user input.
+pub(super) fn process_step(
     config: &Config,
     session: &mut Session,
-    _action_offset: usize,
+    action_offset: usize,
     step: &Step,
     events: Option<EventSender>,
+    next_step: StrategyStep,
+    user_input: Option<String>,
 ) -> Result<ActionState> {
-    let model = config.models.default.clone();
+    let response = step.response.model_response.clone().unwrap_or_default();
+    if response.abort_reason().is_some() {
+        debug!("Action aborted by the model");
+        return Ok(ActionState {
+            completion: Completion::Complete,
+            input_required: InputRequired::No,
+        });
+    }
+    if response.question().is_some() && user_input.is_none() {
+        return Ok(ActionState {
+            completion: Completion::Incomplete,
+            input_required: InputRequired::Yes,
+        });
+    }
+
+    let step_id = StepId::new(
+        config,
+        action_offset,
+        session.actions[action_offset].steps.len().saturating_sub(1),
+    );
     // If any messages are pushed onto here for the model, the step is incomplete.
     let mut messages = Vec::new();
     let mut user_message = Vec::new();
 
     // Check for retryable errors
-    if let Some(err) = &step.err {
+    if let Some(err) = &step.outcome.err {
         if let Some(err_message) = err.should_retry() {
-            messages.push(err_message.to_string());
+            messages.push(match err {
+                TenxError::Check { name, .. } => check_retry_message(
+                    config,
+                    session,
+                    action_offset,
+                    step_id.step,
+                    name,
+                    &err_message,
+                )?,
+                _ => err_message.to_string(),
+            });
             user_message.push(format!("{}", err));
         }
     }
 
     // Check for patch application failures
-    if let Some(patch_info) = &step.patch_info {
+    if let Some(patch_info) = &step.outcome.patch_info {
         if !patch_info.failures.is_empty() {
             let failure_messages = patch_info
                 .failures
@@ -94,25 +298,55 @@ fn process_step(
             ));
             user_message.push("patch failures".into());
         }
-        if patch_info.should_continue {
+        // A model that says it's done doesn't need to see the files it asked for
+        if patch_info.should_continue && !response.is_done() {
             messages.push("Operations applied".to_string());
             user_message.push("operations applied".into());
         }
     }
 
-    // Check for operations in model response that need further action
-    if let Some(model_response) = &step.model_response {
-        if !model_response.operations.is_empty() {
-            let model_message = "Operations applied".to_string();
-            messages.push(model_message.clone());
-            send_event(
-                &events,
-                Event::NextStep {
-                    user: "operations applied".into(),
-                    model: model_message.clone(),
-                },
-            )?;
+    // Add the context the model asked for, and tell it about any we couldn't add. A model that
+    // says it's done gets nothing more, matching `Step::should_continue`.
+    let wants_more = response.wants_more();
+    let requests = response.context_requests();
+    if wants_more && !requests.is_empty() {
+        let mut notes = vec![];
+        for spec in requests {
+            match requested_context(config, spec) {
+                Ok(context) => {
+                    session.add_context(context);
+                    notes.push(format!("Added context: {}", spec));
+                }
+                Err(e) => notes.push(format!("Could not add context {}: {}", spec, e)),
+            }
         }
+        messages.push(notes.join("\n"));
+        user_message.push("context requested".into());
+    }
+
+    let expansions = response.expansions();
+    if wants_more && !expansions.is_empty() {
+        let regions = expansions
+            .iter()
+            .map(|(path, start, end)| format!("{}:{}-{}", slash_path(path), start, end))
+            .collect::<Vec<_>>();
+        messages.push(format!("Expanded: {}", regions.join(", ")));
+        user_message.push("regions expanded".into());
+    }
+
+    let searches = response.searches();
+    if wants_more && !searches.is_empty() {
+        let results = searches
+            .into_iter()
+            .map(|(pattern, literal)| search_results(config, pattern, literal))
+            .collect::<Vec<_>>();
+        messages.push(results.join("\n\n"));
+        user_message.push("project searched".into());
+    }
+
+    if let Some(answer) = user_input.filter(|_| response.question().is_some()) {
+        messages.push(answer);
+        user_message.push("question answered".into());
     }
 
     if !messages.is_empty() {
@@ -123,13 +357,10 @@ fn process_step(
             Event::NextStep {
                 user,
                 model: model_message.clone(),
+                step: step_id,
             },
         )?;
-        let new_step = Step::new(
-            model,
-            model_message,
-            StrategyStep::Code(CodeStep::default()),
-        );
+        let new_step = Step::from_config(config, model_message, next_step);
         session.last_action_mut()?.add_step(new_step)?;
 
         debug!("Action incomplete: creating next step");
@@ -146,8 +377,43 @@ fn process_step(
     }
 }
 
+/// Runs checks on the files changed by an action. Failures identical to those in the action's
+/// baseline are pre-existing, so they are reported to the user but not to the model. If configured,
+/// such checks are marked as known failing and skipped for the rest of the session.
+pub(super) fn check_action(
+    config: &Config,
+    session: &mut Session,
+    action_offset: usize,
+    events: &Option<EventSender>,
+) -> Result<()> {
+    let action = &session.actions[action_offset];
+    let paths = action.state.changed()?;
+    let baseline = action.baseline.clone().unwrap_or_default();
+    let ignored = check_paths_triaged(
+        config,
+        &paths,
+        &baseline,
+        &session.known_failing,
+        &mut session.failing_tests,
+        events,
+    )?;
+    for name in ignored {
+        send_event(
+            events,
+            Event::Log(
+                LogLevel::Warn,
+                format!("check {} failed before changes were made, ignoring", name),
+            ),
+        )?;
+        if config.checks.mark_known_failing {
+            session.known_failing.insert(name);
+        }
+    }
+    Ok(())
+}
+
 /// Determines the current state of an action
-fn get_action_state(action: &Action) -> ActionState {
+pub(super) fn get_action_state(action: &Action) -> ActionState {
     if action.steps.is_empty() {
         return ActionState {
             completion: Completion::Incomplete,
@@ -156,11 +422,23 @@ fn get_action_state(action: &Action) -> ActionState {
     }
 
     if let Some(step) = action.last_step() {
+        let response = step.response.model_response.as_ref();
+        if response.is_some_and(|r| r.abort_reason().is_some()) {
+            return ActionState {
+                completion: Completion::Complete,
+                input_required: InputRequired::No,
+            };
+        }
         if step.is_incomplete() || step.should_continue() {
             return ActionState {
                 completion: Completion::Incomplete,
                 input_required: InputRequired::No,
             };
+        } else if response.is_some_and(|r| r.question().is_some()) {
+            return ActionState {
+                completion: Completion::Incomplete,
+                input_required: InputRequired::Yes,
+            };
         } else {
             return ActionState {
                 completion: Completion::Complete,
@@ -176,8 +454,8 @@ fn get_action_state(action: &Action) -> ActionState {
     }
 }
 
-/// Renders a step with common rendering logic for both Code and Fix strategies
-fn render_step<R: Render>(
+/// Renders a step with common rendering logic for all strategies
+pub(super) fn render_step<R: Render>(
     step: &Step,
     renderer: &mut R,
     step_header: &str,
@@ -185,17 +463,20 @@ fn render_step<R: Render>(
     detail: Detail,
 ) -> Result<()> {
     renderer.push(step_header);
-    #[allow(unreachable_patterns)]
-    let astep = match &step.strategy_step {
-        StrategyStep::Code(astep) => astep,
-        _ => return Err(TenxError::Internal("Invalid strategy step".into())),
-    };
+
+    if detail >= Detail::Detailed {
+        let model = match &step.request.model_alias {
+            Some(alias) => format!("model: {} (alias {})", step.request.model, alias),
+            None => format!("model: {}", step.request.model),
+        };
+        renderer.para(&model);
+    }
 
     if detail == Detail::Full {
         renderer.push("raw prompt");
-        renderer.para(&step.raw_prompt);
+        renderer.para(&step.request.raw_prompt);
         renderer.pop();
-        if let Some(model_response) = &step.model_response {
+        if let Some(model_response) = &step.response.model_response {
             if let Some(raw_response) = &model_response.raw_response {
                 renderer.push("raw response");
                 renderer.para(raw_response);
@@ -203,12 +484,12 @@ fn render_step<R: Render>(
             }
         }
     } else {
-        if let Some(user_input) = &astep.user_input {
+        if let Some(user_input) = step.strategy_step.user_input() {
             renderer.push("prompt");
             renderer.para(user_input);
             renderer.pop();
         }
-        if let Some(model_response) = &step.model_response {
+        if let Some(model_response) = &step.response.model_response {
             if let Some(comment) = &model_response.comment {
                 renderer.push("model comment");
                 renderer.para(comment);
@@ -217,7 +498,55 @@ fn render_step<R: Render>(
         }
     }
 
-    if let Some(model_response) = &step.model_response {
+    if let Some(summary) = step
+        .response
+        .model_response
+        .as_ref()
+        .and_then(|r| r.summary.as_ref())
+    {
+        renderer.push("summary");
+        if detail >= Detail::Detailed {
+            renderer.para(&summary.commit_message());
+        } else {
+            renderer.para(&summary.subject());
+        }
+        renderer.pop();
+    }
+
+    if let Some(model_response) = &step.response.model_response {
+        let requests = model_response.context_requests();
+        if !requests.is_empty() {
+            renderer.push("requested context");
+            renderer.bullets(requests.into_iter().map(String::from).collect());
+            renderer.pop();
+        }
+        let expansions = model_response.expansions();
+        if !expansions.is_empty() {
+            renderer.push("expanded");
+            renderer.bullets(
+                expansions
+                    .into_iter()
+                    .map(|(path, start, end)| format!("{}:{}-{}", path.display(), start, end))
+                    .collect(),
+            );
+            renderer.pop();
+        }
+        let searches = model_response.searches();
+        if !searches.is_empty() {
+            renderer.push("searched");
+            renderer.bullets(searches.into_iter().map(|(p, _)| p.to_string()).collect());
+            renderer.pop();
+        }
+        if let Some(question) = model_response.question() {
+            renderer.push("question");
+            renderer.para(question);
+            renderer.pop();
+        }
+        if let Some(reason) = model_response.abort_reason() {
+            renderer.push_style("aborted", Style::Warn);
+            renderer.para(reason);
+            renderer.pop();
+        }
         if let Some(patch) = &model_response.patch {
             renderer.push("patch");
             patch.render(renderer, detail)?;
@@ -226,7 +555,7 @@ fn render_step<R: Render>(
     }
 
     // Add error if present
-    if let Some(err) = &step.err {
+    if let Some(err) = &step.outcome.err {
         if err.should_retry().is_some() {
             renderer.push_style("retryable error", Style::Warn);
         } else {
@@ -236,8 +565,38 @@ fn render_step<R: Render>(
         renderer.pop();
     }
 
+    if !step.attempts.is_empty() {
+        if detail == Detail::Full {
+            renderer.push("previous attempts");
+            for (i, attempt) in step.attempts.iter().enumerate() {
+                renderer.push(&format!("attempt {}: {}", i + 1, attempt.model));
+                renderer.push("raw prompt");
+                renderer.para(&attempt.raw_prompt);
+                renderer.pop();
+                if let Some(raw_response) = attempt
+                    .model_response
+                    .as_ref()
+                    .and_then(|r| r.raw_response.as_ref())
+                {
+                    renderer.push("raw response");
+                    renderer.para(raw_response);
+                    renderer.pop();
+                }
+                if let Some(err) = &attempt.err {
+                    renderer.push_style("error", Style::Warn);
+                    renderer.para(&err.to_string());
+                    renderer.pop();
+                }
+                renderer.pop();
+            }
+            renderer.pop();
+        } else if detail >= Detail::Detailed {
+            renderer.para(&format!("previous attempts: {}", step.attempts.len()));
+        }
+    }
+
     // Add patch information if present
-    if let Some(patch_info) = &step.patch_info {
+    if let Some(patch_info) = &step.outcome.patch_info {
         if !patch_info.failures.is_empty() {
             let failure_messages: Vec<String> = patch_info
                 .failures
@@ -272,8 +631,7 @@ impl ActionStrategy for Code {
         action_offset: usize,
         events: Option<EventSender>,
     ) -> Result<()> {
-        let paths = session.actions[action_offset].state.changed()?;
-        check_paths(config, &paths, &events)
+        check_action(config, session, action_offset, &events)
     }
 
     fn next_step(
@@ -297,13 +655,20 @@ impl ActionStrategy for Code {
 
             // Clone to avoid borrow issues when calling process_step
             let step_clone = step.clone();
-            process_step(config, session, action_offset, &step_clone, events)
+            process_step(
+                config,
+                session,
+                action_offset,
+                &step_clone,
+                events,
+                StrategyStep::Code(CodeStep::default()),
+                prompt,
+            )
         } else if let Some(p) = prompt {
             // First step in the action
-            let model = config.models.default.clone();
             let raw_prompt = p.clone();
-            let new_step = Step::new(
-                model,
+            let new_step = Step::from_config(
+                config,
                 raw_prompt,
                 StrategyStep::Code(CodeStep::new(Some(p))),
             );
@@ -384,17 +749,28 @@ impl ActionStrategy for Fix {
 
             // Clone to avoid borrow issues when calling process_step
             let step_clone = step.clone();
-            process_step(config, session, action_offset, &step_clone, events)
+            process_step(
+                config,
+                session,
+                action_offset,
+                &step_clone,
+                events,
+                StrategyStep::Code(CodeStep::default()),
+                prompt,
+            )
         } else {
             // First step in the action
-            let model = config.models.default.clone();
             let preamble = match prompt {
                 Some(ref s) => format!("{}\n", s),
                 None => "".to_string(),
             };
             let raw_prompt =
                 format! {"{}Please fix the following errors: {}\n", preamble, self.error};
-            let new_step = Step::new(model, raw_prompt, StrategyStep::Code(CodeStep::new(prompt)));
+            let new_step = Step::from_config(
+                config,
+                raw_prompt,
+                StrategyStep::Code(CodeStep::new(prompt)),
+            );
             session.last_action_mut()?.add_step(new_step)?;
 
             Ok(ActionState {
@@ -444,7 +820,13 @@ impl ActionStrategy for Fix {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{error::TenxError, strategy::Strategy, testutils::test_project};
+    use crate::{
+        context::ContextProvider,
+        error::TenxError,
+        session::{ModelResponse, Operation},
+        strategy::Strategy,
+        testutils::test_project,
+    };
 
     #[test]
     fn test_code_next_step() -> Result<()> {
@@ -472,7 +854,10 @@ mod test {
             Some("Test".into()),
         )?;
         assert_eq!(state.input_required, InputRequired::No);
-        assert_eq!(session_clone.last_step().unwrap().raw_prompt, "Test");
+        assert_eq!(
+            session_clone.last_step().unwrap().request.raw_prompt,
+            "Test"
+        );
 
         // Test retry with patch error
         session.last_action_mut()?.add_step(Step::new(
@@ -484,15 +869,16 @@ mod test {
             user: "Error".into(),
             model: "Retry".into(),
         };
-        session.last_step_mut().unwrap().err = Some(patch_err);
+        session.last_step_mut().unwrap().outcome.err = Some(patch_err);
 
         let state = code.next_step(&test_project.config, &mut session, action_idx, None, None)?;
         assert_eq!(state.completion, Completion::Incomplete);
-        assert_eq!(session.last_step().unwrap().raw_prompt, "Retry");
+        assert_eq!(session.last_step().unwrap().request.raw_prompt, "Retry");
 
         // Non-retryable error should complete the action
         let mut session_clone = session.clone();
-        session_clone.last_step_mut().unwrap().err = Some(TenxError::Config("Error".into()));
+        session_clone.last_step_mut().unwrap().outcome.err =
+            Some(TenxError::Config("Error".into()));
 
         let state = code.next_step(
             &test_project.config,
@@ -507,6 +893,174 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_operations() -> Result<()> {
+        let p = test_project();
+        let config = &p.config;
+        let code = Code::new();
+        let mut session = Session::new(config)?;
+        session.add_action(Action::new(config, Strategy::Code(code.clone()))?)?;
+        let respond = |session: &mut Session, operations: Vec<Operation>| -> Result<()> {
+            session.last_action_mut()?.add_step(Step::new(
+                config.models.default.clone(),
+                "Test".into(),
+                StrategyStep::Code(CodeStep::default()),
+            ))?;
+            session.last_step_mut().unwrap().response.model_response = Some(ModelResponse {
+                operations,
+                ..Default::default()
+            });
+            Ok(())
+        };
+
+        // A question waits for the user, and the answer becomes the next prompt
+        respond(&mut session, vec![Operation::AskUser("Which one?".into())])?;
+        let state = code.state(config, &session, 0);
+        assert_eq!(state.input_required, InputRequired::Yes);
+        let state = code.next_step(config, &mut session, 0, None, None)?;
+        assert_eq!(state.input_required, InputRequired::Yes);
+        let state = code.next_step(config, &mut session, 0, None, Some("The first".into()))?;
+        assert_eq!(state.completion, Completion::Incomplete);
+        assert_eq!(session.last_step().unwrap().request.raw_prompt, "The first");
+
+        // Requested context is added, except for commands and paths outside the project
+        session.actions[0].steps.pop();
+        respond(
+            &mut session,
+            vec![
+                Operation::RequestContext("ruskel:serde".into()),
+                Operation::RequestContext("cmd:rm -rf /".into()),
+                Operation::RequestContext("/etc/passwd".into()),
+                Operation::RequestContext("lines:../secret.txt:1-2".into()),
+            ],
+        )?;
+        assert_eq!(
+            code.state(config, &session, 0).completion,
+            Completion::Incomplete
+        );
+        code.next_step(config, &mut session, 0, None, None)?;
+        assert!(session
+            .contexts
+            .list()
+            .iter()
+            .any(|c| c.id() == Context::new_ruskel("serde").id()));
+        assert!(!session
+            .contexts
+            .list()
+            .iter()
+            .any(|c| matches!(c, Context::Cmd(_))));
+        let prompt = &session.last_step().unwrap().request.raw_prompt;
+        assert!(prompt.contains("Added context: ruskel:serde"));
+        assert!(prompt.contains("Could not add context cmd:rm -rf /"));
+        assert!(prompt.contains("Could not add context /etc/passwd"));
+        assert!(prompt.contains("Could not add context lines:../secret.txt:1-2"));
+        assert!(!session
+            .contexts
+            .list()
+            .iter()
+            .any(|c| matches!(c, Context::Path(_) | Context::Snippet(_))));
+
+        // Searches are run, and the results sent back to the model
+        p.write("lib.rs", "fn parse_patch() {}\n");
+        session.actions[0].steps.pop();
+        respond(
+            &mut session,
+            vec![
+                Operation::Search {
+                    pattern: "parse_patch(".into(),
+                    literal: true,
+                },
+                Operation::Search {
+                    pattern: "nothing_here".into(),
+                    literal: false,
+                },
+                Operation::Search {
+                    pattern: "(".into(),
+                    literal: false,
+                },
+            ],
+        )?;
+        assert_eq!(
+            code.state(config, &session, 0).completion,
+            Completion::Incomplete
+        );
+        code.next_step(config, &mut session, 0, None, None)?;
+        let prompt = &session.last_step().unwrap().request.raw_prompt;
+        assert!(prompt.contains("lib.rs:1:fn parse_patch() {}"));
+        assert!(prompt.contains("No matches for search: nothing_here"));
+        assert!(prompt.contains("Search for ( failed"));
+
+        // Aborting and finishing both complete the action
+        for op in [Operation::Abort("Impossible".into()), Operation::Done] {
+            session.actions[0].steps.pop();
+            respond(&mut session, vec![op])?;
+            assert_eq!(
+                code.state(config, &session, 0).completion,
+                Completion::Complete
+            );
+        }
+
+        // A model that says it's done gets nothing more, whether or not it asked for more
+        session.actions[0].steps.pop();
+        respond(
+            &mut session,
+            vec![
+                Operation::Search {
+                    pattern: "parse".into(),
+                    literal: true,
+                },
+                Operation::Done,
+            ],
+        )?;
+        assert_eq!(
+            code.state(config, &session, 0).completion,
+            Completion::Complete
+        );
+        let state = code.next_step(config, &mut session, 0, None, None)?;
+        assert_eq!(state.completion, Completion::Complete);
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_retry_message() -> Result<()> {
+        let mut test_project = test_project();
+        test_project.create_file_tree(&["src/lib.rs"]);
+        test_project.write(
+            "src/lib.rs",
+            "#[cfg(test)]\nmod tests {\n    #[test]\n    fn test_add() {\n        assert_eq!(1 + 1, 3);\n    }\n}\n",
+        );
+        let mut session = Session::new(&test_project.config)?;
+        session.add_action(Action::new(
+            &test_project.config,
+            Strategy::Code(Code::new()),
+        )?)?;
+        session.last_action_mut()?.add_step(Step::new(
+            test_project.config.models.default.clone(),
+            "Test".into(),
+            StrategyStep::Code(CodeStep::default()),
+        ))?;
+        session
+            .failing_tests
+            .insert("cargo-test".into(), vec!["tests::test_add".into()]);
+        let mut message = |retry_prompt: RetryPrompt| {
+            test_project.config.checks.retry_prompt = retry_prompt;
+            check_retry_message(&test_project.config, &session, 0, 0, "cargo-test", "failed")
+        };
+
+        // Nothing changed, so there's no diff to show
+        assert_eq!(message(RetryPrompt::ErrorsDiff)?, "failed");
+        assert_eq!(message(RetryPrompt::Errors)?, "failed");
+        let tests = message(RetryPrompt::ErrorsTests)?;
+        assert!(tests.starts_with("failed\n\nThis is the source of the failing tests"));
+        assert!(tests.contains("<test name=\"tests::test_add\" path=\"src/lib.rs\" line=\"3\">"));
+        assert!(tests.contains("assert_eq!(1 + 1, 3);"));
+        assert_eq!(
+            message(RetryPrompt::Template("Fix: {errors}|{diff}".into()))?,
+            "Fix: failed|"
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_fix_next_step() -> Result<()> {
         let test_project = test_project();
@@ -532,18 +1086,19 @@ mod test {
         assert!(session
             .last_step()
             .unwrap()
+            .request
             .raw_prompt
             .starts_with("Fix prompt"));
 
         // Test retryable error
-        session.last_step_mut().unwrap().err = Some(TenxError::Patch {
+        session.last_step_mut().unwrap().outcome.err = Some(TenxError::Patch {
             user: "Error".into(),
             model: "Retry".into(),
         });
 
         let state = fix.next_step(&test_project.config, &mut session, action_idx, None, None)?;
         assert_eq!(state.completion, Completion::Incomplete);
-        assert_eq!(session.last_step().unwrap().raw_prompt, "Retry");
+        assert_eq!(session.last_step().unwrap().request.raw_prompt, "Retry");
 
         // Test default prompt in a new action
         let fix2 = Fix::new("error");
@@ -559,6 +1114,7 @@ mod test {
         assert!(session2
             .last_step()
             .unwrap()
+            .request
             .raw_prompt
             .starts_with("Please fix the following errors"),);
 
@@ -1,25 +1,31 @@
 //! Strategies that drive Actions
+use std::collections::BTreeMap;
+
 use async_trait::async_trait;
 use enum_dispatch::enum_dispatch;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::{
     config::Config,
     dialect::DialectProvider,
     error::{Result, TenxError},
-    events::EventSender,
+    events::{send_event, Event, EventSender},
     model::ModelProvider,
+    scrub::ScrubbedChat,
     session::ModelResponse,
     session::Session,
 };
 use unirend::Detail;
 
 mod code;
+mod test_first;
 
 pub use code::*;
+pub use test_first::*;
 
 /// Is the current action complete?
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
 pub enum Completion {
     /// The action is complete.
     Complete,
@@ -38,7 +44,7 @@ impl Completion {
 }
 
 /// Is user input required to create the next step?
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
 pub enum InputRequired {
     /// User input is mandatory to generate the next step.
     Yes,
@@ -51,7 +57,7 @@ pub enum InputRequired {
 }
 
 /// The state of the current action.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct ActionState {
     /// Is the action complete?
     pub completion: Completion,
@@ -128,25 +134,81 @@ pub trait ActionStrategy {
         sender: Option<EventSender>,
     ) -> Result<ModelResponse> {
         let model = config.active_model()?;
-        let mut chat = model
+        let chat = model
             .chat()
             .ok_or(TenxError::Internal("Chat not supported".into()))?;
+        let (mut chat, redactions) = ScrubbedChat::wrap(chat, &config.scrub)?;
         let dialect = config.dialect()?;
-        dialect.build_chat(config, session, action_offset, &mut chat)?;
+        let cuts = dialect.build_chat(config, session, action_offset, &mut chat)?;
+        if let Some(redactions) = redactions {
+            let redactions = redactions.lock().expect("redaction lock poisoned").clone();
+            let mut by_source: BTreeMap<String, Vec<(String, usize)>> = BTreeMap::new();
+            for r in redactions {
+                by_source
+                    .entry(r.source)
+                    .or_default()
+                    .push((r.kind, r.line));
+            }
+            for (source, secrets) in by_source {
+                send_event(&sender, Event::Redacted { source, secrets })?;
+            }
+        }
+        chat.set_sampling(&config.sampling(self.name()))?;
+        for cut in cuts {
+            send_event(
+                &sender,
+                Event::ContextCut {
+                    source: cut.source,
+                    tokens: cut.tokens,
+                    kept: cut.kept,
+                },
+            )?;
+        }
         chat.send(sender).await
     }
 }
 
 /// A strategy for performing an Action.
 #[enum_dispatch]
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub enum Strategy {
     Code(Code),
     Fix(Fix),
+    TestFirst(TestFirst),
 }
 
 /// Strategy-specific state for a step.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub enum StrategyStep {
     Code(CodeStep),
+    TestFirst(TestFirstStep),
+}
+
+impl StrategyStep {
+    /// The user input that started this step, if any.
+    pub fn user_input(&self) -> Option<&String> {
+        match self {
+            StrategyStep::Code(s) => s.user_input.as_ref(),
+            StrategyStep::TestFirst(s) => s.user_input.as_ref(),
+        }
+    }
+
+    /// Replaces the user input that started this step, returning the raw prompt the strategy
+    /// builds from it.
+    pub fn set_user_input(&mut self, input: String) -> String {
+        match self {
+            StrategyStep::Code(s) => {
+                s.user_input = Some(input.clone());
+                input
+            }
+            StrategyStep::TestFirst(s) => {
+                let prompt = match s.phase {
+                    TestPhase::WriteTest => write_test_prompt(&input),
+                    TestPhase::MakePass => input.clone(),
+                };
+                s.user_input = Some(input);
+                prompt
+            }
+        }
+    }
 }
@@ -0,0 +1,461 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{
+    config::Config,
+    error::{Result, TenxError},
+    events::EventSender,
+    session::{Action, Step},
+};
+use state::{Change, Patch};
+use unirend::Detail;
+
+use super::*;
+
+const WRITE_TEST_PROMPT: &str = "Before fixing the bug described above, write a test that \
+reproduces it. The test must fail against the current code because of the bug. Do not modify any \
+non-test code yet.";
+
+const NO_TEST_CHECK: &str = "Test-first fixes need an enabled test check with a focus \
+configuration, so a failing test can be told apart from other check failures. Add a `focus` to \
+the check that runs your tests.";
+
+const MAKE_PASS_PROMPT: &str = "The test you wrote now fails as expected. Fix the bug so that the \
+test passes. Do not modify the test.";
+
+const TEST_PASSES_MESSAGE: &str = "The test you wrote passes against the current code, so it does \
+not reproduce the bug. Please revise the test so that it fails because of the bug.";
+
+/// Returns the prompt for the first step of a test-first fix, asking for a failing test.
+pub(super) fn write_test_prompt(prompt: &str) -> String {
+    format!("{}\n\n{}", prompt, WRITE_TEST_PROMPT)
+}
+
+/// The phases of a test-first fix.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Default, JsonSchema)]
+pub enum TestPhase {
+    /// The model writes a test that reproduces the bug, which we verify fails.
+    #[default]
+    WriteTest,
+    /// The model fixes the bug, so that the failing test passes.
+    MakePass,
+}
+
+/// Step data for the TestFirst strategy.
+#[derive(Clone, Debug, Serialize, Deserialize, Default, JsonSchema)]
+pub struct TestFirstStep {
+    pub user_input: Option<String>,
+    pub phase: TestPhase,
+    /// For MakePass steps, the offset of the WriteTest step that produced the failing test.
+    pub test_step: Option<usize>,
+    /// For WriteTest steps, the check output showing that the test fails.
+    pub failure: Option<String>,
+}
+
+impl TestFirstStep {
+    /// Returns a step for continuing in the same phase, for instance to retry after an error.
+    fn retry(&self) -> Self {
+        Self {
+            phase: self.phase.clone(),
+            test_step: self.test_step,
+            ..Default::default()
+        }
+    }
+}
+
+/// The TestFirst strategy fixes a bug in two linked phases. First, the model writes a test that
+/// reproduces the bug, and we verify that the test fails. Then the model fixes the bug, with the
+/// test file added as editable, until the test passes.
+///
+/// Test checks are told apart from other checks by their `focus` configuration, so the strategy
+/// needs at least one enabled check with a focus. Without one, no failure could confirm the new
+/// test, and the action fails before the model is prompted.
+#[derive(Clone, Debug, Serialize, Deserialize, Default, JsonSchema)]
+pub struct TestFirst {}
+
+impl TestFirst {
+    /// Creates a new TestFirst strategy instance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Returns the TestFirst data for a step.
+fn tf_step(step: &Step) -> Result<&TestFirstStep> {
+    match &step.strategy_step {
+        StrategyStep::TestFirst(s) => Ok(s),
+        _ => Err(TenxError::Internal("Invalid strategy step".into())),
+    }
+}
+
+/// Returns the files written by the model in WriteTest steps.
+fn test_files(action: &Action) -> Vec<std::path::PathBuf> {
+    let mut files = vec![];
+    for step in &action.steps {
+        if !matches!(tf_step(step), Ok(s) if s.phase == TestPhase::WriteTest) {
+            continue;
+        }
+        if let Some(patch) = step
+            .response
+            .model_response
+            .as_ref()
+            .and_then(|r| r.patch.as_ref())
+        {
+            for f in patch.affected_files() {
+                if !files.contains(&f) {
+                    files.push(f);
+                }
+            }
+        }
+    }
+    files
+}
+
+/// Whether a check runs tests, rather than, say, compiling or linting. Only a failing test check
+/// shows that the new test reproduces the bug; a compile error means the test needs fixing. Test
+/// checks are the ones with a focus configuration.
+fn is_test_check(config: &Config, name: &str) -> bool {
+    config
+        .all_checks()
+        .iter()
+        .any(|c| c.name == name && c.focus.is_some())
+}
+
+impl ActionStrategy for TestFirst {
+    fn name(&self) -> &'static str {
+        "test-first"
+    }
+
+    fn check(
+        &self,
+        config: &Config,
+        session: &mut Session,
+        action_offset: usize,
+        events: Option<EventSender>,
+    ) -> Result<()> {
+        let phase = match session.actions[action_offset].last_step() {
+            Some(step) => tf_step(step)?.phase.clone(),
+            None => return Ok(()),
+        };
+        match phase {
+            TestPhase::MakePass => check_action(config, session, action_offset, &events),
+            TestPhase::WriteTest => match check_action(config, session, action_offset, &events) {
+                Ok(()) => Err(TenxError::Check {
+                    name: "test-first".into(),
+                    user: "New test passes, but should fail".into(),
+                    model: TEST_PASSES_MESSAGE.into(),
+                }),
+                Err(TenxError::Check { name, model, .. }) if is_test_check(config, &name) => {
+                    debug!("Test fails as expected");
+                    if let Some(step) = session.actions[action_offset].steps.last_mut() {
+                        if let StrategyStep::TestFirst(s) = &mut step.strategy_step {
+                            s.failure = Some(model);
+                        }
+                    }
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            },
+        }
+    }
+
+    fn next_step(
+        &self,
+        config: &Config,
+        session: &mut Session,
+        action_offset: usize,
+        events: Option<EventSender>,
+        prompt: Option<String>,
+    ) -> Result<ActionState> {
+        let action = &session.actions[action_offset];
+
+        let Some(step) = action.last_step() else {
+            // First step in the action
+            let Some(p) = prompt else {
+                return Ok(ActionState {
+                    completion: Completion::Incomplete,
+                    input_required: InputRequired::Yes,
+                });
+            };
+            if !config.enabled_checks().iter().any(|c| c.focus.is_some()) {
+                return Err(TenxError::Config(NO_TEST_CHECK.into()));
+            }
+            let raw_prompt = write_test_prompt(&p);
+            let new_step = Step::from_config(
+                config,
+                raw_prompt,
+                StrategyStep::TestFirst(TestFirstStep {
+                    user_input: Some(p),
+                    ..Default::default()
+                }),
+            );
+            session.last_action_mut()?.add_step(new_step)?;
+            return Ok(ActionState {
+                completion: Completion::Incomplete,
+                input_required: InputRequired::No,
+            });
+        };
+
+        // If the last step is incomplete, don't synthesize a new step
+        if step.is_incomplete() {
+            return Ok(ActionState {
+                completion: Completion::Incomplete,
+                input_required: InputRequired::No,
+            });
+        }
+
+        let tstep = tf_step(step)?.clone();
+        if tstep.phase == TestPhase::WriteTest && !step.should_continue() {
+            if let Some(failure) = tstep.failure {
+                // The test fails as expected, so we move on to fixing the bug.
+                let test_step = action.steps.len() - 1;
+                let files = test_files(action);
+                let action = session.last_action_mut()?;
+                if !files.is_empty() {
+                    action.state.patch(&Patch {
+                        changes: files.into_iter().map(Change::View).collect(),
+                    })?;
+                }
+                let raw_prompt = format!("{}\n\n{}", MAKE_PASS_PROMPT, failure);
+                let new_step = Step::from_config(
+                    config,
+                    raw_prompt,
+                    StrategyStep::TestFirst(TestFirstStep {
+                        phase: TestPhase::MakePass,
+                        test_step: Some(test_step),
+                        ..Default::default()
+                    }),
+                );
+                action.add_step(new_step)?;
+                return Ok(ActionState {
+                    completion: Completion::Incomplete,
+                    input_required: InputRequired::No,
+                });
+            }
+        }
+
+        let step_clone = step.clone();
+        process_step(
+            config,
+            session,
+            action_offset,
+            &step_clone,
+            events,
+            StrategyStep::TestFirst(tstep.retry()),
+            prompt,
+        )
+    }
+
+    fn state(&self, _config: &Config, session: &Session, action_offset: usize) -> ActionState {
+        let Some(action) = session.actions.get(action_offset) else {
+            return ActionState {
+                completion: Completion::Incomplete,
+                input_required: InputRequired::No,
+            };
+        };
+        // The action can't complete until we've reached the MakePass phase.
+        if let Some(step) = action.last_step() {
+            if tf_step(step).is_ok_and(|s| s.phase == TestPhase::WriteTest && s.failure.is_some())
+                && !step.should_continue()
+            {
+                return ActionState {
+                    completion: Completion::Incomplete,
+                    input_required: InputRequired::No,
+                };
+            }
+        }
+        get_action_state(action)
+    }
+
+    fn render<R: unirend::Render>(
+        &self,
+        _config: &Config,
+        session: &Session,
+        action_offset: usize,
+        step_offset: usize,
+        renderer: &mut R,
+        detail: Detail,
+    ) -> Result<()> {
+        let step = &session.actions[action_offset].steps[step_offset];
+        let header = match tf_step(step)? {
+            TestFirstStep {
+                phase: TestPhase::WriteTest,
+                ..
+            } => format!("step {}:{} (write test)", action_offset, step_offset),
+            TestFirstStep {
+                phase: TestPhase::MakePass,
+                test_step,
+                ..
+            } => match test_step {
+                Some(t) => format!(
+                    "step {}:{} (make test from step {} pass)",
+                    action_offset, step_offset, t
+                ),
+                None => format!("step {}:{} (make test pass)", action_offset, step_offset),
+            },
+        };
+        render_step(step, renderer, &header, true, detail)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        checks::CheckMode,
+        config::{CheckConfig, TestFocus},
+        session::ModelResponse,
+        strategy::Strategy,
+        testutils::test_project,
+    };
+
+    fn check_config(name: &str, command: &str) -> CheckConfig {
+        CheckConfig {
+            name: name.into(),
+            command: command.into(),
+            globs: vec!["*.rs".into()],
+            default_off: false,
+            fail_on_stderr: false,
+            mode: CheckMode::Validate,
+            focus: (name == "test").then(|| TestFocus {
+                pattern: "^FAIL (\\S+)$".into(),
+                command: "true".into(),
+            }),
+            cwd: None,
+            fix: None,
+        }
+    }
+
+    #[test]
+    fn test_test_first_phases() -> Result<()> {
+        let mut p = test_project();
+        p.write("lib.rs", "fn buggy() {}");
+        p.config.checks.builtin = vec![check_config("test", "false")];
+        let strategy = TestFirst::new();
+        let mut session = Session::new(&p.config)?;
+        session.add_action(Action::new(
+            &p.config,
+            Strategy::TestFirst(strategy.clone()),
+        )?)?;
+
+        // Without a prompt, we need user input
+        let state = strategy.next_step(&p.config, &mut session, 0, None, None)?;
+        assert_eq!(state.input_required, InputRequired::Yes);
+
+        let state = strategy.next_step(
+            &p.config,
+            &mut session,
+            0,
+            None,
+            Some("buggy is broken".into()),
+        )?;
+        assert!(!state.should_stop_iteration());
+        assert!(session.actions[0].steps[0]
+            .request
+            .raw_prompt
+            .starts_with("buggy is broken"));
+
+        // The model writes a test
+        let patch = Patch::default().with_write("src/test.rs", "#[test] fn t() {}");
+        session.last_action_mut()?.state.patch(&patch)?;
+        session.last_step_mut().unwrap().response.model_response = Some(ModelResponse {
+            patch: Some(patch),
+            ..Default::default()
+        });
+
+        // A failing check that doesn't run tests, such as a compile error, sends the model back
+        // to fix its test
+        p.config.checks.builtin = vec![
+            check_config("build", "false"),
+            check_config("test", "false"),
+        ];
+        let err = strategy
+            .check(&p.config, &mut session, 0, None)
+            .unwrap_err();
+        assert!(matches!(err, TenxError::Check { ref name, .. } if name == "build"));
+        assert!(tf_step(session.last_step().unwrap())?.failure.is_none());
+
+        // Test checks fail, so the test is verified to fail
+        p.config.checks.builtin = vec![check_config("test", "false")];
+        strategy.check(&p.config, &mut session, 0, None)?;
+        let state = strategy.state(&p.config, &session, 0);
+        assert_eq!(state.completion, Completion::Incomplete);
+
+        // Move on to the MakePass phase
+        strategy.next_step(&p.config, &mut session, 0, None, None)?;
+        let step = session.last_step().unwrap();
+        let tstep = tf_step(step)?;
+        assert_eq!(tstep.phase, TestPhase::MakePass);
+        assert_eq!(tstep.test_step, Some(0));
+        assert!(session
+            .editables_for_step_state(0, 1)?
+            .contains(&"src/test.rs".into()));
+
+        // Once checks pass, the action is complete
+        session.last_step_mut().unwrap().response.model_response = Some(ModelResponse::default());
+        p.config.checks.builtin = vec![check_config("test", "true")];
+        strategy.check(&p.config, &mut session, 0, None)?;
+        let state = strategy.state(&p.config, &session, 0);
+        assert_eq!(state.completion, Completion::Complete);
+        Ok(())
+    }
+
+    #[test]
+    fn test_test_first_needs_test_check() -> Result<()> {
+        let mut p = test_project();
+        p.config.checks.builtin = vec![check_config("pytest", "false")];
+        let strategy = TestFirst::new();
+        let mut session = Session::new(&p.config)?;
+        session.add_action(Action::new(
+            &p.config,
+            Strategy::TestFirst(strategy.clone()),
+        )?)?;
+
+        // A test check without a focus can't confirm the test fails, so we stop before prompting
+        let err = strategy
+            .next_step(&p.config, &mut session, 0, None, Some("bug".into()))
+            .unwrap_err();
+        assert!(matches!(err, TenxError::Config(ref msg) if msg.contains("focus")));
+        assert!(session.last_action()?.steps.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_test_first_passing_test() -> Result<()> {
+        let mut p = test_project();
+        p.config.checks.builtin = vec![check_config("test", "true")];
+        let strategy = TestFirst::new();
+        let mut session = Session::new(&p.config)?;
+        session.add_action(Action::new(
+            &p.config,
+            Strategy::TestFirst(strategy.clone()),
+        )?)?;
+        strategy.next_step(&p.config, &mut session, 0, None, Some("bug".into()))?;
+
+        let patch = Patch::default().with_write("test.rs", "#[test] fn t() {}");
+        session.last_action_mut()?.state.patch(&patch)?;
+        session.last_step_mut().unwrap().response.model_response = Some(ModelResponse {
+            patch: Some(patch),
+            ..Default::default()
+        });
+
+        // A test that passes is an error, which sends us back to the model in the same phase
+        let err = strategy
+            .check(&p.config, &mut session, 0, None)
+            .unwrap_err();
+        assert!(err.should_retry().is_some());
+        session.last_step_mut().unwrap().outcome.err = Some(err);
+        strategy.next_step(&p.config, &mut session, 0, None, None)?;
+        assert_eq!(
+            tf_step(session.last_step().unwrap())?.phase,
+            TestPhase::WriteTest
+        );
+        // The retry prompt includes the diff of the test the model wrote
+        let prompt = &session.last_step().unwrap().request.raw_prompt;
+        assert!(prompt.starts_with(TEST_PASSES_MESSAGE));
+        assert!(prompt.contains("--- a/test.rs\n+++ b/test.rs\n"));
+        assert!(prompt.contains("+#[test] fn t() {}"));
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+//! Detecting a model that's stuck: one that keeps answering a failure with the same patch, and
+//! keeps getting the same error back. Retrying is pointless then, so we stop early rather than
+//! spending the rest of the step limit.
+use std::collections::HashMap;
+
+use state::{Change, Patch};
+
+use crate::{
+    error::TenxError,
+    session::{Action, Step},
+};
+
+/// Patches at least this similar count as the same attempt.
+const SIMILARITY_THRESHOLD: f64 = 0.9;
+
+/// Returns a fingerprint of a retryable error, with numbers and whitespace normalized so that
+/// incidental differences like timings don't make two errors look different. Returns None for
+/// errors that aren't retried.
+fn error_fingerprint(err: &TenxError) -> Option<String> {
+    let text = err.should_retry()?;
+    let kind = match err {
+        TenxError::Check { name, .. } => format!("check {}", name),
+        TenxError::Patch { .. } => "patch".to_string(),
+        _ => "parse".to_string(),
+    };
+    let normalized: String = text
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .map(|c| if c.is_ascii_digit() { '#' } else { c })
+        .collect();
+    Some(format!("{}: {}", kind, normalized))
+}
+
+/// Returns the trimmed, non-empty lines of a patch's changes, each tagged with the file it
+/// touches.
+fn patch_lines(patch: &Patch) -> Vec<String> {
+    let mut lines = vec![];
+    for change in &patch.changes {
+        let text = match change {
+            Change::Write(w) => w.content.clone(),
+            Change::ReplaceFuzzy(r) => format!("{}\n{}", r.old, r.new),
+            Change::Replace(r) => format!("{}\n{}", r.old, r.new),
+            Change::Insert(i) => i.new.clone(),
+            _ => String::new(),
+        };
+        let path = change.path().display().to_string();
+        lines.push(format!("{} {}", change.name(), path));
+        lines.extend(
+            text.lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+                .map(|l| format!("{}: {}", path, l)),
+        );
+    }
+    lines
+}
+
+/// Returns the similarity of two patches, from 0 to 1, as the share of lines they have in common.
+fn similarity(a: &Patch, b: &Patch) -> f64 {
+    let (a, b) = (patch_lines(a), patch_lines(b));
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for line in &a {
+        *counts.entry(line).or_default() += 1;
+    }
+    let mut common = 0;
+    for line in &b {
+        if let Some(n) = counts.get_mut(line.as_str()).filter(|n| **n > 0) {
+            *n -= 1;
+            common += 1;
+        }
+    }
+    2.0 * common as f64 / (a.len() + b.len()) as f64
+}
+
+/// Checks whether the last `limit` steps of an action all failed with the same error after
+/// near-identical patches. Returns a description of the repeated failure if so. A limit below 2
+/// disables the check.
+pub(crate) fn stuck(action: &Action, limit: usize) -> Option<String> {
+    if limit < 2 || action.steps.len() < limit {
+        return None;
+    }
+    let steps = &action.steps[action.steps.len() - limit..];
+    let first = error_fingerprint(steps[0].outcome.err.as_ref()?)?;
+    let patch = |step: &Step| {
+        step.response
+            .model_response
+            .as_ref()
+            .and_then(|r| r.patch.clone())
+            .unwrap_or_default()
+    };
+    for pair in steps.windows(2) {
+        if error_fingerprint(pair[1].outcome.err.as_ref()?)? != first
+            || similarity(&patch(&pair[0]), &patch(&pair[1])) < SIMILARITY_THRESHOLD
+        {
+            return None;
+        }
+    }
+    Some(format!(
+        "the last {} steps made near-identical changes and failed with the same error: {}",
+        limit,
+        steps[limit - 1].outcome.err.as_ref()?
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        session::ModelResponse,
+        strategy::{Code, CodeStep, Strategy, StrategyStep},
+        testutils::test_project,
+    };
+
+    fn step(patch: Patch, err: TenxError) -> Step {
+        let mut step = Step::new(
+            "model".into(),
+            "prompt".into(),
+            StrategyStep::Code(CodeStep::default()),
+        );
+        step.response.model_response = Some(ModelResponse {
+            patch: Some(patch),
+            ..Default::default()
+        });
+        step.outcome.err = Some(err);
+        step
+    }
+
+    fn check_err(output: &str) -> TenxError {
+        TenxError::Check {
+            name: "cargo-test".into(),
+            user: "tests failed".into(),
+            model: output.into(),
+        }
+    }
+
+    #[test]
+    fn test_stuck() {
+        let patch = Patch::default().with_replace_fuzzy("src/lib.rs", "a + b", "a - b");
+        let mut action = Action::new(&test_project().config, Strategy::Code(Code::new())).unwrap();
+        action
+            .steps
+            .push(step(patch.clone(), check_err("failed in 1.2s")));
+        assert_eq!(stuck(&action, 2), None);
+
+        // The same patch and error, apart from the timing
+        action
+            .steps
+            .push(step(patch.clone(), check_err("failed in 3.4s")));
+        assert!(stuck(&action, 2).unwrap().contains("tests failed"));
+        assert_eq!(stuck(&action, 0), None);
+        assert_eq!(stuck(&action, 3), None);
+
+        // A different error means progress
+        action.steps[1].outcome.err = Some(check_err("other failure"));
+        assert_eq!(stuck(&action, 2), None);
+
+        // So does a different patch
+        action.steps[1].outcome.err = Some(check_err("failed in 3.4s"));
+        action.steps[1].response.model_response = Some(ModelResponse {
+            patch: Some(Patch::default().with_write("src/main.rs", "fn main() {}")),
+            ..Default::default()
+        });
+        assert_eq!(stuck(&action, 2), None);
+    }
+}
@@ -0,0 +1,210 @@
+//! Find named items in Rust source files, using tree-sitter. Symbols are paths like `Session`,
+//! `Session::apply_patch` or `context::Context::new_text`. The final segment names the item, and
+//! any preceding segments must match the enclosing impl types, traits or modules in the file.
+use std::path::{Path, PathBuf};
+
+use fs_err as fs;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use state::syntax::{self, field, preamble_start, Node, Parser, CONTAINERS, ITEMS};
+
+use crate::{
+    config::Config,
+    error::{Result, TenxError},
+};
+
+/// An item matching a symbol.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SymbolMatch {
+    /// The path of the file, relative to the project root.
+    pub path: PathBuf,
+    /// The 1-based line the item starts on, including doc comments and attributes.
+    pub line: usize,
+    /// The source text of the item.
+    pub text: String,
+}
+
+/// Returns the name of an impl block's type, without generics or path qualifiers.
+fn impl_name(node: Node, src: &str) -> Option<String> {
+    let ty = field(node, "type", src)?;
+    let ty = ty.split('<').next()?.trim();
+    Some(ty.rsplit("::").next()?.trim().to_string())
+}
+
+/// Walks the items in a container node, collecting the items matching `segments`.
+fn walk<'a>(
+    node: Node<'a>,
+    src: &str,
+    scope: &mut Vec<String>,
+    segments: &[&str],
+    out: &mut Vec<Node<'a>>,
+) {
+    let (name, parents) = segments
+        .split_last()
+        .expect("symbol segments are never empty");
+    let in_scope = scope.ends_with(&parents.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        let kind = child.kind();
+        let (child_name, body) = if kind == "impl_item" {
+            (impl_name(child, src), child.child_by_field_name("body"))
+        } else if ITEMS.contains(&kind) {
+            let n = field(child, "name", src).map(|n| n.to_string());
+            let body = CONTAINERS
+                .contains(&kind)
+                .then(|| child.child_by_field_name("body"))
+                .flatten();
+            (n, body)
+        } else {
+            continue;
+        };
+        let Some(child_name) = child_name else {
+            continue;
+        };
+        if in_scope && child_name == *name {
+            out.push(child);
+        }
+        if let Some(body) = body {
+            scope.push(child_name);
+            walk(body, src, scope, segments, out);
+            scope.pop();
+        }
+    }
+}
+
+/// Finds the items in a Rust source text that match a symbol, returning their 1-based starting
+/// line and text.
+pub fn find_in_source(src: &str, symbol: &str) -> Result<Vec<(usize, String)>> {
+    let segments: Vec<&str> = symbol.split("::").map(|s| s.trim()).collect();
+    if segments.iter().any(|s| s.is_empty()) {
+        return Err(TenxError::Resolve(format!("Invalid symbol: {}", symbol)));
+    }
+    let mut parser = Parser::new();
+    parser
+        .set_language(&syntax::rust())
+        .map_err(|e| TenxError::Internal(format!("Failed to load Rust grammar: {}", e)))?;
+    let tree = parser
+        .parse(src, None)
+        .ok_or_else(|| TenxError::Internal("Failed to parse source".into()))?;
+
+    let mut nodes = vec![];
+    walk(tree.root_node(), src, &mut vec![], &segments, &mut nodes);
+    Ok(nodes
+        .into_iter()
+        .map(|n| {
+            // Attributes and doc comments are part of the item
+            let start = preamble_start(n);
+            (
+                start.start_position().row + 1,
+                src[start.start_byte()..n.end_byte()].to_string(),
+            )
+        })
+        .collect())
+}
+
+/// Finds the items matching a symbol in a single project file.
+pub fn find_in_file(config: &Config, path: &Path, symbol: &str) -> Result<Vec<SymbolMatch>> {
+    let src = fs::read_to_string(config.project_root().join(path))?;
+    Ok(find_in_source(&src, symbol)?
+        .into_iter()
+        .map(|(line, text)| SymbolMatch {
+            path: path.to_path_buf(),
+            line,
+            text,
+        })
+        .collect())
+}
+
+/// Finds the items matching a symbol in all included Rust files in the project.
+pub fn find(config: &Config, symbol: &str) -> Result<Vec<SymbolMatch>> {
+    let mut files: Vec<PathBuf> = config
+        .project_files()?
+        .into_iter()
+        .filter(|p| p.extension().is_some_and(|e| e == "rs"))
+        .collect();
+    files.sort();
+    let mut ret = vec![];
+    for path in files {
+        ret.extend(find_in_file(config, &path, symbol)?);
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutils::test_project;
+    use indoc::indoc;
+
+    const SRC: &str = indoc! {r#"
+        use std::fmt;
+
+        /// A session.
+        #[derive(Debug)]
+        pub struct Session {
+            steps: Vec<usize>,
+        }
+
+        impl Session {
+            pub fn new() -> Self {
+                Self { steps: vec![] }
+            }
+
+            /// Apply a patch.
+            pub fn apply_patch(&mut self) {}
+        }
+
+        impl<T> From<T> for Session {
+            fn from(_: T) -> Self {
+                Session::new()
+            }
+        }
+
+        mod inner {
+            fn apply_patch() {}
+        }
+    "#};
+
+    #[test]
+    fn test_find_in_source() -> Result<()> {
+        let found = find_in_source(SRC, "Session::apply_patch")?;
+        assert_eq!(
+            found,
+            vec![(
+                14,
+                "/// Apply a patch.\n    pub fn apply_patch(&mut self) {}".to_string()
+            )]
+        );
+
+        // Without a scope, all items with the name match
+        assert_eq!(find_in_source(SRC, "apply_patch")?.len(), 2);
+        assert_eq!(find_in_source(SRC, "inner::apply_patch")?[0].0, 25);
+
+        // A type matches its definition and its impl blocks
+        let found = find_in_source(SRC, "Session")?;
+        assert_eq!(found.len(), 3);
+        assert!(found[0]
+            .1
+            .starts_with("/// A session.\n#[derive(Debug)]\npub struct Session"));
+        assert!(found[2].1.starts_with("impl<T> From<T> for Session"));
+
+        assert!(find_in_source(SRC, "Session::missing")?.is_empty());
+        assert!(find_in_source(SRC, "Session::").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_find() -> Result<()> {
+        let p = test_project();
+        p.write("session.rs", SRC);
+        p.write("other.rs", "fn new() {}\n");
+        p.write("notes.txt", "fn apply_patch() {}\n");
+
+        let found = find(&p.config, "Session::apply_patch")?;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].path, PathBuf::from("session.rs"));
+        assert_eq!(found[0].line, 14);
+        assert_eq!(find(&p.config, "new")?.len(), 2);
+        Ok(())
+    }
+}
@@ -1,27 +1,107 @@
-use std::path::PathBuf;
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
 use tracing::warn;
 
+use state::{encoding::Encoding, Patch};
+
 use crate::{
-    checks::{check_all, check_paths},
+    checkpoint::{self, Checkpoint},
+    checks::{baseline_paths, check_all, check_paths, preflight, Baseline},
     config::Config,
-    context::{Context, ContextProvider},
+    context::{Context, ContextProvider, Priority},
+    dialect::DialectProvider,
     error::{Result, TenxError},
-    events::{send_event, Event, EventBlock, EventSender},
-    session::{Action, Session},
-    session_store::{path_to_filename, SessionStore},
+    events::{send_event, Event, EventBlock, EventSender, LogLevel, SessionStats, StepId},
+    model::{estimate_tokens, Chat, TextChat},
+    recipe::{ItemResult, OnFailure, Recipe, RecipeItem},
+    risk::{self, Risk},
+    sarif::Diagnostic,
+    scrub,
+    session::{Action, ModelResponse, Session, Step},
+    session_store::{path_to_filename, SessionLock, SessionStore},
     strategy,
     strategy::{ActionStrategy, Completion},
+    usage::{self, Ledger, UsageRecord},
 };
 
+/// The model name recorded on steps made by a check's fix command rather than a model.
+const AUTOFIX_MODEL: &str = "autofix";
+
+/// A rough allowance for the output of a model request, in tokens, used when estimating its cost
+/// against the budget.
+const ESTIMATED_OUTPUT_TOKENS: u64 = 4096;
+
+/// The changelog file that `Tenx::changelog` writes entries to, relative to the project root.
+const CHANGELOG: &str = "CHANGELOG.md";
+
+/// What to do when pausing before an automatically generated step.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepDecision {
+    /// Send the step to the model.
+    Continue,
+    /// Stop without sending the step. The step is kept, so the action can be resumed later.
+    Abort,
+    /// Replace the step's prompt, then send it to the model.
+    Prompt(String),
+}
+
+/// Called with the session before each automatically generated step is sent to the model. By
+/// this point, the previous step's patch has been applied and its checks have run.
+pub type StepConfirm = Box<dyn Fn(&Session) -> Result<StepDecision> + Send + Sync>;
+
+/// Called with the session and the risks found in the last step's patch, before the patch is
+/// applied. Returns true if the patch should be applied anyway.
+pub type RiskConfirm = Box<dyn Fn(&Session, &[Risk]) -> Result<bool> + Send + Sync>;
+
 /// Tenx is an AI-driven coding assistant.
 pub struct Tenx {
     pub config: Config,
+    /// The write lock on the stored session, acquired the first time the session is loaded for
+    /// modification or saved, and held until Tenx is dropped.
+    session_lock: Mutex<Option<SessionLock>>,
+    /// Confirms automatically generated steps before they are sent, if set.
+    step_confirm: Option<StepConfirm>,
+    /// Confirms risky patches before they're applied, if set.
+    risk_confirm: Option<RiskConfirm>,
+    /// The name of the command being run, recorded in the usage ledger.
+    command: String,
 }
 
 impl Tenx {
     /// Creates a new Context with the specified configuration.
     pub fn new(config: Config) -> Self {
-        Self { config }
+        Self {
+            config,
+            session_lock: Mutex::new(None),
+            step_confirm: None,
+            risk_confirm: None,
+            command: String::new(),
+        }
+    }
+
+    /// Sets the name of the command being run, so the usage ledger can attribute spend to it.
+    pub fn with_command(mut self, command: &str) -> Self {
+        self.command = command.to_string();
+        self
+    }
+
+    /// Pauses before each automatically generated step, letting the callback continue, abort, or
+    /// change the step's prompt. This lets a user supervise multi-step loops.
+    pub fn with_step_confirm(mut self, confirm: StepConfirm) -> Self {
+        self.step_confirm = Some(confirm);
+        self
+    }
+
+    /// Asks the callback before applying a patch with risky changes. Without a callback, risky
+    /// patches are refused unless `safety.allow_risky` is set. See [`Risk`](crate::api::Risk)
+    /// for what counts as risky.
+    pub fn with_risk_confirm(mut self, confirm: RiskConfirm) -> Self {
+        self.risk_confirm = Some(confirm);
+        self
     }
 
     /// Creates a new Session, discovering the root from the current working directory and
@@ -54,6 +134,18 @@ impl Tenx {
             if self.config.context.project_map {
                 session.add_context(Context::new_project_map());
             }
+
+            // Add project facts if configured
+            if self.config.context.project_facts {
+                session.add_context(Context::new_project_facts());
+            }
+
+            // Add the knowledge base if configured
+            if self.config.context.knowledge {
+                session
+                    .contexts
+                    .add_with_priority(Context::new_knowledge(), Priority::Low);
+            }
         }
 
         // Refresh all contexts
@@ -72,9 +164,12 @@ impl Tenx {
         }
 
         let _block = EventBlock::context(sender)?;
-        for context in session.contexts.iter_mut() {
+        for id in session.contexts.ids() {
+            let Some(context) = session.contexts.get(&id) else {
+                continue;
+            };
             let _refresh_block = EventBlock::context_refresh(sender, &context.human())?;
-            context.refresh(&self.config).await?;
+            session.contexts.refresh(&id, &self.config).await?;
         }
         Ok(())
     }
@@ -89,31 +184,79 @@ impl Tenx {
         self.refresh_contexts_inner(session, sender).await
     }
 
-    /// Refreshes only contexts that need refreshing according to their needs_refresh() method.
+    /// Refreshes only contexts that need refreshing according to their needs_refresh() method,
+    /// and contexts whose captured content is stale. Stale contexts are only warned about if
+    /// `context.refresh_stale` is off.
     pub async fn refresh_needed_contexts(
         &self,
         session: &mut Session,
         sender: &Option<EventSender>,
     ) -> Result<()> {
         let _block = EventBlock::start(sender)?;
+        self.refresh_needed_contexts_inner(session, sender).await
+    }
+
+    async fn refresh_needed_contexts_inner(
+        &self,
+        session: &mut Session,
+        sender: &Option<EventSender>,
+    ) -> Result<()> {
         if !session.contexts.is_empty() {
             let _block = EventBlock::context(sender)?;
-            for context in session.contexts.iter_mut() {
-                if context.needs_refresh(&self.config).await {
+            let stale = session.contexts.stale(&self.config);
+            for id in session.contexts.ids() {
+                let Some(context) = session.contexts.get(&id) else {
+                    continue;
+                };
+                let mut refresh = context.needs_refresh(&self.config).await;
+                if let Some(reason) = stale.get(&id) {
+                    if self.config.context.refresh_stale {
+                        refresh = true;
+                    } else {
+                        send_event(
+                            sender,
+                            Event::Log(
+                                LogLevel::Warn,
+                                format!("Context {} is stale: {}", context.human(), reason),
+                            ),
+                        )?;
+                    }
+                }
+                if refresh {
                     let _refresh_block = EventBlock::context_refresh(sender, &context.human())?;
-                    context.refresh(&self.config).await?;
+                    session.contexts.refresh(&id, &self.config).await?;
                 }
             }
         }
         Ok(())
     }
 
-    /// Add files to edit in the session and save it
-    pub fn edit(&self, session: &mut Session, files: &[String]) -> Result<usize> {
-        let (_, count) = session
-            .last_action_mut()?
-            .state
-            .touch(&self.config.cwd()?, files.to_vec())?;
+    /// Add files to edit in the session and save it. If `force` is set, files are taken as
+    /// explicit paths and added even if they are outside the project's included set. Generated
+    /// files are skipped when matched by a glob, but can be named explicitly.
+    pub fn edit(&self, session: &mut Session, files: &[String], force: bool) -> Result<usize> {
+        let cwd = self.config.cwd()?;
+        let state = &mut session.last_action_mut()?.state;
+        let (_, count) = if force {
+            state.touch_forced(&cwd, files.to_vec())?
+        } else {
+            let mut patterns = Vec::new();
+            for pattern in files {
+                let is_glob = pattern.contains(['*', '?', '[']);
+                if !is_glob || self.config.project.generated.is_empty() {
+                    patterns.push(pattern.clone());
+                    continue;
+                }
+                for path in state.find(&cwd, vec![pattern.clone()])? {
+                    if path.to_string_lossy().starts_with(state::MEM_PREFIX) {
+                        patterns.push(path.display().to_string());
+                    } else if !self.config.is_generated(&path) {
+                        patterns.push(self.config.project_root().join(path).display().to_string());
+                    }
+                }
+            }
+            state.touch(&cwd, patterns)?
+        };
         self.save_session(session)?;
         Ok(count)
     }
@@ -130,6 +273,36 @@ impl Tenx {
         Ok(())
     }
 
+    /// Adds a code action that asks the model to draft a changelog entry summarizing the changes
+    /// made in the session so far, and runs it. The entry is written under the Unreleased heading
+    /// of the project's CHANGELOG.md through the usual patch machinery.
+    pub async fn changelog(
+        &self,
+        session: &mut Session,
+        sender: Option<EventSender>,
+    ) -> Result<strategy::ActionState> {
+        let prompt = changelog_prompt(session)?;
+        self.code(session)?;
+        let path = self.config.project_root().join(CHANGELOG);
+        if path.is_file() {
+            self.edit(session, &[path.display().to_string()], true)?;
+        }
+        self.continue_steps(session, Some(prompt), sender, None)
+            .await
+    }
+
+    /// Adds a test-first fix action to the session. The model first writes a failing test for the
+    /// bug described in the prompt, then fixes the bug so that the test passes.
+    pub fn test_first(&self, session: &mut Session) -> Result<()> {
+        let action = Action::new(
+            &self.config,
+            strategy::Strategy::TestFirst(strategy::TestFirst::new()),
+        )?;
+        session.add_action(action)?;
+        self.save_session(session)?;
+        Ok(())
+    }
+
     /// Adds a fix action to the session.
     /// Files must be already added to the session with session.state.view() before calling this.
     pub fn fix(&self, session: &mut Session, sender: &Option<EventSender>) -> Result<()> {
@@ -151,26 +324,52 @@ impl Tenx {
         }
     }
 
+    /// Acquires the write lock on the session store for this project, if we don't already hold
+    /// it.
+    fn lock_session(&self, session_store: &SessionStore, name: &str) -> Result<()> {
+        let mut lock = self
+            .session_lock
+            .lock()
+            .map_err(|_| TenxError::Internal("Session lock poisoned".into()))?;
+        if lock.is_none() {
+            *lock = Some(session_store.lock(name)?);
+        }
+        Ok(())
+    }
+
     /// Saves a session to the store.
     pub fn save_session(&self, session: &Session) -> Result<()> {
         if self.config.session_store_dir.as_os_str().is_empty() {
             return Ok(());
         }
-        let session_store = SessionStore::open(self.config.session_store_dir.clone())?;
+        let session_store = SessionStore::from_config(&self.config)?;
         let root = self.config.project_root();
         let name = path_to_filename(&root);
+        self.lock_session(&session_store, &name)?;
         session_store.save(&name, session)
     }
 
-    /// Loads a session from the store.
+    /// Loads a session from the store for modification. This takes the session write lock, and
+    /// fails if another process holds it.
     pub fn load_session(&self) -> Result<Session> {
         let root = self.config.project_root();
-        let session_store = SessionStore::open(self.config.session_store_dir.clone())?;
+        let session_store = SessionStore::from_config(&self.config)?;
         let name = path_to_filename(&root);
+        self.lock_session(&session_store, &name)?;
         session_store.load(name)
     }
 
-    /// Reverts to a specific step and prepares for retry.
+    /// Loads a session from the store for inspection only. This never takes the write lock or
+    /// writes to the store, so it is safe to use while another process is modifying the session.
+    pub fn load_session_read_only(&self) -> Result<Session> {
+        let root = self.config.project_root();
+        let session_store = SessionStore::from_config_read_only(&self.config);
+        let name = path_to_filename(&root);
+        session_store.load(name)
+    }
+
+    /// Reverts to a specific step and prepares for retry. If a prompt is given, it replaces the
+    /// step's prompt. Returns the (action, step) indices of the step being retried.
     ///
     /// * `action_idx` - Optional 0-based index of the action
     /// * `step_idx` - Optional 0-based index of the step within the action
@@ -181,7 +380,8 @@ impl Tenx {
         session: &mut Session,
         action_idx: Option<usize>,
         step_idx: Option<usize>,
-    ) -> Result<()> {
+        prompt: Option<String>,
+    ) -> Result<(usize, usize)> {
         if session.actions.is_empty() {
             return Err(TenxError::Internal("No actions in session".to_string()));
         }
@@ -201,8 +401,98 @@ impl Tenx {
 
         // Reset to this step and prepare it for retry
         session.retry(action_index, step_index)?;
+        if let Some(p) = prompt {
+            let step = &mut session.actions[action_index].steps[step_index];
+            step.request.raw_prompt = step.strategy_step.set_user_input(p);
+        }
         self.save_session(session)?;
-        Ok(())
+        Ok((action_index, step_index))
+    }
+
+    /// Renders the prompt for a step as plain text, as it would be sent to the model. Later steps
+    /// are ignored, and the step's own response is omitted. Editable files are read from disk, so
+    /// the rendering reflects the current state of the project.
+    pub fn render_step_prompt(
+        &self,
+        session: &Session,
+        action_idx: usize,
+        step_idx: usize,
+    ) -> Result<String> {
+        let mut session = session.clone();
+        session.actions.truncate(action_idx + 1);
+        let action = session
+            .actions
+            .get_mut(action_idx)
+            .ok_or_else(|| TenxError::Internal("Invalid action index".to_string()))?;
+        if step_idx >= action.steps.len() {
+            return Err(TenxError::Internal("Invalid step index".to_string()));
+        }
+        action.steps.truncate(step_idx + 1);
+        let rollback_id = action.steps[step_idx].outcome.rollback_id;
+        action.steps[step_idx].reset(rollback_id);
+
+        let mut chat: Box<dyn Chat> = Box::new(TextChat::default());
+        self.config
+            .dialect()?
+            .build_chat(&self.config, &session, action_idx, &mut chat)?;
+        chat.render()
+    }
+
+    /// Renders exactly what would be sent to the model for the next step of the current action,
+    /// with estimated token counts for each section. No model request is made, and the session is
+    /// not modified. If the next step needs user input, `prompt` is used.
+    pub fn preview(&self, session: &Session, prompt: Option<String>) -> Result<String> {
+        let (session, state) = self.with_next_step(session, prompt)?;
+        if state.input_required == strategy::InputRequired::Yes {
+            return Err(TenxError::Config(
+                "The next step needs a prompt".to_string(),
+            ));
+        }
+        if session
+            .last_step()
+            .is_none_or(|s| s.response.model_response.is_some())
+        {
+            return Err(TenxError::Config(
+                "The current action is complete, there is no next step".to_string(),
+            ));
+        }
+
+        let action_offset = session.actions.len() - 1;
+        let mut chat: Box<dyn Chat> = Box::new(TextChat::with_token_counts());
+        self.config
+            .dialect()?
+            .build_chat(&self.config, &session, action_offset, &mut chat)?;
+        chat.render()
+    }
+
+    /// Returns a copy of the session with the last action's next step synthesized, as it would be
+    /// before the step is sent, along with the action's state.
+    fn with_next_step(
+        &self,
+        session: &Session,
+        prompt: Option<String>,
+    ) -> Result<(Session, strategy::ActionState)> {
+        let mut session = session.clone();
+        let strategy = session.last_action()?.strategy.clone();
+        let action_offset = session.actions.len() - 1;
+        let state = strategy.next_step(&self.config, &mut session, action_offset, None, prompt)?;
+        Ok((session, state))
+    }
+
+    /// Renders a file exactly as it would be sent to the model in the next step, through the
+    /// dialect's `render_file`. If the next step needs a prompt, an empty one stands in for it.
+    /// Without an action, only the session context is considered.
+    pub fn render_file(&self, session: &Session, path: &Path) -> Result<Vec<String>> {
+        let dialect = self.config.dialect()?;
+        if session.actions.is_empty() {
+            return dialect.render_file(&self.config, session, None, path);
+        }
+        let (mut next, state) = self.with_next_step(session, None)?;
+        if state.input_required == strategy::InputRequired::Yes {
+            (next, _) = self.with_next_step(session, Some(String::new()))?;
+        }
+        let action_offset = next.actions.len() - 1;
+        dialect.render_file(&self.config, &next, Some(action_offset), path)
     }
 
     /// Resets the session to a specific action and step.
@@ -219,6 +509,78 @@ impl Tenx {
         self.save_session(session)
     }
 
+    /// Restores a single file to its content at the start of the given (action, step), or of the
+    /// last step if none is given. Returns false if the file is unchanged since then.
+    pub fn revert_file(
+        &self,
+        session: &mut Session,
+        path: &Path,
+        step: Option<(usize, usize)>,
+    ) -> Result<bool> {
+        let path = self.config.normalize_path(path)?;
+        let (action_idx, step_idx) = match step {
+            Some(step) => step,
+            None => {
+                let action_idx = session.actions.len().checked_sub(1);
+                let step_idx = session
+                    .actions
+                    .last()
+                    .and_then(|a| a.steps.len().checked_sub(1));
+                action_idx
+                    .zip(step_idx)
+                    .ok_or_else(|| TenxError::Internal("No steps in session".into()))?
+            }
+        };
+        let changed = session.revert_file(&path, action_idx, step_idx)?;
+        self.save_session(session)?;
+        Ok(changed)
+    }
+
+    /// Takes a git checkpoint of the files a patch is about to change, if enabled and the project
+    /// is in a git repository. Files the session's checkpoint already covers keep their earlier
+    /// state.
+    fn ensure_checkpoint(&self, session: &mut Session, files: &[PathBuf]) -> Result<()> {
+        let root = self.config.project_root();
+        if !self.config.project.checkpoint || !checkpoint::is_repo(&root) {
+            return Ok(());
+        }
+        match &mut session.checkpoint {
+            Some(cp) => {
+                if !checkpoint::extend(&root, cp, files)? {
+                    return Ok(());
+                }
+            }
+            None => {
+                session.checkpoint =
+                    Some(checkpoint::create(&root, &path_to_filename(&root), files)?);
+            }
+        }
+        self.save_session(session)
+    }
+
+    /// Returns the session's checkpoint.
+    fn checkpoint(session: &Session) -> Result<&Checkpoint> {
+        session
+            .checkpoint
+            .as_ref()
+            .ok_or_else(|| TenxError::Internal("The session has no checkpoint to restore".into()))
+    }
+
+    /// Lists the files that abandoning the session would reset or remove, describing what
+    /// happens to each.
+    pub fn abandon_changes(&self, session: &Session) -> Result<Vec<String>> {
+        checkpoint::changes(&self.config.project_root(), Self::checkpoint(session)?)
+    }
+
+    /// Restores the project to the session's checkpoint and clears the session, throwing away
+    /// everything the session changed. Returns the number of files removed.
+    pub fn abandon(&self, session: &mut Session) -> Result<usize> {
+        let removed = checkpoint::restore(&self.config.project_root(), Self::checkpoint(session)?)?;
+        session.clear(&self.config)?;
+        self.save_session(session)?;
+        Ok(removed)
+    }
+
     /// Resets all steps in the session.
     pub fn reset_all(&self, session: &mut Session) -> Result<()> {
         session.reset_all()?;
@@ -235,12 +597,21 @@ impl Tenx {
         }
     }
 
-    /// Take the next step for the current action.
+    /// Run the validators on all project files, returning the diagnostics parsed from their
+    /// failures.
+    pub fn preflight(&self, sender: &Option<EventSender>) -> Result<Vec<Diagnostic>> {
+        let _block = EventBlock::start(sender)?;
+        preflight(&self.config, sender)
+    }
+
+    /// Take the next step for the current action. If a model is given, the step uses it instead
+    /// of the configured default.
     /// Returns the State of the current action after execution.
     async fn next_step(
         &self,
         session: &mut Session,
         prompt: Option<String>,
+        model: Option<&str>,
         sender: Option<EventSender>,
     ) -> Result<strategy::ActionState> {
         self.save_session(session)?;
@@ -256,6 +627,20 @@ impl Tenx {
         let action_offset = session.actions.len() - 1;
         let strategy = action.strategy.clone();
 
+        // If triage is on, record a baseline of pre-existing check failures once, before the
+        // action makes any changes. Fix actions are excluded, since pre-existing failures are
+        // what they're fixing.
+        let triage = self.config.checks.triage || self.config.checks.mark_known_failing;
+        if triage
+            && action.steps.is_empty()
+            && action.baseline.is_none()
+            && !self.config.checks.no_pre
+            && !matches!(strategy, strategy::Strategy::Fix(_))
+        {
+            let baseline = self.run_pre_checks(session, &sender)?;
+            session.last_action_mut()?.baseline = Some(baseline);
+        }
+
         // Now call next_step with the mutable session reference
         let next_step =
             strategy.next_step(&self.config, session, action_offset, sender.clone(), prompt)?;
@@ -268,29 +653,53 @@ impl Tenx {
             return Ok(next_step);
         }
 
-        // Execute the step
-        match self.execute_prompt_cycle(session, sender.clone()).await {
-            Ok(()) => {
-                self.save_session(session)?;
-            }
-            Err(e) => {
-                if let Some(step) = session.last_step_mut() {
-                    step.err = Some(e.clone());
-                    self.save_session(session)?;
-                }
-                if e.should_retry().is_none() {
-                    return Err(e);
+        // Record the override on the new step
+        if let (Some(model), Some(step)) = (model, session.last_step_mut()) {
+            let config = self.config.with_model(model);
+            step.request.model = config.model_name();
+            step.request.model_alias = config.model_alias();
+        }
+
+        // Steps after the first are generated from the previous response, so we confirm them
+        if let Some(confirm) = &self.step_confirm {
+            if session.last_action()?.steps.len() > 1 {
+                send_event(&sender, Event::Interact)?;
+                match confirm(session)? {
+                    StepDecision::Continue => {}
+                    StepDecision::Abort => {
+                        return Ok(strategy::ActionState {
+                            completion: Completion::Incomplete,
+                            input_required: strategy::InputRequired::Yes,
+                        });
+                    }
+                    StepDecision::Prompt(p) => {
+                        // The strategy builds the prompt from the new input, as for a retry
+                        if let Some(step) = session.last_step_mut() {
+                            step.request.raw_prompt = step.strategy_step.set_user_input(p);
+                        }
+                        self.save_session(session)?;
+                    }
                 }
             }
         }
 
+        // Context the model requested in the previous step is added without content
+        self.refresh_needed_contexts_inner(session, &sender).await?;
+
+        // Execute the step, then record the file hashes as of the end of the step
+        let result = self
+            .execute_prompt_cycle(session, model, sender.clone())
+            .await;
+        self.finish_step(session, result, &sender)?;
+
         Ok(session.actions[action_offset]
             .strategy
             .state(&self.config, session, action_offset))
     }
 
     /// Iterate on steps until the action is complete.
-    /// The optional prompt is passed to the first step.
+    /// The optional prompt is passed to the first step. Without one, a patch that was refused as
+    /// risky is applied first if it's now allowed, rather than asking the model again.
     /// Returns the final state of the action.
     pub async fn continue_steps(
         &self,
@@ -298,33 +707,211 @@ impl Tenx {
         prompt: Option<String>,
         sender: Option<EventSender>,
         timeout: Option<std::time::Duration>,
+    ) -> Result<strategy::ActionState> {
+        if prompt.is_none() {
+            self.apply_refused(session, &sender).await?;
+        }
+        self.run_steps(session, prompt, None, sender, timeout).await
+    }
+
+    /// Continue the current session with a prompt, using the given model or alias for the first
+    /// step only. Later steps in the action, and the configured default, are unaffected. If the
+    /// last action is complete, or there is none, a new code action is started for the prompt.
+    /// If a time budget is given, no further steps are started once it's exhausted - see
+    /// `expire_budget`. Returns the final state of the action.
+    pub async fn ask(
+        &self,
+        session: &mut Session,
+        prompt: Option<String>,
+        model: Option<&str>,
+        sender: Option<EventSender>,
+        budget: Option<Duration>,
+    ) -> Result<strategy::ActionState> {
+        if let Some(model) = model {
+            self.config.with_model(model).active_model()?;
+        }
+        let complete = match session.last_action() {
+            Ok(action) => matches!(
+                action
+                    .strategy
+                    .state(&self.config, session, session.actions.len() - 1)
+                    .completion,
+                Completion::Complete
+            ),
+            Err(_) => true,
+        };
+        if complete {
+            self.code(session)?;
+        }
+        self.run_steps(session, prompt, model, sender, budget).await
+    }
+
+    /// Runs a recipe, item by item. Each prompt item gets an action of its own, with the item's
+    /// editables added and, if the item names checks, only those checks enabled. Command items run
+    /// in the project root. Returns the results of the items that ran: running stops after the
+    /// first failure, unless the recipe or item says to continue.
+    pub async fn run_recipe(
+        &mut self,
+        session: &mut Session,
+        recipe: &Recipe,
+        sender: Option<EventSender>,
+    ) -> Result<Vec<ItemResult>> {
+        let mut results = vec![];
+        for (i, item) in recipe.items.iter().enumerate() {
+            let label = item.label(i);
+            send_event(
+                &sender,
+                Event::Log(
+                    LogLevel::Info,
+                    format!("recipe item {}/{}: {}", i + 1, recipe.items.len(), label),
+                ),
+            )?;
+            let error = match self.run_recipe_item(session, item, sender.clone()).await {
+                Ok(failure) => failure,
+                Err(e) => Some(e.to_string()),
+            };
+            let stop = error.is_some() && recipe.on_failure(i) == OnFailure::Stop;
+            results.push(ItemResult { label, error });
+            if stop {
+                break;
+            }
+        }
+        Ok(results)
+    }
+
+    /// Runs a single recipe item, returning a description of the failure if it failed.
+    async fn run_recipe_item(
+        &mut self,
+        session: &mut Session,
+        item: &RecipeItem,
+        sender: Option<EventSender>,
+    ) -> Result<Option<String>> {
+        if let Some(command) = &item.command {
+            let output = self
+                .config
+                .executor()
+                .exec(&self.config.project_root(), command)?;
+            return Ok(
+                (!output.success()).then(|| format!("{} failed: {}", command, output.stderr))
+            );
+        }
+        let prompt = item.prompt.clone().unwrap_or_default();
+        // The item's config applies for the duration of its action
+        let scoped = item.config(&self.config)?;
+        let config = std::mem::replace(&mut self.config, scoped);
+        let result = self.run_recipe_prompt(session, item, prompt, sender).await;
+        self.config = config;
+        result
+    }
+
+    async fn run_recipe_prompt(
+        &self,
+        session: &mut Session,
+        item: &RecipeItem,
+        prompt: String,
+        sender: Option<EventSender>,
+    ) -> Result<Option<String>> {
+        self.code(session)?;
+        if !item.edit.is_empty() {
+            self.edit(session, &item.edit, false)?;
+        }
+        let state = self
+            .ask(session, Some(prompt), item.model.as_deref(), sender, None)
+            .await?;
+        Ok((state.completion != Completion::Complete)
+            .then(|| "the action did not complete".to_string()))
+    }
+
+    /// Runs steps like `run_steps_inner`, then sends stats for the run, whether it succeeded or
+    /// not.
+    async fn run_steps(
+        &self,
+        session: &mut Session,
+        prompt: Option<String>,
+        model: Option<&str>,
+        sender: Option<EventSender>,
+        timeout: Option<std::time::Duration>,
     ) -> Result<strategy::ActionState> {
         let _block = EventBlock::start(&sender)?;
+        let first_step = first_run_step(session.last_action()?);
+        let spend = session.spend;
+        let start_time = std::time::Instant::now();
+
+        let result = self
+            .run_steps_inner(session, prompt, model, sender.clone(), timeout)
+            .await;
+
+        if let Some(action) = session.actions.last() {
+            let stats = SessionStats {
+                cost: session.spend - spend,
+                duration: start_time.elapsed(),
+                ..action.stats(first_step)
+            };
+            send_event(&sender, Event::SessionStats(stats))?;
+        }
+        result
+    }
+
+    async fn run_steps_inner(
+        &self,
+        session: &mut Session,
+        prompt: Option<String>,
+        model: Option<&str>,
+        sender: Option<EventSender>,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<strategy::ActionState> {
         self.save_session(session)?;
         let mut step_count = 0;
+        let first_step = first_run_step(session.last_action()?);
 
         let start_time = std::time::Instant::now();
         loop {
             step_count += 1;
 
-            // Use next_step to handle the strategy logic
-            let action_state = self
-                .next_step(
-                    session,
-                    if step_count == 1 {
-                        prompt.clone()
-                    } else {
-                        None
-                    },
-                    sender.clone(),
-                )
-                .await?;
+            // Use next_step to handle the strategy logic. With a time budget, a step that's still
+            // running when the budget runs out is cut off at its next await, typically the model
+            // request, and rolled back. Checks that are already running finish first.
+            let steps = session.last_action()?.steps.len();
+            let step = self.next_step(
+                session,
+                if step_count == 1 {
+                    prompt.clone()
+                } else {
+                    None
+                },
+                model.filter(|_| step_count == 1),
+                sender.clone(),
+            );
+            let action_state = match timeout {
+                Some(timeout) => {
+                    let remaining = timeout.saturating_sub(start_time.elapsed());
+                    match tokio::time::timeout(remaining, step).await {
+                        Ok(state) => state?,
+                        Err(_) => {
+                            warn!("Timeout reached during a step");
+                            let cut_off = self.cut_off_step(session, steps)?;
+                            return self
+                                .expire_budget(session, first_step, timeout, cut_off, &sender);
+                        }
+                    }
+                }
+                None => step.await?,
+            };
 
             // If the action is complete, we're done
             if action_state.should_stop_iteration() {
                 return Ok(action_state);
             }
 
+            // Stop if the model keeps repeating the same failed attempt
+            let limit = self.config.stuck_limit;
+            if step_count >= limit {
+                if let Some(msg) = crate::stuck::stuck(session.last_action()?, limit) {
+                    warn!("Model is stuck: {}", msg);
+                    return Err(TenxError::Stuck(msg));
+                }
+            }
+
             // Check step limit
             if step_count >= self.config.step_limit {
                 warn!("Step count limit reached");
@@ -336,85 +923,634 @@ impl Tenx {
             if let Some(timeout) = timeout {
                 if start_time.elapsed() > timeout {
                     warn!("Timeout reached");
-                    return Ok(action_state);
+                    return self.expire_budget(session, first_step, timeout, false, &sender);
                 }
             }
         }
     }
 
-    async fn execute_prompt_cycle(
+    /// Records the result of running the last step. Errors the model can fix are kept on the step
+    /// for the strategy to act on, and others are returned.
+    fn finish_step(
         &self,
         session: &mut Session,
-        sender: Option<EventSender>,
+        result: Result<()>,
+        sender: &Option<EventSender>,
     ) -> Result<()> {
-        self.prompt_model(session, sender.clone()).await?;
-        send_event(&sender, Event::ApplyPatch)?;
-        session.apply_last_step(&self.config)?;
-        if !session.should_continue() {
-            // We're done, now we check if checks return an error we need to process
-            self.run_post_checks(session, &sender)?;
+        // A failure to hash mustn't hide the step's own result, or stop the session being saved
+        if let Err(e) = session.update_file_hashes(&self.config) {
+            warn!("Failed to update file hashes: {}", e);
         }
-        Ok(())
-    }
-
-    /// Prompts the current model with the session's state and sets the resulting patch and usage.
-    async fn prompt_model(&self, session: &mut Session, sender: Option<EventSender>) -> Result<()> {
-        let action = session.last_action()?;
-        let strategy = action.strategy.clone();
-        let _block = EventBlock::prompt(&sender, strategy.name())?;
-        // FIXME: Make this param configurable
-        let mut throttler = crate::throttle::Throttler::new(25);
-
-        loop {
-            let start_time = std::time::Instant::now();
-            match strategy
-                .send(
-                    &self.config,
-                    session,
-                    session.actions.len() - 1,
-                    sender.clone(),
-                )
-                .await
-            {
-                Ok(resp) => {
-                    let elapsed = start_time.elapsed().as_secs_f64();
-                    if let Some(last_step) = session.last_step_mut() {
-                        last_step.model_response = Some(resp);
-                        last_step.response_time = Some(elapsed);
+        match result {
+            Ok(()) => {
+                self.save_session(session)?;
+                if let Some(resp) = session
+                    .last_step()
+                    .and_then(|s| s.response.model_response.as_ref())
+                {
+                    if let Some(question) = resp.question() {
+                        send_event(sender, Event::AskUser(question.to_string()))?;
+                    }
+                    if let Some(reason) = resp.abort_reason() {
+                        send_event(sender, Event::Aborted(reason.to_string()))?;
                     }
-                    throttler.reset();
-                    return Ok(());
                 }
-                Err(TenxError::Throttle(t)) => {
-                    throttler.throttle(&t, &sender).await?;
-                    continue;
+            }
+            Err(e) => {
+                if let Some(step) = session.last_step_mut() {
+                    step.outcome.err = Some(e.clone());
+                    self.save_session(session)?;
+                }
+                if e.should_retry().is_none() {
+                    return Err(e);
                 }
-                Err(e) => return Err(e),
             }
         }
+        Ok(())
     }
 
-    fn run_post_checks(&self, session: &mut Session, sender: &Option<EventSender>) -> Result<()> {
-        let _check_block = EventBlock::post_check(sender)?;
-        let action = session.last_action()?;
-        let strategy = action.strategy.clone();
-        strategy.check(
-            &self.config,
-            session,
-            session.actions.len() - 1,
-            sender.clone(),
-        )
+    /// Applies the last step's patch if it was refused as risky, without prompting the model
+    /// again. The patch still has to be allowed, by `safety.allow_risky` or the risk
+    /// confirmation. Returns false if the last step has no refused patch.
+    async fn apply_refused(
+        &self,
+        session: &mut Session,
+        sender: &Option<EventSender>,
+    ) -> Result<bool> {
+        let Some(step) = session.last_step_mut() else {
+            return Ok(false);
+        };
+        let has_patch = step
+            .response
+            .model_response
+            .as_ref()
+            .is_some_and(|r| r.patch.is_some());
+        if !has_patch || !matches!(step.outcome.err, Some(TenxError::Risky(_))) {
+            return Ok(false);
+        }
+        step.outcome.err = None;
+        let result = self.apply_response(session, false, sender).await;
+        self.finish_step(session, result, sender)?;
+        Ok(true)
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
-    use std::path::PathBuf;
+    async fn execute_prompt_cycle(
+        &self,
+        session: &mut Session,
+        model: Option<&str>,
+        sender: Option<EventSender>,
+    ) -> Result<()> {
+        // Hash the editables as they're sent, so edits made while the model responds show up
+        session.update_file_hashes(&self.config)?;
+        self.prompt_model(session, model, sender.clone()).await?;
+        self.apply_response(session, true, &sender).await
+    }
 
-    use crate::{
-        session::ModelResponse,
+    /// Applies the patch in the last step's response, then runs the post checks if the action is
+    /// done. With `conflicts`, the patch is refused if any of the files it writes have changed
+    /// since the editables were last hashed.
+    async fn apply_response(
+        &self,
+        session: &mut Session,
+        conflicts: bool,
+        sender: &Option<EventSender>,
+    ) -> Result<()> {
+        let files = session
+            .last_step()
+            .and_then(|s| s.response.model_response.as_ref())
+            .and_then(|r| r.patch.as_ref())
+            .map(|p| p.affected_files())
+            .unwrap_or_default();
+        if conflicts {
+            self.check_conflicts(session, &files)?;
+        }
+        self.check_redactions(session)?;
+        self.check_risks(session, sender)?;
+        if !files.is_empty() {
+            self.ensure_checkpoint(session, &files)?;
+        }
+        let written = self.apply_patch(session, files, sender)?;
+        self.refresh_overlapping_contexts(session, &written, sender)
+            .await?;
+        if !session.should_continue() {
+            // We're done, now we check if checks return an error we need to process
+            if let Err(e) = self.run_post_checks(session, sender) {
+                // Mechanical fixes are tried before the failure goes back to the model
+                let fixed = match &e {
+                    TenxError::Check { name, .. } if self.config.checks.autofix => {
+                        self.autofix(session, name, sender)?
+                    }
+                    _ => false,
+                };
+                if !fixed {
+                    return Err(e);
+                }
+                self.run_post_checks(session, sender)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Refuses to apply a patch to `files` if any of them changed since the session's file hashes
+    /// were updated, for instance because they were saved from an editor while the model was
+    /// responding. Applying the patch would overwrite those edits.
+    fn check_conflicts(&self, session: &Session, files: &[PathBuf]) -> Result<()> {
+        let changed: Vec<String> = session
+            .changed_files(&self.config)?
+            .into_iter()
+            .filter(|p| files.contains(p))
+            .map(|p| p.display().to_string())
+            .collect();
+        if changed.is_empty() {
+            return Ok(());
+        }
+        Err(TenxError::Conflict(format!(
+            "{} changed while the model was responding. The patch was not applied, so the \
+             changes are kept. Retry the step to prompt with the current contents",
+            changed.join(", ")
+        )))
+    }
+
+    /// Refuses the last step's patch if it would write redaction placeholders over the secrets
+    /// they stand in for.
+    fn check_redactions(&self, session: &Session) -> Result<()> {
+        if self.config.scrub.disable {
+            return Ok(());
+        }
+        let Some(patch) = session
+            .last_step()
+            .and_then(|s| s.response.model_response.as_ref())
+            .and_then(|r| r.patch.as_ref())
+        else {
+            return Ok(());
+        };
+        let state = &session.last_action()?.state;
+        scrub::check_patch(patch, |path| state.read(path).ok())
+    }
+
+    /// Checks the last step's patch for risky changes, and records any it finds on the step. A
+    /// risky patch is refused unless it's allowed in the config or confirmed by the user.
+    fn check_risks(&self, session: &mut Session, sender: &Option<EventSender>) -> Result<()> {
+        if !self.config.safety.check {
+            return Ok(());
+        }
+        let Some(patch) = session
+            .last_step()
+            .and_then(|s| s.response.model_response.as_ref())
+            .and_then(|r| r.patch.as_ref())
+        else {
+            return Ok(());
+        };
+        let risks = risk::assess(&self.config, &session.last_action()?.state, patch);
+        if risks.is_empty() {
+            return Ok(());
+        }
+        if let Some(step) = session.last_step_mut() {
+            step.outcome.risks = risks.clone();
+        }
+        if self.config.safety.allow_risky {
+            return Ok(());
+        }
+        if let Some(confirm) = &self.risk_confirm {
+            send_event(sender, Event::Interact)?;
+            if confirm(session, &risks)? {
+                return Ok(());
+            }
+        }
+        Err(TenxError::Risky(format!(
+            "{}. Continue with --allow-risky to apply it anyway",
+            risk::summary(&risks)
+        )))
+    }
+
+    /// Applies the last step's patch, which affects `files`, reporting each file as it's written.
+    /// Returns the files written.
+    fn apply_patch(
+        &self,
+        session: &mut Session,
+        files: Vec<PathBuf>,
+        sender: &Option<EventSender>,
+    ) -> Result<Vec<PathBuf>> {
+        send_event(
+            sender,
+            Event::PatchApplyStart {
+                step: self.last_step_id(session),
+                files,
+            },
+        )?;
+        let started = std::time::Instant::now();
+        let mut written = vec![];
+        let result = session.apply_last_step_with(&self.config, |path| {
+            // Progress is best effort, and mustn't interrupt a patch half way through writing
+            let _ = send_event(sender, Event::FileWritten(path.to_path_buf()));
+            written.push(path.to_path_buf());
+        });
+        send_event(
+            sender,
+            Event::PatchApplyEnd {
+                files: written.clone(),
+                duration: started.elapsed(),
+            },
+        )?;
+        result.map(|_| written)
+    }
+
+    /// Refreshes the contexts whose content is derived from `files`, so that later steps see the
+    /// files as the patch left them. The patch has already been applied, so a context that fails
+    /// to refresh is warned about rather than failing the step.
+    async fn refresh_overlapping_contexts(
+        &self,
+        session: &mut Session,
+        files: &[PathBuf],
+        sender: &Option<EventSender>,
+    ) -> Result<()> {
+        for id in session.contexts.ids() {
+            let Some(context) = session.contexts.get(&id) else {
+                continue;
+            };
+            if !context.overlaps(files) {
+                continue;
+            }
+            let human = context.human();
+            let _refresh_block = EventBlock::context_refresh(sender, &human)?;
+            if let Err(e) = session.contexts.refresh(&id, &self.config).await {
+                send_event(
+                    sender,
+                    Event::Log(
+                        LogLevel::Warn,
+                        format!("Could not refresh context {}: {}", human, e),
+                    ),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Prompts the current model, or the given model if set, with the session's state and sets
+    /// the resulting patch and usage.
+    async fn prompt_model(
+        &self,
+        session: &mut Session,
+        model: Option<&str>,
+        sender: Option<EventSender>,
+    ) -> Result<()> {
+        let config = match model {
+            Some(model) => Cow::Owned(self.config.with_model(model)),
+            None => Cow::Borrowed(&self.config),
+        };
+        self.check_budget(&config, session)?;
+        // Lints are advisory, so a lint that fails mustn't stop the request
+        match crate::lint::lint(&config, session, session.actions.len() - 1) {
+            Ok(warnings) => {
+                for warning in warnings {
+                    send_event(&sender, Event::PromptLint(warning))?;
+                }
+            }
+            Err(e) => warn!("Prompt lint failed: {}", e),
+        }
+        let action = session.last_action()?;
+        let strategy = action.strategy.clone();
+        let _block = EventBlock::prompt(&sender, &config.model_name(), self.last_step_id(session))?;
+        // FIXME: Make this param configurable
+        let mut throttler = crate::throttle::Throttler::new(25);
+
+        loop {
+            let start_time = std::time::Instant::now();
+            match strategy
+                .send(&config, session, session.actions.len() - 1, sender.clone())
+                .await
+            {
+                Ok(resp) => {
+                    let elapsed = start_time.elapsed().as_secs_f64();
+                    if let Some(last_step) = session.last_step_mut() {
+                        last_step.response.model_response = Some(resp);
+                        last_step.response.time = Some(elapsed);
+                    }
+                    self.record_spend(&config, session)?;
+                    throttler.reset();
+                    return Ok(());
+                }
+                Err(TenxError::Throttle(t)) => {
+                    throttler.throttle(&t, &sender).await?;
+                    continue;
+                }
+                Err(e) if e.is_transient() => {
+                    warn!("Transient provider error, retrying: {}", e);
+                    throttler
+                        .throttle(&crate::throttle::Throttle::Backoff, &sender)
+                        .await?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Refuses to prompt the model if the estimated cost of the next request would take us over the
+    /// session or daily budget. The estimate covers the rendered prompt, plus an allowance for
+    /// the response.
+    fn check_budget(&self, config: &Config, session: &Session) -> Result<()> {
+        let budget = &config.budget;
+        if budget.force || (budget.session_usd <= 0.0 && budget.daily_usd <= 0.0) {
+            return Ok(());
+        }
+        let pricing = config.pricing().ok_or_else(|| {
+            TenxError::Budget(format!(
+                "no pricing is known for model {}, so spend can't be estimated. \
+                 Add it to models.pricing, or use --over-budget",
+                config.model_name()
+            ))
+        })?;
+        let mut chat: Box<dyn Chat> = Box::new(TextChat::default());
+        config
+            .dialect()?
+            .build_chat(config, session, session.actions.len() - 1, &mut chat)?;
+        let estimate = pricing.cost(
+            estimate_tokens(&chat.render()?) as u64,
+            ESTIMATED_OUTPUT_TOKENS,
+        );
+
+        if budget.session_usd > 0.0 && session.spend + estimate > budget.session_usd {
+            return Err(TenxError::Budget(format!(
+                "session spend of ${:.2} plus an estimated ${:.2} for this request exceeds \
+                 the session budget of ${:.2}. Use --over-budget to proceed anyway",
+                session.spend, estimate, budget.session_usd
+            )));
+        }
+        if budget.daily_usd > 0.0 && !config.session_store_dir.as_os_str().is_empty() {
+            let daily = SessionStore::from_config(config)?.spend_today()?;
+            if daily + estimate > budget.daily_usd {
+                return Err(TenxError::Budget(format!(
+                    "today's spend of ${:.2} plus an estimated ${:.2} for this request exceeds \
+                     the daily budget of ${:.2}. Use --over-budget to proceed anyway",
+                    daily, estimate, budget.daily_usd
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the cost of the last step's model response to the session's spend, and to today's
+    /// total in the session store, and records the call in the usage ledger.
+    fn record_spend(&self, config: &Config, session: &mut Session) -> Result<()> {
+        let Some((input, output)) = session
+            .last_step()
+            .and_then(|s| s.response.model_response.as_ref())
+            .and_then(|r| r.usage.as_ref())
+            .map(|u| u.totals())
+        else {
+            return Ok(());
+        };
+        let cost = config.pricing().map_or(0.0, |p| p.cost(input, output));
+        session.spend += cost;
+        if !config.session_store_dir.as_os_str().is_empty() {
+            if cost > 0.0 {
+                SessionStore::from_config(config)?.add_spend(cost)?;
+            }
+            Ledger::from_config(config).add(&UsageRecord {
+                time: usage::now(),
+                model: config.model_name(),
+                input_tokens: input,
+                output_tokens: output,
+                cost,
+                command: self.command.clone(),
+                project: config.project_root().display().to_string(),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Runs the fix command of a failing check, recording the changes it makes as a step of their
+    /// own, so they can be reviewed and rolled back like a model's. Returns false if the check
+    /// has no fix command, or the fix changed nothing.
+    fn autofix(
+        &self,
+        session: &mut Session,
+        name: &str,
+        sender: &Option<EventSender>,
+    ) -> Result<bool> {
+        let Some(check) = self.config.get_check(name) else {
+            return Ok(false);
+        };
+        let Some(fix) = check.fix.clone() else {
+            return Ok(false);
+        };
+        let state = &session.last_action()?.state;
+        let mut before = vec![];
+        for path in check.relevant_paths(&state.list()?)? {
+            if let Ok(content) = state.read(&path) {
+                before.push((path, content));
+            }
+        }
+        send_event(
+            sender,
+            Event::Log(
+                LogLevel::Info,
+                format!("check {} failed, running {}", name, fix),
+            ),
+        )?;
+        check.run_fix(&self.config)?;
+
+        // The fix wrote to the tree directly. We put the original content back, and apply the
+        // fix as a patch, so the state can roll it back.
+        let mut patch = Patch::default();
+        for (path, original) in before {
+            let Ok(fixed) = session.last_action()?.state.read(&path) else {
+                continue;
+            };
+            if fixed != original {
+                patch = patch.with_write(&path, Encoding::detect(&original).decode(&fixed));
+                fs_err::write(self.config.abspath(&path)?, original)?;
+            }
+        }
+        if patch.changes.is_empty() {
+            return Ok(false);
+        }
+        let mut step = Step::new(
+            AUTOFIX_MODEL.into(),
+            format!("Run {} to fix check {}", fix, name),
+            strategy::StrategyStep::Code(strategy::CodeStep::default()),
+        );
+        step.response.model_response = Some(ModelResponse {
+            comment: Some(format!("Applied {}", fix)),
+            patch: Some(patch),
+            ..Default::default()
+        });
+        session.last_action_mut()?.add_step(step)?;
+        session.apply_last_step(&self.config)?;
+        Ok(true)
+    }
+
+    /// Rolls back a step that was cut off by the time budget, and resets it so it's run again
+    /// when the action is continued. `steps` is the number of steps the action had before the
+    /// step started: if the step was cut off before it was added, there's nothing to undo.
+    /// Returns true if a step was rolled back.
+    fn cut_off_step(&self, session: &mut Session, steps: usize) -> Result<bool> {
+        let action = session.last_action_mut()?;
+        let added = action.steps.len() > steps;
+        if added {
+            let rollback_id = action.steps[steps].outcome.rollback_id;
+            action.state.revert_from(rollback_id)?;
+            let mark = action.state.mark()?;
+            action.steps[steps].reset(mark);
+        }
+        self.save_session(session)?;
+        Ok(added)
+    }
+
+    /// Stops a run whose time budget is exhausted. If the last step failed, its changes are
+    /// rolled back, so the tree is left as the last good step left it. A step that was cut off has
+    /// already been rolled back by `cut_off_step`. A summary of what the run
+    /// attempted is recorded as a note on the action, outside the conversation with the model,
+    /// and sent as an event. The action is left incomplete, so it can be continued.
+    fn expire_budget(
+        &self,
+        session: &mut Session,
+        first_step: usize,
+        budget: Duration,
+        cut_off: bool,
+        sender: &Option<EventSender>,
+    ) -> Result<strategy::ActionState> {
+        let action = session.last_action_mut()?;
+        let failed = action
+            .last_step()
+            .filter(|s| s.outcome.err.is_some())
+            .map(|s| s.outcome.rollback_id);
+        if let Some(rollback_id) = failed {
+            action.state.revert_from(rollback_id)?;
+        }
+        let summary = budget_summary(action, first_step, budget, failed.is_some() || cut_off);
+        action.notes.push(summary.clone());
+        self.save_session(session)?;
+        send_event(sender, Event::TimeBudget(summary))?;
+        Ok(strategy::ActionState {
+            completion: Completion::Incomplete,
+            input_required: strategy::InputRequired::Yes,
+        })
+    }
+
+    /// Returns the identity of the last step in the session, for use in events.
+    fn last_step_id(&self, session: &Session) -> StepId {
+        let action = session.actions.len().saturating_sub(1);
+        let step = session
+            .actions
+            .last()
+            .map_or(0, |a| a.steps.len().saturating_sub(1));
+        StepId::new(&self.config, action, step)
+    }
+
+    /// Runs checks on all files in the current action's state, returning the failures.
+    fn run_pre_checks(&self, session: &Session, sender: &Option<EventSender>) -> Result<Baseline> {
+        let _check_block = EventBlock::pre_check(sender)?;
+        let paths = session.last_action()?.state.list()?;
+        baseline_paths(&self.config, &paths, &session.known_failing, sender)
+    }
+
+    fn run_post_checks(&self, session: &mut Session, sender: &Option<EventSender>) -> Result<()> {
+        let _check_block = EventBlock::post_check(sender)?;
+        let action = session.last_action()?;
+        let strategy = action.strategy.clone();
+        strategy.check(
+            &self.config,
+            session,
+            session.actions.len() - 1,
+            sender.clone(),
+        )
+    }
+}
+
+/// The index of the first step a run of the action works on. An incomplete last step, like one
+/// reset for a retry, is run again as part of the run.
+fn first_run_step(action: &Action) -> usize {
+    action
+        .steps
+        .len()
+        .saturating_sub(action.last_step().map_or(0, |s| s.is_incomplete() as usize))
+}
+
+/// Describes what the steps of a run that ran out of time attempted, and how each one ended.
+fn budget_summary(
+    action: &Action,
+    first_step: usize,
+    budget: Duration,
+    rolled_back: bool,
+) -> String {
+    let steps = action.steps.get(first_step..).unwrap_or_default();
+    let mut lines = vec![format!(
+        "The time budget of {}s ran out after {} step(s).",
+        budget.as_secs(),
+        steps.len()
+    )];
+    for (i, step) in steps.iter().enumerate() {
+        let prompt = step.request.raw_prompt.lines().next().unwrap_or_default();
+        let outcome = match &step.outcome.err {
+            Some(e) => format!(
+                "failed: {}",
+                e.to_string().lines().next().unwrap_or_default()
+            ),
+            None if step.is_incomplete() => "cut off".to_string(),
+            None => step.status().to_string(),
+        };
+        lines.push(format!(
+            "- step {}: {} ({})",
+            first_step + i,
+            prompt,
+            outcome
+        ));
+    }
+    if rolled_back {
+        lines.push("The changes made by the last step were rolled back.".into());
+    }
+    lines.join("\n")
+}
+
+/// Builds the prompt for a changelog entry from the comments, summaries and diffs of every action
+/// in the session.
+fn changelog_prompt(session: &Session) -> Result<String> {
+    let mut changes = String::new();
+    for action in &session.actions {
+        if action.steps.is_empty() {
+            continue;
+        }
+        let diff = action.step_diff(0)?;
+        if diff.is_empty() {
+            continue;
+        }
+        for response in action
+            .steps
+            .iter()
+            .filter_map(|s| s.response.model_response.as_ref())
+        {
+            if let Some(summary) = &response.summary {
+                changes.push_str(&format!("{}\n", summary.commit_message()));
+            }
+            if let Some(comment) = &response.comment {
+                changes.push_str(&format!("{}\n", comment));
+            }
+        }
+        changes.push_str(&format!("```diff\n{}```\n\n", diff));
+    }
+    if changes.is_empty() {
+        return Err(TenxError::Internal(
+            "No changes in the session to write a changelog entry for".to_string(),
+        ));
+    }
+    Ok(format!(
+        "Draft an entry for {CHANGELOG} summarizing the changes below, written for the project's \
+         users rather than its developers. Add it under the \"Unreleased\" heading, creating the \
+         heading, or the file, if needed. Follow the style of any existing entries, and don't \
+         change anything else.\n\n{changes}"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use crate::{
+        session::ModelResponse,
         strategy::{Completion, InputRequired},
     };
     use state::{Change, Patch, WriteFile};
@@ -433,11 +1569,14 @@ mod tests {
             ruskel: vec![],
             path: vec![],
             project_map: false,
+            project_facts: false,
+            knowledge: false,
             text: vec![TextContext {
                 name: "test".to_string(),
                 content: "test content".to_string(),
             }],
             cmd: vec![],
+            ..Default::default()
         };
         let tenx = Tenx::new(config);
 
@@ -449,6 +1588,59 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_ask_model_override() -> Result<()> {
+        let temp_dir = tempdir().unwrap();
+        let mut config = Config::default()
+            .with_dummy_model(crate::model::DummyModel::from_model_response(
+                ModelResponse::default(),
+            ))
+            .with_root(temp_dir.path());
+        config.session_store_dir = temp_dir.path().join("sess");
+        config.step_limit = 1;
+        config
+            .aliases
+            .insert("cheap".to_string(), "cheap-model".to_string());
+        let tenx = Tenx::new(config.clone());
+        let mut session = Session::new(&config)?;
+
+        // The override applies to the asked step only. Asking with no action starts one.
+        tenx.ask(
+            &mut session,
+            Some("first".into()),
+            Some("cheap"),
+            None,
+            None,
+        )
+        .await?;
+        tenx.code(&mut session)?;
+        tenx.continue_steps(&mut session, Some("second".into()), None, None)
+            .await?;
+
+        let first = &session.actions[0].steps[0];
+        assert_eq!(first.request.model, "cheap-model");
+        assert_eq!(first.request.model_alias.as_deref(), Some("cheap"));
+        assert_eq!(
+            session.actions[1].steps[0].request.model,
+            config.model_name()
+        );
+
+        // Asking after the last action is complete starts a new action for the prompt
+        tenx.ask(
+            &mut session,
+            Some("third".into()),
+            Some("cheap"),
+            None,
+            None,
+        )
+        .await?;
+        assert_eq!(session.actions.len(), 3);
+        let third = &session.actions[2].steps[0];
+        assert_eq!(third.request.raw_prompt, "third");
+        assert_eq!(third.request.model, "cheap-model");
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_tenx_process_prompt() -> Result<()> {
         let temp_dir = tempdir().unwrap();
@@ -456,6 +1648,7 @@ mod tests {
             .with_dummy_model(crate::model::DummyModel::from_model_response(
                 ModelResponse {
                     comment: Some("Test comment".to_string()),
+                    summary: None,
                     patch: Some(Patch {
                         changes: vec![Change::Write(WriteFile {
                             path: PathBuf::from("test.txt"),
@@ -469,7 +1662,9 @@ mod tests {
             ))
             .with_root(temp_dir.path());
 
-        config.session_store_dir = temp_dir.path().join("sess");
+        // Keep the session store out of the project, so the saved session isn't an editable
+        let store_dir = tempdir().unwrap();
+        config.session_store_dir = store_dir.path().to_path_buf();
         config.step_limit = 1;
         config.project.include.push("**".to_string());
 
@@ -491,16 +1686,35 @@ mod tests {
             .unwrap();
 
         // Run the steps
-        tenx.continue_steps(&mut session, Some("test".into()), None, None)
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        tenx.continue_steps(&mut session, Some("test".into()), Some(tx), None)
             .await
             .unwrap();
+        let mut stats = None;
+        let mut written = vec![];
+        let mut applied = None;
+        while let Ok(event) = rx.try_recv() {
+            match event {
+                Event::SessionStats(s) => stats = Some(s),
+                Event::FileWritten(path) => written.push(path),
+                Event::PatchApplyEnd { files, .. } => applied = Some(files),
+                _ => {}
+            }
+        }
+        let stats = stats.expect("no stats event");
+        assert_eq!(written, vec![PathBuf::from("test.txt")]);
+        assert_eq!(applied, Some(written));
+        assert_eq!(stats.steps, 1);
+        assert_eq!(stats.files, vec![PathBuf::from("test.txt")]);
+        assert_eq!(stats.failing_check, None);
 
         let last_action = session.last_action().unwrap();
 
         assert_eq!(last_action.steps.len(), 1);
-        assert!(last_action.steps[0].model_response.is_some());
+        assert!(last_action.steps[0].response.model_response.is_some());
         assert_eq!(
             last_action.steps[0]
+                .response
                 .model_response
                 .as_ref()
                 .unwrap()
@@ -510,6 +1724,727 @@ mod tests {
 
         let file_content = fs::read_to_string(&test_file_path).unwrap();
         assert_eq!(file_content, "Updated content");
+
+        // Retrying with a new prompt changes the rendered prompt
+        let failed = session.clone();
+        let (a, s) = tenx.retry(&mut session, None, None, Some("revised".into()))?;
+        assert_eq!((a, s), (0, 0));
+        let old = tenx.render_step_prompt(&failed, a, s)?;
+        let new = tenx.render_step_prompt(&session, a, s)?;
+        assert!(!old.contains("Test comment"));
+        assert!(old.contains("<prompt>\ntest\n</prompt>"));
+        assert!(new.contains("<prompt>\nrevised\n</prompt>"));
+        assert_eq!(
+            session.last_step().unwrap().strategy_step.user_input(),
+            Some(&"revised".to_string())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_changelog() -> Result<()> {
+        let temp_dir = tempdir().unwrap();
+        let write = |path: &str, content: &str| ModelResponse {
+            comment: Some(format!("Wrote {}", path)),
+            patch: Some(Patch {
+                changes: vec![Change::Write(WriteFile {
+                    path: PathBuf::from(path),
+                    content: content.to_string(),
+                })],
+            }),
+            ..Default::default()
+        };
+        let mut config = Config::default()
+            .with_dummy_model(crate::model::DummyModel::from_model_response(write(
+                "test.txt",
+                "Updated\n",
+            )))
+            .with_root(temp_dir.path())
+            .with_cwd(temp_dir.path().to_path_buf());
+        config.session_store_dir = temp_dir.path().join("sess");
+        config.step_limit = 1;
+        config.project.include.push("**".to_string());
+        fs::write(temp_dir.path().join("test.txt"), "Initial\n").unwrap();
+
+        let mut session = Session::new(&config)?;
+        assert!(changelog_prompt(&session).is_err());
+
+        let tenx = Tenx::new(config.clone());
+        tenx.code(&mut session)?;
+        tenx.edit(&mut session, &["test.txt".into()], false)?;
+        tenx.continue_steps(&mut session, Some("update".into()), None, None)
+            .await?;
+        let prompt = changelog_prompt(&session)?;
+        assert!(prompt.contains("Wrote test.txt"));
+        assert!(prompt.contains("-Initial\n+Updated\n"));
+
+        drop(tenx);
+        let changelog = "# Changelog\n\n## Unreleased\n\n- Updated test.txt\n";
+        fs::write(
+            temp_dir.path().join(CHANGELOG),
+            "# Changelog\n\n## Unreleased\n",
+        )
+        .unwrap();
+        let tenx = Tenx::new(config.with_dummy_model(
+            crate::model::DummyModel::from_model_response(write(CHANGELOG, changelog)),
+        ));
+        tenx.changelog(&mut session, None).await?;
+        assert_eq!(session.actions.len(), 2);
+        assert_eq!(session.actions[1].steps[0].request.raw_prompt, prompt);
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join(CHANGELOG)).unwrap(),
+            changelog
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_preview() -> Result<()> {
+        let temp_dir = tempdir().unwrap();
+        let mut config = Config::default().with_root(temp_dir.path());
+        config.session_store_dir = temp_dir.path().join("sess");
+        let tenx = Tenx::new(config.clone());
+        let mut session = Session::new(&config)?;
+        tenx.code(&mut session)?;
+
+        assert!(tenx.preview(&session, None).is_err());
+        let preview = tenx.preview(&session, Some("test".into()))?;
+        assert!(preview.starts_with("## system (~"));
+        assert!(preview.contains("<prompt>\ntest\n</prompt>"));
+        assert!(preview.contains("## total"));
+
+        // The session itself is untouched
+        assert!(session.last_action()?.steps.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pre_existing_check_failure() -> Result<()> {
+        let temp_dir = tempdir().unwrap();
+        let mut config = Config::default()
+            .with_dummy_model(crate::model::DummyModel::from_model_response(
+                ModelResponse {
+                    patch: Some(Patch::default().with_write("test.txt", "Updated content")),
+                    ..Default::default()
+                },
+            ))
+            .with_root(temp_dir.path());
+        config.session_store_dir = temp_dir.path().join("sess");
+        config.step_limit = 1;
+        config.project.include.push("**".to_string());
+        config.checks.builtin = vec![crate::config::CheckConfig {
+            name: "broken".into(),
+            command: "echo broken && exit 1".into(),
+            globs: vec!["*.txt".into()],
+            default_off: false,
+            fail_on_stderr: false,
+            mode: crate::checks::CheckMode::Validate,
+            focus: None,
+            cwd: None,
+            fix: None,
+        }];
+        fs::write(temp_dir.path().join("test.txt"), "Initial content").unwrap();
+
+        // Without triage, no baseline is taken, and the failure goes to the model
+        let tenx = Tenx::new(config.clone());
+        let mut session = Session::new(&config)?;
+        tenx.code(&mut session)?;
+        tenx.continue_steps(&mut session, Some("test".into()), None, None)
+            .await?;
+        let action = session.last_action()?;
+        assert!(action.baseline.is_none());
+        assert!(matches!(
+            action.steps[0].outcome.err,
+            Some(TenxError::Check { .. })
+        ));
+        drop(tenx);
+
+        config.checks.mark_known_failing = true;
+        fs::write(temp_dir.path().join("test.txt"), "Initial content").unwrap();
+        let tenx = Tenx::new(config.clone());
+        let mut session = Session::new(&config)?;
+        tenx.code(&mut session)?;
+        tenx.continue_steps(&mut session, Some("test".into()), None, None)
+            .await?;
+
+        // The check fails identically before and after the change, so it isn't reported
+        let action = session.last_action()?;
+        assert!(action.baseline.as_ref().unwrap().contains_key("broken"));
+        assert!(action.steps[0].outcome.err.is_none());
+        assert!(session.known_failing.contains("broken"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_autofix() -> Result<()> {
+        let temp_dir = tempdir().unwrap();
+        let mut config = Config::default()
+            .with_dummy_model(crate::model::DummyModel::from_model_response(
+                ModelResponse {
+                    patch: Some(Patch::default().with_write("test.txt", "broken")),
+                    ..Default::default()
+                },
+            ))
+            .with_root(temp_dir.path());
+        config.session_store_dir = temp_dir.path().join("sess");
+        config.step_limit = 1;
+        config.project.include.push("**".to_string());
+        config.checks.no_pre = true;
+        config.checks.autofix = true;
+        config.checks.builtin = vec![crate::config::CheckConfig {
+            name: "fixable".into(),
+            command: "grep -q fixed test.txt".into(),
+            globs: vec!["*.txt".into()],
+            default_off: false,
+            fail_on_stderr: false,
+            mode: crate::checks::CheckMode::Validate,
+            focus: None,
+            cwd: None,
+            fix: Some("printf fixed > test.txt".into()),
+        }];
+        fs::write(temp_dir.path().join("test.txt"), "Initial content").unwrap();
+
+        let tenx = Tenx::new(config.clone());
+        let mut session = Session::new(&config)?;
+        tenx.code(&mut session)?;
+        tenx.continue_steps(&mut session, Some("test".into()), None, None)
+            .await?;
+
+        // The fix is recorded as a step of its own, which passes the check
+        let action = session.last_action()?;
+        assert_eq!(action.steps.len(), 2);
+        assert_eq!(action.steps[1].request.model, AUTOFIX_MODEL);
+        assert!(action.steps[1].outcome.err.is_none());
+        assert_eq!(action.steps[1].files(), vec![PathBuf::from("test.txt")]);
+        let read = || fs::read_to_string(temp_dir.path().join("test.txt")).unwrap();
+        assert_eq!(read(), "fixed");
+
+        // Reverting the file to the start of the fix step rolls back just the fix
+        assert!(session.revert_file(Path::new("test.txt"), 0, 1)?);
+        assert_eq!(read(), "broken");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_time_budget() -> Result<()> {
+        let temp_dir = tempdir().unwrap();
+        let mut config = Config::default()
+            .with_dummy_model(crate::model::DummyModel::from_model_response(
+                ModelResponse {
+                    patch: Some(Patch::default().with_write("test.txt", "broken")),
+                    ..Default::default()
+                },
+            ))
+            .with_root(temp_dir.path());
+        config.session_store_dir = temp_dir.path().join("sess");
+        config.step_limit = 5;
+        config.project.include.push("**".to_string());
+        config.checks.no_pre = true;
+        config.checks.builtin = vec![crate::config::CheckConfig {
+            name: "failing".into(),
+            command: "grep -q fixed test.txt".into(),
+            globs: vec!["*.txt".into()],
+            default_off: false,
+            fail_on_stderr: false,
+            mode: crate::checks::CheckMode::Validate,
+            focus: None,
+            cwd: None,
+            fix: None,
+        }];
+        fs::write(temp_dir.path().join("test.txt"), "Initial content").unwrap();
+
+        let tenx = Tenx::new(config.clone());
+        let mut session = Session::new(&config)?;
+        tenx.code(&mut session)?;
+        let state = tenx
+            .ask(
+                &mut session,
+                Some("fix it".into()),
+                None,
+                None,
+                Some(Duration::ZERO),
+            )
+            .await?;
+
+        // No retry is attempted, the failing patch is rolled back, and a summary is recorded
+        // outside the conversation with the model, leaving the action incomplete
+        assert_eq!(state.completion, Completion::Incomplete);
+        let action = session.last_action()?;
+        assert_eq!(action.steps.len(), 1);
+        assert!(action.steps[0].outcome.err.is_some());
+        assert_eq!(action.notes.len(), 1);
+        assert!(action.notes[0].contains("- step 0: fix it (failed: "));
+        assert!(action.notes[0].contains("rolled back"));
+        assert_eq!(
+            action.strategy.state(&config, &session, 0).completion,
+            Completion::Incomplete
+        );
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("test.txt")).unwrap(),
+            "Initial content"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_time_budget_cut_off() -> Result<()> {
+        let temp_dir = tempdir().unwrap();
+        let mut config = Config::default()
+            .with_dummy_model(
+                crate::model::DummyModel::from_model_response(ModelResponse {
+                    patch: Some(Patch::default().with_write("test.txt", "changed")),
+                    ..Default::default()
+                })
+                .with_delay(Duration::from_secs(60)),
+            )
+            .with_root(temp_dir.path());
+        config.session_store_dir = temp_dir.path().join("sess");
+        config.project.include.push("**".to_string());
+        config.checks.no_pre = true;
+        fs::write(temp_dir.path().join("test.txt"), "Initial content").unwrap();
+
+        let tenx = Tenx::new(config.clone());
+        let mut session = Session::new(&config)?;
+        tenx.code(&mut session)?;
+        let state = tenx
+            .ask(
+                &mut session,
+                Some("change it".into()),
+                None,
+                None,
+                Some(Duration::from_millis(50)),
+            )
+            .await?;
+
+        // A step still waiting on the model when the budget runs out is cut off, and left to be
+        // run again when the action is continued
+        assert_eq!(state.completion, Completion::Incomplete);
+        let action = session.last_action()?;
+        assert_eq!(action.steps.len(), 1);
+        assert!(action.steps[0].is_incomplete());
+        assert!(action.notes[0].contains("- step 0: change it (cut off)"));
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("test.txt")).unwrap(),
+            "Initial content"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_recipe() -> Result<()> {
+        let temp_dir = tempdir().unwrap();
+        let mut config = Config::default()
+            .with_dummy_model(crate::model::DummyModel::from_model_response(
+                ModelResponse {
+                    patch: Some(Patch::default().with_write("test.txt", "changed")),
+                    ..Default::default()
+                },
+            ))
+            .with_root(temp_dir.path())
+            .with_cwd(temp_dir.path().to_path_buf())
+            .with_dummy_executor(crate::exec::FakeExecutor::default().with_output(
+                "make docs",
+                crate::exec::ExecOutput::new(2, "", "no rule to make target"),
+            ));
+        config.session_store_dir = temp_dir.path().join("sess");
+        config.project.include.push("**".to_string());
+        config.checks.no_pre = true;
+        fs::write(temp_dir.path().join("test.txt"), "Initial content").unwrap();
+
+        let recipe = Recipe::parse(
+            r#"
+            [[item]]
+            prompt = "change it"
+            edit = ["test.txt"]
+
+            [[item]]
+            command = "make docs"
+
+            [[item]]
+            prompt = "never run"
+            "#,
+        )?;
+        let mut tenx = Tenx::new(config.clone());
+        let mut session = Session::new(&config)?;
+        let results = tenx.run_recipe(&mut session, &recipe, None).await?;
+
+        // The failing command stops the recipe
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].error, None);
+        assert_eq!(
+            results[1].error.as_deref(),
+            Some("make docs failed: no rule to make target")
+        );
+        assert_eq!(session.actions.len(), 1);
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("test.txt")).unwrap(),
+            "changed"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_step_confirm() -> Result<()> {
+        use std::sync::{Arc, Mutex};
+        let temp_dir = tempdir().unwrap();
+        let mut config = Config::default()
+            .with_dummy_model(crate::model::DummyModel::from_model_response(
+                ModelResponse {
+                    patch: Some(Patch::default().with_write("test.txt", "Updated content")),
+                    ..Default::default()
+                },
+            ))
+            .with_root(temp_dir.path());
+        config.session_store_dir = temp_dir.path().join("sess");
+        config.step_limit = 10;
+        config.project.include.push("**".to_string());
+        config.checks.no_pre = true;
+        config.checks.builtin = vec![crate::config::CheckConfig {
+            name: "initial".into(),
+            command: "grep -q Initial test.txt".into(),
+            globs: vec!["*.txt".into()],
+            default_off: false,
+            fail_on_stderr: false,
+            mode: crate::checks::CheckMode::Validate,
+            focus: None,
+            cwd: None,
+            fix: None,
+        }];
+        fs::write(temp_dir.path().join("test.txt"), "Initial content").unwrap();
+
+        // Change the prompt of the first generated step, then abort at the next one
+        let seen = Arc::new(Mutex::new(vec![]));
+        let s = seen.clone();
+        let tenx = Tenx::new(config.clone()).with_step_confirm(Box::new(move |session| {
+            let mut seen = s.lock().unwrap();
+            seen.push(session.last_action()?.steps.len());
+            Ok(if seen.len() == 1 {
+                StepDecision::Prompt("try again".into())
+            } else {
+                StepDecision::Abort
+            })
+        }));
+        let mut session = Session::new(&config)?;
+        tenx.code(&mut session)?;
+        let state = tenx
+            .continue_steps(&mut session, Some("test".into()), None, None)
+            .await?;
+
+        assert_eq!(*seen.lock().unwrap(), vec![2, 3]);
+        assert_eq!(state.input_required, InputRequired::Yes);
+        let action = session.last_action()?;
+        assert_eq!(action.steps.len(), 3);
+        assert_eq!(action.steps[1].request.raw_prompt, "try again");
+        assert!(action.steps[1].response.model_response.is_some());
+        assert!(action.steps[2].is_incomplete());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_step_confirm_test_first() -> Result<()> {
+        let temp_dir = tempdir().unwrap();
+        let mut config = Config::default()
+            .with_dummy_model(crate::model::DummyModel::from_model_response(
+                ModelResponse {
+                    patch: Some(Patch::default().with_write("test.txt", "Updated content")),
+                    ..Default::default()
+                },
+            ))
+            .with_root(temp_dir.path());
+        config.session_store_dir = temp_dir.path().join("sess");
+        config.step_limit = 10;
+        config.project.include.push("**".to_string());
+        config.checks.no_pre = true;
+        // A check that doesn't run tests fails, so the model is asked to write the test again
+        let check = |name: &str, command: &str, focus| crate::config::CheckConfig {
+            name: name.into(),
+            command: command.into(),
+            globs: vec!["*.txt".into()],
+            default_off: false,
+            fail_on_stderr: false,
+            mode: crate::checks::CheckMode::Validate,
+            focus,
+            cwd: None,
+            fix: None,
+        };
+        config.checks.builtin = vec![
+            check("build", "grep -q Initial test.txt", None),
+            check(
+                "test",
+                "true",
+                Some(crate::config::TestFocus {
+                    pattern: "^FAIL (\\S+)$".into(),
+                    command: "true".into(),
+                }),
+            ),
+        ];
+        fs::write(temp_dir.path().join("test.txt"), "Initial content").unwrap();
+
+        let tenx = Tenx::new(config.clone()).with_step_confirm(Box::new(|session| {
+            Ok(if session.last_action()?.steps.len() == 2 {
+                StepDecision::Prompt("try again".into())
+            } else {
+                StepDecision::Abort
+            })
+        }));
+        let mut session = Session::new(&config)?;
+        tenx.test_first(&mut session)?;
+        tenx.continue_steps(&mut session, Some("test".into()), None, None)
+            .await?;
+
+        // The edited prompt still asks for a failing test
+        let step = &session.last_action()?.steps[1];
+        assert!(step.request.raw_prompt.starts_with("try again\n\n"));
+        assert!(step
+            .request
+            .raw_prompt
+            .contains("write a test that reproduces it"));
+        assert_eq!(
+            step.strategy_step.user_input().map(|s| s.as_str()),
+            Some("try again")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_refresh_overlapping_contexts() -> Result<()> {
+        let temp_dir = tempdir().unwrap();
+        let mut config = Config::default()
+            .with_dummy_model(crate::model::DummyModel::from_model_response(
+                ModelResponse {
+                    patch: Some(Patch::default().with_write("test.txt", "Updated content")),
+                    ..Default::default()
+                },
+            ))
+            .with_root(temp_dir.path())
+            .with_cwd(temp_dir.path().to_path_buf());
+        config.session_store_dir = temp_dir.path().join("sess");
+        config.project.include.push("**".to_string());
+        config.checks.no_pre = true;
+        fs::write(temp_dir.path().join("test.txt"), "Initial content").unwrap();
+
+        let tenx = Tenx::new(config.clone());
+        let mut session = Session::new(&config)?;
+        session.add_context(Context::new_lines(&config, "test.txt:1-1")?);
+        tenx.refresh_contexts(&mut session, &None).await?;
+        tenx.code(&mut session)?;
+        tenx.continue_steps(&mut session, Some("test".into()), None, None)
+            .await?;
+
+        let items = session.contexts.list()[0].context_items(&config, &session)?;
+        assert!(items[0].body.contains("Updated content"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_conflicts() -> Result<()> {
+        let temp_dir = tempdir().unwrap();
+        let mut config = Config::default().with_root(temp_dir.path());
+        config.session_store_dir = temp_dir.path().join("sess");
+        config.project.include.push("**".to_string());
+        fs::write(temp_dir.path().join("a.txt"), "a").unwrap();
+        fs::write(temp_dir.path().join("b.txt"), "b").unwrap();
+
+        let tenx = Tenx::new(config.clone());
+        let mut session = Session::new(&config)?;
+        tenx.code(&mut session)?;
+        session
+            .last_action_mut()?
+            .state
+            .touch(config.project_root(), vec!["*.txt".into()])?;
+        session.last_action_mut()?.add_step(Step::new(
+            "test_model".into(),
+            "test".into(),
+            strategy::StrategyStep::Code(strategy::CodeStep::default()),
+        ))?;
+        assert_eq!(session.update_file_hashes(&config)?.len(), 2);
+        let files = vec![PathBuf::from("a.txt"), PathBuf::from("new.txt")];
+        tenx.check_conflicts(&session, &files)?;
+
+        // Edits to files the patch doesn't write are fine
+        fs::write(temp_dir.path().join("b.txt"), "edited").unwrap();
+        tenx.check_conflicts(&session, &files)?;
+
+        fs::write(temp_dir.path().join("a.txt"), "edited").unwrap();
+        let err = tenx.check_conflicts(&session, &files).unwrap_err();
+        assert!(matches!(err, TenxError::Conflict(m) if m.starts_with("a.txt")));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_risky_patch() -> Result<()> {
+        let temp_dir = tempdir().unwrap();
+        let mut config = Config::default()
+            .with_dummy_model(crate::model::DummyModel::from_model_response(
+                ModelResponse {
+                    patch: Some(Patch::default().with_write("Cargo.toml", "[package]\n")),
+                    ..Default::default()
+                },
+            ))
+            .with_root(temp_dir.path());
+        config.session_store_dir = temp_dir.path().join("sess");
+        config.project.include.push("**".to_string());
+        config.checks.no_pre = true;
+        config.safety.check = true;
+        fs::write(temp_dir.path().join("Cargo.toml"), "").unwrap();
+
+        // Without confirmation, the patch is refused and the risk recorded
+        let tenx = Tenx::new(config.clone());
+        let mut session = Session::new(&config)?;
+        tenx.code(&mut session)?;
+        let err = tenx
+            .continue_steps(&mut session, Some("test".into()), None, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, TenxError::Risky(_)));
+        let step = session.last_step().unwrap();
+        assert_eq!(step.outcome.risks[0].kind, risk::RiskKind::Manifest);
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("Cargo.toml")).unwrap(),
+            ""
+        );
+
+        // Continuing with risky patches allowed applies the refused patch, without asking the
+        // model again. Each Tenx holds the session lock until it's dropped.
+        drop(tenx);
+        let mut allowed = config.clone();
+        allowed.safety.allow_risky = true;
+        let tenx = Tenx::new(allowed);
+        tenx.continue_steps(&mut session, None, None, None).await?;
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("Cargo.toml")).unwrap(),
+            "[package]\n"
+        );
+        assert_eq!(session.last_action()?.steps.len(), 1);
+        assert!(session.last_step().unwrap().outcome.err.is_none());
+        fs::write(temp_dir.path().join("Cargo.toml"), "").unwrap();
+
+        // A confirmed patch is applied
+        drop(tenx);
+        let tenx = Tenx::new(config.clone()).with_risk_confirm(Box::new(|_, risks| {
+            Ok(risks.iter().all(|r| r.path == "Cargo.toml"))
+        }));
+        let mut session = Session::new(&config)?;
+        tenx.code(&mut session)?;
+        tenx.continue_steps(&mut session, Some("test".into()), None, None)
+            .await?;
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("Cargo.toml")).unwrap(),
+            "[package]\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_redacted_write() -> Result<()> {
+        let temp_dir = tempdir().unwrap();
+        // The model saw the secret as a placeholder, and rewrites the whole file
+        let mut config = Config::default()
+            .with_dummy_model(crate::model::DummyModel::from_model_response(
+                ModelResponse {
+                    patch: Some(
+                        Patch::default()
+                            .with_write(".env", "API_TOKEN=[REDACTED:env-secret]\nDEBUG=false\n"),
+                    ),
+                    ..Default::default()
+                },
+            ))
+            .with_root(temp_dir.path());
+        config.session_store_dir = temp_dir.path().join("sess");
+        config.project.include.push("**".to_string());
+        config.checks.no_pre = true;
+        config.step_limit = 1;
+        let env = "API_TOKEN=s3cr3t-value-here\nDEBUG=true\n";
+        fs::write(temp_dir.path().join(".env"), env).unwrap();
+
+        let tenx = Tenx::new(config.clone());
+        let mut session = Session::new(&config)?;
+        tenx.code(&mut session)?;
+        let _ = tenx
+            .continue_steps(&mut session, Some("test".into()), None, None)
+            .await;
+        let step = session.last_action()?.steps.first().unwrap();
+        assert!(matches!(step.outcome.err, Some(TenxError::Patch { .. })));
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join(".env")).unwrap(),
+            env
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_budget() -> Result<()> {
+        use crate::config::Pricing;
+        let temp_dir = tempdir().unwrap();
+        let mut config = Config::default()
+            .with_dummy_model(crate::model::DummyModel::from_model_response(
+                ModelResponse {
+                    comment: Some("Test comment".to_string()),
+                    summary: None,
+                    patch: None,
+                    operations: vec![],
+                    usage: None,
+                    raw_response: Some("Test comment".to_string()),
+                },
+            ))
+            .with_root(temp_dir.path());
+        config.session_store_dir = temp_dir.path().join("sess");
+        config.step_limit = 1;
+        let pricing = Pricing {
+            input: 1.0,
+            output: 1.0,
+        };
+        config.models.pricing.insert("dummy".into(), pricing);
+        config.budget.session_usd = 0.01;
+
+        let tenx = Tenx::new(config.clone());
+        let mut session = Session::new(&config)?;
+        tenx.code(&mut session)?;
+        tenx.continue_steps(&mut session, Some("test".into()), None, None)
+            .await?;
+        // The dummy model reports one input and one output token
+        assert_eq!(session.spend, pricing.cost(1, 1));
+        let store = SessionStore::from_config(&config)?;
+        assert_eq!(store.spend_today()?, session.spend);
+        let records = Ledger::from_config(&config).records()?;
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            (records[0].input_tokens, records[0].cost),
+            (1, session.spend)
+        );
+
+        session.spend = 0.009;
+        tenx.code(&mut session)?;
+        assert!(matches!(
+            tenx.continue_steps(&mut session, Some("test".into()), None, None)
+                .await,
+            Err(TenxError::Budget(_))
+        ));
+
+        // The daily budget applies across sessions. Each Tenx holds the session lock, so we drop
+        // them before making another.
+        drop(tenx);
+        store.add_spend(0.009)?;
+        let mut daily = config.clone();
+        daily.budget.session_usd = 0.0;
+        daily.budget.daily_usd = 0.01;
+        let tenx = Tenx::new(daily.clone());
+        let mut other = Session::new(&daily)?;
+        tenx.code(&mut other)?;
+        assert!(matches!(
+            tenx.continue_steps(&mut other, Some("test".into()), None, None)
+                .await,
+            Err(TenxError::Budget(_))
+        ));
+
+        drop(tenx);
+        daily.budget.force = true;
+        let tenx = Tenx::new(daily);
+        let mut other = Session::new(&config)?;
+        tenx.code(&mut other)?;
+        tenx.continue_steps(&mut other, Some("test".into()), None, None)
+            .await?;
+        assert_eq!(other.spend, pricing.cost(1, 1));
         Ok(())
     }
 
@@ -520,6 +2455,7 @@ mod tests {
             .with_dummy_model(crate::model::DummyModel::from_model_response(
                 ModelResponse {
                     comment: Some("Test comment".to_string()),
+                    summary: None,
                     patch: Some(Patch {
                         changes: vec![Change::Write(WriteFile {
                             path: PathBuf::from("test.txt"),
@@ -560,7 +2496,7 @@ mod tests {
             .unwrap();
 
         let state = tenx
-            .next_step(&mut session, Some("test".into()), None)
+            .next_step(&mut session, Some("test".into()), None, None)
             .await?;
 
         // Verify the returned state matches what we expect
@@ -571,7 +2507,7 @@ mod tests {
         let last_action = session.last_action().unwrap();
 
         assert_eq!(last_action.steps.len(), 1);
-        assert!(last_action.steps[0].model_response.is_some());
+        assert!(last_action.steps[0].response.model_response.is_some());
         let file_content = fs::read_to_string(&test_file_path).unwrap();
         assert_eq!(file_content, "Updated content");
 
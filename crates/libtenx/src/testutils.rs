@@ -1,3 +1,6 @@
+//! Helpers for tests, in libtenx and in applications that embed it. [`offline_project`] gives a
+//! project in a temporary directory that never touches the network, so test suites can run
+//! hermetically. Combine it with [`config::Config::with_dummy_model`] to script model responses.
 use crate::{config, session::Session};
 use fs_err as fs;
 use std::path::Path;
@@ -48,6 +51,15 @@ pub fn test_project() -> TestProject {
     }
 }
 
+/// Creates a mock project like [`test_project`], in offline mode, so nothing in it touches the
+/// network. Models reply with an empty response unless a dummy model is set, and URL and Ruskel
+/// contexts get placeholder content.
+pub fn offline_project() -> TestProject {
+    let mut project = test_project();
+    project.config.offline = true;
+    project
+}
+
 impl TestProject {
     /// Creates a file tree structure in the mock project's temporary directory.
     ///
@@ -2,6 +2,7 @@ use crate::{
     error::{Result, TenxError},
     events::{send_event, Event, EventSender},
 };
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use tokio::time::sleep;
@@ -9,7 +10,7 @@ use tokio::time::sleep;
 const BACKOFF_MULTIPLIER: f64 = 2.0;
 const MAX_BACKOFF_SECS: u64 = 60;
 
-#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, JsonSchema)]
 pub enum Throttle {
     /// Throttle for a specified number of seconds
     RetryAfter(u64),
@@ -0,0 +1,204 @@
+//! An append-only ledger of model usage, recording every call to a provider across all sessions
+//! and projects, so spend can be reported over time.
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    error::{Result, TenxError},
+};
+
+/// The name of the usage ledger in the session store directory.
+pub(crate) const USAGE_FILE: &str = "usage.jsonl";
+
+/// A single call to a model provider.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UsageRecord {
+    /// When the call completed, in seconds since the Unix epoch
+    pub time: u64,
+    /// The configured name of the model
+    pub model: String,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// The estimated cost in USD, or 0 if the model's pricing is unknown
+    pub cost: f64,
+    /// The tenx command that made the call, if known
+    pub command: String,
+    /// The root of the project the call was made for
+    pub project: String,
+}
+
+/// How to group usage records in a report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+pub enum UsageKey {
+    Model,
+    Project,
+    Command,
+}
+
+impl UsageKey {
+    fn of(self, record: &UsageRecord) -> &str {
+        match self {
+            UsageKey::Model => &record.model,
+            UsageKey::Project => &record.project,
+            UsageKey::Command => &record.command,
+        }
+    }
+}
+
+/// Usage totals for one group in a report.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+pub struct UsageTotal {
+    /// The model, project or command the totals are for
+    pub key: String,
+    pub calls: usize,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cost: f64,
+}
+
+/// Totals records made at or after `since`, in seconds since the Unix epoch, grouped by `key`.
+/// Groups are sorted by cost, most expensive first, and then by key.
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+pub fn report(records: &[UsageRecord], since: u64, key: UsageKey) -> Vec<UsageTotal> {
+    let mut totals: BTreeMap<&str, UsageTotal> = BTreeMap::new();
+    for record in records.iter().filter(|r| r.time >= since) {
+        let total = totals.entry(key.of(record)).or_insert_with(|| UsageTotal {
+            key: key.of(record).to_string(),
+            ..Default::default()
+        });
+        total.calls += 1;
+        total.input_tokens += record.input_tokens;
+        total.output_tokens += record.output_tokens;
+        total.cost += record.cost;
+    }
+    let mut totals: Vec<_> = totals.into_values().collect();
+    totals.sort_by(|a, b| b.cost.total_cmp(&a.cost).then_with(|| a.key.cmp(&b.key)));
+    totals
+}
+
+/// Returns the current time in seconds since the Unix epoch.
+#[cfg_attr(not(feature = "internal"), allow(dead_code))]
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// The usage ledger, a file of JSON records, one per line. Records are only ever appended.
+pub struct Ledger {
+    path: PathBuf,
+}
+
+impl Ledger {
+    /// Opens the ledger in a directory. The file is created when the first record is added.
+    pub fn open(dir: PathBuf) -> Self {
+        Self {
+            path: dir.join(USAGE_FILE),
+        }
+    }
+
+    /// Opens the ledger in the configured session store directory.
+    pub fn from_config(config: &Config) -> Self {
+        Self::open(config.session_store_dir.clone())
+    }
+
+    /// Appends a record to the ledger.
+    pub fn add(&self, record: &UsageRecord) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut line = serde_json::to_string(record)
+            .map_err(|e| TenxError::SessionStore(format!("serialization failed: {}", e)))?;
+        line.push('\n');
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    /// Reads all records in the ledger, oldest first.
+    #[cfg_attr(not(feature = "internal"), allow(dead_code))]
+    pub fn records(&self) -> Result<Vec<UsageRecord>> {
+        if !self.path.exists() {
+            return Ok(vec![]);
+        }
+        fs::read_to_string(&self.path)?
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| {
+                serde_json::from_str(l).map_err(|e| {
+                    TenxError::SessionStore(format!("Failed to parse usage ledger: {}", e))
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn record(time: u64, model: &str, project: &str, cost: f64) -> UsageRecord {
+        UsageRecord {
+            time,
+            model: model.into(),
+            input_tokens: 10,
+            output_tokens: 5,
+            cost,
+            command: "code".into(),
+            project: project.into(),
+        }
+    }
+
+    #[test]
+    fn test_ledger() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let ledger = Ledger::open(dir.path().join("state"));
+        assert!(ledger.records()?.is_empty());
+
+        let records = vec![
+            record(100, "sonnet", "/a", 0.5),
+            record(200, "haiku", "/a", 0.1),
+            record(300, "sonnet", "/b", 0.25),
+        ];
+        for r in &records {
+            ledger.add(r)?;
+        }
+        assert_eq!(ledger.records()?, records);
+
+        let by_model = report(&records, 0, UsageKey::Model);
+        assert_eq!(
+            by_model
+                .iter()
+                .map(|t| (t.key.as_str(), t.calls, t.cost))
+                .collect::<Vec<_>>(),
+            vec![("sonnet", 2, 0.75), ("haiku", 1, 0.1)]
+        );
+        assert_eq!(by_model[0].input_tokens, 20);
+
+        // Older records are left out
+        let by_project = report(&records, 150, UsageKey::Project);
+        assert_eq!(
+            by_project
+                .iter()
+                .map(|t| (t.key.as_str(), t.calls))
+                .collect::<Vec<_>>(),
+            vec![("/b", 1), ("/a", 1)]
+        );
+        Ok(())
+    }
+}
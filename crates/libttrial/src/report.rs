@@ -126,7 +126,7 @@ impl TrialReport {
             TenxError::Internal("Cannot create trial report from empty session".to_string())
         })?;
 
-        let model_name = model.model.clone();
+        let model_name = model.request.model.clone();
 
         let api_model = config
             .get_model_conf(&model_name)
@@ -141,14 +141,17 @@ impl TrialReport {
         let mut error_response_parse = 0;
         let mut error_other = 0;
 
-        let failed = session.last_step().and_then(|s| s.err.as_ref()).is_some();
+        let failed = session
+            .last_step()
+            .and_then(|s| s.outcome.err.as_ref())
+            .is_some();
         let mut total_response_time = 0.0;
         let mut words_received = 0;
         let mut steps = 0;
 
         for act in &session.actions {
             for step in &act.steps {
-                if let Some(err) = &step.err {
+                if let Some(err) = &step.outcome.err {
                     match err {
                         TenxError::Patch { .. } => error_patch += 1,
                         TenxError::Check { .. } => error_check += 1,
@@ -156,8 +159,9 @@ impl TrialReport {
                         _ => error_other += 1,
                     }
                 }
-                total_response_time += step.response_time.unwrap_or(0.0);
+                total_response_time += step.response.time.unwrap_or(0.0);
                 words_received += step
+                    .response
                     .model_response
                     .as_ref()
                     .and_then(|r| r.raw_response.as_ref())
@@ -6,6 +6,7 @@ use std::{
 };
 
 use crate::error::{Error, Result};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 /// A helper trait to convert a type into an AbsPath.
@@ -32,7 +33,9 @@ impl IntoAbsPath for &PathBuf {
 }
 
 /// A PathBuf wrapper that guarantees the enclosed path is absolute.
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[derive(
+    Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, JsonSchema,
+)]
 pub struct AbsPath(PathBuf);
 
 impl AbsPath {
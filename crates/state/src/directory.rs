@@ -1,8 +1,10 @@
 use std::{
     fs,
-    path::{absolute, Path, PathBuf},
+    io::Write,
+    path::{absolute, Component, Path, PathBuf},
 };
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::{
@@ -14,15 +16,29 @@ use super::files;
 use super::SubStore;
 
 /// A file system directory
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Directory {
     pub root: AbsPath,
     globs: Vec<String>,
+    /// The subtree files are listed from, relative to the root. Empty means the whole directory.
+    #[serde(default)]
+    scope: PathBuf,
 }
 
 impl Directory {
     pub fn new(root: AbsPath, globs: Vec<String>) -> Result<Self> {
-        Ok(Self { root, globs })
+        Ok(Self {
+            root,
+            globs,
+            scope: PathBuf::new(),
+        })
+    }
+
+    /// Only lists files in the subtree at `scope`, relative to the root. Files outside it can
+    /// still be read and written by path.
+    pub fn with_scope(mut self, scope: PathBuf) -> Self {
+        self.scope = scope;
+        self
     }
 
     /// Converts a path relative to the root directory to an absolute path
@@ -32,6 +48,49 @@ impl Directory {
             .map_err(|e| Error::Internal(format!("could not absolute {}: {}", p.display(), e)))
     }
 
+    /// Makes sure a path can't reach outside the root directory. Absolute paths, paths that climb
+    /// above the root with `..`, and paths that pass through a symlink pointing outside the root
+    /// are all refused. Paths come from the model, so we treat them as untrusted.
+    pub fn confine(&self, path: &Path) -> Result<()> {
+        let refuse = |why: &str| {
+            let msg = format!(
+                "Refusing to access {} outside the project root: {}",
+                why,
+                path.display()
+            );
+            Err(Error::Patch {
+                user: msg.clone(),
+                model: format!("{}. Paths must be relative to the project root.", msg),
+            })
+        };
+        let mut depth = 0usize;
+        for component in path.components() {
+            match component {
+                Component::Prefix(_) | Component::RootDir => return refuse("absolute path"),
+                Component::ParentDir if depth == 0 => return refuse("path"),
+                Component::ParentDir => depth -= 1,
+                Component::Normal(_) => depth += 1,
+                Component::CurDir => {}
+            }
+        }
+
+        // The path is lexically inside the root, but a symlink along the way could still lead
+        // out of it. Resolve the deepest part of the path that exists and compare.
+        let root = fs::canonicalize(&*self.root)?;
+        let full = root.join(path);
+        for ancestor in full.ancestors() {
+            if fs::symlink_metadata(ancestor).is_err() {
+                continue;
+            }
+            return match fs::canonicalize(ancestor) {
+                Ok(resolved) if resolved.starts_with(&root) => Ok(()),
+                Ok(_) => refuse("symlinked path"),
+                Err(_) => refuse("dangling symlink"),
+            };
+        }
+        Ok(())
+    }
+
     /// List files in the directory using ignore rules, returning all included files relative to
     /// project root.
     ///
@@ -40,7 +99,7 @@ impl Directory {
     ///
     /// Files are sorted by path.
     pub fn list(&self) -> Result<Vec<PathBuf>> {
-        files::list_files(self.root.clone(), self.globs.clone())
+        files::list_files_under(self.root.clone(), &self.scope, self.globs.clone())
     }
 
     /// Gets the content of a file by converting the input path to an absolute path and reading it.
@@ -53,24 +112,63 @@ impl Directory {
             });
         }
         fs::read_to_string(&abs_path).map_err(|e| {
-            Error::Internal(format!("Could not read file {}: {}", abs_path.display(), e))
+            if e.kind() == std::io::ErrorKind::InvalidData {
+                let msg = format!("{} is not UTF-8 text, and can't be edited", path.display());
+                Error::Patch {
+                    user: msg.clone(),
+                    model: msg,
+                }
+            } else {
+                Error::Internal(format!("Could not read file {}: {}", abs_path.display(), e))
+            }
         })
     }
 
-    /// Writes content to a file, creating it if it doesn't exist or overwriting if it does.
+    /// Writes content to a file, creating it if it doesn't exist or overwriting if it does. The
+    /// content goes to a temporary file in the same directory, which is synced and then renamed
+    /// over the target, so a failed write never leaves the file half-written. Existing files keep
+    /// their permissions, and writing through a symlink replaces the file it points to.
     pub fn write(&mut self, path: &Path, content: &str) -> Result<()> {
-        let abs_path = self.abspath(path)?;
-        if let Some(parent) = abs_path.parent() {
-            fs::create_dir_all(parent).map_err(|e| {
-                Error::Internal(format!(
-                    "Could not create directory {}: {}",
-                    parent.display(),
-                    e
-                ))
-            })?;
+        let mut abs_path = self.abspath(path)?;
+        let err = |e: std::io::Error| {
+            Error::Internal(format!("Could not write file {}: {}", path.display(), e))
+        };
+        if fs::symlink_metadata(&abs_path).is_ok_and(|m| m.file_type().is_symlink()) {
+            abs_path = fs::canonicalize(&abs_path).map_err(err)?;
         }
-        fs::write(&abs_path, content)
-            .map_err(|e| Error::Internal(format!("Could not write file {}: {}", path.display(), e)))
+        let parent = abs_path.parent().ok_or_else(|| {
+            Error::Internal(format!("No parent directory for {}", path.display()))
+        })?;
+        fs::create_dir_all(parent).map_err(|e| {
+            Error::Internal(format!(
+                "Could not create directory {}: {}",
+                parent.display(),
+                e
+            ))
+        })?;
+        let permissions = fs::metadata(&abs_path).map(|m| m.permissions()).ok();
+        let mut tmp = tempfile::Builder::new()
+            .prefix(".tenx-")
+            .suffix(".tmp")
+            .tempfile_in(parent)
+            .map_err(err)?;
+        tmp.write_all(content.as_bytes()).map_err(err)?;
+        tmp.as_file().sync_all().map_err(err)?;
+        match permissions {
+            Some(permissions) => tmp.as_file().set_permissions(permissions).map_err(err)?,
+            // Temporary files are private to the user, but new files should get the usual mode
+            #[cfg(unix)]
+            None => {
+                use std::os::unix::fs::PermissionsExt;
+                tmp.as_file()
+                    .set_permissions(fs::Permissions::from_mode(0o644))
+                    .map_err(err)?
+            }
+            #[cfg(not(unix))]
+            None => {}
+        }
+        tmp.persist(&abs_path).map_err(|e| err(e.error))?;
+        Ok(())
     }
 
     /// Removes a file by joining the given path with the root directory.
@@ -109,3 +207,86 @@ impl SubStore for Directory {
         self.remove(path)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let root = temp.path().join("project");
+        fs::create_dir_all(&root)?;
+        let mut dir = Directory::new(AbsPath::new(root.clone())?, vec![])?;
+
+        dir.write(Path::new("src/new.rs"), "new")?;
+        assert_eq!(fs::read_to_string(root.join("src/new.rs"))?, "new");
+
+        // Overwriting keeps the file's permissions, and leaves no temporary files behind
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::write(root.join("run.sh"), "old")?;
+            fs::set_permissions(root.join("run.sh"), fs::Permissions::from_mode(0o755))?;
+            dir.write(Path::new("run.sh"), "updated")?;
+            let meta = fs::metadata(root.join("run.sh"))?;
+            assert_eq!(meta.permissions().mode() & 0o777, 0o755);
+            assert_eq!(fs::read_to_string(root.join("run.sh"))?, "updated");
+        }
+        assert!(fs::read_dir(&root)?.all(|e| !e
+            .unwrap()
+            .file_name()
+            .to_string_lossy()
+            .starts_with(".tenx-")));
+        Ok(())
+    }
+
+    fn is_refused(dir: &Directory, path: &str) -> bool {
+        matches!(dir.confine(Path::new(path)), Err(Error::Patch { .. }))
+    }
+
+    #[test]
+    fn test_confine() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let root = temp.path().join("project");
+        fs::create_dir_all(root.join("src"))?;
+        fs::write(root.join("src/lib.rs"), "")?;
+        let dir = Directory::new(AbsPath::new(root.clone())?, vec![])?;
+
+        for path in [
+            "src/lib.rs",
+            "src/new/mod.rs",
+            "./new.rs",
+            "src/../lib.rs",
+            "a/b/../../c",
+        ] {
+            assert!(dir.confine(Path::new(path)).is_ok(), "{}", path);
+        }
+        for path in [
+            "../../.bashrc",
+            "..",
+            "src/../../escape.rs",
+            "a/../../escape.rs",
+            "./../escape.rs",
+            "/etc/passwd",
+        ] {
+            assert!(is_refused(&dir, path), "{}", path);
+        }
+        let abs = root.join("src/lib.rs");
+        assert!(is_refused(&dir, abs.to_str().unwrap()));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::symlink;
+            let outside = tempfile::tempdir()?;
+            symlink(outside.path(), root.join("out"))?;
+            symlink(root.join("src"), root.join("inside"))?;
+            symlink(outside.path().join("missing"), root.join("dangling"))?;
+            assert!(is_refused(&dir, "out/file.rs"));
+            assert!(is_refused(&dir, "out"));
+            assert!(is_refused(&dir, "dangling"));
+            assert!(dir.confine(Path::new("inside/lib.rs")).is_ok());
+        }
+        Ok(())
+    }
+}
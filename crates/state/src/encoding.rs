@@ -0,0 +1,134 @@
+//! Line ending and byte order mark conventions of text files.
+//!
+//! Models see and produce text with LF line endings and no BOM. When a patch edits a file, we
+//! detect the file's conventions, apply the change to the normalized text, and convert the result
+//! back, so files edited on Windows don't end up with mixed line endings. Lines the edit doesn't
+//! touch keep their own endings, so files that already mix them are left as they were.
+
+/// The UTF-8 byte order mark.
+const BOM: char = '\u{feff}';
+
+/// The line ending and BOM conventions of a text file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Encoding {
+    /// Lines end with CRLF rather than LF.
+    pub crlf: bool,
+    /// The file starts with a UTF-8 byte order mark.
+    pub bom: bool,
+}
+
+impl Encoding {
+    /// Detects the conventions of a file's content. A file uses CRLF line endings if most of its
+    /// lines end with CRLF.
+    pub fn detect(content: &str) -> Self {
+        let crlf = content.matches("\r\n").count();
+        let lf = content.matches('\n').count() - crlf;
+        Encoding {
+            crlf: crlf > lf,
+            bom: content.starts_with(BOM),
+        }
+    }
+
+    /// Strips the BOM and converts all line endings to LF, giving the text as models see it.
+    pub fn decode(&self, content: &str) -> String {
+        content
+            .strip_prefix(BOM)
+            .unwrap_or(content)
+            .replace("\r\n", "\n")
+    }
+
+    /// Converts normalized text back to these conventions. Any CRLF line endings in the text are
+    /// normalized first, so the result never has mixed line endings.
+    pub fn encode(&self, content: &str) -> String {
+        self.encode_edit("", content)
+    }
+
+    /// Converts edited text back to these conventions, given the file's content before the edit.
+    /// Lines before and after the edit keep the endings they had, so a file with mixed line
+    /// endings only changes where it was edited. Edited lines get the file's usual ending.
+    pub fn encode_edit(&self, before: &str, content: &str) -> String {
+        let old: Vec<&str> = before
+            .strip_prefix(BOM)
+            .unwrap_or(before)
+            .split_inclusive('\n')
+            .collect();
+        let text = self.decode(content);
+        let new: Vec<&str> = text.split_inclusive('\n').collect();
+        // Lines match if they're the same apart from their endings
+        let same = |a: &str, b: &str| match a.strip_suffix("\r\n") {
+            Some(body) => b.strip_suffix('\n') == Some(body),
+            None => a == b,
+        };
+        let prefix = old.iter().zip(&new).take_while(|(a, b)| same(a, b)).count();
+        let suffix = old[prefix..]
+            .iter()
+            .rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(a, b)| same(a, b))
+            .count();
+
+        let mut out = String::with_capacity(text.len() + text.len() / 32);
+        if self.bom {
+            out.push(BOM);
+        }
+        for (i, line) in new.iter().enumerate() {
+            let kept = if i < prefix {
+                Some(old[i])
+            } else if i >= new.len() - suffix {
+                Some(old[old.len() - (new.len() - i)])
+            } else {
+                None
+            };
+            match kept {
+                Some(line) => out.push_str(line),
+                None => match line.strip_suffix('\n') {
+                    Some(body) if self.crlf => {
+                        out.push_str(body);
+                        out.push_str("\r\n");
+                    }
+                    _ => out.push_str(line),
+                },
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoding() {
+        let lf = Encoding::detect("one\ntwo\n");
+        assert_eq!(lf, Encoding::default());
+        assert_eq!(lf.encode("one\r\ntwo\n"), "one\ntwo\n");
+
+        let win = Encoding::detect("\u{feff}one\r\ntwo\r\nthree\n");
+        assert_eq!(
+            win,
+            Encoding {
+                crlf: true,
+                bom: true
+            }
+        );
+        let text = win.decode("\u{feff}one\r\ntwo\r\nthree\n");
+        assert_eq!(text, "one\ntwo\nthree\n");
+        assert_eq!(win.encode(&text), "\u{feff}one\r\ntwo\r\nthree\r\n");
+        // Model output with stray CRLFs doesn't double up
+        assert_eq!(win.encode("a\r\nb\n"), "\u{feff}a\r\nb\r\n");
+
+        // Mixed files keep the endings of the lines an edit doesn't touch
+        let before = "one\r\ntwo\nthree\r\nfour\nfive\r\n";
+        let mixed = Encoding::detect(before);
+        assert!(mixed.crlf);
+        let edited = mixed.decode(before).replace("three", "3\n3.5");
+        assert_eq!(
+            mixed.encode_edit(before, &edited),
+            "one\r\ntwo\n3\r\n3.5\r\nfour\nfive\r\n"
+        );
+        assert_eq!(mixed.encode_edit(before, &mixed.decode(before)), before);
+        // Endings survive a change to the last line, which has none
+        assert_eq!(lf.encode_edit("a\r\nb\nc", "a\nb\nd"), "a\r\nb\nd");
+    }
+}
@@ -1,9 +1,10 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Error, Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+#[derive(Error, Debug, Serialize, Deserialize, Clone, Eq, PartialEq, JsonSchema)]
 pub enum Error {
     #[error("Path error: {0}")]
     Path(String),
@@ -1,9 +1,18 @@
 //! File and path manipulation for filesystem state.
-use std::path::PathBuf;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf, MAIN_SEPARATOR},
+    time::SystemTime,
+};
 
 use ignore::{overrides::OverrideBuilder, WalkBuilder};
 use path_clean;
 use pathdiff::diff_paths;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::xxh3_64;
 
 use super::abspath::IntoAbsPath;
 
@@ -60,6 +69,16 @@ where
 ///
 /// Files are sorted by path.
 pub fn list_files<R>(root: R, globs: Vec<String>) -> Result<Vec<PathBuf>>
+where
+    R: IntoAbsPath,
+{
+    list_files_under(root, Path::new(""), globs)
+}
+
+/// Like [`list_files`], but only walks the subtree at `scope`, a path relative to the root. Files
+/// are still returned relative to the root, and glob patterns and ignore files are still
+/// interpreted relative to the root.
+pub fn list_files_under<R>(root: R, scope: &Path, globs: Vec<String>) -> Result<Vec<PathBuf>>
 where
     R: IntoAbsPath,
 {
@@ -82,7 +101,7 @@ where
         .map_err(|e| Error::Path(format!("Failed to build override rules: {}", e)))?;
 
     // Build and configure the walker
-    let mut walker = WalkBuilder::new(&root);
+    let mut walker = WalkBuilder::new(root.join(scope));
     walker
         .hidden(false) // Don't skip hidden files
         .git_ignore(true) // Respect .gitignore
@@ -105,6 +124,133 @@ where
     Ok(files)
 }
 
+/// Render a path with forward slashes, regardless of the platform's separator. Paths shown to
+/// models go through this function, so prompts are the same on every platform.
+pub fn slash_path(path: &Path) -> String {
+    path.to_string_lossy().replace(MAIN_SEPARATOR, "/")
+}
+
+/// Convert a path written by a model to a `PathBuf`. Models sometimes use backslashes as
+/// separators, so these are converted to forward slashes, which all platforms accept.
+pub fn from_slash(path: &str) -> PathBuf {
+    PathBuf::from(path.replace('\\', "/"))
+}
+
+/// Returns a fast, non-cryptographic hash of file content, using XXH3. The hash is stable across
+/// runs and platforms, so it can be persisted.
+pub fn hash_content(content: &[u8]) -> u64 {
+    xxh3_64(content)
+}
+
+/// The content hash of a file, along with the metadata used to skip re-hashing files that haven't
+/// been touched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct FileHash {
+    pub hash: u64,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl FileHash {
+    /// Hashes a file, returning None if it doesn't exist.
+    fn read(path: &Path) -> Result<Option<Self>> {
+        let meta = match fs::metadata(path) {
+            Ok(m) => m,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let content = fs::read(path)?;
+        Ok(Some(FileHash {
+            hash: hash_content(&content),
+            len: meta.len(),
+            modified: meta.modified().ok(),
+        }))
+    }
+
+    /// Returns the current hash of a file, re-reading it only if its size or modification time
+    /// differ from this hash.
+    fn refresh(&self, path: &Path) -> Result<Option<Self>> {
+        match fs::metadata(path) {
+            Ok(meta) if meta.len() == self.len && meta.modified().ok() == self.modified => {
+                Ok(Some(*self))
+            }
+            Ok(_) => Self::read(path),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// An index of file content hashes, used to cheaply find the files that changed between two
+/// points in time. Paths are relative to the project root.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct HashIndex {
+    files: BTreeMap<PathBuf, FileHash>,
+}
+
+impl HashIndex {
+    /// Returns the indexed content hash of a file, if it's in the index.
+    pub fn get(&self, path: &Path) -> Option<u64> {
+        self.files.get(path).map(|f| f.hash)
+    }
+
+    /// Returns the indexed files.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.files.keys().cloned().collect()
+    }
+
+    /// Drops every file not in `paths` from the index.
+    pub fn retain(&mut self, paths: &[PathBuf]) {
+        self.files.retain(|p, _| paths.contains(p));
+    }
+
+    /// Hashes the given files and any files already in the index, returning the new index and
+    /// the sorted list of files whose content differs from the index. Files that were added or
+    /// removed count as changed.
+    fn rehash<R>(&self, root: R, paths: &[PathBuf]) -> Result<(Self, Vec<PathBuf>)>
+    where
+        R: IntoAbsPath,
+    {
+        let root = root.into_abs_path()?;
+        let mut all: Vec<&PathBuf> = self.files.keys().chain(paths).collect();
+        all.sort();
+        all.dedup();
+        let mut next = HashIndex::default();
+        let mut changed = vec![];
+        for path in all {
+            let current = match self.files.get(path) {
+                Some(h) => h.refresh(&root.join(path))?,
+                None => FileHash::read(&root.join(path))?,
+            };
+            if current.map(|c| c.hash) != self.get(path) {
+                changed.push(path.clone());
+            }
+            if let Some(c) = current {
+                next.files.insert(path.clone(), c);
+            }
+        }
+        Ok((next, changed))
+    }
+
+    /// Returns the files whose content differs from the index, without updating it.
+    pub fn changed<R>(&self, root: R, paths: &[PathBuf]) -> Result<Vec<PathBuf>>
+    where
+        R: IntoAbsPath,
+    {
+        Ok(self.rehash(root, paths)?.1)
+    }
+
+    /// Updates the index, returning the files whose content changed since the last update.
+    pub fn update<R>(&mut self, root: R, paths: &[PathBuf]) -> Result<Vec<PathBuf>>
+    where
+        R: IntoAbsPath,
+    {
+        let (next, changed) = self.rehash(root, paths)?;
+        *self = next;
+        Ok(changed)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -237,6 +383,54 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_slash_path() {
+        let p = Path::new("src").join("foo").join("bar.rs");
+        assert_eq!(slash_path(&p), "src/foo/bar.rs");
+        assert_eq!(from_slash("src\\foo/bar.rs"), p);
+        assert_eq!(
+            slash_path(&from_slash("src\\foo\\bar.rs")),
+            "src/foo/bar.rs"
+        );
+    }
+
+    #[test]
+    fn test_hash_index() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let root = AbsPath::new(temp_dir.path().to_path_buf())?;
+        fs::write(root.join("a.txt"), "one")?;
+        fs::write(root.join("b.txt"), "two")?;
+        let paths = vec![PathBuf::from("a.txt"), PathBuf::from("b.txt")];
+
+        let mut index = HashIndex::default();
+        assert_eq!(index.update(root.clone(), &paths)?, paths);
+        assert_eq!(index.get(Path::new("a.txt")), Some(hash_content(b"one")));
+        assert!(index.update(root.clone(), &paths)?.is_empty());
+
+        // A rewrite with the same content isn't a change
+        fs::write(root.join("a.txt"), "one")?;
+        fs::write(root.join("b.txt"), "three")?;
+        fs::write(root.join("c.txt"), "new")?;
+        let all = vec![PathBuf::from("c.txt")];
+        assert_eq!(
+            index.changed(root.clone(), &all)?,
+            vec![PathBuf::from("b.txt"), PathBuf::from("c.txt")]
+        );
+        assert_eq!(index.get(Path::new("b.txt")), Some(hash_content(b"two")));
+
+        index.update(root.clone(), &all)?;
+        fs::remove_file(root.join("a.txt"))?;
+        assert_eq!(
+            index.update(root.clone(), &[])?,
+            vec![PathBuf::from("a.txt")]
+        );
+        assert_eq!(
+            index.paths(),
+            vec![PathBuf::from("b.txt"), PathBuf::from("c.txt")]
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_list_files() -> Result<()> {
         let temp_dir = TempDir::new()?;
@@ -264,6 +458,12 @@ mod tests {
 
         assert_eq!(files, expected, "Files don't match expected list");
 
+        let files = list_files_under(root.clone(), Path::new("src"), vec!["*.rs".to_string()])?;
+        assert_eq!(
+            files,
+            vec![PathBuf::from("src/lib.rs"), PathBuf::from("src/main.rs")]
+        );
+
         Ok(())
     }
 }
@@ -10,21 +10,26 @@ mod error;
 mod memory;
 
 pub mod abspath;
+pub mod encoding;
 pub mod files;
 mod patch;
+pub mod syntax;
 
 pub use crate::error::*;
 pub use crate::patch::*;
 
 use std::{
-    collections::{BTreeSet, HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fmt::Debug,
     path::{Path, PathBuf},
 };
 
 use globset::Glob;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::encoding::Encoding;
+
 /// Prefix for in-memory files
 pub const MEM_PREFIX: &str = "::";
 
@@ -36,7 +41,7 @@ trait SubStore: Debug {
 }
 
 /// Information about a patch operation, including success/failure counts and any errors.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct PatchInfo {
     pub rollback_id: u64,
     pub succeeded: usize,
@@ -58,7 +63,7 @@ impl PatchInfo {
     }
 }
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 struct Snapshot {
     content: HashMap<PathBuf, String>,
     created: Vec<PathBuf>,
@@ -69,6 +74,15 @@ impl Snapshot {
         self.content.insert(path, content);
     }
 
+    /// The encoding of a file when the snapshot was taken. Files that didn't exist get the
+    /// default, LF without a BOM.
+    fn encoding(&self, path: &Path) -> Encoding {
+        self.content
+            .get(path)
+            .map(|c| Encoding::detect(c))
+            .unwrap_or_default()
+    }
+
     pub fn create(&mut self, path: PathBuf) {
         self.content.insert(path.clone(), String::new());
         self.created.push(path);
@@ -90,12 +104,15 @@ impl Snapshot {
 /// The state underlying a session. This is the set of resources that our models are editing. State
 /// presents a unified interface over an optional filesystem directory and a memory store.
 /// In-memory file names are prefixed with "::"
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 pub struct State {
     directory: Option<directory::Directory>,
     memory: memory::Memory,
     snapshots: Vec<(u64, Snapshot)>,
     next_snapshot_id: u64,
+    /// Files outside the directory's included set that were explicitly added with `touch_forced`.
+    #[serde(default)]
+    forced: BTreeSet<PathBuf>,
 }
 
 impl State {
@@ -179,6 +196,13 @@ impl State {
         Ok(self)
     }
 
+    /// Restricts the files listed from the directory to the subtree at `scope`, relative to the
+    /// directory root. Paths are still relative to the root. Has no effect without a directory.
+    pub fn with_scope<P: Into<PathBuf>>(mut self, scope: P) -> Self {
+        self.directory = self.directory.map(|d| d.with_scope(scope.into()));
+        self
+    }
+
     /// Initialize the state with pre-populated memory contents.
     ///
     /// This method takes a HashMap mapping file paths to their contents and
@@ -224,6 +248,24 @@ impl State {
         }
     }
 
+    /// Checks that a file can be edited as text, which means it either doesn't exist yet or is
+    /// valid UTF-8.
+    fn check_text(&self, path: &Path) -> Result<()> {
+        match self.read(path) {
+            Ok(_) | Err(Error::NotFound { .. }) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Checks that a path can't reach outside the directory store. In-memory paths are always
+    /// allowed.
+    pub fn confine(&self, path: &Path) -> Result<()> {
+        match &self.directory {
+            Some(dir) if !path.to_string_lossy().starts_with(MEM_PREFIX) => dir.confine(path),
+            _ => Ok(()),
+        }
+    }
+
     /// Retrieves the content associated with the given path.
     pub fn read(&self, path: &Path) -> Result<String> {
         self.dispatch_ro(path, |store| store.read(path))
@@ -234,6 +276,69 @@ impl State {
         self.dispatch_mut(path, |store| store.write(path, content))
     }
 
+    /// Reads a path, preferring content staged by a patch that's being applied. Staged content of
+    /// None means the patch removes the file.
+    fn read_staged(
+        &self,
+        staged: &BTreeMap<PathBuf, Option<String>>,
+        path: &Path,
+    ) -> Result<String> {
+        match staged.get(path) {
+            Some(Some(content)) => Ok(content.clone()),
+            Some(None) => Err(Error::NotFound {
+                msg: "File not found".to_string(),
+                path: path.display().to_string(),
+            }),
+            None => self.read(path),
+        }
+    }
+
+    /// Writes staged content to the stores, and removes files staged for removal, calling
+    /// `written` with each path as it's done. If this fails, the files already written are
+    /// restored from the snapshot taken before the patch, so the patch is applied entirely or not
+    /// at all.
+    fn commit(
+        &mut self,
+        snap: &Snapshot,
+        staged: BTreeMap<PathBuf, Option<String>>,
+        written: &mut dyn FnMut(&Path),
+    ) -> Result<()> {
+        let mut done: Vec<PathBuf> = Vec::new();
+        for (path, content) in staged {
+            let res = match content {
+                Some(content) => self.write(&path, &content),
+                // A file the patch created and then moved away was never written
+                None if snap.created.contains(&path) => continue,
+                None => self.remove(&path),
+            };
+            if let Err(e) = res {
+                let mut rollback_errors = Vec::new();
+                for path in done.iter().rev() {
+                    let res = match snap.content.get(path) {
+                        Some(original) if !snap.created.contains(path) => {
+                            self.write(path, original)
+                        }
+                        _ => self.remove(path),
+                    };
+                    if let Err(re) = res {
+                        rollback_errors.push(format!("{}: {}", path.display(), re));
+                    }
+                }
+                if rollback_errors.is_empty() {
+                    return Err(e);
+                }
+                return Err(Error::Internal(format!(
+                    "{}, and rolling back the patch failed for {}",
+                    e,
+                    rollback_errors.join(", ")
+                )));
+            }
+            written(&path);
+            done.push(path);
+        }
+        Ok(())
+    }
+
     /// Removes a file or memory entry for the given path.
     fn remove(&mut self, path: &Path) -> Result<()> {
         self.dispatch_mut(path, |store| store.remove(path))
@@ -257,7 +362,11 @@ impl State {
     /// Restores content for files or memory entries that existed and removes those that were created.
     fn revert_snapshot(&mut self, snapshot: Snapshot) -> Result<()> {
         for path in snapshot.created.iter() {
-            self.remove(path)?;
+            match self.remove(path) {
+                // The file may have been moved away since
+                Ok(()) | Err(Error::NotFound { .. }) => {}
+                Err(e) => return Err(e),
+            }
         }
         for (path, content) in snapshot.content.iter() {
             if !snapshot.created.contains(path) {
@@ -283,94 +392,262 @@ impl State {
     /// Applies a patch by taking a snapshot of all files to be modified, then attempts to apply each change in the patch.
     /// If any change fails, the error is collected in a vector of (change, error) tuples.
     /// Returns a tuple containing the snapshot ID and a vector of failed changes.
+    ///
+    /// Changes are staged in memory and only written once all of them have been applied. If
+    /// writing a file fails, the files already written are rolled back and the error is returned,
+    /// so the tree is never left half-patched.
     pub fn patch(&mut self, patch: &Patch) -> Result<PatchInfo> {
-        let snap = self.create_snapshot(&patch.affected_files())?;
+        self.patch_with(patch, |_, _, _| None)
+    }
+
+    /// Applies a patch like `patch`, then passes each file the patch wrote to `process`, along
+    /// with its content and whether the patch created it. If `process` returns new content, it
+    /// replaces the file's content. Processing is part of the patch's snapshot, so reverting the
+    /// patch reverts the processing too.
+    pub fn patch_with<F>(&mut self, patch: &Patch, process: F) -> Result<PatchInfo>
+    where
+        F: FnMut(&Path, &str, bool) -> Option<String>,
+    {
+        self.patch_observed(patch, process, |_| {})
+    }
+
+    /// Applies a patch like `patch_with`, calling `written` with each file as it's written to or
+    /// removed from the store, so that progress on large patches can be reported. Nothing is
+    /// written until the whole patch has been applied to staged copies of the files.
+    pub fn patch_observed<F, W>(
+        &mut self,
+        patch: &Patch,
+        mut process: F,
+        mut written: W,
+    ) -> Result<PatchInfo>
+    where
+        F: FnMut(&Path, &str, bool) -> Option<String>,
+        W: FnMut(&Path),
+    {
         let mut pinfo = PatchInfo {
             rollback_id: 0,
             succeeded: 0,
             should_continue: false,
             failures: Vec::new(),
         };
+        // Changes that would reach outside the directory, or that touch files we can't edit as
+        // text, are refused before we touch anything, including the snapshot, which reads and
+        // later restores every affected file.
+        let mut changes = Vec::new();
         for change in &patch.changes {
+            match change
+                .paths()
+                .into_iter()
+                .try_for_each(|p| self.confine(p).and_then(|_| self.check_text(p)))
+            {
+                Ok(()) => changes.push(change),
+                Err(e) => pinfo.add_failure(change.clone(), e)?,
+            }
+        }
+        let mut affected: BTreeSet<PathBuf> =
+            changes.iter().flat_map(|c| c.paths()).cloned().collect();
+        // Files that refer to a moved file are updated too, so they're part of the snapshot
+        let moves: Vec<&MoveFile> = changes
+            .iter()
+            .filter_map(|c| match c {
+                Change::Move(m) => Some(m),
+                _ => None,
+            })
+            .collect();
+        if !moves.is_empty() {
+            for path in self.list()? {
+                if !affected.contains(&path)
+                    && self.read(&path).is_ok_and(|text| {
+                        moves
+                            .iter()
+                            .any(|m| m.update_references(&path, &text).is_some())
+                    })
+                {
+                    affected.insert(path);
+                }
+            }
+        }
+        let snap = self.create_snapshot(&affected.iter().cloned().collect::<Vec<_>>())?;
+        // Changes are made to staged copies of the files, so that nothing on disk is touched
+        // until we know the whole patch can be applied.
+        let mut staged: BTreeMap<PathBuf, Option<String>> = BTreeMap::new();
+        let mut edited = BTreeSet::new();
+        for change in changes {
             match change {
+                // Edits are made to the normalized text the model sees, and written back with the
+                // file's original line endings and BOM.
                 Change::Write(write_file) => {
-                    if let Err(e) = self.write(write_file.path.as_path(), &write_file.content) {
-                        pinfo.add_failure(change.clone(), e)?;
-                    } else {
-                        pinfo.succeeded += 1;
-                    }
+                    let enc = snap.encoding(&write_file.path);
+                    let before = self
+                        .read_staged(&staged, &write_file.path)
+                        .unwrap_or_default();
+                    staged.insert(
+                        write_file.path.clone(),
+                        Some(enc.encode_edit(&before, &write_file.content)),
+                    );
+                    pinfo.succeeded += 1;
+                    edited.insert(write_file.path.clone());
                 }
+                // Large replacements may be applied in part, in which case the hunks that
+                // matched are kept and the rest are reported as a failure.
                 Change::ReplaceFuzzy(replace) => {
-                    let res = (|| {
-                        let original = self.read(replace.path.as_path())?;
-                        let new_content = replace.apply(&original)?;
-                        self.write(replace.path.as_path(), &new_content)
+                    let res = (|| -> Result<Option<Error>> {
+                        let enc = snap.encoding(&replace.path);
+                        let before = self.read_staged(&staged, &replace.path)?;
+                        let original = enc.decode(&before);
+                        let (new_content, partial) = replace.apply_partial(&original)?;
+                        staged.insert(
+                            replace.path.clone(),
+                            Some(enc.encode_edit(&before, &new_content)),
+                        );
+                        Ok(partial)
                     })();
-                    if let Err(e) = res {
-                        pinfo.add_failure(change.clone(), e)?;
-                    } else {
-                        pinfo.succeeded += 1;
+                    match res {
+                        Ok(None) => {
+                            pinfo.succeeded += 1;
+                            edited.insert(replace.path.clone());
+                        }
+                        Ok(Some(e)) => {
+                            edited.insert(replace.path.clone());
+                            pinfo.add_failure(change.clone(), e)?;
+                        }
+                        Err(e) => pinfo.add_failure(change.clone(), e)?,
                     }
                 }
                 Change::Replace(replace) => {
-                    let res = (|| {
-                        let original = self.read(replace.path.as_path())?;
+                    let res = (|| -> Result<()> {
+                        let enc = snap.encoding(&replace.path);
+                        let before = self.read_staged(&staged, &replace.path)?;
+                        let original = enc.decode(&before);
                         let new_content = replace.apply(&original)?;
-                        self.write(replace.path.as_path(), &new_content)
+                        staged.insert(
+                            replace.path.clone(),
+                            Some(enc.encode_edit(&before, &new_content)),
+                        );
+                        Ok(())
                     })();
                     if let Err(e) = res {
                         pinfo.add_failure(change.clone(), e)?;
                     } else {
                         pinfo.succeeded += 1;
+                        edited.insert(replace.path.clone());
                     }
                 }
                 Change::Insert(insert) => {
-                    let res = (|| {
-                        let original = self.read(insert.path.as_path())?;
+                    let res = (|| -> Result<()> {
+                        let enc = snap.encoding(&insert.path);
+                        let before = self.read_staged(&staged, &insert.path)?;
+                        let original = enc.decode(&before);
                         let new_content = insert.apply(&original)?;
-                        self.write(insert.path.as_path(), &new_content)
+                        staged.insert(
+                            insert.path.clone(),
+                            Some(enc.encode_edit(&before, &new_content)),
+                        );
+                        Ok(())
                     })();
                     if let Err(e) = res {
                         pinfo.add_failure(change.clone(), e)?;
                     } else {
                         pinfo.succeeded += 1;
+                        edited.insert(insert.path.clone());
                     }
                 }
                 Change::View(_) => {
                     pinfo.should_continue = true;
                     pinfo.succeeded += 1;
                 }
-                Change::Undo(path) => {
-                    let res = (|| {
-                        if let Some(previous_content) = self.last_original(path) {
-                            self.write(path, &previous_content)?;
-                            Ok(())
-                        } else {
-                            let msg =
-                                format!("No previous version found for undo: {}", path.display());
-                            Err(Error::Patch {
+                Change::Undo(path) => match self.last_original(path) {
+                    Some(previous_content) => {
+                        staged.insert(path.clone(), Some(previous_content));
+                        pinfo.succeeded += 1;
+                    }
+                    None => {
+                        let msg = format!("No previous version found for undo: {}", path.display());
+                        pinfo.add_failure(
+                            change.clone(),
+                            Error::Patch {
                                 user: msg.clone(),
                                 model: msg,
-                            })
-                        }
-                    })();
+                            },
+                        )?;
+                    }
+                },
+                Change::ViewRange(_path, _, _) => {
+                    pinfo.should_continue = true;
+                    pinfo.succeeded += 1;
+                }
+                Change::Move(move_file) => {
+                    let res = self.stage_move(&mut staged, &affected, move_file);
                     if let Err(e) = res {
                         pinfo.add_failure(change.clone(), e)?;
                     } else {
                         pinfo.succeeded += 1;
                     }
                 }
-                Change::ViewRange(_path, _, _) => {
-                    pinfo.should_continue = true;
-                    pinfo.succeeded += 1;
+            }
+        }
+        for path in edited {
+            // Files moved away later in the patch aren't processed
+            let content = match self.read_staged(&staged, &path) {
+                Ok(content) => content,
+                Err(Error::NotFound { .. }) => continue,
+                Err(e) => return Err(e),
+            };
+            if let Some(processed) = process(&path, &content, snap.created.contains(&path)) {
+                if processed != content {
+                    staged.insert(path, Some(processed));
                 }
             }
         }
+        self.commit(&snap, staged, &mut written)?;
         pinfo.rollback_id = self.push_snapshot(snap);
 
         Ok(pinfo)
     }
 
+    /// Stages a move, and the updates to the references to the moved file in `affected`. The
+    /// source must exist, and the destination must not.
+    fn stage_move(
+        &self,
+        staged: &mut BTreeMap<PathBuf, Option<String>>,
+        affected: &BTreeSet<PathBuf>,
+        move_file: &MoveFile,
+    ) -> Result<()> {
+        let content = match self.read_staged(staged, &move_file.from) {
+            Err(Error::NotFound { .. }) => {
+                return Err(Error::Patch {
+                    user: "File to move not found".to_string(),
+                    model: format!(
+                        "Can't move {}, the file doesn't exist",
+                        move_file.from.display()
+                    ),
+                })
+            }
+            r => r?,
+        };
+        if self.read_staged(staged, &move_file.to).is_ok() {
+            return Err(Error::Patch {
+                user: "Move destination already exists".to_string(),
+                model: format!(
+                    "Can't move {} to {}, the destination already exists",
+                    move_file.from.display(),
+                    move_file.to.display()
+                ),
+            });
+        }
+        staged.insert(move_file.to.clone(), Some(content));
+        staged.insert(move_file.from.clone(), None);
+        for path in affected {
+            let Ok(text) = self.read_staged(staged, path) else {
+                continue;
+            };
+            if let Some(updated) = move_file.update_references(path, &text) {
+                staged.insert(path.clone(), Some(updated));
+            }
+        }
+        Ok(())
+    }
+
     /// Reverts all snapshots up to and including the given ID in reverse order, then removes them from the snapshots list.
     pub fn revert(&mut self, id: u64) -> Result<()> {
         let mut to_revert = Vec::new();
@@ -392,6 +669,49 @@ impl State {
         Ok(())
     }
 
+    /// Reverts the snapshot with the given ID and all later ones, newest first, then removes them
+    /// from the snapshots list. Files are left as they were when the snapshot was taken, so
+    /// reverting to a step's rollback ID undoes that step and everything after it.
+    pub fn revert_from(&mut self, id: u64) -> Result<()> {
+        if !self.snapshots.iter().any(|(sid, _)| *sid == id) {
+            return Err(Error::Internal(format!("Snapshot id {} not found", id)));
+        }
+        while self.snapshots.last().is_some_and(|(sid, _)| *sid >= id) {
+            let (_id, snap) = self.snapshots.pop().expect("snapshot exists");
+            self.revert_snapshot(snap)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the content a file had just before the snapshot with the given ID was taken. This
+    /// comes from the earliest snapshot at or after the ID that affects the file. The outer option
+    /// is None if there's no such snapshot, meaning the file hasn't changed since. The inner option
+    /// is None if the file didn't exist.
+    pub fn content_before(&self, path: &Path, id: u64) -> Option<Option<String>> {
+        self.snapshots
+            .iter()
+            .filter(|(sid, _)| *sid >= id)
+            .find_map(|(_, snap)| {
+                if snap.created.iter().any(|p| p == path) {
+                    Some(None)
+                } else {
+                    snap.content.get(path).map(|c| Some(c.clone()))
+                }
+            })
+    }
+
+    /// Sets the content of a single file, or removes it if `content` is None. The change is
+    /// recorded in a new snapshot, so it can be reverted like a patch. Returns the snapshot ID.
+    pub fn restore(&mut self, path: &Path, content: Option<&str>) -> Result<u64> {
+        let snap = self.create_snapshot(&[path.to_path_buf()])?;
+        match content {
+            Some(content) => self.write(path, content)?,
+            None if !snap.created.is_empty() => {}
+            None => self.remove(path)?,
+        }
+        Ok(self.push_snapshot(snap))
+    }
+
     /// Lists all files from both the memory and directory stores.
     pub fn list(&self) -> Result<Vec<PathBuf>> {
         let mut files = self.memory.list()?;
@@ -477,6 +797,19 @@ impl State {
         Ok(files.into_iter().collect())
     }
 
+    /// Returns the files created by patches applied to this state that still exist.
+    pub fn created(&self) -> Vec<PathBuf> {
+        let created: BTreeSet<PathBuf> = self
+            .snapshots
+            .iter()
+            .flat_map(|(_, snap)| snap.created.iter().cloned())
+            .collect();
+        created
+            .into_iter()
+            .filter(|p| self.read(p).is_ok())
+            .collect()
+    }
+
     /// Returns the original content of a file from the first snapshot where it appears.
     /// If the file was created in the first snapshot, returns an empty string.
     /// If the file does not occur in any snapshot, returns None.
@@ -573,6 +906,59 @@ impl State {
         Ok((patch_info.rollback_id, file_count))
     }
 
+    /// Creates and dispatches a touch patch for explicit file paths, bypassing the directory's
+    /// include filtering. Paths are not expanded as globs, but must exist on disk under the
+    /// directory root. Paths that fall outside the included set are recorded as forced. Returns a
+    /// tuple of (snapshot ID, file count) from applying the patch.
+    pub fn touch_forced<P>(&mut self, cwd: P, paths: Vec<String>) -> Result<(u64, usize)>
+    where
+        P: abspath::IntoAbsPath,
+    {
+        let cwd = cwd.into_abs_path()?;
+        let dir = self
+            .directory
+            .as_ref()
+            .ok_or_else(|| Error::Path("No directory to force files from".to_string()))?;
+        let included = dir.list()?;
+        let mut resolved = BTreeSet::new();
+        for path in &paths {
+            if path.starts_with(MEM_PREFIX) {
+                return Err(Error::Path(format!(
+                    "Can not force in-memory file: {}",
+                    path
+                )));
+            }
+            let normalized = files::normalize_path(dir.root.clone(), cwd.clone(), path)?;
+            if !PathBuf::from(&*dir.root).join(&normalized).is_file() {
+                return Err(Error::NotFound {
+                    msg: "File not found".to_string(),
+                    path: normalized.display().to_string(),
+                });
+            }
+            resolved.insert(normalized);
+        }
+        for path in &resolved {
+            if !included.contains(path) {
+                self.forced.insert(path.clone());
+            }
+        }
+        let file_count = resolved.len();
+        let changes: Vec<Change> = resolved.into_iter().map(patch::Change::View).collect();
+        let patch_info = self.patch(&Patch { changes })?;
+        debug_assert!(patch_info.failures.is_empty());
+        Ok((patch_info.rollback_id, file_count))
+    }
+
+    /// Returns true if the path was added with `touch_forced` from outside the included set.
+    pub fn is_forced(&self, path: &Path) -> bool {
+        self.forced.contains(path)
+    }
+
+    /// Returns all paths added with `touch_forced` from outside the included set.
+    pub fn forced(&self) -> Vec<PathBuf> {
+        self.forced.iter().cloned().collect()
+    }
+
     /// Add an empty patch to the snapshot sequence and return a snapshot ID. Useful as a markder.
     pub fn mark(&mut self) -> Result<u64> {
         let patch = Patch { changes: vec![] };
@@ -949,6 +1335,22 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_revert_from() -> Result<()> {
+        let mut state = State::default();
+        let key = Path::new("::a.txt");
+        state.write(key, "A0")?;
+        for content in ["A1", "A2", "A3"] {
+            state.patch(&Patch::default().with_write(key, content))?;
+        }
+        state.revert_from(2)?;
+        assert_eq!(state.read(key)?, "A2");
+        assert!(state.revert_from(2).is_err());
+        state.revert_from(0)?;
+        assert_eq!(state.read(key)?, "A0");
+        Ok(())
+    }
+
     #[test]
     fn test_find() {
         // Helper function to create an assertion function for find
@@ -1310,6 +1712,246 @@ mod tests {
         StateTest::run_tests(test_cases);
     }
 
+    #[test]
+    fn test_patch_with() -> Result<()> {
+        let mut state = State::default();
+        state.write(Path::new("::a.txt"), "A  \n")?;
+        state.write(Path::new("::b.txt"), "B  \n")?;
+
+        let mut seen = vec![];
+        let info = state.patch_with(
+            &Patch::default()
+                .with_write("::a.txt", "A1  \n")
+                .with_write("::c.txt", "C  \n"),
+            |path, content, created| {
+                seen.push((path.to_path_buf(), created));
+                Some(content.replace("  ", ""))
+            },
+        )?;
+        assert_eq!(
+            seen,
+            vec![("::a.txt".into(), false), ("::c.txt".into(), true)]
+        );
+        assert_eq!(state.read(Path::new("::a.txt"))?, "A1\n");
+        assert_eq!(state.read(Path::new("::c.txt"))?, "C\n");
+        // Files the patch didn't write are left alone
+        assert_eq!(state.read(Path::new("::b.txt"))?, "B  \n");
+
+        // Reverting the patch reverts the processing
+        state.revert(info.rollback_id)?;
+        assert_eq!(state.read(Path::new("::a.txt"))?, "A  \n");
+        assert!(state.read(Path::new("::c.txt")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_patch_observed() -> Result<()> {
+        let mut state =
+            State::default().with_memory(HashMap::from([("::a.txt".into(), "A".to_string())]))?;
+        let mut written = vec![];
+        state.patch_observed(
+            &Patch::default()
+                .with_write("::b.txt", "B")
+                .with_write("::a.txt", "A1")
+                .with_view("::a.txt"),
+            |_, _, _| None,
+            |path| written.push(path.to_path_buf()),
+        )?;
+        assert_eq!(
+            written,
+            vec![PathBuf::from("::a.txt"), PathBuf::from("::b.txt")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_patch_outside_root() -> Result<()> {
+        let temp = TempDir::new()?;
+        let root = temp.path().join("project");
+        std::fs::create_dir_all(&root)?;
+        let mut state = State::default().with_directory(AbsPath::new(root)?, vec![])?;
+
+        let info = state.patch(
+            &Patch::default()
+                .with_write("../escape.txt", "bad")
+                .with_write("inside.txt", "good"),
+        )?;
+        assert_eq!(info.succeeded, 1);
+        assert_eq!(info.failures.len(), 1);
+        assert!(info.failures[0]
+            .1
+            .to_string()
+            .contains("outside the project root"));
+        assert!(!temp.path().join("escape.txt").exists());
+        assert_eq!(state.read(Path::new("inside.txt"))?, "good");
+
+        // Reverting doesn't touch the refused path
+        state.revert(info.rollback_id)?;
+        assert!(state.read(Path::new("inside.txt")).is_err());
+        assert!(!temp.path().join("escape.txt").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_patch_encodings() -> Result<()> {
+        let temp = TempDir::new()?;
+        let mut state =
+            State::default().with_directory(AbsPath::new(temp.path().into())?, vec![])?;
+        std::fs::write(temp.path().join("win.txt"), "\u{feff}one\r\ntwo\r\n")?;
+        std::fs::write(temp.path().join("bin.dat"), [0xff, 0xfe, 0x00])?;
+
+        let info = state.patch(
+            &Patch::default()
+                .with_replace("win.txt", "one\ntwo", "one\ntwo\nthree")
+                .with_write("new.txt", "a\r\nb\n")
+                .with_write("bin.dat", "text"),
+        )?;
+        assert_eq!(info.succeeded, 2);
+        assert!(info.failures[0].1.to_string().contains("not UTF-8"));
+        let read = |p: &str| std::fs::read(temp.path().join(p)).unwrap();
+        assert_eq!(
+            read("win.txt"),
+            "\u{feff}one\r\ntwo\r\nthree\r\n".as_bytes()
+        );
+        assert_eq!(read("new.txt"), b"a\nb\n");
+        assert_eq!(read("bin.dat"), [0xff, 0xfe, 0x00]);
+
+        state.revert(info.rollback_id)?;
+        assert_eq!(read("win.txt"), "\u{feff}one\r\ntwo\r\n".as_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn test_patch_rollback() -> Result<()> {
+        let temp = TempDir::new()?;
+        let mut state =
+            State::default().with_directory(AbsPath::new(temp.path().into())?, vec![])?;
+        std::fs::write(temp.path().join("a.txt"), "A")?;
+        // A file where the patch needs a directory, so the last write fails
+        std::fs::write(temp.path().join("b"), "B")?;
+
+        let res = state.patch(
+            &Patch::default()
+                .with_write("a.txt", "A1")
+                .with_write("a_new.txt", "new")
+                .with_write("b/c.txt", "C"),
+        );
+        assert!(matches!(res, Err(Error::Internal(_))));
+        // Everything the patch wrote before the failure is rolled back
+        assert_eq!(std::fs::read_to_string(temp.path().join("a.txt"))?, "A");
+        assert!(!temp.path().join("a_new.txt").exists());
+        assert_eq!(std::fs::read_to_string(temp.path().join("b"))?, "B");
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore() -> Result<()> {
+        let mut state = State::default().with_memory(HashMap::from([
+            ("::a.txt".into(), "A0".to_string()),
+            ("::b.txt".into(), "B0".to_string()),
+        ]))?;
+        let a = Path::new("::a.txt");
+        let start = state.mark()?;
+        state.patch(&Patch::default().with_write("::a.txt", "A1"))?;
+        let mid = state.mark()?;
+        state.patch(
+            &Patch::default()
+                .with_write("::a.txt", "A2")
+                .with_write("::b.txt", "B2")
+                .with_write("::c.txt", "C2"),
+        )?;
+
+        assert_eq!(state.content_before(a, start), Some(Some("A0".into())));
+        assert_eq!(state.content_before(a, mid), Some(Some("A1".into())));
+        assert_eq!(
+            state.content_before(Path::new("::c.txt"), start),
+            Some(None)
+        );
+        let end = state.mark()?;
+        assert_eq!(state.content_before(a, end), None);
+
+        // Restoring one file leaves the others alone, and is recorded in a snapshot
+        let id = state.restore(a, Some("A0"))?;
+        assert_eq!(state.read(a)?, "A0");
+        assert_eq!(state.read(Path::new("::b.txt"))?, "B2");
+        assert_eq!(state.content_before(a, id), Some(Some("A2".into())));
+        state.restore(Path::new("::c.txt"), None)?;
+        assert!(state.read(Path::new("::c.txt")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_move() -> Result<()> {
+        let mut state = State::default().with_memory(HashMap::from([
+            ("::src/util.rs".into(), "pub fn f() {}".to_string()),
+            (
+                "::src/lib.rs".into(),
+                "mod util;\nuse util::f;\n".to_string(),
+            ),
+            ("::src/other.rs".into(), "fn g() {}".to_string()),
+            ("::notes.txt".into(), "mod util;\n".to_string()),
+        ]))?;
+        let read = |state: &State, p: &str| state.read(Path::new(p));
+
+        let info = state.patch(&Patch::default().with_move("::src/util.rs", "::src/helpers.rs"))?;
+        assert!(info.failures.is_empty());
+        assert!(read(&state, "::src/util.rs").is_err());
+        assert_eq!(read(&state, "::src/helpers.rs")?, "pub fn f() {}");
+        assert_eq!(
+            read(&state, "::src/lib.rs")?,
+            "mod helpers;\nuse helpers::f;\n"
+        );
+        assert_eq!(read(&state, "::src/other.rs")?, "fn g() {}");
+        assert_eq!(read(&state, "::notes.txt")?, "mod util;\n");
+
+        // Reverting the move puts back the file and its references
+        state.revert(info.rollback_id)?;
+        assert_eq!(read(&state, "::src/util.rs")?, "pub fn f() {}");
+        assert!(read(&state, "::src/helpers.rs").is_err());
+        assert_eq!(read(&state, "::src/lib.rs")?, "mod util;\nuse util::f;\n");
+
+        // Moving a missing file, or onto an existing one, fails
+        let info = state.patch(
+            &Patch::default()
+                .with_move("::src/missing.rs", "::src/x.rs")
+                .with_move("::src/util.rs", "::src/other.rs"),
+        )?;
+        assert_eq!(info.failures.len(), 2);
+        assert_eq!(read(&state, "::src/util.rs")?, "pub fn f() {}");
+        assert_eq!(read(&state, "::src/other.rs")?, "fn g() {}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_touch_forced() -> Result<()> {
+        let temp_dir = TempDir::new().expect("failed to create temporary directory");
+        let root = AbsPath::new(temp_dir.path().to_path_buf())?;
+        std::fs::write(temp_dir.path().join("a.rs"), "A").unwrap();
+        std::fs::write(temp_dir.path().join("b.txt"), "B").unwrap();
+        let mut state = State::default().with_directory(root.clone(), vec!["*.rs".to_string()])?;
+
+        // Excluded files can't be touched normally
+        assert_eq!(state.touch(root.clone(), vec!["b.txt".to_string()])?.1, 0);
+
+        let (_, count) =
+            state.touch_forced(root.clone(), vec!["b.txt".to_string(), "a.rs".to_string()])?;
+        assert_eq!(count, 2);
+        assert!(state.is_forced(Path::new("b.txt")));
+        assert!(!state.is_forced(Path::new("a.rs")));
+        assert_eq!(state.forced(), vec![PathBuf::from("b.txt")]);
+
+        state.patch(&Patch::default().with_write("b.txt", "B1"))?;
+        assert_eq!(state.read(Path::new("b.txt"))?, "B1");
+
+        assert!(state
+            .touch_forced(root.clone(), vec!["missing.txt".to_string()])
+            .is_err());
+        assert!(state
+            .touch_forced(root, vec!["::mem.txt".to_string()])
+            .is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_diff_path() {
         // Test diff_path directly without relying on the StateTest framework
@@ -1,11 +1,12 @@
 use std::{collections::HashMap, path::PathBuf};
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use super::SubStore;
 use crate::error::{Error, Result};
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Memory {
     memory: HashMap<PathBuf, String>,
 }
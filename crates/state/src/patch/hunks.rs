@@ -0,0 +1,229 @@
+//! Hunk splitting for large replacements. Models often reproduce a large block of text with some
+//! drift - a reworded comment, a reformatted line - so the block as a whole doesn't match. We
+//! diff the old text against the new to find the hunks that actually change, each with a little
+//! context, and match each hunk on its own, tolerating a few differing context lines. The lines a
+//! hunk removes always have to match, so drift never deletes text the model didn't see. Hunks that
+//! match are applied, and the ones that don't are reported individually.
+use diffy::{DiffOptions, Line};
+
+/// Replacements with fewer old lines than this aren't split.
+const MIN_SPLIT_LINES: usize = 8;
+
+/// Lines of context kept around each hunk.
+const CONTEXT_LINES: usize = 2;
+
+/// The fraction of a hunk's old lines that have to match the file, ignoring leading and trailing
+/// whitespace. Only context lines may differ.
+const SIMILARITY: f64 = 0.75;
+
+/// A hunk that couldn't be matched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FailedHunk {
+    /// The 1-based index of the hunk
+    pub index: usize,
+    /// The 1-based range of lines in the old text the hunk covers
+    pub lines: (usize, usize),
+    /// The old text of the hunk
+    pub old: String,
+    /// Why the hunk couldn't be matched
+    pub reason: &'static str,
+}
+
+/// The result of applying a replacement hunk by hunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct HunkResult {
+    /// The input with the matched hunks applied
+    pub content: String,
+    /// The total number of hunks
+    pub total: usize,
+    /// The hunks that couldn't be matched, and weren't applied
+    pub failed: Vec<FailedHunk>,
+}
+
+fn trim_newline(s: &str) -> &str {
+    s.strip_suffix('\n').unwrap_or(s)
+}
+
+/// Finds the unique position in `haystack` where `needle` matches with at least the required
+/// similarity. Each needle line is paired with whether it must match, as deleted lines must.
+fn find(haystack: &[&str], needle: &[(&str, bool)]) -> std::result::Result<usize, &'static str> {
+    let n = needle.len();
+    if n == 0 || haystack.len() < n {
+        return Err("could not be found");
+    }
+    let scores: Vec<usize> = (0..=haystack.len() - n)
+        .map(|start| {
+            let matches = needle
+                .iter()
+                .zip(&haystack[start..start + n])
+                .map(|((a, required), b)| (a.trim() == b.trim(), *required));
+            if matches
+                .clone()
+                .any(|(matched, required)| required && !matched)
+            {
+                0
+            } else {
+                matches.filter(|(matched, _)| *matched).count()
+            }
+        })
+        .collect();
+    let best = scores.iter().copied().max().unwrap_or_default();
+    if (best as f64) < SIMILARITY * n as f64 {
+        return Err("could not be found");
+    }
+    let mut at = scores.iter().enumerate().filter(|(_, s)| **s == best);
+    match (at.next(), at.next()) {
+        (Some((pos, _)), None) => Ok(pos),
+        _ => Err("matches more than one place"),
+    }
+}
+
+/// Replaces `old` with `new` in `input` hunk by hunk. Returns None if `old` is too small to split,
+/// or no hunk matched.
+pub(crate) fn replace_hunks(input: &str, old: &str, new: &str) -> Option<HunkResult> {
+    if old.lines().count() < MIN_SPLIT_LINES {
+        return None;
+    }
+    let patch = DiffOptions::new()
+        .set_context_len(CONTEXT_LINES)
+        .create_patch(old, new);
+    let input_lines: Vec<&str> = input.lines().collect();
+    let mut out: Vec<&str> = vec![];
+    let mut cursor = 0;
+    let mut failed = vec![];
+    for (i, hunk) in patch.hunks().iter().enumerate() {
+        let old_side: Vec<(&str, bool)> = hunk
+            .lines()
+            .iter()
+            .filter_map(|l| match l {
+                Line::Context(t) => Some((trim_newline(t), false)),
+                Line::Delete(t) => Some((trim_newline(t), true)),
+                Line::Insert(_) => None,
+            })
+            .collect();
+        let start = match find(&input_lines[cursor..], &old_side) {
+            Ok(pos) => cursor + pos,
+            Err(reason) => {
+                let range = hunk.old_range();
+                failed.push(FailedHunk {
+                    index: i + 1,
+                    lines: (range.start(), range.start() + range.len().max(1) - 1),
+                    old: old_side
+                        .iter()
+                        .map(|(line, _)| *line)
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                    reason,
+                });
+                continue;
+            }
+        };
+        out.extend(&input_lines[cursor..start]);
+        // Context lines are kept as they are in the file, since they may have drifted
+        let mut pos = start;
+        for line in hunk.lines() {
+            match line {
+                Line::Context(_) => {
+                    out.push(input_lines[pos]);
+                    pos += 1;
+                }
+                Line::Delete(_) => pos += 1,
+                Line::Insert(t) => out.push(trim_newline(t)),
+            }
+        }
+        cursor = pos;
+    }
+    let total = patch.hunks().len();
+    if failed.len() == total {
+        return None;
+    }
+    out.extend(&input_lines[cursor..]);
+    let mut content = out.join("\n");
+    if input.ends_with('\n') {
+        content.push('\n');
+    }
+    Some(HunkResult {
+        content,
+        total,
+        failed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    const INPUT: &str = indoc! {"
+        fn one() {
+            // The first function
+            let a = 1;
+            a + 1
+        }
+
+        fn two() {
+            // The second function
+            let b = 2;
+            b + 2
+        }
+    "};
+
+    #[test]
+    fn test_replace_hunks() {
+        // The model's copy has drifted in a comment, and changes both functions
+        let old = indoc! {"
+            fn one() {
+                // The first fn
+                let a = 1;
+                a + 1
+            }
+
+            fn two() {
+                // The second function
+                let b = 2;
+                b + 2
+            }
+        "};
+        let new = old
+            .replace("let a = 1;", "let a = 10;")
+            .replace("let b = 2;", "let b = 20;");
+        let result = replace_hunks(INPUT, old, &new).unwrap();
+        assert_eq!(result.total, 2);
+        assert!(result.failed.is_empty());
+        assert_eq!(
+            result.content,
+            INPUT
+                .replace("let a = 1;", "let a = 10;")
+                .replace("let b = 2;", "let b = 20;")
+        );
+
+        // A deleted line that differs from the file doesn't match, even if the rest of the hunk
+        // does, so code the model didn't see is never removed
+        let changed = INPUT
+            .replace("let a = 1;", "let a = 10;")
+            .replace("let b = 2;", "let b = 20;");
+        let drifted = INPUT.replace("let a = 1;", "let a = compute(x);");
+        let result = replace_hunks(&drifted, INPUT, &changed).unwrap();
+        assert_eq!(result.content, drifted.replace("let b = 2;", "let b = 20;"));
+        assert_eq!(result.failed.len(), 1);
+        assert_eq!(result.failed[0].index, 1);
+
+        // One hunk has drifted too far to match, so only the other is applied
+        let old = old.replace("    b + 2\n}", "    b * 2 + 0\n}").replace(
+            "    // The second function\n    let b = 2;",
+            "    // Second\n    let b = 2;",
+        );
+        let new = old
+            .replace("let a = 1;", "let a = 10;")
+            .replace("let b = 2;", "let b = 20;");
+        let result = replace_hunks(INPUT, &old, &new).unwrap();
+        assert_eq!(result.content, INPUT.replace("let a = 1;", "let a = 10;"));
+        assert_eq!(result.failed.len(), 1);
+        assert_eq!(result.failed[0].index, 2);
+        assert_eq!(result.failed[0].reason, "could not be found");
+
+        // Small replacements aren't split
+        assert!(replace_hunks(INPUT, "fn one() {", "fn uno() {").is_none());
+    }
+}
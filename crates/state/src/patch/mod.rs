@@ -1,11 +1,15 @@
 //! Patch operations that modify state. View operations are also included here, which lets us
 //! sequence them with other operations.
+mod hunks;
 mod insert;
+mod move_file;
 mod replace;
 mod replace_fuzzy;
+mod structural;
 mod write;
 
 pub use insert::*;
+pub use move_file::*;
 pub use replace::*;
 pub use replace_fuzzy::*;
 pub use write::*;
@@ -13,13 +17,14 @@ pub use write::*;
 use std::collections::HashMap;
 use std::path::PathBuf;
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use unirend::{Detail, Render};
 
 use crate::error::Result;
 
 /// A change to be applied to the state.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
 pub enum Change {
     /// Write or create a complete file.
     Write(write::WriteFile),
@@ -43,6 +48,9 @@ pub enum Change {
     /// Undo reverts a single file to its previous state. Note that this adds a new snapshot entry,
     /// so undoing twice gets you back to the original state.       
     Undo(PathBuf),
+
+    /// Move a file, updating references to it in the other files in the state.
+    Move(move_file::MoveFile),
 }
 
 impl Change {
@@ -56,10 +64,11 @@ impl Change {
             Change::View(_) => "view",
             Change::ViewRange(_, _, _) => "view_range",
             Change::Undo(_) => "undo",
+            Change::Move(_) => "move",
         }
     }
 
-    /// Returns the path of the file affected by this change.
+    /// Returns the path of the file affected by this change. For a move, this is the destination.
     pub fn path(&self) -> &PathBuf {
         match self {
             Change::Write(write_file) => &write_file.path,
@@ -69,6 +78,16 @@ impl Change {
             Change::View(path) => path,
             Change::ViewRange(path, _, _) => path,
             Change::Undo(path) => path,
+            Change::Move(move_file) => &move_file.to,
+        }
+    }
+
+    /// Returns the paths of all files named by this change, including the source of a move. Files
+    /// whose references a move updates are only known when the patch is applied.
+    pub fn paths(&self) -> Vec<&PathBuf> {
+        match self {
+            Change::Move(move_file) => vec![&move_file.from, &move_file.to],
+            _ => vec![self.path()],
         }
     }
 
@@ -131,6 +150,15 @@ impl Change {
                 renderer.para(&format!("undo changes to: {}", path_str));
                 renderer.pop();
             }
+            Change::Move(move_file) => {
+                renderer.push("move");
+                renderer.para(&format!(
+                    "move {} to {}",
+                    move_file.from.to_string_lossy(),
+                    move_file.to.to_string_lossy()
+                ));
+                renderer.pop();
+            }
             Change::ViewRange(path, start, end) => {
                 let path_str = path.to_string_lossy();
                 renderer.push("view_range");
@@ -150,7 +178,7 @@ impl Change {
 }
 
 /// A unified collection of Change operations, to be applied as a single patch.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default, JsonSchema)]
 pub struct Patch {
     pub changes: Vec<Change>,
 }
@@ -263,11 +291,24 @@ impl Patch {
         self
     }
 
+    /// Adds a Move change to the patch
+    pub fn with_move<P1, P2>(mut self, from: P1, to: P2) -> Self
+    where
+        P1: AsRef<std::path::Path>,
+        P2: AsRef<std::path::Path>,
+    {
+        self.changes.push(Change::Move(MoveFile {
+            from: from.as_ref().to_path_buf(),
+            to: to.as_ref().to_path_buf(),
+        }));
+        self
+    }
+
     /// Returns a vector of unique PathBufs for all files changed in the patch.
     pub fn affected_files(&self) -> Vec<PathBuf> {
         let mut paths = HashMap::new();
-        for change in &self.changes {
-            paths.insert(change.path().clone(), ());
+        for path in self.changes.iter().flat_map(|c| c.paths()) {
+            paths.insert(path.clone(), ());
         }
         paths.into_keys().collect()
     }
@@ -348,17 +389,20 @@ mod tests {
             .with_replace("file3.txt", "old", "new")
             .with_insert("file6.txt", 3, "inserted content")
             .with_view("file4.txt")
-            .with_undo("file5.txt");
+            .with_undo("file5.txt")
+            .with_move("file7.txt", "file8.txt");
 
-        assert_eq!(patch.changes.len(), 6);
+        assert_eq!(patch.changes.len(), 7);
 
         let changed_files = patch.affected_files();
-        assert_eq!(changed_files.len(), 6);
+        assert_eq!(changed_files.len(), 8);
         assert!(changed_files.contains(&PathBuf::from("file1.txt")));
         assert!(changed_files.contains(&PathBuf::from("file2.txt")));
         assert!(changed_files.contains(&PathBuf::from("file3.txt")));
         assert!(changed_files.contains(&PathBuf::from("file4.txt")));
         assert!(changed_files.contains(&PathBuf::from("file5.txt")));
         assert!(changed_files.contains(&PathBuf::from("file6.txt")));
+        assert!(changed_files.contains(&PathBuf::from("file7.txt")));
+        assert!(changed_files.contains(&PathBuf::from("file8.txt")));
     }
 }
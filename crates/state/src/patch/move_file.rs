@@ -0,0 +1,420 @@
+//! Moving a file, and updating the references to it in other source files. References are found
+//! textually rather than by parsing, and only where they must name the moved file:
+//!
+//! - the file's path, anywhere in a source file
+//! - in Rust, the file's module path from the crate root, like `crate::a::util`, and `super::util`
+//!   in its sibling modules. In its parent module, `mod util;`, `self::util` and bare `util::`
+//!   paths are updated too, since the module is in scope there by name.
+//! - in Python, the module's dotted name in an `import` or `from ... import` statement
+//!
+//! Anything these rules miss is left for the checks run after the patch to catch.
+use std::path::{Component, Path, PathBuf};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Extensions of the source files we update references in.
+const SOURCE_EXTENSIONS: &[&str] = &[
+    "rs", "py", "pyi", "js", "jsx", "mjs", "cjs", "ts", "tsx", "go", "c", "h", "cc", "cpp", "hpp",
+    "java", "kt", "rb", "swift",
+];
+
+/// Moves a file, updating references to it in the other files in the state.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+pub struct MoveFile {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+impl MoveFile {
+    /// Returns `text`, the contents of the file at `path`, with its references to the moved file
+    /// updated, or None if it has none. Files that aren't source files are never changed.
+    pub fn update_references(&self, path: &Path, text: &str) -> Option<String> {
+        if !has_extension(path, SOURCE_EXTENSIONS) {
+            return None;
+        }
+        let (old_path, new_path) = (slash(&self.from), slash(&self.to));
+        let rust = has_extension(path, &["rs"])
+            .then(|| RustMove::new(&self.from, &self.to, path))
+            .flatten();
+        let python = has_extension(path, &["py", "pyi"])
+            .then(|| PythonMove::new(&self.from, &self.to))
+            .flatten();
+        let mut changed = false;
+        let lines: Vec<String> = text
+            .split('\n')
+            .map(|line| {
+                let mut updated = replace_bounded(line, &old_path, &new_path, |before, after| {
+                    !before.ends_with(|c: char| is_ident(c) || c == '/' || c == '.')
+                        && !after.starts_with(is_ident)
+                });
+                if let Some(rust) = &rust {
+                    updated = rust.update(&updated);
+                }
+                if let Some(python) = &python {
+                    updated = python.update(&updated);
+                }
+                changed |= updated != line;
+                updated
+            })
+            .collect();
+        changed.then(|| lines.join("\n"))
+    }
+}
+
+/// A move of a Rust module, as seen from one file in the same crate.
+struct RustMove {
+    /// The module's full path before and after the move, like `crate::a::util`
+    old_path: String,
+    new_path: String,
+    old_name: String,
+    new_name: String,
+    /// Whether the referring file is a sibling of the module, so `super::` reaches it
+    sibling: bool,
+    /// Whether the referring file is the module's parent, which declares it
+    parent: bool,
+    /// Whether the module keeps its parent, so its declaration can simply be renamed
+    same_parent: bool,
+}
+
+impl RustMove {
+    /// Returns None if either end of the move isn't a Rust module, or if the referring file is in
+    /// another crate.
+    fn new(from: &Path, to: &Path, referrer: &Path) -> Option<Self> {
+        let (root, old) = rust_module(from)?;
+        let (new_root, new) = rust_module(to)?;
+        let (here_root, here) = rust_module(referrer)?;
+        if root != new_root || root != here_root || old == new || old.is_empty() || new.is_empty() {
+            return None;
+        }
+        let old_parent = &old[..old.len() - 1];
+        let new_parent = &new[..new.len() - 1];
+        let full = |segments: &[String]| format!("crate::{}", segments.join("::"));
+        Some(Self {
+            old_path: full(&old),
+            new_path: full(&new),
+            old_name: old.last()?.clone(),
+            new_name: new.last()?.clone(),
+            sibling: !here.is_empty() && &here[..here.len() - 1] == old_parent,
+            parent: here == old_parent,
+            same_parent: old_parent == new_parent,
+        })
+    }
+
+    fn update(&self, line: &str) -> String {
+        // A path segment that isn't part of a longer path, and isn't followed by more of a name
+        let starts = |before: &str| !before.ends_with(|c: char| is_ident(c) || c == ':');
+        let ends = |after: &str| !after.starts_with(is_ident);
+        let mut line = replace_bounded(line, &self.old_path, &self.new_path, |before, after| {
+            starts(before) && ends(after)
+        });
+        // Once the module has a new parent, relative paths to it no longer work
+        let relative = |prefix: &str| {
+            if self.same_parent {
+                format!("{}{}", prefix, self.new_name)
+            } else {
+                self.new_path.clone()
+            }
+        };
+        if self.sibling {
+            let old = format!("super::{}", self.old_name);
+            line = replace_bounded(&line, &old, &relative("super::"), |before, after| {
+                starts(before) && ends(after)
+            });
+        }
+        if self.parent {
+            let declares = strip_visibility(&line).starts_with("mod ");
+            if declares {
+                if self.same_parent {
+                    line = replace_bounded(&line, &self.old_name, &self.new_name, |b, a| {
+                        !b.ends_with(is_ident) && ends(a)
+                    });
+                }
+            } else {
+                let old = format!("self::{}", self.old_name);
+                line = replace_bounded(&line, &old, &relative("self::"), |before, after| {
+                    starts(before) && ends(after)
+                });
+                line = replace_bounded(&line, &self.old_name, &relative(""), |before, after| {
+                    starts(before) && after.starts_with("::") && !after.starts_with("::<")
+                });
+            }
+        }
+        line
+    }
+}
+
+/// The crate source directory a Rust file is in, and the file's module path within the crate.
+/// The crate root, `lib.rs` or `main.rs`, has an empty path. Returns None for files that aren't
+/// Rust, or aren't under a `src` directory.
+fn rust_module(path: &Path) -> Option<(PathBuf, Vec<String>)> {
+    if !has_extension(path, &["rs"]) {
+        return None;
+    }
+    let components = normal_components(path)?;
+    let src = components.iter().rposition(|c| c == "src")?;
+    let mut segments = components[src + 1..].to_vec();
+    let stem = segments.pop()?;
+    let stem = stem.strip_suffix(".rs")?;
+    if segments.is_empty() && (stem == "lib" || stem == "main") {
+        // The crate root
+    } else if stem != "mod" {
+        segments.push(stem.to_string());
+    }
+    Some((components[..=src].iter().collect(), segments))
+}
+
+/// A move of a Python module.
+struct PythonMove {
+    /// The module's dotted name before and after the move, split on dots
+    old: Vec<String>,
+    new: Vec<String>,
+}
+
+impl PythonMove {
+    fn new(from: &Path, to: &Path) -> Option<Self> {
+        let (old, new) = (python_module(from)?, python_module(to)?);
+        (old != new && !old.is_empty()).then_some(Self { old, new })
+    }
+
+    /// Updates a module named in an import statement. The module may be named by any trailing
+    /// part of its dotted name, depending on where the package root is, so the same leading part
+    /// is dropped from the new name.
+    fn rename(&self, module: &str) -> Option<String> {
+        let parts: Vec<&str> = module.split('.').collect();
+        let prefix = self.old.len().checked_sub(parts.len())?;
+        if self.old[prefix..] != parts || self.new.get(..prefix)? != &self.old[..prefix] {
+            return None;
+        }
+        Some(self.new[prefix..].join("."))
+    }
+
+    fn update(&self, line: &str) -> String {
+        let indent = &line[..line.len() - line.trim_start().len()];
+        let stmt = line.trim_start();
+        if let Some(rest) = stmt.strip_prefix("from ") {
+            let module = rest.split_whitespace().next().unwrap_or_default();
+            let after = rest.trim_start()[module.len()..].trim_start();
+            if after.starts_with("import ") {
+                if let Some(new) = self.rename(module) {
+                    return format!(
+                        "{}from {}{}",
+                        indent,
+                        new,
+                        &rest.trim_start()[module.len()..]
+                    );
+                }
+            }
+        } else if let Some(rest) = stmt.strip_prefix("import ") {
+            // A list of modules, each optionally renamed with `as`
+            let mut renamed = false;
+            let items: Vec<String> = rest
+                .split(',')
+                .map(|item| {
+                    let module = item.split_whitespace().next().unwrap_or_default();
+                    match self.rename(module) {
+                        Some(new) => {
+                            renamed = true;
+                            item.replacen(module, &new, 1)
+                        }
+                        None => item.to_string(),
+                    }
+                })
+                .collect();
+            if renamed {
+                return format!("{}import {}", indent, items.join(","));
+            }
+        }
+        line.to_string()
+    }
+}
+
+/// A Python file's dotted module name, from the path's components. Package `__init__` files are
+/// named by their directory.
+fn python_module(path: &Path) -> Option<Vec<String>> {
+    let mut segments = normal_components(path)?;
+    let file = segments.pop()?;
+    let stem = file
+        .strip_suffix(".py")
+        .or_else(|| file.strip_suffix(".pyi"))?;
+    if stem != "__init__" {
+        segments.push(stem.to_string());
+    }
+    Some(segments)
+}
+
+/// The normal components of a path, with any in-memory prefix removed. Returns None if the path
+/// has components that aren't plain names.
+fn normal_components(path: &Path) -> Option<Vec<String>> {
+    let text = path.to_str()?;
+    let text = text.strip_prefix(crate::MEM_PREFIX).unwrap_or(text);
+    Path::new(text)
+        .components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .map(|c| match c {
+            Component::Normal(name) => name.to_str().map(String::from),
+            _ => None,
+        })
+        .collect()
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| extensions.contains(&e))
+}
+
+/// A path with forward slashes, as it appears in source on every platform.
+fn slash(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Strips leading whitespace and a visibility like `pub` or `pub(crate)` from a line.
+fn strip_visibility(line: &str) -> &str {
+    let line = line.trim_start();
+    let Some(rest) = line.strip_prefix("pub") else {
+        return line;
+    };
+    let rest = match rest.strip_prefix('(') {
+        Some(r) => r.split_once(')').map_or(r, |(_, r)| r),
+        None => rest,
+    };
+    rest.trim_start()
+}
+
+/// Replaces the occurrences of `old` in `line` for which `bounded` returns true, given the text
+/// before and after the occurrence.
+fn replace_bounded<F>(line: &str, old: &str, new: &str, bounded: F) -> String
+where
+    F: Fn(&str, &str) -> bool,
+{
+    let mut out = String::with_capacity(line.len());
+    let mut start = 0;
+    while let Some(i) = line[start..].find(old).map(|i| start + i) {
+        let end = i + old.len();
+        out.push_str(&line[start..i]);
+        out.push_str(if bounded(&line[..i], &line[end..]) {
+            new
+        } else {
+            old
+        });
+        start = end;
+    }
+    out.push_str(&line[start..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn test_update_references() {
+        let mv = MoveFile {
+            from: "src/a/util.rs".into(),
+            to: "src/a/helpers.rs".into(),
+        };
+        let update = |path: &str, text: &str| mv.update_references(Path::new(path), text);
+
+        // The parent module declares the module, and can name it directly
+        let parent = indoc! {r#"
+            pub(crate) mod util;
+            use self::util::{parse, render};
+            // See src/a/util.rs for details, not src/a/util.rsx
+            fn f(util: &str) -> String {
+                util::parse(util).iter::<u8>();
+                x.util::<u8>()
+            }
+        "#};
+        assert_eq!(
+            update("src/a/mod.rs", parent).unwrap(),
+            indoc! {r#"
+                pub(crate) mod helpers;
+                use self::helpers::{parse, render};
+                // See src/a/helpers.rs for details, not src/a/util.rsx
+                fn f(util: &str) -> String {
+                    helpers::parse(util).iter::<u8>();
+                    x.util::<u8>()
+                }
+            "#}
+        );
+        assert_eq!(update("src/a.rs", "mod util;\n").unwrap(), "mod helpers;\n");
+
+        // Elsewhere only the full path, and super paths from siblings, name the module
+        let elsewhere = indoc! {r#"
+            mod util;
+            use crate::a::util;
+            use super::util::f;
+            util::f();
+            other::util::f();
+        "#};
+        assert_eq!(
+            update("src/b.rs", elsewhere).unwrap(),
+            indoc! {r#"
+                mod util;
+                use crate::a::helpers;
+                use super::util::f;
+                util::f();
+                other::util::f();
+            "#}
+        );
+        assert_eq!(
+            update("src/a/sibling.rs", elsewhere).unwrap(),
+            indoc! {r#"
+                mod util;
+                use crate::a::helpers;
+                use super::helpers::f;
+                util::f();
+                other::util::f();
+            "#}
+        );
+        assert_eq!(update("src/b.rs", "use crate::a::utility;\n"), None);
+        // Other crates, and files that aren't source, are left alone
+        assert_eq!(update("other/src/lib.rs", "use crate::a::util;\n"), None);
+        assert_eq!(update("README.md", "See src/a/util.rs\nmod util;\n"), None);
+
+        // Moving to a new parent makes relative paths absolute, and leaves the declaration for
+        // the checks to catch
+        let mv = MoveFile {
+            from: "src/a/util.rs".into(),
+            to: "src/b/util.rs".into(),
+        };
+        assert_eq!(
+            mv.update_references(Path::new("src/a/mod.rs"), "mod util;\nutil::f();\n")
+                .unwrap(),
+            "mod util;\ncrate::b::util::f();\n"
+        );
+
+        // Python modules are imported by their dotted name, from any package root
+        let py = MoveFile {
+            from: "pkg/old/__init__.py".into(),
+            to: "pkg/new/__init__.py".into(),
+        };
+        let src = indoc! {r#"
+            from pkg.old import f
+            import pkg.old as o, os
+            from old import g
+            """
+            from old times, import nothing
+            """
+            old = 1
+        "#};
+        assert_eq!(
+            py.update_references(Path::new("main.py"), src).unwrap(),
+            indoc! {r#"
+                from pkg.new import f
+                import pkg.new as o, os
+                from new import g
+                """
+                from old times, import nothing
+                """
+                old = 1
+            "#}
+        );
+    }
+}
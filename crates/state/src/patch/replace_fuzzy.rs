@@ -1,13 +1,16 @@
 use std::path::PathBuf;
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use super::{hunks, structural};
 use crate::error::{Error, Result};
 
 /// An replace operation that replaces once occurrence of a string with another. This operation is
 /// fuzzy - meaning it tries really hard to make the replacement by ignoring leading and trailing
-/// whitespace.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+/// whitespace. If that fails and the old text is a complete item in a supported language, the item
+/// is matched structurally instead.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
 pub struct ReplaceFuzzy {
     pub path: PathBuf,
     pub old: String,
@@ -20,6 +23,17 @@ impl ReplaceFuzzy {
     /// Replaces only the first occurrence of the old content with the new content.
     /// Returns the modified string if the replacement was successful, or an error if no changes were made.
     pub fn apply(&self, input: &str) -> Result<String> {
+        match self.apply_partial(input)? {
+            (_, Some(e)) => Err(e),
+            (result, None) => Ok(result),
+        }
+    }
+
+    /// Applies the replacement operation, allowing it to be applied in part. If the old text
+    /// doesn't match as a whole, but is large enough to split, its changes are applied hunk by
+    /// hunk. The modified string is then returned along with an error describing the hunks that
+    /// couldn't be matched. An error is returned if nothing could be applied.
+    pub fn apply_partial(&self, input: &str) -> Result<(String, Option<Error>)> {
         let old_lines: Vec<&str> = self.old.lines().map(str::trim).collect();
         let new_lines: Vec<&str> = self.new.lines().collect();
         let input_lines: Vec<&str> = input.lines().collect();
@@ -36,13 +50,22 @@ impl ReplaceFuzzy {
             {
                 result.extend(new_lines.iter().cloned());
                 result.extend(input_lines[i + old_lines.len()..].iter().cloned());
-                return Ok(result.join("\n"));
+                return Ok((result.join("\n"), None));
             } else {
                 result.push(input_lines[i]);
                 i += 1;
             }
         }
 
+        if let Some(result) = structural::replace_item(&self.path, input, &self.old, &self.new) {
+            return Ok((result, None));
+        }
+
+        if let Some(result) = hunks::replace_hunks(input, &self.old, &self.new) {
+            let err = (!result.failed.is_empty()).then(|| self.hunk_error(&result));
+            return Ok((result.content, err));
+        }
+
         Err(Error::Patch {
             user: "Could not find the text to replace".to_string(),
             model: format!(
@@ -51,6 +74,50 @@ impl ReplaceFuzzy {
             )
         })
     }
+
+    /// Describes the hunks of a partially applied replacement that couldn't be matched.
+    fn hunk_error(&self, result: &hunks::HunkResult) -> Error {
+        let applied = result.total - result.failed.len();
+        let failed: Vec<String> = result
+            .failed
+            .iter()
+            .map(|h| {
+                format!(
+                    "hunk {} (lines {}-{} of the old text) {}",
+                    h.index, h.lines.0, h.lines.1, h.reason
+                )
+            })
+            .collect();
+        let details: Vec<String> = result
+            .failed
+            .iter()
+            .map(|h| {
+                format!(
+                    "Hunk {}, lines {}-{} of the old text, {}:\n{}",
+                    h.index, h.lines.0, h.lines.1, h.reason, h.old
+                )
+            })
+            .collect();
+        Error::Patch {
+            user: format!(
+                "Applied {} of {} hunks to {}; {}",
+                applied,
+                result.total,
+                self.path.display(),
+                failed.join(", ")
+            ),
+            model: format!(
+                "The replacement in {} did not match as a whole, so it was split into {} hunks. \
+                 {} were applied, and the file now contains those changes. The following hunks \
+                 could not be applied - re-issue replacements for these only, against the current \
+                 file:\n\n{}",
+                self.path.display(),
+                result.total,
+                applied,
+                details.join("\n\n")
+            ),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -141,4 +208,38 @@ mod tests {
             assert_eq!(result, expected_output.trim_end(), "Test case: {}", name);
         }
     }
+
+    #[test]
+    fn test_apply_partial() {
+        let input = indoc! {"
+            fn one() {
+                let a = 1;
+                a + 1
+            }
+
+            fn two() {
+                let b = 2;
+                b + 2
+            }
+        "};
+        // The second function has drifted too far to match
+        let old = input
+            .replace("    b + 2", "    b * 2 + 0")
+            .replace("let b", "let bb");
+        let replace = ReplaceFuzzy {
+            path: "src/lib.rs".into(),
+            old: old.clone(),
+            new: old
+                .replace("let a = 1;", "let a = 10;")
+                .replace("let bb = 2;", "let bb = 20;"),
+        };
+        let (result, err) = replace.apply_partial(input).unwrap();
+        assert_eq!(result, input.replace("let a = 1;", "let a = 10;"));
+        let Some(Error::Patch { user, model }) = err else {
+            panic!("expected a patch error");
+        };
+        assert!(user.starts_with("Applied 1 of 2 hunks to src/lib.rs; hunk 2"));
+        assert!(model.contains("let bb = 2;"));
+        assert!(replace.apply(input).is_err());
+    }
 }
@@ -0,0 +1,151 @@
+//! Structural matching of replacements, using tree-sitter. When the text a model wants to replace
+//! is a complete item, like a function, impl or trait, we can find the item it names in the file
+//! even if the text doesn't match line for line. Only Rust is supported for now.
+use std::path::Path;
+
+use crate::syntax::{field, language, preamble_start, Node, Parser, CONTAINERS, ITEMS, PREAMBLE};
+
+/// Identifies an item by its kind and name. Impls are identified by their type and trait.
+fn item_key(node: Node, src: &str) -> Option<(String, String)> {
+    let name = if node.kind() == "impl_item" {
+        format!(
+            "{} for {}",
+            field(node, "trait", src).unwrap_or_default(),
+            field(node, "type", src)?
+        )
+    } else {
+        field(node, "name", src)?.to_string()
+    };
+    Some((node.kind().to_string(), name))
+}
+
+/// Collects all items in a container node, including items nested in impls, traits and modules.
+fn collect_items<'a>(node: Node<'a>, out: &mut Vec<Node<'a>>) {
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        if ITEMS.contains(&child.kind()) {
+            out.push(child);
+        }
+        if CONTAINERS.contains(&child.kind()) {
+            if let Some(body) = child.child_by_field_name("body") {
+                collect_items(body, out);
+            }
+        }
+    }
+}
+
+/// Replaces the item in `input` that `old` names with `new`. This succeeds only if `old` parses
+/// cleanly as a single item, optionally preceded by attributes and comments, and exactly one item
+/// in the input has the same kind and name. Whole lines are replaced, as with fuzzy replacement.
+/// Returns None if the replacement can't be made structurally.
+pub fn replace_item(path: &Path, input: &str, old: &str, new: &str) -> Option<String> {
+    let mut parser = Parser::new();
+    parser.set_language(&language(path)?).ok()?;
+
+    let old_tree = parser.parse(old, None)?;
+    let old_root = old_tree.root_node();
+    if old_root.has_error() {
+        return None;
+    }
+    let mut cursor = old_root.walk();
+    let mut old_nodes = old_root
+        .named_children(&mut cursor)
+        .filter(|n| !PREAMBLE.contains(&n.kind()));
+    let old_item = old_nodes.next()?;
+    if old_nodes.next().is_some() || !ITEMS.contains(&old_item.kind()) {
+        return None;
+    }
+    let key = item_key(old_item, old)?;
+    let has_preamble = preamble_start(old_item) != old_item;
+
+    let tree = parser.parse(input, None)?;
+    let mut items = vec![];
+    collect_items(tree.root_node(), &mut items);
+    let mut matches = items
+        .into_iter()
+        .filter(|n| item_key(*n, input).as_ref() == Some(&key));
+    let target = matches.next()?;
+    if matches.next().is_some() {
+        return None;
+    }
+
+    let start = if has_preamble {
+        preamble_start(target)
+    } else {
+        target
+    };
+    let lines: Vec<&str> = input.lines().collect();
+    let first = start.start_position().row;
+    let last = target.end_position().row;
+    let mut result: Vec<&str> = lines[..first].to_vec();
+    result.extend(new.lines());
+    result.extend(lines.get(last + 1..).unwrap_or_default());
+    Some(result.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    const SRC: &str = indoc! {"
+        struct Session;
+
+        impl Session {
+            /// Apply a patch.
+            pub fn apply(&self) {
+                let x = 1;
+                run(x);
+            }
+
+            fn other(&self) {}
+        }
+
+        impl Display for Session {
+            fn fmt(&self) {}
+        }
+
+        fn other() {}
+    "};
+
+    #[test]
+    fn test_replace_item() {
+        let rs = Path::new("src/lib.rs");
+
+        // The old text has a different body, but names a unique method
+        let out = replace_item(
+            rs,
+            SRC,
+            "pub fn apply(&self) {\n    run(1);\n}",
+            "    pub fn apply(&self) {\n        run(2);\n    }",
+        )
+        .unwrap();
+        assert!(out.contains("    /// Apply a patch.\n    pub fn apply(&self) {\n        run(2);\n    }\n\n    fn other"));
+
+        // Including the doc comment replaces it too
+        let out = replace_item(
+            rs,
+            SRC,
+            "/// Apply.\npub fn apply(&self) {}",
+            "    pub fn apply(&self) {}",
+        )
+        .unwrap();
+        assert!(out.contains("impl Session {\n    pub fn apply(&self) {}\n\n"));
+
+        // Impls are matched by trait and type
+        let out = replace_item(
+            rs,
+            SRC,
+            "impl Display for Session {\n    fn fmt(&self) { todo!() }\n}",
+            "impl Display for Session {}",
+        )
+        .unwrap();
+        assert!(out.contains("impl Display for Session {}\n\nfn other() {}"));
+
+        // Ambiguous names, partial items, and unsupported languages don't match
+        assert!(replace_item(rs, SRC, "fn other() {}", "").is_none());
+        assert!(replace_item(rs, SRC, "pub fn apply(&self) {", "").is_none());
+        assert!(replace_item(rs, SRC, "let x = 1;", "").is_none());
+        assert!(replace_item(Path::new("a.py"), SRC, "fn fmt(&self) {}", "").is_none());
+    }
+}
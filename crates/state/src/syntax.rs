@@ -0,0 +1,80 @@
+//! Finding items in source files with tree-sitter. These helpers are shared by structural
+//! replacement here and symbol lookup in libtenx. Only Rust is supported for now.
+use std::path::Path;
+
+pub use tree_sitter::{Language, Node, Parser};
+
+/// Node kinds for named items. Impls are items too, but are named by their type and trait.
+pub const ITEMS: &[&str] = &[
+    "function_item",
+    "function_signature_item",
+    "impl_item",
+    "trait_item",
+    "struct_item",
+    "enum_item",
+    "union_item",
+    "type_item",
+    "associated_type",
+    "const_item",
+    "static_item",
+    "mod_item",
+    "macro_definition",
+];
+
+/// Node kinds that can contain nested items in their `body` field.
+pub const CONTAINERS: &[&str] = &["impl_item", "trait_item", "mod_item"];
+
+/// Node kinds that are attached to the item that follows them.
+pub const PREAMBLE: &[&str] = &["attribute_item", "line_comment", "block_comment"];
+
+/// Returns the tree-sitter language for Rust.
+pub fn rust() -> Language {
+    tree_sitter_rust::LANGUAGE.into()
+}
+
+/// Returns the tree-sitter language for a path, if it's one we support.
+pub fn language(path: &Path) -> Option<Language> {
+    match path.extension()?.to_str()? {
+        "rs" => Some(rust()),
+        _ => None,
+    }
+}
+
+/// Returns the text of a node's field, if it has one.
+pub fn field<'a>(node: Node, name: &str, src: &'a str) -> Option<&'a str> {
+    node.child_by_field_name(name)?
+        .utf8_text(src.as_bytes())
+        .ok()
+}
+
+/// Returns the first node of the preamble attached to an item, or the item itself if it has none.
+/// Attributes and comments are attached if nothing separates them from the item.
+pub fn preamble_start(node: Node) -> Node {
+    let mut start = node;
+    while let Some(prev) = start.prev_sibling() {
+        if !PREAMBLE.contains(&prev.kind())
+            || start.start_position().row > prev.end_position().row + 1
+        {
+            break;
+        }
+        start = prev;
+    }
+    start
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preamble_start() {
+        let src = "// Unattached\n\n/// Docs\n#[inline]\nfn f() {}\n";
+        let mut parser = Parser::new();
+        parser.set_language(&rust()).unwrap();
+        let tree = parser.parse(src, None).unwrap();
+        let item = tree.root_node().named_child(3).unwrap();
+        assert_eq!(field(item, "name", src), Some("f"));
+        assert_eq!(preamble_start(item).start_position().row, 2);
+        assert!(language(Path::new("a.py")).is_none());
+    }
+}
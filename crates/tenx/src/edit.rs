@@ -4,7 +4,7 @@ use anyhow::{Context as AnyhowContext, Result};
 use tempfile::NamedTempFile;
 use tokio::sync::mpsc;
 
-use libtenx::{config::Config, events::Event, session::Session};
+use libtenx::api::{Config, Event, Session};
 
 const SESSION_INFO_MARKER: &str = "\n** Only edit prompt text ABOVE this marker. **\n";
 
@@ -36,7 +36,7 @@ fn render_edit_text(session: &Session, retry: bool) -> Result<String> {
     match session.last_step() {
         Some(step) => {
             if retry {
-                text.push_str(&step.raw_prompt);
+                text.push_str(&step.request.raw_prompt);
             }
         }
         None => {
@@ -164,11 +164,12 @@ mod tests {
                 ))
                 .unwrap();
             if let Some(step) = p.session.last_step_mut() {
-                step.model_response = Some(ModelResponse {
+                step.response.model_response = Some(ModelResponse {
                     patch: Some(Patch { changes: vec![] }),
                     operations: vec![],
                     usage: None,
                     comment: Some(response.to_string()),
+                    summary: None,
                     raw_response: Some(response.to_string()),
                 });
             }
@@ -1,22 +1,75 @@
-use std::{fs, io::Read, path::PathBuf};
+use std::{
+    fs,
+    io::{IsTerminal, Read, Write},
+    path::PathBuf,
+    rc::Rc,
+};
 
 use anyhow::{anyhow, Context as AnyhowContext, Result};
-use clap::{CommandFactory, Parser, Subcommand};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 use colored::*;
 use tokio::sync::mpsc;
 use tracing_subscriber::util::SubscriberInitExt;
 
 use libtenx::{
+    api::{Context, Event, Risk, Session, StepDecision, Tenx},
     config::{self},
-    context::Context,
-    error, event_consumers,
-    events::Event,
-    session::Session,
-    Tenx,
+    context::{ContextProvider, Priority},
+    dialect::{self, DialectProvider},
+    error, event_consumers, model,
+    strategy::ActionStrategy,
+    usage,
 };
 use unirend::Detail;
 
 mod edit;
+mod serve;
+
+/// Parse an age or duration like "7d", "24h", "30m", "90s" or "2w" into a number of seconds.
+fn parse_age(age: &str) -> Result<u64> {
+    let invalid = || anyhow!("Invalid age '{}', expected e.g. 7d, 24h or 2w", age);
+    let age = age.trim();
+    let unit = age.chars().last().ok_or_else(invalid)?;
+    let secs = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    let count: u64 = age[..age.len() - 1].parse().map_err(|_| invalid())?;
+    Ok(count * secs)
+}
+
+/// Resolve a step given as "action:step", or as a step number in the last action, checking that
+/// it exists in the session.
+fn resolve_step(session: &Session, step: &str) -> Result<(usize, usize)> {
+    let (action_idx, step_idx) = match step.split_once(':') {
+        Some(_) => match parse_step_offset(step)? {
+            (a, Some(s)) => (a, s),
+            (a, None) => (a, 0),
+        },
+        None => (
+            session
+                .actions
+                .len()
+                .checked_sub(1)
+                .ok_or_else(|| anyhow!("No actions in session"))?,
+            step.parse()
+                .map_err(|_| anyhow!("Invalid step '{}', expected e.g. 2 or 0:2", step))?,
+        ),
+    };
+    if session
+        .actions
+        .get(action_idx)
+        .and_then(|a| a.steps.get(step_idx))
+        .is_none()
+    {
+        return Err(anyhow!("No step {}:{} in session", action_idx, step_idx));
+    }
+    Ok((action_idx, step_idx))
+}
 
 /// Parse a step offset string in format "action" or "action:step" and return the parsed indices
 /// If the step is not specified (format "action"), the step index will be None.
@@ -64,6 +117,226 @@ fn get_prompt(
     }
 }
 
+/// Print the session's contexts with their provenance, and a warning for each stale context.
+fn show_contexts(config: &config::Config, session: &Session) -> Result<()> {
+    let mut render = term(config);
+    session.contexts.render(&mut render, Detail::Default)?;
+    println!("{}", render.render());
+    for (id, reason) in session.contexts.stale(config) {
+        if let Some(context) = session.contexts.get(&id) {
+            let warning = format!("stale: {}: {}", context.human(), reason);
+            println!("{}", warning.yellow());
+        }
+    }
+    Ok(())
+}
+
+/// Print a colored unified diff between two rendered prompts.
+fn print_prompt_diff(old: &str, new: &str) {
+    if old == new {
+        println!("{}", "prompt unchanged from failed attempt".yellow());
+        return;
+    }
+    let patch = diffy::create_patch(old, new).to_string();
+    for line in patch.lines() {
+        if line.starts_with("+++") || line.starts_with("---") {
+            println!("{}", line.bold());
+        } else if line.starts_with('+') {
+            println!("{}", line.green());
+        } else if line.starts_with('-') {
+            println!("{}", line.red());
+        } else if line.starts_with("@@") {
+            println!("{}", line.cyan());
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+/// Print text through the user's `$PAGER` if stdout is a terminal, otherwise print it directly.
+fn print_paged(text: &str) -> Result<()> {
+    let pager = std::env::var("PAGER").unwrap_or_default();
+    let mut args = pager.split_whitespace();
+    let Some(program) = args.next().filter(|_| std::io::stdout().is_terminal()) else {
+        print!("{}", text);
+        return Ok(());
+    };
+    let mut child = std::process::Command::new(program)
+        .args(args)
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .context("Failed to start pager")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager may exit before reading everything, which is fine
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    child.wait().context("Failed to wait for pager")?;
+    Ok(())
+}
+
+/// Copies text to the system clipboard, using the first clipboard tool that's available.
+fn copy_to_clipboard(text: &str) -> Result<()> {
+    const TOOLS: &[&[&str]] = &[
+        &["pbcopy"],
+        &["wl-copy"],
+        &["xclip", "-selection", "clipboard"],
+        &["xsel", "--clipboard", "--input"],
+        &["clip.exe"],
+    ];
+    for tool in TOOLS {
+        let Ok(mut child) = std::process::Command::new(tool[0])
+            .args(&tool[1..])
+            .stdin(std::process::Stdio::piped())
+            .spawn()
+        else {
+            continue;
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        if child.wait()?.success() {
+            return Ok(());
+        }
+    }
+    Err(anyhow!(
+        "No clipboard tool found, tried pbcopy, wl-copy, xclip, xsel and clip.exe"
+    ))
+}
+
+/// Returns a renderer for command output, wrapped to the configured width when stdout is not a
+/// terminal.
+fn term(config: &config::Config) -> unirend::Term {
+    if config.term_width == 0 {
+        unirend::Term::new()
+    } else {
+        unirend::Term::with_fallback_width(config.term_width)
+    }
+}
+
+/// Shows the patch and error from the last model response, and asks the user whether to send the
+/// next step. Used by --step to supervise multi-step loops.
+fn confirm_step(session: &Session) -> error::Result<StepDecision> {
+    let io_err = |e: std::io::Error| error::TenxError::Io(e.to_string());
+    let action = session.last_action()?;
+    if let Some(prev) = action.steps.len().checked_sub(2).map(|i| &action.steps[i]) {
+        if let Some(patch) = prev
+            .response
+            .model_response
+            .as_ref()
+            .and_then(|r| r.patch.as_ref())
+        {
+            let mut renderer = unirend::Term::new();
+            patch.render(&mut renderer, Detail::Full)?;
+            println!("{}", renderer.render());
+        }
+        match &prev.outcome.err {
+            Some(error::TenxError::Check { name, model, .. }) => {
+                println!("{}", format!("check failed: {}", name).red().bold());
+                println!("{}", model);
+            }
+            Some(e) => println!("{}", format!("error: {}", e).red().bold()),
+            None => {}
+        }
+    }
+    loop {
+        print!("{}", "[c]ontinue, [a]bort, [e]dit prompt? ".yellow().bold());
+        std::io::stdout().flush().map_err(io_err)?;
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line).map_err(io_err)? == 0 {
+            return Ok(StepDecision::Abort);
+        }
+        match line.trim() {
+            "" | "c" | "continue" => return Ok(StepDecision::Continue),
+            "a" | "abort" => return Ok(StepDecision::Abort),
+            "e" | "edit" => {
+                let prompt = edit::edit_prompt(session, true, &None)
+                    .map_err(|e| error::TenxError::Internal(e.to_string()))?;
+                return Ok(prompt.map_or(StepDecision::Continue, StepDecision::Prompt));
+            }
+            _ => continue,
+        }
+    }
+}
+
+/// Lists the risky changes in a patch, and asks the user whether to apply it anyway.
+fn confirm_risks(_session: &Session, risks: &[Risk]) -> error::Result<bool> {
+    let io_err = |e: std::io::Error| error::TenxError::Io(e.to_string());
+    println!("{}", "risky patch:".yellow().bold());
+    for risk in risks {
+        println!("  {}", risk);
+    }
+    print!("{}", "apply it anyway? [y/N] ".yellow().bold());
+    std::io::stdout().flush().map_err(io_err)?;
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line).map_err(io_err)?;
+    Ok(matches!(line.trim(), "y" | "yes"))
+}
+
+/// Asks the user to choose a model when the default model isn't available, and offers to save the
+/// choice as the project's default.
+fn pick_model(config: &config::Config) -> anyhow::Result<String> {
+    let models = config.model_confs();
+    if models.is_empty() {
+        return Err(anyhow!(
+            "No models configured - set an API key or add a custom model"
+        ));
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow!(
+            "Default model {} is not available, specify one with --model",
+            config.models.default
+        ));
+    }
+    println!(
+        "Default model {} is not available. Choose a model:",
+        config.models.default.blue().bold()
+    );
+    for (i, model) in models.iter().enumerate() {
+        let window = config
+            .context_window_for(model.name())
+            .map_or("?".to_string(), |w| format!("{}k", w / 1000));
+        let cost = config
+            .pricing_for(model.name())
+            .map_or("?".to_string(), |p| {
+                format!("${:.4} in, ${:.4} out", p.input / 1000.0, p.output / 1000.0)
+            });
+        println!(
+            "  {}) {} ({}, context {}, per 1K tokens {})",
+            i + 1,
+            model.name().blue().bold(),
+            model.kind(),
+            window,
+            cost
+        );
+    }
+    let chosen = loop {
+        print!("{}", "model number: ".yellow().bold());
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line)? == 0 {
+            return Err(anyhow!("No model chosen"));
+        }
+        match line.trim().parse::<usize>() {
+            Ok(n) if (1..=models.len()).contains(&n) => break models[n - 1].name().to_string(),
+            _ => continue,
+        }
+    };
+    print!(
+        "{}",
+        "Save as the default model in the project config? [y/N] "
+            .yellow()
+            .bold()
+    );
+    std::io::stdout().flush()?;
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+    if matches!(line.trim(), "y" | "yes") {
+        config::set_default_model(config, &chosen)?;
+        println!("Saved {} as the default model", chosen.blue().bold());
+    }
+    Ok(chosen)
+}
+
 #[derive(Parser)]
 #[clap(name = "tenx")]
 #[clap(author = "Aldo Cortesi")]
@@ -79,11 +352,28 @@ struct Cli {
     #[clap(short, long)]
     quiet: bool,
 
-    /// Show raw log output instead of progress indicators
+    /// Show raw log output instead of progress indicators. This is the default when stdout is not
+    /// a terminal.
     #[clap(long)]
     logs: bool,
 
-    /// Model to use (overrides default_model in config)
+    /// Write full trace-level logs and all events to a file, regardless of verbosity
+    #[clap(long)]
+    log_file: Option<PathBuf>,
+
+    /// Write events to a file as JSON lines, alongside the normal output
+    #[clap(long)]
+    events_json: Option<PathBuf>,
+
+    /// Stream events as JSON lines to a TCP address, like 127.0.0.1:9000
+    #[clap(long)]
+    events_socket: Option<String>,
+
+    /// Only send these events to --events-json and --events-socket (comma-separated event names)
+    #[clap(long, value_delimiter = ',')]
+    events_filter: Vec<String>,
+
+    /// Model or model alias to use (overrides default_model in config)
     #[clap(long, env = "TENX_MODEL")]
     model: Option<String>,
 
@@ -107,6 +397,25 @@ struct Cli {
     #[clap(long)]
     no_stream: bool,
 
+    /// Never touch the network: models give empty responses, and URL and Ruskel contexts get
+    /// placeholder content
+    #[clap(long)]
+    offline: bool,
+
+    // Not --force, which `tenx edit --force` already uses for adding excluded files
+    /// Prompt the model even if this would exceed the configured budget. Sets `budget.force`
+    #[clap(long)]
+    over_budget: bool,
+
+    /// Apply patches that delete many lines, change CI workflows or dependency manifests, or touch
+    /// unsafe code, without asking
+    #[clap(long)]
+    allow_risky: bool,
+
+    /// Pause before each automatically generated step, to continue, abort or edit its prompt
+    #[clap(long)]
+    step: bool,
+
     /// Force colored output
     #[clap(long, conflicts_with = "no_color", env = "TENX_COLOR")]
     color: bool,
@@ -133,7 +442,8 @@ enum ContextCommands {
     Clear,
     /// Add ruskel documentation to context
     Ruskel {
-        /// Items to add to context
+        /// Items to add to context: a workspace crate, a path, or a published crate version
+        /// like serde@1.0.200
         items: Vec<String>,
     },
     /// Refresh all contexts in the current session
@@ -161,12 +471,138 @@ enum ContextCommands {
         /// Command to execute
         command: String,
     },
-    /// Show the current session's contexts
+    /// Add a summary of the project's manifests and dependency versions to context
+    Facts,
+    /// Show the current session's contexts, with when and from what revision each was captured
     Show,
 }
 
+#[derive(Subcommand)]
+enum StepsCommands {
+    /// List the steps in the session, with their type, model, changed files and status
+    List,
+    /// Show a step in full
+    Show {
+        /// The step, as "action:step", or a step number in the last action
+        step: String,
+    },
+    /// Remove the last step in the session if it failed, rolling back any changes it made
+    Drop {
+        /// The step, as "action:step", or a step number in the last action. Defaults to the
+        /// last step.
+        step: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum DialectCommands {
+    /// Parse a saved model response with the active dialect, and show the result
+    Check {
+        /// File containing the raw model response
+        file: PathBuf,
+    },
+    /// Parse a corpus of saved model responses with the active dialect, and report which fail.
+    /// Responses with ".fail." in their file name are expected not to parse.
+    Corpus {
+        /// Directory of raw model responses, searched recursively
+        dir: PathBuf,
+    },
+    /// Show the changes and operations the active dialect accepts with the current config
+    Info {
+        /// Output as JSON
+        #[clap(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum AuthCommands {
+    /// Store an API key for a provider (claude, openai or google) in the macOS keychain, or the
+    /// libsecret keyring on Linux. The key is read from stdin.
+    Set {
+        /// The provider to store the key for, or the host of another OpenAI-compatible API, like
+        /// api.groq.com
+        provider: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ModelsCommands {
+    /// Send a minimal request to each configured model, reporting whether it's reachable, whether
+    /// the API key is accepted, and the round-trip latency
+    Ping {
+        /// Only ping these models, by name or alias
+        models: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfCommands {
+    /// Print the effective value of a setting, addressed by a dotted key like "models.default"
+    Get {
+        /// Dotted key of the setting
+        key: String,
+    },
+    /// Set a setting in the project config file. The value is parsed as RON, and taken as a
+    /// string if it isn't valid RON.
+    Set {
+        /// Dotted key of the setting
+        key: String,
+        /// New value for the setting
+        value: String,
+        /// Write to the home config file instead of the project config file
+        #[clap(long)]
+        home: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ChecksCommands {
+    /// Enable a check in the project config file
+    Enable {
+        /// Name of the check
+        name: String,
+        /// Only run the check on files matching these globs (can be repeated)
+        #[clap(long)]
+        glob: Vec<String>,
+    },
+    /// Disable a check in the project config file
+    Disable {
+        /// Name of the check
+        name: String,
+    },
+}
+
 #[derive(Subcommand)]
 enum Commands {
+    /// Restore the files the session changed to their checkpoint, and clear the session. Lists
+    /// the files that will be discarded and asks for confirmation first
+    Abandon {
+        /// Don't ask for confirmation
+        #[clap(long)]
+        yes: bool,
+    },
+    /// Continue the current session, using a different model for the next step only
+    Ask {
+        /// User prompt for the operation
+        #[clap(long)]
+        prompt: Option<String>,
+        /// Path to a file containing the prompt
+        #[clap(long)]
+        prompt_file: Option<PathBuf>,
+        /// Model or model alias to use for this step
+        #[clap(long)]
+        model: Option<String>,
+        /// Stop retrying once this much time has passed, e.g. 90s or 10m. A failing last step is
+        /// rolled back, and a summary of what was attempted is recorded.
+        #[clap(long)]
+        time_budget: Option<String>,
+    },
+    /// Manage provider API keys
+    Auth {
+        #[clap(subcommand)]
+        command: AuthCommands,
+    },
     /// Run check suite all project files, or a subet
     Check {
         /// Files to check, glob patterns accepted
@@ -178,10 +614,15 @@ enum Commands {
         /// Show all checks, including disabled
         #[clap(long)]
         all: bool,
+        #[clap(subcommand)]
+        command: Option<ChecksCommands>,
     },
+    /// Draft a CHANGELOG.md entry under the Unreleased heading, summarizing the session's changes
+    Changelog,
     /// Clear the current session without resetting changes
     Clear,
-    /// Continue with the current session
+    /// Continue with the current session. With --allow-risky, a patch that was refused as risky is
+    /// applied first, without asking the model again
     Continue {
         /// User prompt for the operation
         #[clap(long)]
@@ -208,24 +649,80 @@ enum Commands {
         /// Output default configuration
         #[clap(long)]
         defaults: bool,
+        /// Output a JSON Schema for config files
+        #[clap(long, conflicts_with = "defaults")]
+        schema: bool,
+        #[clap(subcommand)]
+        command: Option<ConfCommands>,
     },
     /// Context commands (alias: ctx)
     #[clap(alias = "ctx")]
     Context {
+        /// Add the contexts from a context group defined in the config (can be repeated)
+        #[clap(long)]
+        group: Vec<String>,
+        /// Add a named item, like a function or impl block, from the project's Rust files, e.g.
+        /// "Session::apply_patch" (can be repeated)
+        #[clap(long)]
+        symbol: Vec<String>,
+        /// Add a range of lines from a project file, e.g. "src/session.rs:100-180" (can be
+        /// repeated)
+        #[clap(long)]
+        lines: Vec<String>,
+        /// Add the uncommitted changes in the working tree as a git diff, against HEAD or the
+        /// given ref
+        #[clap(long, value_name = "REF")]
+        git_diff: Option<Option<String>>,
+        /// Pin the added contexts, so they're always sent in full even when the prompt is cut to
+        /// fit the model's context window
+        #[clap(long, conflicts_with = "priority")]
+        pin: bool,
+        /// Set the priority of the added contexts. Lower priorities are trimmed first when the
+        /// prompt is cut to fit the model's context window.
+        #[clap(long, value_parser = ["low", "normal", "high"])]
+        priority: Option<String>,
         #[clap(subcommand)]
-        command: ContextCommands,
+        command: Option<ContextCommands>,
+    },
+    /// Dialect commands
+    Dialect {
+        #[clap(subcommand)]
+        command: DialectCommands,
+    },
+    /// Show a unified diff of all changes made in the session, in a format git apply accepts
+    Diff {
+        /// Write the diff to a patch file instead of printing it
+        #[clap(long)]
+        output: Option<PathBuf>,
+        /// Copy the diff to the clipboard instead of printing it
+        #[clap(long)]
+        clipboard: bool,
     },
     /// Add editable files to a session
     Edit {
         /// Specifies files to edit, glob patterns accepted
         #[clap(value_parser, required = true)]
         files: Vec<String>,
+        /// Add files even if they are excluded from the project. Paths are taken literally.
+        #[clap(long)]
+        force: bool,
     },
     /// List files included in the project
     Files {
         /// Optional glob pattern to filter files
         pattern: Option<String>,
     },
+    /// Search included project files for a regular expression
+    Grep {
+        /// Regular expression to search for
+        pattern: String,
+        /// Number of lines of context to show around each match
+        #[clap(short = 'C', long, default_value = "2")]
+        context: usize,
+        /// Add the results to the session context
+        #[clap(long)]
+        add_context: bool,
+    },
     /// Start a new session and attempt to fix any pre check failures
     Fix {
         /// Clear the current session, and use it to fix
@@ -243,6 +740,10 @@ enum Commands {
         /// Edit the prompt before fixing
         #[clap(long)]
         edit: bool,
+        /// Describe a bug in the prompt, have the model write a failing test for it, then fix
+        /// the bug so the test passes. Needs an enabled test check with a focus configuration
+        #[clap(long)]
+        test_first: bool,
         /// Specifies files to edit, glob patterns accepted
         #[clap(value_parser)]
         files: Option<Vec<String>>,
@@ -252,6 +753,8 @@ enum Commands {
         /// Show full configuration details
         #[clap(short, long)]
         full: bool,
+        #[clap(subcommand)]
+        command: Option<ModelsCommands>,
     },
     /// Create a new session
     New {
@@ -259,6 +762,26 @@ enum Commands {
         #[clap(long)]
         no_ctx: bool,
     },
+    /// Run all validators on the project, reporting every diagnostic rather than stopping at the
+    /// first failing check
+    Preflight {
+        /// Write the diagnostics to a SARIF file for upload to code scanning tools
+        #[clap(long)]
+        sarif: Option<PathBuf>,
+    },
+    /// Show what would be sent to the model for the next step, with estimated token counts,
+    /// without making a model request
+    Preview {
+        /// User prompt, if the next step needs one
+        #[clap(long)]
+        prompt: Option<String>,
+        /// Path to a file containing the prompt
+        #[clap(long)]
+        prompt_file: Option<PathBuf>,
+        /// Print to stdout, even if $PAGER is set
+        #[clap(long)]
+        no_pager: bool,
+    },
     /// Print information about the current project
     Project,
     /// Start a new session, edit the prompt, and run it
@@ -284,6 +807,21 @@ enum Commands {
         #[clap(long)]
         all: bool,
     },
+    /// Restore a single file to its content at the start of a step, leaving other files alone
+    RevertFile {
+        /// The file to restore
+        path: PathBuf,
+        /// The step to restore to, in format "action" or "action:step" (e.g. "0:3"). Defaults to
+        /// the last step.
+        #[clap(long)]
+        step: Option<String>,
+    },
+    /// Run a recipe: a TOML or YAML file of prompts and commands, run in order in the current
+    /// session
+    Run {
+        /// The recipe file
+        recipe: PathBuf,
+    },
     /// Retry a prompt
     Retry {
         /// The step offset to retry from, in format "action:step" (e.g. "0:3")
@@ -297,29 +835,79 @@ enum Commands {
         /// Path to a file containing the prompt
         #[clap(long)]
         prompt_file: Option<PathBuf>,
+        /// Show what changed in the rendered prompt compared to the failed attempt
+        #[clap(long)]
+        show_prompt_diff: bool,
     },
     /// Show the current session (alias: sess)
     #[clap(alias = "sess")]
     Session {
         /// Path to a session file to load
         session_file: Option<PathBuf>,
-        /// Format to display the session in
-        #[clap(long, value_parser = ["pretty", "raw", "render"], default_value = "pretty")]
+        /// Format to display the session in. The "commit" format prints a commit message built
+        /// from the model's summary of the last action.
+        #[clap(long, value_parser = ["pretty", "raw", "render", "commit"], default_value = "pretty")]
         fmt: String,
+        /// Print a stable, versioned, line-oriented description of the session for editor
+        /// integrations and scripts
+        #[clap(long, conflicts_with = "fmt")]
+        porcelain: bool,
         /// Increase detail level (can be used multiple times)
         #[clap(short = 'd', action = clap::ArgAction::Count, default_value = "0")]
         detail: u8,
         /// Show short output (less detail)
         #[clap(short, long, conflicts_with = "detail")]
         short: bool,
+        /// Show the system prompt pinned when the session was created
+        #[clap(long, conflicts_with = "fmt")]
+        system: bool,
+        /// Output a JSON Schema for stored sessions
+        #[clap(long, conflicts_with_all = ["fmt", "porcelain", "system"])]
+        schema: bool,
+    },
+    /// Serve an HTTP API for creating sessions, adding context and editables, running prompts,
+    /// streaming events and fetching diffs
+    Serve {
+        /// The address to listen on
+        #[clap(long, default_value = "127.0.0.1:4417")]
+        http: String,
+        /// The token clients must present. A random token is generated and printed if not given.
+        #[clap(long, env = "TENX_SERVE_TOKEN")]
+        token: Option<String>,
+    },
+    /// Step commands
+    Steps {
+        #[clap(subcommand)]
+        command: StepsCommands,
+    },
+    /// Print a file exactly as it appears in the prompt for the next step - as an editable file or
+    /// context, with any outlining, truncation or redaction applied
+    ShowFile {
+        /// The file to show
+        path: PathBuf,
+    },
+    /// Report model usage and spend across all sessions and projects
+    Usage {
+        /// How far back to report, like "7d", "24h" or "2w"
+        #[clap(long, default_value = "30d")]
+        since: String,
+        /// Group usage by model, project or command
+        #[clap(long, value_parser = ["model", "project", "command"], default_value = "model")]
+        by: String,
+        /// Output as JSON
+        #[clap(long)]
+        json: bool,
     },
 }
 
 /// Creates a Config from disk and CLI arguments
 fn load_config(cli: &Cli) -> Result<config::Config> {
     let current_dir = std::env::current_dir()?;
-    let mut config = config::load_config(&current_dir)?;
+    apply_cli(cli, config::load_config(&current_dir)?)
+}
 
+/// Applies CLI arguments to a Config loaded from disk
+fn apply_cli(cli: &Cli, mut config: config::Config) -> Result<config::Config> {
     macro_rules! set_config {
         ($config:expr, $($field:ident).+, $value:expr) => {
             if let Some(val) = $value {
@@ -341,6 +929,15 @@ fn load_config(cli: &Cli) -> Result<config::Config> {
     config.checks.no_pre = cli.no_pre_check;
     config.checks.only = cli.only_check.clone();
     config.models.no_stream = cli.no_stream;
+    if cli.over_budget {
+        config.budget.force = true;
+    }
+    if cli.offline {
+        config.offline = true;
+    }
+    if cli.allow_risky {
+        config.safety.allow_risky = true;
+    }
 
     // Validate checks
     if let Some(name) = &cli.only_check {
@@ -369,46 +966,240 @@ fn load_config(cli: &Cli) -> Result<config::Config> {
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     sigpipe::reset();
-    let cli = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let cli = Rc::new(Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit()));
     let verbosity = if cli.quiet { 0 } else { cli.verbose };
     let config = load_config(&cli)?;
-    let tx = Tenx::new(config.clone());
+    let mut tx =
+        Tenx::new(config.clone()).with_command(matches.subcommand_name().unwrap_or_default());
+    if cli.step {
+        tx = tx.with_step_confirm(Box::new(confirm_step));
+    }
+    if std::io::stdin().is_terminal() {
+        tx = tx.with_risk_confirm(Box::new(confirm_risks));
+    }
 
+    // Without a terminal, e.g. in CI or when piped, we print plain log lines with no color
+    let tty = std::io::stdout().is_terminal();
     if cli.color {
         colored::control::set_override(true);
-    } else if cli.no_color {
+    } else if cli.no_color || !tty {
         colored::control::set_override(false);
     }
 
     let (sender, receiver) = mpsc::channel(100);
     let (event_kill_tx, event_kill_rx) = mpsc::channel(1);
-    let subscriber = event_consumers::create_tracing_subscriber(verbosity, sender.clone());
+    let log_file = cli
+        .log_file
+        .as_deref()
+        .map(event_consumers::LogFile::create)
+        .transpose()?;
+    let subscriber =
+        event_consumers::create_tracing_subscriber(verbosity, sender.clone(), log_file.clone());
     subscriber.init();
-    let event_task = if cli.logs {
-        tokio::spawn(event_consumers::output_logs(receiver, event_kill_rx))
+    let mut broadcast = if cli.logs || !tty {
+        event_consumers::Broadcast::new().with(event_consumers::LogConsumer)
     } else {
-        tokio::spawn(event_consumers::output_progress(
-            receiver,
-            event_kill_rx,
-            verbosity,
-        ))
+        event_consumers::Broadcast::new().with(event_consumers::ProgressConsumer::new(verbosity))
     };
+    let filter = event_consumers::EventFilter::names(&cli.events_filter);
+    if let Some(path) = &cli.events_json {
+        let file = std::fs::File::create(path)?;
+        broadcast =
+            broadcast.with_filtered(event_consumers::JsonConsumer::new(file), filter.clone());
+    }
+    if let Some(addr) = &cli.events_socket {
+        let stream = std::net::TcpStream::connect(addr)
+            .map_err(|e| anyhow!("Failed to connect to event socket {}: {}", addr, e))?;
+        broadcast = broadcast.with_filtered(event_consumers::JsonConsumer::new(stream), filter);
+    }
+    let mut consumer: Box<dyn event_consumers::EventConsumer> = Box::new(broadcast);
+    if let Some(file) = log_file {
+        consumer = Box::new(event_consumers::LogFileConsumer::new(file, consumer));
+    }
+    let event_task = tokio::spawn(event_consumers::consume(consumer, receiver, event_kill_rx));
 
     let result = match &cli.command {
         Some(cmd) => {
             match cmd {
-                Commands::Models { full } => {
+                Commands::Ask {
+                    prompt,
+                    prompt_file,
+                    model,
+                    time_budget,
+                } => {
+                    let budget = time_budget
+                        .as_deref()
+                        .map(parse_age)
+                        .transpose()?
+                        .map(std::time::Duration::from_secs);
+                    let mut session = match tx.load_session() {
+                        Ok(sess) => sess,
+                        Err(error::TenxError::NotFound { .. }) => {
+                            println!("No existing session found.");
+                            return Ok(());
+                        }
+                        Err(e) => return Err(e.into()),
+                    };
+
+                    let model = match model {
+                        None if !config.has_default_model() => Some(pick_model(&config)?),
+                        model => model.clone(),
+                    };
+                    let user_prompt =
+                        get_prompt(prompt, prompt_file, &session, false, &Some(sender.clone()))?;
+
+                    tx.ask(
+                        &mut session,
+                        user_prompt,
+                        model.as_deref(),
+                        Some(sender.clone()),
+                        budget,
+                    )
+                    .await?;
+                    Ok(())
+                }
+                Commands::Changelog => {
+                    let mut session = match tx.load_session() {
+                        Ok(sess) => sess,
+                        Err(error::TenxError::NotFound { .. }) => {
+                            println!("No existing session found.");
+                            return Ok(());
+                        }
+                        Err(e) => return Err(e.into()),
+                    };
+                    tx.changelog(&mut session, Some(sender.clone())).await?;
+                    Ok(())
+                }
+                Commands::Auth {
+                    command: AuthCommands::Set { provider },
+                } => {
+                    if std::io::stdin().is_terminal() {
+                        eprint!("API key for {}: ", provider);
+                        std::io::stderr().flush()?;
+                    }
+                    let mut key = String::new();
+                    std::io::stdin().read_line(&mut key)?;
+                    let key = key.trim();
+                    if key.is_empty() {
+                        return Err(anyhow!("No key given"));
+                    }
+                    libtenx::credentials::keychain_set(provider, key)?;
+                    println!(
+                        "Stored the {} key in the {}",
+                        provider.blue().bold(),
+                        libtenx::credentials::keychain_name().unwrap_or("keychain")
+                    );
+                    Ok(())
+                }
+                Commands::Models {
+                    command: Some(ModelsCommands::Ping { models }),
+                    ..
+                } => {
+                    let names = models
+                        .iter()
+                        .map(|m| config.resolve_model_name(m))
+                        .collect::<Vec<_>>();
+                    let confs = config.model_confs();
+                    if let Some(unknown) = names
+                        .iter()
+                        .find(|n| !confs.iter().any(|c| c.name() == n.as_str()))
+                    {
+                        return Err(anyhow!("Unknown model: {}", unknown));
+                    }
+                    let mut failed = 0;
+                    for conf in confs {
+                        if !names.is_empty() && !names.iter().any(|n| n == conf.name()) {
+                            continue;
+                        }
+                        let result = match config.to_model(&conf) {
+                            Ok(model) => model::ping(&model).await,
+                            Err(e) => model::Ping {
+                                model: conf.name().to_string(),
+                                status: model::PingStatus::from_error(&e),
+                                latency: Default::default(),
+                            },
+                        };
+                        let status = if result.status.is_ok() {
+                            result.status.to_string().green()
+                        } else {
+                            failed += 1;
+                            result.status.to_string().red()
+                        };
+                        println!(
+                            "{} {:>6}ms  {}",
+                            format!("{:<24}", result.model).blue().bold(),
+                            result.latency.as_millis(),
+                            status
+                        );
+                    }
+                    if failed > 0 {
+                        return Err(anyhow!("{} model(s) failed", failed));
+                    }
+                    Ok(())
+                }
+                Commands::Models {
+                    full,
+                    command: None,
+                } => {
                     for model in &config.model_confs() {
                         println!("{}", model.name().blue().bold());
                         println!("    kind: {}", model.kind());
+                        let aliases = config.aliases_for(model.name());
+                        if !aliases.is_empty() {
+                            println!("    aliases: {}", aliases.join(", "));
+                        }
                         for line in model.text_config(*full).lines() {
                             println!("    {}", line);
                         }
+                        if *full {
+                            let sampling = config.sampling_for(model.name(), "");
+                            if !sampling.is_empty() {
+                                println!("    sampling = {}", sampling);
+                            }
+                            let mut strategies: Vec<_> =
+                                config.models.strategy_sampling.keys().collect();
+                            strategies.sort();
+                            for strategy in strategies {
+                                println!(
+                                    "    sampling.{} = {}",
+                                    strategy,
+                                    config.sampling_for(model.name(), strategy)
+                                );
+                            }
+                        }
                         println!();
                     }
                     Ok(())
                 }
-                Commands::Conf { defaults } => {
+                Commands::Conf {
+                    command: Some(ConfCommands::Get { key }),
+                    ..
+                } => {
+                    println!("{}", config::get_config_value(&config, key)?);
+                    Ok(())
+                }
+                Commands::Conf {
+                    command: Some(ConfCommands::Set { key, value, home }),
+                    ..
+                } => {
+                    // Start from the config files alone, so flags on the command line aren't
+                    // persisted.
+                    let file_config = config::load_config(&std::env::current_dir()?)?;
+                    config::set_config_value(&file_config, key, value, *home)?;
+                    let path = if *home {
+                        config::home_config_file()
+                    } else {
+                        file_config.project_root().join(config::PROJECT_CONFIG_FILE)
+                    };
+                    println!("Set {} in {}", key.blue().bold(), path.display());
+                    Ok(())
+                }
+                Commands::Conf { schema: true, .. } => {
+                    println!("{}", config::Config::json_schema()?);
+                    Ok(())
+                }
+                Commands::Conf { defaults, .. } => {
                     let conf = if *defaults {
                         config::default_config(std::env::current_dir()?)
                     } else {
@@ -417,9 +1208,272 @@ async fn main() -> anyhow::Result<()> {
                     println!("{}", conf.to_ron()?);
                     Ok(()) as anyhow::Result<()>
                 }
+                Commands::Diff { output, clipboard } => {
+                    let session = tx.load_session_read_only()?;
+                    let diff = session.diff()?;
+                    if diff.is_empty() {
+                        println!("No changes in the session");
+                    } else if let Some(path) = output {
+                        fs::write(path, &diff)
+                            .with_context(|| format!("Failed to write {}", path.display()))?;
+                        println!("Wrote diff to {}", path.display());
+                    } else if *clipboard {
+                        copy_to_clipboard(&diff)?;
+                        println!("Copied diff to the clipboard");
+                    } else {
+                        print!("{}", diff);
+                    }
+                    Ok(())
+                }
+                Commands::Dialect {
+                    command: DialectCommands::Check { file },
+                } => {
+                    let text = fs::read_to_string(file)
+                        .with_context(|| format!("Failed to read {}", file.display()))?;
+                    let dialect = config.dialect()?;
+                    match dialect.parse_located(&text) {
+                        Ok(resp) => {
+                            if let Some(comment) = &resp.comment {
+                                println!("{}", "comment".blue().bold());
+                                println!("{}\n", comment);
+                            }
+                            let patch = resp.patch.unwrap_or_default();
+                            println!("{} changes parsed", patch.changes.len());
+                            let mut renderer = term(&config);
+                            patch.render(&mut renderer, Detail::Full)?;
+                            println!("{}", renderer.render());
+                            Ok(())
+                        }
+                        Err(failure) => {
+                            let location = match (failure.offset, failure.line(&text)) {
+                                (Some(offset), Some(line)) => {
+                                    format!(" at byte {} (line {})", offset, line)
+                                }
+                                _ => String::new(),
+                            };
+                            println!("{}{}", "parse error".red().bold(), location);
+                            println!("{}", failure.error);
+                            if let Some(model) = failure.error.should_retry() {
+                                println!("{}", model);
+                            }
+                            Err(anyhow!("{} failed to parse", file.display()))
+                        }
+                    }
+                }
+                Commands::Dialect {
+                    command: DialectCommands::Corpus { dir },
+                } => {
+                    let results = dialect::parse_corpus(&config.dialect()?, dir)?;
+                    for result in &results {
+                        let status = if result.passed() {
+                            "pass".green().bold()
+                        } else {
+                            "FAIL".red().bold()
+                        };
+                        let detail = match (&result.error, result.line) {
+                            (Some(error), Some(line)) => format!("line {}: {}", line, error),
+                            (Some(error), None) => error.clone(),
+                            (None, _) => format!(
+                                "{} changes, {} operations",
+                                result.changes, result.operations
+                            ),
+                        };
+                        let expected = if result.expect_failure {
+                            " (expected to fail)"
+                        } else {
+                            ""
+                        };
+                        println!(
+                            "{} {}{}: {}",
+                            status,
+                            result.path.display(),
+                            expected,
+                            detail
+                        );
+                    }
+                    let failed = results.iter().filter(|r| !r.passed()).count();
+                    println!("{} fixtures, {} failed", results.len(), failed);
+                    if failed > 0 {
+                        return Err(anyhow!("{} fixtures failed", failed));
+                    }
+                    Ok(())
+                }
+                Commands::Dialect {
+                    command: DialectCommands::Info { json },
+                } => {
+                    let caps = config.dialect()?.capabilities(&config);
+                    if *json {
+                        println!("{}", serde_json::to_string_pretty(&caps)?);
+                    } else {
+                        println!("{} {}", "dialect:".blue().bold(), caps.dialect);
+                        println!("{} {}", "changes:".blue().bold(), caps.changes.join(", "));
+                        println!(
+                            "{} {}",
+                            "operations:".blue().bold(),
+                            caps.operations.join(", ")
+                        );
+                        println!("{} {}", "outlines:".blue().bold(), caps.outlines);
+                    }
+                    Ok(())
+                }
+                Commands::Preflight { sarif } => {
+                    let diagnostics = tx.preflight(&Some(sender.clone()))?;
+                    for d in &diagnostics {
+                        let location = match (&d.path, d.line, d.column) {
+                            (Some(path), Some(line), Some(col)) => {
+                                format!("{}:{}:{}: ", path, line, col)
+                            }
+                            (Some(path), _, _) => format!("{}: ", path),
+                            _ => String::new(),
+                        };
+                        let rule = d
+                            .rule
+                            .as_ref()
+                            .map_or(String::new(), |r| format!("[{}] ", r));
+                        println!(
+                            "{}{}{}{}",
+                            location,
+                            format!("{} ", d.check).dimmed(),
+                            rule,
+                            d.message
+                        );
+                    }
+                    if let Some(path) = sarif {
+                        fs::write(path, libtenx::sarif::to_sarif(&diagnostics)?)
+                            .with_context(|| format!("Failed to write {}", path.display()))?;
+                        println!("Wrote SARIF to {}", path.display());
+                    }
+                    if diagnostics.is_empty() {
+                        Ok(())
+                    } else {
+                        Err(anyhow!("{} problems found", diagnostics.len()))
+                    }
+                }
+                Commands::Preview {
+                    prompt,
+                    prompt_file,
+                    no_pager,
+                } => {
+                    let session = tx.load_session_read_only()?;
+                    let prompt = match (prompt, prompt_file) {
+                        (Some(p), _) => Some(p.clone()),
+                        (None, Some(path)) => {
+                            Some(fs::read_to_string(path).context("Failed to read prompt file")?)
+                        }
+                        (None, None) => None,
+                    };
+                    let preview = tx.preview(&session, prompt)?;
+                    if *no_pager {
+                        print!("{}", preview);
+                    } else {
+                        print_paged(&preview)?;
+                    }
+                    Ok(())
+                }
+                Commands::ShowFile { path } => {
+                    let session = tx.load_session_read_only()?;
+                    let path = config.normalize_path(path)?;
+                    let rendered = tx.render_file(&session, &path)?;
+                    if rendered.is_empty() {
+                        println!("{} is not in the prompt", path.display());
+                    }
+                    for txt in rendered {
+                        print!("{}", txt);
+                    }
+                    Ok(())
+                }
+                Commands::Serve { http, token } => {
+                    let token = match token {
+                        Some(t) if t.is_empty() => return Err(anyhow!("The token can't be empty")),
+                        Some(t) => t.clone(),
+                        None => {
+                            let t = serve::generate_token()?;
+                            println!("Token: {}", t);
+                            t
+                        }
+                    };
+                    // Config changes are picked up between requests, with the CLI arguments
+                    // applied on top as they were at startup
+                    let cli = cli.clone();
+                    let current_dir = std::env::current_dir()?;
+                    let reload = serve::Reload::new(
+                        config::ConfigWatcher::new(
+                            &current_dir,
+                            config::load_config(&current_dir)?,
+                        ),
+                        Box::new(move |config| apply_cli(&cli, config)),
+                    );
+                    serve::serve(tx, http, token, sender.clone(), reload).await
+                }
+                Commands::Usage { since, by, json } => {
+                    let since = usage::now().saturating_sub(parse_age(since)?);
+                    let key = match by.as_str() {
+                        "project" => usage::UsageKey::Project,
+                        "command" => usage::UsageKey::Command,
+                        _ => usage::UsageKey::Model,
+                    };
+                    let records = usage::Ledger::from_config(&config).records()?;
+                    let totals = usage::report(&records, since, key);
+                    if *json {
+                        println!("{}", serde_json::to_string_pretty(&totals)?);
+                    } else if totals.is_empty() {
+                        println!("No usage recorded");
+                    } else {
+                        for total in &totals {
+                            println!(
+                                "{} ${:.2}, {} calls, {} input tokens, {} output tokens",
+                                if total.key.is_empty() {
+                                    "unknown".blue().bold()
+                                } else {
+                                    total.key.blue().bold()
+                                },
+                                total.cost,
+                                total.calls,
+                                total.input_tokens,
+                                total.output_tokens
+                            );
+                        }
+                        let cost: f64 = totals.iter().map(|t| t.cost).sum();
+                        println!("{} ${:.2}", "total:".blue().bold(), cost);
+                    }
+                    Ok(())
+                }
                 Commands::Project => {
-                    // FIXME: Implement this
-                    // print!("{}", pretty::print_project(&config));
+                    let languages = config.languages();
+                    let source = if config.project.languages.is_empty() {
+                        "detected"
+                    } else {
+                        "configured"
+                    };
+                    println!(
+                        "{} {}",
+                        "root:".blue().bold(),
+                        config.project_root().display()
+                    );
+                    if languages.is_empty() {
+                        println!("{} unknown", "languages:".blue().bold());
+                    } else {
+                        println!(
+                            "{} {} ({})",
+                            "languages:".blue().bold(),
+                            languages.join(", "),
+                            source
+                        );
+                    }
+                    if let Some(env) = config.python_env() {
+                        println!(
+                            "{} {} ({})",
+                            "python:".blue().bold(),
+                            env.interpreter().display(),
+                            env.kind
+                        );
+                    }
+                    let checks: Vec<_> = config
+                        .enabled_checks()
+                        .into_iter()
+                        .map(|c| c.name)
+                        .collect();
+                    println!("{} {}", "checks:".blue().bold(), checks.join(", "));
                     Ok(())
                 }
                 Commands::Files { pattern } => {
@@ -434,7 +1488,68 @@ async fn main() -> anyhow::Result<()> {
                     }
                     Ok(())
                 }
-                Commands::Checks { all } => {
+                Commands::Grep {
+                    pattern,
+                    context,
+                    add_context,
+                } => {
+                    let matches = libtenx::search::search(&config, pattern, *context, None)?;
+                    for (i, fm) in matches.iter().enumerate() {
+                        if i > 0 {
+                            println!();
+                        }
+                        println!("{}", fm.path.display().to_string().blue().bold());
+                        for (j, hunk) in fm.hunks.iter().enumerate() {
+                            if j > 0 {
+                                println!("{}", "--".dimmed());
+                            }
+                            for line in &hunk.lines {
+                                if line.is_match {
+                                    println!("{}:{}", line.number.to_string().green(), line.text);
+                                } else {
+                                    println!("{}-{}", line.number.to_string().dimmed(), line.text);
+                                }
+                            }
+                        }
+                    }
+                    if *add_context {
+                        let mut session = tx.load_session()?;
+                        session.add_context(Context::new_search(pattern, *context));
+                        tx.refresh_needed_contexts(&mut session, &Some(sender.clone()))
+                            .await?;
+                        tx.save_session(&session)?;
+                    }
+                    Ok(())
+                }
+                Commands::Checks {
+                    command: Some(command),
+                    ..
+                } => {
+                    // Start from the config files alone, so check flags on the command line
+                    // aren't persisted.
+                    let file_config = config::load_config(&std::env::current_dir()?)?;
+                    let (name, enabled) = match command {
+                        ChecksCommands::Enable { name, glob } => {
+                            config::set_check_enabled(&file_config, name, true, glob)?;
+                            (name, true)
+                        }
+                        ChecksCommands::Disable { name } => {
+                            config::set_check_enabled(&file_config, name, false, &[])?;
+                            (name, false)
+                        }
+                    };
+                    println!(
+                        "{} {} in {}",
+                        if enabled { "Enabled" } else { "Disabled" },
+                        name.blue().bold(),
+                        file_config
+                            .project_root()
+                            .join(config::PROJECT_CONFIG_FILE)
+                            .display()
+                    );
+                    Ok(())
+                }
+                Commands::Checks { all, command: None } => {
                     let checks = if *all {
                         config.all_checks()
                     } else {
@@ -451,6 +1566,7 @@ async fn main() -> anyhow::Result<()> {
                         };
 
                         println!("{}{}", name.blue().bold(), status);
+                        println!("    mode: {}", check.mode);
                         println!("    globs: {:?}", check.globs);
                         println!();
                     }
@@ -495,10 +1611,11 @@ async fn main() -> anyhow::Result<()> {
                 } => {
                     let mut session = match tx.load_session() {
                         Ok(sess) => sess,
-                        Err(_) => {
+                        Err(error::TenxError::NotFound { .. }) => {
                             println!("No existing session to check.");
                             return Ok(());
                         }
+                        Err(e) => return Err(e.into()),
                     };
 
                     let user_prompt = match get_prompt(
@@ -527,22 +1644,59 @@ async fn main() -> anyhow::Result<()> {
                         .await?;
                     Ok(())
                 }
+                Commands::Session { schema: true, .. } => {
+                    println!("{}", Session::json_schema()?);
+                    Ok(())
+                }
                 Commands::Session {
                     session_file,
                     fmt,
+                    porcelain,
                     detail,
                     short,
+                    system,
+                    ..
                 } => {
                     let session = if let Some(path) = session_file {
                         libtenx::session_store::load_session(path)?
                     } else {
-                        tx.load_session()?
+                        tx.load_session_read_only()?
                     };
+                    if *system {
+                        match &session.system_prompt {
+                            Some(prompt) => {
+                                println!(
+                                    "{} dialect {}, tenx {}, hash {}\n",
+                                    "pinned:".blue().bold(),
+                                    prompt.dialect,
+                                    prompt.version,
+                                    prompt.hash
+                                );
+                                println!("{}", prompt.text);
+                            }
+                            None => println!(
+                                "No pinned system prompt - the session uses the current dialect's \
+                                 prompt"
+                            ),
+                        }
+                        return Ok(());
+                    }
+                    if *porcelain {
+                        print!("{}", libtenx::porcelain::render(&config, &session)?);
+                        return Ok(());
+                    }
 
                     match fmt.as_str() {
                         "raw" => {
                             println!("{:#?}", session);
                         }
+                        "commit" => {
+                            let summary = session
+                                .last_action()?
+                                .summary()
+                                .ok_or_else(|| anyhow!("No change summary in the last action"))?;
+                            println!("{}", summary.commit_message());
+                        }
                         "render" => {
                             // FIXME: Use chat
                             // println!("{}", model.render(&config, &session)?);
@@ -560,48 +1714,123 @@ async fn main() -> anyhow::Result<()> {
                             };
 
                             // Use the Term renderer to render the session
-                            let mut renderer = unirend::Term::new();
+                            let mut renderer = term(&config);
                             session.render(&config, &mut renderer, detail_level)?;
                             println!("{}", renderer.render());
                         }
                     }
                     Ok(())
                 }
-                Commands::Edit { files } => {
+                Commands::Edit { files, force } => {
                     let mut session = tx.load_session()?;
-                    let total = tx.edit(&mut session, files)?;
+                    let before = session.last_action()?.state.forced();
+                    let total = tx.edit(&mut session, files, *force)?;
+                    if *force {
+                        // Files forced by earlier commands were warned about then
+                        let forced = session.last_action()?.state.forced();
+                        for path in forced.iter().filter(|p| !before.contains(p)) {
+                            println!(
+                                "{}",
+                                format!(
+                                    "warning: {} is outside the project's included files",
+                                    path.display()
+                                )
+                                .yellow()
+                            );
+                        }
+                    }
                     println!("{} files added for editing", total);
                     Ok(())
                 }
-                Commands::Context { command } => {
+                Commands::Context {
+                    command: Some(ContextCommands::Show),
+                    group,
+                    symbol,
+                    lines,
+                    git_diff: None,
+                    ..
+                } if group.is_empty() && symbol.is_empty() && lines.is_empty() => {
+                    let session = tx.load_session_read_only()?;
+                    if session.contexts.is_empty() {
+                        println!("No contexts in session");
+                    } else {
+                        show_contexts(&config, &session)?;
+                    }
+                    Ok(())
+                }
+                Commands::Context {
+                    command,
+                    group,
+                    symbol,
+                    lines,
+                    git_diff,
+                    pin,
+                    priority,
+                } => {
+                    if command.is_none()
+                        && group.is_empty()
+                        && symbol.is_empty()
+                        && lines.is_empty()
+                        && git_diff.is_none()
+                    {
+                        return Err(anyhow!(
+                            "Specify a context command, --group, --symbol, --lines or --git-diff"
+                        ));
+                    }
+                    let priority = match (*pin, priority.as_deref()) {
+                        (true, _) => Some(Priority::Pinned),
+                        (_, Some("low")) => Some(Priority::Low),
+                        (_, Some("high")) => Some(Priority::High),
+                        (_, Some(_)) => Some(Priority::Normal),
+                        _ => None,
+                    };
                     let mut session = tx.load_session()?;
+                    let add = |session: &mut Session, context: Context| match priority {
+                        Some(priority) => session.contexts.add_with_priority(context, priority),
+                        None => session.add_context(context),
+                    };
+                    for name in group {
+                        for context in Context::from_group(&config, name)? {
+                            add(&mut session, context);
+                        }
+                    }
+                    for s in symbol {
+                        add(&mut session, Context::new_symbol(s));
+                    }
+                    for l in lines {
+                        add(&mut session, Context::new_lines(&config, l)?);
+                    }
+                    if let Some(reference) = git_diff {
+                        add(&mut session, Context::new_git_diff(reference.clone()));
+                    }
                     match command {
-                        ContextCommands::Clear => {
+                        None => {}
+                        Some(ContextCommands::Clear) => {
                             session.clear_ctx();
                             println!("All context cleared from session");
                         }
-                        ContextCommands::Ruskel { items } => {
+                        Some(ContextCommands::Ruskel { items }) => {
                             for item in items {
-                                session.add_context(Context::new_ruskel(item));
+                                add(&mut session, Context::new_ruskel(item));
                             }
                         }
-                        ContextCommands::Refresh => {
+                        Some(ContextCommands::Refresh) => {
                             tx.refresh_contexts(&mut session, &Some(sender.clone()))
                                 .await?;
                             tx.save_session(&session)?;
                             println!("Contexts refreshed.");
                         }
-                        ContextCommands::File { items } => {
+                        Some(ContextCommands::File { items }) => {
                             for item in items {
-                                session.add_context(Context::new_path(&config, item)?);
+                                add(&mut session, Context::new_path(&config, item)?);
                             }
                         }
-                        ContextCommands::Url { items } => {
+                        Some(ContextCommands::Url { items }) => {
                             for item in items {
-                                session.add_context(Context::new_url(item));
+                                add(&mut session, Context::new_url(item));
                             }
                         }
-                        ContextCommands::Text { name, file } => {
+                        Some(ContextCommands::Text { name, file }) => {
                             let text = if let Some(path) = file {
                                 fs::read_to_string(path).context("Failed to read text file")?
                             } else {
@@ -610,20 +1839,16 @@ async fn main() -> anyhow::Result<()> {
                                 buffer
                             };
                             let name = name.as_deref().unwrap_or("<anonymous>");
-                            session.add_context(Context::new_text(name, &text));
+                            add(&mut session, Context::new_text(name, &text));
                         }
-                        ContextCommands::Cmd { command } => {
-                            session.add_context(Context::new_cmd(command));
+                        Some(ContextCommands::Cmd { command }) => {
+                            add(&mut session, Context::new_cmd(command));
                         }
-                        ContextCommands::Show => {
-                            if session.contexts.is_empty() {
-                                println!("No contexts in session");
-                            } else {
-                                let mut render = unirend::Term::new();
-                                session.contexts.render(&mut render, Detail::Default)?;
-                                println!("{}", render.render());
-                            }
-                            return Ok(());
+                        Some(ContextCommands::Facts) => {
+                            add(&mut session, Context::new_project_facts());
+                        }
+                        Some(ContextCommands::Show) => {
+                            show_contexts(&config, &session)?;
                         }
                     };
                     tx.refresh_needed_contexts(&mut session, &Some(sender.clone()))
@@ -631,6 +1856,107 @@ async fn main() -> anyhow::Result<()> {
                     tx.save_session(&session)?;
                     Ok(())
                 }
+                Commands::Steps {
+                    command: StepsCommands::List,
+                } => {
+                    let session = tx.load_session_read_only()?;
+                    let mut rows = vec![];
+                    for (a, action) in session.actions.iter().enumerate() {
+                        for (s, step) in action.steps.iter().enumerate() {
+                            let files = step.files();
+                            rows.push([
+                                format!("{}:{}", a, s),
+                                if step.strategy_step.user_input().is_some() {
+                                    "user".to_string()
+                                } else {
+                                    "auto".to_string()
+                                },
+                                step.request.model.clone(),
+                                match files.len() {
+                                    1 => "1 file".to_string(),
+                                    n => format!("{} files", n),
+                                },
+                                step.status().to_string(),
+                            ]);
+                        }
+                    }
+                    if rows.is_empty() {
+                        println!("No steps in session");
+                        return Ok(());
+                    }
+                    let mut widths = [0; 5];
+                    for row in &rows {
+                        for (w, cell) in widths.iter_mut().zip(row) {
+                            *w = (*w).max(cell.len());
+                        }
+                    }
+                    for row in rows {
+                        let line = row
+                            .iter()
+                            .zip(widths)
+                            .map(|(cell, w)| format!("{:w$}", cell, w = w))
+                            .collect::<Vec<_>>()
+                            .join("  ");
+                        println!("{}", line.trim_end());
+                    }
+                    Ok(())
+                }
+                Commands::Steps {
+                    command: StepsCommands::Show { step },
+                } => {
+                    let session = tx.load_session_read_only()?;
+                    let (a, s) = resolve_step(&session, step)?;
+                    let step = &session.actions[a].steps[s];
+                    let mut render = term(&config);
+                    session.actions[a].strategy.render(
+                        &config,
+                        &session,
+                        a,
+                        s,
+                        &mut render,
+                        Detail::Full,
+                    )?;
+                    println!("{}", render.render());
+                    let files = step.files();
+                    if !files.is_empty() {
+                        println!(
+                            "{} {}",
+                            "files:".blue().bold(),
+                            files
+                                .iter()
+                                .map(|f| f.display().to_string())
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        );
+                    }
+                    println!("{} {}", "status:".blue().bold(), step.status());
+                    Ok(())
+                }
+                Commands::Steps {
+                    command: StepsCommands::Drop { step },
+                } => {
+                    let mut session = tx.load_session()?;
+                    let (a, s) = match step {
+                        Some(step) => resolve_step(&session, step)?,
+                        None => {
+                            let a = session
+                                .actions
+                                .len()
+                                .checked_sub(1)
+                                .ok_or_else(|| anyhow!("No steps in session"))?;
+                            let s = session.actions[a]
+                                .steps
+                                .len()
+                                .checked_sub(1)
+                                .ok_or_else(|| anyhow!("No steps in session"))?;
+                            (a, s)
+                        }
+                    };
+                    session.drop_step(a, s)?;
+                    tx.save_session(&session)?;
+                    println!("Dropped step {}:{}", a, s);
+                    Ok(())
+                }
                 Commands::Reset { step_offset, all } => {
                     if *all && step_offset.is_some() {
                         return Err(anyhow!("Cannot specify both --all and a step offset"));
@@ -652,11 +1978,28 @@ async fn main() -> anyhow::Result<()> {
                     }
                     Ok(())
                 }
+                Commands::RevertFile { path, step } => {
+                    let mut session = tx.load_session()?;
+                    let step = match step {
+                        Some(offset_str) => {
+                            let (a, s) = parse_step_offset(offset_str)?;
+                            Some((a, s.unwrap_or(0)))
+                        }
+                        None => None,
+                    };
+                    if tx.revert_file(&mut session, path, step)? {
+                        println!("Restored {}", path.display());
+                    } else {
+                        println!("{} is unchanged since that step", path.display());
+                    }
+                    Ok(())
+                }
                 Commands::Retry {
                     step_offset,
                     edit,
                     prompt,
                     prompt_file,
+                    show_prompt_diff,
                 } => {
                     let mut session = tx.load_session()?;
 
@@ -676,18 +2019,59 @@ async fn main() -> anyhow::Result<()> {
                     };
 
                     // Retry the step and continue
-                    tx.retry(&mut session, action_idx, step_idx)?;
-                    tx.continue_steps(&mut session, prompt, Some(sender.clone()), None)
+                    let failed = session.clone();
+                    let (a, s) = tx.retry(&mut session, action_idx, step_idx, prompt)?;
+                    if *show_prompt_diff {
+                        tx.refresh_needed_contexts(&mut session, &Some(sender.clone()))
+                            .await?;
+                        let old = tx.render_step_prompt(&failed, a, s)?;
+                        let new = tx.render_step_prompt(&session, a, s)?;
+                        print_prompt_diff(&old, &new);
+                    }
+                    tx.continue_steps(&mut session, None, Some(sender.clone()), None)
                         .await?;
                     Ok(())
                 }
+                Commands::Run { recipe } => {
+                    let recipe = libtenx::recipe::Recipe::load(recipe)?;
+                    let mut session = match tx.load_session() {
+                        Ok(sess) => sess,
+                        Err(error::TenxError::NotFound { .. }) => {
+                            println!("No existing session found.");
+                            return Ok(());
+                        }
+                        Err(e) => return Err(e.into()),
+                    };
+                    let results = tx
+                        .run_recipe(&mut session, &recipe, Some(sender.clone()))
+                        .await?;
+                    let mut failed = 0;
+                    for result in &results {
+                        match &result.error {
+                            None => println!("{} {}", "ok".green(), result.label),
+                            Some(e) => {
+                                failed += 1;
+                                println!("{} {}: {}", "failed".red(), result.label, e);
+                            }
+                        }
+                    }
+                    let skipped = recipe.items.len() - results.len();
+                    if skipped > 0 {
+                        println!("{} items not run", skipped);
+                    }
+                    if failed > 0 {
+                        Err(anyhow!("{} recipe items failed", failed))
+                    } else {
+                        Ok(())
+                    }
+                }
                 Commands::New { no_ctx } => {
                     let session = tx
                         .new_session_from_cwd(&Some(sender.clone()), *no_ctx)
                         .await?;
                     tx.save_session(&session)?;
 
-                    let mut renderer = unirend::Term::new();
+                    let mut renderer = term(&config);
                     session.render(&config, &mut renderer, Detail::Default)?;
                     println!("{}", renderer.render());
 
@@ -699,23 +2083,32 @@ async fn main() -> anyhow::Result<()> {
                     prompt,
                     prompt_file,
                     edit,
+                    test_first,
                     files,
                 } => {
                     let mut session = if *clear {
                         let mut current_session = tx.load_session()?;
-                        current_session.clear();
+                        current_session.clear(&config)?;
                         current_session
                     } else {
                         tx.new_session_from_cwd(&Some(sender.clone()), *no_ctx)
                             .await?
                     };
 
-                    let user_prompt = if prompt.is_some() || prompt_file.is_some() || *edit {
-                        get_prompt(prompt, prompt_file, &session, false, &Some(sender.clone()))?
+                    let user_prompt =
+                        if prompt.is_some() || prompt_file.is_some() || *edit || *test_first {
+                            get_prompt(prompt, prompt_file, &session, false, &Some(sender.clone()))?
+                        } else {
+                            None
+                        };
+                    if *test_first {
+                        if user_prompt.is_none() {
+                            return Ok(());
+                        }
+                        tx.test_first(&mut session)?;
                     } else {
-                        None
-                    };
-                    tx.fix(&mut session, &Some(sender.clone()))?;
+                        tx.fix(&mut session, &Some(sender.clone()))?;
+                    }
                     // Add files to the session if provided
                     if let Some(file_list) = &files {
                         if !file_list.is_empty() {
@@ -730,9 +2123,41 @@ async fn main() -> anyhow::Result<()> {
                         .await?;
                     Ok(())
                 }
+                Commands::Abandon { yes } => {
+                    let mut session = tx.load_session()?;
+                    let changes = tx.abandon_changes(&session)?;
+                    if !changes.is_empty() && !yes {
+                        if !std::io::stdin().is_terminal() {
+                            return Err(anyhow!(
+                                "Abandoning would discard changes to {} file(s), pass --yes to \
+                                 confirm",
+                                changes.len()
+                            ));
+                        }
+                        println!("{}", "abandoning discards:".yellow().bold());
+                        for change in &changes {
+                            println!("  {}", change);
+                        }
+                        print!("{}", "restore the checkpoint? [y/N] ".yellow().bold());
+                        std::io::stdout().flush()?;
+                        let mut line = String::new();
+                        std::io::stdin().read_line(&mut line)?;
+                        if !matches!(line.trim(), "y" | "yes") {
+                            println!("Nothing changed");
+                            return Ok(());
+                        }
+                    }
+                    let removed = tx.abandon(&mut session)?;
+                    println!(
+                        "Restored changed files to their checkpoint, removed {} new file(s), and \
+                         cleared the session",
+                        removed
+                    );
+                    Ok(())
+                }
                 Commands::Clear => {
                     let mut session = tx.load_session()?;
-                    session.clear();
+                    session.clear(&config)?;
                     tx.save_session(&session)?;
                     println!("Session cleared");
                     Ok(())
@@ -767,10 +2192,11 @@ async fn main() -> anyhow::Result<()> {
                 } => {
                     let mut session = match tx.load_session() {
                         Ok(sess) => sess,
-                        Err(_) => {
+                        Err(error::TenxError::NotFound { .. }) => {
                             println!("No existing session found.");
                             return Ok(());
                         }
+                        Err(e) => return Err(e.into()),
                     };
 
                     let user_prompt =
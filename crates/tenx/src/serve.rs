@@ -0,0 +1,722 @@
+//! A small HTTP API for driving tenx from editors and other tools. Each connection carries a
+//! single request, and every request must present the server's token, either as a bearer token
+//! or as a `token` query parameter.
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    future::Future,
+    rc::Rc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::{broadcast, mpsc},
+    task::{self, LocalSet},
+    time::{timeout_at, Instant},
+};
+
+use libtenx::{
+    api::{Config, Context, Event, Session, Tenx},
+    config::ConfigWatcher,
+    error::TenxError,
+    events::{send_event, LogLevel},
+};
+
+/// The largest request body we accept.
+const MAX_BODY: usize = 10 * 1024 * 1024;
+/// The longest request line or header line we accept, including the line ending.
+const MAX_LINE: u64 = 8 * 1024;
+/// The most headers we accept in a request.
+const MAX_HEADERS: usize = 64;
+/// How long a client has to send its whole request.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Returns a random token for guarding the server, drawn from the operating system's secure
+/// random number generator.
+pub fn generate_token() -> Result<String> {
+    let mut bytes = [0u8; 16];
+    getrandom::fill(&mut bytes).map_err(|e| anyhow!("Failed to generate a token: {}", e))?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Compares two strings in time that depends only on their lengths, so a client can't recover
+/// the token by timing its guesses.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// A parsed HTTP request.
+#[derive(Debug, Default)]
+struct Request {
+    method: String,
+    path: String,
+    /// Query parameters. Values are not percent-decoded.
+    query: HashMap<String, String>,
+    /// Headers, keyed by lowercase name
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    /// Parses the body as a JSON object. An empty body is an empty object.
+    fn json(&self) -> Reply<Value> {
+        if self.body.is_empty() {
+            return Ok(json!({}));
+        }
+        serde_json::from_slice(&self.body)
+            .map_err(|e| Response::error(400, format!("invalid JSON body: {}", e)))
+    }
+}
+
+/// Reads a single line into `line`, refusing lines longer than `MAX_LINE`. Returns the number of
+/// bytes read, which is 0 at the end of the stream.
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R, line: &mut String) -> Result<usize> {
+    line.clear();
+    let n = (&mut *reader).take(MAX_LINE).read_line(line).await?;
+    if n as u64 == MAX_LINE && !line.ends_with('\n') {
+        return Err(anyhow!("Request line too long"));
+    }
+    Ok(n)
+}
+
+/// Reads a request's line and headers, but not its body, returning None if the stream closes
+/// before a request starts. The body is only read once the request is authorized.
+async fn read_head<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<Request>> {
+    let mut line = String::new();
+    if read_line(reader, &mut line).await? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(anyhow!("Malformed request line"));
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut req = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query
+            .split('&')
+            .filter_map(|p| p.split_once('='))
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        ..Default::default()
+    };
+    for count in 0.. {
+        if read_line(reader, &mut line).await? == 0 {
+            break;
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if count == MAX_HEADERS {
+            return Err(anyhow!("Too many headers"));
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            req.headers
+                .insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+    Ok(Some(req))
+}
+
+/// Reads a request's body, as given by its content length.
+async fn read_body<R: AsyncBufRead + Unpin>(reader: &mut R, req: &mut Request) -> Result<()> {
+    let len = match req.headers.get("content-length") {
+        Some(len) => len
+            .parse::<usize>()
+            .map_err(|_| anyhow!("Invalid content length"))?,
+        None => 0,
+    };
+    if len > MAX_BODY {
+        return Err(anyhow!("Request body too large"));
+    }
+    req.body = vec![0; len];
+    reader.read_exact(&mut req.body).await?;
+    Ok(())
+}
+
+/// Runs part of reading a request, failing if the request's deadline passes first.
+async fn before<T>(deadline: Instant, fut: impl Future<Output = Result<T>>) -> Result<T> {
+    timeout_at(deadline, fut)
+        .await
+        .map_err(|_| anyhow!("Timed out reading the request"))?
+}
+
+/// A response to a request.
+#[derive(Debug)]
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn json(status: u16, body: String) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body,
+        }
+    }
+
+    fn text(body: String) -> Self {
+        Self {
+            status: 200,
+            content_type: "text/plain; charset=utf-8",
+            body,
+        }
+    }
+
+    fn error(status: u16, message: impl std::fmt::Display) -> Self {
+        Self::json(status, json!({ "error": message.to_string() }).to_string())
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            201 => "Created",
+            202 => "Accepted",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            _ => "Internal Server Error",
+        }
+    }
+
+    async fn write<W: AsyncWrite + Unpin>(&self, out: &mut W) -> Result<()> {
+        let head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            self.reason(),
+            self.content_type,
+            self.body.len()
+        );
+        out.write_all(head.as_bytes()).await?;
+        out.write_all(self.body.as_bytes()).await?;
+        out.flush().await?;
+        Ok(())
+    }
+}
+
+impl From<TenxError> for Response {
+    fn from(e: TenxError) -> Self {
+        Response::error(500, e)
+    }
+}
+
+impl From<serde_json::Error> for Response {
+    fn from(e: serde_json::Error) -> Self {
+        Response::error(500, e)
+    }
+}
+
+/// Either a successful response, or an error response.
+type Reply<T = Response> = std::result::Result<T, Response>;
+
+/// Returns a required string field from a request body.
+fn str_field<'a>(body: &'a Value, name: &str) -> Reply<&'a str> {
+    body[name]
+        .as_str()
+        .ok_or_else(|| Response::error(400, format!("missing string field '{}'", name)))
+}
+
+/// Adds a new code action to the session, unless the last action has not started yet. This lets
+/// editables added through the API collect in an action that the next prompt then runs.
+fn ensure_pending_action(tx: &Tenx, session: &mut Session) -> Reply<()> {
+    if session.actions.last().is_none_or(|a| !a.steps.is_empty()) {
+        tx.code(session)?;
+    }
+    Ok(())
+}
+
+/// Reloads the configuration when the config files change.
+pub struct Reload {
+    watcher: ConfigWatcher,
+    /// Applies command-line overrides to a reloaded configuration
+    overrides: Box<dyn Fn(Config) -> Result<Config>>,
+    /// A reloaded configuration that's waiting for running operations to finish
+    pending: Option<Config>,
+}
+
+impl Reload {
+    pub fn new(watcher: ConfigWatcher, overrides: Box<dyn Fn(Config) -> Result<Config>>) -> Self {
+        Self {
+            watcher,
+            overrides,
+            pending: None,
+        }
+    }
+}
+
+struct Server {
+    /// Shared with running operations, and replaced when the config is reloaded
+    tx: RefCell<Rc<Tenx>>,
+    reload: RefCell<Reload>,
+    token: String,
+    /// Events from operations run by the server
+    sender: mpsc::Sender<Event>,
+    /// Events relayed to clients of the event stream
+    events: broadcast::Sender<Event>,
+    /// Set while a prompt is running, during which the session can't be modified
+    busy: Cell<bool>,
+}
+
+impl Server {
+    fn sender(&self) -> Option<mpsc::Sender<Event>> {
+        Some(self.sender.clone())
+    }
+
+    /// Returns the Tenx to run an operation with, first picking up any config changes.
+    fn tx(&self) -> Rc<Tenx> {
+        self.reload_config();
+        self.tx.borrow().clone()
+    }
+
+    /// Polls the config files for changes, and applies them once no operation is using the
+    /// current config. A config that fails to load is reported, and the current one kept.
+    fn reload_config(&self) {
+        let mut reload = self.reload.borrow_mut();
+        match reload.watcher.poll(&self.sender()) {
+            Ok(Some(config)) => reload.pending = Some(config.clone()),
+            Ok(None) => {}
+            Err(e) => self.warn(format!("Failed to reload config: {}", e)),
+        }
+        let mut tx = self.tx.borrow_mut();
+        let Some(tx) = Rc::get_mut(&mut tx) else {
+            return;
+        };
+        if let Some(config) = reload.pending.take() {
+            match (reload.overrides)(config) {
+                Ok(config) => tx.config = config,
+                Err(e) => self.warn(format!("Failed to reload config: {}", e)),
+            }
+        }
+    }
+
+    fn warn(&self, msg: String) {
+        let _ = send_event(&self.sender(), Event::Log(LogLevel::Warn, msg));
+    }
+
+    fn authorized(&self, req: &Request) -> bool {
+        let bearer = req
+            .headers
+            .get("authorization")
+            .and_then(|h| h.strip_prefix("Bearer "));
+        bearer
+            .or(req.query.get("token").map(|t| t.as_str()))
+            .is_some_and(|t| constant_time_eq(t, &self.token))
+    }
+
+    fn idle(&self) -> Reply<()> {
+        if self.busy.get() {
+            return Err(Response::error(409, "a prompt is running"));
+        }
+        Ok(())
+    }
+
+    async fn handle(self: Rc<Self>, stream: TcpStream) -> Result<()> {
+        let (read, mut write) = stream.into_split();
+        let mut reader = BufReader::new(read);
+        let deadline = Instant::now() + READ_TIMEOUT;
+        let mut req = match before(deadline, read_head(&mut reader)).await {
+            Ok(Some(req)) => req,
+            Ok(None) => return Ok(()),
+            Err(e) => return Response::error(400, e).write(&mut write).await,
+        };
+        if !self.authorized(&req) {
+            return Response::error(401, "missing or invalid token")
+                .write(&mut write)
+                .await;
+        }
+        if let Err(e) = before(deadline, read_body(&mut reader, &mut req)).await {
+            return Response::error(400, e).write(&mut write).await;
+        }
+        if req.method == "GET" && req.path == "/events" {
+            return self.stream_events(write).await;
+        }
+        let resp = self.route(&req).await.unwrap_or_else(|e| e);
+        resp.write(&mut write).await
+    }
+
+    async fn route(self: &Rc<Self>, req: &Request) -> Reply {
+        match (req.method.as_str(), req.path.as_str()) {
+            ("GET", "/session") => {
+                let session = self.tx().load_session_read_only()?;
+                Ok(Response::json(200, serde_json::to_string(&session)?))
+            }
+            ("GET", "/diff") => Ok(Response::text(self.tx().load_session_read_only()?.diff()?)),
+            ("POST", "/session") => {
+                self.idle()?;
+                let no_context = req.json()?["no_context"].as_bool().unwrap_or(false);
+                let tx = self.tx();
+                let session = tx.new_session_from_cwd(&self.sender(), no_context).await?;
+                tx.save_session(&session)?;
+                Ok(Response::json(201, serde_json::to_string(&session)?))
+            }
+            ("POST", "/context") => self.add_context(&req.json()?).await,
+            ("POST", "/edit") => self.edit(&req.json()?),
+            ("POST", "/prompt") => self.prompt(&req.json()?),
+            (_, "/session" | "/diff" | "/context" | "/edit" | "/prompt" | "/events") => {
+                Err(Response::error(405, "method not allowed"))
+            }
+            _ => Err(Response::error(404, "not found")),
+        }
+    }
+
+    /// Adds a context item, given as `{"kind": ..., "value": ..., "name": ...}`. The name is only
+    /// used for text contexts. Files must be inside the project, and command contexts can't be
+    /// added, since they would let any client with the token run commands.
+    async fn add_context(&self, body: &Value) -> Reply {
+        self.idle()?;
+        let tx = self.tx();
+        let value = str_field(body, "value")?;
+        let context = match str_field(body, "kind")? {
+            "file" => {
+                let path = tx.config.normalize_path(value)?;
+                tx.config
+                    .confine(&path)
+                    .map_err(|e| Response::error(400, e))?;
+                Context::new_path(&tx.config, value)?
+            }
+            "url" => Context::new_url(value),
+            "text" => Context::new_text(body["name"].as_str().unwrap_or("text"), value),
+            "cmd" => {
+                return Err(Response::error(
+                    400,
+                    "command contexts can't be added through the API",
+                ))
+            }
+            "ruskel" => Context::new_ruskel(value),
+            kind => {
+                return Err(Response::error(
+                    400,
+                    format!("unknown context kind '{}'", kind),
+                ))
+            }
+        };
+        let mut session = tx.load_session()?;
+        session.add_context(context);
+        tx.refresh_needed_contexts(&mut session, &self.sender())
+            .await?;
+        tx.save_session(&session)?;
+        Ok(Response::json(
+            200,
+            json!({ "contexts": session.contexts.len() }).to_string(),
+        ))
+    }
+
+    /// Adds editable files, given as `{"files": [...], "force": false}`.
+    fn edit(&self, body: &Value) -> Reply {
+        self.idle()?;
+        let files = body["files"]
+            .as_array()
+            .and_then(|f| {
+                f.iter()
+                    .map(|v| v.as_str().map(String::from))
+                    .collect::<Option<Vec<_>>>()
+            })
+            .ok_or_else(|| Response::error(400, "'files' must be a list of strings"))?;
+        let force = body["force"].as_bool().unwrap_or(false);
+        let tx = self.tx();
+        let mut session = tx.load_session()?;
+        ensure_pending_action(&tx, &mut session)?;
+        let count = tx.edit(&mut session, &files, force)?;
+        Ok(Response::json(200, json!({ "added": count }).to_string()))
+    }
+
+    /// Starts running a prompt, given as `{"prompt": ..., "model": ...}`. The prompt runs in the
+    /// background, and progress is reported on the event stream.
+    fn prompt(self: &Rc<Self>, body: &Value) -> Reply {
+        self.idle()?;
+        let prompt = str_field(body, "prompt")?.to_string();
+        let model = body["model"].as_str().map(String::from);
+        let tx = self.tx();
+        if let Some(model) = &model {
+            tx.config
+                .with_model(model)
+                .active_model()
+                .map_err(|e| Response::error(400, e))?;
+        }
+        let mut session = tx.load_session()?;
+        ensure_pending_action(&tx, &mut session)?;
+        self.busy.set(true);
+        let server = self.clone();
+        task::spawn_local(async move {
+            let sender = server.sender();
+            if let Err(e) = tx
+                .ask(
+                    &mut session,
+                    Some(prompt),
+                    model.as_deref(),
+                    sender.clone(),
+                    None,
+                )
+                .await
+            {
+                let _ = send_event(&sender, Event::Fatal(e.to_string()));
+            }
+            server.busy.set(false);
+        });
+        Ok(Response::json(
+            202,
+            json!({ "status": "running" }).to_string(),
+        ))
+    }
+
+    /// Streams events to the client as server-sent events, until the client disconnects.
+    async fn stream_events<W: AsyncWrite + Unpin>(&self, mut out: W) -> Result<()> {
+        let mut events = self.events.subscribe();
+        out.write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n",
+        )
+        .await?;
+        out.flush().await?;
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            };
+            let frame = format!(
+                "event: {}\ndata: {}\n\n",
+                event.name(),
+                serde_json::to_string(&event)?
+            );
+            out.write_all(frame.as_bytes()).await?;
+            out.flush().await?;
+        }
+    }
+}
+
+/// Serves the API on `addr` until the process is stopped. Events from operations run through the
+/// API are relayed both to event stream clients and to `sender`.
+pub async fn serve(
+    tx: Tenx,
+    addr: &str,
+    token: String,
+    sender: mpsc::Sender<Event>,
+    reload: Reload,
+) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| anyhow!("Failed to listen on {}: {}", addr, e))?;
+    let (events, _) = broadcast::channel(1024);
+    let (server_sender, mut receiver) = mpsc::channel::<Event>(100);
+    let relay = events.clone();
+    tokio::spawn(async move {
+        while let Some(event) = receiver.recv().await {
+            let _ = relay.send(event.clone());
+            let _ = sender.send(event).await;
+        }
+    });
+
+    println!("Listening on http://{}", listener.local_addr()?);
+    let server = Rc::new(Server {
+        tx: RefCell::new(Rc::new(tx)),
+        reload: RefCell::new(reload),
+        token,
+        sender: server_sender,
+        events,
+        busy: Cell::new(false),
+    });
+    // Tenx operations aren't required to be Send, so connections are handled on a local set
+    LocalSet::new().run_until(accept(listener, server)).await
+}
+
+/// Accepts connections, handling each in its own local task.
+async fn accept(listener: TcpListener, server: Rc<Server>) -> Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        task::spawn_local(server.clone().handle(stream));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libtenx::testutils;
+
+    /// Reads a whole request, as the server does for an authorized client.
+    async fn read_request(mut reader: &[u8]) -> Result<Option<Request>> {
+        let Some(mut req) = read_head(&mut reader).await? else {
+            return Ok(None);
+        };
+        read_body(&mut reader, &mut req).await?;
+        Ok(Some(req))
+    }
+
+    #[tokio::test]
+    async fn test_read_request() -> Result<()> {
+        let raw = b"POST /prompt?token=abc&x HTTP/1.1\r\nHost: localhost\r\n\
+            Authorization: Bearer abc\r\nContent-Length: 17\r\n\r\n{\"prompt\": \"hi\"}\n";
+        let req = read_request(raw).await?.unwrap();
+        assert_eq!(req.method, "POST");
+        assert_eq!(req.path, "/prompt");
+        assert_eq!(req.query.get("token").map(String::as_str), Some("abc"));
+        assert_eq!(req.headers["authorization"], "Bearer abc");
+        assert_eq!(str_field(&req.json().unwrap(), "prompt").unwrap(), "hi");
+
+        assert!(read_request(b"").await?.is_none());
+        assert!(read_request(b"GET\r\n\r\n").await.is_err());
+
+        // Overlong lines and too many headers are refused
+        let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE as usize));
+        assert!(read_request(long.as_bytes()).await.is_err());
+        let many = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "X: y\r\n".repeat(MAX_HEADERS + 1)
+        );
+        assert!(read_request(many.as_bytes()).await.is_err());
+        let enough = format!("GET / HTTP/1.1\r\n{}\r\n", "X: y\r\n".repeat(MAX_HEADERS));
+        assert!(read_request(enough.as_bytes()).await?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_token() -> Result<()> {
+        let token = generate_token()?;
+        assert_eq!(token.len(), 32);
+        assert_ne!(token, generate_token()?);
+        assert!(constant_time_eq("abc", "abc"));
+        assert!(!constant_time_eq("abc", "abd"));
+        assert!(!constant_time_eq("abc", "abcd"));
+        Ok(())
+    }
+
+    /// Returns a server for a project, with the receiver for its events.
+    fn test_server(p: &testutils::TestProject) -> (Rc<Server>, mpsc::Receiver<Event>) {
+        let (sender, receiver) = mpsc::channel(100);
+        let (events, _) = broadcast::channel(16);
+        let watcher = ConfigWatcher::new(p.tempdir.path(), p.config.clone());
+        let server = Rc::new(Server {
+            tx: RefCell::new(Rc::new(Tenx::new(p.config.clone()))),
+            reload: RefCell::new(Reload::new(watcher, Box::new(Ok))),
+            token: "secret".into(),
+            sender,
+            events,
+            busy: Cell::new(false),
+        });
+        (server, receiver)
+    }
+
+    /// Sends a raw request to the server over a real connection, and returns the response.
+    async fn send(server: &Rc<Server>, raw: &str) -> Result<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mut client = TcpStream::connect(listener.local_addr()?).await?;
+        let (stream, _) = listener.accept().await?;
+        client.write_all(raw.as_bytes()).await?;
+        server.clone().handle(stream).await?;
+        let mut resp = String::new();
+        client.read_to_string(&mut resp).await?;
+        Ok(resp)
+    }
+
+    #[tokio::test]
+    async fn test_auth() -> Result<()> {
+        let p = testutils::offline_project();
+        let (server, _events) = test_server(&p);
+        let req = |auth: &str, target: &str| Request {
+            method: "GET".into(),
+            path: target.into(),
+            headers: [("authorization".to_string(), auth.to_string())].into(),
+            ..Default::default()
+        };
+        assert!(server.authorized(&req("Bearer secret", "/")));
+        assert!(!server.authorized(&req("Bearer secre", "/")));
+        assert!(!server.authorized(&req("secret", "/")));
+        let mut query = req("", "/");
+        query.query.insert("token".into(), "secret".into());
+        assert!(server.authorized(&query));
+
+        // An unauthorized client is refused before its body is read, so it can't make us wait
+        // for, or allocate, a large body
+        let local = LocalSet::new();
+        let resp = local
+            .run_until(send(
+                &server,
+                &format!(
+                    "POST /prompt HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+                    MAX_BODY
+                ),
+            ))
+            .await?;
+        assert!(resp.starts_with("HTTP/1.1 401"), "{}", resp);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_routing() -> Result<()> {
+        let mut p = testutils::offline_project();
+        p.config.session_store_dir = p.tempdir.path().join("sess");
+        p.write("inside.txt", "hello");
+        let (server, _events) = test_server(&p);
+        let auth = "Authorization: Bearer secret\r\n";
+        let post = |path: &str, body: &str| {
+            format!(
+                "POST {} HTTP/1.1\r\n{}Content-Length: {}\r\n\r\n{}",
+                path,
+                auth,
+                body.len(),
+                body
+            )
+        };
+        let local = LocalSet::new();
+        let status = |raw: String| {
+            let server = server.clone();
+            async move {
+                let resp = send(&server, &raw).await.unwrap();
+                resp.split_whitespace().nth(1).unwrap().to_string()
+            }
+        };
+        local
+            .run_until(async {
+                assert_eq!(
+                    status(format!("GET /nope HTTP/1.1\r\n{}\r\n", auth)).await,
+                    "404"
+                );
+                assert_eq!(
+                    status(format!("DELETE /session HTTP/1.1\r\n{}\r\n", auth)).await,
+                    "405"
+                );
+                assert_eq!(status(post("/context", "{")).await, "400");
+                assert_eq!(
+                    status(post("/context", r#"{"kind": "cmd", "value": "ls"}"#)).await,
+                    "400"
+                );
+                assert_eq!(
+                    status(post(
+                        "/context",
+                        r#"{"kind": "file", "value": "/etc/passwd"}"#
+                    ))
+                    .await,
+                    "400"
+                );
+                assert_eq!(
+                    status(post("/context", r#"{"kind": "file", "value": "../x.txt"}"#)).await,
+                    "400"
+                );
+                assert_eq!(status(post("/session", "{}")).await, "201");
+                assert_eq!(
+                    status(post(
+                        "/context",
+                        r#"{"kind": "file", "value": "inside.txt"}"#
+                    ))
+                    .await,
+                    "200"
+                );
+            })
+            .await;
+        Ok(())
+    }
+}
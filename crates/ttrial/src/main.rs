@@ -249,7 +249,7 @@ async fn main() -> anyhow::Result<()> {
 
     let (event_kill_tx, event_kill_rx) = mpsc::channel(1);
     let (sender, receiver) = mpsc::channel(100);
-    let subscriber = event_consumers::create_tracing_subscriber(verbosity, sender.clone());
+    let subscriber = event_consumers::create_tracing_subscriber(verbosity, sender.clone(), None);
     subscriber.init();
 
     let event_task = match cli.output {
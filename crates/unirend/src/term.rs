@@ -1,6 +1,7 @@
 #![allow(dead_code)]
 use colored::CustomColor;
 use colored::*;
+use std::io::IsTerminal;
 use terminal_size::{terminal_size, Height, Width};
 
 // Import our Style enum explicitly to avoid ambiguity with colored::Style
@@ -84,10 +85,20 @@ pub struct Term {
 
 impl Term {
     pub fn new() -> Self {
-        // Get terminal width using terminal_size crate
-        let width = terminal_size()
+        Self::with_fallback_width(DEFAULT_WIDTH)
+    }
+
+    /// Creates a renderer that wraps to the width of the terminal, or to `fallback` if stdout is
+    /// not a terminal, e.g. when output is piped to a file.
+    pub fn with_fallback_width(fallback: usize) -> Self {
+        // Only stdout counts - terminal_size falls back to stderr and stdin, which may still be
+        // attached to a terminal when stdout is piped.
+        let width = std::io::stdout()
+            .is_terminal()
+            .then(terminal_size)
+            .flatten()
             .map(|(Width(w), Height(_))| w as usize)
-            .unwrap_or(DEFAULT_WIDTH);
+            .unwrap_or(fallback);
 
         Self {
             level: 0,